@@ -6,6 +6,7 @@ mod request;
 use serde_json::json;
 use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::Duration;
 use std::{fmt, io};
 
 use crate::parsed_request::ParsedRequest;
@@ -55,6 +56,9 @@ impl fmt::Debug for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How long an API connection can wait for a new request before the server closes it.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct ApiServer {
     /// MMDS info directly accessible from the API thread.
     mmds_info: Arc<Mutex<Mmds>>,
@@ -67,6 +71,8 @@ pub struct ApiServer {
     /// FD on which we notify the VMM that we have sent at least one
     /// `VmmRequest`.
     to_vmm_fd: EventFd,
+    /// How long a connection can wait for a new request before it is closed.
+    idle_timeout: Duration,
 }
 
 impl ApiServer {
@@ -83,6 +89,7 @@ impl ApiServer {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
         })
     }
 
@@ -126,6 +133,7 @@ impl ApiServer {
         }
 
         server.start_server().expect("Cannot start HTTP server");
+        server.set_idle_timeout(Some(self.idle_timeout));
         loop {
             match server.requests() {
                 Ok(request_vec) => {
@@ -311,7 +319,7 @@ mod tests {
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::instance_info::InstanceInfo;
     #[cfg(target_arch = "x86_64")]
-    use vmm::vmm_config::snapshot::CreateSnapshotParams;
+    use vmm::vmm_config::snapshot::{CreateSnapshotParams, MemFileFormat};
 
     #[test]
     fn test_error_messages() {
@@ -391,7 +399,10 @@ mod tests {
                     snapshot_type: SnapshotType::Diff,
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
+                    mem_file_format: MemFileFormat::Raw,
                     version: None,
+                    reset_vsock: false,
+                    drive_overlays: Vec::new(),
                 })),
                 start_time_us,
             );
@@ -405,7 +416,10 @@ mod tests {
                     snapshot_type: SnapshotType::Diff,
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
+                    mem_file_format: MemFileFormat::Raw,
                     version: None,
+                    reset_vsock: false,
+                    drive_overlays: Vec::new(),
                 })),
                 start_time_us,
             );
@@ -686,4 +700,62 @@ mod tests {
         let mut buf: [u8; 100] = [0; 100];
         assert!(sock.read(&mut buf[..]).unwrap() > 0);
     }
+
+    #[test]
+    fn test_idle_timeout() {
+        let mut tmp_socket = TempFile::new().unwrap();
+        tmp_socket.remove().unwrap();
+        let path_to_socket = tmp_socket.as_path().to_str().unwrap().to_owned();
+        let api_thread_path_to_socket = path_to_socket.clone();
+
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_idle_timeout".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mmds_info = MMDS.clone();
+
+        let mut api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .expect("Cannot create API server");
+        assert_eq!(api_server.idle_timeout, DEFAULT_IDLE_TIMEOUT);
+        api_server.idle_timeout = Duration::from_millis(50);
+
+        thread::Builder::new()
+            .name("fc_api_test".to_owned())
+            .spawn(move || {
+                api_server
+                    .bind_and_run(
+                        PathBuf::from(api_thread_path_to_socket),
+                        None,
+                        None,
+                        SeccompFilter::empty().try_into().unwrap(),
+                    )
+                    .unwrap();
+            })
+            .unwrap();
+
+        // Wait for the server to set itself up.
+        thread::sleep(Duration::new(0, 10_000_000));
+        let mut sock = UnixStream::connect(PathBuf::from(path_to_socket)).unwrap();
+
+        // The connection is kept open while it is in use.
+        assert!(sock.write_all(b"GET / HTTP/1.1\r\n\r\n").is_ok());
+        let mut buf = [0u8; 1024];
+        assert!(sock.read(&mut buf[..]).unwrap() > 0);
+
+        // The server closes the connection once it is left idle.
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(sock.read(&mut buf[..]).unwrap(), 0);
+    }
 }
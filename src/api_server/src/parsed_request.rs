@@ -7,15 +7,18 @@ use super::VmmData;
 use crate::request::actions::parse_put_actions;
 use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use crate::request::boot_source::parse_put_boot_source;
-use crate::request::drive::{parse_patch_drive, parse_put_drive};
+use crate::request::drive::{parse_get_drive, parse_patch_drive, parse_put_drive};
 use crate::request::instance_info::parse_get_instance_info;
 use crate::request::logger::parse_put_logger;
 use crate::request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
+use crate::request::memory_hotplug::{
+    parse_get_memory_hotplug, parse_patch_memory_hotplug, parse_put_memory_hotplug,
+};
 use crate::request::metrics::parse_put_metrics;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use crate::request::net::{parse_patch_net, parse_put_net};
+use crate::request::net::{parse_patch_net, parse_patch_net_capture, parse_put_net};
 use crate::request::snapshot::parse_patch_vm_state;
 #[cfg(target_arch = "x86_64")]
 use crate::request::snapshot::parse_put_snapshot;
@@ -58,9 +61,17 @@ impl ParsedRequest {
 
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
-            (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
+            (Method::Get, "balloon", None) => {
+                parse_get_balloon(path_tokens.get(1), path_tokens.get(2))
+            }
+            (Method::Get, "drives", None) => {
+                parse_get_drive(path_tokens.get(1), path_tokens.get(2))
+            }
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"memory") => {
+                parse_get_memory_hotplug()
+            }
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
@@ -75,15 +86,28 @@ impl ParsedRequest {
             }
             #[cfg(target_arch = "x86_64")]
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
+            (Method::Put, "vm", Some(body)) if path_tokens.get(1) == Some(&"memory") => {
+                parse_put_memory_hotplug(body)
+            }
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
-            (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.get(1)),
+            (Method::Patch, "balloon", Some(body)) => {
+                parse_patch_balloon(body, path_tokens.get(1), path_tokens.get(2))
+            }
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.get(1)),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
             (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
+            (Method::Patch, "network-interfaces", Some(body))
+                if path_tokens.get(2) == Some(&"capture") =>
+            {
+                parse_patch_net_capture(body, path_tokens.get(1), path_tokens.get(3))
+            }
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.get(1))
             }
+            (Method::Patch, "vm", Some(body)) if path_tokens.get(1) == Some(&"memory") => {
+                parse_patch_memory_hotplug(body)
+            }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
             (method, unknown_uri, _) => {
@@ -113,12 +137,30 @@ impl ParsedRequest {
                     response.set_body(Body::new(serde_json::to_string(balloon_config).unwrap()));
                     response
                 }
+                VmmData::BalloonHintingStatus(status) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(status).unwrap()));
+                    response
+                }
                 VmmData::BalloonStats(stats) => {
                     info!("The request was executed successfully. Status code: 200 OK.");
                     let mut response = Response::new(Version::Http11, StatusCode::OK);
                     response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
                     response
                 }
+                VmmData::BlockDeviceLatencies(latencies) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(latencies).unwrap()));
+                    response
+                }
+                VmmData::MemoryHotplugStatus(status) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(status).unwrap()));
+                    response
+                }
             },
             Err(vmm_action_error) => {
                 error!(
@@ -184,6 +226,14 @@ pub fn method_to_error(method: Method) -> Result<ParsedRequest, Error> {
             StatusCode::BadRequest,
             "Empty PATCH request.".to_string(),
         )),
+        Method::Post => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Empty POST request.".to_string(),
+        )),
+        Method::Delete => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "DELETE request cannot have a body.".to_string(),
+        )),
     }
 }
 
@@ -604,6 +654,49 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_balloon_hinting_status() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /balloon/hinting/status HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_drive_latencies() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /drives/rootfs/latencies HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /vm/memory HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /vm HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
     #[test]
     fn test_try_from_get_machine_config() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -951,6 +1044,60 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PATCH /balloon/hinting/start HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PATCH /balloon/hinting/stop HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_patch_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PATCH /vm/memory HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 29\r\n\r\n{ \"requested_size_mib\": 512 }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /vm/memory HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 26\r\n\r\n{ \"total_size_mib\": 1024 }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
@@ -1044,4 +1191,31 @@ pub(crate) mod tests {
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
+
+    #[test]
+    fn test_try_from_patch_netif_capture() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PATCH /network-interfaces/string/capture/start HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 52\r\n\r\n{ \"iface_id\": \"string\", \"path\": \"/tmp/string.pcap\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PATCH /network-interfaces/string/capture/stop HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
 }
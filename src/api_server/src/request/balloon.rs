@@ -6,13 +6,19 @@ use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
 use micro_http::StatusCode;
 use vmm::vmm_config::balloon::{
-    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig,
+    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig, StartHintingCmd,
 };
 
-pub fn parse_get_balloon(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
+pub fn parse_get_balloon(
+    path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
     match path_second_token {
-        Some(stats_path) => match *stats_path {
-            "statistics" => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
+        Some(stats_path) => match (*stats_path, path_third_token) {
+            ("statistics", None) => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
+            ("hinting", Some(&"status")) => {
+                Ok(ParsedRequest::new_sync(VmmAction::GetBalloonHintingStatus))
+            }
             _ => Err(Error::Generic(
                 StatusCode::BadRequest,
                 format!("Unrecognized GET request path `{}`.", *stats_path),
@@ -31,13 +37,26 @@ pub fn parse_put_balloon(body: &Body) -> Result<ParsedRequest, Error> {
 pub fn parse_patch_balloon(
     body: &Body,
     path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
 ) -> Result<ParsedRequest, Error> {
     match path_second_token {
-        Some(config_path) => match *config_path {
-            "statistics" => Ok(ParsedRequest::new_sync(VmmAction::UpdateBalloonStatistics(
-                serde_json::from_slice::<BalloonUpdateStatsConfig>(body.raw())
-                    .map_err(Error::SerdeJson)?,
-            ))),
+        Some(config_path) => match (*config_path, path_third_token) {
+            ("statistics", None) => {
+                Ok(ParsedRequest::new_sync(VmmAction::UpdateBalloonStatistics(
+                    serde_json::from_slice::<BalloonUpdateStatsConfig>(body.raw())
+                        .map_err(Error::SerdeJson)?,
+                )))
+            }
+            ("hinting", Some(&"start")) => {
+                Ok(ParsedRequest::new_sync(VmmAction::StartBalloonHinting(
+                    serde_json::from_slice::<StartHintingCmd>(body.raw())
+                        .map_err(Error::SerdeJson)?,
+                )))
+            }
+            // The body of a stop request carries no information.
+            ("hinting", Some(&"stop")) => {
+                Ok(ParsedRequest::new_sync(VmmAction::StopBalloonHinting))
+            }
             _ => Err(Error::Generic(
                 StatusCode::BadRequest,
                 format!("Unrecognized PATCH request path `{}`.", *config_path),
@@ -56,30 +75,40 @@ mod tests {
 
     #[test]
     fn test_parse_get_balloon_request() {
-        assert!(parse_get_balloon(None).is_ok());
+        assert!(parse_get_balloon(None, None).is_ok());
+
+        assert!(parse_get_balloon(Some(&"unrelated"), None).is_err());
+
+        assert!(parse_get_balloon(Some(&"statistics"), None).is_ok());
+
+        assert!(parse_get_balloon(Some(&"hinting"), None).is_err());
 
-        assert!(parse_get_balloon(Some(&"unrelated")).is_err());
+        assert!(parse_get_balloon(Some(&"hinting"), Some(&"unrelated")).is_err());
 
-        assert!(parse_get_balloon(Some(&"statistics")).is_ok());
+        match vmm_action_from_request(parse_get_balloon(Some(&"hinting"), Some(&"status")).unwrap())
+        {
+            VmmAction::GetBalloonHintingStatus => (),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
     }
 
     #[test]
     fn test_parse_patch_balloon_request() {
-        assert!(parse_patch_balloon(&Body::new("invalid_payload"), None).is_err());
+        assert!(parse_patch_balloon(&Body::new("invalid_payload"), None, None).is_err());
 
         // PATCH with invalid fields.
         let body = r#"{
                 "amount_mb": "bar",
                 "foo": "bar"
               }"#;
-        assert!(parse_patch_balloon(&Body::new(body), None).is_err());
+        assert!(parse_patch_balloon(&Body::new(body), None, None).is_err());
 
         // PATCH with invalid types on fields. Adding a polling interval as string instead of bool.
         let body = r#"{
                 "amount_mb": 1000,
                 "stats_polling_interval_s": "false"
               }"#;
-        let res = parse_patch_balloon(&Body::new(body), None);
+        let res = parse_patch_balloon(&Body::new(body), None, None);
         assert!(res.is_err());
 
         // PATCH with invalid types on fields. Adding a amount_mb as a negative number.
@@ -87,21 +116,21 @@ mod tests {
                 "amount_mb": -1000,
                 "stats_polling_interval_s": true
               }"#;
-        let res = parse_patch_balloon(&Body::new(body), None);
+        let res = parse_patch_balloon(&Body::new(body), None, None);
         assert!(res.is_err());
 
         // PATCH on statistics with missing ppolling interval field.
         let body = r#"{
                 "amount_mb": 100
               }"#;
-        let res = parse_patch_balloon(&Body::new(body), Some(&"statistics"));
+        let res = parse_patch_balloon(&Body::new(body), Some(&"statistics"), None);
         assert!(res.is_err());
 
         // PATCH with missing amount_mb field.
         let body = r#"{
                 "stats_polling_interval_s": 0
               }"#;
-        let res = parse_patch_balloon(&Body::new(body), None);
+        let res = parse_patch_balloon(&Body::new(body), None, None);
         assert!(res.is_err());
 
         // PATCH that tries to update something else other than allowed fields.
@@ -110,26 +139,26 @@ mod tests {
                 "stats_polling_interval_s": "dummy_host",
                 "must_tell_host": false
               }"#;
-        let res = parse_patch_balloon(&Body::new(body), None);
+        let res = parse_patch_balloon(&Body::new(body), None, None);
         assert!(res.is_err());
 
         // PATCH with payload that is not a json.
         let body = r#"{
                 "fields": "dummy_field"
               }"#;
-        assert!(parse_patch_balloon(&Body::new(body), None).is_err());
+        assert!(parse_patch_balloon(&Body::new(body), None, None).is_err());
 
         // PATCH on unrecognized path.
         let body = r#"{
             "fields": "dummy_field"
           }"#;
-        assert!(parse_patch_balloon(&Body::new(body), Some(&"config")).is_err());
+        assert!(parse_patch_balloon(&Body::new(body), Some(&"config"), None).is_err());
 
         let body = r#"{
                 "amount_mb": 1
               }"#;
         #[allow(clippy::match_wild_err_arm)]
-        match vmm_action_from_request(parse_patch_balloon(&Body::new(body), None).unwrap()) {
+        match vmm_action_from_request(parse_patch_balloon(&Body::new(body), None, None).unwrap()) {
             VmmAction::UpdateBalloon(balloon_cfg) => assert_eq!(balloon_cfg.amount_mb, 1),
             _ => panic!("Test failed: Invalid parameters"),
         };
@@ -139,13 +168,53 @@ mod tests {
             }"#;
         #[allow(clippy::match_wild_err_arm)]
         match vmm_action_from_request(
-            parse_patch_balloon(&Body::new(body), Some(&"statistics")).unwrap(),
+            parse_patch_balloon(&Body::new(body), Some(&"statistics"), None).unwrap(),
         ) {
             VmmAction::UpdateBalloonStatistics(balloon_cfg) => {
                 assert_eq!(balloon_cfg.stats_polling_interval_s, 1)
             }
             _ => panic!("Test failed: Invalid parameters"),
         };
+
+        // PATCH on hinting without a command.
+        assert!(parse_patch_balloon(&Body::new("{}"), Some(&"hinting"), None).is_err());
+
+        // PATCH on an unrecognized hinting command.
+        assert!(parse_patch_balloon(&Body::new("{}"), Some(&"hinting"), Some(&"pause")).is_err());
+
+        // PATCH on hinting start with invalid fields.
+        let body = r#"{
+                "acknowledge_on_stop": true,
+                "foo": "bar"
+            }"#;
+        assert!(parse_patch_balloon(&Body::new(body), Some(&"hinting"), Some(&"start")).is_err());
+
+        // PATCH on hinting start without fields acknowledges on stop.
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(
+            parse_patch_balloon(&Body::new("{}"), Some(&"hinting"), Some(&"start")).unwrap(),
+        ) {
+            VmmAction::StartBalloonHinting(cmd) => assert!(cmd.acknowledge_on_stop),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+                "acknowledge_on_stop": false
+            }"#;
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(
+            parse_patch_balloon(&Body::new(body), Some(&"hinting"), Some(&"start")).unwrap(),
+        ) {
+            VmmAction::StartBalloonHinting(cmd) => assert!(!cmd.acknowledge_on_stop),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        match vmm_action_from_request(
+            parse_patch_balloon(&Body::new("{}"), Some(&"hinting"), Some(&"stop")).unwrap(),
+        ) {
+            VmmAction::StopBalloonHinting => (),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
     }
 
     #[test]
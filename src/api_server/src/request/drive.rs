@@ -36,22 +36,40 @@ impl PatchDrivePayload {
         Ok(())
     }
 
-    /// Validates that only path_on_host and drive_id are present in the payload.
+    /// Validates that only drive_id, and path_on_host or refresh_size, are present in the
+    /// payload.
     fn validate(&self) -> Result<(), Error> {
         match self.fields.as_object() {
             Some(fields_map) => {
                 // Check that field `drive_id` exists and its type is String.
                 PatchDrivePayload::check_field_is_string(fields_map, "drive_id")
                     .map_err(|e| Error::Generic(StatusCode::BadRequest, e))?;
-                // Check that field `path_on_host` exists and its type is String.
-                PatchDrivePayload::check_field_is_string(fields_map, "path_on_host")
-                    .map_err(|e| Error::Generic(StatusCode::BadRequest, e))?;
+                // Check that field `refresh_size`, if present, has the type Bool.
+                let refresh_size = match fields_map.get("refresh_size") {
+                    None => None,
+                    Some(value) => Some(value.as_bool().ok_or_else(|| {
+                        Error::Generic(
+                            StatusCode::BadRequest,
+                            "Invalid type for key refresh_size.".to_string(),
+                        )
+                    })?),
+                };
+                // Check that field `path_on_host` exists and its type is String, unless the
+                // size of the drive is refreshed.
+                if refresh_size != Some(true) || fields_map.contains_key("path_on_host") {
+                    PatchDrivePayload::check_field_is_string(fields_map, "path_on_host")
+                        .map_err(|e| Error::Generic(StatusCode::BadRequest, e))?;
+                }
 
                 // Check that there are no other fields in the object.
-                if fields_map.len() > 2 {
+                let num_fields = 1
+                    + fields_map.contains_key("path_on_host") as usize
+                    + refresh_size.is_some() as usize;
+                if fields_map.len() > num_fields {
                     return Err(Error::Generic(
                         StatusCode::BadRequest,
-                        "Invalid PATCH payload. Only updates on path_on_host are allowed."
+                        "Invalid PATCH payload. Only updates on path_on_host and refresh_size \
+                         are allowed."
                             .to_string(),
                     ));
                 }
@@ -76,6 +94,27 @@ impl PatchDrivePayload {
     }
 }
 
+pub fn parse_get_drive(
+    id_from_path: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    match path_third_token {
+        Some(&"latencies") => Ok(ParsedRequest::new_sync(VmmAction::GetBlockDeviceLatencies(
+            id.to_string(),
+        ))),
+        _ => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized GET request path `/drives/{}`.", id),
+        )),
+    }
+}
+
 pub fn parse_put_drive(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.drive_count.inc();
     let id = if let Some(id) = id_from_path {
@@ -121,7 +160,6 @@ pub fn parse_patch_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Par
 
     patch_drive_payload.validate()?;
     let drive_id: String = patch_drive_payload.get_string_field_unchecked("drive_id");
-    let path_on_host: String = patch_drive_payload.get_string_field_unchecked("path_on_host");
 
     if id != drive_id.as_str() {
         METRICS.patch_api_requests.drive_fails.inc();
@@ -131,6 +169,13 @@ pub fn parse_patch_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Par
         ));
     }
 
+    // Updating the path also picks up the size of the new backing file.
+    if patch_drive_payload.fields.get("path_on_host").is_none() {
+        return Ok(ParsedRequest::new_sync(VmmAction::UpdateBlockDeviceSize(
+            drive_id,
+        )));
+    }
+    let path_on_host: String = patch_drive_payload.get_string_field_unchecked("path_on_host");
     Ok(ParsedRequest::new_sync(VmmAction::UpdateBlockDevicePath(
         drive_id,
         path_on_host,
@@ -141,6 +186,20 @@ pub fn parse_patch_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Par
 mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::drive::{CacheType, ImageFormat};
+
+    #[test]
+    fn test_parse_get_drive_request() {
+        assert!(parse_get_drive(None, None).is_err());
+        assert!(parse_get_drive(Some(&"bar"), None).is_err());
+        assert!(parse_get_drive(Some(&"bar"), Some(&"unrelated")).is_err());
+        assert!(parse_get_drive(Some(&"b@r"), Some(&"latencies")).is_err());
+
+        match vmm_action_from_request(parse_get_drive(Some(&"bar"), Some(&"latencies")).unwrap()) {
+            VmmAction::GetBlockDeviceLatencies(drive_id) => assert_eq!(drive_id, "bar"),
+            _ => panic!("Test failed: Invalid parameters"),
+        }
+    }
 
     #[test]
     fn test_parse_patch_drive_request() {
@@ -217,6 +276,52 @@ mod tests {
                 "path_on_host": "dummy"
               }"#;
         assert!(parse_patch_drive(&Body::new(body), Some(&"bar")).is_err());
+
+        // PATCH that refreshes the size of the drive.
+        let body = r#"{
+                "drive_id": "foo",
+                "refresh_size": true
+              }"#;
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDeviceSize(a) => assert_eq!(a, "foo".to_string()),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        // A new path also refreshes the size.
+        let body = r#"{
+                "drive_id": "foo",
+                "path_on_host": "dummy",
+                "refresh_size": true
+              }"#;
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevicePath(a, b) => {
+                assert_eq!(a, "foo".to_string());
+                assert_eq!(b, "dummy".to_string());
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        // PATCH with nothing to update.
+        let body = r#"{
+                "drive_id": "foo",
+                "refresh_size": false
+              }"#;
+        assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_err());
+
+        // PATCH with an invalid type for refresh_size.
+        let body = r#"{
+                "drive_id": "foo",
+                "refresh_size": "true"
+              }"#;
+        assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_err());
+
+        // PATCH that refreshes the size and tries to update something else.
+        let body = r#"{
+                "drive_id": "foo",
+                "refresh_size": true,
+                "is_read_only": false
+              }"#;
+        assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_err());
     }
 
     #[test]
@@ -254,6 +359,69 @@ mod tests {
         assert!(parse_put_drive(&Body::new(body), Some(&"1000")).is_ok());
 
         assert!(parse_put_drive(&Body::new(body), Some(&"foo")).is_err());
+
+        // PUT with a qcow2 image.
+        let body = r#"{
+                "drive_id": "1000",
+                "path_on_host": "dummy.qcow2",
+                "format": "Qcow2",
+                "is_root_device": false,
+                "is_read_only": false
+            }"#;
+        match vmm_action_from_request(parse_put_drive(&Body::new(body), Some(&"1000")).unwrap()) {
+            VmmAction::InsertBlockDevice(cfg) => assert_eq!(cfg.format, ImageFormat::Qcow2),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        // PUT with an unknown image format.
+        let body = r#"{
+                "drive_id": "1000",
+                "path_on_host": "dummy.vmdk",
+                "format": "Vmdk",
+                "is_root_device": false,
+                "is_read_only": false
+            }"#;
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000")).is_err());
+
+        // PUT with a cache type.
+        let body = r#"{
+                "drive_id": "1000",
+                "path_on_host": "dummy",
+                "cache_type": "Direct",
+                "is_root_device": false,
+                "is_read_only": false
+            }"#;
+        match vmm_action_from_request(parse_put_drive(&Body::new(body), Some(&"1000")).unwrap()) {
+            VmmAction::InsertBlockDevice(cfg) => assert_eq!(cfg.cache_type, CacheType::Direct),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        // PUT with an unknown cache type.
+        let body = r#"{
+                "drive_id": "1000",
+                "path_on_host": "dummy",
+                "cache_type": "Writethrough",
+                "is_root_device": false,
+                "is_read_only": false
+            }"#;
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000")).is_err());
+
+        // PUT with multiple queues serviced by I/O threads.
+        let body = r#"{
+                "drive_id": "1000",
+                "path_on_host": "dummy",
+                "is_root_device": false,
+                "is_read_only": false,
+                "num_queues": 4,
+                "io_threads": true
+            }"#;
+        match vmm_action_from_request(parse_put_drive(&Body::new(body), Some(&"1000")).unwrap()) {
+            VmmAction::InsertBlockDevice(cfg) => {
+                assert_eq!(cfg.num_queues, 4);
+                assert!(cfg.io_threads);
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
     }
 
     #[test]
@@ -46,6 +46,7 @@ pub fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     check_unsupported_fields(&vm_config)?;
 
     if vm_config.vcpu_count.is_none()
+        && vm_config.max_vcpu_count.is_none()
         && vm_config.mem_size_mib.is_none()
         && vm_config.cpu_template.is_none()
         && vm_config.ht_enabled.is_none()
@@ -67,6 +68,13 @@ fn check_unsupported_fields(_vm_config: &VmConfig) -> Result<(), Error> {
                 "CPU templates are not supported on aarch64".to_string(),
             ));
         }
+        if _vm_config.max_vcpu_count.is_some() {
+            // vCPU hotplug is not supported on aarch64
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "vCPU hotplug is not supported on aarch64".to_string(),
+            ));
+        }
     }
     Ok(())
 }
@@ -116,6 +124,7 @@ mod tests {
               }"#;
         let expected_config = VmConfig {
             vcpu_count: Some(8),
+            max_vcpu_count: None,
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
             cpu_template: None,
@@ -141,6 +150,7 @@ mod tests {
             use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
             let expected_config = VmConfig {
                 vcpu_count: Some(8),
+                max_vcpu_count: None,
                 mem_size_mib: Some(1024),
                 ht_enabled: Some(true),
                 cpu_template: Some(CpuFeaturesTemplate::T2),
@@ -191,5 +201,14 @@ mod tests {
                 "ht_enabled": false
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        // On aarch64, vCPU hotplug is not supported.
+        let body = r#"{
+                "max_vcpu_count": 4
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
     }
 }
@@ -0,0 +1,101 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugSizeUpdate};
+
+pub fn parse_get_memory_hotplug() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetMemoryHotplugStatus))
+}
+
+pub fn parse_put_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryHotplugDevice(
+        serde_json::from_slice::<MemoryHotplugConfig>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
+pub fn parse_patch_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateMemoryHotplugSize(
+        serde_json::from_slice::<MemoryHotplugSizeUpdate>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_memory_hotplug_request() {
+        match vmm_action_from_request(parse_get_memory_hotplug().unwrap()) {
+            VmmAction::GetMemoryHotplugStatus => (),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+    }
+
+    #[test]
+    fn test_parse_put_memory_hotplug_request() {
+        assert!(parse_put_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PUT with missing total size.
+        let body = r#"{
+                "block_size_mib": 2
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "total_size_mib": 1024,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        // PUT with valid input fields.
+        let body = r#"{
+                "total_size_mib": 1024,
+                "block_size_mib": 4,
+                "requested_size_mib": 512
+            }"#;
+        match vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap()) {
+            VmmAction::SetMemoryHotplugDevice(config) => assert_eq!(
+                config,
+                MemoryHotplugConfig {
+                    total_size_mib: 1024,
+                    block_size_mib: 4,
+                    requested_size_mib: 512,
+                }
+            ),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+    }
+
+    #[test]
+    fn test_parse_patch_memory_hotplug_request() {
+        assert!(parse_patch_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PATCH that tries to update something else than the requested size.
+        let body = r#"{
+                "requested_size_mib": 512,
+                "total_size_mib": 2048
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        // PATCH with a negative size.
+        let body = r#"{
+                "requested_size_mib": -2
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "requested_size_mib": 512
+              }"#;
+        match vmm_action_from_request(parse_patch_memory_hotplug(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateMemoryHotplugSize(update) => {
+                assert_eq!(update.requested_size_mib, 512)
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+    }
+}
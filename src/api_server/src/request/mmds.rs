@@ -61,6 +61,17 @@ mod tests {
         let path = "config";
         assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
 
+        let ipv6_body = r#"{
+                "ipv4_address": "169.254.170.2",
+                "ipv6_address": "fd00:ec2::254"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(ipv6_body), Some(&path)).is_ok());
+
+        let ipv6_body = r#"{
+                "ipv6_address": "169.254.170.2"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(ipv6_body), Some(&path)).is_err());
+
         let body = r#"{
                 "ipv4_address": ""
               }"#;
@@ -8,6 +8,7 @@ pub mod drive;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
+pub mod memory_hotplug;
 pub mod metrics;
 pub mod mmds;
 pub mod net;
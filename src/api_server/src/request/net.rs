@@ -5,7 +5,9 @@ use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceUpdateConfig,
+};
 
 pub fn parse_put_net(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.network_count.inc();
@@ -58,10 +60,57 @@ pub fn parse_patch_net(body: &Body, id_from_path: Option<&&str>) -> Result<Parse
     )))
 }
 
+pub fn parse_patch_net_capture(
+    body: &Body,
+    id_from_path: Option<&&str>,
+    path_fourth_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.patch_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.patch_api_requests.network_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    match path_fourth_token {
+        Some(&"start") => {
+            let capture =
+                serde_json::from_slice::<NetworkCaptureConfig>(body.raw()).map_err(|e| {
+                    METRICS.patch_api_requests.network_fails.inc();
+                    Error::SerdeJson(e)
+                })?;
+            if id != capture.iface_id {
+                METRICS.patch_api_requests.network_fails.inc();
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    "The id from the path does not match the id from the body!".to_string(),
+                ));
+            }
+            Ok(ParsedRequest::new_sync(VmmAction::StartNetworkCapture(
+                capture,
+            )))
+        }
+        // The body of a stop request carries no information.
+        Some(&"stop") => Ok(ParsedRequest::new_sync(VmmAction::StopNetworkCapture(
+            id.to_string(),
+        ))),
+        _ => {
+            METRICS.patch_api_requests.network_fails.inc();
+            Err(Error::Generic(
+                StatusCode::BadRequest,
+                "Unrecognized PATCH request path `capture`.".to_string(),
+            ))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
+    use std::net::Ipv4Addr;
+    use vmm::vmm_config::net::{AntiSpoofingConfig, DhcpConfig, NetworkBackendConfig};
 
     #[test]
     fn test_parse_put_net_request() {
@@ -102,6 +151,114 @@ mod tests {
         }"#;
 
         assert!(parse_put_net(&Body::new(body), Some(&"foo")).is_err());
+
+        // 5. Alternative host backends.
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "/tmp/net.sock",
+                "backend": {
+                    "type": "UnixSocket"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => {
+                assert_eq!(netif.backend, NetworkBackendConfig::UnixSocket)
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "eth0",
+                "backend": {
+                    "type": "Xdp",
+                    "xsk_map_path": "/sys/fs/bpf/xsks_map"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => assert_eq!(
+                netif.backend,
+                NetworkBackendConfig::Xdp {
+                    xsk_map_path: String::from("/sys/fs/bpf/xsks_map")
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "eth0",
+                "backend": {
+                    "type": "Xdp"
+                }
+              }"#;
+        assert!(parse_put_net(&Body::new(body), Some(&"foo")).is_err());
+
+        // 6. Anti-spoofing filter.
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "bar",
+                "guest_mac": "12:34:56:78:9A:BC",
+                "anti_spoofing": {
+                    "allowed_ipv4": ["10.1.2.3", "10.1.2.4"]
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => assert_eq!(
+                netif.anti_spoofing,
+                Some(AntiSpoofingConfig {
+                    allowed_ipv4: Some(vec![
+                        Ipv4Addr::new(10, 1, 2, 3),
+                        Ipv4Addr::new(10, 1, 2, 4)
+                    ])
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "bar",
+                "anti_spoofing": {
+                    "allowed_ipv4": ["10.1.2"]
+                }
+              }"#;
+        assert!(parse_put_net(&Body::new(body), Some(&"foo")).is_err());
+
+        // 7. DHCP server.
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "bar",
+                "dhcp": {
+                    "ipv4_address": "172.16.0.2",
+                    "prefix_len": 30,
+                    "gateway": "172.16.0.1"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => assert_eq!(
+                netif.dhcp,
+                Some(DhcpConfig {
+                    ipv4_address: Ipv4Addr::new(172, 16, 0, 2),
+                    prefix_len: 30,
+                    gateway: Some(Ipv4Addr::new(172, 16, 0, 1)),
+                    dns_servers: vec![],
+                    lease_time_s: 86400,
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "bar",
+                "dhcp": {
+                    "ipv4_address": "172.16.0.2",
+                    "prefix_len": 30,
+                    "netmask": "255.255.255.252"
+                }
+              }"#;
+        assert!(parse_put_net(&Body::new(body), Some(&"foo")).is_err());
     }
 
     #[test]
@@ -143,5 +300,73 @@ mod tests {
             }
         }"#;
         assert!(parse_patch_net(&Body::new(body), Some(&"foo")).is_err());
+
+        // 5. Link state update.
+        let body = r#"{
+                "iface_id": "foo",
+                "link_up": false,
+                "announce": true
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => {
+                assert_eq!(netif.link_up, Some(false));
+                assert!(netif.announce);
+                assert_eq!(netif.host_dev_name, None);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 6. TAP device swap.
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "tap1"
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => {
+                assert_eq!(netif.host_dev_name, Some(String::from("tap1")));
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_patch_net_capture_request() {
+        let body = r#"{
+                "iface_id": "foo",
+                "path": "/tmp/foo.pcapng",
+                "format": "Pcapng",
+                "ring_size": 100
+        }"#;
+        // 1. Exercise infamous "The id from the path does not match id from the body!".
+        assert!(parse_patch_net_capture(&Body::new(body), Some(&"bar"), Some(&"start")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_patch_net_capture(&Body::new(body), None, Some(&"start")).is_err());
+        // 3. Unknown actions.
+        assert!(parse_patch_net_capture(&Body::new(body), Some(&"foo"), None).is_err());
+        assert!(parse_patch_net_capture(&Body::new(body), Some(&"foo"), Some(&"pause")).is_err());
+
+        // 4. Success cases.
+        let capture_clone = serde_json::from_str::<NetworkCaptureConfig>(body).unwrap();
+        assert_eq!(capture_clone.snaplen, 65535);
+        match vmm_action_from_request(
+            parse_patch_net_capture(&Body::new(body), Some(&"foo"), Some(&"start")).unwrap(),
+        ) {
+            VmmAction::StartNetworkCapture(capture) => assert_eq!(capture, capture_clone),
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(
+            parse_patch_net_capture(&Body::new(""), Some(&"foo"), Some(&"stop")).unwrap(),
+        ) {
+            VmmAction::StopNetworkCapture(iface_id) => assert_eq!(iface_id, "foo"),
+            _ => panic!("Test failed."),
+        }
+
+        // 5. Serde error for an unknown format.
+        let body = r#"{
+                "iface_id": "foo",
+                "path": "/tmp/foo.pcap",
+                "format": "Erf"
+        }"#;
+        assert!(parse_patch_net_capture(&Body::new(body), Some(&"foo"), Some(&"start")).is_err());
     }
 }
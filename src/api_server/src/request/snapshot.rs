@@ -56,20 +56,37 @@ mod tests {
     #[cfg(target_arch = "x86_64")]
     fn test_parse_put_snapshot() {
         use std::path::PathBuf;
-        use vmm::vmm_config::snapshot::SnapshotType;
+        use utils::net::mac::MacAddr;
+        use vmm::vmm_config::snapshot::{
+            DriveOverlay, MemFileFormat, NetworkOverride, SnapshotType,
+        };
 
         let mut body = r#"{
                 "snapshot_type": "Diff",
                 "snapshot_path": "foo",
                 "mem_file_path": "bar",
-                "version": "0.23.0"
+                "mem_file_format": "Compressed",
+                "version": "0.23.0",
+                "reset_vsock": true,
+                "drive_overlays": [
+                    {
+                        "drive_id": "rootfs",
+                        "overlay_path": "rootfs.overlay"
+                    }
+                ]
               }"#;
 
         let mut expected_cfg = CreateSnapshotParams {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_file_format: MemFileFormat::Compressed,
             version: Some(String::from("0.23.0")),
+            reset_vsock: true,
+            drive_overlays: vec![DriveOverlay {
+                drive_id: String::from("rootfs"),
+                overlay_path: String::from("rootfs.overlay"),
+            }],
         };
 
         match vmm_action_from_request(
@@ -88,7 +105,10 @@ mod tests {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_file_format: MemFileFormat::Raw,
             version: None,
+            reset_vsock: false,
+            drive_overlays: Vec::new(),
         };
 
         match vmm_action_from_request(
@@ -114,6 +134,8 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             enable_diff_snapshots: false,
+            network_overrides: Vec::new(),
+            drive_overlays: Vec::new(),
         };
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
         {
@@ -131,6 +153,47 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             enable_diff_snapshots: true,
+            network_overrides: Vec::new(),
+            drive_overlays: Vec::new(),
+        };
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "network_overrides": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "tap1",
+                        "guest_mac": "06:00:00:00:00:01"
+                    }
+                ],
+                "drive_overlays": [
+                    {
+                        "drive_id": "rootfs",
+                        "overlay_path": "clone.overlay"
+                    }
+                ]
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            enable_diff_snapshots: false,
+            network_overrides: vec![NetworkOverride {
+                iface_id: String::from("eth0"),
+                host_dev_name: String::from("tap1"),
+                guest_mac: Some(MacAddr::parse_str("06:00:00:00:00:01").unwrap()),
+            }],
+            drive_overlays: vec![DriveOverlay {
+                drive_id: String::from("rootfs"),
+                overlay_path: String::from("clone.overlay"),
+            }],
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
@@ -25,6 +25,25 @@ mod tests {
               }"#;
         assert!(parse_put_vsock(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "port_map": [
+                    { "port": 52, "tcp_port": 8080 },
+                    { "port": 53, "uds_path": "53.sock", "listen_uds_path": "53_listen.sock" }
+                ]
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "port_map": [{ "port": 52, "invalid_field": 8080 }]
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_err());
+
         let body = r#"{
                 "vsock_id": "foo",
                 "guest_cid": 42,
@@ -54,6 +54,9 @@ pub const DRAM_MEM_START: u64 = 0x8000_0000; // 2 GB.
 /// The maximum RAM size.
 pub const DRAM_MEM_MAX_SIZE: u64 = 0x00FF_8000_0000; // 1024 - 2 = 1022G.
 
+/// Alignment of the memory hotplug region, matching the largest arm64 memory section size.
+pub const HOTPLUG_MEM_ALIGNMENT: u64 = 1 << 30; // 1 GB.
+
 /// Kernel command line maximum size.
 /// As per `arch/arm64/include/uapi/asm/setup.h`.
 pub const CMDLINE_MAX_SIZE: usize = 2048;
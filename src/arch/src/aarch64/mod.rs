@@ -76,6 +76,18 @@ pub fn get_kernel_start() -> u64 {
     layout::DRAM_MEM_START
 }
 
+/// Returns the start address of the memory hotplug region.
+/// The region is placed right after the DRAM and is aligned to the largest memory block size
+/// used by aarch64 guest kernels, so it can be onlined in whole blocks.
+pub fn hotplug_memory_start(guest_mem: &GuestMemoryMmap) -> u64 {
+    hotplug_memory_start_after(guest_mem.last_addr().raw_value() + 1)
+}
+
+/// Returns the start address of the memory hotplug region for DRAM ending at `dram_end`.
+pub fn hotplug_memory_start_after(dram_end: u64) -> u64 {
+    (dram_end + layout::HOTPLUG_MEM_ALIGNMENT - 1) & !(layout::HOTPLUG_MEM_ALIGNMENT - 1)
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> super::Result<u64> {
     let round_to_pagesize = |size| (size + (super::PAGE_SIZE - 1)) & !(super::PAGE_SIZE - 1);
@@ -127,6 +139,16 @@ mod tests {
         assert_eq!(super::layout::DRAM_MEM_MAX_SIZE, regions[0].1 as u64);
     }
 
+    #[test]
+    fn test_hotplug_memory_start() {
+        let regions = arch_memory_regions(1usize << 29);
+        let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
+        assert_eq!(
+            hotplug_memory_start(&mem),
+            layout::DRAM_MEM_START + layout::HOTPLUG_MEM_ALIGNMENT
+        );
+    }
+
     #[test]
     fn test_get_fdt_addr() {
         let regions = arch_memory_regions(layout::FDT_MAX_SIZE - 0x1000);
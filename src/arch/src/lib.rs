@@ -13,8 +13,9 @@ pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, get_kernel_start, hotplug_memory_start,
+    hotplug_memory_start_after, initrd_load_addr, layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE,
+    layout::IRQ_MAX, Error, MMIO_MEM_START,
 };
 
 /// Module for x86_64 related functionality.
@@ -23,8 +24,9 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use crate::x86_64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, get_kernel_start, hotplug_memory_start,
+    hotplug_memory_start_after, initrd_load_addr, layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE,
+    layout::IRQ_MAX, Error, MMIO_MEM_START,
 };
 
 /// Type for returning public functions outcome.
@@ -43,6 +45,9 @@ pub enum DeviceType {
     RTC,
     /// Device Type: BootTimer.
     BootTimer,
+    /// Device Type: CpuHotplug.
+    #[cfg(target_arch = "x86_64")]
+    CpuHotplug,
 }
 
 /// Type for passing information about the initrd in the guest memory.
@@ -50,6 +50,8 @@ pub enum Error {
 const EBDA_START: u64 = 0x9fc00;
 const FIRST_ADDR_PAST_32BITS: u64 = 1 << 32;
 const MEM_32BIT_GAP_SIZE: u64 = 768 << 20;
+// Linux memory block size (the hotplug granularity) on x86_64.
+const HOTPLUG_MEM_ALIGNMENT: u64 = 128 << 20;
 /// The start of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
 
@@ -76,6 +78,20 @@ pub fn get_kernel_start() -> u64 {
     layout::HIMEM_START
 }
 
+/// Returns the start address of the memory hotplug region.
+/// The region is placed right after the boot memory, above the 32bit memory hole, and is aligned
+/// to the memory block size of the guest kernel so it can be onlined in whole blocks.
+pub fn hotplug_memory_start(guest_mem: &GuestMemoryMmap) -> u64 {
+    hotplug_memory_start_after(guest_mem.last_addr().raw_value() + 1)
+}
+
+/// Returns the start address of the memory hotplug region for boot memory ending at
+/// `boot_mem_end`.
+pub fn hotplug_memory_start_after(boot_mem_end: u64) -> u64 {
+    let boot_mem_end = std::cmp::max(boot_mem_end, FIRST_ADDR_PAST_32BITS);
+    (boot_mem_end + HOTPLUG_MEM_ALIGNMENT - 1) & !(HOTPLUG_MEM_ALIGNMENT - 1)
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> super::Result<u64> {
     let first_region = guest_mem
@@ -216,6 +232,22 @@ mod tests {
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn test_hotplug_memory_start() {
+        let gm = GuestMemoryMmap::from_ranges(&arch_memory_regions(128 << 20)).unwrap();
+        assert_eq!(hotplug_memory_start(&gm), FIRST_ADDR_PAST_32BITS);
+
+        let gm = GuestMemoryMmap::from_ranges(&arch_memory_regions(3330 << 20)).unwrap();
+        assert_eq!(
+            hotplug_memory_start(&gm),
+            FIRST_ADDR_PAST_32BITS + (128 << 20)
+        );
+        assert_eq!(
+            hotplug_memory_start_after(gm.last_addr().raw_value() + 1),
+            hotplug_memory_start(&gm)
+        );
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
@@ -29,6 +29,11 @@ pub(crate) fn report_balloon_event_fail(err: virtio::balloon::Error) {
     METRICS.balloon.event_fails.inc();
 }
 
+pub(crate) fn report_mem_event_fail(err: virtio::mem::Error) {
+    error!("{:?}", err);
+    METRICS.mem.event_fails.inc();
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Failed to read from the TAP device.
@@ -0,0 +1,211 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+
+use crate::bus::BusDevice;
+use snapshot::Persist;
+use utils::byte_order;
+use utils::eventfd::EventFd;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+// Read only: bitmap of the vCPUs currently present, bit `n` standing for vCPU `n`.
+const PRESENT_CPUS_OFFSET: u64 = 0x0;
+// Read: 1 if the set of present vCPUs changed since the last acknowledge, 0 otherwise.
+// Write: any value acknowledges the change.
+const STATUS_OFFSET: u64 = 0x4;
+// Read/write: bitmap of the vCPUs the guest has online. The guest writes it after onlining or
+// offlining vCPUs.
+const ONLINE_CPUS_OFFSET: u64 = 0x8;
+
+/// Pseudo device notifying the guest about vCPUs being hot-plugged or unplugged.
+///
+/// Each change of the present vCPUs set raises the device interrupt. The guest reads the new
+/// set from the device, onlines or offlines the vCPUs accordingly, and reports the vCPUs it
+/// has online back to the device.
+pub struct CpuHotplug {
+    present_cpus: u32,
+    online_cpus: u32,
+    pending: bool,
+    interrupt_evt: EventFd,
+}
+
+impl CpuHotplug {
+    /// Creates the device with the first `vcpu_count` vCPUs marked as present and online.
+    pub fn new(vcpu_count: u8, interrupt_evt: EventFd) -> CpuHotplug {
+        CpuHotplug {
+            present_cpus: Self::cpus_mask(vcpu_count),
+            online_cpus: Self::cpus_mask(vcpu_count),
+            pending: false,
+            interrupt_evt,
+        }
+    }
+
+    /// Marks the first `vcpu_count` vCPUs as present and notifies the guest.
+    pub fn set_vcpu_count(&mut self, vcpu_count: u8) -> io::Result<()> {
+        self.present_cpus = Self::cpus_mask(vcpu_count);
+        self.pending = true;
+        self.interrupt_evt.write(1)
+    }
+
+    /// Returns the bitmap of the present vCPUs.
+    pub fn present_cpus(&self) -> u32 {
+        self.present_cpus
+    }
+
+    /// Returns the bitmap of the vCPUs the guest reported as online.
+    pub fn online_cpus(&self) -> u32 {
+        self.online_cpus
+    }
+
+    /// Returns the event signaling the device interrupt.
+    pub fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    /// Returns the bitmap of the first `vcpu_count` vCPUs.
+    pub fn cpus_mask(vcpu_count: u8) -> u32 {
+        if vcpu_count >= 32 {
+            u32::max_value()
+        } else {
+            (1u32 << vcpu_count) - 1
+        }
+    }
+}
+
+impl BusDevice for CpuHotplug {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        // Only handle 4 byte accesses.
+        if data.len() != 4 {
+            return;
+        }
+
+        match offset {
+            PRESENT_CPUS_OFFSET => byte_order::write_le_u32(data, self.present_cpus),
+            STATUS_OFFSET => byte_order::write_le_u32(data, self.pending as u32),
+            ONLINE_CPUS_OFFSET => byte_order::write_le_u32(data, self.online_cpus),
+            _ => (),
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        match offset {
+            STATUS_OFFSET => self.pending = false,
+            ONLINE_CPUS_OFFSET if data.len() == 4 => {
+                self.online_cpus = byte_order::read_le_u32(data)
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Holds the state of the vCPU hotplug device.
+#[derive(Clone, Versionize)]
+pub struct CpuHotplugState {
+    present_cpus: u32,
+    online_cpus: u32,
+    pending: bool,
+}
+
+impl Persist<'_> for CpuHotplug {
+    type State = CpuHotplugState;
+    type ConstructorArgs = EventFd;
+    type Error = ();
+
+    fn save(&self) -> Self::State {
+        CpuHotplugState {
+            present_cpus: self.present_cpus,
+            online_cpus: self.online_cpus,
+            pending: self.pending,
+        }
+    }
+
+    fn restore(
+        interrupt_evt: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        Ok(CpuHotplug {
+            present_cpus: state.present_cpus,
+            online_cpus: state.online_cpus,
+            pending: state.pending,
+            interrupt_evt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_reg(device: &mut CpuHotplug, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        device.read(offset, &mut data);
+        byte_order::read_le_u32(&data)
+    }
+
+    #[test]
+    fn test_cpu_hotplug() {
+        let evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut device = CpuHotplug::new(2, evt.try_clone().unwrap());
+        assert_eq!(read_reg(&mut device, PRESENT_CPUS_OFFSET), 0b11);
+        assert_eq!(read_reg(&mut device, STATUS_OFFSET), 0);
+        assert_eq!(read_reg(&mut device, ONLINE_CPUS_OFFSET), 0b11);
+        assert!(evt.read().is_err());
+
+        // Plugging vCPUs raises the interrupt and flags the change.
+        device.set_vcpu_count(4).unwrap();
+        assert_eq!(device.present_cpus(), 0b1111);
+        assert_eq!(read_reg(&mut device, PRESENT_CPUS_OFFSET), 0b1111);
+        assert_eq!(read_reg(&mut device, STATUS_OFFSET), 1);
+        assert_eq!(evt.read().unwrap(), 1);
+
+        // The guest acknowledges the change and onlines the new vCPUs.
+        device.write(STATUS_OFFSET, &[0, 0, 0, 0]);
+        assert_eq!(read_reg(&mut device, STATUS_OFFSET), 0);
+        assert_eq!(device.online_cpus(), 0b11);
+        device.write(ONLINE_CPUS_OFFSET, &[0b1111, 0, 0, 0]);
+        assert_eq!(device.online_cpus(), 0b1111);
+
+        // Unplugging works the same way.
+        device.set_vcpu_count(1).unwrap();
+        assert_eq!(read_reg(&mut device, PRESENT_CPUS_OFFSET), 0b1);
+        assert_eq!(read_reg(&mut device, STATUS_OFFSET), 1);
+        assert_eq!(evt.read().unwrap(), 1);
+        device.write(ONLINE_CPUS_OFFSET, &[0b1, 0, 0, 0]);
+        assert_eq!(read_reg(&mut device, ONLINE_CPUS_OFFSET), 0b1);
+
+        // Writes to the read only register and wrongly sized accesses are ignored.
+        device.write(PRESENT_CPUS_OFFSET, &[0xff, 0, 0, 0]);
+        device.write(ONLINE_CPUS_OFFSET, &[0xff]);
+        let mut data = [0u8; 2];
+        device.read(PRESENT_CPUS_OFFSET, &mut data);
+        assert_eq!(data, [0, 0]);
+        assert_eq!(read_reg(&mut device, PRESENT_CPUS_OFFSET), 0b1);
+        assert_eq!(device.online_cpus(), 0b1);
+
+        assert_eq!(CpuHotplug::cpus_mask(32), u32::max_value());
+    }
+
+    #[test]
+    fn test_cpu_hotplug_persistence() {
+        let mut device = CpuHotplug::new(2, EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        device.set_vcpu_count(3).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        device
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        let restored_device = CpuHotplug::restore(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            &CpuHotplugState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_device.present_cpus(), 0b111);
+        assert_eq!(restored_device.online_cpus(), 0b11);
+        assert!(restored_device.pending);
+    }
+}
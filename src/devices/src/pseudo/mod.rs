@@ -2,5 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod boot_timer;
+#[cfg(target_arch = "x86_64")]
+mod cpu_hotplug;
 
 pub use self::boot_timer::BootTimer;
+#[cfg(target_arch = "x86_64")]
+pub use self::cpu_hotplug::{CpuHotplug, CpuHotplugState};
@@ -1,8 +1,9 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cmp;
+use std::collections::HashMap;
 use std::io::Write;
 use std::result::Result;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -19,9 +20,10 @@ use ::vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
 use super::*;
 use super::{
     super::{
-        ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON, VIRTIO_MMIO_INT_VRING,
+        ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON, VIRTIO_MMIO_INT_CONFIG,
+        VIRTIO_MMIO_INT_VRING,
     },
-    utils::{compact_page_frame_numbers, remove_range},
+    utils::{compact_page_frame_numbers, mark_discarded_range, remove_range},
     BALLOON_DEV_ID,
 };
 
@@ -56,6 +58,7 @@ fn pages_to_mb(amount_pages: u32) -> u32 {
 pub(crate) struct ConfigSpace {
     pub num_pages: u32,
     pub actual_pages: u32,
+    pub free_page_hint_cmd_id: u32,
 }
 
 // Safe because ConfigSpace only contains plain data.
@@ -80,6 +83,47 @@ pub struct BalloonConfig {
     pub deflate_on_oom: bool,
     pub must_tell_host: bool,
     pub stats_polling_interval_s: u16,
+    pub free_page_hinting: bool,
+    pub free_page_reporting: bool,
+    pub auto_target: Option<AutoTarget>,
+}
+
+// AutoTarget describes the policy used to adjust the target size of the
+// balloon from the statistics reported by the driver.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoTarget {
+    // The amount of memory the guest should keep available, in MiB.
+    pub min_free_mib: u32,
+    // The maximum target size of the balloon, in MiB.
+    pub max_balloon_mib: u32,
+    // The maximum amount the balloon is inflated by at once, in MiB.
+    pub inflate_rate_mib: u32,
+    // The maximum amount the balloon is deflated by at once, in MiB.
+    pub deflate_rate_mib: u32,
+}
+
+// The device side of the free page hinting protocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct HintingState {
+    // The command id of the last hinting run started by the device.
+    pub last_cmd_id: u32,
+    // The command id reported by the driver while it is hinting pages.
+    pub guest_cmd: Option<u32>,
+    // Whether the run is completed as soon as the driver stops hinting.
+    pub acknowledge_on_stop: bool,
+}
+
+/// Bitmaps of the pages discarded through free page hinting or reporting, one
+/// bit per 4K page, keyed by the index of the guest memory region.
+pub type DiscardedPages = HashMap<usize, Vec<u64>>;
+
+// HintingStatus describes the progress of the current free page hinting run.
+#[derive(Clone, Default, Debug, PartialEq, Serialize)]
+pub struct HintingStatus {
+    pub host_cmd: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guest_cmd: Option<u32>,
 }
 
 // BalloonStats holds statistics returned from the stats_queue.
@@ -158,6 +202,10 @@ pub struct Balloon {
     // it is acknowledged after the stats queue is processed.
     pub(crate) stats_desc_index: Option<u16>,
     pub(crate) latest_stats: BalloonStats,
+    pub(crate) hinting_state: HintingState,
+    pub(crate) auto_target: Option<AutoTarget>,
+    // The pages hinted or reported since the last call to `take_discarded_pages`.
+    pub(crate) discarded_pages: DiscardedPages,
 }
 
 impl Balloon {
@@ -166,6 +214,8 @@ impl Balloon {
         must_tell_host: bool,
         deflate_on_oom: bool,
         stats_polling_interval_s: u16,
+        free_page_hinting: bool,
+        free_page_reporting: bool,
         restored: bool,
     ) -> Result<Balloon, BalloonError> {
         let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
@@ -182,19 +232,30 @@ impl Balloon {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
         }
 
+        if free_page_hinting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT;
+        }
+
+        if free_page_reporting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_PAGE_REPORTING;
+        }
+
         let queue_evts = [
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
         ];
 
-        let mut queues: Vec<Queue> = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
-
-        // The VirtIO specification states that the statistics queue should
-        // not be present at all if the statistics are not enabled.
-        if stats_polling_interval_s == 0 {
-            let _ = queues.remove(STATS_INDEX);
-        }
+        // The VirtIO specification states that the statistics, free page hinting
+        // and free page reporting queues should not be present at all if the
+        // corresponding features are not enabled.
+        let num_queues = STATS_INDEX
+            + usize::from(stats_polling_interval_s > 0)
+            + usize::from(free_page_hinting)
+            + usize::from(free_page_reporting);
+        let queues: Vec<Queue> = (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect();
 
         let stats_timer =
             TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(BalloonError::Timer)?;
@@ -205,6 +266,7 @@ impl Balloon {
             config_space: ConfigSpace {
                 num_pages: mb_to_pages(amount_mb)?,
                 actual_pages: 0,
+                free_page_hint_cmd_id: FREE_PAGE_HINT_STOP,
             },
             interrupt_status: Arc::new(AtomicUsize::new(0)),
             interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
@@ -217,6 +279,9 @@ impl Balloon {
             stats_timer,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
+            hinting_state: HintingState::default(),
+            auto_target: None,
+            discarded_pages: DiscardedPages::new(),
         })
     }
 
@@ -241,6 +306,20 @@ impl Balloon {
         self.process_stats_queue()
     }
 
+    pub(crate) fn process_free_page_hinting_queue_event(&mut self) -> Result<(), BalloonError> {
+        self.queue_evts[self.free_page_hinting_idx()]
+            .read()
+            .map_err(BalloonError::EventFd)?;
+        self.process_free_page_hinting_queue()
+    }
+
+    pub(crate) fn process_free_page_reporting_queue_event(&mut self) -> Result<(), BalloonError> {
+        self.queue_evts[self.free_page_reporting_idx()]
+            .read()
+            .map_err(BalloonError::EventFd)?;
+        self.process_free_page_reporting_queue()
+    }
+
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
         let mem = mem_of_active_device!(self.device_state);
         self.stats_timer.read();
@@ -341,6 +420,7 @@ impl Balloon {
     pub(crate) fn process_stats_queue(&mut self) -> std::result::Result<(), BalloonError> {
         let mem = mem_of_active_device!(self.device_state);
         METRICS.balloon.stats_updates_count.inc();
+        let mut stats_updated = false;
 
         while let Some(head) = self.queues[STATS_INDEX].pop(&mem) {
             if let Some(prev_stats_desc) = self.stats_desc_index {
@@ -370,11 +450,170 @@ impl Balloon {
             }
 
             self.stats_desc_index = Some(head.index);
+            stats_updated = true;
+        }
+
+        if stats_updated {
+            self.adjust_target()?;
+        }
+
+        Ok(())
+    }
+
+    // Moves the target size of the balloon towards keeping `min_free_mib`
+    // of guest memory available, if an automatic target policy is set.
+    pub(crate) fn adjust_target(&mut self) -> Result<(), BalloonError> {
+        let policy = match self.auto_target {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        // The estimate of available memory accounts for the caches the guest
+        // can drop, so prefer it over the strictly free memory.
+        let free_mib = match self
+            .latest_stats
+            .available_memory
+            .or(self.latest_stats.free_memory)
+        {
+            Some(free_bytes) => free_bytes >> 20,
+            None => return Ok(()),
+        };
+        let min_free_mib = u64::from(policy.min_free_mib);
+        let target_mib = pages_to_mb(self.config_space.num_pages);
+
+        let new_target_mib = if free_mib < min_free_mib {
+            let step = cmp::min(min_free_mib - free_mib, u64::from(policy.deflate_rate_mib));
+            target_mib.saturating_sub(step as u32)
+        } else if self.config_space.actual_pages >= self.config_space.num_pages {
+            // Only inflate once the driver has caught up with the current
+            // target, otherwise the available memory is overestimated.
+            let step = cmp::min(free_mib - min_free_mib, u64::from(policy.inflate_rate_mib));
+            target_mib.saturating_add(step as u32)
+        } else {
+            target_mib
+        };
+        let new_target_mib = cmp::min(new_target_mib, policy.max_balloon_mib);
+
+        if new_target_mib == target_mib {
+            return Ok(());
+        }
+        if new_target_mib > target_mib {
+            METRICS.balloon.auto_inflate_count.inc();
+        } else {
+            METRICS.balloon.auto_deflate_count.inc();
+        }
+        self.config_space.num_pages = mb_to_pages(new_target_mib)?;
+        self.signal_config_change()
+    }
+
+    pub(crate) fn process_free_page_hinting_queue(&mut self) -> Result<(), BalloonError> {
+        let mem = mem_of_active_device!(self.device_state);
+        let host_cmd = self.config_space.free_page_hint_cmd_id;
+        let idx = self.free_page_hinting_idx();
+        let queue = &mut self.queues[idx];
+        let mut needs_interrupt = false;
+        let mut complete_run = false;
+
+        while let Some(head) = queue.pop(&mem) {
+            let head_index = head.index;
+            let mut next_desc = Some(head);
+
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    // Free pages are only hinted for the run in progress. The
+                    // driver holds on to them until the run is completed.
+                    if self.hinting_state.guest_cmd == Some(host_cmd) {
+                        METRICS.balloon.free_page_hint_count.inc();
+                        let range = (desc.addr, u64::from(desc.len));
+                        match remove_range(&mem, range, self.restored) {
+                            Ok(_) => {
+                                mark_discarded_range(&mut self.discarded_pages, &mem, range);
+                                METRICS.balloon.free_page_hint_freed.add(desc.len as usize)
+                            }
+                            Err(e) => {
+                                error!("Error removing memory range: {:?}", e);
+                                METRICS.balloon.free_page_hint_fails.inc();
+                            }
+                        };
+                    }
+                } else if desc.len as usize == SIZE_OF_U32 {
+                    // A read-only descriptor carries the command id of the run
+                    // the driver is hinting pages for.
+                    let cmd_id = mem
+                        .read_obj::<u32>(desc.addr)
+                        .map_err(|_| BalloonError::MalformedDescriptor)?;
+                    if cmd_id == FREE_PAGE_HINT_STOP {
+                        self.hinting_state.guest_cmd = None;
+                        complete_run = self.hinting_state.acknowledge_on_stop;
+                    } else {
+                        self.hinting_state.guest_cmd = Some(cmd_id);
+                    }
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            queue
+                .add_used(&mem, head_index, 0)
+                .map_err(BalloonError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        if complete_run && host_cmd >= FREE_PAGE_HINT_CMD_ID_MIN {
+            self.stop_hinting()?;
         }
 
         Ok(())
     }
 
+    pub(crate) fn process_free_page_reporting_queue(&mut self) -> Result<(), BalloonError> {
+        let mem = mem_of_active_device!(self.device_state);
+        let idx = self.free_page_reporting_idx();
+        let queue = &mut self.queues[idx];
+        let mut needs_interrupt = false;
+
+        while let Some(head) = queue.pop(&mem) {
+            let head_index = head.index;
+            let mut next_desc = Some(head);
+
+            // Each descriptor of the chain describes a range of free pages,
+            // which the driver does not touch until the chain is acknowledged.
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    METRICS.balloon.free_page_report_count.inc();
+                    let range = (desc.addr, u64::from(desc.len));
+                    match remove_range(&mem, range, self.restored) {
+                        Ok(_) => {
+                            mark_discarded_range(&mut self.discarded_pages, &mem, range);
+                            METRICS
+                                .balloon
+                                .free_page_report_freed
+                                .add(desc.len as usize)
+                        }
+                        Err(e) => {
+                            error!("Error removing memory range: {:?}", e);
+                            METRICS.balloon.free_page_report_fails.inc();
+                        }
+                    };
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            queue
+                .add_used(&mem, head_index, 0)
+                .map_err(BalloonError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
     pub(crate) fn signal_used_queue(&self) -> Result<(), BalloonError> {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
@@ -386,10 +625,25 @@ impl Balloon {
         Ok(())
     }
 
+    fn signal_config_change(&self) -> Result<(), BalloonError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
+
+        self.interrupt_evt
+            .write(1)
+            .map_err(BalloonError::InterruptError)
+    }
+
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
         let _ = self.process_inflate();
         let _ = self.process_deflate_queue();
+        if self.free_page_hinting() {
+            let _ = self.process_free_page_hinting_queue();
+        }
+        if self.free_page_reporting() {
+            let _ = self.process_free_page_reporting_queue();
+        }
     }
 
     pub fn id(&self) -> &str {
@@ -397,6 +651,10 @@ impl Balloon {
     }
 
     pub fn update_size(&mut self, amount_mb: u32) -> Result<(), BalloonError> {
+        // The policy would override the target size on the next statistics update.
+        if self.auto_target.is_some() {
+            return Err(BalloonError::AutoTargetEnabled);
+        }
         if self.is_activated() {
             self.config_space.num_pages = mb_to_pages(amount_mb)?;
             Ok(())
@@ -448,6 +706,84 @@ impl Balloon {
         self.stats_polling_interval_s
     }
 
+    pub fn auto_target(&self) -> Option<AutoTarget> {
+        self.auto_target
+    }
+
+    /// Sets the policy used to adjust the target size from the statistics.
+    pub fn set_auto_target(&mut self, auto_target: Option<AutoTarget>) -> Result<(), BalloonError> {
+        if let Some(policy) = auto_target {
+            if !self.stats_enabled() {
+                return Err(BalloonError::AutoTargetWithoutStats);
+            }
+            if policy.inflate_rate_mib == 0 || policy.deflate_rate_mib == 0 {
+                return Err(BalloonError::InvalidAutoTarget);
+            }
+            mb_to_pages(policy.max_balloon_mib)?;
+        }
+        self.auto_target = auto_target;
+        Ok(())
+    }
+
+    pub fn free_page_hinting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT) != 0
+    }
+
+    pub fn free_page_reporting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_PAGE_REPORTING) != 0
+    }
+
+    /// Asks the driver to hint the pages it currently has free.
+    pub fn start_hinting(&mut self, acknowledge_on_stop: bool) -> Result<(), BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::HintingDisabled);
+        }
+        if !self.is_activated() {
+            return Err(BalloonError::DeviceNotActive);
+        }
+
+        // Every run needs a new command id, which must not collide with
+        // the ids that have a special meaning.
+        let cmd_id = match self.hinting_state.last_cmd_id.checked_add(1) {
+            Some(cmd_id) if cmd_id >= FREE_PAGE_HINT_CMD_ID_MIN => cmd_id,
+            _ => FREE_PAGE_HINT_CMD_ID_MIN,
+        };
+        self.hinting_state.last_cmd_id = cmd_id;
+        self.hinting_state.acknowledge_on_stop = acknowledge_on_stop;
+        self.config_space.free_page_hint_cmd_id = cmd_id;
+        self.signal_config_change()
+    }
+
+    /// Completes the current hinting run, letting the driver reuse the hinted pages.
+    pub fn stop_hinting(&mut self) -> Result<(), BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::HintingDisabled);
+        }
+        if !self.is_activated() {
+            return Err(BalloonError::DeviceNotActive);
+        }
+
+        self.config_space.free_page_hint_cmd_id = FREE_PAGE_HINT_DONE;
+        self.signal_config_change()
+    }
+
+    pub fn hinting_status(&self) -> Result<HintingStatus, BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::HintingDisabled);
+        }
+
+        Ok(HintingStatus {
+            host_cmd: self.config_space.free_page_hint_cmd_id,
+            guest_cmd: self.hinting_state.guest_cmd,
+        })
+    }
+
+    /// Returns the pages discarded through free page hinting or reporting since
+    /// the previous call.
+    pub fn take_discarded_pages(&mut self) -> DiscardedPages {
+        std::mem::replace(&mut self.discarded_pages, HashMap::new())
+    }
+
     pub fn latest_stats(&mut self) -> Option<&BalloonStats> {
         if self.stats_enabled() {
             self.latest_stats.target_pages = self.config_space.num_pages;
@@ -466,12 +802,23 @@ impl Balloon {
             deflate_on_oom: self.deflate_on_oom(),
             must_tell_host: self.must_tell_host(),
             stats_polling_interval_s: self.stats_polling_interval_s(),
+            free_page_hinting: self.free_page_hinting(),
+            free_page_reporting: self.free_page_reporting(),
+            auto_target: self.auto_target(),
         }
     }
 
     pub(crate) fn stats_enabled(&self) -> bool {
         self.stats_polling_interval_s > 0
     }
+
+    pub(crate) fn free_page_hinting_idx(&self) -> usize {
+        STATS_INDEX + usize::from(self.stats_enabled())
+    }
+
+    pub(crate) fn free_page_reporting_idx(&self) -> usize {
+        self.free_page_hinting_idx() + usize::from(self.free_page_hinting())
+    }
 }
 
 impl VirtioDevice for Balloon {
@@ -665,29 +1012,52 @@ pub(crate) mod tests {
         for must_tell_host in vec![true, false].iter() {
             for deflate_on_oom in vec![true, false].iter() {
                 for stats_interval in vec![0, 1].iter() {
-                    let mut balloon =
-                        Balloon::new(0, *must_tell_host, *deflate_on_oom, *stats_interval, false)
+                    for free_page_hinting in vec![true, false].iter() {
+                        for free_page_reporting in vec![true, false].iter() {
+                            let mut balloon = Balloon::new(
+                                0,
+                                *must_tell_host,
+                                *deflate_on_oom,
+                                *stats_interval,
+                                *free_page_hinting,
+                                *free_page_reporting,
+                                false,
+                            )
                             .unwrap();
-                    assert_eq!(balloon.device_type(), TYPE_BALLOON);
-
-                    let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
-                        | ((if *must_tell_host { 1 } else { 0 })
-                            << VIRTIO_BALLOON_F_MUST_TELL_HOST)
-                        | ((if *deflate_on_oom { 1 } else { 0 })
-                            << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
-                        | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ);
-
-                    assert_eq!(balloon.avail_features_by_page(0), features as u32);
-                    assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
-                    for i in 2..10 {
-                        assert_eq!(balloon.avail_features_by_page(i), 0u32);
-                    }
-
-                    for i in 0..10 {
-                        balloon.ack_features_by_page(i, u32::MAX);
+                            assert_eq!(balloon.device_type(), TYPE_BALLOON);
+
+                            let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+                                | ((if *must_tell_host { 1 } else { 0 })
+                                    << VIRTIO_BALLOON_F_MUST_TELL_HOST)
+                                | ((if *deflate_on_oom { 1 } else { 0 })
+                                    << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
+                                | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ)
+                                | ((if *free_page_hinting { 1 } else { 0 })
+                                    << VIRTIO_BALLOON_F_FREE_PAGE_HINT)
+                                | ((if *free_page_reporting { 1 } else { 0 })
+                                    << VIRTIO_BALLOON_F_PAGE_REPORTING);
+
+                            assert_eq!(balloon.avail_features_by_page(0), features as u32);
+                            assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
+                            for i in 2..10 {
+                                assert_eq!(balloon.avail_features_by_page(i), 0u32);
+                            }
+
+                            for i in 0..10 {
+                                balloon.ack_features_by_page(i, u32::MAX);
+                            }
+                            // Only present features should be acknowledged.
+                            assert_eq!(balloon.acked_features, features);
+
+                            // Only the queues of the present features should exist.
+                            assert_eq!(
+                                balloon.queues().len(),
+                                2 + *stats_interval as usize
+                                    + usize::from(*free_page_hinting)
+                                    + usize::from(*free_page_reporting)
+                            );
+                        }
                     }
-                    // Only present features should be acknowledged.
-                    assert_eq!(balloon.acked_features, features);
                 }
             }
         }
@@ -695,29 +1065,34 @@ pub(crate) mod tests {
 
     #[test]
     fn test_virtio_read_config() {
-        let balloon = Balloon::new(0x10, true, true, 0, false).unwrap();
+        let balloon = Balloon::new(0x10, true, true, 0, false, false, false).unwrap();
 
         let cfg = BalloonConfig {
             amount_mb: 16,
             deflate_on_oom: true,
             must_tell_host: true,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         };
         assert_eq!(balloon.config(), cfg);
 
         let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
         balloon.read_config(0, &mut actual_config_space);
-        // The first 4 bytes are num_pages, the last 4 bytes are actual_pages.
+        // The first 4 bytes are num_pages, the next 4 bytes are actual_pages
+        // and the last 4 bytes are the free page hinting command id.
         // The config space is little endian.
         // 0x10 MB in the constructor corresponds to 0x1000 pages in the
         // config space.
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; CONFIG_SPACE_SIZE] = [
+            0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
         assert_eq!(actual_config_space, expected_config_space);
 
         // Invalid read.
         let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+            [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf, 0xd, 0xe, 0xa, 0xd];
         actual_config_space = expected_config_space;
         balloon.read_config(CONFIG_SPACE_SIZE as u64 + 1, &mut actual_config_space);
 
@@ -727,10 +1102,11 @@ pub(crate) mod tests {
 
     #[test]
     fn test_virtio_write_config() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
 
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; CONFIG_SPACE_SIZE] = [
+            0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
         balloon.write_config(0, &expected_config_space);
 
         let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
@@ -739,7 +1115,7 @@ pub(crate) mod tests {
 
         // Invalid write.
         let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
-        balloon.write_config(5, &new_config_space);
+        balloon.write_config(5 + 4, &new_config_space);
         // Make sure nothing got written.
         balloon.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
@@ -747,7 +1123,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_invalid_request() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
         let mem = default_mem();
         // Only initialize the inflate queue to demonstrate invalid request handling.
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
@@ -806,7 +1182,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_inflate() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
@@ -868,7 +1244,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_deflate() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
         let mem = default_mem();
         let defq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
@@ -908,7 +1284,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_stats() {
-        let mut balloon = Balloon::new(0, true, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 1, false, false, false).unwrap();
         let mem = default_mem();
         let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(STATS_INDEX, statsq.create_queue());
@@ -991,9 +1367,232 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_auto_target() {
+        let policy = AutoTarget {
+            min_free_mib: 64,
+            max_balloon_mib: 100,
+            inflate_rate_mib: 32,
+            deflate_rate_mib: 16,
+        };
+
+        // The policy needs the statistics and non-zero rates.
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.set_auto_target(Some(policy))),
+            "Err(AutoTargetWithoutStats)"
+        );
+        let mut balloon = Balloon::new(0, true, true, 1, false, false, false).unwrap();
+        assert_eq!(
+            format!(
+                "{:?}",
+                balloon.set_auto_target(Some(AutoTarget {
+                    deflate_rate_mib: 0,
+                    ..policy
+                }))
+            ),
+            "Err(InvalidAutoTarget)"
+        );
+
+        balloon.activate(default_mem()).unwrap();
+        // Nothing happens without a policy.
+        balloon.latest_stats.available_memory = Some(256 << 20);
+        balloon.adjust_target().unwrap();
+        assert_eq!(balloon.num_pages(), 0);
+
+        balloon.set_auto_target(Some(policy)).unwrap();
+        assert_eq!(balloon.config().auto_target, Some(policy));
+
+        // Inflate by at most the inflate rate.
+        check_metric_after_block!(
+            METRICS.balloon.auto_inflate_count,
+            1,
+            balloon.adjust_target().unwrap()
+        );
+        assert_eq!(balloon.num_pages(), mb_to_pages(32).unwrap());
+        assert_eq!(balloon.interrupt_evt().read().unwrap(), 1);
+
+        // Wait for the driver to catch up with the target.
+        balloon.adjust_target().unwrap();
+        assert_eq!(balloon.num_pages(), mb_to_pages(32).unwrap());
+        balloon.config_space.actual_pages = balloon.num_pages();
+
+        // Never inflate over the maximum size.
+        balloon.config_space.num_pages = mb_to_pages(90).unwrap();
+        balloon.config_space.actual_pages = mb_to_pages(90).unwrap();
+        balloon.adjust_target().unwrap();
+        assert_eq!(balloon.num_pages(), mb_to_pages(100).unwrap());
+
+        // The target size cannot be set by hand while the policy is in place.
+        assert_eq!(
+            format!("{:?}", balloon.update_size(50)),
+            "Err(AutoTargetEnabled)"
+        );
+        assert_eq!(balloon.num_pages(), mb_to_pages(100).unwrap());
+
+        // Deflate by at most the deflate rate when the guest runs low on memory.
+        balloon.latest_stats.available_memory = Some(40 << 20);
+        check_metric_after_block!(
+            METRICS.balloon.auto_deflate_count,
+            1,
+            balloon.adjust_target().unwrap()
+        );
+        assert_eq!(balloon.num_pages(), mb_to_pages(84).unwrap());
+
+        // Deflate by the missing amount only.
+        balloon.latest_stats.available_memory = Some(60 << 20);
+        balloon.adjust_target().unwrap();
+        assert_eq!(balloon.num_pages(), mb_to_pages(80).unwrap());
+
+        // Leave the balloon alone when the guest has exactly enough memory.
+        balloon.latest_stats.available_memory = Some(64 << 20);
+        balloon.config_space.actual_pages = balloon.num_pages();
+        balloon.adjust_target().unwrap();
+        assert_eq!(balloon.num_pages(), mb_to_pages(80).unwrap());
+    }
+
+    #[test]
+    fn test_free_page_reporting() {
+        let mut balloon = Balloon::new(0, true, true, 1, true, true, false).unwrap();
+        let mem = default_mem();
+        let repq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let repq_index = balloon.free_page_reporting_idx();
+        // The reporting queue follows the stats and hinting queues.
+        assert_eq!(repq_index, STATS_INDEX + 2);
+        balloon.set_queue(repq_index, repq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Fill two pages with non-zero bytes.
+        for i in 0..0x2000 {
+            assert!(mem.write_obj::<u8>(1, GuestAddress(0x4000 + i)).is_ok());
+        }
+
+        // The driver reports both pages in a single chain.
+        repq.avail.idx.set(1);
+        repq.avail.ring[0].set(0);
+        repq.dtable[0].set(0x4000, 0x1000, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 1);
+        repq.dtable[1].set(0x5000, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+
+        check_metric_after_block!(
+            METRICS.balloon.free_page_report_count,
+            2,
+            invoke_handler_for_queue_event(&mut balloon, repq_index)
+        );
+        check_request_completion(&repq, 0);
+
+        // Check that the pages were zeroed.
+        for i in 0..0x2000 {
+            assert_eq!(mem.read_obj::<u8>(GuestAddress(0x4000 + i)).unwrap(), 0);
+        }
+
+        // The pages are recorded so that diff snapshots can skip them.
+        let discarded_pages = balloon.take_discarded_pages();
+        assert_eq!(discarded_pages[&0][0], 0b11_0000);
+        assert!(balloon.take_discarded_pages().is_empty());
+    }
+
+    #[test]
+    fn test_free_page_hinting() {
+        let mut balloon = Balloon::new(0, true, true, 0, true, false, false).unwrap();
+        let mem = default_mem();
+        let hintq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let hintq_index = balloon.free_page_hinting_idx();
+        // Without statistics, the hinting queue takes the place of the stats queue.
+        assert_eq!(hintq_index, STATS_INDEX);
+        balloon.set_queue(hintq_index, hintq.create_queue());
+
+        // Hinting can't be started before activation.
+        assert_eq!(
+            format!("{:?}", balloon.start_hinting(true)),
+            "Err(DeviceNotActive)"
+        );
+        balloon.activate(mem.clone()).unwrap();
+
+        assert_eq!(
+            balloon.hinting_status().unwrap(),
+            HintingStatus {
+                host_cmd: FREE_PAGE_HINT_STOP,
+                guest_cmd: None,
+            }
+        );
+        balloon.start_hinting(true).unwrap();
+        let host_cmd = balloon.hinting_status().unwrap().host_cmd;
+        assert_eq!(host_cmd, FREE_PAGE_HINT_CMD_ID_MIN);
+        assert_eq!(balloon.interrupt_evt().read().unwrap(), 1);
+        assert_eq!(
+            balloon.interrupt_status().load(Ordering::SeqCst) as u32 & VIRTIO_MMIO_INT_CONFIG,
+            VIRTIO_MMIO_INT_CONFIG
+        );
+
+        // Fill a page with non-zero bytes.
+        for i in 0..0x1000 {
+            assert!(mem.write_obj::<u8>(1, GuestAddress(0x4000 + i)).is_ok());
+        }
+
+        // The driver acknowledges the run and hints a free page.
+        let cmd_addr = 0x3000;
+        mem.write_obj::<u32>(host_cmd, GuestAddress(cmd_addr))
+            .unwrap();
+        set_request(&hintq, 0, cmd_addr, SIZE_OF_U32 as u32, 0);
+        invoke_handler_for_queue_event(&mut balloon, hintq_index);
+        check_request_completion(&hintq, 0);
+        assert_eq!(balloon.hinting_status().unwrap().guest_cmd, Some(host_cmd));
+
+        set_request(&hintq, 1, 0x4000, 0x1000, VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            METRICS.balloon.free_page_hint_count,
+            1,
+            invoke_handler_for_queue_event(&mut balloon, hintq_index)
+        );
+        check_request_completion(&hintq, 1);
+        for i in 0..0x1000 {
+            assert_eq!(mem.read_obj::<u8>(GuestAddress(0x4000 + i)).unwrap(), 0);
+        }
+        assert_eq!(balloon.take_discarded_pages()[&0][0], 0b1_0000);
+
+        // The driver stops hinting, which completes the run.
+        mem.write_obj::<u32>(FREE_PAGE_HINT_STOP, GuestAddress(cmd_addr))
+            .unwrap();
+        set_request(&hintq, 2, cmd_addr, SIZE_OF_U32 as u32, 0);
+        balloon.queue_evts[hintq_index].write(1).unwrap();
+        balloon.process_free_page_hinting_queue_event().unwrap();
+        check_request_completion(&hintq, 2);
+        assert_eq!(
+            balloon.hinting_status().unwrap(),
+            HintingStatus {
+                host_cmd: FREE_PAGE_HINT_DONE,
+                guest_cmd: None,
+            }
+        );
+
+        // A new run gets a new command id.
+        balloon.start_hinting(false).unwrap();
+        assert_eq!(
+            balloon.hinting_status().unwrap().host_cmd,
+            FREE_PAGE_HINT_CMD_ID_MIN + 1
+        );
+        balloon.stop_hinting().unwrap();
+        assert_eq!(
+            balloon.hinting_status().unwrap().host_cmd,
+            FREE_PAGE_HINT_DONE
+        );
+
+        // Hinting is rejected when the feature is not enabled.
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
+        balloon.activate(mem).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.start_hinting(true)),
+            "Err(HintingDisabled)"
+        );
+        assert_eq!(
+            format!("{:?}", balloon.hinting_status()),
+            "Err(HintingDisabled)"
+        );
+    }
+
     #[test]
     fn test_process_balloon_queues() {
-        let mut balloon = Balloon::new(0x10, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0x10, true, true, 0, false, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         balloon.process_virtio_queues()
@@ -1001,14 +1600,14 @@ pub(crate) mod tests {
 
     #[test]
     fn test_update_stats_interval() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
         assert_eq!(
             format!("{:?}", balloon.update_stats_polling_interval(1)),
             "Err(StatisticsStateChange)"
         );
         assert!(balloon.update_stats_polling_interval(0).is_ok());
 
-        let mut balloon = Balloon::new(0, true, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 1, false, false, false).unwrap();
         assert_eq!(
             format!("{:?}", balloon.update_stats_polling_interval(0)),
             "Err(StatisticsStateChange)"
@@ -1019,7 +1618,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_num_pages() {
-        let mut balloon = Balloon::new(0, true, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 0, false, false, false).unwrap();
         // Assert that we can't update an inactive device.
         assert!(balloon.update_size(1).is_err());
         // Switch the state to active.
@@ -1036,7 +1635,10 @@ pub(crate) mod tests {
 
         let mut actual_config = vec![0; CONFIG_SPACE_SIZE];
         balloon.read_config(0, &mut actual_config);
-        assert_eq!(actual_config, vec![0x0, 0x10, 0x0, 0x0, 0x34, 0x12, 0, 0]);
+        assert_eq!(
+            actual_config,
+            vec![0x0, 0x10, 0x0, 0x0, 0x34, 0x12, 0, 0, 0, 0, 0, 0]
+        );
         assert_eq!(balloon.num_pages(), 0x1000);
         assert_eq!(balloon.actual_pages(), 0x1234);
         assert_eq!(balloon.size_mb(), 16);
@@ -63,9 +63,13 @@ impl Subscriber for Balloon {
             let virtq_inflate_ev_fd = self.queue_evts[INFLATE_INDEX].as_raw_fd();
             let virtq_deflate_ev_fd = self.queue_evts[DEFLATE_INDEX].as_raw_fd();
             let virtq_stats_ev_fd = self.queue_evts[STATS_INDEX].as_raw_fd();
+            let virtq_hinting_ev_fd = self.queue_evts[self.free_page_hinting_idx()].as_raw_fd();
+            let virtq_reporting_ev_fd = self.queue_evts[self.free_page_reporting_idx()].as_raw_fd();
             let stats_timer_fd = self.stats_timer.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
+            // The optional queues share indexes depending on the enabled
+            // features, so the feature has to be checked along the fd.
             // Looks better than C style if/else if/else.
             match source {
                 _ if source == virtq_inflate_ev_fd => self
@@ -74,9 +78,15 @@ impl Subscriber for Balloon {
                 _ if source == virtq_deflate_ev_fd => self
                     .process_deflate_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
-                _ if source == virtq_stats_ev_fd => self
+                _ if self.stats_enabled() && source == virtq_stats_ev_fd => self
                     .process_stats_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
+                _ if self.free_page_hinting() && source == virtq_hinting_ev_fd => self
+                    .process_free_page_hinting_queue_event()
+                    .unwrap_or_else(report_balloon_event_fail),
+                _ if self.free_page_reporting() && source == virtq_reporting_ev_fd => self
+                    .process_free_page_reporting_queue_event()
+                    .unwrap_or_else(report_balloon_event_fail),
                 _ if source == stats_timer_fd => self
                     .process_stats_timer_event()
                     .unwrap_or_else(report_balloon_event_fail),
@@ -118,6 +128,18 @@ impl Subscriber for Balloon {
                     EpollEvent::new(EventSet::IN, self.stats_timer.as_raw_fd() as u64),
                 ]);
             }
+            if self.free_page_hinting() {
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[self.free_page_hinting_idx()].as_raw_fd() as u64,
+                ));
+            }
+            if self.free_page_reporting() {
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[self.free_page_reporting_idx()].as_raw_fd() as u64,
+                ));
+            }
             events
         } else {
             vec![EpollEvent::new(
@@ -140,7 +162,7 @@ pub mod tests {
     #[test]
     fn test_event_handler() {
         let mut event_manager = EventManager::new().unwrap();
-        let mut balloon = Balloon::new(0, true, true, 10, false).unwrap();
+        let mut balloon = Balloon::new(0, true, true, 10, false, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
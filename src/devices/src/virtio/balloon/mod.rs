@@ -5,22 +5,25 @@ pub mod device;
 pub mod event_handler;
 pub mod persist;
 pub mod test_utils;
-mod utils;
+pub(crate) mod utils;
 
 use vm_memory::GuestMemoryError;
 
+pub use self::device::AutoTarget;
 pub use self::device::Balloon;
 pub use self::device::BalloonConfig;
 pub use self::device::BalloonStats;
+pub use self::device::DiscardedPages;
+pub use self::device::HintingStatus;
 pub use self::event_handler::*;
 
 /// Device ID used in MMIO device identification.
 /// Because Balloon is unique per-vm, this ID can be hardcoded.
 pub const BALLOON_DEV_ID: &str = "balloon";
-pub const CONFIG_SPACE_SIZE: usize = 8;
+pub const CONFIG_SPACE_SIZE: usize = 12;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 3;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
+// The maximum number of queues, when all the optional queues are enabled.
+pub const NUM_QUEUES: usize = 5;
 // Number of 4K pages in a MB.
 pub const MB_TO_4K_PAGES: u32 = 256;
 // The maximum number of pages that can be received in a single descriptor.
@@ -31,13 +34,23 @@ pub const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
 pub const INFLATE_INDEX: usize = 0;
 // The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const DEFLATE_INDEX: usize = 1;
-// The index of the deflate queue from Balloon device queues/queues_evts vector.
+// The index of the stats queue from Balloon device queues/queues_evts vector.
+// The free page hinting and reporting queues follow the last enabled queue,
+// so their indexes depend on the negotiated features.
 pub const STATS_INDEX: usize = 2;
 
 // The feature bitmap for virtio balloon.
 const VIRTIO_BALLOON_F_MUST_TELL_HOST: u32 = 0; // Tell before reclaiming pages.
 const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1; // Enable statistics.
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2; // Deflate balloon on OOM.
+const VIRTIO_BALLOON_F_FREE_PAGE_HINT: u32 = 3; // Report free pages on request.
+const VIRTIO_BALLOON_F_PAGE_REPORTING: u32 = 5; // Continuously report free pages.
+
+// The free page hinting command ids with a special meaning. Any other
+// value starts a new hinting run.
+const FREE_PAGE_HINT_STOP: u32 = 0;
+const FREE_PAGE_HINT_DONE: u32 = 1;
+const FREE_PAGE_HINT_CMD_ID_MIN: u32 = 2;
 
 // The statistics tags.
 const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
@@ -55,6 +68,10 @@ const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
 pub enum Error {
     /// Activation error.
     Activate(super::ActivateError),
+    /// The target size is managed by the automatic target policy.
+    AutoTargetEnabled,
+    /// The automatic target policy needs the statistics to be enabled.
+    AutoTargetWithoutStats,
     /// No balloon device found.
     DeviceNotFound,
     /// Device not activated yet.
@@ -65,8 +82,12 @@ pub enum Error {
     FailedSignalingUsedQueue(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// Received a free page hinting request when free page hinting is disabled.
+    HintingDisabled,
     /// Received error while sending an interrupt.
     InterruptError(std::io::Error),
+    /// The automatic target policy has a zero inflate or deflate rate.
+    InvalidAutoTarget,
     /// Guest gave us a malformed descriptor.
     MalformedDescriptor,
     /// Guest gave us a malformed payload.
@@ -16,7 +16,7 @@ use vm_memory::GuestMemoryMmap;
 
 use super::*;
 
-use crate::virtio::balloon::device::{BalloonStats, ConfigSpace};
+use crate::virtio::balloon::device::{AutoTarget, BalloonStats, ConfigSpace, HintingState};
 use crate::virtio::persist::VirtioDeviceState;
 use crate::virtio::{DeviceState, TYPE_BALLOON};
 
@@ -24,6 +24,22 @@ use crate::virtio::{DeviceState, TYPE_BALLOON};
 pub struct BalloonConfigSpaceState {
     num_pages: u32,
     actual_pages: u32,
+    free_page_hint_cmd_id: u32,
+}
+
+#[derive(Clone, Versionize)]
+pub struct BalloonHintingState {
+    last_cmd_id: u32,
+    guest_cmd: Option<u32>,
+    acknowledge_on_stop: bool,
+}
+
+#[derive(Clone, Versionize)]
+pub struct BalloonAutoTargetState {
+    min_free_mib: u32,
+    max_balloon_mib: u32,
+    inflate_rate_mib: u32,
+    deflate_rate_mib: u32,
 }
 
 #[derive(Clone, Versionize)]
@@ -82,6 +98,8 @@ pub struct BalloonState {
     stats_desc_index: Option<u16>,
     latest_stats: BalloonStatsState,
     config_space: BalloonConfigSpaceState,
+    hinting_state: BalloonHintingState,
+    auto_target: Option<BalloonAutoTargetState>,
     virtio_state: VirtioDeviceState,
 }
 
@@ -102,7 +120,19 @@ impl Persist<'_> for Balloon {
             config_space: BalloonConfigSpaceState {
                 num_pages: self.config_space.num_pages,
                 actual_pages: self.config_space.actual_pages,
+                free_page_hint_cmd_id: self.config_space.free_page_hint_cmd_id,
             },
+            hinting_state: BalloonHintingState {
+                last_cmd_id: self.hinting_state.last_cmd_id,
+                guest_cmd: self.hinting_state.guest_cmd,
+                acknowledge_on_stop: self.hinting_state.acknowledge_on_stop,
+            },
+            auto_target: self.auto_target.map(|policy| BalloonAutoTargetState {
+                min_free_mib: policy.min_free_mib,
+                max_balloon_mib: policy.max_balloon_mib,
+                inflate_rate_mib: policy.inflate_rate_mib,
+                deflate_rate_mib: policy.deflate_rate_mib,
+            }),
             virtio_state: VirtioDeviceState::from_device(self),
         }
     }
@@ -111,16 +141,26 @@ impl Persist<'_> for Balloon {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
+        let free_page_hinting =
+            state.virtio_state.avail_features & (1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT) != 0;
+        let free_page_reporting =
+            state.virtio_state.avail_features & (1u64 << VIRTIO_BALLOON_F_PAGE_REPORTING) != 0;
         // We can safely create the balloon with arbitrary flags and
-        // num_pages because we will overwrite them after.
-        let mut balloon = Balloon::new(0, false, false, state.stats_polling_interval_s, true)?;
-
-        let mut num_queues = NUM_QUEUES;
-        // As per the virtio 1.1 specification, the statistics queue
-        // should not exist if the statistics are not enabled.
-        if state.stats_polling_interval_s == 0 {
-            num_queues -= 1;
-        }
+        // num_pages because we will overwrite them after. The optional
+        // queues have to match the saved features though.
+        let mut balloon = Balloon::new(
+            0,
+            false,
+            false,
+            state.stats_polling_interval_s,
+            free_page_hinting,
+            free_page_reporting,
+            true,
+        )?;
+
+        // As per the virtio 1.1 specification, the optional queues
+        // should not exist if their features are not enabled.
+        let num_queues = balloon.queues.len();
         balloon.queues = state
             .virtio_state
             .build_queues_checked(&constructor_args.mem, TYPE_BALLOON, num_queues, QUEUE_SIZE)
@@ -132,7 +172,19 @@ impl Persist<'_> for Balloon {
         balloon.config_space = ConfigSpace {
             num_pages: state.config_space.num_pages,
             actual_pages: state.config_space.actual_pages,
+            free_page_hint_cmd_id: state.config_space.free_page_hint_cmd_id,
         };
+        balloon.hinting_state = HintingState {
+            last_cmd_id: state.hinting_state.last_cmd_id,
+            guest_cmd: state.hinting_state.guest_cmd,
+            acknowledge_on_stop: state.hinting_state.acknowledge_on_stop,
+        };
+        balloon.auto_target = state.auto_target.as_ref().map(|policy| AutoTarget {
+            min_free_mib: policy.min_free_mib,
+            max_balloon_mib: policy.max_balloon_mib,
+            inflate_rate_mib: policy.inflate_rate_mib,
+            deflate_rate_mib: policy.deflate_rate_mib,
+        });
 
         if state.virtio_state.activated {
             balloon.device_state = DeviceState::Activated(constructor_args.mem);
@@ -169,7 +221,15 @@ mod tests {
         let version_map = VersionMap::new();
 
         // Create and save the balloon device.
-        let balloon = Balloon::new(0x42, true, false, 2, false).unwrap();
+        let mut balloon = Balloon::new(0x42, true, false, 2, true, true, false).unwrap();
+        balloon
+            .set_auto_target(Some(AutoTarget {
+                min_free_mib: 1,
+                max_balloon_mib: 2,
+                inflate_rate_mib: 3,
+                deflate_rate_mib: 4,
+            }))
+            .unwrap();
 
         <Balloon as Persist>::save(&balloon)
             .serialize(&mut mem.as_mut_slice(), &version_map, 1)
@@ -201,5 +261,9 @@ mod tests {
         );
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
+        assert_eq!(restored_balloon.hinting_state, balloon.hinting_state);
+        assert_eq!(restored_balloon.auto_target, balloon.auto_target);
+        assert!(restored_balloon.free_page_hinting());
+        assert!(restored_balloon.free_page_reporting());
     }
 }
@@ -3,8 +3,8 @@
 
 use std::io;
 
-use super::{RemoveRegionError, MAX_PAGES_IN_DESC};
-use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use super::{DiscardedPages, RemoveRegionError, MAX_PAGES_IN_DESC, VIRTIO_BALLOON_PFN_SHIFT};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 /// This takes a vector of page frame numbers, and compacts them
 /// into ranges of consecutive pages. The result is a vector
@@ -100,6 +100,34 @@ pub(crate) fn remove_range(
     }
 }
 
+/// Marks the pages fully covered by `range` in the bitmap of the guest memory
+/// region they belong to. The range is expected to be validated already.
+pub(crate) fn mark_discarded_range(
+    discarded_pages: &mut DiscardedPages,
+    guest_memory: &GuestMemoryMmap,
+    range: (GuestAddress, u64),
+) {
+    let page_size = 1u64 << VIRTIO_BALLOON_PFN_SHIFT;
+    let (guest_address, range_len) = range;
+
+    let _: std::result::Result<(), ()> = guest_memory.with_regions_mut(|index, region| {
+        if guest_address < region.start_addr() || guest_address > region.last_addr() {
+            return Ok(());
+        }
+
+        let region_pages = region.len() / page_size;
+        let bitmap = discarded_pages
+            .entry(index)
+            .or_insert_with(|| vec![0; ((region_pages + 63) / 64) as usize]);
+        let first_page = guest_address.unchecked_offset_from(region.start_addr()) / page_size;
+        let last_page = std::cmp::min(first_page + range_len / page_size, region_pages);
+        for page in first_page..last_page {
+            bitmap[(page / 64) as usize] |= 1u64 << (page % 64);
+        }
+        Ok(())
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -234,4 +262,44 @@ mod tests {
             RemoveRegionError::MmapFail(_)
         );
     }
+
+    #[test]
+    fn test_mark_discarded_range() {
+        let page_size: u64 = 0x1000;
+        let mem = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 2 * page_size as usize),
+            (GuestAddress(0x10_0000), 100 * page_size as usize),
+        ])
+        .unwrap();
+        let mut discarded_pages = DiscardedPages::new();
+
+        // The second page of the first region.
+        mark_discarded_range(
+            &mut discarded_pages,
+            &mem,
+            (GuestAddress(page_size), page_size),
+        );
+        assert_eq!(discarded_pages.len(), 1);
+        assert_eq!(discarded_pages[&0], vec![0b10]);
+
+        // Pages 63 to 65 of the second region, spanning two bitmap words.
+        mark_discarded_range(
+            &mut discarded_pages,
+            &mem,
+            (GuestAddress(0x10_0000 + 63 * page_size), 3 * page_size),
+        );
+        assert_eq!(discarded_pages[&1], vec![1u64 << 63, 0b11]);
+
+        // Partial pages are not marked.
+        mark_discarded_range(&mut discarded_pages, &mem, (GuestAddress(0), 0x10));
+        assert_eq!(discarded_pages[&0], vec![0b10]);
+
+        // Ranges outside the guest memory are ignored.
+        mark_discarded_range(
+            &mut discarded_pages,
+            &mem,
+            (GuestAddress(0x1000_0000), page_size),
+        );
+        assert_eq!(discarded_pages.len(), 2);
+    }
 }
@@ -8,70 +8,318 @@
 use std::cmp;
 use std::convert::From;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
-use std::path::PathBuf;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
-use logger::{error, warn, IncMetric, METRICS};
+use logger::{error, warn, BlockDriveMetrics, IncMetric, LatencyHistogram, METRICS};
 use rate_limiter::{RateLimiter, TokenType};
+use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
+use utils::time::{get_time_us, ClockType};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 use virtio_gen::virtio_blk::*;
 use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::{
-    super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BLOCK, VIRTIO_MMIO_INT_VRING},
+    super::{
+        ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice, TYPE_BLOCK,
+        VIRTIO_MMIO_INT_VRING,
+    },
+    direct::DirectFile,
+    overlay::CowOverlay,
+    qcow::QcowFile,
     request::*,
-    Error, CONFIG_SPACE_SIZE, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE,
+    sparse::{is_unsupported, punch_hole, zero_range},
+    Error, CONFIG_NUM_QUEUES_OFFSET, CONFIG_SPACE_SIZE, CONFIG_WCE_OFFSET, MAX_DISCARD_SECTORS,
+    MAX_DISCARD_SEGMENTS, MAX_QUEUES, QUEUE_SIZE, SECTOR_SHIFT, SECTOR_SIZE, VIRTIO_BLK_F_DISCARD,
+    VIRTIO_BLK_F_WRITE_ZEROES,
 };
 
 use crate::virtio::VIRTIO_MMIO_INT_CONFIG;
 use crate::Error as DeviceError;
 
+/// The format of the disk image backing a block device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+pub enum ImageFormat {
+    /// The disk content as is.
+    Raw,
+    /// The qcow2 format, possibly with a backing file.
+    Qcow2,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        ImageFormat::Raw
+    }
+}
+
+/// How the host caches the disk image of a block device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+pub enum CacheType {
+    /// The host page cache holds the guest writes, and the flush requests are ignored.
+    Unsafe,
+    /// The host page cache holds the guest writes, and the flush requests write it back.
+    Writeback,
+    /// The host page cache is bypassed with `O_DIRECT`, and the flush requests write back
+    /// the cache of the host disk.
+    Direct,
+}
+
+impl Default for CacheType {
+    fn default() -> Self {
+        CacheType::Unsafe
+    }
+}
+
+/// The host file(s) backing a block device.
+pub(crate) enum DiskImage {
+    /// The guest reads and writes the file directly.
+    Raw(File),
+    /// The guest reads and writes the file directly, bypassing the host page cache.
+    Direct(DirectFile),
+    /// The guest writes go to a copy-on-write overlay over the file.
+    Overlay(CowOverlay),
+    /// The file is a qcow2 image.
+    Qcow(QcowFile),
+}
+
+impl DiskImage {
+    /// Lets the host reclaim the `len` bytes at `offset`, which then read as zeros. Discarding
+    /// is only a hint, so nothing happens when the host file system can't deallocate ranges.
+    pub fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        let result = match self {
+            DiskImage::Raw(file) => punch_hole(file, offset, len),
+            DiskImage::Direct(direct) => punch_hole(direct.file(), offset, len),
+            DiskImage::Overlay(overlay) => overlay.discard(offset, len),
+            DiskImage::Qcow(qcow) => qcow.discard(offset, len),
+        };
+        match result {
+            Err(ref e) if is_unsupported(e) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Makes the `len` bytes at `offset` read as zeros. With `unmap`, the host may also
+    /// reclaim them.
+    pub fn write_zeroes(&mut self, offset: u64, len: u64, unmap: bool) -> io::Result<()> {
+        match self {
+            DiskImage::Raw(file) if unmap => match punch_hole(file, offset, len) {
+                Err(ref e) if is_unsupported(e) => zero_range(file, offset, len),
+                result => result,
+            },
+            DiskImage::Raw(file) => zero_range(file, offset, len),
+            DiskImage::Direct(direct) => direct.write_zeroes(offset, len, unmap),
+            DiskImage::Overlay(overlay) => overlay.write_zeroes(offset, len),
+            DiskImage::Qcow(qcow) => qcow.write_zeroes(offset, len),
+        }
+    }
+
+    /// Picks up the current size of the host file, after it was resized, and returns it.
+    pub fn refresh_size(&mut self) -> io::Result<u64> {
+        match self {
+            DiskImage::Raw(file) => Ok(file.metadata()?.len()),
+            DiskImage::Direct(direct) => direct.refresh_size(),
+            // The size of an overlay follows the base image it was created over, and the size
+            // of a qcow2 image is recorded in its header.
+            DiskImage::Overlay(_) | DiskImage::Qcow(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only raw images without an overlay can be resized",
+            )),
+        }
+    }
+
+    /// Writes back the data written to the host files to their disks.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        match self {
+            DiskImage::Raw(file) => file.sync_data(),
+            DiskImage::Direct(direct) => direct.file().sync_data(),
+            DiskImage::Overlay(overlay) => overlay.sync(),
+            DiskImage::Qcow(qcow) => qcow.sync(),
+        }
+    }
+}
+
+impl Read for DiskImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DiskImage::Raw(file) => file.read(buf),
+            DiskImage::Direct(direct) => direct.read(buf),
+            DiskImage::Overlay(overlay) => overlay.read(buf),
+            DiskImage::Qcow(qcow) => qcow.read(buf),
+        }
+    }
+}
+
+impl Write for DiskImage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DiskImage::Raw(file) => file.write(buf),
+            DiskImage::Direct(direct) => direct.write(buf),
+            DiskImage::Overlay(overlay) => overlay.write(buf),
+            DiskImage::Qcow(qcow) => qcow.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DiskImage::Raw(file) => file.flush(),
+            DiskImage::Direct(direct) => direct.flush(),
+            DiskImage::Overlay(overlay) => overlay.flush(),
+            DiskImage::Qcow(qcow) => qcow.flush(),
+        }
+    }
+}
+
+impl Seek for DiskImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            DiskImage::Raw(file) => file.seek(pos),
+            DiskImage::Direct(direct) => direct.seek(pos),
+            DiskImage::Overlay(overlay) => overlay.seek(pos),
+            DiskImage::Qcow(qcow) => qcow.seek(pos),
+        }
+    }
+}
+
 /// Helper object for setting up all `Block` fields derived from its backing file.
 pub(crate) struct DiskProperties {
     file_path: String,
-    file: File,
+    format: ImageFormat,
+    cache_type: CacheType,
+    file: DiskImage,
     nsectors: u64,
     image_id: Vec<u8>,
+    writethrough: bool,
 }
 
 impl DiskProperties {
-    pub fn new(disk_image_path: String, is_disk_read_only: bool) -> io::Result<Self> {
-        let mut disk_image = OpenOptions::new()
+    pub fn new(
+        disk_image_path: String,
+        format: ImageFormat,
+        cache_type: CacheType,
+        is_disk_read_only: bool,
+        overlay_path: Option<String>,
+    ) -> io::Result<Self> {
+        if format == ImageFormat::Qcow2 && overlay_path.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "qcow2 images cannot have a copy-on-write overlay",
+            ));
+        }
+        let direct = cache_type == CacheType::Direct;
+        if direct && (format != ImageFormat::Raw || overlay_path.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only raw images without an overlay can bypass the host page cache",
+            ));
+        }
+        // The base image is never written to when the device has an overlay.
+        let disk_image = OpenOptions::new()
             .read(true)
-            .write(!is_disk_read_only)
+            .write(!is_disk_read_only && overlay_path.is_none())
+            .custom_flags(if direct { libc::O_DIRECT } else { 0 })
             .open(PathBuf::from(&disk_image_path))?;
-        let disk_size = disk_image.seek(SeekFrom::End(0))? as u64;
 
-        // We only support disk size, which uses the first two words of the configuration space.
-        // If the image is not a multiple of the sector size, the tail bits are not exposed.
-        if disk_size % SECTOR_SIZE != 0 {
-            warn!(
-                "Disk size {} is not a multiple of sector size {}; \
-                 the remainder will not be visible to the guest.",
-                disk_size, SECTOR_SIZE
-            );
-        }
+        let image_id = Self::build_disk_image_id(&disk_image);
+        let mut file = match (format, overlay_path) {
+            (ImageFormat::Qcow2, _) => {
+                DiskImage::Qcow(QcowFile::new(disk_image, Path::new(&disk_image_path))?)
+            }
+            (ImageFormat::Raw, Some(overlay_path)) => DiskImage::Overlay(CowOverlay::new(
+                disk_image,
+                overlay_path,
+                is_disk_read_only,
+            )?),
+            (ImageFormat::Raw, None) if direct => DiskImage::Direct(DirectFile::new(disk_image)?),
+            (ImageFormat::Raw, None) => DiskImage::Raw(disk_image),
+        };
+        let disk_size = file.seek(SeekFrom::End(0))? as u64;
 
         Ok(Self {
-            nsectors: disk_size >> SECTOR_SHIFT,
-            image_id: Self::build_disk_image_id(&disk_image),
+            nsectors: Self::size_to_sectors(disk_size),
+            image_id,
             file_path: disk_image_path,
-            file: disk_image,
+            format,
+            cache_type,
+            file,
+            writethrough: false,
         })
     }
 
-    pub fn file_mut(&mut self) -> &mut File {
+    /// Opens the disk image again, for a handle which can be used alongside this one. Only raw
+    /// images without an overlay can be opened more than once, since the metadata of overlays
+    /// and qcow2 images is kept in memory.
+    pub fn reopen(&self, is_disk_read_only: bool) -> io::Result<Option<Self>> {
+        match self.file {
+            DiskImage::Raw(_) | DiskImage::Direct(_) => (),
+            DiskImage::Overlay(_) | DiskImage::Qcow(_) => return Ok(None),
+        }
+        let mut disk = Self::new(
+            self.file_path.clone(),
+            self.format,
+            self.cache_type,
+            is_disk_read_only,
+            None,
+        )?;
+        disk.nsectors = self.nsectors;
+        disk.writethrough = self.writethrough;
+        Ok(Some(disk))
+    }
+
+    pub fn file_mut(&mut self) -> &mut DiskImage {
         &mut self.file
     }
 
+    /// The copy-on-write overlay of the disk, if any.
+    pub fn overlay(&self) -> Option<&CowOverlay> {
+        match self.file {
+            DiskImage::Overlay(ref overlay) => Some(overlay),
+            _ => None,
+        }
+    }
+
+    pub fn overlay_mut(&mut self) -> Option<&mut CowOverlay> {
+        match self.file {
+            DiskImage::Overlay(ref mut overlay) => Some(overlay),
+            _ => None,
+        }
+    }
+
     pub fn nsectors(&self) -> u64 {
         self.nsectors
     }
 
+    pub(crate) fn set_nsectors(&mut self, nsectors: u64) {
+        self.nsectors = nsectors;
+    }
+
+    /// Picks up the current size of the disk image, after it was resized.
+    pub fn refresh_size(&mut self) -> io::Result<()> {
+        let disk_size = self.file.refresh_size()?;
+        self.nsectors = Self::size_to_sectors(disk_size);
+        Ok(())
+    }
+
+    fn size_to_sectors(disk_size: u64) -> u64 {
+        // If the image is not a multiple of the sector size, the tail bits are not exposed.
+        if disk_size % SECTOR_SIZE != 0 {
+            warn!(
+                "Disk size {} is not a multiple of sector size {}; \
+                 the remainder will not be visible to the guest.",
+                disk_size, SECTOR_SIZE
+            );
+        }
+        disk_size >> SECTOR_SHIFT
+    }
+
     pub fn image_id(&self) -> &[u8] {
         &self.image_id
     }
@@ -110,19 +358,172 @@ impl DiskProperties {
         &self.file_path
     }
 
+    /// Format of the backing file.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Caching of the backing file by the host.
+    pub fn cache_type(&self) -> CacheType {
+        self.cache_type
+    }
+
+    /// Whether the writes complete only once on the host disk, as set by the guest through
+    /// the writeback field of the config space.
+    pub fn writethrough(&self) -> bool {
+        self.writethrough
+    }
+
+    pub fn set_writethrough(&mut self, writethrough: bool) {
+        self.writethrough = writethrough;
+    }
+
+    /// Writes back the guest writes to the host disk, unless the cache type is unsafe.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.cache_type == CacheType::Unsafe {
+            return self.file.flush();
+        }
+        self.file.sync()
+    }
+
     /// Provides vec containing the virtio block configuration space
     /// buffer. The config space is populated with the disk size based
-    /// on the backing file size.
-    pub fn virtio_block_config_space(&self) -> Vec<u8> {
-        // The config space is little endian.
-        let mut config = Vec::with_capacity(CONFIG_SPACE_SIZE);
-        for i in 0..CONFIG_SPACE_SIZE {
-            config.push((self.nsectors >> (8 * i)) as u8);
-        }
+    /// on the backing file size, with the write cache state, with the
+    /// number of queues of the device, and with the discard and write
+    /// zeroes limits.
+    pub fn virtio_block_config_space(&self, num_queues: u16) -> Vec<u8> {
+        // The config space is little endian, laid out as `struct virtio_blk_config`.
+        let mut config = vec![0u8; CONFIG_SPACE_SIZE];
+        config[0..8].copy_from_slice(&self.nsectors.to_le_bytes());
+        config[CONFIG_WCE_OFFSET] = !self.writethrough as u8;
+        config[CONFIG_NUM_QUEUES_OFFSET..CONFIG_NUM_QUEUES_OFFSET + 2]
+            .copy_from_slice(&num_queues.to_le_bytes());
+        // max_discard_sectors, max_discard_seg and discard_sector_alignment.
+        config[36..40].copy_from_slice(&MAX_DISCARD_SECTORS.to_le_bytes());
+        config[40..44].copy_from_slice(&MAX_DISCARD_SEGMENTS.to_le_bytes());
+        config[44..48].copy_from_slice(&1u32.to_le_bytes());
+        // max_write_zeroes_sectors, max_write_zeroes_seg and write_zeroes_may_unmap.
+        config[48..52].copy_from_slice(&MAX_DISCARD_SECTORS.to_le_bytes());
+        config[52..56].copy_from_slice(&MAX_DISCARD_SEGMENTS.to_le_bytes());
+        config[56] = 1;
         config
     }
 }
 
+/// Cumulative latency histogram of one type of block requests.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LatencyStats {
+    /// Number of completed requests.
+    pub count: u64,
+    /// Sum of the latencies of all the completed requests, in microseconds.
+    pub sum_us: u64,
+    /// Number of requests in each bucket. Bucket `i` counts the latencies in
+    /// `[2^(i-1), 2^i)` microseconds, and the last bucket also counts the slower requests.
+    pub buckets: Vec<u64>,
+}
+
+impl From<&LatencyHistogram> for LatencyStats {
+    fn from(histogram: &LatencyHistogram) -> Self {
+        LatencyStats {
+            count: histogram.count.count() as u64,
+            sum_us: histogram.sum_us.count() as u64,
+            buckets: histogram.bucket_counts(),
+        }
+    }
+}
+
+/// Request latencies of a block device since it was attached, from the guest kicking the queue
+/// of the requests to their completion.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BlockDeviceLatencies {
+    pub read: LatencyStats,
+    pub write: LatencyStats,
+    pub flush: LatencyStats,
+    /// Time spent with the requests blocked by the rate limiter, in microseconds.
+    pub rate_limiter_blocked_us: u64,
+}
+
+/// Executes `request`, popped from a queue of a block device, on `disk`, and writes its status
+/// to guest memory. Returns the number of bytes written to guest memory, to be put in the used
+/// ring along with the request. A `None` request stands for a descriptor chain which could not be
+/// parsed.
+pub(crate) fn execute_request(
+    request: Option<&Request>,
+    disk: &mut DiskProperties,
+    mem: &GuestMemoryMmap,
+    metrics: &BlockDriveMetrics,
+    arrival_us: u64,
+) -> u32 {
+    let request = match request {
+        Some(request) => request,
+        None => return 0,
+    };
+    let (status, len) = match request.execute(disk, mem) {
+        Ok(len) => (VIRTIO_BLK_S_OK, len),
+        Err(e) => {
+            error!("Failed to execute request: {:?}", e);
+            METRICS.block.invalid_reqs_count.inc();
+            // We need at least 1 byte for the status.
+            (e.status(), 1)
+        }
+    };
+    let latency_us = get_time_us(ClockType::Monotonic).saturating_sub(arrival_us);
+    match request.request_type {
+        RequestType::In => metrics.read_latency_us.record(latency_us),
+        RequestType::Out => metrics.write_latency_us.record(latency_us),
+        RequestType::Flush => metrics.flush_latency_us.record(latency_us),
+        _ => (),
+    }
+    // We use unwrap because the request parsing process already checked that the
+    // status_addr was valid.
+    mem.write_obj(status, request.status_addr).unwrap();
+    len
+}
+
+/// Parses the descriptor chains popped from a queue of a block device, and lets the requests
+/// through `rate_limiter`. Each request comes with the index of its descriptor chain head, and is
+/// `None` if the chain could not be parsed. Stops at the first request held back by the rate
+/// limiter, which has to be returned to the avail ring along with the following chains.
+pub(crate) fn admit_requests(
+    heads: &[DescriptorChain],
+    mem: &GuestMemoryMmap,
+    rate_limiter: &mut RateLimiter,
+) -> Vec<(u16, Option<Request>)> {
+    let mut requests = Vec::with_capacity(heads.len());
+    for head in heads {
+        match Request::parse(head, mem) {
+            Ok(request) => {
+                // If limiter.consume() fails it means there is no more TokenType::Ops
+                // budget and rate limiting is in effect.
+                if !rate_limiter.consume(1, TokenType::Ops) {
+                    METRICS.block.rate_limiter_throttled_events.inc();
+                    break;
+                }
+                // Exercise the rate limiter only if this request is of data transfer type.
+                if request.request_type == RequestType::In
+                    || request.request_type == RequestType::Out
+                {
+                    // If limiter.consume() fails it means there is no more TokenType::Bytes
+                    // budget and rate limiting is in effect.
+                    if !rate_limiter.consume(u64::from(request.data_len), TokenType::Bytes) {
+                        // Revert the OPS consume().
+                        rate_limiter.manual_replenish(1, TokenType::Ops);
+                        METRICS.block.rate_limiter_throttled_events.inc();
+                        break;
+                    }
+                }
+                requests.push((head.index, Some(request)));
+            }
+            Err(e) => {
+                error!("Failed to parse available descriptor chain: {:?}", e);
+                METRICS.block.execute_fails.inc();
+                requests.push((head.index, None));
+            }
+        }
+    }
+    requests
+}
+
 /// Virtio device for exposing block level read/write operations on a host file.
 pub struct Block {
     // Host file and properties.
@@ -131,53 +532,109 @@ pub struct Block {
     // Virtio fields.
     pub(crate) avail_features: u64,
     pub(crate) acked_features: u64,
-    config_space: Vec<u8>,
+    pub(crate) config_space: Vec<u8>,
     pub(crate) activate_evt: EventFd,
 
     // Transport related fields.
     pub(crate) queues: Vec<Queue>,
     pub(crate) interrupt_status: Arc<AtomicUsize>,
     pub(crate) interrupt_evt: EventFd,
-    pub(crate) queue_evts: [EventFd; 1],
+    pub(crate) queue_evts: Vec<EventFd>,
     pub(crate) device_state: DeviceState,
 
     // Implementation specific fields.
     pub(crate) id: String,
     pub(crate) partuuid: Option<String>,
     pub(crate) root_device: bool,
-    pub(crate) rate_limiter: RateLimiter,
+    // The rate limiter has its own lock, so that the I/O threads go through it without holding
+    // the device lock.
+    pub(crate) rate_limiter: Arc<Mutex<RateLimiter>>,
+    pub(crate) io_threads: bool,
+    // Signaled to make the I/O threads exit, along with the handles to join them.
+    pub(crate) io_thread_exit_evt: EventFd,
+    pub(crate) io_thread_handles: Vec<JoinHandle<()>>,
+    // The disk handles of the I/O threads, one per queue, when the disk image can be opened
+    // once per queue. The I/O threads then execute the requests without holding the device
+    // lock.
+    pub(crate) queue_disks: Vec<Arc<Mutex<DiskProperties>>>,
+    // The number of requests popped from each queue and not yet put in the used ring.
+    pub(crate) in_flight: Vec<u16>,
+
+    // Latency accounting. A request arrives when the guest kicks its queue. The arrival of a
+    // request deferred by the rate limiter is the moment its queue got throttled, not the
+    // moment it is eventually popped again.
+    pub(crate) metrics: Arc<BlockDriveMetrics>,
+    pub(crate) throttled_since: Vec<Option<u64>>,
+    pub(crate) rate_limiter_blocked_since: Option<u64>,
 }
 
 impl Block {
     /// Create a new virtio block device that operates on the given file.
     ///
-    /// The given file must be seekable and sizable.
+    /// The given file must be seekable and sizable. The device has `num_queues` request queues,
+    /// which are serviced by dedicated I/O threads instead of the event loop when `io_threads`
+    /// is set.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         partuuid: Option<String>,
         disk_image_path: String,
+        image_format: ImageFormat,
+        cache_type: CacheType,
+        overlay_path: Option<String>,
         is_disk_read_only: bool,
         is_disk_root: bool,
+        num_queues: usize,
+        io_threads: bool,
         rate_limiter: RateLimiter,
     ) -> io::Result<Block> {
-        let disk_properties = DiskProperties::new(disk_image_path, is_disk_read_only)?;
+        if num_queues == 0 || num_queues > MAX_QUEUES {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
 
-        let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BLK_F_FLUSH);
+        let disk_properties = DiskProperties::new(
+            disk_image_path,
+            image_format,
+            cache_type,
+            is_disk_read_only,
+            overlay_path,
+        )?;
+
+        let mut avail_features = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_BLK_F_FLUSH)
+            | (1u64 << VIRTIO_BLK_F_CONFIG_WCE);
 
         if is_disk_read_only {
             avail_features |= 1u64 << VIRTIO_BLK_F_RO;
+        } else {
+            avail_features |= (1u64 << VIRTIO_BLK_F_DISCARD) | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
         };
 
-        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK)?];
+        if num_queues > 1 {
+            avail_features |= 1u64 << VIRTIO_BLK_F_MQ;
+        }
 
-        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let mut queue_evts = Vec::with_capacity(num_queues);
+        for _ in 0..num_queues {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK)?);
+        }
+
+        let queues = (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect();
 
         Ok(Block {
+            metrics: METRICS.block.drives.get_or_insert(&id),
+            throttled_since: vec![None; num_queues],
+            rate_limiter_blocked_since: None,
             id,
             root_device: is_disk_root,
             partuuid,
-            rate_limiter,
-            config_space: disk_properties.virtio_block_config_space(),
+            rate_limiter: Arc::new(Mutex::new(rate_limiter)),
+            io_threads,
+            io_thread_exit_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            io_thread_handles: Vec::new(),
+            queue_disks: Vec::new(),
+            in_flight: vec![0; num_queues],
+            config_space: disk_properties.virtio_block_config_space(num_queues as u16),
             disk: disk_properties,
             avail_features,
             acked_features: 0u64,
@@ -190,21 +647,64 @@ impl Block {
         })
     }
 
-    pub(crate) fn process_queue_event(&mut self) {
+    pub(crate) fn process_queue_event(&mut self, queue_index: usize) {
+        // The requests of the queue arrive when the guest kicks it.
+        let kick_us = get_time_us(ClockType::Monotonic);
+        self.process_queue_kick(queue_index, kick_us);
+    }
+
+    /// Processes the requests of queue `queue_index` after the guest kicked it at `kick_us`.
+    pub(crate) fn process_queue_kick(&mut self, queue_index: usize, kick_us: u64) {
+        if !self.consume_queue_event(queue_index) {
+            return;
+        }
+        if self
+            .rate_limiter
+            .lock()
+            .expect("Poisoned lock")
+            .is_blocked()
+        {
+            METRICS.block.rate_limiter_throttled_events.inc();
+            self.throttle_queue(queue_index, kick_us);
+        } else if self.process_queue(queue_index, kick_us) {
+            let _ = self.signal_used_queue();
+        }
+    }
+
+    /// Reads the event of queue `queue_index`, and returns whether the requests of the queue can
+    /// be processed.
+    pub(crate) fn consume_queue_event(&mut self, queue_index: usize) -> bool {
         METRICS.block.queue_event_count.inc();
-        if let Err(e) = self.queue_evts[0].read() {
+        if let Err(e) = self.queue_evts[queue_index].read() {
             error!("Failed to get queue event: {:?}", e);
             METRICS.block.event_fails.inc();
-        } else if self.rate_limiter.is_blocked() {
-            METRICS.block.rate_limiter_throttled_events.inc();
+            false
+        } else if !self.is_activated() {
+            // Only the I/O threads, which don't go through the event handler, get here.
+            warn!("Block: The device is not yet activated. Spurious queue event received.");
+            false
         } else {
-            self.process_virtio_queues();
+            true
         }
     }
 
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
-        if self.process_queue(0) {
+        if !self.io_thread_handles.is_empty() {
+            // Only the I/O threads pop the requests of their queue, so they are kicked instead.
+            for queue_evt in &self.queue_evts {
+                if let Err(e) = queue_evt.write(1) {
+                    error!("Failed to kick block I/O thread: {:?}", e);
+                }
+            }
+            return;
+        }
+        let mut used_any = false;
+        let now_us = get_time_us(ClockType::Monotonic);
+        for queue_index in 0..self.queues.len() {
+            used_any |= self.process_queue(queue_index, now_us);
+        }
+        if used_any {
             let _ = self.signal_used_queue();
         }
     }
@@ -212,89 +712,154 @@ impl Block {
     pub(crate) fn process_rate_limiter_event(&mut self) {
         METRICS.block.rate_limiter_event_count.inc();
         // Upon rate limiter event, call the rate limiter handler
-        // and restart processing the queue.
-        if self.rate_limiter.event_handler().is_ok() && self.process_queue(0) {
-            let _ = self.signal_used_queue();
+        // and restart processing the queues.
+        let handled = self
+            .rate_limiter
+            .lock()
+            .expect("Poisoned lock")
+            .event_handler()
+            .is_ok();
+        if handled {
+            if let Some(blocked_since) = self.rate_limiter_blocked_since.take() {
+                let blocked_us = get_time_us(ClockType::Monotonic).saturating_sub(blocked_since);
+                self.metrics
+                    .rate_limiter_blocked_us
+                    .add(blocked_us as usize);
+            }
+            self.process_virtio_queues();
+        }
+    }
+
+    // Marks the requests of queue `queue_index`, which arrived at `arrival_us`, as waiting for
+    // the rate limiter.
+    pub(crate) fn throttle_queue(&mut self, queue_index: usize, arrival_us: u64) {
+        self.throttled_since[queue_index].get_or_insert(arrival_us);
+        self.rate_limiter_blocked_since
+            .get_or_insert_with(|| get_time_us(ClockType::Monotonic));
+    }
+
+    /// Returns the request latencies of the device.
+    pub fn latencies(&self) -> BlockDeviceLatencies {
+        BlockDeviceLatencies {
+            read: LatencyStats::from(&self.metrics.read_latency_us),
+            write: LatencyStats::from(&self.metrics.write_latency_us),
+            flush: LatencyStats::from(&self.metrics.flush_latency_us),
+            rate_limiter_blocked_us: self.metrics.rate_limiter_blocked_us.count() as u64,
         }
     }
 
-    pub fn process_queue(&mut self, queue_index: usize) -> bool {
+    pub fn process_queue(&mut self, queue_index: usize, kick_us: u64) -> bool {
+        let (requests, arrival_us) = self.pop_requests(queue_index, kick_us);
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
             // This should never happen, it's been already validated in the event handler.
             DeviceState::Inactive => unreachable!(),
         };
+        let mut completed = Vec::with_capacity(requests.len());
+        for (head_index, request) in requests {
+            let len = execute_request(
+                request.as_ref(),
+                &mut self.disk,
+                mem,
+                &self.metrics,
+                arrival_us,
+            );
+            completed.push((head_index, len));
+        }
+        self.complete_requests(queue_index, &completed)
+    }
+
+    /// Pops the requests of queue `queue_index`, kicked at `kick_us`, which the rate limiter lets
+    /// through, along with the time they arrived at. Each request comes with the index of its
+    /// descriptor chain head, and is `None` if the chain could not be parsed.
+    ///
+    /// The popped requests are in flight until handed to `complete_requests`.
+    pub(crate) fn pop_requests(
+        &mut self,
+        queue_index: usize,
+        kick_us: u64,
+    ) -> (Vec<(u16, Option<Request>)>, u64) {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let arrival_us = self.arrival_us(queue_index, kick_us);
+        let heads = self.pop_heads(queue_index, &mem);
+        let requests = admit_requests(
+            &heads,
+            &mem,
+            &mut self.rate_limiter.lock().expect("Poisoned lock"),
+        );
+        self.return_heads(queue_index, heads.len() - requests.len(), arrival_us);
+
+        (requests, arrival_us)
+    }
+
+    /// Returns the time the requests popped now from queue `queue_index` arrived at: when the
+    /// queue got throttled, or else when the guest kicked it at `kick_us`.
+    pub(crate) fn arrival_us(&mut self, queue_index: usize, kick_us: u64) -> u64 {
+        self.throttled_since[queue_index].take().unwrap_or(kick_us)
+    }
+
+    /// Pops all the descriptor chains available in queue `queue_index`. They are in flight until
+    /// handed to `return_heads` or `complete_requests`.
+    pub(crate) fn pop_heads<'a>(
+        &mut self,
+        queue_index: usize,
+        mem: &'a GuestMemoryMmap,
+    ) -> Vec<DescriptorChain<'a>> {
         let queue = &mut self.queues[queue_index];
-        let mut used_any = false;
+        let mut heads = Vec::new();
         while let Some(head) = queue.pop(mem) {
-            let len;
-            match Request::parse(&head, mem) {
-                Ok(request) => {
-                    // If limiter.consume() fails it means there is no more TokenType::Ops
-                    // budget and rate limiting is in effect.
-                    if !self.rate_limiter.consume(1, TokenType::Ops) {
-                        // Stop processing the queue and return this descriptor chain to the
-                        // avail ring, for later processing.
-                        queue.undo_pop();
-                        METRICS.block.rate_limiter_throttled_events.inc();
-                        break;
-                    }
-                    // Exercise the rate limiter only if this request is of data transfer type.
-                    if request.request_type == RequestType::In
-                        || request.request_type == RequestType::Out
-                    {
-                        // If limiter.consume() fails it means there is no more TokenType::Bytes
-                        // budget and rate limiting is in effect.
-                        if !self
-                            .rate_limiter
-                            .consume(u64::from(request.data_len), TokenType::Bytes)
-                        {
-                            // Revert the OPS consume().
-                            self.rate_limiter.manual_replenish(1, TokenType::Ops);
-                            // Stop processing the queue and return this descriptor chain to the
-                            // avail ring, for later processing.
-                            queue.undo_pop();
-                            METRICS.block.rate_limiter_throttled_events.inc();
-                            break;
-                        }
-                    }
-                    let status = match request.execute(&mut self.disk, mem) {
-                        Ok(l) => {
-                            len = l;
-                            VIRTIO_BLK_S_OK
-                        }
-                        Err(e) => {
-                            error!("Failed to execute request: {:?}", e);
-                            METRICS.block.invalid_reqs_count.inc();
-                            len = 1; // We need at least 1 byte for the status.
-                            e.status()
-                        }
-                    };
-                    // We use unwrap because the request parsing process already checked that the
-                    // status_addr was valid.
-                    mem.write_obj(status, request.status_addr).unwrap();
-                }
-                Err(e) => {
-                    error!("Failed to parse available descriptor chain: {:?}", e);
-                    METRICS.block.execute_fails.inc();
-                    len = 0;
-                }
-            }
+            heads.push(head);
+        }
+        self.in_flight[queue_index] += heads.len() as u16;
+        heads
+    }
 
-            queue.add_used(mem, head.index, len).unwrap_or_else(|e| {
+    /// Returns the last `count` descriptor chains popped from queue `queue_index`, held back by
+    /// the rate limiter, to the avail ring. Their requests arrived at `arrival_us`.
+    pub(crate) fn return_heads(&mut self, queue_index: usize, count: usize, arrival_us: u64) {
+        if count == 0 {
+            return;
+        }
+        for _ in 0..count {
+            self.queues[queue_index].undo_pop();
+        }
+        self.in_flight[queue_index] -= count as u16;
+        self.throttle_queue(queue_index, arrival_us);
+    }
+
+    /// Puts the requests popped from queue `queue_index` in the used ring, given the index of
+    /// their descriptor chain head and the number of bytes written to guest memory. Returns
+    /// whether any request was used.
+    pub(crate) fn complete_requests(
+        &mut self,
+        queue_index: usize,
+        completed: &[(u16, u32)],
+    ) -> bool {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[queue_index];
+        for &(head_index, len) in completed {
+            queue.add_used(mem, head_index, len).unwrap_or_else(|e| {
                 error!(
                     "Failed to add available descriptor head {}: {}",
-                    head.index, e
+                    head_index, e
                 )
             });
-            used_any = true;
         }
+        self.in_flight[queue_index] -= completed.len() as u16;
 
-        if !used_any {
+        if completed.is_empty() {
             METRICS.block.no_avail_buffer.inc();
         }
 
-        used_any
+        !completed.is_empty()
     }
 
     pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -311,9 +876,36 @@ impl Block {
 
     /// Update the backing file and the config space of the block device.
     pub fn update_disk_image(&mut self, disk_image_path: String) -> io::Result<()> {
-        let disk_properties = DiskProperties::new(disk_image_path, self.is_read_only())?;
+        // The overlay only makes sense over the base image it was written against.
+        if self.disk.overlay().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot replace the base image of a drive with an overlay",
+            ));
+        }
+        // The new image is expected in the format of the one it replaces, and is cached the
+        // same way.
+        let mut disk_properties = DiskProperties::new(
+            disk_image_path,
+            self.disk.format(),
+            self.disk.cache_type(),
+            self.is_read_only(),
+            None,
+        )?;
+        disk_properties.set_writethrough(self.disk.writethrough());
+        let mut queue_disks = Vec::with_capacity(self.queue_disks.len());
+        for _ in &self.queue_disks {
+            queue_disks.push(disk_properties.reopen(self.is_read_only())?);
+        }
         self.disk = disk_properties;
-        self.config_space = self.disk.virtio_block_config_space();
+        for (queue_disk, disk) in self.queue_disks.iter().zip(queue_disks) {
+            if let Some(disk) = disk {
+                *queue_disk.lock().expect("Poisoned lock") = disk;
+            }
+        }
+        self.config_space = self
+            .disk
+            .virtio_block_config_space(self.queues.len() as u16);
 
         // Kick the driver to pick up the changes.
         self.interrupt_status
@@ -324,16 +916,99 @@ impl Block {
         Ok(())
     }
 
+    /// Update the capacity in the config space of the block device after its backing file
+    /// was resized, and notify the guest.
+    pub fn refresh_size(&mut self) -> io::Result<()> {
+        self.disk.refresh_size()?;
+        for queue_disk in &self.queue_disks {
+            queue_disk
+                .lock()
+                .expect("Poisoned lock")
+                .set_nsectors(self.disk.nsectors());
+        }
+        self.config_space = self
+            .disk
+            .virtio_block_config_space(self.queues.len() as u16);
+
+        // Kick the driver to pick up the new capacity.
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1)?;
+
+        METRICS.block.update_count.inc();
+        Ok(())
+    }
+
+    /// Opens the disk image once per queue, for the I/O threads to execute the requests of
+    /// their queue without holding the device lock. Returns no handles when the disk image can
+    /// only be opened once.
+    pub(crate) fn open_queue_disks(&mut self) -> io::Result<Vec<Arc<Mutex<DiskProperties>>>> {
+        let mut queue_disks = Vec::with_capacity(self.queues.len());
+        for _ in 0..self.queues.len() {
+            match self.disk.reopen(self.is_read_only())? {
+                Some(disk) => queue_disks.push(Arc::new(Mutex::new(disk))),
+                None => return Ok(Vec::new()),
+            }
+        }
+        self.queue_disks = queue_disks.clone();
+        Ok(queue_disks)
+    }
+
     /// Provides the ID of this block device.
     pub fn id(&self) -> &String {
         &self.id
     }
 
+    /// Provides the format of the disk image of this block device.
+    pub fn image_format(&self) -> ImageFormat {
+        self.disk.format()
+    }
+
+    /// Provides the host caching of the disk image of this block device.
+    pub fn cache_type(&self) -> CacheType {
+        self.disk.cache_type()
+    }
+
+    /// Provides the number of request queues of this block device.
+    pub fn num_queues(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Specifies if the queues of this block device are serviced by dedicated I/O threads.
+    pub fn has_io_threads(&self) -> bool {
+        self.io_threads
+    }
+
+    /// Makes the I/O threads of the device exit, and returns their handles. The threads may be
+    /// waiting for the device lock, so they have to be joined after releasing it.
+    pub fn stop_io_threads(&mut self) -> Vec<JoinHandle<()>> {
+        if !self.io_thread_handles.is_empty() {
+            if let Err(e) = self.io_thread_exit_evt.write(1) {
+                error!("Failed to signal block I/O threads exit: {:?}", e);
+            }
+        }
+        self.io_thread_handles.drain(..).collect()
+    }
+
     /// Provides the PARTUUID of this block device.
     pub fn partuuid(&self) -> Option<&String> {
         self.partuuid.as_ref()
     }
 
+    /// Provides the path of the copy-on-write overlay of this block device, if any.
+    pub fn overlay_path(&self) -> Option<&str> {
+        self.disk.overlay().map(CowOverlay::overlay_path)
+    }
+
+    /// Writes the current content of the copy-on-write overlay of this block device to a new
+    /// overlay file at `path`.
+    pub fn save_overlay(&self, path: &str) -> io::Result<()> {
+        self.disk
+            .overlay()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?
+            .save_to(path)
+    }
+
     /// Specifies if this block device is read only.
     pub fn is_read_only(&self) -> bool {
         self.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0
@@ -407,6 +1082,15 @@ impl VirtioDevice for Block {
         }
 
         self.config_space[offset as usize..(offset + data_len) as usize].copy_from_slice(data);
+        // The guest turns the write cache on and off through the writeback field.
+        let writethrough = self.config_space[CONFIG_WCE_OFFSET] == 0;
+        self.disk.set_writethrough(writethrough);
+        for queue_disk in &self.queue_disks {
+            queue_disk
+                .lock()
+                .expect("Poisoned lock")
+                .set_writethrough(writethrough);
+        }
     }
 
     fn is_activated(&self) -> bool {
@@ -429,6 +1113,7 @@ impl VirtioDevice for Block {
 #[cfg(test)]
 pub(crate) mod tests {
     use std::fs::metadata;
+    use std::os::unix::fs::FileExt;
     use std::os::unix::io::AsRawFd;
     use std::thread;
     use std::time::Duration;
@@ -439,11 +1124,15 @@ pub(crate) mod tests {
     use polly::event_manager::{EventManager, Subscriber};
     use utils::epoll::{EpollEvent, EventSet};
     use utils::tempfile::TempFile;
-    use vm_memory::GuestAddress;
+    use vm_memory::{Address, GuestAddress};
 
     use crate::check_metric_after_block;
     use crate::virtio::block::test_utils::{
-        default_block, invoke_handler_for_queue_event, set_queue, set_rate_limiter,
+        default_block, default_block_with_path, default_block_with_queues,
+        invoke_handler_for_queue_event, set_queue, set_rate_limiter,
+    };
+    use crate::virtio::block::{
+        VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_WRITE_ZEROES, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
     };
     use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
 
@@ -454,20 +1143,62 @@ pub(crate) mod tests {
         let size = SECTOR_SIZE * num_sectors;
         f.as_file().set_len(size).unwrap();
 
-        let disk_properties =
-            DiskProperties::new(String::from(f.as_path().to_str().unwrap()), true).unwrap();
+        let disk_properties = DiskProperties::new(
+            String::from(f.as_path().to_str().unwrap()),
+            ImageFormat::Raw,
+            CacheType::Unsafe,
+            true,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(size, SECTOR_SIZE * num_sectors);
         assert_eq!(disk_properties.nsectors, num_sectors);
-        let cfg = disk_properties.virtio_block_config_space();
+        let cfg = disk_properties.virtio_block_config_space(4);
         assert_eq!(cfg.len(), CONFIG_SPACE_SIZE);
-        for (i, byte) in cfg.iter().enumerate() {
-            assert_eq!(*byte, (num_sectors >> (8 * i)) as u8);
-        }
+        assert_eq!(&cfg[0..8], &num_sectors.to_le_bytes()[..]);
+        assert_eq!(cfg[CONFIG_WCE_OFFSET], 1);
+        assert_eq!(&cfg[34..36], &4u16.to_le_bytes()[..]);
+        assert_eq!(&cfg[36..40], &MAX_DISCARD_SECTORS.to_le_bytes()[..]);
+        assert_eq!(&cfg[40..44], &MAX_DISCARD_SEGMENTS.to_le_bytes()[..]);
+        assert_eq!(&cfg[44..48], &1u32.to_le_bytes()[..]);
+        assert_eq!(&cfg[48..52], &MAX_DISCARD_SECTORS.to_le_bytes()[..]);
+        assert_eq!(&cfg[52..56], &MAX_DISCARD_SEGMENTS.to_le_bytes()[..]);
+        assert_eq!(cfg[56], 1);
         // Testing `backing_file.virtio_block_disk_image_id()` implies
         // duplicating that logic in tests, so skipping it.
 
-        assert!(DiskProperties::new("invalid-disk-path".to_string(), true).is_err());
+        assert!(DiskProperties::new(
+            "invalid-disk-path".to_string(),
+            ImageFormat::Raw,
+            CacheType::Unsafe,
+            true,
+            None
+        )
+        .is_err());
+
+        // A raw image is not a valid qcow2 image.
+        let path = String::from(f.as_path().to_str().unwrap());
+        let unsafe_cache = CacheType::Unsafe;
+        assert!(
+            DiskProperties::new(path.clone(), ImageFormat::Qcow2, unsafe_cache, true, None)
+                .is_err()
+        );
+        // qcow2 images have their own copy-on-write mechanism.
+        assert!(DiskProperties::new(
+            path.clone(),
+            ImageFormat::Qcow2,
+            unsafe_cache,
+            false,
+            Some(path.clone())
+        )
+        .is_err());
+        // Only raw images are opened with O_DIRECT.
+        let direct = CacheType::Direct;
+        assert!(DiskProperties::new(path.clone(), ImageFormat::Qcow2, direct, true, None).is_err());
+        assert!(
+            DiskProperties::new(path.clone(), ImageFormat::Raw, direct, false, Some(path)).is_err()
+        );
     }
 
     #[test]
@@ -476,7 +1207,11 @@ pub(crate) mod tests {
 
         assert_eq!(block.device_type(), TYPE_BLOCK);
 
-        let features: u64 = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BLK_F_FLUSH);
+        let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_BLK_F_FLUSH)
+            | (1u64 << VIRTIO_BLK_F_CONFIG_WCE)
+            | (1u64 << VIRTIO_BLK_F_DISCARD)
+            | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
 
         assert_eq!(block.avail_features_by_page(0), features as u32);
         assert_eq!(block.avail_features_by_page(1), (features >> 32) as u32);
@@ -495,18 +1230,28 @@ pub(crate) mod tests {
     fn test_virtio_read_config() {
         let block = default_block();
 
-        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
-        block.read_config(0, &mut actual_config_space);
         // This will read the number of sectors.
+        let mut actual_config_space = [0u8; 8];
+        block.read_config(0, &mut actual_config_space);
         // The block's backing file size is 0x1000, so there are 8 (4096/512) sectors.
         // The config space is little endian.
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; 8] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
         assert_eq!(actual_config_space, expected_config_space);
 
+        // The discard limits follow the disk capacity.
+        let mut actual_discard_config = [0u8; 8];
+        block.read_config(36, &mut actual_discard_config);
+        assert_eq!(
+            &actual_discard_config[..4],
+            &MAX_DISCARD_SECTORS.to_le_bytes()
+        );
+        assert_eq!(
+            &actual_discard_config[4..],
+            &MAX_DISCARD_SEGMENTS.to_le_bytes()
+        );
+
         // Invalid read.
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+        let expected_config_space: [u8; 8] = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         actual_config_space = expected_config_space;
         block.read_config(CONFIG_SPACE_SIZE as u64 + 1, &mut actual_config_space);
 
@@ -518,11 +1263,10 @@ pub(crate) mod tests {
     fn test_virtio_write_config() {
         let mut block = default_block();
 
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; 8] = [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
         block.write_config(0, &expected_config_space);
 
-        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
+        let mut actual_config_space = [0u8; 8];
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
 
@@ -536,7 +1280,7 @@ pub(crate) mod tests {
 
         // Invalid write.
         let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
-        block.write_config(5, &new_config_space);
+        block.write_config(CONFIG_SPACE_SIZE as u64 - 5, &new_config_space);
         // Make sure nothing got written.
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
@@ -637,8 +1381,8 @@ pub(crate) mod tests {
         let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
         let status_addr = GuestAddress(vq.dtable[2].addr.get());
 
-        // Currently only VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
-        // VIRTIO_BLK_T_FLUSH and VIRTIO_BLK_T_GET_ID  are supported.
+        // Currently only VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_FLUSH,
+        // VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_DISCARD and VIRTIO_BLK_T_WRITE_ZEROES are supported.
         // Generate an unsupported request.
         let request_header = RequestHeader::new(42, 0);
         mem.write_obj::<RequestHeader>(request_header, request_type_addr)
@@ -717,6 +1461,7 @@ pub(crate) mod tests {
     #[test]
     fn test_flush() {
         let mut block = default_block();
+        block.metrics = Arc::new(BlockDriveMetrics::default());
         let mem = default_mem();
         let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
         set_queue(&mut block, 0, vq.create_queue());
@@ -755,6 +1500,11 @@ pub(crate) mod tests {
             assert_eq!(vq.used.ring[0].get().len, 0);
             assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
         }
+
+        let latencies = block.latencies();
+        assert_eq!(latencies.flush.count, 2);
+        assert_eq!(latencies.flush.buckets.len(), logger::LATENCY_BUCKETS);
+        assert_eq!(latencies.read.count, 0);
     }
 
     #[test]
@@ -769,7 +1519,7 @@ pub(crate) mod tests {
         let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
         let data_addr = GuestAddress(vq.dtable[1].addr.get());
         let status_addr = GuestAddress(vq.dtable[2].addr.get());
-        let blk_metadata = block.disk.file.metadata();
+        let blk_metadata = std::fs::metadata(block.disk.file_path());
 
         // Test that the driver receives the correct device id.
         {
@@ -845,7 +1595,10 @@ pub(crate) mod tests {
         assert!(rl.consume(8, TokenType::Bytes));
 
         set_rate_limiter(&mut block, rl);
-        let rate_limiter_evt = EpollEvent::new(EventSet::IN, block.rate_limiter.as_raw_fd() as u64);
+        let rate_limiter_evt = EpollEvent::new(
+            EventSet::IN,
+            block.rate_limiter.lock().unwrap().as_raw_fd() as u64,
+        );
 
         mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
             .unwrap();
@@ -865,7 +1618,7 @@ pub(crate) mod tests {
             );
 
             // Assert that limiter is blocked.
-            assert!(block.rate_limiter.is_blocked());
+            assert!(block.rate_limiter.lock().unwrap().is_blocked());
             // Assert that no operation actually completed (limiter blocked it).
             assert!(block.interrupt_evt.read().is_err());
             // Make sure the data is still queued for processing.
@@ -884,7 +1637,7 @@ pub(crate) mod tests {
                 block.process(&rate_limiter_evt, &mut event_manager)
             );
             // Validate the rate_limiter is no longer blocked.
-            assert!(!block.rate_limiter.is_blocked());
+            assert!(!block.rate_limiter.lock().unwrap().is_blocked());
 
             // Make sure the virtio queue operation completed this time.
             assert_eq!(block.interrupt_evt.read().unwrap(), 1);
@@ -900,6 +1653,8 @@ pub(crate) mod tests {
     #[test]
     fn test_ops_rate_limiter() {
         let mut block = default_block();
+        // Don't share the latency metrics with the other tests using the same drive id.
+        block.metrics = Arc::new(BlockDriveMetrics::default());
         let mem = default_mem();
         let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
         set_queue(&mut block, 0, vq.create_queue());
@@ -919,7 +1674,10 @@ pub(crate) mod tests {
         assert!(rl.consume(1, TokenType::Ops));
 
         set_rate_limiter(&mut block, rl);
-        let rate_limiter_evt = EpollEvent::new(EventSet::IN, block.rate_limiter.as_raw_fd() as u64);
+        let rate_limiter_evt = EpollEvent::new(
+            EventSet::IN,
+            block.rate_limiter.lock().unwrap().as_raw_fd() as u64,
+        );
 
         mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
             .unwrap();
@@ -939,7 +1697,7 @@ pub(crate) mod tests {
             );
 
             // Assert that limiter is blocked.
-            assert!(block.rate_limiter.is_blocked());
+            assert!(block.rate_limiter.lock().unwrap().is_blocked());
             // Assert that no operation actually completed (limiter blocked it).
             assert!(block.interrupt_evt.read().is_err());
             // Make sure the data is still queued for processing.
@@ -957,7 +1715,7 @@ pub(crate) mod tests {
             );
 
             // Assert that limiter is blocked.
-            assert!(block.rate_limiter.is_blocked());
+            assert!(block.rate_limiter.lock().unwrap().is_blocked());
             // Assert that no operation actually completed (limiter blocked it).
             assert!(block.interrupt_evt.read().is_err());
             // Make sure the data is still queued for processing.
@@ -976,7 +1734,7 @@ pub(crate) mod tests {
                 block.process(&rate_limiter_evt, &mut event_manager)
             );
             // Validate the rate_limiter is no longer blocked.
-            assert!(!block.rate_limiter.is_blocked());
+            assert!(!block.rate_limiter.lock().unwrap().is_blocked());
             // Make sure the virtio queue operation completed this time.
             assert_eq!(block.interrupt_evt.read().unwrap(), 1);
 
@@ -986,6 +1744,223 @@ pub(crate) mod tests {
             assert_eq!(vq.used.ring[0].get().len, 0);
             assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
         }
+
+        // The write waited for the rate limiter since the first attempt.
+        let latencies = block.latencies();
+        assert_eq!(latencies.read.count, 0);
+        assert_eq!(latencies.write.count, 1);
+        assert!(latencies.write.sum_us >= 100_000);
+        assert_eq!(latencies.write.buckets.iter().sum::<u64>(), 1);
+        assert!(latencies.rate_limiter_blocked_us >= 100_000);
+        assert!(block.throttled_since[0].is_none());
+        assert!(block.rate_limiter_blocked_since.is_none());
+    }
+
+    #[test]
+    fn test_discard_write_zeroes() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        let segment_size = std::mem::size_of::<DiscardSegment>() as u32;
+
+        let disk_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(block.disk.file_path())
+            .unwrap();
+        disk_file.write_all_at(&[0xaa; 0x1000], 0).unwrap();
+
+        // The segments are read from the data descriptor.
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(2 * segment_size);
+
+        // Write zeroes, with and without unmapping the sectors.
+        {
+            mem.write_obj::<u32>(VIRTIO_BLK_T_WRITE_ZEROES, request_type_addr)
+                .unwrap();
+            mem.write_obj(DiscardSegment::new(1, 2, 0), data_addr)
+                .unwrap();
+            mem.write_obj(
+                DiscardSegment::new(6, 1, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP),
+                data_addr.unchecked_add(u64::from(segment_size)),
+            )
+            .unwrap();
+
+            check_metric_after_block!(
+                &METRICS.block.write_zeroes_count,
+                1,
+                invoke_handler_for_queue_event(&mut block)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+            let mut data = [0u8; 0x1000];
+            disk_file.read_exact_at(&mut data, 0).unwrap();
+            assert!(data[..0x200].iter().all(|&byte| byte == 0xaa));
+            assert!(data[0x200..0x600].iter().all(|&byte| byte == 0));
+            assert!(data[0x600..0xc00].iter().all(|&byte| byte == 0xaa));
+            assert!(data[0xc00..0xe00].iter().all(|&byte| byte == 0));
+            assert!(data[0xe00..].iter().all(|&byte| byte == 0xaa));
+        }
+
+        // Discard.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj::<u32>(VIRTIO_BLK_T_DISCARD, request_type_addr)
+                .unwrap();
+            mem.write_obj(
+                DiscardSegment::new(7, 1, 0),
+                data_addr.unchecked_add(u64::from(segment_size)),
+            )
+            .unwrap();
+
+            check_metric_after_block!(
+                &METRICS.block.discard_count,
+                1,
+                invoke_handler_for_queue_event(&mut block)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        }
+
+        // Discard segments cannot ask for unmapping.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj(
+                DiscardSegment::new(7, 1, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP),
+                data_addr.unchecked_add(u64::from(segment_size)),
+            )
+            .unwrap();
+
+            invoke_handler_for_queue_event(&mut block);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_UNSUPP
+            );
+        }
+
+        // Segments past the end of the disk.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj(
+                DiscardSegment::new(7, 2, 0),
+                data_addr.unchecked_add(u64::from(segment_size)),
+            )
+            .unwrap();
+
+            invoke_handler_for_queue_event(&mut block);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+
+        // Data which is not a list of segments.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            vq.dtable[1].len.set(segment_size + 1);
+
+            invoke_handler_for_queue_event(&mut block);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+    }
+
+    #[test]
+    fn test_cache_type() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+
+        for &cache_type in &[CacheType::Unsafe, CacheType::Writeback, CacheType::Direct] {
+            let mut block = match Block::new(
+                "test".to_string(),
+                None,
+                path.clone(),
+                ImageFormat::Raw,
+                cache_type,
+                None,
+                false,
+                false,
+                1,
+                false,
+                RateLimiter::default(),
+            ) {
+                Ok(block) => block,
+                // Not all the file systems the tests run on support O_DIRECT.
+                Err(ref e)
+                    if cache_type == CacheType::Direct
+                        && e.raw_os_error() == Some(libc::EINVAL) =>
+                {
+                    continue
+                }
+                Err(e) => panic!("{}", e),
+            };
+            assert_eq!(block.cache_type(), cache_type);
+
+            let mem = default_mem();
+            let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+            set_queue(&mut block, 0, vq.create_queue());
+            block.activate(mem.clone()).unwrap();
+            initialize_virtqueue(&vq);
+
+            let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+            let data_addr = GuestAddress(vq.dtable[1].addr.get());
+            let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+            // The guest turns the write cache off, so that the writes complete on the disk.
+            let mut wce = [0u8];
+            block.read_config(CONFIG_WCE_OFFSET as u64, &mut wce);
+            assert_eq!(wce, [1]);
+            block.write_config(CONFIG_WCE_OFFSET as u64, &[0]);
+            assert!(block.disk.writethrough());
+
+            // Unaligned write, then flush.
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].len.set(8);
+            mem.write_obj::<u64>(0x1122_3344_5566_7788, data_addr)
+                .unwrap();
+            let request_header = RequestHeader::new(VIRTIO_BLK_T_OUT, 1);
+            mem.write_obj::<RequestHeader>(request_header, request_type_addr)
+                .unwrap();
+            invoke_handler_for_queue_event(&mut block);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            vq.dtable[0].next.set(2);
+            mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+                .unwrap();
+            check_metric_after_block!(
+                &METRICS.block.flush_count,
+                1,
+                invoke_handler_for_queue_event(&mut block)
+            );
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+            let mut data = [0u8; 8];
+            f.as_file().read_exact_at(&mut data, SECTOR_SIZE).unwrap();
+            assert_eq!(u64::from_le_bytes(data), 0x1122_3344_5566_7788);
+            f.as_file().write_all_at(&[0u8; 8], SECTOR_SIZE).unwrap();
+        }
     }
 
     #[test]
@@ -1004,7 +1979,175 @@ pub(crate) mod tests {
             .update_disk_image(String::from(path.to_str().unwrap()))
             .unwrap();
 
-        assert_eq!(block.disk.file.metadata().unwrap().st_ino(), mdata.st_ino());
+        match block.disk.file {
+            DiskImage::Raw(ref file) => {
+                assert_eq!(file.metadata().unwrap().st_ino(), mdata.st_ino())
+            }
+            _ => panic!("Unexpected disk image."),
+        }
         assert_eq!(block.disk.image_id, id);
     }
+
+    #[test]
+    fn test_refresh_size() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = default_block_with_path(f.as_path().to_str().unwrap().to_string());
+        assert_eq!(block.disk.nsectors(), 8);
+
+        f.as_file().set_len(0x3000).unwrap();
+        block.refresh_size().unwrap();
+        assert_eq!(block.disk.nsectors(), 24);
+        let mut capacity = [0u8; 8];
+        block.read_config(0, &mut capacity);
+        assert_eq!(u64::from_le_bytes(capacity), 24);
+        // The guest is notified of the config change.
+        assert_eq!(
+            block.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_CONFIG as usize
+        );
+        assert_eq!(block.interrupt_evt.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let base = TempFile::new().unwrap();
+        base.as_file().set_len(0x1000).unwrap();
+        let overlay = TempFile::new().unwrap();
+        overlay.as_file().set_len(0).unwrap();
+        let overlay_path = overlay.as_path().to_str().unwrap().to_string();
+
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            base.as_path().to_str().unwrap().to_string(),
+            ImageFormat::Raw,
+            CacheType::Unsafe,
+            Some(overlay_path.clone()),
+            false,
+            false,
+            1,
+            false,
+            RateLimiter::default(),
+        )
+        .unwrap();
+        assert_eq!(block.overlay_path(), Some(overlay_path.as_str()));
+        assert!(!block.is_read_only());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(8);
+        mem.write_obj::<u64>(123_456_789, data_addr).unwrap();
+        invoke_handler_for_queue_event(&mut block);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+        // The write landed in the overlay, the base image is left untouched.
+        let mut data = [0u8; 8];
+        base.as_file().read_exact(&mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), 0);
+        overlay.as_file().read_exact(&mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), 123_456_789);
+
+        // The overlay is only valid over its base image.
+        assert!(block
+            .update_disk_image(base.as_path().to_str().unwrap().to_string())
+            .is_err());
+
+        let saved = TempFile::new().unwrap();
+        block
+            .save_overlay(saved.as_path().to_str().unwrap())
+            .unwrap();
+        saved.as_file().read_exact(&mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), 123_456_789);
+        assert!(default_block()
+            .save_overlay(saved.as_path().to_str().unwrap())
+            .is_err());
+
+        // The size of the overlay follows the base image.
+        assert!(block.refresh_size().is_err());
+    }
+
+    #[test]
+    fn test_multi_queue() {
+        // Out of range numbers of queues.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        for &num_queues in &[0, MAX_QUEUES + 1] {
+            assert!(Block::new(
+                "test".to_string(),
+                None,
+                f.as_path().to_str().unwrap().to_string(),
+                ImageFormat::Raw,
+                CacheType::Unsafe,
+                None,
+                false,
+                false,
+                num_queues,
+                false,
+                RateLimiter::default(),
+            )
+            .is_err());
+        }
+
+        // Single queue devices don't offer multi-queue.
+        let block = default_block();
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_MQ), 0);
+        assert_eq!(block.queue_events().len(), 1);
+
+        let mut block = default_block_with_queues(4, false);
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_MQ), 0);
+        assert_eq!(block.num_queues(), 4);
+        assert_eq!(block.queues().len(), 4);
+        assert_eq!(block.queue_events().len(), 4);
+        let mut num_queues = [0u8; 2];
+        block.read_config(CONFIG_NUM_QUEUES_OFFSET as u64, &mut num_queues);
+        assert_eq!(u16::from_le_bytes(num_queues), 4);
+
+        // Each queue event processes its own queue.
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 2, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+            .unwrap();
+
+        let mut event_manager = EventManager::new().unwrap();
+        block.queue_evts[0].write(1).unwrap();
+        block.process(
+            &EpollEvent::new(EventSet::IN, block.queue_evts[0].as_raw_fd() as u64),
+            &mut event_manager,
+        );
+        assert_eq!(vq.used.idx.get(), 0);
+
+        block.queue_evts[2].write(1).unwrap();
+        block.process(
+            &EpollEvent::new(EventSet::IN, block.queue_evts[2].as_raw_fd() as u64),
+            &mut event_manager,
+        );
+        assert_eq!(block.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+        // Queue events are left to the I/O threads when the device has them.
+        assert_eq!(block.interest_list().len(), 5);
+        assert!(!block.has_io_threads());
+        block.io_threads = true;
+        assert_eq!(block.interest_list().len(), 1);
+    }
 }
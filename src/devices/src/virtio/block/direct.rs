@@ -0,0 +1,337 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Disk images opened with `O_DIRECT`, bypassing the host page cache.
+//!
+//! Direct I/O needs the file offsets, the lengths and the memory buffers to be aligned, which
+//! the guest buffers are not. All the I/O therefore goes through an aligned bounce buffer, and
+//! partially written blocks are read back first.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+
+use super::overlay::add_offset;
+use super::sparse::{is_unsupported, punch_hole, zero_file_range};
+
+/// Alignment of the offsets, lengths and buffers of the direct I/O.
+pub const ALIGNMENT: u64 = 4096;
+// Size of the bounce buffer, which bounds the I/O done at once.
+const BOUNCE_BUFFER_SIZE: u64 = 1 << 20;
+
+fn align_down(offset: u64) -> u64 {
+    offset - offset % ALIGNMENT
+}
+
+fn align_up(offset: u64) -> u64 {
+    align_down(offset + ALIGNMENT - 1)
+}
+
+fn check_aligned_size(size: u64) -> io::Result<()> {
+    if size % ALIGNMENT != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The size of disk images opened with O_DIRECT must be a multiple of {} bytes",
+                ALIGNMENT
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// A buffer whose memory is aligned for direct I/O.
+struct BounceBuffer {
+    data: Vec<u8>,
+    start: usize,
+}
+
+impl BounceBuffer {
+    fn new(len: u64) -> Self {
+        // The vector never grows, so the aligned slice never moves.
+        let data = vec![0u8; (len + ALIGNMENT) as usize];
+        let start = data.as_ptr().align_offset(ALIGNMENT as usize);
+        BounceBuffer { data, start }
+    }
+
+    fn get(&self, len: usize) -> &[u8] {
+        &self.data[self.start..self.start + len]
+    }
+
+    fn get_mut(&mut self, len: usize) -> &mut [u8] {
+        &mut self.data[self.start..self.start + len]
+    }
+}
+
+/// A disk image opened with `O_DIRECT`.
+pub struct DirectFile {
+    file: File,
+    size: u64,
+    pos: u64,
+    buffer: BounceBuffer,
+}
+
+impl DirectFile {
+    /// Wraps `file`, which must be opened with `O_DIRECT` and be a multiple of `ALIGNMENT`
+    /// bytes long.
+    pub fn new(file: File) -> io::Result<Self> {
+        let size = file.metadata()?.len();
+        check_aligned_size(size)?;
+        Ok(DirectFile {
+            file,
+            size,
+            pos: 0,
+            buffer: BounceBuffer::new(BOUNCE_BUFFER_SIZE),
+        })
+    }
+
+    /// The underlying file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Picks up the current size of the underlying file, which must still be a multiple of
+    /// `ALIGNMENT` bytes, and returns it.
+    pub fn refresh_size(&mut self) -> io::Result<u64> {
+        let size = self.file.metadata()?.len();
+        check_aligned_size(size)?;
+        self.size = size;
+        Ok(size)
+    }
+
+    /// Makes the `len` bytes at `offset` read as zeros. With `unmap`, they are deallocated
+    /// from the file when the file system supports it.
+    pub fn write_zeroes(&mut self, offset: u64, len: u64, unmap: bool) -> io::Result<()> {
+        let result = if unmap {
+            punch_hole(&self.file, offset, len)
+        } else {
+            zero_file_range(&self.file, offset, len)
+        };
+        match result {
+            Err(ref e) if is_unsupported(e) => self.write_zeroes_at(offset, len),
+            result => result,
+        }
+    }
+
+    fn write_zeroes_at(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        let zeroes = vec![0u8; cmp::min(len, BOUNCE_BUFFER_SIZE) as usize];
+        let mut count = 0;
+        while count < len {
+            let chunk = cmp::min(len - count, BOUNCE_BUFFER_SIZE) as usize;
+            match self.write_at(&zeroes[..chunk], offset + count)? {
+                0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                n => count += n as u64,
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the aligned range holding the first bytes of the `len` bytes at `offset`, which
+    // fits the bounce buffer, and how many of these bytes it holds.
+    fn aligned_range(&self, offset: u64, len: usize) -> (u64, u64, usize) {
+        let start = align_down(offset);
+        let end = cmp::min(
+            cmp::min(align_up(offset + len as u64), self.size),
+            start + BOUNCE_BUFFER_SIZE,
+        );
+        let count = cmp::min(len as u64, end - offset) as usize;
+        (start, end, count)
+    }
+
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let (start, end, count) = self.aligned_range(offset, buf.len());
+        let len = (end - start) as usize;
+        self.file.read_exact_at(self.buffer.get_mut(len), start)?;
+
+        let skip = (offset - start) as usize;
+        buf[..count].copy_from_slice(&self.buffer.get(len)[skip..skip + count]);
+        Ok(count)
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let (start, end, count) = self.aligned_range(offset, buf.len());
+        let len = (end - start) as usize;
+        // The partially written blocks keep the rest of their content.
+        if offset != start || offset + count as u64 != end {
+            self.file.read_exact_at(self.buffer.get_mut(len), start)?;
+        }
+
+        let skip = (offset - start) as usize;
+        self.buffer.get_mut(len)[skip..skip + count].copy_from_slice(&buf[..count]);
+        self.file.write_all_at(self.buffer.get(len), start)?;
+        Ok(count)
+    }
+}
+
+impl Read for DirectFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.read_at(buf, self.pos)?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Write for DirectFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.write_at(buf, self.pos)?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for DirectFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => add_offset(self.pos, offset),
+            SeekFrom::End(offset) => add_offset(self.size, offset),
+        };
+        self.pos = new_pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use utils::tempfile::TempFile;
+
+    const DISK_SIZE: u64 = 4 * BOUNCE_BUFFER_SIZE;
+
+    // Returns None when the file system of the temporary files can't do direct I/O.
+    fn direct_file(size: u64) -> Option<(TempFile, DirectFile)> {
+        let image = TempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(image.as_path())
+        {
+            Ok(file) => file,
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => return None,
+            Err(e) => panic!("{}", e),
+        };
+        let direct = DirectFile::new(file).unwrap();
+        Some((image, direct))
+    }
+
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn test_read_write() {
+        let (image, mut direct) = match direct_file(DISK_SIZE) {
+            Some(files) => files,
+            None => return,
+        };
+        let mut expected = vec![0u8; DISK_SIZE as usize];
+
+        // Unaligned writes, within a block and over the bounce buffer size.
+        let writes = [
+            (0, 512),
+            (100, 7),
+            (ALIGNMENT - 10, 20),
+            (3 * ALIGNMENT + 512, 2 * ALIGNMENT),
+            (BOUNCE_BUFFER_SIZE - 100, BOUNCE_BUFFER_SIZE + 300),
+            (DISK_SIZE - 1000, 1000),
+        ];
+        for (i, &(offset, len)) in writes.iter().enumerate() {
+            let data = pattern(len as usize, i as u8);
+            direct.seek(SeekFrom::Start(offset)).unwrap();
+            direct.write_all(&data).unwrap();
+            expected[offset as usize..(offset + len) as usize].copy_from_slice(&data);
+        }
+
+        let mut data = vec![0u8; DISK_SIZE as usize];
+        image.as_file().read_exact_at(&mut data, 0).unwrap();
+        assert!(data == expected);
+
+        // Unaligned reads.
+        for &(offset, len) in &[(10, 5000), (BOUNCE_BUFFER_SIZE - 3, BOUNCE_BUFFER_SIZE + 6)] {
+            let mut data = vec![0u8; len as usize];
+            direct.seek(SeekFrom::Start(offset)).unwrap();
+            direct.read_exact(&mut data).unwrap();
+            assert!(data[..] == expected[offset as usize..(offset + len) as usize]);
+        }
+
+        // The image doesn't grow.
+        assert_eq!(direct.seek(SeekFrom::End(-10)).unwrap(), DISK_SIZE - 10);
+        assert!(direct.write_all(&[1u8; 20]).is_err());
+        let mut data = [0u8; 20];
+        direct.seek(SeekFrom::Start(DISK_SIZE - 10)).unwrap();
+        assert_eq!(direct.read(&mut data).unwrap(), 10);
+        assert_eq!(direct.read(&mut data).unwrap(), 0);
+        assert_eq!(image.as_file().metadata().unwrap().len(), DISK_SIZE);
+    }
+
+    #[test]
+    fn test_write_zeroes() {
+        let (image, mut direct) = match direct_file(DISK_SIZE) {
+            Some(files) => files,
+            None => return,
+        };
+        image
+            .as_file()
+            .write_all_at(&vec![0xaa; DISK_SIZE as usize], 0)
+            .unwrap();
+
+        direct.write_zeroes(100, 5000, false).unwrap();
+        direct.write_zeroes(3 * ALIGNMENT, ALIGNMENT, true).unwrap();
+        direct.write_zeroes_at(5 * ALIGNMENT + 1, 10).unwrap();
+
+        let mut data = vec![0u8; 6 * ALIGNMENT as usize];
+        direct.seek(SeekFrom::Start(0)).unwrap();
+        direct.read_exact(&mut data).unwrap();
+        for (i, &byte) in data.iter().enumerate() {
+            let i = i as u64;
+            let zeroed = (100..5100).contains(&i)
+                || (3 * ALIGNMENT..4 * ALIGNMENT).contains(&i)
+                || (5 * ALIGNMENT + 1..5 * ALIGNMENT + 11).contains(&i);
+            assert_eq!(byte, if zeroed { 0 } else { 0xaa }, "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn test_unaligned_size() {
+        let image = TempFile::new().unwrap();
+        image.as_file().set_len(ALIGNMENT + 512).unwrap();
+        let file = OpenOptions::new().read(true).open(image.as_path()).unwrap();
+        assert!(DirectFile::new(file).is_err());
+    }
+
+    #[test]
+    fn test_refresh_size() {
+        let (image, mut direct) = match direct_file(ALIGNMENT) {
+            Some(files) => files,
+            None => return,
+        };
+
+        image.as_file().set_len(3 * ALIGNMENT).unwrap();
+        assert_eq!(direct.refresh_size().unwrap(), 3 * ALIGNMENT);
+        assert_eq!(direct.seek(SeekFrom::End(0)).unwrap(), 3 * ALIGNMENT);
+        direct.seek(SeekFrom::Start(2 * ALIGNMENT)).unwrap();
+        direct.write_all(&[0xaa; 16]).unwrap();
+
+        // The size is kept when the file is left unaligned.
+        image.as_file().set_len(4 * ALIGNMENT + 512).unwrap();
+        assert!(direct.refresh_size().is_err());
+        assert_eq!(direct.seek(SeekFrom::End(0)).unwrap(), 3 * ALIGNMENT);
+    }
+}
@@ -60,13 +60,18 @@ impl Subscriber for Block {
         }
 
         if self.is_activated() {
-            let queue_evt = self.queue_evts[0].as_raw_fd();
-            let rate_limiter_evt = self.rate_limiter.as_raw_fd();
+            for queue_index in 0..self.queue_evts.len() {
+                if self.queue_evts[queue_index].as_raw_fd() == source {
+                    self.process_queue_event(queue_index);
+                    return;
+                }
+            }
+
+            let rate_limiter_evt = self.rate_limiter.lock().expect("Poisoned lock").as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
             match source {
-                _ if queue_evt == source => self.process_queue_event(),
                 _ if rate_limiter_evt == source => self.process_rate_limiter_event(),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => warn!("Block: Spurious event received: {:?}", source),
@@ -85,10 +90,18 @@ impl Subscriber for Block {
         //  - on device activation (is-activated already true at this point),
         //  - on device restore from snapshot.
         if self.is_activated() {
-            vec![
-                EpollEvent::new(EventSet::IN, self.queue_evts[0].as_raw_fd() as u64),
-                EpollEvent::new(EventSet::IN, self.rate_limiter.as_raw_fd() as u64),
-            ]
+            let mut events = Vec::with_capacity(self.queue_evts.len() + 1);
+            // The I/O threads of the device wait for the queue events themselves.
+            if !self.io_threads {
+                for queue_evt in &self.queue_evts {
+                    events.push(EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64));
+                }
+            }
+            events.push(EpollEvent::new(
+                EventSet::IN,
+                self.rate_limiter.lock().expect("Poisoned lock").as_raw_fd() as u64,
+            ));
+            events
         } else {
             vec![EpollEvent::new(
                 EventSet::IN,
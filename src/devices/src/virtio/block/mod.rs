@@ -2,23 +2,44 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod device;
+pub mod direct;
 pub mod event_handler;
+pub mod overlay;
 pub mod persist;
+pub mod qcow;
 pub mod request;
+pub mod sparse;
 pub mod test_utils;
+pub mod worker;
 
-pub use self::device::Block;
+pub use self::device::{Block, BlockDeviceLatencies, CacheType, ImageFormat, LatencyStats};
 pub use self::event_handler::*;
 pub use self::request::*;
+pub use self::worker::spawn_io_threads;
 
 use vm_memory::GuestMemoryError;
 
-pub const CONFIG_SPACE_SIZE: usize = 8;
+pub const CONFIG_SPACE_SIZE: usize = 60;
+/// Offset of the writeback field in the config space.
+pub const CONFIG_WCE_OFFSET: usize = 32;
+/// Offset of the number of queues in the config space.
+pub const CONFIG_NUM_QUEUES_OFFSET: usize = 34;
 pub const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = (0x01 as u64) << SECTOR_SHIFT;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 1;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+/// Maximum number of request queues of a block device.
+pub const MAX_QUEUES: usize = 16;
+
+// Feature bits and request types missing from the virtio_blk bindings.
+pub const VIRTIO_BLK_F_DISCARD: u32 = 13;
+pub const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 14;
+pub const VIRTIO_BLK_T_DISCARD: u32 = 11;
+pub const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+pub const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
+/// Maximum number of segments in a discard or write zeroes request.
+pub const MAX_DISCARD_SEGMENTS: u32 = 32;
+/// Maximum number of sectors in a discard or write zeroes segment.
+pub const MAX_DISCARD_SECTORS: u32 = 1 << 22;
 
 #[derive(Debug)]
 pub enum Error {
@@ -30,6 +51,8 @@ pub enum Error {
     GetFileMetadata(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// Guest gave us a discard or write zeroes request without a valid list of segments.
+    InvalidDiscardSegments,
     /// The requested operation would cause a seek beyond disk end.
     InvalidOffset,
     /// Guest gave us a read only descriptor that protocol says to write to.
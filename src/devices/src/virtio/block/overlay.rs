@@ -0,0 +1,463 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Copy-on-write overlay over a read-only base image.
+//!
+//! The overlay is a sparse file as large as the base image. The guest writes land in the
+//! overlay, one cluster at a time: the first write to a cluster copies it from the base image,
+//! and the cluster is read from the overlay from then on. The clusters present in the overlay
+//! are tracked in a bitmap, which is rebuilt from the holes of the overlay file when it is
+//! opened.
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use super::sparse::punch_hole;
+
+/// Size of the copy-on-write unit.
+pub const CLUSTER_SIZE: u64 = 64 * 1024;
+
+// Number of clusters tracked by each word of the allocation bitmap.
+const BITS_PER_WORD: u64 = 64;
+
+/// A disk image whose writes go to an overlay file instead of the base image.
+pub struct CowOverlay {
+    base: File,
+    overlay: File,
+    overlay_path: String,
+    size: u64,
+    // Bitmap of the clusters present in the overlay.
+    allocated: Vec<u64>,
+    // Current offset, for the `Read`, `Write` and `Seek` implementations.
+    pos: u64,
+}
+
+impl CowOverlay {
+    /// Layers the overlay file at `overlay_path` over `base`. Unless `read_only` is set, the
+    /// overlay file is created if missing.
+    pub fn new(mut base: File, overlay_path: String, read_only: bool) -> io::Result<Self> {
+        let size = base.seek(SeekFrom::End(0))?;
+        let overlay = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .create(!read_only)
+            .open(&overlay_path)?;
+        let overlay_size = overlay.metadata()?.len();
+        if overlay_size < size && !read_only {
+            // Extending the file leaves a hole, which is not allocated on the host.
+            overlay.set_len(size)?;
+        } else if overlay_size != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The overlay size {} does not match the base image size {}",
+                    overlay_size, size
+                ),
+            ));
+        }
+
+        let allocated = Self::scan_allocated(&overlay, size)?;
+        Ok(CowOverlay {
+            base,
+            overlay,
+            overlay_path,
+            size,
+            allocated,
+            pos: 0,
+        })
+    }
+
+    /// Path of the overlay file.
+    pub fn overlay_path(&self) -> &str {
+        &self.overlay_path
+    }
+
+    /// Size of the disk, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Bitmap of the clusters present in the overlay.
+    pub fn allocated(&self) -> &[u64] {
+        &self.allocated
+    }
+
+    /// Replaces the bitmap of the clusters present in the overlay, when it is known better
+    /// than the holes of the overlay file tell.
+    pub fn set_allocated(&mut self, allocated: Vec<u64>) -> io::Result<()> {
+        if allocated.len() != self.allocated.len() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.allocated = allocated;
+        Ok(())
+    }
+
+    /// Writes the clusters present in the overlay to a new overlay file at `path`.
+    pub fn save_to(&self, path: &str) -> io::Result<()> {
+        copy_clusters(&self.overlay, path, self.size, &self.allocated)
+    }
+
+    /// Flushes the overlay writes to the host disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.overlay.sync_all()
+    }
+
+    /// Lets the host reclaim the overlay clusters in the `len` bytes at `offset`. They read as
+    /// zeros from then on, until the overlay is reopened from its file, after which the base
+    /// image shows through them again.
+    pub fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        // Punching the clusters which are not in the overlay is a no-op.
+        punch_hole(&self.overlay, offset, len)
+    }
+
+    /// Makes the `len` bytes at `offset` read as zeros.
+    pub fn write_zeroes(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        // The zeros are written rather than left to the file system, which may report zeroed
+        // ranges as holes, and thus unallocated clusters, once the overlay is reopened.
+        let zeroes = vec![0u8; cmp::min(len, CLUSTER_SIZE) as usize];
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let chunk = cmp::min(end - pos, CLUSTER_SIZE) as usize;
+            pos += self.write_cluster_at(&zeroes[..chunk], pos)? as u64;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn num_words(size: u64) -> usize {
+        let num_clusters = (size + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+        ((num_clusters + BITS_PER_WORD - 1) / BITS_PER_WORD) as usize
+    }
+
+    fn is_allocated(&self, cluster: u64) -> bool {
+        is_set(&self.allocated, cluster)
+    }
+
+    fn set_allocated_cluster(&mut self, cluster: u64) {
+        self.allocated[(cluster / BITS_PER_WORD) as usize] |= 1 << (cluster % BITS_PER_WORD);
+    }
+
+    // Marks every cluster holding data in `overlay` as allocated.
+    fn scan_allocated(overlay: &File, size: u64) -> io::Result<Vec<u64>> {
+        let mut allocated = vec![0u64; Self::num_words(size)];
+        let fd = overlay.as_raw_fd();
+        let mut offset = 0u64;
+        while offset < size {
+            // Safe because the file descriptor is valid, and we check the return value.
+            let data_start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+            if data_start < 0 {
+                let err = io::Error::last_os_error();
+                // There is no data past `offset`.
+                if err.raw_os_error() == Some(libc::ENXIO) {
+                    break;
+                }
+                return Err(err);
+            }
+            // Safe because the file descriptor is valid, and we check the return value.
+            let data_end = unsafe { libc::lseek(fd, data_start, libc::SEEK_HOLE) };
+            if data_end < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let data_end = cmp::min(data_end as u64, size);
+            for cluster in (data_start as u64 / CLUSTER_SIZE)..=((data_end - 1) / CLUSTER_SIZE) {
+                allocated[(cluster / BITS_PER_WORD) as usize] |= 1 << (cluster % BITS_PER_WORD);
+            }
+            offset = data_end;
+        }
+        Ok(allocated)
+    }
+
+    // Reads at most one cluster at `offset` into `buf`.
+    fn read_cluster_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let cluster = offset / CLUSTER_SIZE;
+        let cluster_end = cmp::min((cluster + 1) * CLUSTER_SIZE, self.size);
+        let len = cmp::min(buf.len() as u64, cluster_end - offset) as usize;
+        let file = if self.is_allocated(cluster) {
+            &self.overlay
+        } else {
+            &self.base
+        };
+        file.read_exact_at(&mut buf[..len], offset)?;
+        Ok(len)
+    }
+
+    // Writes at most one cluster from `buf` at `offset`, copying the cluster from the base
+    // image first if it is not in the overlay yet.
+    fn write_cluster_at(&mut self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let cluster = offset / CLUSTER_SIZE;
+        let cluster_start = cluster * CLUSTER_SIZE;
+        let cluster_end = cmp::min(cluster_start + CLUSTER_SIZE, self.size);
+        let len = cmp::min(buf.len() as u64, cluster_end - offset) as usize;
+
+        if !self.is_allocated(cluster) {
+            let mut data = vec![0u8; (cluster_end - cluster_start) as usize];
+            // No need to read the cluster if the write covers it entirely.
+            if len < data.len() {
+                self.base.read_exact_at(&mut data, cluster_start)?;
+            }
+            let start = (offset - cluster_start) as usize;
+            data[start..start + len].copy_from_slice(&buf[..len]);
+            self.overlay.write_all_at(&data, cluster_start)?;
+            self.set_allocated_cluster(cluster);
+        } else {
+            self.overlay.write_all_at(&buf[..len], offset)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Read for CowOverlay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() && self.pos < self.size {
+            let len = self.read_cluster_at(&mut buf[count..], self.pos)?;
+            count += len;
+            self.pos += len as u64;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for CowOverlay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() && self.pos < self.size {
+            let len = self.write_cluster_at(&buf[count..], self.pos)?;
+            count += len;
+            self.pos += len as u64;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.overlay.flush()
+    }
+}
+
+impl Seek for CowOverlay {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => add_offset(self.pos, offset),
+            SeekFrom::End(offset) => add_offset(self.size, offset),
+        };
+        self.pos = new_pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+}
+
+pub(super) fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+fn is_set(bitmap: &[u64], cluster: u64) -> bool {
+    bitmap[(cluster / BITS_PER_WORD) as usize] & (1 << (cluster % BITS_PER_WORD)) != 0
+}
+
+/// Copies the clusters of the `size` bytes long overlay file `src` marked in `allocated` to a
+/// new overlay file at `dst_path`, leaving holes everywhere else.
+pub fn copy_clusters(src: &File, dst_path: &str, size: u64, allocated: &[u64]) -> io::Result<()> {
+    let dst = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst_path)?;
+    dst.set_len(size)?;
+
+    let mut data = vec![0u8; CLUSTER_SIZE as usize];
+    let mut cluster_start = 0;
+    while cluster_start < size {
+        let cluster = cluster_start / CLUSTER_SIZE;
+        if (cluster / BITS_PER_WORD) as usize >= allocated.len() {
+            break;
+        }
+        if is_set(allocated, cluster) {
+            let len = cmp::min(CLUSTER_SIZE, size - cluster_start) as usize;
+            src.read_exact_at(&mut data[..len], cluster_start)?;
+            dst.write_all_at(&data[..len], cluster_start)?;
+        }
+        cluster_start += CLUSTER_SIZE;
+    }
+    dst.sync_all()
+}
+
+/// Copies the overlay file at `src_path` to `dst_path`, keeping only the clusters marked in
+/// `allocated`.
+pub fn copy_overlay(src_path: &str, dst_path: &str, allocated: &[u64]) -> io::Result<()> {
+    let src = File::open(src_path)?;
+    let size = src.metadata()?.len();
+    copy_clusters(&src, dst_path, size, allocated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    const DISK_SIZE: u64 = 4 * CLUSTER_SIZE + 512;
+
+    fn base_image() -> TempFile {
+        let base = TempFile::new().unwrap();
+        let data: Vec<u8> = (0..DISK_SIZE).map(|i| (i % 251) as u8).collect();
+        base.as_file().write_all_at(&data, 0).unwrap();
+        base
+    }
+
+    fn overlay_path() -> (TempFile, String) {
+        let overlay = TempFile::new().unwrap();
+        let path = overlay.as_path().to_str().unwrap().to_string();
+        // The overlay file starts empty.
+        overlay.as_file().set_len(0).unwrap();
+        (overlay, path)
+    }
+
+    fn read_all(disk: &mut CowOverlay) -> Vec<u8> {
+        let mut data = vec![0u8; DISK_SIZE as usize];
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let base = base_image();
+        let (_overlay_file, path) = overlay_path();
+        let mut disk =
+            CowOverlay::new(base.as_file().try_clone().unwrap(), path.clone(), false).unwrap();
+        assert_eq!(disk.size(), DISK_SIZE);
+        assert_eq!(disk.overlay_path(), path);
+        assert_eq!(disk.allocated(), &[0]);
+
+        let mut expected = read_all(&mut disk);
+        let base_data = expected.clone();
+
+        // A write spanning the end of the first cluster and the start of the second one.
+        let offset = CLUSTER_SIZE - 100;
+        disk.seek(SeekFrom::Start(offset)).unwrap();
+        disk.write_all(&[0xaa; 200]).unwrap();
+        expected[offset as usize..offset as usize + 200].copy_from_slice(&[0xaa; 200]);
+        assert_eq!(disk.allocated(), &[0b11]);
+
+        // A write to the last, partial cluster.
+        disk.seek(SeekFrom::End(-10)).unwrap();
+        disk.write_all(&[0xbb; 10]).unwrap();
+        let len = expected.len();
+        expected[len - 10..].copy_from_slice(&[0xbb; 10]);
+        assert_eq!(disk.allocated(), &[0b10011]);
+
+        // Writes and reads stop at the end of the disk.
+        assert_eq!(disk.write(&[0xcc; 10]).unwrap(), 0);
+        assert_eq!(disk.read(&mut [0u8; 10]).unwrap(), 0);
+
+        assert_eq!(read_all(&mut disk), expected);
+        // The base image is left untouched.
+        let mut data = vec![0u8; DISK_SIZE as usize];
+        base.as_file().read_exact_at(&mut data, 0).unwrap();
+        assert_eq!(data, base_data);
+
+        // Reopening the overlay finds the same clusters in it.
+        let mut disk = CowOverlay::new(base.as_file().try_clone().unwrap(), path, true).unwrap();
+        assert_eq!(disk.allocated(), &[0b10011]);
+        assert_eq!(read_all(&mut disk), expected);
+    }
+
+    #[test]
+    fn test_save_to() {
+        let base = base_image();
+        let (_overlay_file, path) = overlay_path();
+        let mut disk = CowOverlay::new(base.as_file().try_clone().unwrap(), path, false).unwrap();
+        disk.seek(SeekFrom::Start(2 * CLUSTER_SIZE + 3)).unwrap();
+        disk.write_all(&[0xaa; 3]).unwrap();
+        let expected = read_all(&mut disk);
+
+        let saved = TempFile::new().unwrap();
+        let saved_path = saved.as_path().to_str().unwrap().to_string();
+        disk.save_to(&saved_path).unwrap();
+
+        // Later writes don't reach the saved overlay.
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.write_all(&[0xbb; 3]).unwrap();
+
+        let copy = TempFile::new().unwrap();
+        let copy_path = copy.as_path().to_str().unwrap().to_string();
+        copy_overlay(&saved_path, &copy_path, &[0b100]).unwrap();
+
+        let mut restored =
+            CowOverlay::new(base.as_file().try_clone().unwrap(), copy_path, false).unwrap();
+        restored.set_allocated(vec![0b100]).unwrap();
+        assert!(restored.set_allocated(vec![0, 0]).is_err());
+        assert_eq!(read_all(&mut restored), expected);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let base = base_image();
+        let (overlay_file, path) = overlay_path();
+        overlay_file.as_file().set_len(DISK_SIZE + 1).unwrap();
+        assert!(CowOverlay::new(base.as_file().try_clone().unwrap(), path, false).is_err());
+    }
+
+    #[test]
+    fn test_discard_write_zeroes() {
+        let base = base_image();
+        let (_overlay_file, path) = overlay_path();
+        let mut disk = CowOverlay::new(base.as_file().try_clone().unwrap(), path, false).unwrap();
+        let mut expected = read_all(&mut disk);
+
+        // Zeroing a range copies the partial clusters up, and leaves the base image untouched.
+        disk.write_zeroes(CLUSTER_SIZE + 10, CLUSTER_SIZE + 20)
+            .unwrap();
+        for byte in
+            expected[(CLUSTER_SIZE + 10) as usize..(2 * CLUSTER_SIZE + 30) as usize].iter_mut()
+        {
+            *byte = 0;
+        }
+        assert_eq!(disk.allocated(), &[0b110]);
+        assert_eq!(read_all(&mut disk), expected);
+        let mut data = [0u8; 1];
+        base.as_file()
+            .read_exact_at(&mut data, CLUSTER_SIZE + 10)
+            .unwrap();
+        assert_ne!(data[0], 0);
+
+        // Discarded clusters of the overlay read as zeros, the others are left alone.
+        if disk.discard(0, 2 * CLUSTER_SIZE).is_ok() {
+            for byte in expected[CLUSTER_SIZE as usize..2 * CLUSTER_SIZE as usize].iter_mut() {
+                *byte = 0;
+            }
+            assert_eq!(read_all(&mut disk), expected);
+        }
+
+        assert!(disk.write_zeroes(DISK_SIZE - 1, 2).is_err());
+        assert!(disk.discard(u64::max_value(), 2).is_err());
+    }
+
+    #[test]
+    fn test_seek() {
+        let base = base_image();
+        let (_overlay_file, path) = overlay_path();
+        let mut disk = CowOverlay::new(base.as_file().try_clone().unwrap(), path, false).unwrap();
+        assert_eq!(disk.seek(SeekFrom::End(0)).unwrap(), DISK_SIZE);
+        assert_eq!(disk.seek(SeekFrom::Current(-12)).unwrap(), DISK_SIZE - 12);
+        assert_eq!(disk.seek(SeekFrom::Start(3)).unwrap(), 3);
+        assert!(disk.seek(SeekFrom::Current(-4)).is_err());
+    }
+}
@@ -4,16 +4,18 @@
 //! Defines the structures needed for saving/restoring block devices.
 
 use std::io;
+use std::num::Wrapping;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_gen::virtio_blk::VIRTIO_BLK_F_RO;
 use vm_memory::GuestMemoryMmap;
 
+use super::overlay::{copy_overlay, CLUSTER_SIZE};
 use super::*;
 
 use crate::virtio::persist::VirtioDeviceState;
@@ -27,6 +29,185 @@ pub struct BlockState {
     disk_path: String,
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
+    #[version(start = 2, ser_fn = "overlay_serialize")]
+    overlay: Option<BlockOverlayState>,
+    #[version(start = 2, default_fn = "default_format", ser_fn = "format_serialize")]
+    format: ImageFormat,
+    #[version(
+        start = 2,
+        default_fn = "default_cache_type",
+        ser_fn = "cache_type_serialize"
+    )]
+    cache_type: CacheType,
+    #[version(start = 2, ser_fn = "writethrough_serialize")]
+    writethrough: bool,
+    #[version(
+        start = 2,
+        default_fn = "default_num_queues",
+        ser_fn = "num_queues_serialize"
+    )]
+    num_queues: u16,
+    #[version(start = 2, ser_fn = "io_threads_serialize")]
+    io_threads: bool,
+    #[version(start = 2)]
+    nsectors: Option<u64>,
+}
+
+/// The serializable state of a copy-on-write overlay.
+#[derive(Clone, Versionize)]
+pub struct BlockOverlayState {
+    path: String,
+    cluster_size: u64,
+    allocated: Vec<u64>,
+}
+
+impl BlockState {
+    /// Returns the ID of the saved device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the unique id of the boot partition of the saved device, if any.
+    pub fn partuuid(&self) -> Option<&String> {
+        self.partuuid.as_ref()
+    }
+
+    /// Returns whether the saved device is the root device.
+    pub fn is_root_device(&self) -> bool {
+        self.root_device
+    }
+
+    /// Returns the path of the file backing the saved device.
+    pub fn disk_path(&self) -> &str {
+        &self.disk_path
+    }
+
+    /// Sets the path of the file the restored device will be backed by.
+    pub fn set_disk_path(&mut self, disk_path: String) {
+        self.disk_path = disk_path;
+    }
+
+    /// Returns the path of the copy-on-write overlay of the saved device, if any.
+    pub fn overlay_path(&self) -> Option<&str> {
+        self.overlay.as_ref().map(|overlay| overlay.path.as_str())
+    }
+
+    /// Sets the path of the copy-on-write overlay the restored device will write to.
+    pub fn set_overlay_path(&mut self, overlay_path: String) {
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.path = overlay_path;
+        }
+    }
+
+    /// Copies the saved copy-on-write overlay to `overlay_path`, and makes the restored
+    /// device write to the copy.
+    pub fn clone_overlay(&mut self, overlay_path: String) -> io::Result<()> {
+        let overlay = self
+            .overlay
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        copy_overlay(&overlay.path, &overlay_path, &overlay.allocated)?;
+        overlay.path = overlay_path;
+        Ok(())
+    }
+
+    /// Returns the format of the disk image of the saved device.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Returns the host caching of the disk image of the saved device.
+    pub fn cache_type(&self) -> CacheType {
+        self.cache_type
+    }
+
+    /// Returns the number of request queues of the saved device.
+    pub fn num_queues(&self) -> u16 {
+        self.num_queues
+    }
+
+    /// Returns whether the queues of the saved device are serviced by dedicated I/O threads.
+    pub fn io_threads(&self) -> bool {
+        self.io_threads
+    }
+
+    /// Returns the size of the disk of the saved device in sectors, as seen by the guest, if
+    /// it was recorded.
+    pub fn nsectors(&self) -> Option<u64> {
+        self.nsectors
+    }
+
+    fn overlay_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.overlay.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement block device overlays.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn format_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.format != ImageFormat::Raw {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement disk image formats other than raw.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_format(_: u16) -> ImageFormat {
+        ImageFormat::Raw
+    }
+
+    fn cache_type_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.cache_type != CacheType::Unsafe {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement block cache types other than unsafe.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_cache_type(_: u16) -> CacheType {
+        CacheType::Unsafe
+    }
+
+    fn writethrough_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.writethrough {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement disabling the block write cache.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn num_queues_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.num_queues > 1 {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement multi-queue block devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_num_queues(_: u16) -> u16 {
+        1
+    }
+
+    fn io_threads_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.io_threads {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement block I/O threads.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct BlockConstructorArgs {
@@ -39,13 +220,38 @@ impl Persist<'_> for Block {
     type Error = io::Error;
 
     fn save(&self) -> Self::State {
+        let mut virtio_state = VirtioDeviceState::from_device(self);
+        // The requests an I/O thread is executing are saved as not yet popped, for the restored
+        // device to execute them again.
+        virtio_state.queues = self
+            .queues
+            .iter()
+            .zip(&self.in_flight)
+            .map(|(queue, in_flight)| {
+                let mut queue = queue.clone();
+                queue.next_avail -= Wrapping(*in_flight);
+                queue.save()
+            })
+            .collect();
+
         BlockState {
             id: self.id.clone(),
             partuuid: self.partuuid.clone(),
             root_device: self.root_device,
             disk_path: self.disk.file_path().clone(),
-            virtio_state: VirtioDeviceState::from_device(self),
-            rate_limiter_state: self.rate_limiter.save(),
+            virtio_state,
+            rate_limiter_state: self.rate_limiter.lock().expect("Poisoned lock").save(),
+            overlay: self.disk.overlay().map(|overlay| BlockOverlayState {
+                path: overlay.overlay_path().to_string(),
+                cluster_size: CLUSTER_SIZE,
+                allocated: overlay.allocated().to_vec(),
+            }),
+            format: self.disk.format(),
+            cache_type: self.disk.cache_type(),
+            writethrough: self.disk.writethrough(),
+            num_queues: self.queues.len() as u16,
+            io_threads: self.io_threads,
+            nsectors: Some(self.disk.nsectors()),
         }
     }
 
@@ -60,18 +266,52 @@ impl Persist<'_> for Block {
             state.id.clone(),
             state.partuuid.clone(),
             state.disk_path.clone(),
+            state.format,
+            state.cache_type,
+            state.overlay.as_ref().map(|overlay| overlay.path.clone()),
             is_disk_read_only,
             state.root_device,
+            state.num_queues as usize,
+            state.io_threads,
             rate_limiter,
         )?;
+        if let Some(ref overlay_state) = state.overlay {
+            if overlay_state.cluster_size != CLUSTER_SIZE {
+                return Err(io::Error::from(io::ErrorKind::InvalidInput));
+            }
+            // Safe to unwrap since the device was created with an overlay.
+            block
+                .disk
+                .overlay_mut()
+                .unwrap()
+                .set_allocated(overlay_state.allocated.clone())?;
+        }
 
         block.queues = state
             .virtio_state
-            .build_queues_checked(&constructor_args.mem, TYPE_BLOCK, NUM_QUEUES, QUEUE_SIZE)
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_BLOCK,
+                state.num_queues as usize,
+                QUEUE_SIZE,
+            )
             .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
         block.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         block.avail_features = state.virtio_state.avail_features;
         block.acked_features = state.virtio_state.acked_features;
+        block.disk.set_writethrough(state.writethrough);
+        // The guest keeps the disk size it last saw, until the next refresh. A backing file
+        // shrunk since then would fail the guest I/O past its end.
+        if let Some(nsectors) = state.nsectors {
+            if nsectors > block.disk.nsectors() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The backing file is smaller than the saved disk",
+                ));
+            }
+            block.disk.set_nsectors(nsectors);
+        }
+        block.config_space = block.disk.virtio_block_config_space(state.num_queues);
 
         if state.virtio_state.activated {
             block.device_state = DeviceState::Activated(constructor_args.mem);
@@ -87,8 +327,14 @@ mod tests {
     use crate::virtio::device::VirtioDevice;
     use utils::tempfile::TempFile;
 
-    use crate::virtio::test_utils::default_mem;
+    use crate::virtio::block::test_utils::{
+        block_with_queues, default_block, default_block_with_path, set_queue,
+    };
+    use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
+    use std::io::{Read, Write};
     use std::sync::atomic::Ordering;
+    use virtio_gen::virtio_blk::VIRTIO_BLK_T_FLUSH;
+    use vm_memory::{Bytes, GuestAddress};
 
     #[test]
     fn test_persistence() {
@@ -101,8 +347,13 @@ mod tests {
             id,
             None,
             f.as_path().to_str().unwrap().to_string(),
+            ImageFormat::Raw,
+            CacheType::Unsafe,
+            None,
             false,
             false,
+            1,
+            false,
             RateLimiter::default(),
         )
         .unwrap();
@@ -137,4 +388,283 @@ mod tests {
         // Test that block specific fields are the same.
         assert_eq!(restored_block.disk.file_path(), block.disk.file_path());
     }
+
+    #[test]
+    fn test_persist_overlay() {
+        let base = TempFile::new().unwrap();
+        base.as_file().set_len(0x1000).unwrap();
+        let overlay = TempFile::new().unwrap();
+        overlay.as_file().set_len(0).unwrap();
+
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            base.as_path().to_str().unwrap().to_string(),
+            ImageFormat::Raw,
+            CacheType::Unsafe,
+            Some(overlay.as_path().to_str().unwrap().to_string()),
+            false,
+            false,
+            1,
+            false,
+            RateLimiter::default(),
+        )
+        .unwrap();
+        block.disk.file_mut().write_all(&[0xaa; 16]).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+
+        // Overlays can't be saved in the first snapshot version.
+        let mut state = <Block as Persist>::save(&block);
+        assert_eq!(
+            format!(
+                "{:?}",
+                state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement block device overlays.\")"
+        );
+
+        // Each restored device writes to its own copy of the saved overlay.
+        let copy = TempFile::new().unwrap();
+        let copy_path = copy.as_path().to_str().unwrap().to_string();
+        state.clone_overlay(copy_path.clone()).unwrap();
+        assert_eq!(state.overlay_path(), Some(copy_path.as_str()));
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+
+        let mut restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.overlay_path(), Some(copy_path.as_str()));
+        let mut data = [0u8; 16];
+        restored_block
+            .disk
+            .file_mut()
+            .read_exact(&mut data)
+            .unwrap();
+        assert_eq!(data, [0xaa; 16]);
+    }
+
+    #[test]
+    fn test_persist_format() {
+        let mut state = <Block as Persist>::save(&default_block());
+        assert_eq!(state.format(), ImageFormat::Raw);
+        state.format = ImageFormat::Qcow2;
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+
+        // Only raw images can be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement disk image formats other than raw.\")"
+        );
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state.format(), ImageFormat::Qcow2);
+    }
+
+    #[test]
+    fn test_persist_cache_type() {
+        // The backing file has to outlive the restored device.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = default_block_with_path(f.as_path().to_str().unwrap().to_string());
+        block.disk.set_writethrough(true);
+        let mut state = <Block as Persist>::save(&block);
+        assert_eq!(state.cache_type(), CacheType::Unsafe);
+        state.cache_type = CacheType::Writeback;
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+
+        // Only unsafe caching with the write cache on can be saved in the first snapshot
+        // version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement block cache types other than unsafe.\")"
+        );
+        state.cache_type = CacheType::Unsafe;
+        assert_eq!(
+            format!(
+                "{:?}",
+                state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement disabling the block write cache.\")"
+        );
+
+        state.cache_type = CacheType::Writeback;
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.cache_type(), CacheType::Writeback);
+        assert!(restored_block.disk.writethrough());
+        assert_eq!(restored_block.config_space[CONFIG_WCE_OFFSET], 0);
+    }
+
+    #[test]
+    fn test_persist_queues() {
+        // The backing file has to outlive the restored device.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let mut state = <Block as Persist>::save(&block_with_queues(path.clone(), 4, false));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+
+        // Only single queue devices without I/O threads can be saved in the first snapshot
+        // version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement multi-queue block devices.\")"
+        );
+        let mut single_queue_state = <Block as Persist>::save(&block_with_queues(path, 1, true));
+        assert!(single_queue_state.io_threads());
+        assert_eq!(
+            format!(
+                "{:?}",
+                single_queue_state
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .unwrap_err()
+            ),
+            "Semantic(\"Target version does not implement block I/O threads.\")"
+        );
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.num_queues(), 4);
+        assert_eq!(restored_block.queue_events().len(), 4);
+        assert!(!restored_block.has_io_threads());
+        assert_eq!(
+            &restored_block.config_space[CONFIG_NUM_QUEUES_OFFSET..CONFIG_NUM_QUEUES_OFFSET + 2],
+            &4u16.to_le_bytes()[..]
+        );
+    }
+
+    #[test]
+    fn test_persist_in_flight() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = default_block_with_path(f.as_path().to_str().unwrap().to_string());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        initialize_virtqueue(&vq);
+        block.activate(mem.clone()).unwrap();
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+
+        // A request popped by an I/O thread is saved as not yet popped.
+        let (requests, _) = block.pop_requests(0, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(block.queues[0].next_avail, Wrapping(1));
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: mem.clone() },
+            &<Block as Persist>::save(&block),
+        )
+        .unwrap();
+        assert_eq!(restored_block.queues[0].next_avail, Wrapping(0));
+
+        // Once completed, it is saved as popped.
+        assert!(block.complete_requests(0, &[(requests[0].0, 0)]));
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem },
+            &<Block as Persist>::save(&block),
+        )
+        .unwrap();
+        assert_eq!(restored_block.queues[0].next_avail, Wrapping(1));
+        assert_eq!(restored_block.queues[0].next_used, Wrapping(1));
+    }
+
+    #[test]
+    fn test_persist_size() {
+        // The backing file has to outlive the restored device.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = default_block_with_path(f.as_path().to_str().unwrap().to_string());
+        let mut state = <Block as Persist>::save(&block);
+        assert_eq!(state.nsectors(), Some(8));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+
+        // The restored guest sees the saved size, even when the backing file grew since.
+        f.as_file().set_len(0x2000).unwrap();
+        let mut restored_block =
+            Block::restore(BlockConstructorArgs { mem: default_mem() }, &restored_state).unwrap();
+        assert_eq!(restored_block.disk.nsectors(), 8);
+        assert_eq!(&restored_block.config_space[0..8], &8u64.to_le_bytes()[..]);
+        restored_block.refresh_size().unwrap();
+        assert_eq!(restored_block.disk.nsectors(), 16);
+
+        // The backing file can't shrink below the saved size.
+        f.as_file().set_len(0x800).unwrap();
+        assert!(
+            Block::restore(BlockConstructorArgs { mem: default_mem() }, &restored_state).is_err()
+        );
+
+        // The first snapshot version doesn't record the size.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(restored_state.nsectors(), None);
+    }
 }
@@ -0,0 +1,861 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads and writes disk images in the qcow2 format.
+//!
+//! The guest clusters are mapped to host clusters of the image through two levels of tables:
+//! the L1 table points to L2 tables, whose entries point to the data clusters. Clusters
+//! without data are read from the backing file, if any, and as zeros otherwise. The first
+//! write to such a cluster allocates a host cluster at the end of the image, and counts it in
+//! the refcount blocks. Compressed clusters, encryption and internal snapshots are not
+//! supported.
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use super::overlay::add_offset;
+use super::sparse::{punch_hole, zero_range};
+
+const QCOW_MAGIC: u32 = 0x5146_49fb;
+const V2_HEADER_SIZE: usize = 72;
+const V3_HEADER_SIZE: usize = 104;
+
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+// Only 16 bit refcounts are supported, the only width before version 3.
+const REFCOUNT_ORDER: u32 = 4;
+const REFCOUNT_SIZE: u64 = 2;
+// Upper bound of the memory taken by the L1 and refcount tables.
+const MAX_TABLE_SIZE: u64 = 32 * 1024 * 1024;
+const MAX_BACKING_FILE_NAME: u32 = 1023;
+// Protects against loops in the backing file chain.
+const MAX_BACKING_DEPTH: usize = 16;
+
+const TABLE_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const REFCOUNT_TABLE_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+// Set in the L1 and L2 entries whose cluster has a refcount of exactly one.
+const COPIED_FLAG: u64 = 1 << 63;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+// Set in the L2 entries of clusters reading as zeros, from version 3 on.
+const ZERO_FLAG: u64 = 1;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn be_u32(buf: &[u8], offset: usize) -> u32 {
+    // Safe to unwrap since the slice has the right length.
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(buf: &[u8], offset: usize) -> u64 {
+    // Safe to unwrap since the slice has the right length.
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_table(file: &File, offset: u64, entries: u64) -> io::Result<Vec<u64>> {
+    let mut buf = vec![0u8; (entries * 8) as usize];
+    file.read_exact_at(&mut buf, offset)?;
+    Ok(buf.chunks_exact(8).map(|entry| be_u64(entry, 0)).collect())
+}
+
+/// Checks whether `file` starts with the qcow2 magic.
+pub fn is_qcow(file: &File) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    match file.read_exact_at(&mut magic, 0) {
+        Ok(()) => Ok(u32::from_be_bytes(magic) == QCOW_MAGIC),
+        // Files shorter than the magic are raw images.
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Where the data of a guest cluster is.
+enum Cluster {
+    // Read from the backing file, or as zeros without one.
+    Unallocated,
+    // Reads as zeros, possibly with a host cluster preallocated for it.
+    Zero(Option<u64>),
+    // In the host cluster at the given offset.
+    Data(u64),
+}
+
+// The image the clusters without data are read from.
+enum BackingFile {
+    Raw(File, u64),
+    Qcow(QcowFile),
+}
+
+impl BackingFile {
+    fn open(path: &Path, depth: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if is_qcow(&file)? {
+            Ok(BackingFile::Qcow(QcowFile::open(file, path, depth)?))
+        } else {
+            let size = file.metadata()?.len();
+            Ok(BackingFile::Raw(file, size))
+        }
+    }
+
+    // Fills `buf` from `offset`, with zeros past the end of the backing file.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self {
+            BackingFile::Raw(file, size) => {
+                let len = cmp::min(buf.len() as u64, size.saturating_sub(offset)) as usize;
+                file.read_exact_at(&mut buf[..len], offset)?;
+                for byte in buf[len..].iter_mut() {
+                    *byte = 0;
+                }
+                Ok(())
+            }
+            BackingFile::Qcow(qcow) => qcow.read_exact_at(buf, offset),
+        }
+    }
+}
+
+/// A disk image in the qcow2 format.
+pub struct QcowFile {
+    file: File,
+    version: u32,
+    size: u64,
+    cluster_size: u64,
+    // Number of entries of an L2 table.
+    l2_entries: u64,
+    l1_table_offset: u64,
+    l1_table: Vec<u64>,
+    refcount_table_offset: u64,
+    refcount_table: Vec<u64>,
+    // Number of refcounts in a refcount block.
+    refcount_block_entries: u64,
+    backing_file: Option<Box<BackingFile>>,
+    // Host offset of the next cluster to allocate, at the end of the image.
+    next_cluster: u64,
+    // Current offset, for the `Read`, `Write` and `Seek` implementations.
+    pos: u64,
+}
+
+impl QcowFile {
+    /// Opens the qcow2 image `file`, found at `path`. Relative backing file names are
+    /// resolved from the directory of `path`.
+    pub fn new(file: File, path: &Path) -> io::Result<Self> {
+        Self::open(file, path, 0)
+    }
+
+    fn open(file: File, path: &Path, depth: usize) -> io::Result<Self> {
+        let mut header = [0u8; V3_HEADER_SIZE];
+        file.read_exact_at(&mut header[..V2_HEADER_SIZE], 0)?;
+        if be_u32(&header, 0) != QCOW_MAGIC {
+            return Err(invalid_data("Not a qcow2 image".to_string()));
+        }
+        let version = be_u32(&header, 4);
+        match version {
+            2 => (),
+            3 => file.read_exact_at(&mut header[V2_HEADER_SIZE..], V2_HEADER_SIZE as u64)?,
+            _ => {
+                return Err(invalid_data(format!(
+                    "Unsupported qcow2 version {}",
+                    version
+                )))
+            }
+        }
+
+        let backing_file_offset = be_u64(&header, 8);
+        let backing_file_size = be_u32(&header, 16);
+        let cluster_bits = be_u32(&header, 20);
+        let size = be_u64(&header, 24);
+        let crypt_method = be_u32(&header, 32);
+        let l1_size = u64::from(be_u32(&header, 36));
+        let l1_table_offset = be_u64(&header, 40);
+        let refcount_table_offset = be_u64(&header, 48);
+        let refcount_table_clusters = u64::from(be_u32(&header, 56));
+        let nb_snapshots = be_u32(&header, 60);
+
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(invalid_data(format!(
+                "Invalid qcow2 cluster bits {}",
+                cluster_bits
+            )));
+        }
+        if crypt_method != 0 {
+            return Err(invalid_data(
+                "Encrypted qcow2 images are not supported".to_string(),
+            ));
+        }
+        if nb_snapshots != 0 {
+            return Err(invalid_data(
+                "qcow2 images with internal snapshots are not supported".to_string(),
+            ));
+        }
+        if version >= 3 {
+            let incompatible_features = be_u64(&header, 72);
+            if incompatible_features != 0 {
+                return Err(invalid_data(format!(
+                    "Unsupported qcow2 incompatible features {:#x}",
+                    incompatible_features
+                )));
+            }
+            let refcount_order = be_u32(&header, 96);
+            if refcount_order != REFCOUNT_ORDER {
+                return Err(invalid_data(format!(
+                    "Unsupported qcow2 refcount order {}",
+                    refcount_order
+                )));
+            }
+        }
+
+        let cluster_size = 1u64 << cluster_bits;
+        let l2_entries = cluster_size / 8;
+        // The number of L1 entries needed to cover the disk, if that can be computed at all.
+        let l1_entries = size
+            .checked_add(cluster_size - 1)
+            .map(|end| end / cluster_size)
+            .and_then(|num_clusters| num_clusters.checked_add(l2_entries - 1))
+            .map(|end| end / l2_entries);
+        if l1_entries.map_or(true, |l1_entries| l1_size < l1_entries)
+            || l1_size * 8 > MAX_TABLE_SIZE
+            || refcount_table_clusters * cluster_size > MAX_TABLE_SIZE
+        {
+            return Err(invalid_data("Invalid qcow2 table sizes".to_string()));
+        }
+        if l1_table_offset % cluster_size != 0 || refcount_table_offset % cluster_size != 0 {
+            return Err(invalid_data("Unaligned qcow2 tables".to_string()));
+        }
+        let l1_table = read_table(&file, l1_table_offset, l1_size)?;
+        let refcount_table = read_table(
+            &file,
+            refcount_table_offset,
+            refcount_table_clusters * cluster_size / 8,
+        )?;
+
+        let backing_file = if backing_file_offset != 0 {
+            if depth >= MAX_BACKING_DEPTH {
+                return Err(invalid_data("Too many qcow2 backing files".to_string()));
+            }
+            if backing_file_size > MAX_BACKING_FILE_NAME {
+                return Err(invalid_data("Invalid qcow2 backing file name".to_string()));
+            }
+            let mut name = vec![0u8; backing_file_size as usize];
+            file.read_exact_at(&mut name, backing_file_offset)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| invalid_data("Invalid qcow2 backing file name".to_string()))?;
+            // Joining an absolute name yields the name itself.
+            let backing_path = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+            Some(Box::new(BackingFile::open(&backing_path, depth + 1)?))
+        } else {
+            None
+        };
+
+        let file_size = file.metadata()?.len();
+        Ok(QcowFile {
+            file,
+            version,
+            size,
+            cluster_size,
+            l2_entries,
+            l1_table_offset,
+            l1_table,
+            refcount_table_offset,
+            refcount_table,
+            refcount_block_entries: cluster_size / REFCOUNT_SIZE,
+            backing_file,
+            next_cluster: (file_size + cluster_size - 1) / cluster_size * cluster_size,
+            pos: 0,
+        })
+    }
+
+    /// Size of the disk, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Writes back the data and metadata written to the image to the host disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Lets the host reclaim the data clusters in the `len` bytes at `offset`, which then read
+    /// as zeros. The clusters stay allocated in the image.
+    pub fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let cluster_start = pos - pos % self.cluster_size;
+            let chunk = cmp::min(end, cluster_start + self.cluster_size) - pos;
+            if let Cluster::Data(host_offset) = self.cluster(pos)? {
+                punch_hole(&self.file, host_offset + pos - cluster_start, chunk)?;
+            }
+            pos += chunk;
+        }
+        Ok(())
+    }
+
+    /// Makes the `len` bytes at `offset` read as zeros.
+    pub fn write_zeroes(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let cluster_start = pos - pos % self.cluster_size;
+            let cluster_end = cmp::min(cluster_start + self.cluster_size, self.size);
+            let chunk = cmp::min(end, cluster_end) - pos;
+            match self.cluster(pos)? {
+                Cluster::Data(host_offset) => {
+                    zero_range(&self.file, host_offset + pos - cluster_start, chunk)?
+                }
+                Cluster::Zero(_) => (),
+                Cluster::Unallocated if self.backing_file.is_none() => (),
+                // Whole clusters over the backing file are marked as reading zeros, instead of
+                // being allocated.
+                Cluster::Unallocated
+                    if self.version >= 3 && chunk == cluster_end - cluster_start =>
+                {
+                    self.set_l2_entry(pos, ZERO_FLAG)?
+                }
+                Cluster::Unallocated => {
+                    self.write_cluster_at(&vec![0u8; chunk as usize], pos)?;
+                }
+            }
+            pos += chunk;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    // Index of the L1 entry covering the guest cluster `cluster`.
+    fn l1_index(&self, cluster: u64) -> io::Result<usize> {
+        let l1_index = cluster / self.l2_entries;
+        if l1_index >= self.l1_table.len() as u64 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        Ok(l1_index as usize)
+    }
+
+    // Looks up where the data of the guest cluster holding `offset` is.
+    fn cluster(&self, offset: u64) -> io::Result<Cluster> {
+        let cluster = offset / self.cluster_size;
+        let l2_table = self.l1_table[self.l1_index(cluster)?] & TABLE_OFFSET_MASK;
+        if l2_table == 0 {
+            return Ok(Cluster::Unallocated);
+        }
+
+        let mut entry = [0u8; 8];
+        self.file
+            .read_exact_at(&mut entry, l2_table + cluster % self.l2_entries * 8)?;
+        let entry = u64::from_be_bytes(entry);
+        if entry & COMPRESSED_FLAG != 0 {
+            return Err(invalid_data(
+                "Compressed qcow2 clusters are not supported".to_string(),
+            ));
+        }
+
+        let host_offset = entry & TABLE_OFFSET_MASK;
+        if self.version >= 3 && entry & ZERO_FLAG != 0 {
+            Ok(Cluster::Zero(
+                Some(host_offset).filter(|&offset| offset != 0),
+            ))
+        } else if host_offset == 0 {
+            Ok(Cluster::Unallocated)
+        } else {
+            Ok(Cluster::Data(host_offset))
+        }
+    }
+
+    // Sets the L2 entry of the guest cluster holding `offset`, allocating the L2 table if
+    // needed.
+    fn set_l2_entry(&mut self, offset: u64, entry: u64) -> io::Result<()> {
+        let cluster = offset / self.cluster_size;
+        let l1_index = self.l1_index(cluster)?;
+        let mut l2_table = self.l1_table[l1_index] & TABLE_OFFSET_MASK;
+        if l2_table == 0 {
+            l2_table = self.allocate_cluster()?;
+            self.file
+                .write_all_at(&vec![0u8; self.cluster_size as usize], l2_table)?;
+            let l1_entry = l2_table | COPIED_FLAG;
+            self.file.write_all_at(
+                &l1_entry.to_be_bytes(),
+                self.l1_table_offset + l1_index as u64 * 8,
+            )?;
+            self.l1_table[l1_index] = l1_entry;
+        }
+
+        self.file.write_all_at(
+            &entry.to_be_bytes(),
+            l2_table + cluster % self.l2_entries * 8,
+        )
+    }
+
+    // Takes the next host cluster at the end of the image.
+    fn allocate_cluster(&mut self) -> io::Result<u64> {
+        let host_offset = self.next_cluster;
+        self.next_cluster += self.cluster_size;
+        self.set_refcount(host_offset, 1)?;
+        Ok(host_offset)
+    }
+
+    // Sets the refcount of the host cluster at `host_offset`, allocating the refcount block
+    // if needed.
+    fn set_refcount(&mut self, host_offset: u64, refcount: u16) -> io::Result<()> {
+        let cluster = host_offset / self.cluster_size;
+        let table_index = (cluster / self.refcount_block_entries) as usize;
+        if table_index >= self.refcount_table.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The qcow2 refcount table is full",
+            ));
+        }
+
+        let mut refcount_block = self.refcount_table[table_index] & REFCOUNT_TABLE_OFFSET_MASK;
+        if refcount_block == 0 {
+            refcount_block = self.next_cluster;
+            self.next_cluster += self.cluster_size;
+            self.file
+                .write_all_at(&vec![0u8; self.cluster_size as usize], refcount_block)?;
+            self.file.write_all_at(
+                &refcount_block.to_be_bytes(),
+                self.refcount_table_offset + table_index as u64 * 8,
+            )?;
+            self.refcount_table[table_index] = refcount_block;
+            // The new refcount block counts itself when it lies in the range it covers.
+            self.set_refcount(refcount_block, 1)?;
+        }
+
+        self.file.write_all_at(
+            &refcount.to_be_bytes(),
+            refcount_block + cluster % self.refcount_block_entries * REFCOUNT_SIZE,
+        )
+    }
+
+    // Fills `buf` from `offset`, with zeros past the end of the disk.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut count = 0;
+        while count < buf.len() && offset + (count as u64) < self.size {
+            count += self.read_cluster_at(&mut buf[count..], offset + count as u64)?;
+        }
+        for byte in buf[count..].iter_mut() {
+            *byte = 0;
+        }
+        Ok(())
+    }
+
+    // Reads at most one cluster at `offset` into `buf`.
+    fn read_cluster_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let cluster_start = offset - offset % self.cluster_size;
+        let cluster_end = cmp::min(cluster_start + self.cluster_size, self.size);
+        let len = cmp::min(buf.len() as u64, cluster_end - offset) as usize;
+        let buf = &mut buf[..len];
+
+        match self.cluster(offset)? {
+            Cluster::Data(host_offset) => self
+                .file
+                .read_exact_at(buf, host_offset + offset - cluster_start)?,
+            Cluster::Unallocated if self.backing_file.is_some() => {
+                // Safe to unwrap since we just checked there is a backing file.
+                self.backing_file
+                    .as_ref()
+                    .unwrap()
+                    .read_exact_at(buf, offset)?
+            }
+            Cluster::Unallocated | Cluster::Zero(_) => {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    // Writes at most one cluster from `buf` at `offset`, allocating the cluster first if it
+    // has no data yet.
+    fn write_cluster_at(&mut self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let cluster_start = offset - offset % self.cluster_size;
+        let cluster_end = cmp::min(cluster_start + self.cluster_size, self.size);
+        let len = cmp::min(buf.len() as u64, cluster_end - offset) as usize;
+
+        let cluster = self.cluster(offset)?;
+        if let Cluster::Data(host_offset) = cluster {
+            self.file
+                .write_all_at(&buf[..len], host_offset + offset - cluster_start)?;
+            return Ok(len);
+        }
+
+        // Host clusters are always whole, even the one of a partial last guest cluster.
+        let mut data = vec![0u8; self.cluster_size as usize];
+        let guest_len = (cluster_end - cluster_start) as usize;
+        // No need to read the cluster if the write covers it entirely.
+        if let (Cluster::Unallocated, Some(backing_file)) = (&cluster, &self.backing_file) {
+            if len < guest_len {
+                backing_file.read_exact_at(&mut data[..guest_len], cluster_start)?;
+            }
+        }
+        let start = (offset - cluster_start) as usize;
+        data[start..start + len].copy_from_slice(&buf[..len]);
+
+        let host_offset = match cluster {
+            Cluster::Zero(Some(host_offset)) => host_offset,
+            _ => self.allocate_cluster()?,
+        };
+        // The data is written before the L2 entry points to it.
+        self.file.write_all_at(&data, host_offset)?;
+        self.set_l2_entry(offset, host_offset | COPIED_FLAG)?;
+        Ok(len)
+    }
+}
+
+impl Read for QcowFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() && self.pos < self.size {
+            let len = self.read_cluster_at(&mut buf[count..], self.pos)?;
+            count += len;
+            self.pos += len as u64;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for QcowFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() && self.pos < self.size {
+            let len = self.write_cluster_at(&buf[count..], self.pos)?;
+            count += len;
+            self.pos += len as u64;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for QcowFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => add_offset(self.pos, offset),
+            SeekFrom::End(offset) => add_offset(self.size, offset),
+        };
+        self.pos = new_pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+
+    use utils::tempfile::TempFile;
+
+    const CLUSTER_BITS: u32 = 9;
+    const CLUSTER_SIZE: u64 = 1 << CLUSTER_BITS;
+    const DISK_SIZE: u64 = 1024 * 1024 + 100;
+
+    fn open_qcow(path: &Path, read_only: bool) -> io::Result<QcowFile> {
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        QcowFile::new(file, path)
+    }
+
+    // Writes an empty image with the header in the first cluster, followed by one cluster of
+    // refcount table, one refcount block and the L1 table.
+    fn create_image(file: &File, version: u32, backing_file: Option<&str>) {
+        let l1_size =
+            (DISK_SIZE + CLUSTER_SIZE * CLUSTER_SIZE / 8 - 1) / (CLUSTER_SIZE * CLUSTER_SIZE / 8);
+        let mut header = vec![0u8; CLUSTER_SIZE as usize];
+        let header_size = if version == 2 {
+            V2_HEADER_SIZE
+        } else {
+            V3_HEADER_SIZE
+        };
+        header[0..4].copy_from_slice(&QCOW_MAGIC.to_be_bytes());
+        header[4..8].copy_from_slice(&version.to_be_bytes());
+        if let Some(name) = backing_file {
+            header[8..16].copy_from_slice(&(header_size as u64).to_be_bytes());
+            header[16..20].copy_from_slice(&(name.len() as u32).to_be_bytes());
+            header[header_size..header_size + name.len()].copy_from_slice(name.as_bytes());
+        }
+        header[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        header[24..32].copy_from_slice(&DISK_SIZE.to_be_bytes());
+        header[36..40].copy_from_slice(&(l1_size as u32).to_be_bytes());
+        header[40..48].copy_from_slice(&(3 * CLUSTER_SIZE).to_be_bytes());
+        header[48..56].copy_from_slice(&CLUSTER_SIZE.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        if version == 3 {
+            header[96..100].copy_from_slice(&REFCOUNT_ORDER.to_be_bytes());
+            header[100..104].copy_from_slice(&(V3_HEADER_SIZE as u32).to_be_bytes());
+        }
+        file.write_all_at(&header, 0).unwrap();
+        file.write_all_at(&(2 * CLUSTER_SIZE).to_be_bytes(), CLUSTER_SIZE)
+            .unwrap();
+        for cluster in 0..4 {
+            file.write_all_at(&1u16.to_be_bytes(), 2 * CLUSTER_SIZE + cluster * 2)
+                .unwrap();
+        }
+        file.set_len(4 * CLUSTER_SIZE).unwrap();
+    }
+
+    fn new_image(version: u32, backing_file: Option<&str>) -> (TempFile, QcowFile) {
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), version, backing_file);
+        let qcow = open_qcow(image.as_path(), false).unwrap();
+        (image, qcow)
+    }
+
+    fn read_all(disk: &mut QcowFile) -> Vec<u8> {
+        let mut data = vec![0u8; DISK_SIZE as usize];
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        data
+    }
+
+    // Checks that every cluster of the image is counted exactly once.
+    fn check_refcounts(image: &TempFile) {
+        let file = image.as_file();
+        let len = file.metadata().unwrap().len();
+        assert_eq!(len % CLUSTER_SIZE, 0);
+        let refcount_table = read_table(file, CLUSTER_SIZE, CLUSTER_SIZE / 8).unwrap();
+        for cluster in 0..len / CLUSTER_SIZE {
+            let block = refcount_table[(cluster / (CLUSTER_SIZE / 2)) as usize];
+            assert_ne!(block, 0);
+            let mut refcount = [0u8; 2];
+            file.read_exact_at(&mut refcount, block + cluster % (CLUSTER_SIZE / 2) * 2)
+                .unwrap();
+            assert_eq!(u16::from_be_bytes(refcount), 1, "cluster {}", cluster);
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        for &version in &[2, 3] {
+            let (image, mut disk) = new_image(version, None);
+            assert_eq!(disk.size(), DISK_SIZE);
+            let mut expected = vec![0u8; DISK_SIZE as usize];
+            assert_eq!(read_all(&mut disk), expected);
+
+            // A write spanning three clusters.
+            let offset = 3 * CLUSTER_SIZE - 7;
+            disk.seek(SeekFrom::Start(offset)).unwrap();
+            disk.write_all(&[0xaa; 2 * CLUSTER_SIZE as usize]).unwrap();
+            expected[offset as usize..(offset + 2 * CLUSTER_SIZE) as usize]
+                .copy_from_slice(&[0xaa; 2 * CLUSTER_SIZE as usize]);
+
+            // Enough writes to need more L2 tables and refcount blocks.
+            for i in 0..400 {
+                let offset = i * 2 * CLUSTER_SIZE + 11;
+                disk.seek(SeekFrom::Start(offset)).unwrap();
+                disk.write_all(&[i as u8; 3]).unwrap();
+                expected[offset as usize..offset as usize + 3].copy_from_slice(&[i as u8; 3]);
+            }
+
+            // A write to the last, partial cluster.
+            disk.seek(SeekFrom::End(-10)).unwrap();
+            disk.write_all(&[0xbb; 10]).unwrap();
+            let len = expected.len();
+            expected[len - 10..].copy_from_slice(&[0xbb; 10]);
+
+            // Writes and reads stop at the end of the disk.
+            assert_eq!(disk.write(&[0xcc; 10]).unwrap(), 0);
+            assert_eq!(disk.read(&mut [0u8; 10]).unwrap(), 0);
+
+            assert_eq!(read_all(&mut disk), expected);
+            check_refcounts(&image);
+
+            let mut disk = open_qcow(image.as_path(), true).unwrap();
+            assert_eq!(read_all(&mut disk), expected);
+        }
+    }
+
+    #[test]
+    fn test_backing_file() {
+        let backing = TempFile::new().unwrap();
+        // The backing file is shorter than the disk.
+        let backing_data: Vec<u8> = (0..DISK_SIZE / 2).map(|i| (i % 251) as u8).collect();
+        backing.as_file().write_all_at(&backing_data, 0).unwrap();
+        let backing_path = backing.as_path().to_str().unwrap().to_string();
+
+        let (image, mut disk) = new_image(3, Some(&backing_path));
+        let mut expected = backing_data.clone();
+        expected.resize(DISK_SIZE as usize, 0);
+        assert_eq!(read_all(&mut disk), expected);
+
+        // A partial write copies the rest of the cluster from the backing file.
+        disk.seek(SeekFrom::Start(CLUSTER_SIZE + 5)).unwrap();
+        disk.write_all(&[0xaa; 5]).unwrap();
+        expected[CLUSTER_SIZE as usize + 5..CLUSTER_SIZE as usize + 10].copy_from_slice(&[0xaa; 5]);
+        assert_eq!(read_all(&mut disk), expected);
+        check_refcounts(&image);
+
+        // The backing file is left untouched.
+        let mut data = vec![0u8; backing_data.len()];
+        backing.as_file().read_exact_at(&mut data, 0).unwrap();
+        assert_eq!(data, backing_data);
+
+        // A qcow2 image can back another one, by a name relative to its directory.
+        let top = TempFile::new().unwrap();
+        let name = image.as_path().file_name().unwrap().to_str().unwrap();
+        create_image(top.as_file(), 3, Some(name));
+        let mut top_disk = open_qcow(top.as_path(), false).unwrap();
+        assert_eq!(read_all(&mut top_disk), expected);
+
+        // A missing backing file fails the opening of the image.
+        let image = TempFile::new().unwrap();
+        create_image(image.as_file(), 3, Some("/nonexistent/backing.img"));
+        assert!(open_qcow(image.as_path(), false).is_err());
+    }
+
+    #[test]
+    fn test_zero_clusters() {
+        let backing = TempFile::new().unwrap();
+        backing
+            .as_file()
+            .write_all_at(&vec![0xaa; DISK_SIZE as usize], 0)
+            .unwrap();
+        let backing_path = backing.as_path().to_str().unwrap().to_string();
+        let (image, mut disk) = new_image(3, Some(&backing_path));
+
+        // Give the first cluster an L2 table, then mark the second one as zeros.
+        disk.write_all(&[0xbb; 1]).unwrap();
+        let l2_table = disk.l1_table[0] & TABLE_OFFSET_MASK;
+        image
+            .as_file()
+            .write_all_at(&ZERO_FLAG.to_be_bytes(), l2_table + 8)
+            .unwrap();
+
+        let mut data = vec![0u8; 2 * CLUSTER_SIZE as usize];
+        disk.seek(SeekFrom::Start(CLUSTER_SIZE)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        assert!(data[..CLUSTER_SIZE as usize].iter().all(|&byte| byte == 0));
+        assert!(data[CLUSTER_SIZE as usize..]
+            .iter()
+            .all(|&byte| byte == 0xaa));
+
+        // A partial write to a zero cluster doesn't copy it from the backing file.
+        disk.seek(SeekFrom::Start(CLUSTER_SIZE)).unwrap();
+        disk.write_all(&[0xcc; 1]).unwrap();
+        disk.seek(SeekFrom::Start(CLUSTER_SIZE)).unwrap();
+        disk.read_exact(&mut data[..CLUSTER_SIZE as usize]).unwrap();
+        assert_eq!(data[0], 0xcc);
+        assert!(data[1..CLUSTER_SIZE as usize].iter().all(|&byte| byte == 0));
+        check_refcounts(&image);
+    }
+
+    #[test]
+    fn test_discard_write_zeroes() {
+        let backing = TempFile::new().unwrap();
+        backing
+            .as_file()
+            .write_all_at(&vec![0xaa; DISK_SIZE as usize], 0)
+            .unwrap();
+        let backing_path = backing.as_path().to_str().unwrap().to_string();
+
+        for &version in &[2, 3] {
+            let (image, mut disk) = new_image(version, Some(&backing_path));
+            let mut expected = vec![0xaa; DISK_SIZE as usize];
+            disk.write_all(&[0xbb; 4 * CLUSTER_SIZE as usize]).unwrap();
+            for byte in expected[..4 * CLUSTER_SIZE as usize].iter_mut() {
+                *byte = 0xbb;
+            }
+
+            // Zeroing a range zeroes the data clusters in place, and hides the backing file
+            // from the others.
+            disk.write_zeroes(3 * CLUSTER_SIZE + 10, 3 * CLUSTER_SIZE)
+                .unwrap();
+            for byte in expected[(3 * CLUSTER_SIZE + 10) as usize..(6 * CLUSTER_SIZE + 10) as usize]
+                .iter_mut()
+            {
+                *byte = 0;
+            }
+            assert_eq!(read_all(&mut disk), expected);
+            match disk.cluster(4 * CLUSTER_SIZE).unwrap() {
+                Cluster::Zero(None) => assert_eq!(version, 3),
+                Cluster::Data(_) => assert_eq!(version, 2),
+                _ => panic!("Unexpected cluster."),
+            }
+            check_refcounts(&image);
+
+            // Discarded data clusters read as zeros, the others are left alone.
+            if disk.discard(CLUSTER_SIZE, 2 * CLUSTER_SIZE).is_ok() {
+                for byte in expected[CLUSTER_SIZE as usize..3 * CLUSTER_SIZE as usize].iter_mut() {
+                    *byte = 0;
+                }
+                assert_eq!(read_all(&mut disk), expected);
+            }
+            disk.discard(10 * CLUSTER_SIZE, CLUSTER_SIZE).unwrap();
+            assert_eq!(read_all(&mut disk), expected);
+            check_refcounts(&image);
+
+            assert!(disk.write_zeroes(DISK_SIZE - 1, 2).is_err());
+            assert!(disk.discard(u64::max_value(), 2).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unsupported_images() {
+        let image = TempFile::new().unwrap();
+        assert!(!is_qcow(image.as_file()).unwrap());
+        image.as_file().write_all_at(&[0u8; 512], 0).unwrap();
+        assert!(!is_qcow(image.as_file()).unwrap());
+        assert!(open_qcow(image.as_path(), false).is_err());
+
+        // Each field is checked on its own.
+        let fields: &[(u64, &[u8])] = &[
+            // Version 4.
+            (4, &[0, 0, 0, 4]),
+            // Clusters of 256 bytes.
+            (20, &[0, 0, 0, 8]),
+            // AES encryption.
+            (32, &[0, 0, 0, 1]),
+            // An L1 table too short for the disk.
+            (36, &[0, 0, 0, 1]),
+            // A disk too large for any L1 table.
+            (24, &[0xff; 8]),
+            // One internal snapshot.
+            (60, &[0, 0, 0, 1]),
+            // The dirty bit.
+            (72, &[0, 0, 0, 0, 0, 0, 0, 1]),
+            // 32 bit refcounts.
+            (96, &[0, 0, 0, 5]),
+        ];
+        for (offset, value) in fields {
+            create_image(image.as_file(), 3, None);
+            assert!(is_qcow(image.as_file()).unwrap());
+            assert!(open_qcow(image.as_path(), false).is_ok());
+            image.as_file().write_all_at(value, *offset).unwrap();
+            assert!(open_qcow(image.as_path(), false).is_err());
+        }
+
+        // Compressed clusters can't be read.
+        let (image, mut disk) = new_image(3, None);
+        disk.write_all(&[0xaa; 1]).unwrap();
+        let l2_table = disk.l1_table[0] & TABLE_OFFSET_MASK;
+        image
+            .as_file()
+            .write_all_at(&(COMPRESSED_FLAG | 0x1000).to_be_bytes(), l2_table + 8)
+            .unwrap();
+        disk.seek(SeekFrom::Start(CLUSTER_SIZE)).unwrap();
+        assert!(disk.read(&mut [0u8; 1]).is_err());
+
+        // Clusters past the L1 table are rejected instead of being looked up.
+        let (_image, mut disk) = new_image(3, None);
+        disk.l1_table.truncate(1);
+        let l1_coverage = CLUSTER_SIZE * CLUSTER_SIZE / 8;
+        disk.seek(SeekFrom::Start(l1_coverage)).unwrap();
+        assert!(disk.read(&mut [0u8; 1]).is_err());
+        disk.seek(SeekFrom::Start(l1_coverage)).unwrap();
+        assert!(disk.write(&[0xaa; 1]).is_err());
+        assert!(disk.write_zeroes(l1_coverage, CLUSTER_SIZE).is_err());
+    }
+}
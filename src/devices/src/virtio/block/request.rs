@@ -6,25 +6,32 @@
 // found in the THIRD-PARTY file.
 
 use std::convert::From;
-use std::io::{self, Seek, SeekFrom, Write};
+use std::io::{self, Seek, SeekFrom};
 use std::mem;
 use std::result;
 
 use logger::{IncMetric, METRICS};
 use virtio_gen::virtio_blk::*;
-use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap};
+use vm_memory::{
+    Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
+};
 
 use super::super::DescriptorChain;
 use super::device::DiskProperties;
-use super::{Error, SECTOR_SHIFT, SECTOR_SIZE};
+use super::{
+    Error, MAX_DISCARD_SECTORS, MAX_DISCARD_SEGMENTS, SECTOR_SHIFT, SECTOR_SIZE,
+    VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_WRITE_ZEROES, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
+};
 
 #[derive(Debug)]
 pub enum ExecuteError {
     BadRequest(Error),
+    Discard(io::Error),
     Flush(io::Error),
     Read(GuestMemoryError),
     Seek(io::Error),
     Write(GuestMemoryError),
+    WriteZeroes(io::Error),
     Unsupported(u32),
 }
 
@@ -32,10 +39,12 @@ impl ExecuteError {
     pub fn status(&self) -> u32 {
         match *self {
             ExecuteError::BadRequest(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Discard(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Flush(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Read(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Seek(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Write(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::WriteZeroes(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Unsupported(_) => VIRTIO_BLK_S_UNSUPP,
         }
     }
@@ -47,6 +56,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceID,
+    Discard,
+    WriteZeroes,
     Unsupported(u32),
 }
 
@@ -57,6 +68,8 @@ impl From<u32> for RequestType {
             VIRTIO_BLK_T_OUT => RequestType::Out,
             VIRTIO_BLK_T_FLUSH => RequestType::Flush,
             VIRTIO_BLK_T_GET_ID => RequestType::GetDeviceID,
+            VIRTIO_BLK_T_DISCARD => RequestType::Discard,
+            VIRTIO_BLK_T_WRITE_ZEROES => RequestType::WriteZeroes,
             t => RequestType::Unsupported(t),
         }
     }
@@ -112,6 +125,29 @@ impl RequestHeader {
     }
 }
 
+/// A range of sectors to discard or zero, as found in the data of the discard and write zeroes
+/// requests.
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct DiscardSegment {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+// Safe because DiscardSegment only contains plain data.
+unsafe impl ByteValued for DiscardSegment {}
+
+impl DiscardSegment {
+    pub fn new(sector: u64, num_sectors: u32, flags: u32) -> DiscardSegment {
+        DiscardSegment {
+            sector,
+            num_sectors,
+            flags,
+        }
+    }
+}
+
 impl Request {
     pub fn parse(
         avail_desc: &DescriptorChain,
@@ -149,8 +185,13 @@ impl Request {
                 .next_descriptor()
                 .ok_or(Error::DescriptorChainTooShort)?;
 
-            if data_desc.is_write_only() && req.request_type == RequestType::Out {
-                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            match req.request_type {
+                RequestType::Out | RequestType::Discard | RequestType::WriteZeroes
+                    if data_desc.is_write_only() =>
+                {
+                    return Err(Error::UnexpectedWriteOnlyDescriptor);
+                }
+                _ => (),
             }
             if !data_desc.is_write_only() && req.request_type == RequestType::In {
                 return Err(Error::UnexpectedReadOnlyDescriptor);
@@ -197,6 +238,10 @@ impl Request {
         disk: &mut DiskProperties,
         mem: &GuestMemoryMmap,
     ) -> result::Result<u32, ExecuteError> {
+        if let RequestType::Discard | RequestType::WriteZeroes = self.request_type {
+            return self.execute_segments(disk, mem);
+        }
+
         let mut top: u64 = u64::from(self.data_len) / SECTOR_SIZE;
         if u64::from(self.data_len) % SECTOR_SIZE != 0 {
             top += 1;
@@ -224,10 +269,14 @@ impl Request {
             RequestType::Out => {
                 mem.write_to(self.data_addr, diskfile, self.data_len as usize)
                     .map_err(ExecuteError::Write)?;
+                // Without a write cache, the writes only complete once on the host disk.
+                if disk.writethrough() {
+                    disk.flush().map_err(ExecuteError::Flush)?;
+                }
                 METRICS.block.write_bytes.add(self.data_len as usize);
                 METRICS.block.write_count.inc();
             }
-            RequestType::Flush => match diskfile.flush() {
+            RequestType::Flush => match disk.flush() {
                 Ok(_) => {
                     METRICS.block.flush_count.inc();
                     return Ok(0);
@@ -242,10 +291,74 @@ impl Request {
                 mem.write_slice(disk_id, self.data_addr)
                     .map_err(ExecuteError::Write)?;
             }
+            RequestType::Discard | RequestType::WriteZeroes => unreachable!(),
             RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
         };
         Ok(0)
     }
+
+    // Discard and write zeroes requests carry a list of segments instead of data.
+    fn execute_segments(
+        &self,
+        disk: &mut DiskProperties,
+        mem: &GuestMemoryMmap,
+    ) -> result::Result<u32, ExecuteError> {
+        let (request_type, allowed_flags) = if self.request_type == RequestType::Discard {
+            (VIRTIO_BLK_T_DISCARD, 0)
+        } else {
+            (
+                VIRTIO_BLK_T_WRITE_ZEROES,
+                VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
+            )
+        };
+        let segment_size = mem::size_of::<DiscardSegment>() as u32;
+        if self.data_len == 0
+            || self.data_len % segment_size != 0
+            || self.data_len / segment_size > MAX_DISCARD_SEGMENTS
+        {
+            return Err(ExecuteError::BadRequest(Error::InvalidDiscardSegments));
+        }
+
+        for i in 0..self.data_len / segment_size {
+            // The data descriptor was checked against the guest memory when parsing.
+            let segment: DiscardSegment = mem
+                .read_obj(self.data_addr.unchecked_add(u64::from(i * segment_size)))
+                .map_err(|e| ExecuteError::BadRequest(Error::GuestMemory(e)))?;
+            if segment.flags & !allowed_flags != 0 {
+                return Err(ExecuteError::Unsupported(request_type));
+            }
+            let top = segment
+                .sector
+                .checked_add(u64::from(segment.num_sectors))
+                .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+            if segment.num_sectors > MAX_DISCARD_SECTORS || top > disk.nsectors() {
+                return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+            }
+
+            let offset = segment.sector << SECTOR_SHIFT;
+            let len = u64::from(segment.num_sectors) << SECTOR_SHIFT;
+            if self.request_type == RequestType::Discard {
+                disk.file_mut()
+                    .discard(offset, len)
+                    .map_err(ExecuteError::Discard)?;
+            } else {
+                let unmap = segment.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0;
+                disk.file_mut()
+                    .write_zeroes(offset, len, unmap)
+                    .map_err(ExecuteError::WriteZeroes)?;
+            }
+        }
+
+        if disk.writethrough() {
+            disk.flush().map_err(ExecuteError::Flush)?;
+        }
+        if self.request_type == RequestType::Discard {
+            METRICS.block.discard_count.inc();
+        } else {
+            METRICS.block.write_zeroes_count.inc();
+        }
+        Ok(0)
+    }
 }
 
 #[cfg(test)]
@@ -268,6 +381,8 @@ mod tests {
             VIRTIO_BLK_T_OUT,
             VIRTIO_BLK_T_FLUSH,
             VIRTIO_BLK_T_GET_ID,
+            VIRTIO_BLK_T_DISCARD,
+            VIRTIO_BLK_T_WRITE_ZEROES,
         ];
 
         for request_type in supported_request_types {
@@ -294,6 +409,14 @@ mod tests {
             RequestType::from(VIRTIO_BLK_T_GET_ID),
             RequestType::GetDeviceID
         );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_DISCARD),
+            RequestType::Discard
+        );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_WRITE_ZEROES),
+            RequestType::WriteZeroes
+        );
         assert_eq!(RequestType::from(42), RequestType::Unsupported(42));
     }
 
@@ -303,6 +426,10 @@ mod tests {
             ExecuteError::BadRequest(Error::InvalidOffset).status(),
             VIRTIO_BLK_S_IOERR
         );
+        assert_eq!(
+            ExecuteError::Discard(io::Error::from_raw_os_error(42)).status(),
+            VIRTIO_BLK_S_IOERR
+        );
         assert_eq!(
             ExecuteError::Flush(io::Error::from_raw_os_error(42)).status(),
             VIRTIO_BLK_S_IOERR
@@ -319,6 +446,10 @@ mod tests {
             ExecuteError::Write(GuestMemoryError::InvalidBackendAddress).status(),
             VIRTIO_BLK_S_IOERR
         );
+        assert_eq!(
+            ExecuteError::WriteZeroes(io::Error::from_raw_os_error(42)).status(),
+            VIRTIO_BLK_S_IOERR
+        );
         assert_eq!(ExecuteError::Unsupported(42).status(), VIRTIO_BLK_S_UNSUPP);
     }
 
@@ -386,6 +517,20 @@ mod tests {
             });
         }
 
+        {
+            let mut q = vq.create_queue();
+            // Write only data for DISCARD and WRITE_ZEROES.
+            for &request_type in &[VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_WRITE_ZEROES] {
+                m.write_obj::<u32>(request_type, GuestAddress(0x1000))
+                    .unwrap();
+                assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                    Err(Error::UnexpectedWriteOnlyDescriptor) => true,
+                    _ => false,
+                });
+                q.undo_pop();
+            }
+        }
+
         {
             let mut q = vq.create_queue();
             // Read only data for GetDeviceID.
@@ -0,0 +1,145 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deallocates or zeroes ranges of the host files backing block devices.
+
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+// Largest chunk of zeros written at once, when the file system can't zero a range by itself.
+const ZEROES_CHUNK_SIZE: u64 = 64 * 1024;
+
+fn fallocate(file: &File, mode: libc::c_int, offset: u64, len: u64) -> io::Result<()> {
+    // Safe because the file descriptor is valid, and we check the return value.
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            mode,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Checks whether `err` tells that the file system can't deallocate or zero ranges.
+pub fn is_unsupported(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EOPNOTSUPP)
+}
+
+/// Deallocates the `len` bytes of `file` at `offset`, which then read as zeros. The size of
+/// the file is left unchanged.
+pub fn punch_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    fallocate(
+        file,
+        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+        offset,
+        len,
+    )
+}
+
+/// Has the file system zero the `len` bytes of `file` at `offset`, keeping them allocated.
+pub fn zero_file_range(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    fallocate(
+        file,
+        libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE,
+        offset,
+        len,
+    )
+}
+
+/// Zeroes the `len` bytes of `file` at `offset`, keeping them allocated. Falls back to writing
+/// zeros when the file system can't zero the range by itself.
+pub fn zero_range(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    match zero_file_range(file, offset, len) {
+        Err(ref e) if is_unsupported(e) => write_zeroes_at(file, offset, len),
+        result => result,
+    }
+}
+
+/// Writes `len` zero bytes to `file` at `offset`.
+pub fn write_zeroes_at(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let zeroes = vec![0u8; cmp::min(len, ZEROES_CHUNK_SIZE) as usize];
+    let mut count = 0;
+    while count < len {
+        let chunk = cmp::min(len - count, ZEROES_CHUNK_SIZE) as usize;
+        file.write_all_at(&zeroes[..chunk], offset + count)?;
+        count += chunk as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::linux::fs::MetadataExt;
+
+    use utils::tempfile::TempFile;
+
+    const FILE_SIZE: u64 = 1024 * 1024;
+
+    fn data_file() -> TempFile {
+        let file = TempFile::new().unwrap();
+        file.as_file()
+            .write_all_at(&vec![0xaa; FILE_SIZE as usize], 0)
+            .unwrap();
+        file.as_file().sync_all().unwrap();
+        file
+    }
+
+    fn read_at(file: &File, offset: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        file.read_exact_at(&mut data, offset).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_punch_hole() {
+        let file = data_file();
+        let blocks = file.as_file().metadata().unwrap().st_blocks();
+
+        match punch_hole(file.as_file(), 4096, 2 * 65536) {
+            Ok(()) => {
+                // The hole frees host blocks, and the file keeps its size.
+                let metadata = file.as_file().metadata().unwrap();
+                assert!(metadata.st_blocks() < blocks);
+                assert_eq!(metadata.len(), FILE_SIZE);
+                assert_eq!(
+                    read_at(file.as_file(), 4096, 2 * 65536),
+                    vec![0u8; 2 * 65536]
+                );
+                assert_eq!(read_at(file.as_file(), 0, 4096), vec![0xaa; 4096]);
+            }
+            // Not all the file systems the tests run on support holes.
+            Err(ref e) if is_unsupported(e) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_zero_range() {
+        let file = data_file();
+        zero_range(file.as_file(), 100, 5000).unwrap();
+        assert_eq!(file.as_file().metadata().unwrap().len(), FILE_SIZE);
+        assert_eq!(read_at(file.as_file(), 0, 100), vec![0xaa; 100]);
+        assert_eq!(read_at(file.as_file(), 100, 5000), vec![0u8; 5000]);
+        assert_eq!(read_at(file.as_file(), 5100, 100), vec![0xaa; 100]);
+
+        write_zeroes_at(file.as_file(), 10, 3 * ZEROES_CHUNK_SIZE + 7).unwrap();
+        assert_eq!(
+            read_at(file.as_file(), 10, 3 * ZEROES_CHUNK_SIZE as usize + 7),
+            vec![0u8; 3 * ZEROES_CHUNK_SIZE as usize + 7]
+        );
+        assert_eq!(
+            read_at(file.as_file(), 3 * ZEROES_CHUNK_SIZE + 17, 10),
+            vec![0xaa; 10]
+        );
+    }
+}
@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use crate::virtio::{Block, Queue};
+use crate::virtio::{Block, CacheType, ImageFormat, Queue};
 use polly::event_manager::{EventManager, Subscriber};
 use rate_limiter::RateLimiter;
 use utils::epoll::{EpollEvent, EventSet};
@@ -18,14 +19,46 @@ pub fn default_block() -> Block {
     default_block_with_path(f.as_path().to_str().unwrap().to_string())
 }
 
+/// Create a default Block instance with `num_queues` queues to be used in tests.
+pub fn default_block_with_queues(num_queues: usize, io_threads: bool) -> Block {
+    // Create backing file.
+    let f = TempFile::new().unwrap();
+    f.as_file().set_len(0x1000).unwrap();
+
+    block_with_queues(
+        f.as_path().to_str().unwrap().to_string(),
+        num_queues,
+        io_threads,
+    )
+}
+
 /// Create a default Block instance using file at the specified path to be used in tests.
 pub fn default_block_with_path(path: String) -> Block {
+    block_with_queues(path, 1, false)
+}
+
+/// Create a default Block instance with `num_queues` queues using file at the specified path to
+/// be used in tests.
+pub fn block_with_queues(path: String, num_queues: usize, io_threads: bool) -> Block {
     // Rate limiting is enabled but with a high operation rate (10 million ops/s).
     let rate_limiter = RateLimiter::new(0, 0, 0, 100_000, 0, 10).unwrap();
 
     let id = "test".to_string();
     // The default block device is read-write and non-root.
-    Block::new(id, None, path, false, false, rate_limiter).unwrap()
+    Block::new(
+        id,
+        None,
+        path,
+        ImageFormat::Raw,
+        CacheType::Unsafe,
+        None,
+        false,
+        false,
+        num_queues,
+        io_threads,
+        rate_limiter,
+    )
+    .unwrap()
 }
 
 pub fn invoke_handler_for_queue_event(b: &mut Block) {
@@ -45,9 +78,9 @@ pub fn set_queue(blk: &mut Block, idx: usize, q: Queue) {
 }
 
 pub fn set_rate_limiter(blk: &mut Block, rl: RateLimiter) {
-    blk.rate_limiter = rl;
+    blk.rate_limiter = Arc::new(Mutex::new(rl));
 }
 
-pub fn rate_limiter(blk: &mut Block) -> &RateLimiter {
-    &blk.rate_limiter
+pub fn rate_limiter(blk: &mut Block) -> MutexGuard<RateLimiter> {
+    blk.rate_limiter.lock().unwrap()
 }
@@ -0,0 +1,251 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dedicated I/O threads servicing the queues of a block device.
+//!
+//! Each queue gets its own thread, which waits for the queue event instead of the VMM event
+//! loop. The thread pops the requests of its queue under the device lock, lets them through the
+//! rate limiter and executes them on its own handle of the disk image without holding the device
+//! lock, and then puts them in the used ring. The queues of a device are thus serviced in parallel. Overlays and qcow2 images keep
+//! their metadata in memory and can only be opened once, so their requests are still executed
+//! under the device lock.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use logger::error;
+use rate_limiter::RateLimiter;
+use seccomp::{BpfProgram, BpfProgramRef, SeccompFilter};
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::time::{get_time_us, ClockType};
+
+use super::device::{admit_requests, execute_request, Block, DiskProperties};
+use crate::virtio::DeviceState;
+
+// The epoll data of the events an I/O thread waits for.
+const QUEUE_EVENT: u64 = 0;
+const EXIT_EVENT: u64 = 1;
+
+// Blocks until the queue event or the exit event registered with `epoll` is readable, and
+// returns whether the thread has to exit. The wait goes through epoll, which the VMM seccomp
+// filter allows.
+fn wait_queue_event(epoll: &Epoll) -> io::Result<bool> {
+    let mut events = [EpollEvent::default(); 2];
+    loop {
+        match epoll.wait(events.len(), -1, &mut events) {
+            Ok(n) => return Ok(events[..n].iter().any(|event| event.data() == EXIT_EVENT)),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Processes the requests of queue `queue_index` after its queue event fired at `kick_us`,
+// executing them on `disk` without holding the device lock. The device lock is only held to pop the descriptor
+// chains and to put the requests in the used ring; the chains are parsed and go through the rate
+// limiter outside of it. Returns `None` if the device lock is poisoned.
+fn process_queue_event(
+    block: &Mutex<Block>,
+    disk: &Mutex<DiskProperties>,
+    rate_limiter: &Mutex<RateLimiter>,
+    queue_index: usize,
+    kick_us: u64,
+) -> Option<()> {
+    let mem;
+    let (heads, arrival_us, metrics) = {
+        let mut locked_block = block.lock().ok()?;
+        if !locked_block.consume_queue_event(queue_index) {
+            return Some(());
+        }
+        mem = match locked_block.device_state {
+            DeviceState::Activated(ref guest_mem) => guest_mem.clone(),
+            // The queue event was consumed only if the device is activated.
+            DeviceState::Inactive => return Some(()),
+        };
+        let arrival_us = locked_block.arrival_us(queue_index, kick_us);
+        let heads = locked_block.pop_heads(queue_index, &mem);
+        (heads, arrival_us, locked_block.metrics.clone())
+    };
+
+    let requests = admit_requests(&heads, &mem, &mut *rate_limiter.lock().ok()?);
+    let held_back = heads.len() - requests.len();
+
+    let mut completed = Vec::with_capacity(requests.len());
+    {
+        let mut disk = disk.lock().ok()?;
+        for (head_index, request) in requests {
+            let len = execute_request(request.as_ref(), &mut disk, &mem, &metrics, arrival_us);
+            completed.push((head_index, len));
+        }
+    }
+
+    let mut locked_block = block.lock().ok()?;
+    locked_block.return_heads(queue_index, held_back, arrival_us);
+    if locked_block.complete_requests(queue_index, &completed) {
+        let _ = locked_block.signal_used_queue();
+    }
+    Some(())
+}
+
+fn run_io_thread(
+    block: Arc<Mutex<Block>>,
+    disk: Option<Arc<Mutex<DiskProperties>>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    epoll: Epoll,
+    queue_index: usize,
+    seccomp_filter: BpfProgram,
+) {
+    // Load the seccomp filters for this I/O thread.
+    // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
+    // altogether is the desired behaviour.
+    if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+        panic!(
+            "Failed to set the requested seccomp filters on block I/O thread {}: Error: {}",
+            queue_index, e
+        );
+    }
+
+    loop {
+        match wait_queue_event(&epoll) {
+            Ok(false) => (),
+            Ok(true) => return,
+            Err(e) => {
+                error!("Failed to wait for block queue event: {:?}", e);
+                return;
+            }
+        }
+        // The requests arrive when the guest kicks the queue, not when the thread gets the
+        // device lock.
+        let kick_us = get_time_us(ClockType::Monotonic);
+        let processed = match disk {
+            Some(ref disk) => {
+                process_queue_event(&block, disk, &rate_limiter, queue_index, kick_us)
+            }
+            None => block
+                .lock()
+                .ok()
+                .map(|mut locked_block| locked_block.process_queue_kick(queue_index, kick_us)),
+        };
+        // Another thread panicked while holding a lock. Exit instead of panicking in turn.
+        if processed.is_none() {
+            error!("Block I/O thread {} found a poisoned lock.", queue_index);
+            return;
+        }
+    }
+}
+
+/// Spawns one I/O thread per queue of `block`, which processes the requests of that queue until
+/// `Block::stop_io_threads` is called. The threads load `seccomp_filter` before anything else.
+pub fn spawn_io_threads(
+    block: &Arc<Mutex<Block>>,
+    seccomp_filter: BpfProgramRef,
+) -> io::Result<()> {
+    let mut locked_block = block.lock().expect("Poisoned lock");
+    let mut queue_disks = locked_block.open_queue_disks()?.into_iter();
+    let mut handles = Vec::with_capacity(locked_block.queue_evts.len());
+    for (queue_index, queue_evt) in locked_block.queue_evts.iter().enumerate() {
+        // The epoll fd is created here, since the thread can't create it under the seccomp
+        // filter.
+        let epoll = Epoll::new()?;
+        epoll.ctl(
+            ControlOperation::Add,
+            queue_evt.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, QUEUE_EVENT),
+        )?;
+        epoll.ctl(
+            ControlOperation::Add,
+            locked_block.io_thread_exit_evt.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, EXIT_EVENT),
+        )?;
+        let block = block.clone();
+        let disk = queue_disks.next();
+        let rate_limiter = locked_block.rate_limiter.clone();
+        let seccomp_filter = seccomp_filter.to_vec();
+        handles.push(
+            thread::Builder::new()
+                .name(format!("fc_blk {} {}", locked_block.id(), queue_index))
+                .spawn(move || {
+                    run_io_thread(
+                        block,
+                        disk,
+                        rate_limiter,
+                        epoll,
+                        queue_index,
+                        seccomp_filter,
+                    )
+                })?,
+        );
+    }
+    locked_block.io_thread_handles = handles;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::virtio::block::test_utils::{block_with_queues, set_queue};
+    use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
+    use crate::virtio::VirtioDevice;
+    use logger::BlockDriveMetrics;
+    use utils::tempfile::TempFile;
+    use virtio_gen::virtio_blk::*;
+    use vm_memory::{Bytes, GuestAddress};
+
+    #[test]
+    fn test_io_threads() {
+        // The backing file has to outlive the block device, which opens it again for each queue.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = block_with_queues(f.as_path().to_str().unwrap().to_string(), 2, true);
+        // Don't share the latency metrics with the other tests using the same drive id.
+        block.metrics = Arc::new(BlockDriveMetrics::default());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 1, vq.create_queue());
+        initialize_virtqueue(&vq);
+        block.activate(mem.clone()).unwrap();
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+            .unwrap();
+
+        let block = Arc::new(Mutex::new(block));
+        spawn_io_threads(&block, &[]).unwrap();
+        // The raw image is opened once per queue.
+        assert_eq!(block.lock().unwrap().queue_disks.len(), 2);
+
+        // The thread of the second queue picks up the request. The latency of the request
+        // includes the time the thread waits for the device lock.
+        {
+            let locked_block = block.lock().unwrap();
+            locked_block.queue_evts[1].write(1).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while vq.used.idx.get() == 0 {
+            assert!(Instant::now() < deadline, "The request was not processed");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert_eq!(block.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+        assert_eq!(block.lock().unwrap().in_flight, vec![0, 0]);
+        let latencies = block.lock().unwrap().latencies();
+        assert_eq!(latencies.flush.count, 1);
+        assert!(latencies.flush.sum_us >= 50_000);
+
+        // The threads exit once asked to.
+        let handles = block.lock().unwrap().stop_io_threads();
+        assert_eq!(handles.len(), 2);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(block.lock().unwrap().stop_io_threads().is_empty());
+    }
+}
@@ -0,0 +1,830 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Serialize;
+use std::cmp;
+use std::io::Write;
+use std::ops::Range;
+use std::result::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ::logger::{error, IncMetric, METRICS};
+use ::utils::eventfd::EventFd;
+use ::virtio_gen::virtio_blk::*;
+use ::vm_memory::{
+    Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+};
+
+use super::super::{
+    balloon::utils::remove_range, ActivateResult, DescriptorChain, DeviceState, Queue,
+    VirtioDevice, TYPE_MEM, VIRTIO_MMIO_INT_VRING,
+};
+use super::*;
+
+use crate::report_mem_event_fail;
+use crate::virtio::mem::Error as MemError;
+
+const SIZE_OF_REQ: usize = std::mem::size_of::<MemRequest>();
+const SIZE_OF_RESP: usize = std::mem::size_of::<MemResponse>();
+
+macro_rules! mem_of_active_device {
+    ($state:expr) => {
+        match $state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        }
+    };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ConfigSpace {
+    pub block_size: u64,
+    pub node_id: u16,
+    pub padding: [u8; 6],
+    pub addr: u64,
+    pub region_size: u64,
+    pub usable_region_size: u64,
+    pub plugged_size: u64,
+    pub requested_size: u64,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct MemRequest {
+    req_type: u16,
+    padding: [u16; 3],
+    addr: u64,
+    nb_blocks: u16,
+    padding_1: [u16; 3],
+}
+
+// Safe because MemRequest only contains plain data.
+unsafe impl ByteValued for MemRequest {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct MemResponse {
+    resp_type: u16,
+    padding: [u16; 3],
+    state: u16,
+}
+
+// Safe because MemResponse only contains plain data.
+unsafe impl ByteValued for MemResponse {}
+
+impl MemResponse {
+    fn new(resp_type: u16) -> Self {
+        MemResponse {
+            resp_type,
+            ..Default::default()
+        }
+    }
+
+    fn state(state: u16) -> Self {
+        MemResponse {
+            resp_type: VIRTIO_MEM_RESP_ACK,
+            state,
+            ..Default::default()
+        }
+    }
+}
+
+// MemStatus holds the sizes reported for the memory hotplug device.
+#[derive(Clone, Default, Debug, PartialEq, Serialize)]
+pub struct MemStatus {
+    pub total_size_mib: u64,
+    pub block_size_mib: u64,
+    pub plugged_size_mib: u64,
+    pub requested_size_mib: u64,
+}
+
+// Virtio memory device.
+pub struct Mem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: [EventFd; MEM_NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    pub(crate) restored: bool,
+    // One entry per block of the device region, true if the guest plugged it.
+    pub(crate) plugged_blocks: Vec<bool>,
+}
+
+impl Mem {
+    pub fn new(
+        addr: GuestAddress,
+        region_size: u64,
+        block_size: u64,
+        requested_size: u64,
+        restored: bool,
+    ) -> Result<Mem, MemError> {
+        // The block size must be a power of two and a multiple of the page size.
+        if !block_size.is_power_of_two() || block_size % 4096 != 0 {
+            return Err(MemError::InvalidBlockSize(block_size));
+        }
+        if region_size == 0 || region_size % block_size != 0 {
+            return Err(MemError::InvalidSize(region_size));
+        }
+        if addr.0 % block_size != 0 {
+            return Err(MemError::InvalidSize(addr.0));
+        }
+
+        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(MemError::EventFd)?];
+        let queues: Vec<Queue> = MEM_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        let mut mem = Mem {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace {
+                block_size,
+                addr: addr.0,
+                region_size,
+                usable_region_size: region_size,
+                ..Default::default()
+            },
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(MemError::EventFd)?,
+            queue_evts,
+            queues,
+            device_state: DeviceState::Inactive,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(MemError::EventFd)?,
+            restored,
+            plugged_blocks: vec![false; (region_size / block_size) as usize],
+        };
+        mem.set_requested_size(requested_size)?;
+
+        Ok(mem)
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<(), MemError> {
+        self.queue_evts[MEM_REQ_INDEX]
+            .read()
+            .map_err(MemError::EventFd)?;
+        self.process_queue()
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<(), MemError> {
+        // Cloning the memory is cheap and lets the request handlers update the device state.
+        let mem = mem_of_active_device!(self.device_state).clone();
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[MEM_REQ_INDEX].pop(&mem) {
+            let len = match self.handle_request(&mem, &head) {
+                Ok(len) => len,
+                Err(e) => {
+                    report_mem_event_fail(e);
+                    0
+                }
+            };
+
+            self.queues[MEM_REQ_INDEX]
+                .add_used(&mem, head.index, len)
+                .map_err(MemError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Handles a single request and returns the number of bytes written in the response.
+    fn handle_request(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        head: &DescriptorChain,
+    ) -> Result<u32, MemError> {
+        if head.is_write_only() || (head.len as usize) < SIZE_OF_REQ {
+            return Err(MemError::MalformedDescriptor);
+        }
+        let request = mem
+            .read_obj::<MemRequest>(head.addr)
+            .map_err(MemError::GuestMemory)?;
+
+        let resp_desc = head
+            .next_descriptor()
+            .filter(|desc| desc.is_write_only() && desc.len as usize >= SIZE_OF_RESP)
+            .ok_or(MemError::MalformedDescriptor)?;
+
+        let response = match request.req_type {
+            VIRTIO_MEM_REQ_PLUG => match self.block_range(request.addr, request.nb_blocks) {
+                Some(range) => self.plug_blocks(range),
+                None => MemResponse::new(VIRTIO_MEM_RESP_ERROR),
+            },
+            VIRTIO_MEM_REQ_UNPLUG => match self.block_range(request.addr, request.nb_blocks) {
+                Some(range) => self.unplug_blocks(mem, range),
+                None => MemResponse::new(VIRTIO_MEM_RESP_ERROR),
+            },
+            VIRTIO_MEM_REQ_UNPLUG_ALL => self.unplug_all(mem),
+            VIRTIO_MEM_REQ_STATE => match self.block_range(request.addr, request.nb_blocks) {
+                Some(range) => self.blocks_state(range),
+                None => MemResponse::new(VIRTIO_MEM_RESP_ERROR),
+            },
+            _ => MemResponse::new(VIRTIO_MEM_RESP_ERROR),
+        };
+
+        mem.write_obj(response, resp_desc.addr)
+            .map_err(MemError::GuestMemory)?;
+
+        Ok(SIZE_OF_RESP as u32)
+    }
+
+    // Translates a request range into a range of block indexes, if it is
+    // block aligned and fits in the usable region.
+    fn block_range(&self, addr: u64, nb_blocks: u16) -> Option<Range<usize>> {
+        let config = &self.config_space;
+        if nb_blocks == 0 || addr < config.addr || (addr - config.addr) % config.block_size != 0 {
+            return None;
+        }
+
+        let first = ((addr - config.addr) / config.block_size) as usize;
+        let last = first.checked_add(nb_blocks as usize)?;
+        if last as u64 > config.usable_region_size / config.block_size {
+            return None;
+        }
+
+        Some(first..last)
+    }
+
+    fn plug_blocks(&mut self, range: Range<usize>) -> MemResponse {
+        if self.plugged_blocks[range.clone()]
+            .iter()
+            .any(|&plugged| plugged)
+        {
+            return MemResponse::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        let size = range.len() as u64 * self.config_space.block_size;
+        if self.config_space.plugged_size + size > self.config_space.requested_size {
+            return MemResponse::new(VIRTIO_MEM_RESP_NACK);
+        }
+
+        METRICS.mem.plug_count.inc();
+        self.plugged_blocks[range]
+            .iter_mut()
+            .for_each(|b| *b = true);
+        self.config_space.plugged_size += size;
+
+        MemResponse::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn unplug_blocks(&mut self, mem: &GuestMemoryMmap, range: Range<usize>) -> MemResponse {
+        if !self.plugged_blocks[range.clone()]
+            .iter()
+            .all(|&plugged| plugged)
+        {
+            return MemResponse::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        METRICS.mem.unplug_count.inc();
+        if let Err(e) = self.discard_blocks(mem, range.clone()) {
+            error!("Error removing memory range: {:?}", e);
+            return MemResponse::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        self.plugged_blocks[range.clone()]
+            .iter_mut()
+            .for_each(|b| *b = false);
+        self.config_space.plugged_size -= range.len() as u64 * self.config_space.block_size;
+
+        MemResponse::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn unplug_all(&mut self, mem: &GuestMemoryMmap) -> MemResponse {
+        METRICS.mem.unplug_count.inc();
+
+        let mut index = 0;
+        while index < self.plugged_blocks.len() {
+            if !self.plugged_blocks[index] {
+                index += 1;
+                continue;
+            }
+
+            // Discard each run of consecutive plugged blocks at once.
+            let start = index;
+            while index < self.plugged_blocks.len() && self.plugged_blocks[index] {
+                index += 1;
+            }
+            if let Err(e) = self.discard_blocks(mem, start..index) {
+                error!("Error removing memory range: {:?}", e);
+                return MemResponse::new(VIRTIO_MEM_RESP_ERROR);
+            }
+            self.plugged_blocks[start..index]
+                .iter_mut()
+                .for_each(|b| *b = false);
+            self.config_space.plugged_size -= (index - start) as u64 * self.config_space.block_size;
+        }
+
+        MemResponse::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn blocks_state(&self, range: Range<usize>) -> MemResponse {
+        let blocks = &self.plugged_blocks[range];
+        if blocks.iter().all(|&plugged| plugged) {
+            MemResponse::state(VIRTIO_MEM_STATE_PLUGGED)
+        } else if blocks.iter().all(|&plugged| !plugged) {
+            MemResponse::state(VIRTIO_MEM_STATE_UNPLUGGED)
+        } else {
+            MemResponse::state(VIRTIO_MEM_STATE_MIXED)
+        }
+    }
+
+    fn discard_blocks(&self, mem: &GuestMemoryMmap, range: Range<usize>) -> Result<(), MemError> {
+        let block_size = self.config_space.block_size;
+        let guest_addr = GuestAddress(self.config_space.addr + range.start as u64 * block_size);
+        let len = range.len() as u64 * block_size;
+
+        remove_range(mem, (guest_addr, len), self.restored)
+            .map_err(MemError::RemoveMemoryRegion)?;
+
+        // The discarded blocks now read as zeroes, which a diff snapshot
+        // has to capture as well.
+        if let Some(region) = mem.find_region(guest_addr) {
+            let offset = guest_addr.unchecked_offset_from(region.start_addr());
+            region.mark_dirty_pages(offset as usize, len as usize);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<(), MemError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            MemError::FailedSignalingUsedQueue(e)
+        })?;
+        Ok(())
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+
+    pub fn id(&self) -> &str {
+        MEM_DEV_ID
+    }
+
+    fn set_requested_size(&mut self, requested_size: u64) -> Result<(), MemError> {
+        if requested_size > self.config_space.region_size
+            || requested_size % self.config_space.block_size != 0
+        {
+            return Err(MemError::InvalidSize(requested_size));
+        }
+        self.config_space.requested_size = requested_size;
+        Ok(())
+    }
+
+    pub fn update_requested_size(&mut self, requested_size_mib: u64) -> Result<(), MemError> {
+        if self.is_activated() {
+            let requested_size = requested_size_mib
+                .checked_mul(MIB_TO_BYTES)
+                .ok_or(MemError::InvalidSize(requested_size_mib))?;
+            self.set_requested_size(requested_size)
+        } else {
+            Err(MemError::DeviceNotActive)
+        }
+    }
+
+    pub fn start_addr(&self) -> GuestAddress {
+        GuestAddress(self.config_space.addr)
+    }
+
+    pub fn region_size(&self) -> u64 {
+        self.config_space.region_size
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.config_space.block_size
+    }
+
+    pub fn plugged_size(&self) -> u64 {
+        self.config_space.plugged_size
+    }
+
+    pub fn requested_size(&self) -> u64 {
+        self.config_space.requested_size
+    }
+
+    pub fn status(&self) -> MemStatus {
+        MemStatus {
+            total_size_mib: self.region_size() / MIB_TO_BYTES,
+            block_size_mib: self.block_size() / MIB_TO_BYTES,
+            plugged_size_mib: self.plugged_size() / MIB_TO_BYTES,
+            requested_size_mib: self.requested_size() / MIB_TO_BYTES,
+        }
+    }
+}
+
+impl VirtioDevice for Mem {
+    fn device_type(&self) -> u32 {
+        TYPE_MEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The virtio-mem config space is read-only for the driver.
+        error!(
+            "Mem: guest attempted to write {} bytes to the config space at offset {:#x}",
+            data.len(),
+            offset
+        );
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        self.device_state = DeviceState::Activated(mem);
+        if self.activate_evt.write(1).is_err() {
+            error!("Mem: Cannot write to activate_evt");
+            METRICS.mem.activate_fails.inc();
+            self.device_state = DeviceState::Inactive;
+            return Err(super::super::ActivateError::BadActivate);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::VirtQueue;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use ::utils::epoll::{EpollEvent, EventSet};
+    use polly::event_manager::{EventManager, Subscriber};
+
+    const BLOCK_SIZE: u64 = 0x1000;
+    const REGION_ADDR: u64 = 0x10000;
+    const REGION_SIZE: u64 = 4 * BLOCK_SIZE;
+    const REQ_ADDR: u64 = 0x1000;
+    const RESP_ADDR: u64 = 0x2000;
+
+    pub(crate) fn mem_with_hotplug_region() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(REGION_ADDR), REGION_SIZE as usize),
+        ])
+        .unwrap()
+    }
+
+    impl Mem {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    fn default_mem_device() -> Mem {
+        Mem::new(
+            GuestAddress(REGION_ADDR),
+            REGION_SIZE,
+            BLOCK_SIZE,
+            2 * BLOCK_SIZE,
+            false,
+        )
+        .unwrap()
+    }
+
+    // Places a request in the queue at position `idx`, invokes the queue
+    // handler and returns the response written by the device.
+    fn send_request(
+        m: &mut Mem,
+        mem: &GuestMemoryMmap,
+        queue: &VirtQueue,
+        idx: u16,
+        req_type: u16,
+        addr: u64,
+        nb_blocks: u16,
+    ) -> MemResponse {
+        let request = MemRequest {
+            req_type,
+            addr,
+            nb_blocks,
+            ..Default::default()
+        };
+        mem.write_obj(request, GuestAddress(REQ_ADDR)).unwrap();
+        mem.write_obj(MemResponse::new(0xff), GuestAddress(RESP_ADDR))
+            .unwrap();
+
+        let desc = (idx * 2) as usize;
+        queue.dtable[desc].set(
+            REQ_ADDR,
+            SIZE_OF_REQ as u32,
+            VIRTQ_DESC_F_NEXT,
+            (desc + 1) as u16,
+        );
+        queue.dtable[desc + 1].set(RESP_ADDR, SIZE_OF_RESP as u32, VIRTQ_DESC_F_WRITE, 0);
+        queue.avail.ring[idx as usize].set(desc as u16);
+        queue.avail.idx.set(idx + 1);
+
+        m.queue_evts[MEM_REQ_INDEX].write(1).unwrap();
+        m.process(
+            &EpollEvent::new(EventSet::IN, m.queue_evts[MEM_REQ_INDEX].as_raw_fd() as u64),
+            &mut EventManager::new().unwrap(),
+        );
+        assert_eq!(m.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(queue.used.idx.get(), idx + 1);
+        assert_eq!(queue.used.ring[idx as usize].get().len, SIZE_OF_RESP as u32);
+
+        mem.read_obj(GuestAddress(RESP_ADDR)).unwrap()
+    }
+
+    #[test]
+    fn test_request_sizes() {
+        assert_eq!(SIZE_OF_REQ, 24);
+        assert_eq!(SIZE_OF_RESP, 10);
+        assert_eq!(std::mem::size_of::<ConfigSpace>(), 56);
+    }
+
+    #[test]
+    fn test_new() {
+        let m = default_mem_device();
+        assert_eq!(m.start_addr(), GuestAddress(REGION_ADDR));
+        assert_eq!(m.region_size(), REGION_SIZE);
+        assert_eq!(m.block_size(), BLOCK_SIZE);
+        assert_eq!(m.requested_size(), 2 * BLOCK_SIZE);
+        assert_eq!(m.plugged_size(), 0);
+        assert_eq!(m.plugged_blocks.len(), 4);
+        assert!(!m.restored);
+
+        // Invalid block sizes.
+        match Mem::new(GuestAddress(REGION_ADDR), REGION_SIZE, 0x1800, 0, false) {
+            Err(MemError::InvalidBlockSize(0x1800)) => (),
+            _ => unreachable!(),
+        }
+        match Mem::new(GuestAddress(REGION_ADDR), REGION_SIZE, 0x800, 0, false) {
+            Err(MemError::InvalidBlockSize(0x800)) => (),
+            _ => unreachable!(),
+        }
+        // The region size is not a multiple of the block size.
+        match Mem::new(GuestAddress(REGION_ADDR), 0x1800, BLOCK_SIZE, 0, false) {
+            Err(MemError::InvalidSize(0x1800)) => (),
+            _ => unreachable!(),
+        }
+        // The requested size exceeds the region size.
+        match Mem::new(
+            GuestAddress(REGION_ADDR),
+            REGION_SIZE,
+            BLOCK_SIZE,
+            REGION_SIZE + BLOCK_SIZE,
+            false,
+        ) {
+            Err(MemError::InvalidSize(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let mut m = default_mem_device();
+
+        let features: u64 = 1u64 << VIRTIO_F_VERSION_1;
+        assert_eq!(m.avail_features_by_page(0), features as u32);
+        assert_eq!(m.avail_features_by_page(1), (features >> 32) as u32);
+        m.ack_features_by_page(0, features as u32);
+        m.ack_features_by_page(1, (features >> 32) as u32);
+        assert_eq!(m.acked_features, features);
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut m = default_mem_device();
+
+        let mut actual_config_space = [0u8; 56];
+        m.read_config(0, &mut actual_config_space);
+        let config = ConfigSpace {
+            block_size: BLOCK_SIZE,
+            addr: REGION_ADDR,
+            region_size: REGION_SIZE,
+            usable_region_size: REGION_SIZE,
+            requested_size: 2 * BLOCK_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(actual_config_space, config.as_slice());
+
+        // Writes are ignored.
+        m.write_config(0, &[0xff; 8]);
+        m.read_config(0, &mut actual_config_space);
+        assert_eq!(actual_config_space, config.as_slice());
+
+        // Invalid read.
+        let mut data = [0xd, 0xe, 0xa, 0xd];
+        m.read_config(56, &mut data);
+        assert_eq!(data, [0xd, 0xe, 0xa, 0xd]);
+    }
+
+    #[test]
+    fn test_plug_unplug() {
+        let mut m = default_mem_device();
+        let mem = mem_with_hotplug_region();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        m.set_queue(MEM_REQ_INDEX, queue.create_queue());
+        m.activate(mem.clone()).unwrap();
+
+        // Plug the first two blocks.
+        check_metric_after_block!(METRICS.mem.plug_count, 1, {
+            let resp = send_request(&mut m, &mem, &queue, 0, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 2);
+            assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        });
+        assert_eq!(m.plugged_size(), 2 * BLOCK_SIZE);
+        assert_eq!(m.plugged_blocks, vec![true, true, false, false]);
+
+        // Plugging more than requested is refused.
+        let addr = REGION_ADDR + 2 * BLOCK_SIZE;
+        let resp = send_request(&mut m, &mem, &queue, 1, VIRTIO_MEM_REQ_PLUG, addr, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_NACK);
+
+        // Plugging an already plugged block is an error.
+        m.config_space.requested_size = REGION_SIZE;
+        let addr = REGION_ADDR + BLOCK_SIZE;
+        let resp = send_request(&mut m, &mem, &queue, 2, VIRTIO_MEM_REQ_PLUG, addr, 2);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        // Mixed state.
+        let resp = send_request(&mut m, &mem, &queue, 3, VIRTIO_MEM_REQ_STATE, addr, 2);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_MIXED);
+
+        // Write a pattern in the second block, then unplug it.
+        mem.write_obj::<u64>(0xdead_beef, GuestAddress(addr))
+            .unwrap();
+        check_metric_after_block!(METRICS.mem.unplug_count, 1, {
+            let resp = send_request(&mut m, &mem, &queue, 4, VIRTIO_MEM_REQ_UNPLUG, addr, 1);
+            assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        });
+        assert_eq!(mem.read_obj::<u64>(GuestAddress(addr)).unwrap(), 0);
+        assert_eq!(m.plugged_size(), BLOCK_SIZE);
+
+        // Unplugging a block that is not plugged is an error.
+        let resp = send_request(&mut m, &mem, &queue, 5, VIRTIO_MEM_REQ_UNPLUG, addr, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        let resp = send_request(&mut m, &mem, &queue, 6, VIRTIO_MEM_REQ_STATE, addr, 1);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_UNPLUGGED);
+        let resp = send_request(
+            &mut m,
+            &mem,
+            &queue,
+            7,
+            VIRTIO_MEM_REQ_STATE,
+            REGION_ADDR,
+            1,
+        );
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_PLUGGED);
+
+        // Unplug everything.
+        let resp = send_request(&mut m, &mem, &queue, 8, VIRTIO_MEM_REQ_UNPLUG_ALL, 0, 0);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(m.plugged_size(), 0);
+        assert!(m.plugged_blocks.iter().all(|&plugged| !plugged));
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let mut m = default_mem_device();
+        let mem = mem_with_hotplug_region();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        m.set_queue(MEM_REQ_INDEX, queue.create_queue());
+        m.activate(mem.clone()).unwrap();
+
+        // Unaligned address.
+        let resp = send_request(
+            &mut m,
+            &mem,
+            &queue,
+            0,
+            VIRTIO_MEM_REQ_PLUG,
+            REGION_ADDR + 1,
+            1,
+        );
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        // Outside of the device region.
+        let resp = send_request(&mut m, &mem, &queue, 1, VIRTIO_MEM_REQ_PLUG, 0, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        let resp = send_request(&mut m, &mem, &queue, 2, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 5);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        // No blocks.
+        let resp = send_request(
+            &mut m,
+            &mem,
+            &queue,
+            3,
+            VIRTIO_MEM_REQ_STATE,
+            REGION_ADDR,
+            0,
+        );
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        // Unknown request type.
+        let resp = send_request(&mut m, &mem, &queue, 4, 0x42, REGION_ADDR, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        assert_eq!(m.plugged_size(), 0);
+
+        // A request without a response descriptor is completed without a response.
+        mem.write_obj(MemRequest::default(), GuestAddress(REQ_ADDR))
+            .unwrap();
+        queue.dtable[0].set(REQ_ADDR, SIZE_OF_REQ as u32, 0, 0);
+        queue.avail.ring[5].set(0);
+        queue.avail.idx.set(6);
+        check_metric_after_block!(METRICS.mem.event_fails, 1, m.process_queue().unwrap());
+        assert_eq!(queue.used.idx.get(), 6);
+        assert_eq!(queue.used.ring[5].get().len, 0);
+        assert_eq!(m.plugged_size(), 0);
+    }
+
+    #[test]
+    fn test_update_requested_size() {
+        let mut m = default_mem_device();
+        // Can not update the size before the device is activated.
+        assert!(m.update_requested_size(0).is_err());
+
+        let mem = mem_with_hotplug_region();
+        m.activate(mem).unwrap();
+        // The test device is smaller than 1 MiB, so any non-zero size is too large.
+        match m.update_requested_size(1) {
+            Err(MemError::InvalidSize(_)) => (),
+            _ => unreachable!(),
+        }
+        // Sizes overflowing once converted to bytes are rejected as well.
+        match m.update_requested_size(u64::max_value()) {
+            Err(MemError::InvalidSize(_)) => (),
+            _ => unreachable!(),
+        }
+        m.update_requested_size(0).unwrap();
+        assert_eq!(m.requested_size(), 0);
+    }
+}
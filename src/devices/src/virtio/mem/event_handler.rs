@@ -0,0 +1,162 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{debug, error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::report_mem_event_fail;
+use crate::virtio::{mem::device::Mem, VirtioDevice, MEM_REQ_INDEX};
+
+impl Mem {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("mem: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume mem activate event: {:?}", e);
+        }
+        let activate_fd = self.activate_evt.as_raw_fd();
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(activate_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process mem activate evt: {:?}", e);
+                return;
+            }
+        };
+
+        // Interest list changes when the device is activated.
+        let interest_list = self.interest_list();
+        for event in interest_list {
+            event_manager
+                .register(event.data() as i32, event, self_subscriber.clone())
+                .unwrap_or_else(|e| {
+                    error!("Failed to register mem events: {:?}", e);
+                });
+        }
+
+        event_manager.unregister(activate_fd).unwrap_or_else(|e| {
+            error!("Failed to unregister mem activate evt: {:?}", e);
+        });
+    }
+}
+
+impl Subscriber for Mem {
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let virtq_req_ev_fd = self.queue_evts[MEM_REQ_INDEX].as_raw_fd();
+            let activate_fd = self.activate_evt.as_raw_fd();
+
+            match source {
+                _ if source == virtq_req_ev_fd => self
+                    .process_queue_event()
+                    .unwrap_or_else(report_mem_event_fail),
+                _ if activate_fd == source => self.process_activate_event(evmgr),
+                _ => {
+                    warn!("Mem: Spurious event received: {:?}", source);
+                }
+            };
+        } else {
+            warn!(
+                "Mem: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point),
+        //  - on device restore from snapshot.
+        if self.is_activated() {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.queue_evts[MEM_REQ_INDEX].as_raw_fd() as u64,
+            )]
+        } else {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.activate_evt.as_raw_fd() as u64,
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::mem::device::tests::mem_with_hotplug_region;
+    use crate::virtio::test_utils::VirtQueue;
+    use crate::virtio::VIRTQ_DESC_F_NEXT;
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut m = Mem::new(GuestAddress(0x10000), 0x4000, 0x1000, 0, false).unwrap();
+        let mem = mem_with_hotplug_region();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        m.set_queue(MEM_REQ_INDEX, queue.create_queue());
+
+        let m = Arc::new(Mutex::new(m));
+        event_manager.add_subscriber(m.clone()).unwrap();
+
+        // Push a queue event with a request that lacks a response descriptor.
+        {
+            queue.avail.idx.set(1);
+            queue.avail.ring[0].set(0);
+            queue.dtable[0].set(0x1000, 24, VIRTQ_DESC_F_NEXT, 1);
+            m.lock().unwrap().queue_evts[MEM_REQ_INDEX]
+                .write(1)
+                .unwrap();
+        }
+
+        // EventManager should report no events since the device has only registered
+        // its activation event so far (even though there is also a queue event pending).
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Manually force a queue event and check it's ignored pre-activation.
+        {
+            let mut dev = m.lock().unwrap();
+            let raw_q_evt = dev.queue_evts[MEM_REQ_INDEX].as_raw_fd() as u64;
+            // Artificially push event.
+            dev.process(
+                &EpollEvent::new(EventSet::IN, raw_q_evt),
+                &mut event_manager,
+            );
+            // Validate there was no queue operation.
+            assert_eq!(queue.used.idx.get(), 0);
+        }
+
+        // Now activate the device.
+        m.lock().unwrap().activate(mem.clone()).unwrap();
+        // Process the activate event.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the previously pushed queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Metrics event timeout or error.");
+        // Make sure the queue advanced.
+        assert_eq!(queue.used.idx.get(), 1);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::Mem;
+pub use self::device::MemStatus;
+pub use self::event_handler::*;
+
+use super::balloon::RemoveRegionError;
+
+/// Device ID used in MMIO device identification.
+/// Because the memory device is unique per-vm, this ID can be hardcoded.
+pub const MEM_DEV_ID: &str = "mem";
+pub const MEM_QUEUE_SIZE: u16 = 128;
+pub const MEM_NUM_QUEUES: usize = 1;
+pub const MEM_QUEUE_SIZES: &[u16] = &[MEM_QUEUE_SIZE];
+// The index of the request queue from the memory device queues/queue_evts vector.
+pub const MEM_REQ_INDEX: usize = 0;
+// Number of bytes in a MiB.
+pub const MIB_TO_BYTES: u64 = 1 << 20;
+
+// The request types.
+const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+const VIRTIO_MEM_REQ_STATE: u16 = 3;
+
+// The response types.
+const VIRTIO_MEM_RESP_ACK: u16 = 0;
+const VIRTIO_MEM_RESP_NACK: u16 = 1;
+const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+
+// The block states reported for a VIRTIO_MEM_REQ_STATE request.
+const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+const VIRTIO_MEM_STATE_MIXED: u16 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// No memory hotplug device found.
+    DeviceNotFound,
+    /// Device not activated yet.
+    DeviceNotActive,
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Received error while sending an interrupt.
+    InterruptError(std::io::Error),
+    /// The block size is not a power of two of at least one page.
+    InvalidBlockSize(u64),
+    /// A size or address is not a multiple of the block size, or exceeds the region size.
+    InvalidSize(u64),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the memory device queues.
+    QueueRestoreError,
+    /// Error removing a memory range at unplug time.
+    RemoveMemoryRegion(RemoveRegionError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
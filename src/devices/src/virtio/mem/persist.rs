@@ -0,0 +1,153 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring memory hotplug devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use vm_memory::{GuestAddress, GuestMemoryMmap};
+
+use super::*;
+
+use crate::virtio::mem::device::ConfigSpace;
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, TYPE_MEM};
+
+#[derive(Clone, Versionize)]
+pub struct MemConfigSpaceState {
+    block_size: u64,
+    addr: u64,
+    region_size: u64,
+    usable_region_size: u64,
+    plugged_size: u64,
+    requested_size: u64,
+}
+
+#[derive(Clone, Versionize)]
+pub struct MemState {
+    config_space: MemConfigSpaceState,
+    plugged_blocks: Vec<bool>,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct MemConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for Mem {
+    type State = MemState;
+    type ConstructorArgs = MemConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        MemState {
+            config_space: MemConfigSpaceState {
+                block_size: self.config_space.block_size,
+                addr: self.config_space.addr,
+                region_size: self.config_space.region_size,
+                usable_region_size: self.config_space.usable_region_size,
+                plugged_size: self.config_space.plugged_size,
+                requested_size: self.config_space.requested_size,
+            },
+            plugged_blocks: self.plugged_blocks.clone(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let config = &state.config_space;
+        let mut mem = Mem::new(
+            GuestAddress(config.addr),
+            config.region_size,
+            config.block_size,
+            config.requested_size,
+            true,
+        )?;
+        if state.plugged_blocks.len() != mem.plugged_blocks.len() {
+            return Err(Self::Error::InvalidSize(config.region_size));
+        }
+
+        mem.queues = state
+            .virtio_state
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_MEM,
+                MEM_NUM_QUEUES,
+                MEM_QUEUE_SIZE,
+            )
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        mem.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        mem.avail_features = state.virtio_state.avail_features;
+        mem.acked_features = state.virtio_state.acked_features;
+        mem.config_space = ConfigSpace {
+            block_size: config.block_size,
+            addr: config.addr,
+            region_size: config.region_size,
+            usable_region_size: config.usable_region_size,
+            plugged_size: config.plugged_size,
+            requested_size: config.requested_size,
+            ..Default::default()
+        };
+        mem.plugged_blocks = state.plugged_blocks.clone();
+
+        if state.virtio_state.activated {
+            mem.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(mem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::mem::device::tests::mem_with_hotplug_region;
+
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = mem_with_hotplug_region();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the memory device.
+        let mut m = Mem::new(GuestAddress(0x10000), 0x4000, 0x1000, 0x2000, false).unwrap();
+        m.plugged_blocks[1] = true;
+        m.config_space.plugged_size = 0x1000;
+
+        <Mem as Persist>::save(&m)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the memory device.
+        let restored_mem = Mem::restore(
+            MemConstructorArgs { mem: guest_mem },
+            &MemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_mem.device_type(), TYPE_MEM);
+        assert!(restored_mem.restored);
+
+        assert_eq!(restored_mem.acked_features, m.acked_features);
+        assert_eq!(restored_mem.avail_features, m.avail_features);
+        assert_eq!(restored_mem.config_space, m.config_space);
+        assert_eq!(restored_mem.plugged_blocks, m.plugged_blocks);
+        assert_eq!(restored_mem.queues(), m.queues());
+        assert_eq!(
+            restored_mem.interrupt_status().load(Ordering::Relaxed),
+            m.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_mem.is_activated(), m.is_activated());
+    }
+}
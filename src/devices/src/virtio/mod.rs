@@ -12,6 +12,7 @@ use std::io::Error as IOError;
 pub mod balloon;
 pub mod block;
 pub mod device;
+pub mod mem;
 mod mmio;
 pub mod net;
 pub mod persist;
@@ -22,6 +23,7 @@ pub mod vsock;
 pub use self::balloon::*;
 pub use self::block::*;
 pub use self::device::*;
+pub use self::mem::*;
 pub use self::mmio::*;
 pub use self::net::*;
 pub use self::persist::*;
@@ -50,6 +52,7 @@ mod device_status {
 pub const TYPE_NET: u32 = 1;
 pub const TYPE_BLOCK: u32 = 2;
 pub const TYPE_BALLOON: u32 = 5;
+pub const TYPE_MEM: u32 = 24;
 
 /// Interrupt flags (re: interrupt status & acknowledge registers).
 /// See linux/virtio_mmio.h.
@@ -0,0 +1,254 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filtering of the frames sent by the guest, so that it can only use the addresses it was
+//! given on the host network.
+
+use std::net::Ipv4Addr;
+
+use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::IPv4Packet;
+use logger::{IncMetric, METRICS};
+use utils::net::mac::MacAddr;
+
+// The length of an IPv4 header without options.
+const IPV4_MIN_HEADER_LEN: usize = 20;
+
+/// The reasons for which a frame is dropped by the filter.
+#[derive(Debug, PartialEq)]
+enum Violation {
+    /// The frame could not be parsed.
+    Malformed,
+    /// The source MAC address of the frame, or the sender hardware address of an ARP frame,
+    /// is not the one of the guest.
+    SpoofedMac,
+    /// The source address of an IPv4 packet, or the sender protocol address of an ARP frame,
+    /// is not an allowed one.
+    SpoofedIp,
+}
+
+/// Drops the frames sent by the guest whose source MAC address is not the one of the guest,
+/// or whose IPv4 sender is not an allowed one. The frames of other protocols are only checked
+/// for their source MAC address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AntiSpoofingFilter {
+    mac: MacAddr,
+    // The IPv4 sender addresses are not checked when missing.
+    allowed_ipv4: Option<Vec<Ipv4Addr>>,
+}
+
+impl AntiSpoofingFilter {
+    /// Creates a filter for a guest using `mac`, which can only send IPv4 packets and ARP
+    /// frames from `allowed_ipv4` when present.
+    pub fn new(mac: MacAddr, allowed_ipv4: Option<Vec<Ipv4Addr>>) -> Self {
+        AntiSpoofingFilter { mac, allowed_ipv4 }
+    }
+
+    /// Provides the MAC address the guest is allowed to use.
+    pub fn mac(&self) -> &MacAddr {
+        &self.mac
+    }
+
+    /// Sets the MAC address the guest is allowed to use.
+    pub fn set_mac(&mut self, mac: MacAddr) {
+        self.mac = mac;
+    }
+
+    /// Provides the IPv4 addresses the guest is allowed to use, if they are checked.
+    pub fn allowed_ipv4(&self) -> Option<&[Ipv4Addr]> {
+        self.allowed_ipv4.as_deref()
+    }
+
+    /// Returns whether the guest is allowed to send the Ethernet frame in `frame_bytes`,
+    /// accounting the frame in the drop metrics otherwise.
+    pub(crate) fn allows(&self, frame_bytes: &[u8]) -> bool {
+        match self.check(frame_bytes) {
+            Ok(()) => true,
+            Err(violation) => {
+                match violation {
+                    Violation::Malformed => &METRICS.net.tx_malformed_frames,
+                    Violation::SpoofedMac => &METRICS.net.tx_spoofed_mac_drops,
+                    Violation::SpoofedIp => &METRICS.net.tx_spoofed_ip_drops,
+                }
+                .inc();
+                false
+            }
+        }
+    }
+
+    fn check(&self, frame_bytes: &[u8]) -> Result<(), Violation> {
+        let eth_frame = EthernetFrame::from_bytes(frame_bytes).map_err(|_| Violation::Malformed)?;
+        if eth_frame.src_mac() != self.mac {
+            return Err(Violation::SpoofedMac);
+        }
+
+        let allowed_ipv4 = match self.allowed_ipv4.as_ref() {
+            Some(allowed_ipv4) => allowed_ipv4,
+            None => return Ok(()),
+        };
+        // Short frames may be padded up to the minimum Ethernet frame size, so the payloads
+        // are trimmed to the length of the packets they carry before being parsed.
+        let payload = eth_frame.payload();
+        let sender = match eth_frame.ethertype() {
+            ETHERTYPE_IPV4 => {
+                if payload.len() < IPV4_MIN_HEADER_LEN {
+                    return Err(Violation::Malformed);
+                }
+                let total_len = IPv4Packet::from_bytes_unchecked(payload).total_len() as usize;
+                let packet =
+                    IPv4Packet::from_bytes(&payload[..total_len.min(payload.len())], false)
+                        .map_err(|_| Violation::Malformed)?;
+                packet.source_address()
+            }
+            ETHERTYPE_ARP => {
+                if payload.len() < ETH_IPV4_FRAME_LEN {
+                    return Err(Violation::Malformed);
+                }
+                let arp_frame = EthIPv4ArpFrame::from_bytes(&payload[..ETH_IPV4_FRAME_LEN])
+                    .map_err(|_| Violation::Malformed)?;
+                if arp_frame.sha() != self.mac {
+                    return Err(Violation::SpoofedMac);
+                }
+                arp_frame.spa()
+            }
+            _ => return Ok(()),
+        };
+
+        if allowed_ipv4.contains(&sender) {
+            Ok(())
+        } else {
+            Err(Violation::SpoofedIp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dumbo::pdu::ethernet::PAYLOAD_OFFSET;
+
+    // The minimum length of an Ethernet frame, without its frame check sequence.
+    const ETH_MIN_FRAME_LEN: usize = 60;
+
+    fn ipv4_frame(buf: &mut [u8], src_mac: MacAddr, src_ip: Ipv4Addr) -> usize {
+        let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let mut eth_frame =
+            EthernetFrame::write_incomplete(&mut buf[..], dst_mac, src_mac, ETHERTYPE_IPV4)
+                .unwrap()
+                .with_payload_len_unchecked(IPV4_MIN_HEADER_LEN);
+        IPv4Packet::write_header(
+            eth_frame.payload_mut(),
+            0,
+            src_ip,
+            Ipv4Addr::new(10, 1, 1, 1),
+        )
+        .unwrap()
+        .with_payload_len_unchecked(0, true);
+        PAYLOAD_OFFSET + IPV4_MIN_HEADER_LEN
+    }
+
+    fn arp_frame(buf: &mut [u8], src_mac: MacAddr, sha: MacAddr, spa: Ipv4Addr) -> usize {
+        let dst_mac = MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap();
+        let mut eth_frame =
+            EthernetFrame::write_incomplete(&mut buf[..], dst_mac, src_mac, ETHERTYPE_ARP)
+                .unwrap()
+                .with_payload_len_unchecked(ETH_IPV4_FRAME_LEN);
+        EthIPv4ArpFrame::write_reply(
+            eth_frame.payload_mut(),
+            sha,
+            spa,
+            dst_mac,
+            Ipv4Addr::new(10, 1, 1, 1),
+        )
+        .unwrap();
+        PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN
+    }
+
+    #[test]
+    fn test_mac_filtering() {
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let spoofed_mac = MacAddr::parse_str("33:33:33:33:33:33").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let mut filter = AntiSpoofingFilter::new(guest_mac, None);
+        let mut buf = [0u8; 128];
+
+        assert_eq!(filter.check(&buf[..10]), Err(Violation::Malformed));
+
+        let len = ipv4_frame(&mut buf, guest_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+        let len = ipv4_frame(&mut buf, spoofed_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Err(Violation::SpoofedMac));
+
+        // The sender hardware address of ARP frames is only checked along with the IPv4 sender.
+        let len = arp_frame(&mut buf, guest_mac, spoofed_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+
+        // The filter follows MAC address updates.
+        filter.set_mac(spoofed_mac);
+        assert_eq!(filter.mac(), &spoofed_mac);
+        let len = ipv4_frame(&mut buf, spoofed_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+    }
+
+    #[test]
+    fn test_ipv4_filtering() {
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let spoofed_mac = MacAddr::parse_str("33:33:33:33:33:33").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let spoofed_ip = Ipv4Addr::new(10, 1, 2, 4);
+        let filter = AntiSpoofingFilter::new(guest_mac, Some(vec![guest_ip]));
+        assert_eq!(filter.allowed_ipv4(), Some(&[guest_ip][..]));
+        let mut buf = [0u8; 128];
+
+        // IPv4 packets.
+        let len = ipv4_frame(&mut buf, guest_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+        // Padded frames are parsed as well.
+        assert_eq!(filter.check(&buf[..ETH_MIN_FRAME_LEN]), Ok(()));
+        let len = ipv4_frame(&mut buf, guest_mac, spoofed_ip);
+        assert_eq!(filter.check(&buf[..len]), Err(Violation::SpoofedIp));
+        assert_eq!(
+            filter.check(&buf[..PAYLOAD_OFFSET + 10]),
+            Err(Violation::Malformed)
+        );
+
+        // ARP frames.
+        let len = arp_frame(&mut buf, guest_mac, guest_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+        assert_eq!(filter.check(&buf[..ETH_MIN_FRAME_LEN]), Ok(()));
+        assert_eq!(filter.check(&buf[..len - 1]), Err(Violation::Malformed));
+        let len = arp_frame(&mut buf, guest_mac, guest_mac, spoofed_ip);
+        assert_eq!(filter.check(&buf[..len]), Err(Violation::SpoofedIp));
+        let len = arp_frame(&mut buf, guest_mac, spoofed_mac, guest_ip);
+        assert_eq!(filter.check(&buf[..len]), Err(Violation::SpoofedMac));
+
+        // Other protocols are only checked for their source MAC address.
+        let len = ipv4_frame(&mut buf, guest_mac, spoofed_ip);
+        EthernetFrame::from_bytes_unchecked(&mut buf[..len]).set_ethertype(0x86dd);
+        assert_eq!(filter.check(&buf[..len]), Ok(()));
+    }
+
+    #[test]
+    fn test_drop_metrics() {
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let spoofed_mac = MacAddr::parse_str("33:33:33:33:33:33").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let filter = AntiSpoofingFilter::new(guest_mac, Some(vec![guest_ip]));
+        let mut buf = [0u8; 128];
+
+        let len = ipv4_frame(&mut buf, spoofed_mac, guest_ip);
+        let mac_drops = METRICS.net.tx_spoofed_mac_drops.count();
+        assert!(!filter.allows(&buf[..len]));
+        assert!(METRICS.net.tx_spoofed_mac_drops.count() > mac_drops);
+
+        let len = ipv4_frame(&mut buf, guest_mac, Ipv4Addr::new(10, 1, 2, 4));
+        let ip_drops = METRICS.net.tx_spoofed_ip_drops.count();
+        assert!(!filter.allows(&buf[..len]));
+        assert!(METRICS.net.tx_spoofed_ip_drops.count() > ip_drops);
+
+        let len = ipv4_frame(&mut buf, guest_mac, guest_ip);
+        assert!(filter.allows(&buf[..len]));
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host backends of the net device. Each rx/tx queue pair of a device exchanges
+//! Ethernet frames with its own backend instance.
+
+use std::any::Any;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::device::vnet_hdr_len;
+use super::tap::Tap;
+use super::unix_socket::UnixSocketBackend;
+use super::xdp::XdpSocket;
+use super::{Error, Result};
+
+/// The kind of host backend of a net device, along with its specific configuration.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+pub enum BackendConfig {
+    /// Queues of the TAP device named by the host device name.
+    Tap,
+    /// Connections to the Unix `SOCK_SEQPACKET` socket found at the host device name.
+    /// Every message carries a single Ethernet frame.
+    UnixSocket,
+    /// AF_XDP sockets bound to the queues of the host interface named by the host device
+    /// name. The sockets are registered in the XSKMAP pinned at the given path, which an
+    /// XDP program attached to the interface redirects the guest traffic to.
+    Xdp(String),
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig::Tap
+    }
+}
+
+impl BackendConfig {
+    /// Whether the backend handles the checksum and segmentation offloads described by the
+    /// virtio-net header. The offloads are not offered to the guest otherwise.
+    pub fn supports_offloads(&self) -> bool {
+        match self {
+            BackendConfig::Tap => true,
+            BackendConfig::UnixSocket | BackendConfig::Xdp(_) => false,
+        }
+    }
+}
+
+/// The host side of an rx/tx queue pair. The frames exchanged with the device are prefixed
+/// by a virtio-net header.
+pub trait NetBackend: AsRawFd + Send {
+    /// Reads a frame into `buf`, returning its length including the virtio-net header.
+    fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Sends the frame in `buf`.
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Starts or stops steering incoming traffic to this queue pair.
+    fn set_queue_attached(&self, _attached: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl NetBackend for Tap {
+    fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write(buf)
+    }
+
+    fn set_queue_attached(&self, attached: bool) -> Result<()> {
+        Tap::set_queue_attached(self, attached).map_err(Error::TapSetQueue)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Opens the backend of the `queue_index` queue pair of a device with `num_queue_pairs`
+/// queue pairs.
+pub(crate) fn open_backend(
+    config: &BackendConfig,
+    host_dev_name: &str,
+    queue_index: usize,
+    num_queue_pairs: usize,
+) -> Result<Box<dyn NetBackend>> {
+    match config {
+        BackendConfig::Tap => {
+            let tap = if num_queue_pairs > 1 {
+                Tap::open_named_multi_queue(host_dev_name)
+            } else {
+                Tap::open_named(host_dev_name)
+            }
+            .map_err(Error::TapOpen)?;
+
+            // Set offload flags to match the virtio features of the device.
+            tap.set_offload(
+                net_gen::TUN_F_CSUM
+                    | net_gen::TUN_F_UFO
+                    | net_gen::TUN_F_TSO4
+                    | net_gen::TUN_F_TSO6,
+            )
+            .map_err(Error::TapSetOffload)?;
+
+            let vnet_hdr_size = vnet_hdr_len() as i32;
+            tap.set_vnet_hdr_size(vnet_hdr_size)
+                .map_err(Error::TapSetVnetHdrSize)?;
+
+            Ok(Box::new(tap))
+        }
+        BackendConfig::UnixSocket => Ok(Box::new(
+            UnixSocketBackend::connect(host_dev_name).map_err(Error::UnixSocketConnect)?,
+        )),
+        BackendConfig::Xdp(xsk_map_path) => Ok(Box::new(
+            XdpSocket::open(host_dev_name, queue_index as u32, xsk_map_path)
+                .map_err(Error::XdpSocket)?,
+        )),
+    }
+}
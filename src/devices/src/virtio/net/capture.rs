@@ -0,0 +1,320 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Capture of the frames exchanged by the net device with the guest, in the pcap or pcapng
+//! file formats.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// The default maximum number of bytes captured from each frame.
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+
+// The link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u16 = 1;
+
+// The magic number of pcap files with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+
+const PCAPNG_SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_VERSION_MAJOR: u16 = 1;
+const PCAPNG_VERSION_MINOR: u16 = 0;
+// The `epb_flags` option of enhanced packet blocks, carrying the direction of the frame.
+const PCAPNG_OPT_EPB_FLAGS: u16 = 2;
+const PCAPNG_EPB_FLAGS_INBOUND: u32 = 1;
+const PCAPNG_EPB_FLAGS_OUTBOUND: u32 = 2;
+
+/// The format of a capture file.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CaptureFormat {
+    /// The classic libpcap format.
+    Pcap,
+    /// The pcapng format, which also records the direction of the frames.
+    Pcapng,
+}
+
+impl Default for CaptureFormat {
+    fn default() -> Self {
+        CaptureFormat::Pcap
+    }
+}
+
+/// The direction of a captured frame, from the point of view of the guest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Direction {
+    /// A frame received by the guest.
+    Rx,
+    /// A frame sent by the guest.
+    Tx,
+}
+
+/// Writes the frames exchanged with the guest to a capture file.
+pub struct PacketCapture {
+    writer: BufWriter<File>,
+    format: CaptureFormat,
+    snaplen: u32,
+    // The maximum number of records kept in `ring`, when the capture is a ring.
+    ring_size: Option<usize>,
+    // The most recent records, only written to the file when the capture stops.
+    ring: VecDeque<Vec<u8>>,
+}
+
+impl PacketCapture {
+    /// Starts a capture into `file`, keeping at most `snaplen` bytes of each frame. When
+    /// `ring_size` is set, only the `ring_size` most recent frames are kept in memory and
+    /// written to the file when the capture stops. Otherwise, the frames are written as they
+    /// are captured.
+    pub fn new(
+        file: File,
+        format: CaptureFormat,
+        snaplen: u32,
+        ring_size: Option<usize>,
+    ) -> Result<Self> {
+        let mut capture = PacketCapture {
+            writer: BufWriter::new(file),
+            format,
+            snaplen,
+            ring_size,
+            ring: VecDeque::new(),
+        };
+        let header = capture.file_header();
+        capture.writer.write_all(&header)?;
+
+        Ok(capture)
+    }
+
+    /// Records the Ethernet frame in `frame`.
+    pub(crate) fn record(&mut self, direction: Direction, frame: &[u8]) -> Result<()> {
+        let record = self.frame_record(direction, frame);
+        match self.ring_size {
+            Some(ring_size) => {
+                if self.ring.len() == ring_size {
+                    self.ring.pop_front();
+                }
+                if ring_size > 0 {
+                    self.ring.push_back(record);
+                }
+                Ok(())
+            }
+            None => self.writer.write_all(&record),
+        }
+    }
+
+    /// Stops the capture, writing the pending records to the file.
+    pub fn finish(mut self) -> Result<()> {
+        for record in self.ring.drain(..) {
+            self.writer.write_all(&record)?;
+        }
+        self.writer.flush()
+    }
+
+    fn file_header(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        match self.format {
+            CaptureFormat::Pcap => {
+                header.extend_from_slice(&PCAP_MAGIC.to_ne_bytes());
+                header.extend_from_slice(&PCAP_VERSION_MAJOR.to_ne_bytes());
+                header.extend_from_slice(&PCAP_VERSION_MINOR.to_ne_bytes());
+                // The timezone offset and the accuracy of the timestamps.
+                header.extend_from_slice(&0i32.to_ne_bytes());
+                header.extend_from_slice(&0u32.to_ne_bytes());
+                header.extend_from_slice(&self.snaplen.to_ne_bytes());
+                header.extend_from_slice(&u32::from(LINKTYPE_ETHERNET).to_ne_bytes());
+            }
+            CaptureFormat::Pcapng => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_ne_bytes());
+                body.extend_from_slice(&PCAPNG_VERSION_MAJOR.to_ne_bytes());
+                body.extend_from_slice(&PCAPNG_VERSION_MINOR.to_ne_bytes());
+                // The length of the section is not specified.
+                body.extend_from_slice(&(-1i64).to_ne_bytes());
+                push_pcapng_block(&mut header, PCAPNG_SECTION_HEADER_BLOCK, &body);
+
+                // The section holds a single interface, the one of the device.
+                let mut body = Vec::new();
+                body.extend_from_slice(&LINKTYPE_ETHERNET.to_ne_bytes());
+                body.extend_from_slice(&0u16.to_ne_bytes());
+                body.extend_from_slice(&self.snaplen.to_ne_bytes());
+                push_pcapng_block(&mut header, PCAPNG_INTERFACE_DESCRIPTION_BLOCK, &body);
+            }
+        }
+        header
+    }
+
+    fn frame_record(&self, direction: Direction, frame: &[u8]) -> Vec<u8> {
+        // The clock can't be set before the epoch.
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let captured = &frame[..frame.len().min(self.snaplen as usize)];
+
+        let mut record = Vec::new();
+        match self.format {
+            CaptureFormat::Pcap => {
+                record.extend_from_slice(&(timestamp.as_secs() as u32).to_ne_bytes());
+                record.extend_from_slice(&timestamp.subsec_micros().to_ne_bytes());
+                record.extend_from_slice(&(captured.len() as u32).to_ne_bytes());
+                record.extend_from_slice(&(frame.len() as u32).to_ne_bytes());
+                record.extend_from_slice(captured);
+            }
+            CaptureFormat::Pcapng => {
+                // The timestamps have the default resolution of one microsecond.
+                let micros = timestamp.as_micros() as u64;
+                let mut body = Vec::new();
+                // The index of the interface.
+                body.extend_from_slice(&0u32.to_ne_bytes());
+                body.extend_from_slice(&((micros >> 32) as u32).to_ne_bytes());
+                body.extend_from_slice(&(micros as u32).to_ne_bytes());
+                body.extend_from_slice(&(captured.len() as u32).to_ne_bytes());
+                body.extend_from_slice(&(frame.len() as u32).to_ne_bytes());
+                body.extend_from_slice(captured);
+                pad_to_u32(&mut body);
+
+                let flags = match direction {
+                    Direction::Rx => PCAPNG_EPB_FLAGS_INBOUND,
+                    Direction::Tx => PCAPNG_EPB_FLAGS_OUTBOUND,
+                };
+                body.extend_from_slice(&PCAPNG_OPT_EPB_FLAGS.to_ne_bytes());
+                body.extend_from_slice(&4u16.to_ne_bytes());
+                body.extend_from_slice(&flags.to_ne_bytes());
+                // The end of the options.
+                body.extend_from_slice(&0u32.to_ne_bytes());
+                push_pcapng_block(&mut record, PCAPNG_ENHANCED_PACKET_BLOCK, &body);
+            }
+        }
+        record
+    }
+}
+
+// Pads `buf` with zeroes up to a multiple of 32 bits, as required by pcapng blocks.
+fn pad_to_u32(buf: &mut Vec<u8>) {
+    let padded_len = (buf.len() + 3) & !3;
+    buf.resize(padded_len, 0);
+}
+
+// Appends to `buf` a pcapng block of type `block_type`, whose body is `body`. The total length
+// of the block is written before and after the body.
+fn push_pcapng_block(buf: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let block_len = (body.len() + 12) as u32;
+    buf.extend_from_slice(&block_type.to_ne_bytes());
+    buf.extend_from_slice(&block_len.to_ne_bytes());
+    buf.extend_from_slice(body);
+    buf.extend_from_slice(&block_len.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::fs;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn read_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_pcap() {
+        let file = TempFile::new().unwrap();
+        let mut capture = PacketCapture::new(
+            file.as_file().try_clone().unwrap(),
+            CaptureFormat::Pcap,
+            4,
+            None,
+        )
+        .unwrap();
+        capture.record(Direction::Tx, &[1, 2, 3, 4, 5, 6]).unwrap();
+        capture.record(Direction::Rx, &[7, 8]).unwrap();
+        capture.finish().unwrap();
+
+        let content = fs::read(file.as_path()).unwrap();
+        // The file header, followed by two records.
+        assert_eq!(content.len(), 24 + (16 + 4) + (16 + 2));
+        assert_eq!(read_u32(&content, 0), PCAP_MAGIC);
+        assert_eq!(read_u32(&content, 16), 4);
+        assert_eq!(read_u32(&content, 20), u32::from(LINKTYPE_ETHERNET));
+        // The first frame is truncated to the snaplen.
+        assert_eq!(read_u32(&content, 24 + 8), 4);
+        assert_eq!(read_u32(&content, 24 + 12), 6);
+        assert_eq!(&content[24 + 16..24 + 20], &[1, 2, 3, 4]);
+        assert_eq!(read_u32(&content, 44 + 8), 2);
+        assert_eq!(read_u32(&content, 44 + 12), 2);
+        assert_eq!(&content[44 + 16..], &[7, 8]);
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let file = TempFile::new().unwrap();
+        let mut capture = PacketCapture::new(
+            file.as_file().try_clone().unwrap(),
+            CaptureFormat::Pcapng,
+            DEFAULT_SNAPLEN,
+            None,
+        )
+        .unwrap();
+        capture.record(Direction::Rx, &[1, 2, 3, 4, 5]).unwrap();
+        capture.record(Direction::Tx, &[6, 7, 8, 9]).unwrap();
+        capture.finish().unwrap();
+
+        let content = fs::read(file.as_path()).unwrap();
+        // The section header block.
+        assert_eq!(read_u32(&content, 0), PCAPNG_SECTION_HEADER_BLOCK);
+        assert_eq!(read_u32(&content, 4), 28);
+        assert_eq!(read_u32(&content, 8), PCAPNG_BYTE_ORDER_MAGIC);
+        assert_eq!(read_u32(&content, 24), 28);
+        // The interface description block.
+        assert_eq!(read_u32(&content, 28), PCAPNG_INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(read_u32(&content, 32), 20);
+        assert_eq!(read_u32(&content, 40), DEFAULT_SNAPLEN);
+        // The enhanced packet blocks, the frames being padded to 32 bits.
+        let epb = &content[48..];
+        assert_eq!(read_u32(epb, 0), PCAPNG_ENHANCED_PACKET_BLOCK);
+        assert_eq!(read_u32(epb, 4), 52);
+        assert_eq!(read_u32(epb, 20), 5);
+        assert_eq!(read_u32(epb, 24), 5);
+        assert_eq!(&epb[28..36], &[1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(read_u32(epb, 40), PCAPNG_EPB_FLAGS_INBOUND);
+        assert_eq!(read_u32(epb, 48), 52);
+        let epb = &epb[52..];
+        assert_eq!(read_u32(epb, 4), 48);
+        assert_eq!(&epb[28..32], &[6, 7, 8, 9]);
+        assert_eq!(read_u32(epb, 36), PCAPNG_EPB_FLAGS_OUTBOUND);
+        assert_eq!(epb.len(), 48);
+    }
+
+    #[test]
+    fn test_ring() {
+        let file = TempFile::new().unwrap();
+        let mut capture = PacketCapture::new(
+            file.as_file().try_clone().unwrap(),
+            CaptureFormat::Pcap,
+            DEFAULT_SNAPLEN,
+            Some(2),
+        )
+        .unwrap();
+        for i in 0..5 {
+            capture.record(Direction::Tx, &[i]).unwrap();
+        }
+        // The records are only written when the capture stops.
+        capture.writer.flush().unwrap();
+        assert_eq!(fs::read(file.as_path()).unwrap().len(), 24);
+
+        capture.finish().unwrap();
+        let content = fs::read(file.as_path()).unwrap();
+        // Only the two most recent frames are kept.
+        assert_eq!(content.len(), 24 + 2 * 17);
+        assert_eq!(content[24 + 16], 3);
+        assert_eq!(content[24 + 17 + 16], 4);
+    }
+}
@@ -5,25 +5,29 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use crate::virtio::net::anti_spoofing::AntiSpoofingFilter;
+use crate::virtio::net::backend::{open_backend, BackendConfig, NetBackend};
+use crate::virtio::net::capture::{Direction, PacketCapture};
+#[cfg(test)]
 use crate::virtio::net::tap::Tap;
 #[cfg(test)]
 use crate::virtio::net::test_utils::Mocks;
 use crate::virtio::net::Error;
 use crate::virtio::net::Result;
-use crate::virtio::net::{MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX};
+use crate::virtio::net::{MAX_BUFFER_SIZE, MAX_QUEUE_PAIRS, QUEUE_SIZE, RX_INDEX, TX_INDEX};
 use crate::virtio::{
-    ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_VRING,
+    ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_CONFIG,
+    VIRTIO_MMIO_INT_VRING,
 };
 use crate::{report_net_event_fail, Error as DeviceError};
 
 use dumbo::pdu::ethernet::EthernetFrame;
 use libc::EAGAIN;
-use logger::{error, warn, IncMetric, METRICS};
+use logger::{error, warn, IncMetric, NetQueuePairMetrics, METRICS};
+use mmds::dhcp::DhcpServer;
 use mmds::ns::MmdsNetworkStack;
 use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
-#[cfg(not(test))]
 use std::io;
-use std::io::{Read, Write};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -31,12 +35,18 @@ use std::{cmp, mem, result};
 use utils::eventfd::EventFd;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use virtio_gen::virtio_net::{
-    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
+    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_ANNOUNCE, VIRTIO_NET_CTRL_ANNOUNCE_ACK,
+    VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
+    VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_ANNOUNCE, VIRTIO_NET_F_GUEST_CSUM,
     VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MAC,
+    VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ, VIRTIO_NET_F_STATUS, VIRTIO_NET_OK, VIRTIO_NET_S_ANNOUNCE,
+    VIRTIO_NET_S_LINK_UP,
 };
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
+// The control queue commands we handle are a few bytes long.
+const CTRL_COMMAND_MAX_LEN: usize = 16;
+
 enum FrontendError {
     AddUsed,
     DescriptorChainTooSmall,
@@ -49,6 +59,16 @@ pub(crate) fn vnet_hdr_len() -> usize {
     mem::size_of::<virtio_net_hdr_v1>()
 }
 
+// Returns the index of the rx queue of queue pair `q` in the queues/queue_evts vectors.
+pub(crate) fn rx_index(q: usize) -> usize {
+    2 * q + RX_INDEX
+}
+
+// Returns the index of the tx queue of queue pair `q` in the queues/queue_evts vectors.
+pub(crate) fn tx_index(q: usize) -> usize {
+    2 * q + TX_INDEX
+}
+
 // Frames being sent/received through the network device model have a VNET header. This
 // function returns a slice which holds the L2 frame bytes without this header.
 fn frame_bytes_from_buf(buf: &[u8]) -> Result<&[u8]> {
@@ -68,7 +88,7 @@ fn frame_bytes_from_buf_mut(buf: &mut [u8]) -> Result<&mut [u8]> {
 }
 
 // This initializes to all 0 the VNET hdr part of a buf.
-fn init_vnet_hdr(buf: &mut [u8]) {
+pub(crate) fn init_vnet_hdr(buf: &mut [u8]) {
     // The buffer should be larger than vnet_hdr_len.
     // TODO: any better way to set all these bytes to 0? Or is this optimized by the compiler?
     for i in &mut buf[0..vnet_hdr_len()] {
@@ -77,42 +97,83 @@ fn init_vnet_hdr(buf: &mut [u8]) {
 }
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct ConfigSpace {
     pub guest_mac: [u8; MAC_ADDR_LEN],
+    pub status: u16,
+    pub max_virtqueue_pairs: u16,
 }
 
 impl Default for ConfigSpace {
     fn default() -> ConfigSpace {
         ConfigSpace {
             guest_mac: [0; MAC_ADDR_LEN],
+            status: VIRTIO_NET_S_LINK_UP as u16,
+            max_virtqueue_pairs: 1,
         }
     }
 }
 
 unsafe impl ByteValued for ConfigSpace {}
 
-pub struct Net {
-    pub(crate) id: String,
-
-    pub(crate) tap: Tap,
-
-    pub(crate) avail_features: u64,
-    pub(crate) acked_features: u64,
-
-    pub(crate) queues: Vec<Queue>,
-    pub(crate) queue_evts: Vec<EventFd>,
+// The state of an rx/tx queue pair. Each queue pair is backed by its own instance of the
+// host backend (e.g. a queue of the tap interface) and has its own rate limiters.
+pub(crate) struct QueuePair {
+    pub(crate) backend: Box<dyn NetBackend>,
 
     pub(crate) rx_rate_limiter: RateLimiter,
     pub(crate) tx_rate_limiter: RateLimiter,
 
     pub(crate) rx_deferred_frame: bool,
-    rx_deferred_irqs: bool,
 
     rx_bytes_read: usize,
     rx_frame_buf: [u8; MAX_BUFFER_SIZE],
 
     tx_iovec: Vec<(GuestAddress, usize)>,
     tx_frame_buf: [u8; MAX_BUFFER_SIZE],
+}
+
+impl QueuePair {
+    fn new(
+        backend: Box<dyn NetBackend>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Self {
+        QueuePair {
+            backend,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            rx_deferred_frame: false,
+            rx_bytes_read: 0,
+            rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
+            tx_iovec: Vec::with_capacity(QUEUE_SIZE as usize),
+            tx_frame_buf: [0u8; MAX_BUFFER_SIZE],
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn tap(&self) -> &Tap {
+        self.backend.as_any().downcast_ref::<Tap>().unwrap()
+    }
+}
+
+pub struct Net {
+    pub(crate) id: String,
+
+    pub(crate) host_dev_name: String,
+    pub(crate) backend_config: BackendConfig,
+
+    pub(crate) queue_pairs: Vec<QueuePair>,
+    // Number of queue pairs enabled by the driver, the others are not used.
+    pub(crate) active_queue_pairs: usize,
+
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+
+    rx_deferred_irqs: bool,
 
     pub(crate) interrupt_status: Arc<AtomicUsize>,
     pub(crate) interrupt_evt: EventFd,
@@ -123,7 +184,16 @@ pub struct Net {
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
 
+    // Signals that the backends of the device were replaced while it was active, so that
+    // the event manager registrations can be moved to the new ones.
+    pub(crate) backend_swap_evt: EventFd,
+    // The replaced backends, kept open until they are unregistered from the event manager.
+    pub(crate) detached_backends: Vec<Box<dyn NetBackend>>,
+
     pub(crate) mmds_ns: Option<MmdsNetworkStack>,
+    pub(crate) dhcp_server: Option<DhcpServer>,
+    pub(crate) anti_spoofing: Option<AntiSpoofingFilter>,
+    pub(crate) capture: Option<PacketCapture>,
 
     #[cfg(test)]
     pub(crate) mocks: Mocks,
@@ -139,25 +209,73 @@ impl Net {
         tx_rate_limiter: RateLimiter,
         allow_mmds_requests: bool,
     ) -> Result<Self> {
-        let tap = Tap::open_named(&tap_if_name).map_err(Error::TapOpen)?;
+        Self::new_with_multi_queue_tap(
+            id,
+            tap_if_name,
+            guest_mac,
+            vec![(rx_rate_limiter, tx_rate_limiter)],
+            allow_mmds_requests,
+            false,
+        )
+    }
 
-        // Set offload flags to match the virtio features below.
-        tap.set_offload(
-            net_gen::TUN_F_CSUM | net_gen::TUN_F_UFO | net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6,
+    /// Create a new virtio network device with one rx/tx queue pair for each pair of
+    /// rate limiters in `rate_limiters`. When there is more than one queue pair, the TAP
+    /// interface is opened in multi-queue mode and every queue pair gets its own TAP queue.
+    /// When `guest_announce` is set, the guest can be asked to announce itself on the network
+    /// through `update_link()`.
+    pub fn new_with_multi_queue_tap(
+        id: String,
+        tap_if_name: String,
+        guest_mac: Option<&MacAddr>,
+        rate_limiters: Vec<(RateLimiter, RateLimiter)>,
+        allow_mmds_requests: bool,
+        guest_announce: bool,
+    ) -> Result<Self> {
+        Self::new(
+            id,
+            tap_if_name,
+            BackendConfig::Tap,
+            guest_mac,
+            rate_limiters,
+            allow_mmds_requests,
+            guest_announce,
         )
-        .map_err(Error::TapSetOffload)?;
+    }
+
+    /// Create a new virtio network device backed by `host_dev_name`, which is interpreted
+    /// according to `backend_config`. Each pair of rate limiters in `rate_limiters` gets its
+    /// own rx/tx queue pair, backed by its own backend instance.
+    pub fn new(
+        id: String,
+        host_dev_name: String,
+        backend_config: BackendConfig,
+        guest_mac: Option<&MacAddr>,
+        rate_limiters: Vec<(RateLimiter, RateLimiter)>,
+        allow_mmds_requests: bool,
+        guest_announce: bool,
+    ) -> Result<Self> {
+        let num_queue_pairs = rate_limiters.len();
+        if num_queue_pairs == 0 || num_queue_pairs > MAX_QUEUE_PAIRS {
+            return Err(Error::InvalidQueuePairs(num_queue_pairs));
+        }
+        let multi_queue = num_queue_pairs > 1;
 
-        let vnet_hdr_size = vnet_hdr_len() as i32;
-        tap.set_vnet_hdr_size(vnet_hdr_size)
-            .map_err(Error::TapSetVnetHdrSize)?;
+        let mut queue_pairs = Vec::with_capacity(num_queue_pairs);
+        for (index, (rx_rate_limiter, tx_rate_limiter)) in rate_limiters.into_iter().enumerate() {
+            let backend = open_backend(&backend_config, &host_dev_name, index, num_queue_pairs)?;
+            queue_pairs.push(QueuePair::new(backend, rx_rate_limiter, tx_rate_limiter));
+        }
 
-        let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
-            | 1 << VIRTIO_NET_F_CSUM
-            | 1 << VIRTIO_NET_F_GUEST_TSO4
-            | 1 << VIRTIO_NET_F_GUEST_UFO
-            | 1 << VIRTIO_NET_F_HOST_TSO4
-            | 1 << VIRTIO_NET_F_HOST_UFO
-            | 1 << VIRTIO_F_VERSION_1;
+        let mut avail_features = 1 << VIRTIO_NET_F_STATUS | 1 << VIRTIO_F_VERSION_1;
+        if backend_config.supports_offloads() {
+            avail_features |= 1 << VIRTIO_NET_F_GUEST_CSUM
+                | 1 << VIRTIO_NET_F_CSUM
+                | 1 << VIRTIO_NET_F_GUEST_TSO4
+                | 1 << VIRTIO_NET_F_GUEST_UFO
+                | 1 << VIRTIO_NET_F_HOST_TSO4
+                | 1 << VIRTIO_NET_F_HOST_UFO;
+        }
 
         let mut config_space = ConfigSpace::default();
         if let Some(mac) = guest_mac {
@@ -167,44 +285,104 @@ impl Net {
             avail_features |= 1 << VIRTIO_NET_F_MAC;
         }
 
+        // The rx/tx queues of each pair, followed by the control queue when there are
+        // several pairs or guest announcements are enabled. The driver enables the extra
+        // pairs and acknowledges the announcements through the control queue.
+        let mut num_queues = 2 * num_queue_pairs;
+        if multi_queue {
+            avail_features |= 1 << VIRTIO_NET_F_MQ;
+            config_space.max_virtqueue_pairs = num_queue_pairs as u16;
+        }
+        if guest_announce {
+            avail_features |= 1 << VIRTIO_NET_F_GUEST_ANNOUNCE;
+        }
+        if multi_queue || guest_announce {
+            avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
+            num_queues += 1;
+        }
+
         let mut queue_evts = Vec::new();
-        for _ in QUEUE_SIZES.iter() {
+        for _ in 0..num_queues {
             queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
         }
 
-        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let queues = (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect();
 
         let mmds_ns = if allow_mmds_requests {
             Some(MmdsNetworkStack::new_with_defaults(None))
         } else {
             None
         };
-        Ok(Net {
+        let mut net = Net {
             id,
-            tap,
+            host_dev_name,
+            backend_config,
+            queue_pairs,
+            // All the backends are attached when opened.
+            active_queue_pairs: num_queue_pairs,
             avail_features,
             acked_features: 0u64,
             queues,
             queue_evts,
-            rx_rate_limiter,
-            tx_rate_limiter,
-            rx_deferred_frame: false,
             rx_deferred_irqs: false,
-            rx_bytes_read: 0,
-            rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
-            tx_frame_buf: [0u8; MAX_BUFFER_SIZE],
-            tx_iovec: Vec::with_capacity(QUEUE_SIZE as usize),
             interrupt_status: Arc::new(AtomicUsize::new(0)),
             interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            backend_swap_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            detached_backends: Vec::new(),
             config_space,
             mmds_ns,
+            dhcp_server: None,
+            anti_spoofing: None,
+            capture: None,
             guest_mac: guest_mac.copied(),
 
             #[cfg(test)]
             mocks: Mocks::default(),
-        })
+        };
+        // Only the first queue pair is used until the driver enables more.
+        net.set_active_queue_pairs(1)?;
+
+        Ok(net)
+    }
+
+    /// Replaces the backend of the device by `host_dev_name`, of the same kind as the current
+    /// one. The frames queued on the previous backend are dropped.
+    pub fn swap_backend(&mut self, host_dev_name: &str) -> Result<()> {
+        // AF_XDP sockets need system calls which are not allowed once the microVM runs.
+        if let BackendConfig::Xdp(_) = self.backend_config {
+            return Err(Error::BackendSwapUnsupported);
+        }
+
+        let num_queue_pairs = self.queue_pairs.len();
+        let mut backends = Vec::with_capacity(num_queue_pairs);
+        for index in 0..num_queue_pairs {
+            let backend =
+                open_backend(&self.backend_config, host_dev_name, index, num_queue_pairs)?;
+            // The backends are attached when opened, detach the ones of the unused pairs.
+            if index >= self.active_queue_pairs {
+                backend.set_queue_attached(false)?;
+            }
+            backends.push(backend);
+        }
+
+        let activated = self.is_activated();
+        for (pair, backend) in self.queue_pairs.iter_mut().zip(backends) {
+            let old_backend = mem::replace(&mut pair.backend, backend);
+            // The backends of an active device are registered to the event manager, they
+            // are released once the event handler swaps the registrations.
+            if activated {
+                self.detached_backends.push(old_backend);
+            }
+        }
+        if activated {
+            self.backend_swap_evt.write(1).map_err(Error::EventFd)?;
+        }
+        self.host_dev_name = host_dev_name.to_string();
+        METRICS.net.tap_swaps.inc();
+
+        Ok(())
     }
 
     /// Provides the ID of this net device.
@@ -217,11 +395,100 @@ impl Net {
         self.guest_mac.as_ref()
     }
 
+    /// Provides the number of rx/tx queue pairs of this net device.
+    pub fn num_queue_pairs(&self) -> usize {
+        self.queue_pairs.len()
+    }
+
     /// Provides a mutable reference to the `MmdsNetworkStack`.
     pub fn mmds_ns_mut(&mut self) -> Option<&mut MmdsNetworkStack> {
         self.mmds_ns.as_mut()
     }
 
+    /// Provides the DHCP server configuring the guest interface, if any.
+    pub fn dhcp_server(&self) -> Option<&DhcpServer> {
+        self.dhcp_server.as_ref()
+    }
+
+    /// Sets the DHCP server answering the DHCP messages of the guest, which then never reach
+    /// the backend.
+    pub fn set_dhcp_server(&mut self, dhcp_server: Option<DhcpServer>) {
+        self.dhcp_server = dhcp_server;
+    }
+
+    /// Provides the filter of the frames sent by the guest, if any.
+    pub fn anti_spoofing_filter(&self) -> Option<&AntiSpoofingFilter> {
+        self.anti_spoofing.as_ref()
+    }
+
+    /// Sets the filter of the frames sent by the guest. The frames the filter rejects are
+    /// dropped instead of being sent to the backend.
+    pub fn set_anti_spoofing_filter(&mut self, filter: Option<AntiSpoofingFilter>) {
+        self.anti_spoofing = filter;
+    }
+
+    /// Starts capturing the frames exchanged with the guest, including the ones handled
+    /// by MMDS.
+    pub fn start_capture(&mut self, capture: PacketCapture) -> Result<()> {
+        if self.capture.is_some() {
+            return Err(Error::CaptureInProgress);
+        }
+        self.capture = Some(capture);
+
+        Ok(())
+    }
+
+    /// Stops the running packet capture, writing its pending frames to the capture file.
+    pub fn stop_capture(&mut self) -> Result<()> {
+        self.capture
+            .take()
+            .ok_or(Error::NoCapture)?
+            .finish()
+            .map_err(Error::CaptureWrite)
+    }
+
+    // Records the frame in `frame_buf`, prefixed by the VNET header, in the running packet
+    // capture, if any.
+    fn capture_frame(capture: Option<&mut PacketCapture>, direction: Direction, frame_buf: &[u8]) {
+        if let (Some(capture), Ok(frame)) = (capture, frame_bytes_from_buf(frame_buf)) {
+            match capture.record(direction, frame) {
+                Ok(()) => METRICS.net.capture_frames.inc(),
+                Err(e) => {
+                    error!("Failed to capture frame: {:?}", e);
+                    METRICS.net.capture_fails.inc();
+                }
+            }
+        }
+    }
+
+    // Returns the index of the control queue, if the device has one.
+    pub(crate) fn ctrl_queue_index(&self) -> Option<usize> {
+        let index = 2 * self.queue_pairs.len();
+        if index < self.queues.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    // Attaches the tap queues of the first `count` queue pairs and detaches the other ones,
+    // so that the kernel only steers incoming traffic to the queue pairs in use.
+    pub(crate) fn set_active_queue_pairs(&mut self, count: usize) -> Result<()> {
+        if count == 0 || count > self.queue_pairs.len() {
+            return Err(Error::InvalidQueuePairs(count));
+        }
+
+        for (index, pair) in self.queue_pairs.iter().enumerate() {
+            let attached = index < count;
+            if attached != (index < self.active_queue_pairs) {
+                pair.backend.set_queue_attached(attached)?;
+            }
+        }
+        self.active_queue_pairs = count;
+
+        Ok(())
+    }
+
     fn signal_used_queue(&mut self) -> result::Result<(), DeviceError> {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
@@ -235,6 +502,58 @@ impl Net {
         Ok(())
     }
 
+    fn signal_config_change(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal config change: {:?}", e);
+            METRICS.net.event_fails.inc();
+            Error::EventFd(e)
+        })
+    }
+
+    /// Provides the link state reported to the guest.
+    pub fn link_up(&self) -> bool {
+        self.config_space.status & VIRTIO_NET_S_LINK_UP as u16 != 0
+    }
+
+    /// Checks that the link can be updated, asking the guest to announce itself if `announce`
+    /// is set.
+    pub fn check_link_update(&self, announce: bool) -> Result<()> {
+        if announce && self.acked_features & (1 << VIRTIO_NET_F_GUEST_ANNOUNCE) == 0 {
+            return Err(Error::GuestAnnounceNotNegotiated);
+        }
+
+        Ok(())
+    }
+
+    /// Updates the link state reported to the guest and, if `announce` is set, asks the
+    /// guest to announce itself on the network (e.g. by sending gratuitous ARPs).
+    pub fn update_link(&mut self, link_up: Option<bool>, announce: bool) -> Result<()> {
+        self.check_link_update(announce)?;
+
+        let mut status = self.config_space.status;
+        match link_up {
+            Some(true) => status |= VIRTIO_NET_S_LINK_UP as u16,
+            Some(false) => status &= !(VIRTIO_NET_S_LINK_UP as u16),
+            None => (),
+        }
+        if announce {
+            status |= VIRTIO_NET_S_ANNOUNCE as u16;
+        }
+
+        if status != self.config_space.status {
+            self.config_space.status = status;
+            METRICS.net.link_state_updates.inc();
+            // The driver reads the new status upon the config change interrupt.
+            if self.is_activated() {
+                self.signal_config_change()?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn signal_rx_used_queue(&mut self) -> result::Result<(), DeviceError> {
         if self.rx_deferred_irqs {
             return self.signal_used_queue();
@@ -243,44 +562,48 @@ impl Net {
         Ok(())
     }
 
-    // Attempts to copy a single frame into the guest if there is enough
-    // rate limiting budget.
+    // Attempts to copy a single frame into the rx queue of queue pair `q` if there is
+    // enough rate limiting budget.
     // Returns true on successful frame delivery.
-    fn rate_limited_rx_single_frame(&mut self) -> bool {
+    fn rate_limited_rx_single_frame(&mut self, q: usize) -> bool {
+        let pair = &mut self.queue_pairs[q];
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
-        if !self.rx_rate_limiter.consume(1, TokenType::Ops) {
+        if !pair.rx_rate_limiter.consume(1, TokenType::Ops) {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            METRICS.net.queue_pairs[q].rx_rate_limiter_throttled.inc();
             return false;
         }
         // If limiter.consume() fails it means there is no more TokenType::Bytes
         // budget and rate limiting is in effect.
-        if !self
+        if !pair
             .rx_rate_limiter
-            .consume(self.rx_bytes_read as u64, TokenType::Bytes)
+            .consume(pair.rx_bytes_read as u64, TokenType::Bytes)
         {
             // revert the OPS consume()
-            self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+            pair.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
             METRICS.net.rx_rate_limiter_throttled.inc();
+            METRICS.net.queue_pairs[q].rx_rate_limiter_throttled.inc();
             return false;
         }
 
         // Attempt frame delivery.
-        let success = self.write_frame_to_guest();
+        let success = self.write_frame_to_guest(q);
 
         // Undo the tokens consumption if guest delivery failed.
         if !success {
+            let pair = &mut self.queue_pairs[q];
             // revert the OPS consume()
-            self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+            pair.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
             // revert the BYTES consume()
-            self.rx_rate_limiter
-                .manual_replenish(self.rx_bytes_read as u64, TokenType::Bytes);
+            pair.rx_rate_limiter
+                .manual_replenish(pair.rx_bytes_read as u64, TokenType::Bytes);
         }
         success
     }
 
-    // Copies a single frame from `self.rx_frame_buf` into the guest.
-    fn do_write_frame_to_guest(&mut self) -> std::result::Result<(), FrontendError> {
+    // Copies a single frame from the `rx_frame_buf` of queue pair `q` into the guest.
+    fn do_write_frame_to_guest(&mut self, q: usize) -> std::result::Result<(), FrontendError> {
         let mut result: std::result::Result<(), FrontendError> = Ok(());
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
@@ -288,14 +611,15 @@ impl Net {
             DeviceState::Inactive => unreachable!(),
         };
 
-        let queue = &mut self.queues[RX_INDEX];
+        let queue = &mut self.queues[rx_index(q)];
         let head_descriptor = queue.pop(mem).ok_or_else(|| {
             METRICS.net.no_rx_avail_buffer.inc();
             FrontendError::EmptyQueue
         })?;
         let head_index = head_descriptor.index;
 
-        let mut frame_slice = &self.rx_frame_buf[..self.rx_bytes_read];
+        let pair = &self.queue_pairs[q];
+        let mut frame_slice = &pair.rx_frame_buf[..pair.rx_bytes_read];
         let frame_len = frame_slice.len();
         let mut maybe_next_descriptor = Some(head_descriptor);
         while let Some(descriptor) = &maybe_next_descriptor {
@@ -345,16 +669,19 @@ impl Net {
         if result.is_ok() {
             METRICS.net.rx_bytes_count.add(frame_len);
             METRICS.net.rx_packets_count.inc();
+            METRICS.net.queue_pairs[q].rx_bytes_count.add(frame_len);
+            METRICS.net.queue_pairs[q].rx_packets_count.inc();
         }
         result
     }
 
-    // Copies a single frame from `self.rx_frame_buf` into the guest. In case of an error retries
-    // the operation if possible. Returns true if the operation was successfull.
-    fn write_frame_to_guest(&mut self) -> bool {
-        let max_iterations = self.queues[RX_INDEX].actual_size();
+    // Copies a single frame from the `rx_frame_buf` of queue pair `q` into the guest. In case
+    // of an error retries the operation if possible. Returns true if the operation was
+    // successfull.
+    fn write_frame_to_guest(&mut self, q: usize) -> bool {
+        let max_iterations = self.queues[rx_index(q)].actual_size();
         for _ in 0..max_iterations {
-            match self.do_write_frame_to_guest() {
+            match self.do_write_frame_to_guest(q) {
                 Ok(()) => return true,
                 Err(FrontendError::EmptyQueue) | Err(FrontendError::AddUsed) => {
                     return false;
@@ -369,17 +696,26 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to the DHCP server or to MMDS and if neither accepts it, sends
+    // it on the host TAP.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
-    // Returns whether MMDS consumed the frame.
+    // Returns whether the DHCP server or MMDS consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
+        dhcp_server: Option<&mut DhcpServer>,
         rate_limiter: &mut RateLimiter,
         frame_buf: &[u8],
-        tap: &mut Tap,
+        backend: &mut dyn NetBackend,
         guest_mac: Option<MacAddr>,
+        anti_spoofing: Option<&AntiSpoofingFilter>,
+        capture: Option<&mut PacketCapture>,
+        queue_pair_metrics: &NetQueuePairMetrics,
     ) -> Result<bool> {
+        // Every frame sent by the guest is captured, whether it goes to MMDS or to the TAP.
+        Self::capture_frame(capture, Direction::Tx, frame_buf);
+
         let checked_frame = |frame_buf| {
             frame_bytes_from_buf(frame_buf).map_err(|e| {
                 error!("VNET header missing in the TX frame.");
@@ -387,6 +723,15 @@ impl Net {
                 e
             })
         };
+        if let Some(dhcp_server) = dhcp_server {
+            if dhcp_server.detour_frame(checked_frame(frame_buf)?) {
+                // DHCP frames are not accounted by the rate limiter either.
+                rate_limiter.manual_replenish(frame_buf.len() as u64, TokenType::Bytes);
+                rate_limiter.manual_replenish(1, TokenType::Ops);
+
+                return Ok(true);
+            }
+        }
         if let Some(ns) = mmds_ns {
             if ns.detour_frame(checked_frame(frame_buf)?) {
                 METRICS.mmds.rx_accepted.inc();
@@ -400,7 +745,12 @@ impl Net {
             }
         }
 
-        // This frame goes to the TAP.
+        // This frame goes to the TAP, unless the guest uses addresses it was not given.
+        if let Some(filter) = anti_spoofing {
+            if !filter.allows(checked_frame(frame_buf)?) {
+                return Ok(false);
+            }
+        }
 
         // Check for guest MAC spoofing.
         if let Some(mac) = guest_mac {
@@ -412,11 +762,13 @@ impl Net {
             });
         }
 
-        match tap.write(frame_buf) {
+        match backend.write_frame(frame_buf) {
             Ok(_) => {
                 METRICS.net.tx_bytes_count.add(frame_buf.len());
                 METRICS.net.tx_packets_count.inc();
                 METRICS.net.tx_count.inc();
+                queue_pair_metrics.tx_bytes_count.add(frame_buf.len());
+                queue_pair_metrics.tx_packets_count.inc();
             }
             Err(e) => {
                 error!("Failed to write to tap: {:?}", e);
@@ -426,32 +778,56 @@ impl Net {
         Ok(false)
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
-    fn read_from_mmds_or_tap(&mut self) -> Result<usize> {
-        if let Some(ns) = self.mmds_ns.as_mut() {
-            if let Some(len) =
-                ns.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+    // We currently prioritize packets from the DHCP server and the MMDS over regular network
+    // packets.
+    fn read_from_mmds_or_tap(&mut self, q: usize) -> Result<usize> {
+        if let Some(dhcp_server) = self.dhcp_server.as_mut() {
+            let rx_frame_buf = &mut self.queue_pairs[q].rx_frame_buf;
+            if let Some(len) = dhcp_server.write_next_frame(frame_bytes_from_buf_mut(rx_frame_buf)?)
             {
+                init_vnet_hdr(rx_frame_buf);
+                let len = vnet_hdr_len() + len.get();
+                Self::capture_frame(self.capture.as_mut(), Direction::Rx, &rx_frame_buf[..len]);
+                return Ok(len);
+            }
+        }
+        if let Some(ns) = self.mmds_ns.as_mut() {
+            let rx_frame_buf = &mut self.queue_pairs[q].rx_frame_buf;
+            if let Some(len) = ns.write_next_frame(frame_bytes_from_buf_mut(rx_frame_buf)?) {
                 let len = len.get();
                 METRICS.mmds.tx_frames.inc();
                 METRICS.mmds.tx_bytes.add(len);
-                init_vnet_hdr(&mut self.rx_frame_buf);
-                return Ok(vnet_hdr_len() + len);
+                init_vnet_hdr(rx_frame_buf);
+                let len = vnet_hdr_len() + len;
+                Self::capture_frame(self.capture.as_mut(), Direction::Rx, &rx_frame_buf[..len]);
+                return Ok(len);
             }
         }
 
-        self.read_tap().map_err(Error::IO)
+        // The tap queues of the inactive queue pairs are detached and can't be read.
+        if q >= self.active_queue_pairs {
+            return Err(Error::IO(io::Error::from_raw_os_error(EAGAIN)));
+        }
+
+        let len = self.read_tap(q).map_err(Error::IO)?;
+        Self::capture_frame(
+            self.capture.as_mut(),
+            Direction::Rx,
+            &self.queue_pairs[q].rx_frame_buf[..len],
+        );
+
+        Ok(len)
     }
 
-    fn process_rx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_rx(&mut self, q: usize) -> result::Result<(), DeviceError> {
         // Read as many frames as possible.
         loop {
-            match self.read_from_mmds_or_tap() {
+            match self.read_from_mmds_or_tap(q) {
                 Ok(count) => {
-                    self.rx_bytes_read = count;
+                    self.queue_pairs[q].rx_bytes_read = count;
                     METRICS.net.rx_count.inc();
-                    if !self.rate_limited_rx_single_frame() {
-                        self.rx_deferred_frame = true;
+                    if !self.rate_limited_rx_single_frame(q) {
+                        self.queue_pairs[q].rx_deferred_frame = true;
                         break;
                     }
                 }
@@ -480,26 +856,26 @@ impl Net {
     }
 
     // Process the deferred frame first, then continue reading from tap.
-    fn handle_deferred_frame(&mut self) -> result::Result<(), DeviceError> {
-        if self.rate_limited_rx_single_frame() {
-            self.rx_deferred_frame = false;
+    fn handle_deferred_frame(&mut self, q: usize) -> result::Result<(), DeviceError> {
+        if self.rate_limited_rx_single_frame(q) {
+            self.queue_pairs[q].rx_deferred_frame = false;
             // process_rx() was interrupted possibly before consuming all
             // packets in the tap; try continuing now.
-            return self.process_rx();
+            return self.process_rx(q);
         }
 
         self.signal_rx_used_queue()
     }
 
-    fn resume_rx(&mut self) -> result::Result<(), DeviceError> {
-        if self.rx_deferred_frame {
-            self.handle_deferred_frame()
+    fn resume_rx(&mut self, q: usize) -> result::Result<(), DeviceError> {
+        if self.queue_pairs[q].rx_deferred_frame {
+            self.handle_deferred_frame(q)
         } else {
             Ok(())
         }
     }
 
-    fn process_tx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_tx(&mut self, q: usize) -> result::Result<(), DeviceError> {
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
             // This should never happen, it's been already validated in the event handler.
@@ -512,16 +888,18 @@ impl Net {
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
         let mut raise_irq = false;
-        let tx_queue = &mut self.queues[TX_INDEX];
+        let tx_queue = &mut self.queues[tx_index(q)];
+        let pair = &mut self.queue_pairs[q];
 
         while let Some(head) = tx_queue.pop(mem) {
             // If limiter.consume() fails it means there is no more TokenType::Ops
             // budget and rate limiting is in effect.
-            if !self.tx_rate_limiter.consume(1, TokenType::Ops) {
+            if !pair.tx_rate_limiter.consume(1, TokenType::Ops) {
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
                 METRICS.net.tx_rate_limiter_throttled.inc();
+                METRICS.net.queue_pairs[q].tx_rate_limiter_throttled.inc();
                 break;
             }
 
@@ -529,29 +907,30 @@ impl Net {
             let mut read_count = 0;
             let mut next_desc = Some(head);
 
-            self.tx_iovec.clear();
+            pair.tx_iovec.clear();
             while let Some(desc) = next_desc {
                 if desc.is_write_only() {
-                    self.tx_iovec.clear();
+                    pair.tx_iovec.clear();
                     break;
                 }
-                self.tx_iovec.push((desc.addr, desc.len as usize));
+                pair.tx_iovec.push((desc.addr, desc.len as usize));
                 read_count += desc.len as usize;
                 next_desc = desc.next_descriptor();
             }
 
             // If limiter.consume() fails it means there is no more TokenType::Bytes
             // budget and rate limiting is in effect.
-            if !self
+            if !pair
                 .tx_rate_limiter
                 .consume(read_count as u64, TokenType::Bytes)
             {
                 // revert the OPS consume()
-                self.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
+                pair.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
                 METRICS.net.tx_rate_limiter_throttled.inc();
+                METRICS.net.queue_pairs[q].tx_rate_limiter_throttled.inc();
                 break;
             }
 
@@ -559,11 +938,11 @@ impl Net {
             // Copy buffer from across multiple descriptors.
             // TODO(performance - Issue #420): change this to use `writev()` instead of `write()`
             // and get rid of the intermediate buffer.
-            for (desc_addr, desc_len) in self.tx_iovec.drain(..) {
-                let limit = cmp::min((read_count + desc_len) as usize, self.tx_frame_buf.len());
+            for (desc_addr, desc_len) in pair.tx_iovec.drain(..) {
+                let limit = cmp::min((read_count + desc_len) as usize, pair.tx_frame_buf.len());
 
                 let read_result = mem.read_slice(
-                    &mut self.tx_frame_buf[read_count..limit as usize],
+                    &mut pair.tx_frame_buf[read_count..limit as usize],
                     desc_addr,
                 );
                 match read_result {
@@ -586,13 +965,17 @@ impl Net {
 
             let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
-                &mut self.tx_rate_limiter,
-                &self.tx_frame_buf[..read_count],
-                &mut self.tap,
+                self.dhcp_server.as_mut(),
+                &mut pair.tx_rate_limiter,
+                &pair.tx_frame_buf[..read_count],
+                pair.backend.as_mut(),
                 self.guest_mac,
+                self.anti_spoofing.as_ref(),
+                self.capture.as_mut(),
+                &METRICS.net.queue_pairs[q],
             )
             .unwrap_or_else(|_| false);
-            if frame_consumed_by_mmds && !self.rx_deferred_frame {
+            if frame_consumed_by_mmds && !pair.rx_deferred_frame {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
             }
@@ -611,13 +994,115 @@ impl Net {
 
         // An incoming frame for the MMDS may trigger the transmission of a new message.
         if process_rx_for_mmds {
-            self.process_rx()
+            self.process_rx(q)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Executes a control queue command and returns the status reported to the driver.
+    fn handle_ctrl_command(&mut self, command: &[u8]) -> u8 {
+        // Commands start with their class and code, followed by their data.
+        if command.len() < 2 {
+            return VIRTIO_NET_ERR as u8;
+        }
+        let data = &command[2..];
+
+        match (u32::from(command[0]), u32::from(command[1])) {
+            (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET) if data.len() == 2 => {
+                let count = u16::from_le_bytes([data[0], data[1]]) as usize;
+                match self.set_active_queue_pairs(count) {
+                    Ok(()) => VIRTIO_NET_OK as u8,
+                    Err(e) => {
+                        error!("Failed to set the number of queue pairs: {:?}", e);
+                        VIRTIO_NET_ERR as u8
+                    }
+                }
+            }
+            (VIRTIO_NET_CTRL_ANNOUNCE, VIRTIO_NET_CTRL_ANNOUNCE_ACK) => {
+                self.config_space.status &= !(VIRTIO_NET_S_ANNOUNCE as u16);
+                VIRTIO_NET_OK as u8
+            }
+            (class, cmd) => {
+                warn!("Unsupported net control command {}:{}", class, cmd);
+                VIRTIO_NET_ERR as u8
+            }
+        }
+    }
+
+    fn process_ctrl_queue(&mut self) -> result::Result<(), DeviceError> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        // Safe to unwrap, control queue events are only registered when there is one.
+        let ctrl_index = self.ctrl_queue_index().unwrap();
+        let mut used_any = false;
+
+        while let Some(head) = self.queues[ctrl_index].pop(&mem) {
+            let head_index = head.index;
+            let mut command = Vec::with_capacity(CTRL_COMMAND_MAX_LEN);
+            let mut status_addr = None;
+            let mut valid = true;
+            let mut next_desc = Some(head);
+
+            // The command is read from the device readable descriptors and its status is
+            // written to the first device writable one.
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    if desc.len > 0 {
+                        status_addr = Some(desc.addr);
+                    }
+                    break;
+                }
+                let start = command.len();
+                if start + desc.len as usize > CTRL_COMMAND_MAX_LEN {
+                    valid = false;
+                    break;
+                }
+                command.resize(start + desc.len as usize, 0);
+                if let Err(e) = mem.read_slice(&mut command[start..], desc.addr) {
+                    error!("Failed to read net control command: {:?}", e);
+                    valid = false;
+                    break;
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            let mut used_len = 0;
+            if let Some(addr) = status_addr {
+                let status = if valid {
+                    self.handle_ctrl_command(&command)
+                } else {
+                    VIRTIO_NET_ERR as u8
+                };
+                if status != VIRTIO_NET_OK as u8 {
+                    METRICS.net.ctrl_fails.inc();
+                }
+                match mem.write_obj(status, addr) {
+                    Ok(()) => used_len = 1,
+                    Err(e) => error!("Failed to write net control status: {:?}", e),
+                }
+            } else {
+                error!("Net control command without status descriptor");
+                METRICS.net.ctrl_fails.inc();
+            }
+
+            self.queues[ctrl_index]
+                .add_used(&mem, head_index, used_len)
+                .map_err(DeviceError::QueueError)?;
+            used_any = true;
+        }
+
+        if used_any {
+            self.signal_used_queue()
         } else {
             Ok(())
         }
     }
 
-    /// Updates the parameters for the rate limiters
+    /// Updates the parameters for the rate limiters of all the queue pairs.
     pub fn patch_rate_limiters(
         &mut self,
         rx_bytes: BucketUpdate,
@@ -625,33 +1110,39 @@ impl Net {
         tx_bytes: BucketUpdate,
         tx_ops: BucketUpdate,
     ) {
-        self.rx_rate_limiter.update_buckets(rx_bytes, rx_ops);
-        self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
+        for pair in self.queue_pairs.iter_mut() {
+            pair.rx_rate_limiter
+                .update_buckets(rx_bytes.clone(), rx_ops.clone());
+            pair.tx_rate_limiter
+                .update_buckets(tx_bytes.clone(), tx_ops.clone());
+        }
     }
 
     #[cfg(not(test))]
-    fn read_tap(&mut self) -> io::Result<usize> {
-        self.tap.read(&mut self.rx_frame_buf)
+    fn read_tap(&mut self, q: usize) -> io::Result<usize> {
+        let pair = &mut self.queue_pairs[q];
+        pair.backend.read_frame(&mut pair.rx_frame_buf)
     }
 
-    pub fn process_rx_queue_event(&mut self) {
+    pub fn process_rx_queue_event(&mut self, q: usize) {
         METRICS.net.rx_queue_event_count.inc();
 
-        if let Err(e) = self.queue_evts[RX_INDEX].read() {
+        if let Err(e) = self.queue_evts[rx_index(q)].read() {
             // rate limiters present but with _very high_ allowed rate
             error!("Failed to get rx queue event: {:?}", e);
             METRICS.net.event_fails.inc();
         } else {
             // If the limiter is not blocked, resume the receiving of bytes.
-            if !self.rx_rate_limiter.is_blocked() {
-                self.resume_rx().unwrap_or_else(report_net_event_fail);
+            if !self.queue_pairs[q].rx_rate_limiter.is_blocked() {
+                self.resume_rx(q).unwrap_or_else(report_net_event_fail);
             } else {
                 METRICS.net.rx_rate_limiter_throttled.inc();
+                METRICS.net.queue_pairs[q].rx_rate_limiter_throttled.inc();
             }
         }
     }
 
-    pub fn process_tap_rx_event(&mut self) {
+    pub fn process_tap_rx_event(&mut self, q: usize) {
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
             // This should never happen, it's been already validated in the event handler.
@@ -663,51 +1154,65 @@ impl Net {
         // don't process any more incoming. Otherwise start processing a frame. In the
         // process the deferred_frame flag will be set in order to avoid freezing the
         // RX queue.
-        if self.queues[RX_INDEX].is_empty(mem) && self.rx_deferred_frame {
+        if self.queues[rx_index(q)].is_empty(mem) && self.queue_pairs[q].rx_deferred_frame {
             METRICS.net.no_rx_avail_buffer.inc();
             return;
         }
 
         // While limiter is blocked, don't process any more incoming.
-        if self.rx_rate_limiter.is_blocked() {
+        if self.queue_pairs[q].rx_rate_limiter.is_blocked() {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            METRICS.net.queue_pairs[q].rx_rate_limiter_throttled.inc();
             return;
         }
 
-        if self.rx_deferred_frame
+        if self.queue_pairs[q].rx_deferred_frame
         // Process a deferred frame first if available. Don't read from tap again
         // until we manage to receive this deferred frame.
         {
-            self.handle_deferred_frame()
+            self.handle_deferred_frame(q)
                 .unwrap_or_else(report_net_event_fail);
         } else {
-            self.process_rx().unwrap_or_else(report_net_event_fail);
+            self.process_rx(q).unwrap_or_else(report_net_event_fail);
         }
     }
 
-    pub fn process_tx_queue_event(&mut self) {
+    pub fn process_tx_queue_event(&mut self, q: usize) {
         METRICS.net.tx_queue_event_count.inc();
-        if let Err(e) = self.queue_evts[TX_INDEX].read() {
+        if let Err(e) = self.queue_evts[tx_index(q)].read() {
             error!("Failed to get tx queue event: {:?}", e);
             METRICS.net.event_fails.inc();
-        } else if !self.tx_rate_limiter.is_blocked()
+        } else if !self.queue_pairs[q].tx_rate_limiter.is_blocked()
         // If the limiter is not blocked, continue transmitting bytes.
         {
-            self.process_tx().unwrap_or_else(report_net_event_fail);
+            self.process_tx(q).unwrap_or_else(report_net_event_fail);
         } else {
             METRICS.net.tx_rate_limiter_throttled.inc();
+            METRICS.net.queue_pairs[q].tx_rate_limiter_throttled.inc();
+        }
+    }
+
+    pub fn process_ctrl_queue_event(&mut self) {
+        // Safe to unwrap, control queue events are only registered when there is one.
+        let ctrl_index = self.ctrl_queue_index().unwrap();
+        if let Err(e) = self.queue_evts[ctrl_index].read() {
+            error!("Failed to get ctrl queue event: {:?}", e);
+            METRICS.net.event_fails.inc();
+        } else {
+            self.process_ctrl_queue()
+                .unwrap_or_else(report_net_event_fail);
         }
     }
 
-    pub fn process_rx_rate_limiter_event(&mut self) {
+    pub fn process_rx_rate_limiter_event(&mut self, q: usize) {
         METRICS.net.rx_event_rate_limiter_count.inc();
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
 
-        match self.rx_rate_limiter.event_handler() {
+        match self.queue_pairs[q].rx_rate_limiter.event_handler() {
             Ok(_) => {
                 // There might be enough budget now to receive the frame.
-                self.resume_rx().unwrap_or_else(report_net_event_fail);
+                self.resume_rx(q).unwrap_or_else(report_net_event_fail);
             }
             Err(e) => {
                 error!("Failed to get rx rate-limiter event: {:?}", e);
@@ -716,14 +1221,14 @@ impl Net {
         }
     }
 
-    pub fn process_tx_rate_limiter_event(&mut self) {
+    pub fn process_tx_rate_limiter_event(&mut self, q: usize) {
         METRICS.net.tx_rate_limiter_event_count.inc();
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
-        match self.tx_rate_limiter.event_handler() {
+        match self.queue_pairs[q].tx_rate_limiter.event_handler() {
             Ok(_) => {
                 // There might be enough budget now to send the frame.
-                self.process_tx().unwrap_or_else(report_net_event_fail);
+                self.process_tx(q).unwrap_or_else(report_net_event_fail);
             }
             Err(e) => {
                 error!("Failed to get tx rate-limiter event: {:?}", e);
@@ -734,8 +1239,13 @@ impl Net {
 
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
-        let _ = self.resume_rx();
-        let _ = self.process_tx();
+        for q in 0..self.queue_pairs.len() {
+            let _ = self.resume_rx(q);
+            let _ = self.process_tx(q);
+        }
+        if self.ctrl_queue_index().is_some() {
+            let _ = self.process_ctrl_queue();
+        }
     }
 }
 
@@ -795,7 +1305,8 @@ impl VirtioDevice for Net {
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         let data_len = data.len() as u64;
-        let config_space_bytes = self.config_space.as_mut_slice();
+        // Only the MAC address is writable by the driver.
+        let config_space_bytes = &mut self.config_space.guest_mac;
         let config_len = config_space_bytes.len() as u64;
         if offset + data_len > config_len {
             error!("Failed to write config space");
@@ -835,6 +1346,7 @@ pub mod tests {
         frame_bytes_from_buf, frame_bytes_from_buf_mut, init_vnet_hdr, vnet_hdr_len,
     };
     use std::net::Ipv4Addr;
+    use std::os::unix::io::AsRawFd;
     use std::sync::atomic::Ordering;
     use std::time::Duration;
     use std::{io, mem, thread};
@@ -842,18 +1354,25 @@ pub mod tests {
     use crate::check_metric_after_block;
     use crate::virtio::net::test_utils::test::TestHelper;
     use crate::virtio::net::test_utils::{
-        check_used_queue_signal, default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent,
-        NetQueue, ReadTapMock, TapTrafficSimulator,
+        check_used_queue_signal, default_guest_memory, default_net, if_index, inject_tap_tx_frame,
+        multi_queue_net, set_mac, NetEvent, NetQueue, ReadTapMock, TapTrafficSimulator,
     };
-    use crate::virtio::net::QUEUE_SIZES;
+    use crate::virtio::net::{CaptureFormat, DEFAULT_SNAPLEN, QUEUE_SIZES};
     use crate::virtio::{
         Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTIO_MMIO_INT_VRING,
         VIRTQ_DESC_F_WRITE,
     };
     use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-    use dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use dumbo::pdu::dhcp::{
+        DhcpMessage, CLIENT_PORT, DHCPDISCOVER, DHCPOFFER, OPTION_MESSAGE_TYPE, SERVER_PORT,
+    };
+    use dumbo::pdu::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+    use dumbo::pdu::udp::UdpDatagram;
     use logger::{IncMetric, METRICS};
+    use mmds::dhcp::DhcpLease;
     use rate_limiter::{RateLimiter, TokenBucket, TokenType};
+    use utils::tempfile::TempFile;
     use virtio_gen::virtio_net::{
         virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
         VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4,
@@ -862,17 +1381,18 @@ pub mod tests {
     use vm_memory::{Address, GuestMemory};
 
     impl Net {
-        pub fn read_tap(&mut self) -> io::Result<usize> {
+        pub fn read_tap(&mut self, q: usize) -> io::Result<usize> {
+            let pair = &mut self.queue_pairs[q];
             match &self.mocks.read_tap {
                 ReadTapMock::MockFrame(frame) => {
-                    self.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+                    pair.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
                     Ok(frame.len())
                 }
                 ReadTapMock::Failure => Err(io::Error::new(
                     io::ErrorKind::Other,
                     "Read tap synthetically failed.",
                 )),
-                ReadTapMock::TapFrame => self.tap.read(&mut self.rx_frame_buf),
+                ReadTapMock::TapFrame => pair.backend.read_frame(&mut pair.rx_frame_buf),
             }
         }
     }
@@ -928,6 +1448,7 @@ pub mod tests {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_STATUS
             | 1 << VIRTIO_F_VERSION_1;
 
         assert_eq!(net.avail_features_by_page(0), features as u32);
@@ -943,6 +1464,217 @@ pub mod tests {
         assert_eq!(net.acked_features, features);
     }
 
+    #[test]
+    fn test_multi_queue_features() {
+        let net = default_net();
+        assert_eq!(net.queues.len(), 2);
+        assert_eq!(net.avail_features & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_eq!(net.avail_features & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+        assert!(net.ctrl_queue_index().is_none());
+
+        let net = multi_queue_net(4);
+        assert_eq!(net.num_queue_pairs(), 4);
+        // Four rx/tx queue pairs and the control queue.
+        assert_eq!(net.queues.len(), 9);
+        assert_eq!(net.queue_evts.len(), 9);
+        assert_eq!(net.ctrl_queue_index(), Some(8));
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+        assert_eq!(net.config_space.max_virtqueue_pairs, 4);
+        // Only the first queue pair is in use until the driver enables more.
+        assert_eq!(net.active_queue_pairs, 1);
+
+        let rate_limiters = (0..=MAX_QUEUE_PAIRS)
+            .map(|_| (RateLimiter::default(), RateLimiter::default()))
+            .collect();
+        assert_eq!(
+            format!(
+                "{:?}",
+                Net::new_with_multi_queue_tap(
+                    "net".to_string(),
+                    "net-device-mq".to_string(),
+                    None,
+                    rate_limiters,
+                    false,
+                    false
+                )
+                .err()
+                .unwrap()
+            ),
+            format!("InvalidQueuePairs({})", MAX_QUEUE_PAIRS + 1)
+        );
+    }
+
+    #[test]
+    fn test_ctrl_mq_command() {
+        let mut net = multi_queue_net(4);
+
+        let mq_command = |count: u16| {
+            let mut command = vec![
+                VIRTIO_NET_CTRL_MQ as u8,
+                VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET as u8,
+            ];
+            command.extend_from_slice(&count.to_le_bytes());
+            command
+        };
+
+        assert_eq!(net.handle_ctrl_command(&mq_command(4)), VIRTIO_NET_OK as u8);
+        assert_eq!(net.active_queue_pairs, 4);
+        assert_eq!(net.handle_ctrl_command(&mq_command(2)), VIRTIO_NET_OK as u8);
+        assert_eq!(net.active_queue_pairs, 2);
+
+        // Invalid number of queue pairs.
+        assert_eq!(
+            net.handle_ctrl_command(&mq_command(0)),
+            VIRTIO_NET_ERR as u8
+        );
+        assert_eq!(
+            net.handle_ctrl_command(&mq_command(5)),
+            VIRTIO_NET_ERR as u8
+        );
+        assert_eq!(net.active_queue_pairs, 2);
+
+        // Malformed and unsupported commands.
+        assert_eq!(net.handle_ctrl_command(&[]), VIRTIO_NET_ERR as u8);
+        assert_eq!(
+            net.handle_ctrl_command(&mq_command(2)[..3]),
+            VIRTIO_NET_ERR as u8
+        );
+        assert_eq!(net.handle_ctrl_command(&[0, 0, 1]), VIRTIO_NET_ERR as u8);
+        assert_eq!(net.active_queue_pairs, 2);
+
+        // Inactive queue pairs only read frames from MMDS.
+        assert_eq!(
+            format!("{:?}", net.read_from_mmds_or_tap(3).err().unwrap()),
+            format!("{:?}", Error::IO(io::Error::from_raw_os_error(EAGAIN)))
+        );
+    }
+
+    #[test]
+    fn test_link_state() {
+        let mut net = default_net();
+        assert!(net.link_up());
+        assert_eq!(net.config_space.status, VIRTIO_NET_S_LINK_UP as u16);
+
+        // Updating the link of an inactive device doesn't raise an interrupt.
+        check_metric_after_block!(
+            &METRICS.net.link_state_updates,
+            1,
+            net.update_link(Some(false), false).unwrap()
+        );
+        assert!(!net.link_up());
+        assert_eq!(net.interrupt_status().load(Ordering::SeqCst), 0);
+
+        // Guest announcements must be negotiated first.
+        assert!(net.check_link_update(false).is_ok());
+        assert_eq!(
+            format!("{:?}", net.check_link_update(true).err().unwrap()),
+            "GuestAnnounceNotNegotiated"
+        );
+        assert_eq!(
+            format!("{:?}", net.update_link(None, true).err().unwrap()),
+            "GuestAnnounceNotNegotiated"
+        );
+
+        // Setting the current link state is a no-op.
+        net.activate(default_guest_memory()).unwrap();
+        check_metric_after_block!(
+            &METRICS.net.link_state_updates,
+            0,
+            net.update_link(Some(false), false).unwrap()
+        );
+        assert_eq!(net.interrupt_status().load(Ordering::SeqCst), 0);
+
+        // The guest is notified of the link changes once the device is active.
+        net.update_link(Some(true), false).unwrap();
+        assert!(net.link_up());
+        assert_eq!(
+            net.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_CONFIG as usize
+        );
+        let mut status = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64, &mut status);
+        assert_eq!(u16::from_le_bytes(status), VIRTIO_NET_S_LINK_UP as u16);
+    }
+
+    #[test]
+    fn test_guest_announce() {
+        let net = default_net();
+        assert_eq!(net.avail_features & (1 << VIRTIO_NET_F_GUEST_ANNOUNCE), 0);
+
+        let mut net = Net::new_with_multi_queue_tap(
+            "net".to_string(),
+            "net-device-announce".to_string(),
+            None,
+            vec![(RateLimiter::default(), RateLimiter::default())],
+            false,
+            true,
+        )
+        .unwrap();
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_GUEST_ANNOUNCE), 0);
+        // Announcements are acknowledged through the control queue.
+        assert_eq!(net.ctrl_queue_index(), Some(2));
+        assert_eq!(net.queues.len(), 3);
+
+        net.acked_features = net.avail_features;
+        net.update_link(None, true).unwrap();
+        assert_eq!(
+            net.config_space.status,
+            (VIRTIO_NET_S_LINK_UP | VIRTIO_NET_S_ANNOUNCE) as u16
+        );
+
+        assert_eq!(
+            net.handle_ctrl_command(&[
+                VIRTIO_NET_CTRL_ANNOUNCE as u8,
+                VIRTIO_NET_CTRL_ANNOUNCE_ACK as u8
+            ]),
+            VIRTIO_NET_OK as u8
+        );
+        assert_eq!(net.config_space.status, VIRTIO_NET_S_LINK_UP as u16);
+    }
+
+    #[test]
+    fn test_swap_backend() {
+        // The previous tap of an inactive device is released right away.
+        let mut net = default_net();
+        check_metric_after_block!(
+            &METRICS.net.tap_swaps,
+            1,
+            net.swap_backend("net-device-swap0").unwrap()
+        );
+        assert_eq!(
+            net.queue_pairs[0].tap().if_name_as_str(),
+            "net-device-swap0"
+        );
+        assert_eq!(net.host_dev_name, "net-device-swap0");
+        assert!(net.detached_backends.is_empty());
+
+        let mut th = TestHelper::default();
+        th.activate_net();
+        th.net().mocks.set_read_tap(ReadTapMock::TapFrame);
+        let old_tap_fd = th.net().queue_pairs[0].backend.as_raw_fd();
+        th.net().swap_backend("net-device-swap1").unwrap();
+        assert_eq!(th.net().detached_backends.len(), 1);
+
+        // Handle the backend swap event.
+        let ev_count = th.event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        assert!(th.net().detached_backends.is_empty());
+
+        // The event manager now listens to the new tap only.
+        let new_tap_fd = th.net().queue_pairs[0].backend.as_raw_fd();
+        assert_ne!(new_tap_fd, old_tap_fd);
+        assert!(th.event_manager.subscriber(new_tap_fd).is_ok());
+        assert!(th.event_manager.subscriber(old_tap_fd).is_err());
+
+        // AF_XDP backends can't be replaced at runtime.
+        th.net().backend_config = BackendConfig::Xdp(String::from("/sys/fs/bpf/xsks_map"));
+        assert_eq!(
+            format!("{:?}", th.net().swap_backend("net-device-swap2")),
+            "Err(BackendSwapUnsupported)"
+        );
+    }
+
     #[test]
     fn test_virtio_device_read_config() {
         let mut net = default_net();
@@ -956,7 +1688,7 @@ pub mod tests {
 
         // Invalid read.
         config_mac = [0u8; MAC_ADDR_LEN];
-        net.read_config(MAC_ADDR_LEN as u64 + 1, &mut config_mac);
+        net.read_config(mem::size_of::<ConfigSpace>() as u64 + 1, &mut config_mac);
         assert_eq!(config_mac, [0u8, 0u8, 0u8, 0u8, 0u8, 0u8]);
     }
 
@@ -1108,7 +1840,7 @@ pub mod tests {
         th.rxq.check_used_elem(1, 3, 0);
         th.rxq.check_used_elem(2, 4, 0);
         // Check that the frame wasn't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the frame has been written successfully to the valid Rx descriptor chain.
         th.rxq.check_used_elem(3, 5, frame.len() as u32);
         th.rxq.dtable[5].check_data(&frame);
@@ -1141,7 +1873,7 @@ pub mod tests {
         );
 
         // Check that the frame wasn't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the used queue has advanced.
         assert_eq!(th.rxq.used.idx.get(), 1);
         check_used_queue_signal(&th.net(), 1);
@@ -1180,7 +1912,7 @@ pub mod tests {
         );
 
         // Check that the frames weren't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the used queue has advanced.
         assert_eq!(th.rxq.used.idx.get(), 2);
         check_used_queue_signal(&th.net(), 1);
@@ -1198,7 +1930,8 @@ pub mod tests {
     fn test_tx_missing_queue_signal() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         th.add_desc_chain(NetQueue::Tx, 0, &[(0, 4096, 0)]);
         th.net().queue_evts[TX_INDEX].read().unwrap();
@@ -1218,7 +1951,8 @@ pub mod tests {
     fn test_tx_writeable_descriptor() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         let desc_list = [(0, 100, 0), (1, 100, VIRTQ_DESC_F_WRITE), (2, 500, 0)];
         th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
@@ -1237,7 +1971,8 @@ pub mod tests {
     fn test_tx_short_frame() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         // Send an invalid frame (too small, VNET header missing).
         th.add_desc_chain(NetQueue::Tx, 0, &[(0, 1, 0)]);
@@ -1259,7 +1994,8 @@ pub mod tests {
     fn test_tx_partial_read() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         // The descriptor chain is created so that the last descriptor doesn't fit in the
         // guest memory.
@@ -1287,7 +2023,8 @@ pub mod tests {
     fn test_tx_retry() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         // Add invalid descriptor chain - writeable descriptor.
         th.add_desc_chain(
@@ -1327,7 +2064,8 @@ pub mod tests {
     fn test_tx_complex_descriptor() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         // Add gaps between the descriptor ids in order to ensure that we follow
         // the `next` field.
@@ -1355,7 +2093,8 @@ pub mod tests {
     fn test_tx_multiple_frame() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator =
+            TapTrafficSimulator::new(if_index(th.net().queue_pairs[0].tap()));
 
         // Write the first frame to the Tx queue
         let desc_list = [(0, 50, 0), (1, 100, 0), (2, 150, 0)];
@@ -1429,6 +2168,7 @@ pub mod tests {
         let dst_ip = Ipv4Addr::new(169, 254, 169, 254);
 
         let (frame_buf, frame_len) = create_arp_request(src_mac, src_ip, dst_mac, dst_ip);
+        let pair = &mut net.queue_pairs[0];
 
         // Call the code which sends the packet to the host or MMDS.
         // Validate the frame was consumed by MMDS and that the metrics reflect that.
@@ -1437,10 +2177,14 @@ pub mod tests {
             1,
             assert!(Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
-                &mut net.tx_rate_limiter,
+                None,
+                &mut pair.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                pair.backend.as_mut(),
                 Some(src_mac),
+                None,
+                None,
+                &METRICS.net.queue_pairs[0],
             )
             .unwrap())
         );
@@ -1449,8 +2193,148 @@ pub mod tests {
         check_metric_after_block!(
             &METRICS.mmds.tx_frames,
             1,
-            net.read_from_mmds_or_tap().unwrap()
+            net.read_from_mmds_or_tap(0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dhcp_detour_and_injection() {
+        let mut net = default_net();
+        let lease = DhcpLease {
+            ipv4_addr: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 30,
+            gateway: Some(Ipv4Addr::new(172, 16, 0, 1)),
+            dns_servers: vec![],
+            lease_time_s: 3600,
+        };
+        assert!(net.dhcp_server().is_none());
+        net.set_dhcp_server(Some(DhcpServer::new(lease.clone())));
+        assert_eq!(net.dhcp_server().unwrap().lease(), &lease);
+
+        // Build a DHCPDISCOVER broadcast by the guest.
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let mut message = vec![0u8; 240];
+        message[0] = 1;
+        message[1] = 1;
+        message[2] = 6;
+        message[28..34].copy_from_slice(guest_mac.get_bytes());
+        message[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER, 255]);
+
+        let mut frame_buf = [0u8; MAX_BUFFER_SIZE];
+        let mut eth = EthernetFrame::write_incomplete(
+            frame_bytes_from_buf_mut(&mut frame_buf).unwrap(),
+            MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap(),
+            guest_mac,
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut ip = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+        )
+        .unwrap();
+        let udp_len = UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), &message)
+            .unwrap()
+            .finalize(CLIENT_PORT, SERVER_PORT, None)
+            .len() as usize;
+        let ip_len = ip.with_payload_len_unchecked(udp_len, true).len();
+        let frame_len = vnet_hdr_len() + eth.with_payload_len_unchecked(ip_len).len();
+
+        // The DHCP server consumes the frame, which never reaches the tap.
+        let pair = &mut net.queue_pairs[0];
+        check_metric_after_block!(
+            &METRICS.dhcp.rx_count,
+            1,
+            assert!(Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                net.dhcp_server.as_mut(),
+                &mut pair.tx_rate_limiter,
+                &frame_buf[..frame_len],
+                pair.backend.as_mut(),
+                Some(guest_mac),
+                None,
+                None,
+                &METRICS.net.queue_pairs[0],
+            )
+            .unwrap())
+        );
+
+        // The offer is injected ahead of the tap traffic.
+        let len;
+        check_metric_after_block!(
+            &METRICS.dhcp.tx_count,
+            1,
+            len = net.read_from_mmds_or_tap(0).unwrap()
         );
+        let rx_frame_buf = &net.queue_pairs[0].rx_frame_buf[vnet_hdr_len()..len];
+        let eth = EthernetFrame::from_bytes(rx_frame_buf).unwrap();
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        let udp = UdpDatagram::from_bytes(ip.payload(), None).unwrap();
+        let offer = DhcpMessage::from_bytes(udp.payload()).unwrap();
+        assert_eq!(offer.message_type(), Some(DHCPOFFER));
+        assert_eq!(offer.yiaddr(), lease.ipv4_addr);
+    }
+
+    #[test]
+    fn test_packet_capture() {
+        let mut net = default_net();
+
+        let src_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let src_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let dst_ip = Ipv4Addr::new(169, 254, 169, 254);
+
+        assert!(matches!(net.stop_capture(), Err(Error::NoCapture)));
+        let file = TempFile::new().unwrap();
+        let new_capture = || {
+            PacketCapture::new(
+                file.as_file().try_clone().unwrap(),
+                CaptureFormat::Pcap,
+                DEFAULT_SNAPLEN,
+                None,
+            )
+            .unwrap()
+        };
+        net.start_capture(new_capture()).unwrap();
+        assert!(matches!(
+            net.start_capture(new_capture()),
+            Err(Error::CaptureInProgress)
+        ));
+
+        // Both the request detoured to MMDS and its response are captured.
+        let (frame_buf, frame_len) = create_arp_request(src_mac, src_ip, dst_mac, dst_ip);
+        let pair = &mut net.queue_pairs[0];
+        check_metric_after_block!(
+            &METRICS.net.capture_frames,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                None,
+                &mut pair.tx_rate_limiter,
+                &frame_buf[..frame_len],
+                pair.backend.as_mut(),
+                Some(src_mac),
+                None,
+                net.capture.as_mut(),
+                &METRICS.net.queue_pairs[0],
+            )
+        );
+        check_metric_after_block!(
+            &METRICS.net.capture_frames,
+            1,
+            net.read_from_mmds_or_tap(0).unwrap()
+        );
+
+        net.stop_capture().unwrap();
+        assert!(net.capture.is_none());
+        let content = std::fs::read(file.as_path()).unwrap();
+        // The file header and the two records, each made of a header and an ARP frame.
+        let frame = frame_bytes_from_buf(&frame_buf[..frame_len]).unwrap();
+        assert_eq!(content.len(), 24 + 2 * (16 + frame.len()));
+        assert_eq!(&content[24 + 16..24 + 16 + frame.len()], frame);
     }
 
     #[test]
@@ -1464,6 +2348,7 @@ pub mod tests {
         let dst_ip = Ipv4Addr::new(10, 1, 1, 1);
 
         let (frame_buf, frame_len) = create_arp_request(guest_mac, guest_ip, dst_mac, dst_ip);
+        let pair = &mut net.queue_pairs[0];
 
         // Check that a legit MAC doesn't affect the spoofed MAC metric.
         check_metric_after_block!(
@@ -1471,10 +2356,14 @@ pub mod tests {
             0,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
-                &mut net.tx_rate_limiter,
+                None,
+                &mut pair.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                pair.backend.as_mut(),
                 Some(guest_mac),
+                None,
+                None,
+                &METRICS.net.queue_pairs[0],
             )
         );
 
@@ -1484,10 +2373,94 @@ pub mod tests {
             1,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
-                &mut net.tx_rate_limiter,
+                None,
+                &mut pair.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                pair.backend.as_mut(),
                 Some(not_guest_mac),
+                None,
+                None,
+                &METRICS.net.queue_pairs[0],
+            )
+        );
+    }
+
+    #[test]
+    fn test_anti_spoofing_filter() {
+        let mut net = default_net();
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let not_guest_mac = MacAddr::parse_str("33:33:33:33:33:33").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let dst_ip = Ipv4Addr::new(10, 1, 1, 1);
+
+        assert!(net.anti_spoofing_filter().is_none());
+        net.set_anti_spoofing_filter(Some(AntiSpoofingFilter::new(
+            guest_mac,
+            Some(vec![guest_ip]),
+        )));
+        assert_eq!(net.anti_spoofing_filter().unwrap().mac(), &guest_mac);
+
+        let pair = &mut net.queue_pairs[0];
+
+        // A legit frame is sent to the tap.
+        let (frame_buf, frame_len) = create_arp_request(guest_mac, guest_ip, dst_mac, dst_ip);
+        check_metric_after_block!(
+            &METRICS.net.queue_pairs[0].tx_packets_count,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                None,
+                &mut pair.tx_rate_limiter,
+                &frame_buf[..frame_len],
+                pair.backend.as_mut(),
+                Some(guest_mac),
+                net.anti_spoofing.as_ref(),
+                None,
+                &METRICS.net.queue_pairs[0],
+            )
+        );
+
+        // Frames with a spoofed MAC or IP address are dropped.
+        let (frame_buf, frame_len) = create_arp_request(not_guest_mac, guest_ip, dst_mac, dst_ip);
+        check_metric_after_block!(
+            &METRICS.net.queue_pairs[0].tx_packets_count,
+            0,
+            check_metric_after_block!(
+                &METRICS.net.tx_spoofed_mac_drops,
+                1,
+                Net::write_to_mmds_or_tap(
+                    net.mmds_ns.as_mut(),
+                    None,
+                    &mut pair.tx_rate_limiter,
+                    &frame_buf[..frame_len],
+                    pair.backend.as_mut(),
+                    Some(guest_mac),
+                    net.anti_spoofing.as_ref(),
+                    None,
+                    &METRICS.net.queue_pairs[0],
+                )
+            )
+        );
+        let (frame_buf, frame_len) = create_arp_request(guest_mac, dst_ip, dst_mac, dst_ip);
+        check_metric_after_block!(
+            &METRICS.net.queue_pairs[0].tx_packets_count,
+            0,
+            check_metric_after_block!(
+                &METRICS.net.tx_spoofed_ip_drops,
+                1,
+                Net::write_to_mmds_or_tap(
+                    net.mmds_ns.as_mut(),
+                    None,
+                    &mut pair.tx_rate_limiter,
+                    &frame_buf[..frame_len],
+                    pair.backend.as_mut(),
+                    Some(guest_mac),
+                    net.anti_spoofing.as_ref(),
+                    None,
+                    &METRICS.net.queue_pairs[0],
+                )
             )
         );
     }
@@ -1524,7 +2497,7 @@ pub mod tests {
         th.net().mocks.set_read_tap(ReadTapMock::Failure);
 
         // The RX queue is empty and rx_deffered_frame is set.
-        th.net().rx_deferred_frame = true;
+        th.net().queue_pairs[0].rx_deferred_frame = true;
         check_metric_after_block!(
             &METRICS.net.no_rx_avail_buffer,
             1,
@@ -1545,7 +2518,7 @@ pub mod tests {
         let mut th = TestHelper::default();
         th.activate_net();
 
-        th.net().rx_rate_limiter = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
+        th.net().queue_pairs[0].rx_rate_limiter = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
         // There is no actual event on the rate limiter's timerfd.
         check_metric_after_block!(
             &METRICS.net.event_fails,
@@ -1559,7 +2532,7 @@ pub mod tests {
         let mut th = TestHelper::default();
         th.activate_net();
 
-        th.net().tx_rate_limiter = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
+        th.net().queue_pairs[0].tx_rate_limiter = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
         th.simulate_event(NetEvent::TxRateLimiter);
         // There is no actual event on the rate limiter's timerfd.
         check_metric_after_block!(
@@ -1582,7 +2555,7 @@ pub mod tests {
             assert!(rl.consume(0x1000, TokenType::Bytes));
 
             // set this tx rate limiter to be used
-            th.net().tx_rate_limiter = rl;
+            th.net().queue_pairs[0].tx_rate_limiter = rl;
 
             // try doing TX
             // following TX procedure should fail because of bandwidth rate limiting
@@ -1592,7 +2565,7 @@ pub mod tests {
                 th.simulate_event(NetEvent::TxQueue);
 
                 // assert that limiter is blocked
-                assert!(th.net().tx_rate_limiter.is_blocked());
+                assert!(th.net().queue_pairs[0].tx_rate_limiter.is_blocked());
                 assert_eq!(METRICS.net.tx_rate_limiter_throttled.count(), 1);
                 // make sure the data is still queued for processing
                 assert_eq!(th.txq.used.idx.get(), 0);
@@ -1611,7 +2584,7 @@ pub mod tests {
                     th.simulate_event(NetEvent::TxRateLimiter)
                 );
                 // validate the rate_limiter is no longer blocked
-                assert!(!th.net().tx_rate_limiter.is_blocked());
+                assert!(!th.net().queue_pairs[0].tx_rate_limiter.is_blocked());
                 // make sure the data queue advanced
                 assert_eq!(th.txq.used.idx.get(), 1);
             }
@@ -1625,10 +2598,10 @@ pub mod tests {
             assert!(rl.consume(0x1000, TokenType::Bytes));
 
             // set this rx rate limiter to be used
-            th.net().rx_rate_limiter = rl;
+            th.net().queue_pairs[0].rx_rate_limiter = rl;
 
             // set up RX
-            assert!(!th.net().rx_deferred_frame);
+            assert!(!th.net().queue_pairs[0].rx_deferred_frame);
             th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
 
             // following RX procedure should fail because of bandwidth rate limiting
@@ -1637,9 +2610,9 @@ pub mod tests {
                 th.simulate_event(NetEvent::Tap);
 
                 // assert that limiter is blocked
-                assert!(th.net().rx_rate_limiter.is_blocked());
+                assert!(th.net().queue_pairs[0].rx_rate_limiter.is_blocked());
                 assert_eq!(METRICS.net.rx_rate_limiter_throttled.count(), 1);
-                assert!(th.net().rx_deferred_frame);
+                assert!(th.net().queue_pairs[0].rx_deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 check_used_queue_signal(&th.net(), 1);
                 // make sure the data is still queued for processing
@@ -1660,7 +2633,7 @@ pub mod tests {
                     th.simulate_event(NetEvent::RxRateLimiter)
                 );
                 // validate the rate_limiter is no longer blocked
-                assert!(!th.net().rx_rate_limiter.is_blocked());
+                assert!(!th.net().queue_pairs[0].rx_rate_limiter.is_blocked());
                 // make sure the virtio queue operation completed this time
                 check_used_queue_signal(&th.net(), 1);
                 // make sure the data queue advanced
@@ -1684,7 +2657,7 @@ pub mod tests {
             assert!(rl.consume(1, TokenType::Ops));
 
             // set this tx rate limiter to be used
-            th.net().tx_rate_limiter = rl;
+            th.net().queue_pairs[0].tx_rate_limiter = rl;
 
             // try doing TX
             // following TX procedure should fail because of ops rate limiting
@@ -1698,7 +2671,7 @@ pub mod tests {
                 );
 
                 // assert that limiter is blocked
-                assert!(th.net().tx_rate_limiter.is_blocked());
+                assert!(th.net().queue_pairs[0].tx_rate_limiter.is_blocked());
                 // make sure the data is still queued for processing
                 assert_eq!(th.txq.used.idx.get(), 0);
             }
@@ -1716,7 +2689,7 @@ pub mod tests {
                     th.simulate_event(NetEvent::TxRateLimiter)
                 );
                 // validate the rate_limiter is no longer blocked
-                assert!(!th.net().tx_rate_limiter.is_blocked());
+                assert!(!th.net().queue_pairs[0].tx_rate_limiter.is_blocked());
                 // make sure the data queue advanced
                 assert_eq!(th.txq.used.idx.get(), 1);
             }
@@ -1730,10 +2703,10 @@ pub mod tests {
             assert!(rl.consume(1, TokenType::Ops));
 
             // set this rx rate limiter to be used
-            th.net().rx_rate_limiter = rl;
+            th.net().queue_pairs[0].rx_rate_limiter = rl;
 
             // set up RX
-            assert!(!th.net().rx_deferred_frame);
+            assert!(!th.net().queue_pairs[0].rx_deferred_frame);
             th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
 
             // following RX procedure should fail because of ops rate limiting
@@ -1746,9 +2719,9 @@ pub mod tests {
                 );
 
                 // assert that limiter is blocked
-                assert!(th.net().rx_rate_limiter.is_blocked());
+                assert!(th.net().queue_pairs[0].rx_rate_limiter.is_blocked());
                 assert!(METRICS.net.rx_rate_limiter_throttled.count() >= 1);
-                assert!(th.net().rx_deferred_frame);
+                assert!(th.net().queue_pairs[0].rx_deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 check_used_queue_signal(&th.net(), 1);
                 // make sure the data is still queued for processing
@@ -1785,8 +2758,8 @@ pub mod tests {
         let mut th = TestHelper::default();
         th.activate_net();
 
-        th.net().rx_rate_limiter = RateLimiter::new(10, 0, 10, 2, 0, 2).unwrap();
-        th.net().tx_rate_limiter = RateLimiter::new(10, 0, 10, 2, 0, 2).unwrap();
+        th.net().queue_pairs[0].rx_rate_limiter = RateLimiter::new(10, 0, 10, 2, 0, 2).unwrap();
+        th.net().queue_pairs[0].tx_rate_limiter = RateLimiter::new(10, 0, 10, 2, 0, 2).unwrap();
 
         let rx_bytes = TokenBucket::new(1000, 1001, 1002).unwrap();
         let rx_ops = TokenBucket::new(1003, 1004, 1005).unwrap();
@@ -1804,10 +2777,22 @@ pub mod tests {
             assert_eq!(a.one_time_burst(), b.one_time_burst());
             assert_eq!(a.refill_time_ms(), b.refill_time_ms());
         };
-        compare_buckets(th.net().rx_rate_limiter.bandwidth().unwrap(), &rx_bytes);
-        compare_buckets(th.net().rx_rate_limiter.ops().unwrap(), &rx_ops);
-        compare_buckets(th.net().tx_rate_limiter.bandwidth().unwrap(), &tx_bytes);
-        compare_buckets(th.net().tx_rate_limiter.ops().unwrap(), &tx_ops);
+        compare_buckets(
+            th.net().queue_pairs[0].rx_rate_limiter.bandwidth().unwrap(),
+            &rx_bytes,
+        );
+        compare_buckets(
+            th.net().queue_pairs[0].rx_rate_limiter.ops().unwrap(),
+            &rx_ops,
+        );
+        compare_buckets(
+            th.net().queue_pairs[0].tx_rate_limiter.bandwidth().unwrap(),
+            &tx_bytes,
+        );
+        compare_buckets(
+            th.net().queue_pairs[0].tx_rate_limiter.ops().unwrap(),
+            &tx_ops,
+        );
 
         th.net().patch_rate_limiters(
             BucketUpdate::Disabled,
@@ -1815,10 +2800,16 @@ pub mod tests {
             BucketUpdate::Disabled,
             BucketUpdate::Disabled,
         );
-        assert!(th.net().rx_rate_limiter.bandwidth().is_none());
-        assert!(th.net().rx_rate_limiter.ops().is_none());
-        assert!(th.net().tx_rate_limiter.bandwidth().is_none());
-        assert!(th.net().tx_rate_limiter.ops().is_none());
+        assert!(th.net().queue_pairs[0]
+            .rx_rate_limiter
+            .bandwidth()
+            .is_none());
+        assert!(th.net().queue_pairs[0].rx_rate_limiter.ops().is_none());
+        assert!(th.net().queue_pairs[0]
+            .tx_rate_limiter
+            .bandwidth()
+            .is_none());
+        assert!(th.net().queue_pairs[0].tx_rate_limiter.ops().is_none());
     }
 
     #[test]
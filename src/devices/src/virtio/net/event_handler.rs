@@ -7,8 +7,8 @@ use logger::{debug, error, warn, IncMetric, METRICS};
 use polly::event_manager::{EventManager, Subscriber};
 use utils::epoll::{EpollEvent, EventSet};
 
-use crate::virtio::net::device::Net;
-use crate::virtio::{VirtioDevice, RX_INDEX, TX_INDEX};
+use crate::virtio::net::device::{rx_index, tx_index, Net};
+use crate::virtio::VirtioDevice;
 
 impl Net {
     fn process_activate_event(&self, event_manager: &mut EventManager) {
@@ -41,6 +41,48 @@ impl Net {
             error!("Failed to unregister net activate evt: {:?}", e);
         });
     }
+
+    fn process_backend_swap_event(&mut self, event_manager: &mut EventManager) {
+        debug!("net: backend swap event");
+        if let Err(e) = self.backend_swap_evt.read() {
+            error!("Failed to consume net backend swap event: {:?}", e);
+        }
+        // The subscriber must exist as we previously registered backend_swap_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(self.backend_swap_evt.as_raw_fd()) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process net backend swap evt: {:?}", e);
+                return;
+            }
+        };
+
+        for backend in self.detached_backends.drain(..) {
+            event_manager
+                .unregister(backend.as_raw_fd())
+                .unwrap_or_else(|e| {
+                    error!("Failed to unregister net backend: {:?}", e);
+                });
+        }
+        for pair in self.queue_pairs.iter() {
+            let backend_fd = pair.backend.as_raw_fd();
+            event_manager
+                .register(
+                    backend_fd,
+                    EpollEvent::new(EventSet::IN | EventSet::EDGE_TRIGGERED, backend_fd as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register net backend: {:?}", e);
+                });
+        }
+
+        // The frames which reached the new backends before their registration didn't
+        // trigger any event.
+        for q in 0..self.active_queue_pairs {
+            self.process_tap_rx_event(q);
+        }
+    }
 }
 
 impl Subscriber for Net {
@@ -60,21 +102,42 @@ impl Subscriber for Net {
         }
 
         if self.is_activated() {
-            let virtq_rx_ev_fd = self.queue_evts[RX_INDEX].as_raw_fd();
-            let virtq_tx_ev_fd = self.queue_evts[TX_INDEX].as_raw_fd();
-            let rx_rate_limiter_fd = self.rx_rate_limiter.as_raw_fd();
-            let tx_rate_limiter_fd = self.tx_rate_limiter.as_raw_fd();
-            let tap_fd = self.tap.as_raw_fd();
+            for q in 0..self.queue_pairs.len() {
+                let pair = &self.queue_pairs[q];
+                let virtq_rx_ev_fd = self.queue_evts[rx_index(q)].as_raw_fd();
+                let virtq_tx_ev_fd = self.queue_evts[tx_index(q)].as_raw_fd();
+                let rx_rate_limiter_fd = pair.rx_rate_limiter.as_raw_fd();
+                let tx_rate_limiter_fd = pair.tx_rate_limiter.as_raw_fd();
+                let backend_fd = pair.backend.as_raw_fd();
+
+                // Looks better than C style if/else if/else.
+                match source {
+                    _ if source == virtq_rx_ev_fd => self.process_rx_queue_event(q),
+                    _ if source == backend_fd => self.process_tap_rx_event(q),
+                    _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(q),
+                    _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(q),
+                    _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(q),
+                    _ => continue,
+                }
+                return;
+            }
+
             let activate_fd = self.activate_evt.as_raw_fd();
+            let backend_swap_fd = self.backend_swap_evt.as_raw_fd();
+            let ctrl_ev_fd = self
+                .ctrl_queue_index()
+                .map(|index| self.queue_evts[index].as_raw_fd());
 
-            // Looks better than C style if/else if/else.
             match source {
-                _ if source == virtq_rx_ev_fd => self.process_rx_queue_event(),
-                _ if source == tap_fd => self.process_tap_rx_event(),
-                _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(),
-                _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(),
-                _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
+                _ if ctrl_ev_fd == Some(source) => self.process_ctrl_queue_event(),
+                _ if backend_swap_fd == source => self.process_backend_swap_event(evmgr),
+                // The replaced backends are still registered until the backend swap event
+                // is handled.
+                _ if self
+                    .detached_backends
+                    .iter()
+                    .any(|backend| backend.as_raw_fd() == source) => {}
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
                     METRICS.net.event_fails.inc();
@@ -94,16 +157,40 @@ impl Subscriber for Net {
         //  - on device activation (is-activated already true at this point),
         //  - on device restore from snapshot.
         if self.is_activated() {
-            vec![
-                EpollEvent::new(EventSet::IN, self.queue_evts[RX_INDEX].as_raw_fd() as u64),
-                EpollEvent::new(EventSet::IN, self.queue_evts[TX_INDEX].as_raw_fd() as u64),
-                EpollEvent::new(EventSet::IN, self.rx_rate_limiter.as_raw_fd() as u64),
-                EpollEvent::new(EventSet::IN, self.tx_rate_limiter.as_raw_fd() as u64),
-                EpollEvent::new(
+            let mut events = Vec::new();
+            for (q, pair) in self.queue_pairs.iter().enumerate() {
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[rx_index(q)].as_raw_fd() as u64,
+                ));
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[tx_index(q)].as_raw_fd() as u64,
+                ));
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    pair.rx_rate_limiter.as_raw_fd() as u64,
+                ));
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    pair.tx_rate_limiter.as_raw_fd() as u64,
+                ));
+                events.push(EpollEvent::new(
                     EventSet::IN | EventSet::EDGE_TRIGGERED,
-                    self.tap.as_raw_fd() as u64,
-                ),
-            ]
+                    pair.backend.as_raw_fd() as u64,
+                ));
+            }
+            if let Some(index) = self.ctrl_queue_index() {
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[index].as_raw_fd() as u64,
+                ));
+            }
+            events.push(EpollEvent::new(
+                EventSet::IN,
+                self.backend_swap_evt.as_raw_fd() as u64,
+            ));
+            events
         } else {
             vec![EpollEvent::new(
                 EventSet::IN,
@@ -11,16 +11,27 @@ pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
 pub const RX_INDEX: usize = 0;
 // The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+// The maximum number of rx/tx queue pairs of a Net device.
+pub const MAX_QUEUE_PAIRS: usize = logger::NET_MAX_QUEUE_PAIRS;
 
+mod anti_spoofing;
+pub mod backend;
+mod capture;
 pub mod device;
 pub mod event_handler;
 pub mod persist;
 mod tap;
 pub mod test_utils;
+mod unix_socket;
+mod xdp;
 
+pub use self::anti_spoofing::AntiSpoofingFilter;
+pub use self::backend::{BackendConfig, NetBackend};
+pub use self::capture::{CaptureFormat, PacketCapture, DEFAULT_SNAPLEN};
 pub use self::device::Net;
 pub use self::event_handler::*;
 pub use tap::Error as TapError;
+pub use xdp::Error as XdpError;
 
 #[derive(Debug)]
 pub enum Error {
@@ -32,6 +43,24 @@ pub enum Error {
     TapSetVnetHdrSize(TapError),
     /// Enabling tap interface failed.
     TapEnable(TapError),
+    /// Attaching or detaching a queue of the tap interface failed.
+    TapSetQueue(TapError),
+    /// Connecting to the Unix socket backend failed.
+    UnixSocketConnect(io::Error),
+    /// Setting up an AF_XDP socket failed.
+    XdpSocket(XdpError),
+    /// The backend of the device can't be replaced at runtime.
+    BackendSwapUnsupported,
+    /// The number of queue pairs is zero or larger than `MAX_QUEUE_PAIRS`.
+    InvalidQueuePairs(usize),
+    /// The guest driver did not negotiate guest announcements.
+    GuestAnnounceNotNegotiated,
+    /// A packet capture is already running on the device.
+    CaptureInProgress,
+    /// No packet capture is running on the device.
+    NoCapture,
+    /// Writing the packet capture file failed.
+    CaptureWrite(io::Error),
     /// EventFd error.
     EventFd(io::Error),
     /// IO error.
@@ -4,19 +4,27 @@
 //! Defines the structures needed for saving/restoring net devices.
 
 use std::io;
+use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use mmds::{ns::MmdsNetworkStack, persist::MmdsNetworkStackState};
+use mmds::dhcp::DhcpServer;
+use mmds::ns::MmdsNetworkStack;
+use mmds::persist::{DhcpServerState, MmdsNetworkStackState};
 use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
+use virtio_gen::virtio_net::{
+    VIRTIO_NET_F_GUEST_ANNOUNCE, VIRTIO_NET_F_STATUS, VIRTIO_NET_S_LINK_UP,
+};
 use vm_memory::GuestMemoryMmap;
 
-use super::device::{ConfigSpace, Net};
-use super::{NUM_QUEUES, QUEUE_SIZE};
+use super::anti_spoofing::AntiSpoofingFilter;
+use super::backend::BackendConfig;
+use super::device::Net;
+use super::QUEUE_SIZE;
 
 use crate::virtio::persist::{Error as VirtioStateError, VirtioDeviceState};
 use crate::virtio::{DeviceState, TYPE_NET};
@@ -26,6 +34,40 @@ pub struct NetConfigSpaceState {
     guest_mac: [u8; MAC_ADDR_LEN],
 }
 
+#[derive(Clone, Versionize)]
+pub struct NetQueuePairState {
+    rx_rate_limiter_state: RateLimiterState,
+    tx_rate_limiter_state: RateLimiterState,
+}
+
+#[derive(Clone, Versionize)]
+pub struct AntiSpoofingState {
+    mac: [u8; MAC_ADDR_LEN],
+    allowed_ipv4: Option<Vec<u32>>,
+}
+
+impl AntiSpoofingState {
+    fn from_filter(filter: &AntiSpoofingFilter) -> Self {
+        let mut mac = [0; MAC_ADDR_LEN];
+        mac.copy_from_slice(filter.mac().get_bytes());
+        AntiSpoofingState {
+            mac,
+            allowed_ipv4: filter
+                .allowed_ipv4()
+                .map(|addrs| addrs.iter().map(|addr| u32::from(*addr)).collect()),
+        }
+    }
+
+    fn to_filter(&self) -> AntiSpoofingFilter {
+        AntiSpoofingFilter::new(
+            MacAddr::from_bytes_unchecked(&self.mac),
+            self.allowed_ipv4
+                .as_ref()
+                .map(|addrs| addrs.iter().map(|addr| Ipv4Addr::from(*addr)).collect()),
+        )
+    }
+}
+
 #[derive(Clone, Versionize)]
 pub struct NetState {
     id: String,
@@ -35,6 +77,131 @@ pub struct NetState {
     mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    // The rate limiters of the queue pairs following the first one.
+    #[version(start = 2, ser_fn = "extra_queue_pairs_serialize")]
+    extra_queue_pairs: Vec<NetQueuePairState>,
+    #[version(start = 2, default_fn = "default_active_queue_pairs")]
+    active_queue_pairs: u16,
+    // The link state and pending announcement reported to the guest.
+    #[version(
+        start = 2,
+        default_fn = "default_config_status",
+        ser_fn = "config_status_serialize"
+    )]
+    config_status: u16,
+    // The kind of host backend `tap_if_name` refers to.
+    #[version(
+        start = 2,
+        default_fn = "default_backend",
+        ser_fn = "backend_serialize"
+    )]
+    backend: BackendConfig,
+    #[version(start = 2, ser_fn = "anti_spoofing_serialize")]
+    anti_spoofing: Option<AntiSpoofingState>,
+    #[version(start = 2, ser_fn = "dhcp_server_serialize")]
+    dhcp_server: Option<DhcpServerState>,
+}
+
+impl NetState {
+    fn extra_queue_pairs_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.extra_queue_pairs.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement multi-queue net devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_active_queue_pairs(_: u16) -> u16 {
+        1
+    }
+
+    fn config_status_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // The first version has no status field in the config space, so a driver which
+        // negotiated it would read garbage after the restore.
+        if target_version < 2
+            && (self.config_status != VIRTIO_NET_S_LINK_UP as u16
+                || self.virtio_state.avail_features & (1 << VIRTIO_NET_F_GUEST_ANNOUNCE) != 0
+                || self.virtio_state.acked_features & (1 << VIRTIO_NET_F_STATUS) != 0)
+        {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net link state and guest announcements."
+                    .to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_config_status(_: u16) -> u16 {
+        VIRTIO_NET_S_LINK_UP as u16
+    }
+
+    fn backend_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.backend != BackendConfig::Tap {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net backends other than TAP.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_backend(_: u16) -> BackendConfig {
+        BackendConfig::Tap
+    }
+
+    fn anti_spoofing_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.anti_spoofing.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net anti-spoofing filters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn dhcp_server_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.dhcp_server.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net DHCP servers.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ID of the saved device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the name of the tap interface backing the saved device.
+    pub fn tap_if_name(&self) -> &str {
+        &self.tap_if_name
+    }
+
+    /// Returns the MAC address of the saved device.
+    pub fn guest_mac(&self) -> MacAddr {
+        MacAddr::from_bytes_unchecked(&self.config_space.guest_mac)
+    }
+
+    /// Sets the name of the tap interface the restored device will be backed by.
+    pub fn set_tap_if_name(&mut self, tap_if_name: String) {
+        self.tap_if_name = tap_if_name;
+    }
+
+    /// Sets the MAC address of the restored device.
+    pub fn set_guest_mac(&mut self, guest_mac: &MacAddr) {
+        self.config_space
+            .guest_mac
+            .copy_from_slice(guest_mac.get_bytes());
+        // The filter keeps allowing the address the guest uses.
+        if let Some(anti_spoofing) = self.anti_spoofing.as_mut() {
+            anti_spoofing.mac.copy_from_slice(guest_mac.get_bytes());
+        }
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -56,14 +223,29 @@ impl Persist<'_> for Net {
     fn save(&self) -> Self::State {
         NetState {
             id: self.id().clone(),
-            tap_if_name: self.tap.if_name_as_str().to_string(),
-            rx_rate_limiter_state: self.rx_rate_limiter.save(),
-            tx_rate_limiter_state: self.tx_rate_limiter.save(),
+            tap_if_name: self.host_dev_name.clone(),
+            rx_rate_limiter_state: self.queue_pairs[0].rx_rate_limiter.save(),
+            tx_rate_limiter_state: self.queue_pairs[0].tx_rate_limiter.save(),
             mmds_ns: self.mmds_ns.as_ref().map(|mmds| mmds.save()),
             config_space: NetConfigSpaceState {
                 guest_mac: self.config_space.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            extra_queue_pairs: self.queue_pairs[1..]
+                .iter()
+                .map(|pair| NetQueuePairState {
+                    rx_rate_limiter_state: pair.rx_rate_limiter.save(),
+                    tx_rate_limiter_state: pair.tx_rate_limiter.save(),
+                })
+                .collect(),
+            active_queue_pairs: self.active_queue_pairs as u16,
+            config_status: self.config_space.status,
+            backend: self.backend_config.clone(),
+            anti_spoofing: self
+                .anti_spoofing
+                .as_ref()
+                .map(AntiSpoofingState::from_filter),
+            dhcp_server: self.dhcp_server.as_ref().map(|server| server.save()),
         }
     }
 
@@ -71,18 +253,30 @@ impl Persist<'_> for Net {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        // RateLimiter::restore() can fail at creating a timerfd.
-        let rx_rate_limiter = RateLimiter::restore((), &state.rx_rate_limiter_state)
-            .map_err(Error::CreateRateLimiter)?;
-        let tx_rate_limiter = RateLimiter::restore((), &state.tx_rate_limiter_state)
-            .map_err(Error::CreateRateLimiter)?;
-        let mut net = Net::new_with_tap(
+        let mut rate_limiters = Vec::with_capacity(1 + state.extra_queue_pairs.len());
+        let rate_limiter_states =
+            std::iter::once((&state.rx_rate_limiter_state, &state.tx_rate_limiter_state)).chain(
+                state
+                    .extra_queue_pairs
+                    .iter()
+                    .map(|pair| (&pair.rx_rate_limiter_state, &pair.tx_rate_limiter_state)),
+            );
+        for (rx_state, tx_state) in rate_limiter_states {
+            // RateLimiter::restore() can fail at creating a timerfd.
+            let rx_rate_limiter =
+                RateLimiter::restore((), rx_state).map_err(Error::CreateRateLimiter)?;
+            let tx_rate_limiter =
+                RateLimiter::restore((), tx_state).map_err(Error::CreateRateLimiter)?;
+            rate_limiters.push((rx_rate_limiter, tx_rate_limiter));
+        }
+        let mut net = Net::new(
             state.id.clone(),
             state.tap_if_name.clone(),
+            state.backend.clone(),
             None,
-            rx_rate_limiter,
-            tx_rate_limiter,
+            rate_limiters,
             state.mmds_ns.is_some(),
+            state.virtio_state.avail_features & (1 << VIRTIO_NET_F_GUEST_ANNOUNCE) != 0,
         )
         .map_err(Error::CreateNet)?;
 
@@ -92,20 +286,31 @@ impl Persist<'_> for Net {
             .as_ref()
             .map(|mmds_state| MmdsNetworkStack::restore((), &mmds_state).unwrap());
 
+        let num_queues = net.queues.len();
         net.queues = state
             .virtio_state
-            .build_queues_checked(&constructor_args.mem, TYPE_NET, NUM_QUEUES, QUEUE_SIZE)
+            .build_queues_checked(&constructor_args.mem, TYPE_NET, num_queues, QUEUE_SIZE)
             .map_err(Error::VirtioState)?;
         net.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
-        net.config_space = ConfigSpace {
-            guest_mac: state.config_space.guest_mac,
-        };
+        net.config_space.guest_mac = state.config_space.guest_mac;
+        net.config_space.status = state.config_status;
+        net.set_active_queue_pairs(state.active_queue_pairs as usize)
+            .map_err(Error::CreateNet)?;
 
         net.guest_mac = Some(MacAddr::from_bytes_unchecked(
             &state.config_space.guest_mac[..MAC_ADDR_LEN],
         ));
+        net.anti_spoofing = state
+            .anti_spoofing
+            .as_ref()
+            .map(AntiSpoofingState::to_filter);
+        // Safe to unwrap because DhcpServer::restore() cannot fail.
+        net.dhcp_server = state
+            .dhcp_server
+            .as_ref()
+            .map(|server_state| DhcpServer::restore((), server_state).unwrap());
 
         if state.virtio_state.activated {
             net.device_state = DeviceState::Activated(constructor_args.mem);
@@ -119,8 +324,9 @@ impl Persist<'_> for Net {
 mod tests {
     use super::*;
     use crate::virtio::device::VirtioDevice;
+    use mmds::dhcp::DhcpLease;
 
-    use crate::virtio::net::test_utils::{default_guest_memory, default_net};
+    use crate::virtio::net::test_utils::{default_guest_memory, default_net, multi_queue_net};
     use std::sync::atomic::Ordering;
 
     #[test]
@@ -144,7 +350,7 @@ mod tests {
 
             // Save some fields that we want to check later.
             id = net.id.clone();
-            tap_if_name = net.tap.if_name_as_str().to_string();
+            tap_if_name = net.queue_pairs[0].tap().if_name_as_str().to_string();
             allow_mmds_requests = net.mmds_ns.is_some();
             virtio_state = VirtioDeviceState::from_device(&net);
         }
@@ -169,10 +375,258 @@ mod tests {
 
             // Test that net specific fields are the same.
             assert_eq!(&restored_net.id, &id);
-            assert_eq!(&restored_net.tap.if_name_as_str(), &tap_if_name);
+            assert_eq!(
+                &restored_net.queue_pairs[0].tap().if_name_as_str(),
+                &tap_if_name
+            );
             assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
-            assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
-            assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+            assert_eq!(
+                restored_net.queue_pairs[0].rx_rate_limiter,
+                RateLimiter::default()
+            );
+            assert_eq!(
+                restored_net.queue_pairs[0].tx_rate_limiter,
+                RateLimiter::default()
+            );
         }
     }
+
+    #[test]
+    fn test_multi_queue_persistence() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+
+        let mut net = multi_queue_net(2);
+        net.set_active_queue_pairs(2).unwrap();
+
+        // Multi-queue devices can't be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                <Net as Persist>::save(&net)
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .err()
+                    .unwrap()
+            ),
+            "Semantic(\"Target version does not implement multi-queue net devices.\")"
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let tap_if_name = net.queue_pairs[0].tap().if_name_as_str().to_string();
+        // Release the tap queues before restoring the device.
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.num_queue_pairs(), 2);
+        assert_eq!(restored_net.active_queue_pairs, 2);
+        assert_eq!(restored_net.queues.len(), 5);
+        for pair in restored_net.queue_pairs.iter() {
+            assert_eq!(pair.tap().if_name_as_str(), tap_if_name);
+        }
+    }
+
+    #[test]
+    fn test_link_state_persistence() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+
+        let mut net = default_net();
+        net.update_link(Some(false), false).unwrap();
+
+        // The link state can't be saved in the first snapshot version.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        // Neither can a negotiated status field, even with the link up.
+        net.update_link(Some(true), false).unwrap();
+        net.set_acked_features(1 << VIRTIO_NET_F_STATUS);
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+        net.update_link(Some(false), false).unwrap();
+
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(!restored_net.link_up());
+    }
+
+    #[test]
+    fn test_backend_persistence() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+
+        let mut net = default_net();
+        net.backend_config = BackendConfig::UnixSocket;
+
+        // Only TAP backends can be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                <Net as Persist>::save(&net)
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .err()
+                    .unwrap()
+            ),
+            "Semantic(\"Target version does not implement net backends other than TAP.\")"
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(state.backend, BackendConfig::UnixSocket);
+    }
+
+    #[test]
+    fn test_anti_spoofing_persistence() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let filter = AntiSpoofingFilter::new(guest_mac, Some(vec![Ipv4Addr::new(10, 1, 2, 3)]));
+
+        {
+            let mut net = default_net();
+            net.set_anti_spoofing_filter(Some(filter.clone()));
+
+            // Anti-spoofing filters can't be saved in the first snapshot version.
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    <Net as Persist>::save(&net)
+                        .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                        .err()
+                        .unwrap()
+                ),
+                "Semantic(\"Target version does not implement net anti-spoofing filters.\")"
+            );
+
+            version_map
+                .new_version()
+                .set_type_version(NetState::type_id(), 2);
+            <Net as Persist>::save(&net)
+                .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+                .unwrap();
+        }
+
+        let mut state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_net.anti_spoofing_filter(), Some(&filter));
+        drop(restored_net);
+
+        // The filter follows the MAC address the restored guest is given.
+        let new_guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        state.set_guest_mac(&new_guest_mac);
+        let restored_net = Net::restore(NetConstructorArgs { mem: guest_mem }, &state).unwrap();
+        assert_eq!(
+            restored_net.anti_spoofing_filter().unwrap().mac(),
+            &new_guest_mac
+        );
+        assert_eq!(
+            restored_net.anti_spoofing_filter().unwrap().allowed_ipv4(),
+            filter.allowed_ipv4()
+        );
+    }
+
+    #[test]
+    fn test_dhcp_server_persistence() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        let lease = DhcpLease {
+            ipv4_addr: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 30,
+            gateway: None,
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8)],
+            lease_time_s: 3600,
+        };
+
+        let mut net = default_net();
+        net.set_dhcp_server(Some(DhcpServer::new(lease.clone())));
+
+        // DHCP servers can't be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                <Net as Persist>::save(&net)
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .err()
+                    .unwrap()
+            ),
+            "Semantic(\"Target version does not implement net DHCP servers.\")"
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.dhcp_server().unwrap().lease(), &lease);
+    }
+
+    #[test]
+    fn test_restore_overrides() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let net = default_net();
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        let mut state = NetState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        state.set_tap_if_name("net-device-override".to_string());
+        state.set_guest_mac(&guest_mac);
+
+        // The original tap is still in use, the restored device gets the new one.
+        let restored_net = Net::restore(NetConstructorArgs { mem: guest_mem }, &state).unwrap();
+        assert_eq!(
+            restored_net.queue_pairs[0].tap().if_name_as_str(),
+            "net-device-override"
+        );
+        assert_eq!(restored_net.guest_mac(), Some(&guest_mac));
+        assert_eq!(restored_net.config_space.guest_mac, guest_mac.get_bytes());
+    }
 }
@@ -36,6 +36,7 @@ const TUNTAP: ::std::os::raw::c_uint = 84;
 ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETQUEUE, TUNTAP, 217, ::std::os::raw::c_int);
 
 /// Handle for a network tap interface.
 ///
@@ -105,6 +106,19 @@ impl Tap {
     ///
     /// * `if_name` - the name of the interface.
     pub fn open_named(if_name: &str) -> Result<Tap> {
+        Self::open(if_name, false)
+    }
+
+    /// Open a queue of a multi-queue TUN/TAP device given the interface name.
+    /// Each call opens a new queue of the interface.
+    /// # Arguments
+    ///
+    /// * `if_name` - the name of the interface.
+    pub fn open_named_multi_queue(if_name: &str) -> Result<Tap> {
+        Self::open(if_name, true)
+    }
+
+    fn open(if_name: &str, multi_queue: bool) -> Result<Tap> {
         let terminated_if_name = build_terminated_if_name(if_name)?;
 
         let fd = unsafe {
@@ -121,9 +135,13 @@ impl Tap {
         // We just checked that the fd is valid.
         let tuntap = unsafe { File::from_raw_fd(fd) };
 
+        let mut flags = net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR;
+        if multi_queue {
+            flags |= net_gen::IFF_MULTI_QUEUE;
+        }
         let ifreq = IfReqBuilder::new()
             .if_name(&terminated_if_name)
-            .flags((net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR) as i16)
+            .flags(flags as i16)
             .execute(&tuntap, TUNSETIFF())?;
 
         // Safe since only the name is accessed, and it's cloned out.
@@ -153,6 +171,21 @@ impl Tap {
         Ok(())
     }
 
+    /// Attach or detach this queue of a multi-queue interface. The kernel only
+    /// steers incoming packets to attached queues.
+    pub fn set_queue_attached(&self, attached: bool) -> Result<()> {
+        let flags = if attached {
+            net_gen::IFF_ATTACH_QUEUE
+        } else {
+            net_gen::IFF_DETACH_QUEUE
+        };
+        IfReqBuilder::new()
+            .flags(flags as i16)
+            .execute(&self.tap_file, TUNSETQUEUE())?;
+
+        Ok(())
+    }
+
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<()> {
         // ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -237,6 +270,21 @@ pub mod tests {
         Tap::open_named("exclusivetap").unwrap_err();
     }
 
+    #[test]
+    fn test_tap_multi_queue() {
+        let tap1 = Tap::open_named_multi_queue("multiqueuetap").unwrap();
+        let tap2 = Tap::open_named_multi_queue("multiqueuetap").unwrap();
+        assert_eq!(tap1.if_name, tap2.if_name);
+        // A multi-queue interface can't be opened as a single queue one.
+        Tap::open_named("multiqueuetap").unwrap_err();
+
+        tap2.set_queue_attached(false).unwrap();
+        tap2.set_queue_attached(true).unwrap();
+        // Single queue interfaces have no queues to detach.
+        let tap = Tap::open_named("").unwrap();
+        assert!(tap.set_queue_attached(false).is_err());
+    }
+
     #[test]
     fn test_set_options() {
         // This line will fail to provide an initialized FD if the test is not run as root.
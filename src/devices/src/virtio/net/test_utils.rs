@@ -41,7 +41,30 @@ pub fn default_net() -> Net {
         true,
     )
     .unwrap();
-    enable(&net.tap);
+    enable(net.queue_pairs[0].tap());
+
+    net
+}
+
+pub fn multi_queue_net(num_queue_pairs: usize) -> Net {
+    let next_tap = NEXT_INDEX.fetch_add(1, Ordering::SeqCst);
+    let tap_dev_name = format!("net-device{}", next_tap);
+
+    let guest_mac = default_guest_mac();
+
+    let rate_limiters = (0..num_queue_pairs)
+        .map(|_| (RateLimiter::default(), RateLimiter::default()))
+        .collect();
+    let net = Net::new_with_multi_queue_tap(
+        format!("net-device{}", next_tap),
+        tap_dev_name,
+        Some(&guest_mac),
+        rate_limiters,
+        true,
+        false,
+    )
+    .unwrap();
+    enable(net.queue_pairs[0].tap());
 
     net
 }
@@ -242,7 +265,7 @@ pub fn check_used_queue_signal(net: &Net, count: u64) {
 #[cfg(test)]
 pub(crate) fn inject_tap_tx_frame(net: &Net, len: usize) -> Vec<u8> {
     assert!(len >= vnet_hdr_len());
-    let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&net.tap));
+    let tap_traffic_simulator = TapTrafficSimulator::new(if_index(net.queue_pairs[0].tap()));
     let mut frame = utils::rand::rand_alphanumerics(len - vnet_hdr_len())
         .as_bytes()
         .to_vec();
@@ -368,10 +391,10 @@ pub mod test {
             let event_fd = match event {
                 NetEvent::Custom(event_fd) => event_fd,
                 NetEvent::RxQueue => self.net().queue_evts[RX_INDEX].as_raw_fd(),
-                NetEvent::RxRateLimiter => self.net().rx_rate_limiter.as_raw_fd(),
-                NetEvent::Tap => self.net().tap.as_raw_fd(),
+                NetEvent::RxRateLimiter => self.net().queue_pairs[0].rx_rate_limiter.as_raw_fd(),
+                NetEvent::Tap => self.net().queue_pairs[0].backend.as_raw_fd(),
                 NetEvent::TxQueue => self.net().queue_evts[TX_INDEX].as_raw_fd(),
-                NetEvent::TxRateLimiter => self.net().tx_rate_limiter.as_raw_fd(),
+                NetEvent::TxRateLimiter => self.net().queue_pairs[0].tx_rate_limiter.as_raw_fd(),
             };
             self.net.lock().unwrap().process(
                 &EpollEvent::new(EventSet::IN, event_fd as u64),
@@ -435,7 +458,7 @@ pub mod test {
                 self.event_manager.run_with_timeout(100).unwrap()
             );
             // Check that the frame has been deferred.
-            assert!(self.net().rx_deferred_frame);
+            assert!(self.net().queue_pairs[0].rx_deferred_frame);
             // Check that the descriptor chain has been discarded.
             assert_eq!(self.rxq.used.idx.get(), used_idx + 1);
             check_used_queue_signal(&self.net(), 1);
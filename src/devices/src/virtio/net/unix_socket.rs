@@ -0,0 +1,179 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::any::Any;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+
+use super::backend::NetBackend;
+use super::device::{init_vnet_hdr, vnet_hdr_len};
+
+/// A connection to a Unix `SOCK_SEQPACKET` socket, exchanging raw Ethernet frames with a
+/// userspace process. Every message carries a single frame, without any virtio-net header.
+#[derive(Debug)]
+pub struct UnixSocketBackend {
+    socket: File,
+}
+
+impl UnixSocketBackend {
+    /// Connects to the socket listening at `path`.
+    pub fn connect<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let path = path.as_ref().as_os_str().as_bytes();
+
+        // Safe because we zero-initialize a plain C struct.
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        // The path must fit in `sun_path` along with its null terminator.
+        if path.is_empty() || path.len() >= addr.sun_path.len() {
+            return Err(IoError::from(ErrorKind::InvalidInput));
+        }
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+            *dst = *src as libc::c_char;
+        }
+
+        // Safe because we check the result.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        // We just checked that the fd is valid, the file closes it when dropped.
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        // Safe because `addr` is a valid `sockaddr_un` and we check the result.
+        let ret = unsafe {
+            libc::connect(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(UnixSocketBackend { socket })
+    }
+}
+
+impl NetBackend for UnixSocketBackend {
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if buf.len() < vnet_hdr_len() {
+            return Err(IoError::from(ErrorKind::InvalidInput));
+        }
+        let len = self.socket.read(&mut buf[vnet_hdr_len()..])?;
+        // An empty read means that the peer closed the connection.
+        if len == 0 {
+            return Err(IoError::from(ErrorKind::ConnectionReset));
+        }
+        // The frame does not need any offload from the guest.
+        init_vnet_hdr(buf);
+
+        Ok(vnet_hdr_len() + len)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if buf.len() < vnet_hdr_len() {
+            return Err(IoError::from(ErrorKind::InvalidInput));
+        }
+        // The offloads are not offered to the guest, so the header carries no information.
+        self.socket
+            .write(&buf[vnet_hdr_len()..])
+            .map(|len| vnet_hdr_len() + len)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl AsRawFd for UnixSocketBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn test_connect_errors() {
+        assert_eq!(
+            UnixSocketBackend::connect("").unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        let long_path = "a".repeat(128);
+        assert_eq!(
+            UnixSocketBackend::connect(&long_path).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            UnixSocketBackend::connect("/nonexistent/net.sock")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_read_write_frame() {
+        // A socket pair stands in for the connection to a userspace network stack.
+        let mut fds = [0; 2];
+        // Safe because `fds` can hold the two descriptors and we check the result.
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        assert_eq!(ret, 0);
+        let mut backend = UnixSocketBackend {
+            socket: unsafe { File::from_raw_fd(fds[0]) },
+        };
+        // Only used to send and receive whole messages on the other end.
+        let peer = unsafe { UnixDatagram::from_raw_fd(fds[1]) };
+
+        let mut buf = [0xffu8; 128];
+        assert_eq!(
+            backend.read_frame(&mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        peer.send(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(backend.read_frame(&mut buf).unwrap(), vnet_hdr_len() + 4);
+        assert!(buf[..vnet_hdr_len()].iter().all(|b| *b == 0));
+        assert_eq!(&buf[vnet_hdr_len()..vnet_hdr_len() + 4], &[1, 2, 3, 4]);
+
+        let mut frame = vec![0u8; vnet_hdr_len()];
+        frame.extend_from_slice(&[5, 6, 7]);
+        assert_eq!(backend.write_frame(&frame).unwrap(), frame.len());
+        let mut recv_buf = [0u8; 16];
+        assert_eq!(peer.recv(&mut recv_buf).unwrap(), 3);
+        assert_eq!(&recv_buf[..3], &[5, 6, 7]);
+
+        assert_eq!(
+            backend.write_frame(&[0u8; 1]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        drop(peer);
+        assert_eq!(
+            backend.read_frame(&mut buf).unwrap_err().kind(),
+            ErrorKind::ConnectionReset
+        );
+    }
+}
@@ -0,0 +1,558 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! AF_XDP sockets, exchanging the frames of a host interface queue with userspace
+//! without going through the kernel network stack.
+
+use std::any::Any;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{mem, ptr};
+
+use super::backend::NetBackend;
+use super::device::{init_vnet_hdr, vnet_hdr_len};
+
+// See include/uapi/linux/if_xdp.h and include/linux/socket.h in the kernel code.
+const AF_XDP: c_int = 44;
+const SOL_XDP: c_int = 283;
+const XDP_MMAP_OFFSETS: c_int = 1;
+const XDP_RX_RING: c_int = 2;
+const XDP_TX_RING: c_int = 3;
+const XDP_UMEM_REG: c_int = 4;
+const XDP_UMEM_FILL_RING: c_int = 5;
+const XDP_UMEM_COMPLETION_RING: c_int = 6;
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x1_8000_0000;
+
+// See include/uapi/linux/bpf.h in the kernel code.
+const BPF_MAP_UPDATE_ELEM: c_int = 2;
+const BPF_OBJ_GET: c_int = 7;
+
+// Number of descriptors of each ring.
+const RING_SIZE: u32 = 256;
+// Size of the UMEM chunks, each of them holds a single frame.
+const FRAME_SIZE: usize = 4096;
+// The first half of the chunks receives frames, the second half sends them.
+const NUM_FRAMES: usize = 2 * RING_SIZE as usize;
+
+/// Errors that can occur while setting up an AF_XDP socket.
+#[derive(Debug)]
+pub enum Error {
+    /// The host interface does not exist.
+    InvalidIfname,
+    /// Creating the socket failed.
+    CreateSocket(IoError),
+    /// Allocating the memory shared with the kernel failed.
+    AllocUmem(IoError),
+    /// Configuring the socket failed.
+    SetSockOpt(IoError),
+    /// Getting the offsets of the rings failed.
+    GetMmapOffsets(IoError),
+    /// Mapping a ring failed.
+    MapRing(IoError),
+    /// Binding the socket to the interface queue failed.
+    Bind(IoError),
+    /// Registering the socket in the XSKMAP failed.
+    RegisterXskMap(IoError),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[repr(C)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpRingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpMmapOffsets {
+    rx: XdpRingOffset,
+    tx: XdpRingOffset,
+    fr: XdpRingOffset,
+    cr: XdpRingOffset,
+}
+
+#[repr(C)]
+struct SockaddrXdp {
+    sxdp_family: u16,
+    sxdp_flags: u16,
+    sxdp_ifindex: u32,
+    sxdp_queue_id: u32,
+    sxdp_shared_umem_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+#[repr(C)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+#[repr(C)]
+struct BpfMapUpdateElemAttr {
+    map_fd: u32,
+    pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+// A memory mapping, unmapped when dropped.
+struct MmapRegion {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl MmapRegion {
+    fn new(len: usize, flags: c_int, fd: RawFd, offset: libc::off_t) -> IoResult<Self> {
+        // Safe because we check the result, and the mapping is only unmapped when dropped.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(IoError::last_os_error());
+        }
+        Ok(MmapRegion { addr, len })
+    }
+}
+
+impl Drop for MmapRegion {
+    fn drop(&mut self) {
+        // Safe because the region was mapped by `new()`.
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
+
+// A single producer, single consumer ring shared with the kernel. The producer and consumer
+// indexes wrap around and their difference is the number of entries in the ring.
+struct Ring<T> {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    entries: *mut T,
+}
+
+impl<T: Copy> Ring<T> {
+    // Builds a ring at `offset` in the memory starting at `base`.
+    //
+    // Safe as long as the memory holds the indexes and `RING_SIZE` entries at the offsets,
+    // and outlives the ring.
+    unsafe fn new(base: *mut c_void, offset: &XdpRingOffset) -> Self {
+        let base = base as *mut u8;
+        Ring {
+            producer: base.add(offset.producer as usize) as *const AtomicU32,
+            consumer: base.add(offset.consumer as usize) as *const AtomicU32,
+            entries: base.add(offset.desc as usize) as *mut T,
+        }
+    }
+
+    fn map_len(offset: &XdpRingOffset) -> usize {
+        offset.desc as usize + RING_SIZE as usize * mem::size_of::<T>()
+    }
+
+    fn entry(&self, index: u32) -> *mut T {
+        // Safe because the index is masked to the size of the ring.
+        unsafe { self.entries.add((index & (RING_SIZE - 1)) as usize) }
+    }
+
+    // Adds an entry to a ring we produce to, returning false if the ring is full.
+    fn push(&mut self, entry: T) -> bool {
+        // Safe because the indexes are valid for the lifetime of the ring.
+        let (producer, consumer) = unsafe { (&*self.producer, &*self.consumer) };
+        let prod = producer.load(Ordering::Relaxed);
+        if prod.wrapping_sub(consumer.load(Ordering::Acquire)) >= RING_SIZE {
+            return false;
+        }
+        // Safe because the entry belongs to the producer until the index is published.
+        unsafe { ptr::write_volatile(self.entry(prod), entry) };
+        producer.store(prod.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // Takes an entry from a ring we consume from, if there is any.
+    fn pop(&mut self) -> Option<T> {
+        // Safe because the indexes are valid for the lifetime of the ring.
+        let (producer, consumer) = unsafe { (&*self.producer, &*self.consumer) };
+        let cons = consumer.load(Ordering::Relaxed);
+        if cons == producer.load(Ordering::Acquire) {
+            return None;
+        }
+        // Safe because the entry belongs to the consumer until the index is published.
+        let entry = unsafe { ptr::read_volatile(self.entry(cons)) };
+        consumer.store(cons.wrapping_add(1), Ordering::Release);
+        Some(entry)
+    }
+}
+
+fn set_sock_opt<T>(socket: &File, name: c_int, value: &T) -> Result<()> {
+    // Safe because `value` is valid for its size and we check the result.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_XDP,
+            name,
+            value as *const T as *const c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::SetSockOpt(IoError::last_os_error()));
+    }
+    Ok(())
+}
+
+fn bpf<T>(cmd: c_int, attr: &T) -> IoResult<c_int> {
+    // Safe because `attr` is valid for its size and we check the result.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(ret as c_int)
+}
+
+/// An AF_XDP socket bound to a queue of a host interface.
+pub struct XdpSocket {
+    socket: File,
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    // The chunks which can hold frames to send.
+    free_tx_frames: Vec<u64>,
+    umem: MmapRegion,
+    // Keep the rings mapped as long as they are used.
+    _ring_regions: Vec<MmapRegion>,
+}
+
+// The raw pointers of the rings and of the UMEM are only accessed through the socket.
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    /// Opens a socket for the `queue_id` queue of the `if_name` host interface, and registers
+    /// it in the XSKMAP pinned at `xsk_map_path`.
+    pub fn open(if_name: &str, queue_id: u32, xsk_map_path: &str) -> Result<Self> {
+        let if_name = CString::new(if_name).map_err(|_| Error::InvalidIfname)?;
+        // Safe because `if_name` is null terminated.
+        let ifindex = unsafe { libc::if_nametoindex(if_name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(Error::InvalidIfname);
+        }
+
+        // Safe because we check the result.
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(Error::CreateSocket(IoError::last_os_error()));
+        }
+        // We just checked that the fd is valid, the file closes it when dropped.
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        let umem = MmapRegion::new(
+            NUM_FRAMES * FRAME_SIZE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+        .map_err(Error::AllocUmem)?;
+        let umem_reg = XdpUmemReg {
+            addr: umem.addr as u64,
+            len: umem.len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+        };
+        set_sock_opt(&socket, XDP_UMEM_REG, &umem_reg)?;
+        for ring in &[
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            set_sock_opt(&socket, *ring, &RING_SIZE)?;
+        }
+
+        let mut offsets = XdpMmapOffsets::default();
+        let mut offsets_len = mem::size_of::<XdpMmapOffsets>() as libc::socklen_t;
+        // Safe because `offsets` is valid for `offsets_len` bytes and we check the result.
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut offsets as *mut XdpMmapOffsets as *mut c_void,
+                &mut offsets_len,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::GetMmapOffsets(IoError::last_os_error()));
+        }
+        // Kernels older than 5.4 use a layout without the ring flags.
+        if offsets_len as usize != mem::size_of::<XdpMmapOffsets>() {
+            return Err(Error::GetMmapOffsets(IoError::from(ErrorKind::InvalidData)));
+        }
+
+        let map_ring = |len, pgoff| {
+            MmapRegion::new(
+                len,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                socket.as_raw_fd(),
+                pgoff,
+            )
+            .map_err(Error::MapRing)
+        };
+        let rx_region = map_ring(Ring::<XdpDesc>::map_len(&offsets.rx), XDP_PGOFF_RX_RING)?;
+        let tx_region = map_ring(Ring::<XdpDesc>::map_len(&offsets.tx), XDP_PGOFF_TX_RING)?;
+        let fill_region = map_ring(Ring::<u64>::map_len(&offsets.fr), XDP_UMEM_PGOFF_FILL_RING)?;
+        let completion_region = map_ring(
+            Ring::<u64>::map_len(&offsets.cr),
+            XDP_UMEM_PGOFF_COMPLETION_RING,
+        )?;
+
+        // Safe because each region was mapped with the size of its ring, and is kept
+        // along with it.
+        let mut xdp_socket = unsafe {
+            XdpSocket {
+                rx: Ring::new(rx_region.addr, &offsets.rx),
+                tx: Ring::new(tx_region.addr, &offsets.tx),
+                fill: Ring::new(fill_region.addr, &offsets.fr),
+                completion: Ring::new(completion_region.addr, &offsets.cr),
+                free_tx_frames: (RING_SIZE as usize..NUM_FRAMES)
+                    .map(|frame| (frame * FRAME_SIZE) as u64)
+                    .collect(),
+                socket,
+                umem,
+                _ring_regions: vec![rx_region, tx_region, fill_region, completion_region],
+            }
+        };
+        // Hand the receive chunks to the kernel.
+        for frame in 0..RING_SIZE as usize {
+            xdp_socket.fill.push((frame * FRAME_SIZE) as u64);
+        }
+
+        // The kernel picks the zero-copy mode if the driver supports it.
+        let addr = SockaddrXdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: 0,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue_id,
+            sxdp_shared_umem_fd: 0,
+        };
+        // Safe because `addr` is valid for its size and we check the result.
+        let ret = unsafe {
+            libc::bind(
+                xdp_socket.socket.as_raw_fd(),
+                &addr as *const SockaddrXdp as *const libc::sockaddr,
+                mem::size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Bind(IoError::last_os_error()));
+        }
+
+        xdp_socket.register(queue_id, xsk_map_path)?;
+
+        Ok(xdp_socket)
+    }
+
+    // Inserts the socket in the XSKMAP at index `queue_id`, so that the XDP program can
+    // redirect the frames of that queue to it.
+    fn register(&self, queue_id: u32, xsk_map_path: &str) -> Result<()> {
+        let path = CString::new(xsk_map_path)
+            .map_err(|_| Error::RegisterXskMap(IoError::from(ErrorKind::InvalidInput)))?;
+        let map_fd = bpf(
+            BPF_OBJ_GET,
+            &BpfObjGetAttr {
+                pathname: path.as_ptr() as u64,
+                bpf_fd: 0,
+                file_flags: 0,
+            },
+        )
+        .map_err(Error::RegisterXskMap)?;
+        // We just checked that the fd is valid, the file closes it when dropped.
+        let map = unsafe { File::from_raw_fd(map_fd) };
+
+        let socket_fd = self.socket.as_raw_fd() as u32;
+        bpf(
+            BPF_MAP_UPDATE_ELEM,
+            &BpfMapUpdateElemAttr {
+                map_fd: map.as_raw_fd() as u32,
+                pad: 0,
+                key: &queue_id as *const u32 as u64,
+                value: &socket_fd as *const u32 as u64,
+                flags: 0,
+            },
+        )
+        .map_err(Error::RegisterXskMap)?;
+
+        Ok(())
+    }
+
+    fn umem_chunk(&mut self, addr: u64, len: usize) -> &mut [u8] {
+        // Safe because the descriptors only refer to memory inside the UMEM.
+        unsafe {
+            std::slice::from_raw_parts_mut((self.umem.addr as *mut u8).add(addr as usize), len)
+        }
+    }
+}
+
+impl NetBackend for XdpSocket {
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let desc = self
+            .rx
+            .pop()
+            .ok_or_else(|| IoError::from(ErrorKind::WouldBlock))?;
+        let len = std::cmp::min(desc.len as usize, buf.len().saturating_sub(vnet_hdr_len()));
+        let frame = &self.umem_chunk(desc.addr, len)[..];
+        buf[vnet_hdr_len()..vnet_hdr_len() + len].copy_from_slice(frame);
+        // The frame does not need any offload from the guest.
+        init_vnet_hdr(buf);
+
+        // Give the chunk back to the kernel. It can't overflow the fill ring, which has
+        // room for all the receive chunks.
+        self.fill.push(desc.addr & !(FRAME_SIZE as u64 - 1));
+
+        Ok(vnet_hdr_len() + len)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if buf.len() < vnet_hdr_len() || buf.len() - vnet_hdr_len() > FRAME_SIZE {
+            return Err(IoError::from(ErrorKind::InvalidInput));
+        }
+        let frame = &buf[vnet_hdr_len()..];
+
+        // Reclaim the chunks of the frames sent so far.
+        while let Some(addr) = self.completion.pop() {
+            self.free_tx_frames.push(addr);
+        }
+        let addr = self
+            .free_tx_frames
+            .pop()
+            .ok_or_else(|| IoError::from(ErrorKind::WouldBlock))?;
+        self.umem_chunk(addr, frame.len()).copy_from_slice(frame);
+        // There are as many send chunks as tx ring entries, so the ring can't be full.
+        self.tx.push(XdpDesc {
+            addr,
+            len: frame.len() as u32,
+            options: 0,
+        });
+
+        // Wake up the kernel to send the frame.
+        // Safe because no buffer is passed and we check the result.
+        let ret = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = IoError::last_os_error();
+            match err.raw_os_error() {
+                // The kernel is busy sending previous frames and will also send this one.
+                Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ENOBUFS) => (),
+                _ => return Err(err),
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring() {
+        // The indexes followed by the entries, as laid out by the kernel.
+        let offset = XdpRingOffset {
+            producer: 0,
+            consumer: 64,
+            desc: 128,
+            flags: 0,
+        };
+        let mut memory = vec![0u64; Ring::<u64>::map_len(&offset) / 8];
+        // Safe because the memory is large enough for the ring, and outlives it.
+        let mut ring: Ring<u64> = unsafe { Ring::new(memory.as_mut_ptr() as *mut c_void, &offset) };
+
+        assert_eq!(ring.pop(), None);
+        for entry in 0..RING_SIZE as u64 {
+            assert!(ring.push(entry));
+        }
+        assert!(!ring.push(RING_SIZE as u64));
+        for entry in 0..RING_SIZE as u64 {
+            assert_eq!(ring.pop(), Some(entry));
+        }
+        assert_eq!(ring.pop(), None);
+
+        // The indexes wrap around.
+        assert!(ring.push(RING_SIZE as u64));
+        assert_eq!(ring.pop(), Some(RING_SIZE as u64));
+        assert_eq!(memory[0], RING_SIZE as u64 + 1);
+        assert_eq!(memory[8], RING_SIZE as u64 + 1);
+    }
+
+    #[test]
+    fn test_open_errors() {
+        match XdpSocket::open("invalid\0name", 0, "/sys/fs/bpf/xsks_map") {
+            Err(Error::InvalidIfname) => (),
+            _ => panic!("Unexpected result."),
+        }
+        match XdpSocket::open("noiface", 0, "/sys/fs/bpf/xsks_map") {
+            Err(Error::InvalidIfname) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+}
@@ -26,7 +26,7 @@ use std::sync::Arc;
 use logger::{debug, error, warn, IncMetric, METRICS};
 use utils::byte_order;
 use utils::eventfd::EventFd;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::super::super::Error as DeviceError;
 use super::super::{
@@ -132,6 +132,46 @@ where
         })
     }
 
+    /// Send a `VIRTIO_VSOCK_EVENT_TRANSPORT_RESET` event to the guest driver, which then drops all
+    /// its connections. Nothing is sent if the device is not activated.
+    pub fn send_transport_reset_event(&mut self) -> result::Result<(), DeviceError> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            DeviceState::Inactive => return Ok(()),
+        };
+
+        let head = match self.queues[EVQ_INDEX].pop(mem) {
+            Some(head) => head,
+            None => {
+                warn!("vsock: no event queue buffer for the transport reset event");
+                METRICS.vsock.ev_queue_event_fails.inc();
+                return Ok(());
+            }
+        };
+
+        let event_len = std::mem::size_of::<u32>() as u32;
+        let result = if head.is_write_only() && head.len >= event_len {
+            mem.write_obj(uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET.to_le(), head.addr)
+                .map_err(|_| DeviceError::MalformedDescriptor)
+        } else {
+            Err(DeviceError::MalformedDescriptor)
+        };
+        let used_len = if result.is_ok() { event_len } else { 0 };
+
+        self.queues[EVQ_INDEX]
+            .add_used(mem, head.index, used_len)
+            .map_err(DeviceError::QueueError)?;
+        self.signal_used_queue()?;
+        result
+    }
+
+    /// Drop the connections of the backend, and let the guest driver know that it should do the
+    /// same. The host-side listening sockets are kept, so both ends can reconnect right away.
+    pub fn reset_transport(&mut self) -> result::Result<(), DeviceError> {
+        self.backend.reset();
+        self.send_transport_reset_event()
+    }
+
     /// Walk the driver-provided RX queue buffers and attempt to fill them up with any data that we
     /// have pending. Return `true` if descriptors have been added to the used ring, and `false`
     /// otherwise.
@@ -229,9 +229,9 @@ mod tests {
 
     use crate::virtio::vsock::packet::VSOCK_PKT_HDR_SIZE;
     use crate::virtio::vsock::test_utils::{EventHandlerContext, TestContext};
-    use crate::virtio::VIRTIO_MMIO_INT_VRING;
+    use crate::virtio::{VIRTIO_MMIO_INT_VRING, VIRTQ_DESC_F_WRITE};
     use crate::Error as DeviceError;
-    use vm_memory::Bytes;
+    use vm_memory::{Bytes, GuestAddress};
 
     #[test]
     fn test_irq() {
@@ -418,6 +418,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_transport_reset() {
+        const EVENT_ADDR: u64 = 0x0060_0000;
+
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+
+        // Inactive devices only reset their backend.
+        ctx.device.reset_transport().unwrap();
+        assert_eq!(ctx.device.backend.reset_cnt, 1);
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 0);
+
+        ctx.mock_activate(test_ctx.mem.clone());
+
+        // Without any event queue buffer, the event is dropped.
+        ctx.device.reset_transport().unwrap();
+        assert_eq!(ctx.device.backend.reset_cnt, 2);
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 0);
+
+        // The event is written to the first available buffer.
+        test_ctx
+            .mem
+            .write_obj(0xffff_ffffu32, GuestAddress(EVENT_ADDR))
+            .unwrap();
+        ctx.guest_evvq.dtable[0].set(EVENT_ADDR, 4, VIRTQ_DESC_F_WRITE, 0);
+        ctx.guest_evvq.avail.ring[0].set(0);
+        ctx.guest_evvq.avail.idx.set(1);
+        ctx.device.reset_transport().unwrap();
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+        assert_eq!(ctx.guest_evvq.used.ring[0].get().len, 4);
+        assert_eq!(
+            test_ctx
+                .mem
+                .read_obj::<u32>(GuestAddress(EVENT_ADDR))
+                .unwrap(),
+            defs::uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET
+        );
+        assert_eq!(
+            ctx.device.interrupt_status.load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+
+        // Read-only buffers are returned unused.
+        ctx.guest_evvq.dtable[1].set(EVENT_ADDR, 4, 0, 0);
+        ctx.guest_evvq.avail.ring[1].set(1);
+        ctx.guest_evvq.avail.idx.set(2);
+        match ctx.device.send_transport_reset_event() {
+            Err(DeviceError::MalformedDescriptor) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 2);
+        assert_eq!(ctx.guest_evvq.used.ring[1].get().len, 0);
+    }
+
     #[test]
     fn test_backend_event() {
         // Test case:
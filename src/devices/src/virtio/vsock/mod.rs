@@ -19,7 +19,10 @@ use crate::virtio::persist::Error as VirtioStateError;
 
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
 pub use self::device::Vsock;
-pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
+pub use self::unix::{
+    Error as VsockUnixBackendError, HostEndpoint as VsockHostEndpoint,
+    PortMapping as VsockPortMapping, VsockUnixBackend,
+};
 
 use utils::epoll::EventSet;
 use vm_memory::GuestMemoryError;
@@ -90,6 +93,12 @@ mod defs {
         pub const VSOCK_TYPE_STREAM: u16 = 1;
 
         pub const VSOCK_HOST_CID: u64 = 2;
+
+        /// Vsock event IDs.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        ///
+        /// The transport was reset: the driver drops all its connections.
+        pub const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
     }
 }
 
@@ -160,4 +169,8 @@ pub trait VsockChannel {
 /// The vsock backend, which is basically an epoll-event-driven vsock channel.
 /// Currently, the only implementation we have is `crate::virtio::unix::muxer::VsockMuxer`, which
 /// translates guest-side vsock connections to host-side Unix domain socket connections.
-pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {}
+pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {
+    /// Drop all the connections, since the guest driver is told to do the same through a
+    /// transport reset event. New connections are accepted afterwards.
+    fn reset(&mut self);
+}
@@ -7,6 +7,7 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use super::*;
+use logger::error;
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
@@ -39,6 +40,71 @@ pub enum VsockBackendState {
 pub struct VsockUdsState {
     /// The path for the UDS socket.
     pub(crate) path: String,
+    /// The routing overrides of individual guest ports.
+    #[version(start = 2, ser_fn = "port_map_serialize")]
+    pub(crate) port_map: Vec<VsockPortMappingState>,
+}
+
+impl VsockUdsState {
+    /// Returns the path of the host Unix socket of the saved backend.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Sets the path of the host Unix socket the restored backend will listen on.
+    pub fn set_path(&mut self, path: String) {
+        self.path = path;
+    }
+
+    fn port_map_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.port_map.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vsock port maps.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The serializable state of a vsock port map entry.
+#[derive(Clone, Versionize)]
+pub struct VsockPortMappingState {
+    port: u32,
+    target_uds_path: Option<String>,
+    target_tcp_port: Option<u16>,
+    listen_path: Option<String>,
+}
+
+impl From<&VsockPortMapping> for VsockPortMappingState {
+    fn from(mapping: &VsockPortMapping) -> Self {
+        let (target_uds_path, target_tcp_port) = match mapping.target.as_ref() {
+            Some(VsockHostEndpoint::Unix(path)) => (Some(path.clone()), None),
+            Some(VsockHostEndpoint::TcpLocalhost(port)) => (None, Some(*port)),
+            None => (None, None),
+        };
+        VsockPortMappingState {
+            port: mapping.port,
+            target_uds_path,
+            target_tcp_port,
+            listen_path: mapping.listen_path.clone(),
+        }
+    }
+}
+
+impl From<&VsockPortMappingState> for VsockPortMapping {
+    fn from(state: &VsockPortMappingState) -> Self {
+        let target = match (state.target_uds_path.as_ref(), state.target_tcp_port) {
+            (Some(path), _) => Some(VsockHostEndpoint::Unix(path.clone())),
+            (None, Some(port)) => Some(VsockHostEndpoint::TcpLocalhost(port)),
+            (None, None) => None,
+        };
+        VsockPortMapping {
+            port: state.port,
+            target,
+            listen_path: state.listen_path.clone(),
+        }
+    }
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -61,6 +127,11 @@ impl Persist<'_> for VsockUnixBackend {
     fn save(&self) -> Self::State {
         VsockBackendState::Uds(VsockUdsState {
             path: self.host_sock_path.clone(),
+            port_map: self
+                .port_map
+                .iter()
+                .map(VsockPortMappingState::from)
+                .collect(),
         })
     }
 
@@ -72,6 +143,11 @@ impl Persist<'_> for VsockUnixBackend {
             VsockBackendState::Uds(uds_state) => Ok(VsockUnixBackend::new(
                 constructor_args.cid,
                 uds_state.path.clone(),
+                uds_state
+                    .port_map
+                    .iter()
+                    .map(VsockPortMapping::from)
+                    .collect(),
             )?),
         }
     }
@@ -116,6 +192,12 @@ where
         } else {
             DeviceState::Inactive
         };
+
+        // The connections of the guest driver didn't survive the snapshot on the host side, so
+        // let the driver know it has to drop them.
+        if let Err(e) = vsock.send_transport_reset_event() {
+            error!("Failed to send the vsock transport reset event: {:?}", e);
+        }
         Ok(vsock)
     }
 }
@@ -137,6 +219,7 @@ pub(crate) mod tests {
         fn save(&self) -> Self::State {
             VsockBackendState::Uds(VsockUdsState {
                 path: "test".to_owned(),
+                port_map: Vec::new(),
             })
         }
 
@@ -230,4 +313,93 @@ pub(crate) mod tests {
         restored_device.read_config(2, &mut data);
         assert_eq!(data, [0u8, 1, 2, 3, 4, 5, 6, 7]);
     }
+
+    #[test]
+    fn test_persist_transport_reset() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.mock_activate(test_ctx.mem.clone());
+        ctx.guest_evvq.dtable[0].set(0x0060_0000, 4, crate::virtio::VIRTQ_DESC_F_WRITE, 0);
+        ctx.guest_evvq.avail.ring[0].set(0);
+        ctx.guest_evvq.avail.idx.set(1);
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        ctx.device
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Restoring an activated device makes the guest driver drop its stale connections.
+        let restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: test_ctx.mem.clone(),
+                backend: TestBackend::new(),
+            },
+            &VsockFrontendState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_device.is_activated());
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+        assert_eq!(ctx.guest_evvq.used.ring[0].get().len, 4);
+    }
+
+    #[test]
+    fn test_persist_port_map() {
+        let mut host_sock_file = utils::tempfile::TempFile::new().unwrap();
+        host_sock_file.remove().unwrap();
+        let host_sock_path = host_sock_file.as_path().to_str().unwrap().to_owned();
+        let listen_path = format!("{}_listen", host_sock_path);
+        let port_map = vec![
+            VsockPortMapping {
+                port: 52,
+                target: Some(VsockHostEndpoint::TcpLocalhost(8080)),
+                listen_path: Some(listen_path.clone()),
+            },
+            VsockPortMapping {
+                port: 53,
+                target: Some(VsockHostEndpoint::Unix("/tmp/dns.sock".to_owned())),
+                listen_path: None,
+            },
+        ];
+        let backend = VsockUnixBackend::new(3, host_sock_path.clone(), port_map.clone()).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+
+        // Port maps can't be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                backend
+                    .save()
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .err()
+                    .unwrap()
+            ),
+            "Semantic(\"Target version does not implement vsock port maps.\")"
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(VsockUdsState::type_id(), 2);
+        backend
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(backend);
+        std::fs::remove_file(&host_sock_path).unwrap();
+        std::fs::remove_file(&listen_path).unwrap();
+
+        let restored_backend = VsockUnixBackend::restore(
+            VsockUdsConstructorArgs { cid: 3 },
+            &VsockBackendState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_backend.port_map, port_map);
+
+        drop(restored_backend);
+        std::fs::remove_file(&host_sock_path).unwrap();
+        std::fs::remove_file(&listen_path).unwrap();
+    }
 }
@@ -26,6 +26,7 @@ pub struct TestBackend {
     pub rx_ok_cnt: usize,
     pub tx_ok_cnt: usize,
     pub evset: Option<EventSet>,
+    pub reset_cnt: usize,
 }
 
 impl TestBackend {
@@ -38,6 +39,7 @@ impl TestBackend {
             rx_ok_cnt: 0,
             tx_ok_cnt: 0,
             evset: None,
+            reset_cnt: 0,
         }
     }
 
@@ -104,7 +106,11 @@ impl VsockEpollListener for TestBackend {
         self.evset = Some(evset);
     }
 }
-impl VsockBackend for TestBackend {}
+impl VsockBackend for TestBackend {
+    fn reset(&mut self) {
+        self.reset_cnt += 1;
+    }
+}
 
 pub struct TestContext {
     pub cid: u64,
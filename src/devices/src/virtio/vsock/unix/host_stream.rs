@@ -0,0 +1,65 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+
+/// `HostStream` is the host-side end of a muxer connection. Most connections go through Unix
+/// sockets, but the port map can also route guest ports to TCP endpoints on localhost.
+use std::io::{Read, Result, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+/// A connected host-side socket.
+#[derive(Debug)]
+pub enum HostStream {
+    /// A Unix domain socket stream.
+    Unix(UnixStream),
+    /// A TCP stream, connected to localhost.
+    Tcp(TcpStream),
+}
+
+impl Read for HostStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            HostStream::Unix(stream) => stream.read(buf),
+            HostStream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for HostStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            HostStream::Unix(stream) => stream.write(buf),
+            HostStream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            HostStream::Unix(stream) => stream.flush(),
+            HostStream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for HostStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            HostStream::Unix(stream) => stream.as_raw_fd(),
+            HostStream::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl From<UnixStream> for HostStream {
+    fn from(stream: UnixStream) -> Self {
+        HostStream::Unix(stream)
+    }
+}
+
+impl From<TcpStream> for HostStream {
+    fn from(stream: TcpStream) -> Self {
+        HostStream::Tcp(stream)
+    }
+}
@@ -7,12 +7,35 @@
 /// `muxer::VsockMuxer`, a connection multiplexer that uses `super::csm::VsockConnection` for
 /// handling vsock connection states.
 /// Check out `muxer.rs` for a more detailed explanation of the inner workings of this backend.
+mod host_stream;
 mod muxer;
 mod muxer_killq;
 mod muxer_rxq;
 
 pub use muxer::VsockMuxer as VsockUnixBackend;
 
+/// A host-side endpoint, to which guest-initiated connections can be forwarded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostEndpoint {
+    /// A Unix socket, listening at the given path.
+    Unix(String),
+    /// A TCP socket, listening on the given localhost port.
+    TcpLocalhost(u16),
+}
+
+/// Overrides how connections to a guest vsock port are routed on the host side.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortMapping {
+    /// The guest vsock port.
+    pub port: u32,
+    /// Guest-initiated connections to `port` are forwarded to this endpoint, instead of the
+    /// `<uds_path>_<port>` Unix socket.
+    pub target: Option<HostEndpoint>,
+    /// Host-initiated connections accepted on the Unix socket bound at this path are forwarded
+    /// to the guest `port`, without the `CONNECT <port>` handshake.
+    pub listen_path: Option<String>,
+}
+
 mod defs {
     /// Maximum number of established connections that we can handle.
     pub const MAX_CONNECTIONS: usize = 1023;
@@ -38,6 +61,8 @@ pub enum Error {
     UnixBind(std::io::Error),
     /// Error connecting to a host-side Unix socket.
     UnixConnect(std::io::Error),
+    /// Error connecting to a host-side TCP socket.
+    TcpConnect(std::io::Error),
     /// Error reading from host-side Unix socket.
     UnixRead(std::io::Error),
     /// Muxer connection limit reached.
@@ -45,4 +70,4 @@ pub enum Error {
 }
 
 type Result<T> = std::result::Result<T, Error>;
-type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
+type MuxerConnection = super::csm::VsockConnection<host_stream::HostStream>;
@@ -25,6 +25,9 @@
 ///       destination port to which it wants to connect);
 ///    3. Some event was triggered for a connected Unix socket, that belongs to a
 ///       `VsockConnection`.
+///    4. A new host-initiated connection is ready to be accepted from the listening host Unix
+///       socket of a port map entry. These connections skip step 2, since the destination
+///       port is given by the port map.
 ///    The muxer gets notified about all of these events, because, as a `VsockEpollListener`
 ///    implementor, it gets to register a nested epoll FD into the main VMM epolling loop. All
 ///    other pollable FDs are then registered under this nested epoll FD.
@@ -32,6 +35,7 @@
 ///    mapping `RawFd`s to `EpollListener`s.
 use std::collections::{HashMap, HashSet};
 use std::io::Read;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 
@@ -45,10 +49,11 @@ use super::super::{
     Result as VsockResult, VsockBackend, VsockChannel, VsockEpollListener, VsockError,
 };
 use super::defs;
+use super::host_stream::HostStream;
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
 use super::MuxerConnection;
-use super::{Error, Result};
+use super::{Error, HostEndpoint, PortMapping, Result};
 
 /// A unique identifier of a `MuxerConnection` object. Connections are stored in a hash map,
 /// keyed by a `ConnMapKey` object.
@@ -78,6 +83,9 @@ enum EpollListener {
     /// A listener interested in reading host "connect <port>" commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// A listener interested in new host-initiated connections to the guest `port`, accepted
+    /// from the Unix socket of a port map entry.
+    PortSock { port: u32, sock: UnixListener },
 }
 
 /// The vsock connection multiplexer.
@@ -109,6 +117,11 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The routing overrides of individual guest ports.
+    pub(crate) port_map: Vec<PortMapping>,
+    /// Host-initiated connections accepted from port map sockets. Since they skipped the
+    /// "connect <port>" command, they don't get the "OK <port>" ack either.
+    port_sock_conns: HashSet<ConnMapKey>,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -305,11 +318,28 @@ impl VsockEpollListener for VsockMuxer {
     }
 }
 
-impl VsockBackend for VsockMuxer {}
+impl VsockBackend for VsockMuxer {
+    /// Drop all the connections, closing their host-side streams.
+    ///
+    /// The host-side listening sockets are left untouched, and so are the host-initiated
+    /// connections still waiting for their `CONNECT <port>` request, since the guest driver
+    /// knows nothing about them yet.
+    fn reset(&mut self) {
+        let keys: Vec<ConnMapKey> = self.conn_map.keys().copied().collect();
+        for key in keys {
+            self.remove_connection(key);
+        }
+        self.rxq = MuxerRxQ::new();
+        self.killq = MuxerKillQ::new();
+    }
+}
 
 impl VsockMuxer {
     /// Muxer constructor.
-    pub fn new(cid: u64, host_sock_path: String) -> Result<Self> {
+    ///
+    /// The Unix sockets of the `port_map` entries which accept host-initiated connections are
+    /// bound here as well.
+    pub fn new(cid: u64, host_sock_path: String, port_map: Vec<PortMapping>) -> Result<Self> {
         // Open/bind on the host Unix socket, so we can accept host-initiated
         // connections.
         let host_sock = UnixListener::bind(&host_sock_path)
@@ -327,10 +357,30 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            port_map: Vec::new(),
+            port_sock_conns: HashSet::new(),
         };
 
         // Listen on the host initiated socket, for incomming connections.
         muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
+
+        // Listen on the port map sockets as well.
+        for mapping in port_map.iter() {
+            if let Some(listen_path) = mapping.listen_path.as_ref() {
+                let sock = UnixListener::bind(listen_path)
+                    .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+                    .map_err(Error::UnixBind)?;
+                muxer.add_listener(
+                    sock.as_raw_fd(),
+                    EpollListener::PortSock {
+                        port: mapping.port,
+                        sock,
+                    },
+                )?;
+            }
+        }
+        muxer.port_map = port_map;
+
         Ok(muxer)
     }
 
@@ -399,7 +449,7 @@ impl VsockMuxer {
                                     peer_port,
                                 },
                                 MuxerConnection::new_local_init(
-                                    stream,
+                                    HostStream::from(stream),
                                     uapi::VSOCK_HOST_CID,
                                     self.cid,
                                     local_port,
@@ -413,6 +463,47 @@ impl VsockMuxer {
                 }
             }
 
+            // A new host-initiated connection to a port map entry is ready to be accepted. The
+            // destination port is already known, so the connection is created right away.
+            Some(EpollListener::PortSock { port, sock }) => {
+                let peer_port = *port;
+                let accepted = sock.accept();
+                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                    warn!("vsock: connection limit reached; refusing new host connection");
+                    return;
+                }
+                accepted
+                    .and_then(|(stream, _)| stream.set_nonblocking(true).map(|_| stream))
+                    .map_err(Error::UnixAccept)
+                    .and_then(|stream| {
+                        let local_port = self.allocate_local_port();
+                        let key = ConnMapKey {
+                            local_port,
+                            peer_port,
+                        };
+                        self.add_connection(
+                            key,
+                            MuxerConnection::new_local_init(
+                                HostStream::from(stream),
+                                uapi::VSOCK_HOST_CID,
+                                self.cid,
+                                local_port,
+                                peer_port,
+                            ),
+                        )
+                        .map(|_| {
+                            self.port_sock_conns.insert(key);
+                        })
+                        .map_err(|err| {
+                            self.free_local_port(local_port);
+                            err
+                        })
+                    })
+                    .unwrap_or_else(|err| {
+                        info!("vsock: error adding port map connection: {:?}", err);
+                    });
+            }
+
             _ => {
                 info!("vsock: unexpected event: fd={:?}, evset={:?}", fd, evset);
                 METRICS.vsock.muxer_event_fails.inc();
@@ -506,6 +597,7 @@ impl VsockMuxer {
             self.remove_listener(conn.as_raw_fd());
             METRICS.vsock.conns_removed.inc();
         }
+        self.port_sock_conns.remove(&key);
         self.free_local_port(key.local_port);
     }
 
@@ -536,6 +628,7 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => EventSet::IN,
             EpollListener::HostSock => EventSet::IN,
+            EpollListener::PortSock { .. } => EventSet::IN,
         };
 
         self.epoll
@@ -587,18 +680,43 @@ impl VsockMuxer {
         self.local_port_set.remove(&port);
     }
 
+    /// Connect to the host-side endpoint of a guest-initiated connection to `port`.
+    ///
+    /// That's the endpoint given by the port map, if any. Otherwise, it's the Unix socket
+    /// expected to be listening at the file system path corresponding to the port.
+    fn connect_host_endpoint(&self, port: u32) -> Result<HostStream> {
+        let target = self
+            .port_map
+            .iter()
+            .find(|mapping| mapping.port == port)
+            .and_then(|mapping| mapping.target.as_ref());
+
+        match target {
+            Some(HostEndpoint::Unix(path)) => UnixStream::connect(path)
+                .and_then(|stream| stream.set_nonblocking(true).map(|_| stream.into()))
+                .map_err(Error::UnixConnect),
+            // Only the loopback addresses are dialed, so the port map can't reach other hosts.
+            Some(HostEndpoint::TcpLocalhost(tcp_port)) => TcpStream::connect(
+                &[
+                    SocketAddr::from((Ipv4Addr::LOCALHOST, *tcp_port)),
+                    SocketAddr::from((Ipv6Addr::LOCALHOST, *tcp_port)),
+                ][..],
+            )
+            .and_then(|stream| stream.set_nonblocking(true).map(|_| stream.into()))
+            .map_err(Error::TcpConnect),
+            None => UnixStream::connect(format!("{}_{}", self.host_sock_path, port))
+                .and_then(|stream| stream.set_nonblocking(true).map(|_| stream.into()))
+                .map_err(Error::UnixConnect),
+        }
+    }
+
     /// Handle a new connection request comming from our peer (the guest vsock driver).
     ///
-    /// This will attempt to connect to a host-side Unix socket, expected to be listening at
-    /// the file system path corresponing to the destination port. If successful, a new
-    /// connection object will be created and added to the connection pool. On failure, a new
-    /// RST packet will be scheduled for delivery to the guest.
+    /// This will attempt to connect to the host-side endpoint of the destination port. If
+    /// successful, a new connection object will be created and added to the connection pool.
+    /// On failure, a new RST packet will be scheduled for delivery to the guest.
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
-        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
-
-        UnixStream::connect(port_path)
-            .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
-            .map_err(Error::UnixConnect)
+        self.connect_host_endpoint(pkt.dst_port())
             .and_then(|stream| {
                 self.add_connection(
                     ConnMapKey {
@@ -637,8 +755,11 @@ impl VsockMuxer {
             mut_fn(conn);
 
             // If this is a host-initiated connection that has just become established, we'll have
-            // to send an ack message to the host end.
-            if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
+            // to send an ack message to the host end (unless it skipped the handshake).
+            if prev_state == ConnState::LocalInit
+                && conn.state() == ConnState::Established
+                && !self.port_sock_conns.contains(&key)
+            {
                 let msg = format!("OK {}\n", key.local_port);
                 match conn.send_bytes_raw(msg.as_bytes()) {
                     Ok(written) if written == msg.len() => (),
@@ -811,6 +932,10 @@ mod tests {
 
     impl MuxerTestContext {
         fn new(name: &str) -> Self {
+            Self::new_with_port_map(name, Vec::new())
+        }
+
+        fn new_with_port_map(name: &str, port_map: Vec<PortMapping>) -> Self {
             let vsock_test_ctx = VsockTestContext::new();
             let mut handler_ctx = vsock_test_ctx.create_event_handler_context();
             let pkt = VsockPacket::from_rx_virtq_head(
@@ -820,7 +945,7 @@ mod tests {
             )
             .unwrap();
 
-            let muxer = VsockMuxer::new(PEER_CID, get_file(name)).unwrap();
+            let muxer = VsockMuxer::new(PEER_CID, get_file(name), port_map).unwrap();
             Self {
                 _vsock_test_ctx: vsock_test_ctx,
                 pkt,
@@ -1084,6 +1209,137 @@ mod tests {
         assert!(!ctx.muxer.has_pending_rx());
     }
 
+    #[test]
+    fn test_connect_tcp_localhost() {
+        const TCP_PORT: u32 = 2001;
+
+        let tcp_listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let tcp_port = tcp_listener.local_addr().unwrap().port();
+        let port_map = vec![PortMapping {
+            port: TCP_PORT,
+            target: Some(HostEndpoint::TcpLocalhost(tcp_port)),
+            listen_path: None,
+        }];
+        let ctx = MuxerTestContext::new_with_port_map("connect_tcp_localhost", port_map);
+
+        match ctx.muxer.connect_host_endpoint(TCP_PORT).unwrap() {
+            HostStream::Tcp(stream) => {
+                let peer_addr = stream.peer_addr().unwrap();
+                assert!(peer_addr.ip().is_loopback());
+                assert_eq!(peer_addr.port(), tcp_port);
+            }
+            _ => panic!("Expected a TCP stream."),
+        }
+        let (_, peer_addr) = tcp_listener.accept().unwrap();
+        assert!(peer_addr.ip().is_loopback());
+
+        // Nothing listens on the port anymore.
+        drop(tcp_listener);
+        match ctx.muxer.connect_host_endpoint(TCP_PORT) {
+            Err(Error::TcpConnect(_)) => (),
+            _ => panic!("Expected a connection error."),
+        }
+    }
+
+    #[test]
+    fn test_port_map() {
+        const UNIX_PORT: u32 = 2000;
+        const TCP_PORT: u32 = 2001;
+        const LISTEN_PORT: u32 = 2002;
+        const PEER_PORT: u32 = 1025;
+
+        let mut unix_listener = LocalListener::new(get_file("port_map_target"));
+        let tcp_listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let listen_path = get_file("port_map_listen");
+        let port_map = vec![
+            PortMapping {
+                port: UNIX_PORT,
+                target: Some(HostEndpoint::Unix(
+                    unix_listener.path.to_str().unwrap().to_owned(),
+                )),
+                listen_path: None,
+            },
+            PortMapping {
+                port: TCP_PORT,
+                target: Some(HostEndpoint::TcpLocalhost(
+                    tcp_listener.local_addr().unwrap().port(),
+                )),
+                listen_path: None,
+            },
+            PortMapping {
+                port: LISTEN_PORT,
+                target: None,
+                listen_path: Some(listen_path.clone()),
+            },
+        ];
+        let mut ctx = MuxerTestContext::new_with_port_map("port_map", port_map);
+
+        // Guest connections to mapped ports are forwarded to the mapped Unix socket, instead of
+        // the `<uds_path>_<port>` one.
+        ctx.init_pkt(UNIX_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        let mut unix_stream = unix_listener.accept();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(UNIX_PORT, PEER_PORT, &data);
+        ctx.send();
+        let mut buf = vec![0; data.len()];
+        unix_stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), data);
+
+        // Or to the mapped TCP port on localhost.
+        ctx.init_pkt(TCP_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        let (mut tcp_stream, _) = tcp_listener.accept().unwrap();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), TCP_PORT);
+        ctx.init_data_pkt(TCP_PORT, PEER_PORT, &data);
+        ctx.send();
+        tcp_stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), data);
+        assert_eq!(ctx.muxer.conn_map.len(), 2);
+
+        // Host connections accepted on the socket of a port map entry go straight to the guest
+        // port, without the "connect <port>" command.
+        let mut stream = UnixStream::connect(&listen_path).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.muxer.conn_map.len(), 3);
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+        assert_eq!(ctx.pkt.dst_port(), LISTEN_PORT);
+        let local_port = ctx.pkt.src_port();
+        assert!(ctx.muxer.local_port_set.contains(&local_port));
+
+        // There is no "OK <port>" ack either once the guest accepts the connection.
+        ctx.init_pkt(local_port, LISTEN_PORT, uapi::VSOCK_OP_RESPONSE);
+        ctx.send();
+        let mut buf = vec![0u8; 32];
+        assert_eq!(
+            stream.read(&mut buf[..]).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+
+        let data = [5u8, 6, 7, 8];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
+        assert_eq!(ctx.pkt.src_port(), local_port);
+        assert_eq!(ctx.pkt.dst_port(), LISTEN_PORT);
+
+        // The connection is forgotten once reset.
+        ctx.init_pkt(local_port, LISTEN_PORT, uapi::VSOCK_OP_RST);
+        ctx.send();
+        assert!(ctx.muxer.port_sock_conns.is_empty());
+
+        std::fs::remove_file(&listen_path).unwrap();
+    }
+
     #[test]
     fn test_local_connection() {
         let mut ctx = MuxerTestContext::new("local_connection");
@@ -1112,6 +1368,31 @@ mod tests {
         assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
     }
 
+    #[test]
+    fn test_reset() {
+        let mut ctx = MuxerTestContext::new("reset");
+        let peer_port = 1025;
+        let (mut stream, _) = ctx.local_connect(peer_port);
+
+        // Leave some data pending for the guest.
+        stream.write_all(&[1, 2, 3, 4]).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+
+        ctx.muxer.reset();
+        assert!(!ctx.muxer.has_pending_rx());
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert!(ctx.muxer.local_port_set.is_empty());
+        assert_eq!(ctx.count_epoll_listeners(), (0, 0));
+
+        // The host-side stream has been closed.
+        let mut buf = vec![0u8; 4];
+        assert_eq!(stream.read(buf.as_mut_slice()).unwrap(), 0);
+
+        // The host socket still accepts connections.
+        ctx.local_connect(peer_port);
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
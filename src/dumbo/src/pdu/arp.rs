@@ -89,6 +89,19 @@ impl<'a, T: NetworkBytes> EthIPv4ArpFrame<'a, T> {
     /// If no error occurs, it guarantees accessor methods (which make use of various `_unchecked`
     /// functions) are safe to call on the result, because all predefined offsets will be valid.
     pub fn request_from_bytes(bytes: T) -> Result<Self, Error> {
+        let maybe = EthIPv4ArpFrame::from_bytes(bytes)?;
+
+        if maybe.operation() != OPER_REQUEST {
+            return Err(Error::Operation);
+        }
+
+        Ok(maybe)
+    }
+
+    /// Tries to interpret a byte slice as a valid IPv4 over Ethernet ARP request or reply.
+    ///
+    /// The same guarantees as for `request_from_bytes` apply to the result.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
         // This kind of frame has a fixed length, so we know what to expect.
         if bytes.len() != ETH_IPV4_FRAME_LEN {
             return Err(Error::SliceExactLen);
@@ -113,7 +126,7 @@ impl<'a, T: NetworkBytes> EthIPv4ArpFrame<'a, T> {
             return Err(Error::PLen);
         }
 
-        if maybe.operation() != OPER_REQUEST {
+        if maybe.operation() != OPER_REQUEST && maybe.operation() != OPER_REPLY {
             return Err(Error::Operation);
         }
 
@@ -406,6 +419,18 @@ mod tests {
             Error::Operation
         );
 
+        // Replies are accepted when the operation doesn't matter.
+        {
+            let f = EthIPv4ArpFrame::from_bytes(&a[..ETH_IPV4_FRAME_LEN]).unwrap();
+            assert_eq!(f.operation(), OPER_REPLY);
+            assert_eq!(f.sha(), sha);
+            assert_eq!(f.spa(), spa);
+        }
+        assert_eq!(
+            EthIPv4ArpFrame::from_bytes(a.as_ref()).unwrap_err(),
+            Error::SliceExactLen
+        );
+
         // TODO: The following test code is way more verbose than it should've been. Make it
         // prettier at some point.
 
@@ -0,0 +1,497 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing DHCPv4 messages exchanged over Ethernet.
+//!
+//! Details of the message format and of the options it carries can be found at [1] [2].
+//!
+//! [1]: https://tools.ietf.org/html/rfc2131
+//! [2]: https://tools.ietf.org/html/rfc2132
+use std::net::Ipv4Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// The UDP port DHCP servers listen on.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
+
+/// Operation of the messages sent by clients.
+pub const OP_BOOTREQUEST: u8 = 1;
+/// Operation of the messages sent by servers.
+pub const OP_BOOTREPLY: u8 = 2;
+
+/// DHCP is used for Ethernet hardware.
+pub const HTYPE_ETHERNET: u8 = 1;
+
+/// Subnet mask option.
+pub const OPTION_SUBNET_MASK: u8 = 1;
+/// Router option.
+pub const OPTION_ROUTER: u8 = 3;
+/// Domain name server option.
+pub const OPTION_DNS_SERVERS: u8 = 6;
+/// Requested IP address option.
+pub const OPTION_REQUESTED_IP: u8 = 50;
+/// IP address lease time option.
+pub const OPTION_LEASE_TIME: u8 = 51;
+/// DHCP message type option.
+pub const OPTION_MESSAGE_TYPE: u8 = 53;
+/// Server identifier option.
+pub const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PAD: u8 = 0;
+const OPTION_END: u8 = 255;
+
+/// Message type of the client broadcasts looking for servers.
+pub const DHCPDISCOVER: u8 = 1;
+/// Message type of the server offers of configuration parameters.
+pub const DHCPOFFER: u8 = 2;
+/// Message type of the client requests for the offered, or a previously allocated, address.
+pub const DHCPREQUEST: u8 = 3;
+/// Message type of the client notices that the offered address is already in use.
+pub const DHCPDECLINE: u8 = 4;
+/// Message type of the server acknowledgements of a request.
+pub const DHCPACK: u8 = 5;
+/// Message type of the server refusals of a request.
+pub const DHCPNAK: u8 = 6;
+/// Message type of the client notices giving up their address.
+pub const DHCPRELEASE: u8 = 7;
+/// Message type of the client requests for configuration parameters only.
+pub const DHCPINFORM: u8 = 8;
+
+/// The flag asking servers to broadcast their replies.
+pub const FLAG_BROADCAST: u16 = 0x8000;
+
+const OP_OFFSET: usize = 0;
+const HTYPE_OFFSET: usize = 1;
+const HLEN_OFFSET: usize = 2;
+const HOPS_OFFSET: usize = 3;
+const XID_OFFSET: usize = 4;
+const SECS_OFFSET: usize = 8;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const SIADDR_OFFSET: usize = 20;
+const GIADDR_OFFSET: usize = 24;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+/// The minimum length of the messages written by servers, which some BOOTP relays and
+/// clients expect.
+pub const MIN_MESSAGE_LEN: usize = 300;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Invalid hardware address length.
+    HLen,
+    /// Invalid hardware type.
+    HType,
+    /// The message doesn't start its options with the DHCP magic cookie.
+    MagicCookie,
+    /// The options to be written are longer than allowed.
+    OptionTooLong,
+    /// The provided slice is too short to hold the message.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as a DHCP message.
+///
+/// Only the messages related to Ethernet hardware addresses are supported.
+pub struct DhcpMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> DhcpMessage<'a, T> {
+    /// Interprets `bytes` as a DHCP message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        DhcpMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Interprets `bytes` as a DHCP message if possible or returns the reason for failing to
+    /// do so.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let message = DhcpMessage::from_bytes_unchecked(bytes);
+
+        if message.htype() != HTYPE_ETHERNET {
+            return Err(Error::HType);
+        }
+
+        if message.hlen() != MAC_ADDR_LEN as u8 {
+            return Err(Error::HLen);
+        }
+
+        if message.bytes.ntohl_unchecked(MAGIC_COOKIE_OFFSET) != MAGIC_COOKIE {
+            return Err(Error::MagicCookie);
+        }
+
+        Ok(message)
+    }
+
+    /// Returns the operation of the message.
+    #[inline]
+    pub fn op(&self) -> u8 {
+        self.bytes[OP_OFFSET]
+    }
+
+    /// Returns the hardware address type of the message.
+    #[inline]
+    pub fn htype(&self) -> u8 {
+        self.bytes[HTYPE_OFFSET]
+    }
+
+    /// Returns the hardware address length of the message.
+    #[inline]
+    pub fn hlen(&self) -> u8 {
+        self.bytes[HLEN_OFFSET]
+    }
+
+    /// Returns the transaction ID of the message.
+    #[inline]
+    pub fn xid(&self) -> u32 {
+        self.bytes.ntohl_unchecked(XID_OFFSET)
+    }
+
+    /// Returns the flags of the message.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.bytes.ntohs_unchecked(FLAGS_OFFSET)
+    }
+
+    /// Returns the client IP address of the message.
+    #[inline]
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(CIADDR_OFFSET))
+    }
+
+    /// Returns the address the server assigns to the client.
+    #[inline]
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(YIADDR_OFFSET))
+    }
+
+    /// Returns the address of the relay agent the message went through.
+    #[inline]
+    pub fn giaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(GIADDR_OFFSET))
+    }
+
+    /// Returns the hardware address of the client.
+    #[inline]
+    pub fn chaddr(&self) -> MacAddr {
+        MacAddr::from_bytes_unchecked(&self.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN])
+    }
+
+    /// Returns the value of the first option with the given `code`, if the message carries
+    /// it. The options following a truncated one are ignored.
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        let options = &self.bytes[OPTIONS_OFFSET..];
+        let mut offset = 0;
+        while offset < options.len() {
+            match options[offset] {
+                OPTION_END => break,
+                OPTION_PAD => offset += 1,
+                option_code => {
+                    let len = *options.get(offset + 1)? as usize;
+                    let value = options.get(offset + 2..offset + 2 + len)?;
+                    if option_code == code {
+                        return Some(value);
+                    }
+                    offset += 2 + len;
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the DHCP message type, if the message carries a valid one.
+    pub fn message_type(&self) -> Option<u8> {
+        match self.option(OPTION_MESSAGE_TYPE) {
+            Some(&[message_type]) => Some(message_type),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the first option with the given `code` as an IPv4 address, if the
+    /// message carries a valid one.
+    pub fn ipv4_option(&self, code: u8) -> Option<Ipv4Addr> {
+        match self.option(code) {
+            Some(&[a, b, c, d]) => Some(Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> DhcpMessage<'a, T> {
+    /// Writes a server reply to the `request` message in `buf`, which is shrunk to the length
+    /// of the reply.
+    ///
+    /// The transaction ID, flags, relay agent and client hardware addresses are copied from
+    /// the request. `options` are written in order, followed by the end option, and the
+    /// message is padded up to `MIN_MESSAGE_LEN`.
+    pub fn write_reply<R: NetworkBytes>(
+        buf: T,
+        request: &DhcpMessage<R>,
+        ciaddr: Ipv4Addr,
+        yiaddr: Ipv4Addr,
+        siaddr: Ipv4Addr,
+        options: &[(u8, &[u8])],
+    ) -> Result<Self, Error> {
+        let mut len = OPTIONS_OFFSET + 1;
+        for (_, value) in options {
+            if value.len() > u8::max_value() as usize {
+                return Err(Error::OptionTooLong);
+            }
+            len += 2 + value.len();
+        }
+        let len = len.max(MIN_MESSAGE_LEN);
+        if buf.len() < len {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut message = DhcpMessage::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(len);
+        for byte in message.bytes.iter_mut() {
+            *byte = 0;
+        }
+
+        message.bytes[OP_OFFSET] = OP_BOOTREPLY;
+        message.bytes[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        message.bytes[HLEN_OFFSET] = MAC_ADDR_LEN as u8;
+        message.bytes[HOPS_OFFSET] = 0;
+        message.bytes.htonl_unchecked(XID_OFFSET, request.xid());
+        message.bytes.htons_unchecked(SECS_OFFSET, 0);
+        message.bytes.htons_unchecked(FLAGS_OFFSET, request.flags());
+        message
+            .bytes
+            .htonl_unchecked(CIADDR_OFFSET, u32::from(ciaddr));
+        message
+            .bytes
+            .htonl_unchecked(YIADDR_OFFSET, u32::from(yiaddr));
+        message
+            .bytes
+            .htonl_unchecked(SIADDR_OFFSET, u32::from(siaddr));
+        message
+            .bytes
+            .htonl_unchecked(GIADDR_OFFSET, u32::from(request.giaddr()));
+        message.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN]
+            .copy_from_slice(request.chaddr().get_bytes());
+        message
+            .bytes
+            .htonl_unchecked(MAGIC_COOKIE_OFFSET, MAGIC_COOKIE);
+
+        let mut offset = OPTIONS_OFFSET;
+        for (code, value) in options {
+            message.bytes[offset] = *code;
+            message.bytes[offset + 1] = value.len() as u8;
+            message.bytes[offset + 2..offset + 2 + value.len()].copy_from_slice(value);
+            offset += 2 + value.len();
+        }
+        message.bytes[offset] = OPTION_END;
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for DhcpMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(DHCP message)")
+        }
+    }
+
+    // Writes a request from the client with `chaddr` MAC address, followed by the raw
+    // `options` bytes, to `buf`.
+    fn write_request(buf: &mut [u8], chaddr: MacAddr, options: &[u8]) -> usize {
+        let len = OPTIONS_OFFSET + options.len();
+        for byte in buf[..len].iter_mut() {
+            *byte = 0;
+        }
+        buf[OP_OFFSET] = OP_BOOTREQUEST;
+        buf[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        buf[HLEN_OFFSET] = MAC_ADDR_LEN as u8;
+        buf[XID_OFFSET] = 0x42;
+        buf[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN].copy_from_slice(chaddr.get_bytes());
+        buf[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf[OPTIONS_OFFSET..len].copy_from_slice(options);
+        len
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut buf = [0u8; 512];
+        let chaddr = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let requested_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let options = [
+            OPTION_PAD,
+            OPTION_MESSAGE_TYPE,
+            1,
+            DHCPREQUEST,
+            OPTION_REQUESTED_IP,
+            4,
+            10,
+            0,
+            0,
+            2,
+            OPTION_END,
+        ];
+        let len = write_request(&mut buf, chaddr, &options);
+
+        let message = DhcpMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(message.op(), OP_BOOTREQUEST);
+        assert_eq!(message.xid(), 0x4200_0000);
+        assert_eq!(message.flags(), 0);
+        assert_eq!(message.chaddr(), chaddr);
+        assert_eq!(message.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.len(), len);
+        assert_eq!(message.message_type(), Some(DHCPREQUEST));
+        assert_eq!(message.ipv4_option(OPTION_REQUESTED_IP), Some(requested_ip));
+        assert_eq!(message.option(OPTION_SERVER_ID), None);
+
+        // Truncated options are ignored.
+        let message = DhcpMessage::from_bytes(&buf[..len - 2]).unwrap();
+        assert_eq!(message.message_type(), Some(DHCPREQUEST));
+        assert_eq!(message.option(OPTION_REQUESTED_IP), None);
+
+        // Options with an unexpected length.
+        let len = write_request(&mut buf, chaddr, &[OPTION_MESSAGE_TYPE, 2, DHCPREQUEST, 0]);
+        let message = DhcpMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(message.message_type(), None);
+
+        // Invalid messages.
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..OPTIONS_OFFSET - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+        buf[MAGIC_COOKIE_OFFSET] = 0;
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..len]).unwrap_err(),
+            Error::MagicCookie
+        );
+        buf[HLEN_OFFSET] = 16;
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..len]).unwrap_err(),
+            Error::HLen
+        );
+        buf[HTYPE_OFFSET] = 6;
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..len]).unwrap_err(),
+            Error::HType
+        );
+    }
+
+    #[test]
+    fn test_write_reply() {
+        let mut request_buf = [0u8; 512];
+        let chaddr = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let len = write_request(
+            &mut request_buf,
+            chaddr,
+            &[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER, OPTION_END],
+        );
+        let request = DhcpMessage::from_bytes(&request_buf[..len]).unwrap();
+
+        let yiaddr = Ipv4Addr::new(10, 0, 0, 2);
+        let siaddr = Ipv4Addr::new(10, 0, 0, 1);
+        let dns_servers = [8u8, 8, 8, 8, 1, 1, 1, 1];
+        let mut buf = [0xffu8; 1024];
+        let reply = DhcpMessage::write_reply(
+            &mut buf[..],
+            &request,
+            Ipv4Addr::UNSPECIFIED,
+            yiaddr,
+            siaddr,
+            &[
+                (OPTION_MESSAGE_TYPE, &[DHCPOFFER]),
+                (OPTION_DNS_SERVERS, &dns_servers),
+            ],
+        )
+        .unwrap();
+        assert_eq!(reply.len(), MIN_MESSAGE_LEN);
+
+        let reply = DhcpMessage::from_bytes(&buf[..MIN_MESSAGE_LEN]).unwrap();
+        assert_eq!(reply.op(), OP_BOOTREPLY);
+        assert_eq!(reply.xid(), request.xid());
+        assert_eq!(reply.flags(), 0);
+        assert_eq!(reply.chaddr(), chaddr);
+        assert_eq!(reply.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(reply.yiaddr(), yiaddr);
+        assert_eq!(reply.giaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(reply.message_type(), Some(DHCPOFFER));
+        assert_eq!(reply.option(OPTION_DNS_SERVERS), Some(&dns_servers[..]));
+        // The options are followed by the end option and zeroed padding.
+        assert_eq!(buf[OPTIONS_OFFSET + 13], OPTION_END);
+        assert!(buf[OPTIONS_OFFSET + 14..MIN_MESSAGE_LEN]
+            .iter()
+            .all(|b| *b == 0));
+
+        // Replies longer than the minimum length.
+        let long_option = [1u8; 255];
+        let reply = DhcpMessage::write_reply(
+            &mut buf[..],
+            &request,
+            Ipv4Addr::UNSPECIFIED,
+            yiaddr,
+            siaddr,
+            &[(OPTION_DNS_SERVERS, &long_option)],
+        )
+        .unwrap();
+        assert_eq!(reply.len(), OPTIONS_OFFSET + 2 + 255 + 1);
+
+        // Invalid replies.
+        let too_long_option = [1u8; 256];
+        assert_eq!(
+            DhcpMessage::write_reply(
+                &mut buf[..],
+                &request,
+                Ipv4Addr::UNSPECIFIED,
+                yiaddr,
+                siaddr,
+                &[(OPTION_DNS_SERVERS, &too_long_option)],
+            )
+            .unwrap_err(),
+            Error::OptionTooLong
+        );
+        assert_eq!(
+            DhcpMessage::write_reply(
+                &mut buf[..MIN_MESSAGE_LEN - 1],
+                &request,
+                Ipv4Addr::UNSPECIFIED,
+                yiaddr,
+                siaddr,
+                &[],
+            )
+            .unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+}
@@ -25,7 +25,7 @@ const PROTOCOL_OFFSET: usize = 9;
 const HEADER_CHECKSUM_OFFSET: usize = 10;
 const SOURCE_ADDRESS_OFFSET: usize = 12;
 const DESTINATION_ADDRESS_OFFSET: usize = 16;
-const OPTIONS_OFFSET: usize = 20;
+pub(crate) const OPTIONS_OFFSET: usize = 20;
 
 /// Indicates version 4 of the IP protocol
 pub const IPV4_VERSION: u8 = 0x04;
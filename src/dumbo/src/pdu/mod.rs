@@ -7,15 +7,19 @@
 //! protocol. Ethernet frames, IP packets, and TCP segments are all examples of protocol data
 //! units.
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+use crate::pdu::ipv6::PROTOCOL_ICMPV6;
 
 pub mod arp;
 pub mod bytes;
+pub mod dhcp;
 pub mod ethernet;
 pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
 pub mod tcp;
 pub mod udp;
 
@@ -55,6 +59,7 @@ impl<T> Incomplete<T> {
 enum ChecksumProto {
     Tcp = PROTOCOL_TCP,
     Udp = PROTOCOL_UDP,
+    Icmpv6 = PROTOCOL_ICMPV6,
 }
 
 /// Computes the checksum of a TCP/UDP packet. Since both protocols use
@@ -77,8 +82,6 @@ fn compute_checksum<T: NetworkBytes>(
     dst_addr: Ipv4Addr,
     protocol: ChecksumProto,
 ) -> u16 {
-    // TODO: Is u32 enough to prevent overflow for the code in this function? I think so, but it
-    // would be nice to double-check.
     let mut sum = 0u32;
 
     let a = u32::from(src_addr);
@@ -89,6 +92,41 @@ fn compute_checksum<T: NetworkBytes>(
     sum += b & 0xffff;
     sum += b >> 16;
 
+    finish_checksum(bytes, sum, protocol)
+}
+
+/// Computes the checksum of a TCP segment, UDP datagram or ICMPv6 message carried by an IPv6
+/// packet, which only differs from IPv4 by the addresses included in the pseudo-header.
+///
+/// # Arguments
+/// * `bytes` - Raw bytes of the upper-layer packet
+/// * `src_addr` - IPv6 source address
+/// * `dst_addr` - IPv6 destination address
+/// * `protocol` - the upper-layer protocol
+#[inline]
+fn compute_checksum_ipv6<T: NetworkBytes>(
+    bytes: &T,
+    src_addr: Ipv6Addr,
+    dst_addr: Ipv6Addr,
+    protocol: ChecksumProto,
+) -> u16 {
+    let mut sum = 0u32;
+
+    for addr in [src_addr, dst_addr].iter() {
+        for segment in addr.segments().iter() {
+            sum += u32::from(*segment);
+        }
+    }
+
+    finish_checksum(bytes, sum, protocol)
+}
+
+// Adds the rest of the pseudo-header and the contents of `bytes` to the sum of the addresses,
+// and returns the resulting checksum.
+#[inline]
+fn finish_checksum<T: NetworkBytes>(bytes: &T, mut sum: u32, protocol: ChecksumProto) -> u16 {
+    // TODO: Is u32 enough to prevent overflow for the code in this function? I think so, but it
+    // would be nice to double-check.
     let len = bytes.len();
     sum += protocol as u32;
     sum += len as u32;
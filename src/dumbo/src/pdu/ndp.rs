@@ -0,0 +1,471 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains logic that helps with handling the Neighbor Solicitation and Neighbor Advertisement
+//! messages of the Neighbor Discovery Protocol (NDP), which resolves IPv6 addresses to link-layer
+//! addresses the way ARP does for IPv4. These are ICMPv6 messages, carried by IPv6 packets.
+//!
+//! A more detailed view of these messages can be found [here].
+//!
+//! [here]: https://tools.ietf.org/html/rfc4861#section-4.3
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ChecksumProto;
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// The ICMPv6 type of Neighbor Solicitation messages.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+/// The ICMPv6 type of Neighbor Advertisement messages.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// Neighbor Advertisement flag telling the sender is a router.
+pub const FLAG_ROUTER: u8 = 0x80;
+/// Neighbor Advertisement flag telling the advertisement answers a solicitation.
+pub const FLAG_SOLICITED: u8 = 0x40;
+/// Neighbor Advertisement flag telling the advertisement should override cached entries.
+pub const FLAG_OVERRIDE: u8 = 0x20;
+
+/// The option carrying the link-layer address of the sender of a solicitation.
+pub const OPTION_SOURCE_LL_ADDR: u8 = 1;
+/// The option carrying the link-layer address of the target of an advertisement.
+pub const OPTION_TARGET_LL_ADDR: u8 = 2;
+
+/// The hop limit of every NDP packet, which tells receivers they were not forwarded.
+pub const HOP_LIMIT: u8 = 255;
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const FLAGS_OFFSET: usize = 4;
+const TARGET_ADDRESS_OFFSET: usize = 8;
+const OPTIONS_OFFSET: usize = 24;
+
+const IPV6_ADDR_LEN: usize = 16;
+// Options lengths are expressed in units of 8 bytes.
+const OPTION_LEN_UNIT: usize = 8;
+// The length of a link-layer address option for Ethernet.
+const LL_ADDR_OPTION_LEN: usize = 8;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The checksum is invalid.
+    Checksum,
+    /// The code is invalid.
+    Code,
+    /// The message is neither a Neighbor Solicitation nor a Neighbor Advertisement.
+    MessageType,
+    /// An option is malformed.
+    Option,
+    /// The provided slice is shorter than the message.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as a Neighbor Solicitation or Neighbor Advertisement message.
+pub struct NeighborMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> NeighborMessage<'a, T> {
+    /// Interprets the given bytes as a neighbor message, without doing any validity checks
+    /// beforehand.
+    ///
+    ///  # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        NeighborMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor message.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the ICMPv6 checksum must be validated.
+    pub fn from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let message = Self::from_bytes_unchecked(bytes);
+
+        match message.message_type() {
+            TYPE_NEIGHBOR_SOLICITATION | TYPE_NEIGHBOR_ADVERTISEMENT => (),
+            _ => return Err(Error::MessageType),
+        };
+
+        if message.code() != 0 {
+            return Err(Error::Code);
+        }
+
+        // Every option has a non-zero length, and is contained by the message.
+        let mut offset = OPTIONS_OFFSET;
+        while offset < message.len() {
+            let option_len = message.option_len_at(offset)?;
+            if option_len == 0 || offset + option_len > message.len() {
+                return Err(Error::Option);
+            }
+            offset += option_len;
+        }
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if message.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(message)
+    }
+
+    /// Tries to interpret a byte slice as a valid Neighbor Solicitation message.
+    #[inline]
+    pub fn solicitation_from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        let message = Self::from_bytes(bytes, verify_checksum)?;
+        if message.message_type() != TYPE_NEIGHBOR_SOLICITATION {
+            return Err(Error::MessageType);
+        }
+        Ok(message)
+    }
+
+    /// Returns the ICMPv6 type of the message.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the ICMPv6 code of the message.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the value of the checksum field.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the flags of an advertisement. Always 0 for solicitations.
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.bytes[FLAGS_OFFSET]
+    }
+
+    /// Returns the address the message is about.
+    #[inline]
+    pub fn target_address(&self) -> Ipv6Addr {
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(
+            &self.bytes[TARGET_ADDRESS_OFFSET..TARGET_ADDRESS_OFFSET + IPV6_ADDR_LEN],
+        );
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the link-layer address carried by the option of the given type, if present.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the options are malformed, which `from_bytes` checks.
+    pub fn ll_addr_option(&self, option_type: u8) -> Option<MacAddr> {
+        let mut offset = OPTIONS_OFFSET;
+        while offset < self.len() {
+            let option_len = self.bytes[offset + 1] as usize * OPTION_LEN_UNIT;
+            if self.bytes[offset] == option_type && option_len == LL_ADDR_OPTION_LEN {
+                return Some(MacAddr::from_bytes_unchecked(
+                    &self.bytes[offset + 2..offset + 2 + MAC_ADDR_LEN],
+                ));
+            }
+            offset += option_len;
+        }
+        None
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the ICMPv6 checksum of the message.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum_ipv6(&self.bytes, src_addr, dst_addr, ChecksumProto::Icmpv6)
+    }
+
+    fn option_len_at(&self, offset: usize) -> Result<usize, Error> {
+        if offset + 2 > self.len() {
+            return Err(Error::Option);
+        }
+        Ok(self.bytes[offset + 1] as usize * OPTION_LEN_UNIT)
+    }
+}
+
+impl<'a, T: NetworkBytesMut> NeighborMessage<'a, T> {
+    #[allow(clippy::too_many_arguments)]
+    fn write_raw(
+        buf: T,
+        message_type: u8,
+        flags: u8,
+        target: Ipv6Addr,
+        ll_addr_option: Option<(u8, MacAddr)>,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        let len = OPTIONS_OFFSET + ll_addr_option.map_or(0, |_| LL_ADDR_OPTION_LEN);
+        if buf.len() < len {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut message = Self::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(len);
+        // Zeroes the reserved bytes.
+        for byte in message.bytes[FLAGS_OFFSET..TARGET_ADDRESS_OFFSET].iter_mut() {
+            *byte = 0;
+        }
+        message.bytes[TYPE_OFFSET] = message_type;
+        message.bytes[CODE_OFFSET] = 0;
+        message.set_flags(flags);
+        message.set_target_address(target);
+        if let Some((option_type, ll_addr)) = ll_addr_option {
+            message.bytes[OPTIONS_OFFSET] = option_type;
+            message.bytes[OPTIONS_OFFSET + 1] = (LL_ADDR_OPTION_LEN / OPTION_LEN_UNIT) as u8;
+            message.bytes[OPTIONS_OFFSET + 2..OPTIONS_OFFSET + 2 + MAC_ADDR_LEN]
+                .copy_from_slice(ll_addr.get_bytes());
+        }
+
+        message.set_checksum(0);
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Attempts to write a Neighbor Solicitation for `target` to `buf`, shrinking it to the
+    /// length of the message. The source link-layer address option is included when `sha` is
+    /// present, and the checksum is computed from the addresses of the enclosing IPv6 packet.
+    #[inline]
+    pub fn write_solicitation(
+        buf: T,
+        target: Ipv6Addr,
+        sha: Option<MacAddr>,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_SOLICITATION,
+            0,
+            target,
+            sha.map(|addr| (OPTION_SOURCE_LL_ADDR, addr)),
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Attempts to write a Neighbor Advertisement telling `target` is reachable at `tha` to
+    /// `buf`, shrinking it to the length of the message. The checksum is computed from the
+    /// addresses of the enclosing IPv6 packet.
+    #[inline]
+    pub fn write_advertisement(
+        buf: T,
+        flags: u8,
+        target: Ipv6Addr,
+        tha: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_ADVERTISEMENT,
+            flags,
+            target,
+            Some((OPTION_TARGET_LL_ADDR, tha)),
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Sets the value of the checksum field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+    }
+
+    /// Sets the flags of the message.
+    #[inline]
+    pub fn set_flags(&mut self, value: u8) {
+        self.bytes[FLAGS_OFFSET] = value;
+    }
+
+    /// Sets the address the message is about.
+    #[inline]
+    pub fn set_target_address(&mut self, addr: Ipv6Addr) {
+        self.bytes[TARGET_ADDRESS_OFFSET..TARGET_ADDRESS_OFFSET + IPV6_ADDR_LEN]
+            .copy_from_slice(&addr.octets());
+    }
+}
+
+/// Returns the solicited-node multicast address of `addr`, which the solicitations for `addr`
+/// are sent to.
+pub fn solicited_node_addr(addr: Ipv6Addr) -> Ipv6Addr {
+    let octets = addr.octets();
+    Ipv6Addr::from([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, octets[13], octets[14], octets[15],
+    ])
+}
+
+/// Returns the Ethernet MAC address the IPv6 multicast address `addr` maps to.
+pub fn multicast_mac_addr(addr: Ipv6Addr) -> MacAddr {
+    let octets = addr.octets();
+    MacAddr::from_bytes_unchecked(&[0x33, 0x33, octets[12], octets[13], octets[14], octets[15]])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for NeighborMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Neighbor message)")
+        }
+    }
+
+    #[test]
+    fn test_neighbor_messages() {
+        let mut buf = [1u8; 100];
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+        let guest_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1, 0x2, 0x3, 0x4);
+        let target = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let solicited_node = solicited_node_addr(target);
+        assert_eq!(
+            solicited_node,
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0x1, 0xff00, 0x254)
+        );
+        assert_eq!(
+            multicast_mac_addr(solicited_node),
+            MacAddr::parse_str("33:33:ff:00:02:54").unwrap()
+        );
+
+        // A solicitation, with and without the source link-layer address option.
+        assert_eq!(
+            NeighborMessage::write_solicitation(
+                &mut buf[..OPTIONS_OFFSET - 1],
+                target,
+                None,
+                guest_addr,
+                solicited_node
+            )
+            .unwrap_err(),
+            Error::SliceTooShort
+        );
+        let len = NeighborMessage::write_solicitation(
+            buf.as_mut(),
+            target,
+            Some(mac),
+            guest_addr,
+            solicited_node,
+        )
+        .unwrap()
+        .len();
+        assert_eq!(len, OPTIONS_OFFSET + LL_ADDR_OPTION_LEN);
+        let ns = NeighborMessage::solicitation_from_bytes(
+            &buf[..len],
+            Some((guest_addr, solicited_node)),
+        )
+        .unwrap();
+        assert_eq!(ns.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+        assert_eq!(ns.code(), 0);
+        assert_eq!(ns.flags(), 0);
+        assert_eq!(ns.target_address(), target);
+        assert_eq!(ns.ll_addr_option(OPTION_SOURCE_LL_ADDR), Some(mac));
+        assert_eq!(ns.ll_addr_option(OPTION_TARGET_LL_ADDR), None);
+
+        // The checksum covers the addresses of the IPv6 packet.
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..len], Some((guest_addr, target))).unwrap_err(),
+            Error::Checksum
+        );
+
+        let len = NeighborMessage::write_solicitation(
+            buf.as_mut(),
+            target,
+            None,
+            guest_addr,
+            solicited_node,
+        )
+        .unwrap()
+        .len();
+        assert_eq!(len, OPTIONS_OFFSET);
+        let ns = NeighborMessage::from_bytes(&buf[..len], None).unwrap();
+        assert_eq!(ns.ll_addr_option(OPTION_SOURCE_LL_ADDR), None);
+
+        // An advertisement.
+        let flags = FLAG_SOLICITED | FLAG_OVERRIDE;
+        let len = NeighborMessage::write_advertisement(
+            buf.as_mut(),
+            flags,
+            target,
+            mac,
+            target,
+            guest_addr,
+        )
+        .unwrap()
+        .len();
+        let na = NeighborMessage::from_bytes(&buf[..len], Some((target, guest_addr))).unwrap();
+        assert_eq!(na.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+        assert_eq!(na.flags(), flags);
+        assert_eq!(na.target_address(), target);
+        assert_eq!(na.ll_addr_option(OPTION_TARGET_LL_ADDR), Some(mac));
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::MessageType
+        );
+
+        // Invalid messages.
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..OPTIONS_OFFSET - 1], None).unwrap_err(),
+            Error::SliceTooShort
+        );
+        // The option is truncated.
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..len - 1], None).unwrap_err(),
+            Error::Option
+        );
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..OPTIONS_OFFSET + 1], None).unwrap_err(),
+            Error::Option
+        );
+        {
+            let mut na = NeighborMessage::from_bytes_unchecked(&mut buf[..len]);
+            na.bytes[OPTIONS_OFFSET + 1] = 0;
+        }
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..len], None).unwrap_err(),
+            Error::Option
+        );
+        buf[CODE_OFFSET] = 1;
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..len], None).unwrap_err(),
+            Error::Code
+        );
+        buf[TYPE_OFFSET] = 128;
+        assert_eq!(
+            NeighborMessage::from_bytes(&buf[..len], None).unwrap_err(),
+            Error::MessageType
+        );
+    }
+}
@@ -8,7 +8,7 @@
 //! [Here]: https://en.wikipedia.org/wiki/Transmission_Control_Protocol#TCP_segment_structure
 
 use std::cmp::min;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroU16;
 use std::result::Result;
 
@@ -211,6 +211,11 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
         crate::pdu::compute_checksum(&self.bytes, src_addr, dst_addr, ChecksumProto::Tcp)
     }
 
+    /// Computes the TCP checksum of a segment carried by an IPv6 packet.
+    pub fn compute_checksum_ipv6(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum_ipv6(&self.bytes, src_addr, dst_addr, ChecksumProto::Tcp)
+    }
+
     /// Parses TCP header options (only `MSS` is supported for now).
     ///
     /// If no error is encountered, returns the `MSS` value, or `None` if the option is not
@@ -807,4 +812,32 @@ mod tests {
             Error::MssRemaining
         );
     }
+
+    #[test]
+    fn test_ipv6_checksum() {
+        let mut a = [1u8; 100];
+        let src_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1, 0x2, 0x3, 0x4);
+        let dst_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+
+        let mut segment = TcpSegment::write_segment::<[u8]>(
+            a.as_mut(),
+            1234,
+            80,
+            11_111_222,
+            34_566_543,
+            Flags::ACK,
+            10000,
+            None,
+            0,
+            None,
+            None,
+        )
+        .unwrap();
+
+        segment.set_checksum(0);
+        let checksum = segment.compute_checksum_ipv6(src_addr, dst_addr);
+        segment.set_checksum(checksum);
+        assert_eq!(segment.compute_checksum_ipv6(src_addr, dst_addr), 0);
+        assert_ne!(segment.compute_checksum_ipv6(dst_addr, dst_addr), 0);
+    }
 }
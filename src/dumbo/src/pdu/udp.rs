@@ -12,7 +12,8 @@
 use std::net::Ipv4Addr;
 
 use crate::pdu::bytes::NetworkBytesMut;
-use crate::pdu::{ChecksumProto, Incomplete};
+use crate::pdu::ipv4::{self, IPv4Packet, PROTOCOL_UDP};
+use crate::pdu::{ethernet, ChecksumProto, Incomplete};
 
 use super::bytes::{InnerBytes, NetworkBytes};
 
@@ -205,6 +206,23 @@ impl<'a, T: NetworkBytesMut> Incomplete<UdpDatagram<'a, T>> {
     }
 }
 
+/// This function checks if `buf` may hold an Ethernet frame which encapsulates a UDP datagram
+/// over IPv4 heading towards the given port. Cannot produce false negatives.
+#[inline]
+pub fn test_speculative_dst_port(buf: &[u8], port: u16) -> bool {
+    // The unchecked methods are safe because we actually check the buffer length beforehand.
+    if buf.len() >= ethernet::PAYLOAD_OFFSET + ipv4::OPTIONS_OFFSET {
+        let bytes = &buf[ethernet::PAYLOAD_OFFSET..];
+        let packet = IPv4Packet::from_bytes_unchecked(bytes);
+        let header_len = packet.header_len();
+        if packet.protocol() == PROTOCOL_UDP && bytes.len() >= header_len + UDP_HEADER_SIZE {
+            return UdpDatagram::from_bytes_unchecked(&bytes[header_len..]).destination_port()
+                == port;
+        }
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt;
@@ -317,4 +335,39 @@ mod tests {
         let p = p.finalize(41103, 9876, Some((src_ip, dst_ip)));
         assert_eq!(p.checksum(), correct_checksum);
     }
+
+    #[test]
+    fn test_speculative_port() {
+        let mut buf = [0u8; 100];
+        let src_addr = Ipv4Addr::new(10, 0, 0, 2);
+        let dst_addr = Ipv4Addr::new(10, 0, 0, 1);
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                &mut buf[ethernet::PAYLOAD_OFFSET..],
+                PROTOCOL_UDP,
+                src_addr,
+                dst_addr,
+            )
+            .unwrap();
+            let datagram_len = UdpDatagram::write_incomplete_datagram(
+                packet.inner_mut().payload_mut(),
+                b"payload",
+            )
+            .unwrap()
+            .finalize(68, 67, Some((src_addr, dst_addr)))
+            .len() as usize;
+            packet.with_payload_len_unchecked(datagram_len, true).len()
+        };
+        let frame_len = ethernet::PAYLOAD_OFFSET + packet_len;
+
+        assert!(test_speculative_dst_port(&buf[..frame_len], 67));
+        assert!(!test_speculative_dst_port(&buf[..frame_len], 68));
+        // The frame is too short to hold the UDP header.
+        assert!(!test_speculative_dst_port(&buf[..frame_len - 8], 67));
+
+        // Other protocols are not UDP datagrams.
+        IPv4Packet::from_bytes_unchecked(&mut buf[ethernet::PAYLOAD_OFFSET..])
+            .set_protocol(crate::pdu::ipv4::PROTOCOL_TCP);
+        assert!(!test_speculative_dst_port(&buf[..frame_len], 67));
+    }
 }
@@ -1,23 +1,23 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure.
+//! Exposes simple TCP over IPv4 and IPv6 listener functionality via the [`TcpIPHandler`]
+//! structure.
 //!
-//! [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+//! [`TcpIPHandler`]: struct.TcpIPHandler.html
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use crate::pdu::ipv6::{Error as IPv6PacketError, IPv6Packet};
 use crate::pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
 use crate::tcp::endpoint::Endpoint;
 use crate::tcp::{NextSegmentStatus, RstConfig};
 use micro_http::{Request, Response};
 
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
-
 /// Describes events which may occur when the handler receives packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvEvent {
@@ -51,12 +51,14 @@ pub enum WriteEvent {
 }
 
 /// Describes errors which may be encountered by the [`receive_packet`] method from
-/// [`TcpIPv4Handler`].
+/// [`TcpIPHandler`].
 ///
-/// [`receive_packet`]: struct.TcpIPv4Handler.html#method.receive_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`receive_packet`]: struct.TcpIPHandler.html#method.receive_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvError {
+    /// The handler has no address of the IP version of the packet.
+    InvalidAddress,
     /// The inner segment has an invalid destination port.
     InvalidPort,
     /// The handler encountered an error while parsing the inner TCP segment.
@@ -64,51 +66,54 @@ pub enum RecvError {
 }
 
 /// Describes errors which may be encountered by the [`write_next_packet`] method from
-/// [`TcpIPv4Handler`].
+/// [`TcpIPHandler`].
 ///
-/// [`write_next_packet`]: struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`write_next_packet`]: struct.TcpIPHandler.html#method.write_next_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum WriteNextError {
     /// There was an error while writing the contents of the IPv4 packet.
     IPv4Packet(IPv4PacketError),
+    /// There was an error while writing the contents of the IPv6 packet.
+    IPv6Packet(IPv6PacketError),
     /// There was an error while writing the contents of the inner TCP segment.
     TcpSegment(TcpSegmentError),
 }
 
-// Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
+// Generally speaking, a TCP/IP connection is identified using the four-tuple (src_addr, src_port,
+// dst_addr, dst_port). However, the IP addresses and TCP port of the MMDS endpoint are fixed, so
 // we can get away with uniquely identifying connections using just the remote address and port.
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new<A: Into<IpAddr>>(remote_addr: A, remote_port: u16) -> Self {
         ConnectionTuple {
-            remote_addr,
+            remote_addr: remote_addr.into(),
             remote_port,
         }
     }
 }
 
-/// Implements a minimalist TCP over IPv4 listener.
+/// Implements a minimalist TCP over IPv4 and IPv6 listener.
 ///
 /// Forwards incoming TCP segments to the appropriate connection object, based on the associated
 /// tuple, or attempts to establish new connections (when receiving `SYN` segments). Aside from
 /// constructors, the handler operation is based on three methods:
 ///
-/// * [`receive_packet`] examines an incoming IPv4 packet. It checks whether the destination
+/// * [`receive_packet`] examines an incoming IPv4 packet ([`receive_ipv6_packet`] does the same
+///   for IPv6 packets). It checks whether the destination
 ///   address is correct, the attempts examine the inner TCP segment, making sure the destination
 ///   port number is also correct. Then, it steers valid segments towards exiting connections,
 ///   creates new connections for incoming `SYN` segments, and enqueues `RST` replies in response
 ///   to any segments which cannot be associated with a connection (except other `RST` segments).
 ///   On success, also describes any internal status changes triggered by the reception of the
 ///   packet.
-/// * [`write_next_packet`] writes the next IPv4 packet (if available) that would be sent by the
+/// * [`write_next_packet`] writes the next IPv4 or IPv6 packet (if available) that would be sent by the
 ///   handler itself (right now it can only mean an enqueued `RST`), or one of the existing
 ///   connections. On success, also describes any internal status changes triggered as the packet
 ///   gets transmitted.
@@ -117,12 +122,15 @@ impl ConnectionTuple {
 ///   to send for the moment. This is used to determine whether it's appropriate to call
 ///   [`write_next_packet`].
 ///
-/// [`receive_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_packet
-/// [`write_next_packet`]: ../handler/struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`next_segment_status`]: ../handler/struct.TcpIPv4Handler.html#method.next_segment_status
-pub struct TcpIPv4Handler {
-    // Handler IPv4 address used for every connection.
+/// [`receive_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_packet
+/// [`receive_ipv6_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_ipv6_packet
+/// [`write_next_packet`]: ../handler/struct.TcpIPHandler.html#method.write_next_packet
+/// [`next_segment_status`]: ../handler/struct.TcpIPHandler.html#method.next_segment_status
+pub struct TcpIPHandler {
+    // Handler IPv4 address used for every connection over IPv4.
     local_ipv4_addr: Ipv4Addr,
+    // Handler IPv6 address used for every connection over IPv6, if any.
+    local_ipv6_addr: Option<Ipv6Addr>,
     // Handler TCP port used for every connection.
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
@@ -149,8 +157,8 @@ enum RecvSegmentOutcome {
     UnexpectedSegment(bool),
 }
 
-impl TcpIPv4Handler {
-    /// Creates a new `TcpIPv4Handler`.
+impl TcpIPHandler {
+    /// Creates a new `TcpIPHandler`.
     ///
     /// The handler acts as if bound to `local_addr`:`local_port`, and will accept at most
     /// `max_connections` concurrent connections. `RST` segments generated by unexpected incoming
@@ -164,8 +172,9 @@ impl TcpIPv4Handler {
     ) -> Self {
         let max_connections = max_connections.get();
         let max_pending_resets = max_pending_resets.get();
-        TcpIPv4Handler {
+        TcpIPHandler {
             local_ipv4_addr,
+            local_ipv6_addr: None,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
@@ -186,6 +195,17 @@ impl TcpIPv4Handler {
         self.local_ipv4_addr
     }
 
+    /// Setter for the local IPv6 address of this TCP handler. IPv6 packets are only accepted
+    /// when there is one.
+    pub fn set_local_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        self.local_ipv6_addr = ipv6_addr;
+    }
+
+    /// Returns the local IPv6 address of this TCP handler, if any.
+    pub fn local_ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.local_ipv6_addr
+    }
+
     /// Returns the local port of this TCP handler.
     pub fn local_port(&self) -> u16 {
         self.local_port
@@ -208,18 +228,41 @@ impl TcpIPv4Handler {
         &mut self,
         packet: &IPv4Packet<T>,
         callback: fn(Request) -> Response,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(packet.source_address(), packet.payload(), callback)
+    }
+
+    /// Contains logic for handling incoming segments carried by IPv6 packets, which are only
+    /// accepted when the handler has an IPv6 address.
+    ///
+    /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
+    pub fn receive_ipv6_packet<T: NetworkBytes>(
+        &mut self,
+        packet: &IPv6Packet<T>,
+        callback: fn(Request) -> Response,
+    ) -> Result<RecvEvent, RecvError> {
+        if self.local_ipv6_addr.is_none() {
+            return Err(RecvError::InvalidAddress);
+        }
+        self.receive_segment(packet.source_address(), packet.payload(), callback)
+    }
+
+    fn receive_segment<A: Into<IpAddr>>(
+        &mut self,
+        remote_addr: A,
+        bytes: &[u8],
+        callback: fn(Request) -> Response,
     ) -> Result<RecvEvent, RecvError> {
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
-        let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+        let segment = TcpSegment::from_bytes(bytes, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment, callback);
@@ -386,10 +429,10 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // Write an incomplete Ipv4 packet and complete it afterwards with missing information.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)
-                .map_err(WriteNextError::IPv4Packet)?;
+        let local_ipv4_addr = self.local_ipv4_addr;
+        // Connections over IPv6 only exist when the handler has an IPv6 address.
+        let local_ipv6_addr = self.local_ipv6_addr.unwrap_or(Ipv6Addr::UNSPECIFIED);
+        let local_port = self.local_port;
 
         // We set mss_used to 0, because we don't add any IP options.
         // TODO: Maybe get this nicely from packet at some point.
@@ -400,35 +443,29 @@ impl TcpIPv4Handler {
         // any TCP options, or a payload.
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
-            let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
-                seq,
-                ack,
-                flags_after_ns,
-                10000,
-                None,
-                0,
-                None,
-            )
-            .map_err(WriteNextError::TcpSegment)?
-            .finalize(
-                self.local_port,
-                tuple.remote_port,
-                Some((self.local_ipv4_addr, tuple.remote_addr)),
-            )
-            .len();
-
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
-            // The unwrap() is safe because packet_len > 0.
-            return Ok((
-                Some(NonZeroUsize::new(packet_len).unwrap()),
-                WriteEvent::Nothing,
-            ));
+            let packet_len = write_tcp_packet(
+                buf,
+                local_ipv4_addr,
+                local_ipv6_addr,
+                tuple.remote_addr,
+                |payload| {
+                    TcpSegment::write_incomplete_segment::<[u8]>(
+                        payload,
+                        seq,
+                        ack,
+                        flags_after_ns,
+                        10000,
+                        None,
+                        0,
+                        None,
+                    )
+                    .map(|segment| {
+                        Some(segment.finalize(local_port, tuple.remote_port, None).len())
+                    })
+                },
+            )?;
+
+            return Ok((packet_len, WriteEvent::Nothing));
         }
 
         for tuple in self
@@ -439,33 +476,23 @@ impl TcpIPv4Handler {
             // Tuples in self.active_connection or self.next_timeout should also appear as keys
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
-            // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
-            let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
-
-                match maybe_segment {
-                    Some(segment) => segment
-                        .finalize(
-                            self.local_port,
-                            tuple.remote_port,
-                            Some((self.local_ipv4_addr, tuple.remote_addr)),
-                        )
-                        .len(),
-                    None => continue,
-                }
-            };
-
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let packet_len = write_tcp_packet(
+                buf,
+                local_ipv4_addr,
+                local_ipv6_addr,
+                tuple.remote_addr,
+                |payload| {
+                    Ok(endpoint
+                        .write_next_segment(payload, mss_reserved)
+                        .map(|segment| segment.finalize(local_port, tuple.remote_port, None).len()))
+                },
+            )?;
+
+            if packet_len.is_none() {
+                continue;
+            }
 
-            // The unwrap is safe because ip_len > 0.
-            len = Some(NonZeroUsize::new(ip_len).unwrap());
+            len = packet_len;
             writer_status = Some((*tuple, endpoint.is_done()));
 
             break;
@@ -503,6 +530,57 @@ impl TcpIPv4Handler {
     }
 }
 
+// Writes to `buf` an IP packet heading from the local address of the IP version of `remote_addr`
+// to `remote_addr`, which carries the TCP segment written by `write_segment`. The latter returns
+// the length of the segment, with its ports set, or None when there is no segment to send. The
+// TCP checksum is computed here, since it depends on the IP version.
+fn write_tcp_packet<F>(
+    buf: &mut [u8],
+    local_ipv4_addr: Ipv4Addr,
+    local_ipv6_addr: Ipv6Addr,
+    remote_addr: IpAddr,
+    write_segment: F,
+) -> Result<Option<NonZeroUsize>, WriteNextError>
+where
+    F: FnOnce(&mut [u8]) -> Result<Option<usize>, TcpSegmentError>,
+{
+    let packet_len = match remote_addr {
+        IpAddr::V4(remote_addr) => {
+            let mut packet =
+                IPv4Packet::write_header(buf, PROTOCOL_TCP, local_ipv4_addr, remote_addr)
+                    .map_err(WriteNextError::IPv4Packet)?;
+            let payload = packet.inner_mut().payload_mut();
+            let segment_len = match write_segment(payload).map_err(WriteNextError::TcpSegment)? {
+                Some(segment_len) => segment_len,
+                None => return Ok(None),
+            };
+            let mut segment = TcpSegment::from_bytes_unchecked(&mut payload[..segment_len]);
+            segment.set_checksum(0);
+            let checksum = segment.compute_checksum(local_ipv4_addr, remote_addr);
+            segment.set_checksum(checksum);
+            packet.with_payload_len_unchecked(segment_len, true).len()
+        }
+        IpAddr::V6(remote_addr) => {
+            let mut packet =
+                IPv6Packet::write_header(buf, PROTOCOL_TCP, local_ipv6_addr, remote_addr)
+                    .map_err(WriteNextError::IPv6Packet)?;
+            let payload = packet.inner_mut().payload_mut();
+            let segment_len = match write_segment(payload).map_err(WriteNextError::TcpSegment)? {
+                Some(segment_len) => segment_len,
+                None => return Ok(None),
+            };
+            let mut segment = TcpSegment::from_bytes_unchecked(&mut payload[..segment_len]);
+            segment.set_checksum(0);
+            let checksum = segment.compute_checksum_ipv6(local_ipv6_addr, remote_addr);
+            segment.set_checksum(checksum);
+            packet.with_payload_len_unchecked(segment_len).len()
+        }
+    };
+
+    // The unwrap() is safe because packet_len > 0.
+    Ok(Some(NonZeroUsize::new(packet_len).unwrap()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -517,7 +595,7 @@ mod tests {
 
     #[allow(clippy::type_complexity)]
     fn write_next<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
     ) -> Result<(Option<IPv4Packet<'a, &'a mut [u8]>>, WriteEvent), WriteNextError> {
         h.write_next_packet(buf).map(|(o, e)| {
@@ -532,7 +610,7 @@ mod tests {
     }
 
     fn next_written_segment<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
         expected_event: WriteEvent,
     ) -> TcpSegment<'a, &'a mut [u8]> {
@@ -550,7 +628,7 @@ mod tests {
     // When successful, returns how many packets were written. The remote_addr argument is used
     // to check the packets are sent to the appropriate destination.
     fn drain_packets(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         src_addr: Ipv4Addr,
         remote_addr: Ipv4Addr,
     ) -> Result<usize, WriteNextError> {
@@ -583,7 +661,7 @@ mod tests {
         let max_connections = 2;
         let max_pending_resets = 2;
 
-        let mut h = TcpIPv4Handler::new(
+        let mut h = TcpIPHandler::new(
             local_addr,
             local_port,
             NonZeroUsize::new(max_connections).unwrap(),
@@ -823,4 +901,72 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_handler_ipv6() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_ipv4_addr = Ipv4Addr::new(169, 254, 169, 254);
+        let local_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let local_port = 80;
+        let remote_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let remote_port = 1012;
+
+        let mut h = TcpIPHandler::new(
+            local_ipv4_addr,
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+
+        let mut p =
+            IPv6Packet::write_header(buf.as_mut(), PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            0,
+            TcpFlags::SYN,
+            10000,
+            None,
+            100,
+            None,
+            None,
+        )
+        .unwrap()
+        .len();
+        let p = p.with_payload_len_unchecked(s_len);
+
+        // IPv6 packets are rejected until the handler has an IPv6 address.
+        assert_eq!(h.local_ipv6_addr(), None);
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Err(RecvError::InvalidAddress)
+        );
+
+        h.set_local_ipv6_addr(Some(local_addr));
+        assert_eq!(h.local_ipv6_addr(), Some(local_addr));
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Ok(RecvEvent::NewConnectionSuccessful)
+        );
+        assert!(h
+            .connections
+            .contains_key(&ConnectionTuple::new(remote_addr, remote_port)));
+
+        // The SYNACK is sent over IPv6, with a valid checksum.
+        let len = h.write_next_packet(buf2.as_mut()).unwrap().0.unwrap().get();
+        let packet = IPv6Packet::from_bytes(&buf2[..len]).unwrap();
+        assert_eq!(packet.next_header(), PROTOCOL_TCP);
+        assert_eq!(packet.source_address(), local_addr);
+        assert_eq!(packet.destination_address(), remote_addr);
+
+        let s = TcpSegment::from_bytes(packet.payload(), None).unwrap();
+        assert_eq!(s.compute_checksum_ipv6(local_addr, remote_addr), 0);
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.source_port(), local_port);
+        assert_eq!(s.destination_port(), remote_port);
+    }
 }
@@ -60,11 +60,13 @@
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
 
+use std::cmp;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io::Write;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use lazy_static::lazy_static;
 use serde::{Serialize, Serializer};
@@ -269,6 +271,44 @@ impl Serialize for SharedStoreMetric {
     }
 }
 
+/// Number of buckets of a `LatencyHistogram`.
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// Histogram of durations measured in microseconds.
+///
+/// Bucket `i` counts the samples in `[2^(i-1), 2^i)` us (bucket 0 counts the samples below 1us),
+/// while the last bucket also counts all the samples above its lower bound. Like any other
+/// `SharedIncMetric`, the buckets are reset when the histogram is flushed.
+#[derive(Default, Serialize)]
+pub struct LatencyHistogram {
+    /// Number of samples.
+    pub count: SharedIncMetric,
+    /// Sum of all the samples, in microseconds.
+    pub sum_us: SharedIncMetric,
+    /// Number of samples in each bucket.
+    pub buckets: [SharedIncMetric; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Returns the index of the bucket counting a sample of `duration_us` microseconds.
+    pub fn bucket_index(duration_us: u64) -> usize {
+        let bits = (64 - duration_us.leading_zeros()) as usize;
+        cmp::min(bits, LATENCY_BUCKETS - 1)
+    }
+
+    /// Adds a sample of `duration_us` microseconds to the histogram.
+    pub fn record(&self, duration_us: u64) {
+        self.count.inc();
+        self.sum_us.add(duration_us as usize);
+        self.buckets[Self::bucket_index(duration_us)].inc();
+    }
+
+    /// Returns the cumulative count of each bucket since the histogram was created.
+    pub fn bucket_counts(&self) -> Vec<u64> {
+        self.buckets.iter().map(|b| b.count() as u64).collect()
+    }
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -364,6 +404,22 @@ pub struct BalloonDeviceMetrics {
     pub deflate_count: SharedIncMetric,
     /// Number of times when handling events on a balloon device failed.
     pub event_fails: SharedIncMetric,
+    /// Number of memory ranges hinted as free by the driver.
+    pub free_page_hint_count: SharedIncMetric,
+    /// Number of bytes freed through free page hinting.
+    pub free_page_hint_freed: SharedIncMetric,
+    /// Number of hinted memory ranges that could not be freed.
+    pub free_page_hint_fails: SharedIncMetric,
+    /// Number of memory ranges reported as free by the driver.
+    pub free_page_report_count: SharedIncMetric,
+    /// Number of bytes freed through free page reporting.
+    pub free_page_report_freed: SharedIncMetric,
+    /// Number of reported memory ranges that could not be freed.
+    pub free_page_report_fails: SharedIncMetric,
+    /// Number of times the balloon was inflated by the automatic target policy.
+    pub auto_inflate_count: SharedIncMetric,
+    /// Number of times the balloon was deflated by the automatic target policy.
+    pub auto_deflate_count: SharedIncMetric,
 }
 
 /// Block Device associated metrics.
@@ -399,8 +455,66 @@ pub struct BlockDeviceMetrics {
     pub read_count: SharedIncMetric,
     /// Number of successful write operations.
     pub write_count: SharedIncMetric,
+    /// Number of successful discard operations.
+    pub discard_count: SharedIncMetric,
+    /// Number of successful write zeroes operations.
+    pub write_zeroes_count: SharedIncMetric,
     /// Number of rate limiter throttling events.
     pub rate_limiter_throttled_events: SharedIncMetric,
+    /// Metrics of the individual block devices, keyed by drive id.
+    pub drives: BlockDriveMetricsMap,
+}
+
+/// Metrics of one block device.
+#[derive(Default, Serialize)]
+pub struct BlockDriveMetrics {
+    /// Latency of read requests, from their arrival to their completion.
+    pub read_latency_us: LatencyHistogram,
+    /// Latency of write requests, from their arrival to their completion.
+    pub write_latency_us: LatencyHistogram,
+    /// Latency of flush requests, from their arrival to their completion.
+    pub flush_latency_us: LatencyHistogram,
+    /// Time spent with the requests blocked by the rate limiter, in microseconds.
+    pub rate_limiter_blocked_us: SharedIncMetric,
+}
+
+/// Registry of the per-drive block device metrics.
+#[derive(Default)]
+pub struct BlockDriveMetricsMap(Mutex<BTreeMap<String, Arc<BlockDriveMetrics>>>);
+
+impl BlockDriveMetricsMap {
+    /// Returns the metrics of the drive with id `drive_id`, registering them on first use.
+    ///
+    /// A drive that is replaced by another with the same id keeps accumulating on the same
+    /// metrics.
+    pub fn get_or_insert(&self, drive_id: &str) -> Arc<BlockDriveMetrics> {
+        extract_guard(self.0.lock())
+            .entry(drive_id.to_string())
+            .or_insert_with(Default::default)
+            .clone()
+    }
+}
+
+impl Serialize for BlockDriveMetricsMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let drives = extract_guard(self.0.lock());
+        serializer.collect_map(drives.iter().map(|(id, metrics)| (id, metrics.as_ref())))
+    }
+}
+
+/// Metrics for the built-in DHCP server.
+#[derive(Default, Serialize)]
+pub struct DhcpMetrics {
+    /// Number of messages received by the DHCP server.
+    pub rx_count: SharedIncMetric,
+    /// Number of messages the DHCP server could not parse or does not handle.
+    pub rx_invalid: SharedIncMetric,
+    /// Number of requests for an address other than the configured one.
+    pub nak_count: SharedIncMetric,
+    /// Number of replies sent by the DHCP server.
+    pub tx_count: SharedIncMetric,
+    /// Number of errors raised while writing replies.
+    pub tx_errors: SharedIncMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -433,6 +547,19 @@ pub struct LoggerSystemMetrics {
     pub log_fails: SharedIncMetric,
 }
 
+/// Memory hotplug device associated metrics.
+#[derive(Default, Serialize)]
+pub struct MemDeviceMetrics {
+    /// Number of times when activate failed on a memory hotplug device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of plug requests acknowledged to the driver.
+    pub plug_count: SharedIncMetric,
+    /// Number of unplug requests received from the driver.
+    pub unplug_count: SharedIncMetric,
+    /// Number of times when handling events on a memory hotplug device failed.
+    pub event_fails: SharedIncMetric,
+}
+
 /// Metrics for the MMDS functionality.
 #[derive(Default, Serialize)]
 pub struct MmdsMetrics {
@@ -460,6 +587,26 @@ pub struct MmdsMetrics {
     pub connections_destroyed: SharedIncMetric,
 }
 
+/// Maximum number of RX/TX queue pairs of a network device.
+pub const NET_MAX_QUEUE_PAIRS: usize = 16;
+
+/// Metrics related to one RX/TX queue pair of the network devices.
+#[derive(Default, Serialize)]
+pub struct NetQueuePairMetrics {
+    /// Number of bytes received on the queue pair.
+    pub rx_bytes_count: SharedIncMetric,
+    /// Number of packets received on the queue pair.
+    pub rx_packets_count: SharedIncMetric,
+    /// Number of RX rate limiter throttling events on the queue pair.
+    pub rx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of bytes transmitted on the queue pair.
+    pub tx_bytes_count: SharedIncMetric,
+    /// Number of packets transmitted on the queue pair.
+    pub tx_packets_count: SharedIncMetric,
+    /// Number of TX rate limiter throttling events on the queue pair.
+    pub tx_rate_limiter_throttled: SharedIncMetric,
+}
+
 /// Network-related metrics.
 #[derive(Default, Serialize)]
 pub struct NetDeviceMetrics {
@@ -469,6 +616,10 @@ pub struct NetDeviceMetrics {
     pub cfg_fails: SharedIncMetric,
     //// Number of times the mac address was updated through the config space.
     pub mac_address_updates: SharedIncMetric,
+    /// Number of times the link state reported to the guest was updated.
+    pub link_state_updates: SharedIncMetric,
+    /// Number of times the tap interface backing the device was replaced.
+    pub tap_swaps: SharedIncMetric,
     /// No available buffer for the net device rx queue.
     pub no_rx_avail_buffer: SharedIncMetric,
     /// No available buffer for the net device tx queue.
@@ -517,6 +668,18 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
+    /// Number of frames dropped by the anti-spoofing filter because of their source MAC.
+    pub tx_spoofed_mac_drops: SharedIncMetric,
+    /// Number of frames dropped by the anti-spoofing filter because of their IPv4 sender.
+    pub tx_spoofed_ip_drops: SharedIncMetric,
+    /// Number of control queue commands which failed.
+    pub ctrl_fails: SharedIncMetric,
+    /// Number of frames written to a packet capture.
+    pub capture_frames: SharedIncMetric,
+    /// Number of frames which could not be written to a packet capture.
+    pub capture_fails: SharedIncMetric,
+    /// Metrics of each queue pair, the index being the one of the queue pair in its device.
+    pub queue_pairs: [NetQueuePairMetrics; NET_MAX_QUEUE_PAIRS],
 }
 
 /// Performance metrics related for the moment only to snapshots.
@@ -704,6 +867,8 @@ pub struct FirecrackerMetrics {
     pub balloon: BalloonDeviceMetrics,
     /// A block device's related metrics.
     pub block: BlockDeviceMetrics,
+    /// Metrics specific to the built-in DHCP server.
+    pub dhcp: DhcpMetrics,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
     /// Metrics related to the i8042 device.
@@ -712,6 +877,8 @@ pub struct FirecrackerMetrics {
     pub latencies_us: PerformanceMetrics,
     /// Logging related metrics.
     pub logger: LoggerSystemMetrics,
+    /// A memory hotplug device's related metrics.
+    pub mem: MemDeviceMetrics,
     /// Metrics specific to MMDS functionality.
     pub mmds: MmdsMetrics,
     /// A network device's related metrics.
@@ -808,6 +975,57 @@ mod tests {
         assert_eq!(1, m1.fetch());
     }
 
+    #[test]
+    fn test_latency_histogram() {
+        assert_eq!(LatencyHistogram::bucket_index(0), 0);
+        assert_eq!(LatencyHistogram::bucket_index(1), 1);
+        assert_eq!(LatencyHistogram::bucket_index(3), 2);
+        assert_eq!(LatencyHistogram::bucket_index(4), 3);
+        assert_eq!(LatencyHistogram::bucket_index(1023), 10);
+        assert_eq!(LatencyHistogram::bucket_index(1024), 11);
+        assert_eq!(
+            LatencyHistogram::bucket_index(u64::max_value()),
+            LATENCY_BUCKETS - 1
+        );
+
+        let histogram = LatencyHistogram::default();
+        histogram.record(3);
+        histogram.record(2);
+        histogram.record(100);
+        assert_eq!(histogram.count.count(), 3);
+        assert_eq!(histogram.sum_us.count(), 105);
+        let mut expected = vec![0; LATENCY_BUCKETS];
+        expected[2] = 2;
+        expected[7] = 1;
+        assert_eq!(histogram.bucket_counts(), expected);
+
+        // Flushing resets the buckets, but not the cumulative counts.
+        let s = serde_json::to_string(&histogram).unwrap();
+        assert!(s.starts_with("{\"count\":3,\"sum_us\":105,\"buckets\":[0,0,2,0,0,0,0,1,"));
+        let s = serde_json::to_string(&histogram).unwrap();
+        assert!(s.starts_with("{\"count\":0,\"sum_us\":0,\"buckets\":[0,0,0,0,0,0,0,0,"));
+        assert_eq!(histogram.bucket_counts(), expected);
+    }
+
+    #[test]
+    fn test_block_drive_metrics() {
+        let drives = BlockDriveMetricsMap::default();
+        let rootfs = drives.get_or_insert("rootfs");
+        rootfs.rate_limiter_blocked_us.add(10);
+        assert_eq!(
+            drives
+                .get_or_insert("rootfs")
+                .rate_limiter_blocked_us
+                .count(),
+            10
+        );
+        drives.get_or_insert("scratch");
+
+        let s = serde_json::to_string(&drives).unwrap();
+        assert!(s.starts_with("{\"rootfs\":{\"read_latency_us\":{"));
+        assert!(s.contains("\"rate_limiter_blocked_us\":10},\"scratch\":{"));
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
@@ -23,6 +23,8 @@ pub enum Header {
     Accept,
     /// Header `Accept-Encoding`
     AcceptEncoding,
+    /// Header `Connection`.
+    Connection,
 }
 
 impl Header {
@@ -36,6 +38,7 @@ impl Header {
             Self::Server => b"Server",
             Self::Accept => b"Accept",
             Self::AcceptEncoding => b"Accept-Encoding",
+            Self::Connection => b"Connection",
         }
     }
 
@@ -56,6 +59,7 @@ impl Header {
                 "server" => Ok(Self::Server),
                 "accept" => Ok(Self::Accept),
                 "accept-encoding" => Ok(Self::AcceptEncoding),
+                "connection" => Ok(Self::Connection),
                 invalid_key => Err(RequestError::HeaderError(HttpHeaderError::UnsupportedName(
                     invalid_key.to_string(),
                 ))),
@@ -94,6 +98,10 @@ pub struct Headers {
     /// `Accept` header might be used by HTTP clients to enforce server responses with content
     /// formatted in a specific way.
     accept: MediaType,
+    /// The `Connection` header field lets the client ask for the connection to be closed
+    /// ("close") or kept open ("keep-alive") after the response. It is `None` when the
+    /// header is missing or has neither option, so that the HTTP version default applies.
+    keep_alive: Option<bool>,
 }
 
 impl Default for Headers {
@@ -106,6 +114,7 @@ impl Default for Headers {
             // The default `Accept` media type is plain text. This is inclusive enough
             // for structured and unstructured text.
             accept: MediaType::PlainText,
+            keep_alive: None,
         }
     }
 }
@@ -204,6 +213,19 @@ impl Headers {
                         },
                         Header::Server => Ok(()),
                         Header::AcceptEncoding => Encoding::try_from(entry[1].trim().as_bytes()),
+                        Header::Connection => {
+                            // The value is a comma separated list of case insensitive options.
+                            for option in entry[1].split(',') {
+                                match option.trim().to_ascii_lowercase().as_str() {
+                                    "close" => self.keep_alive = Some(false),
+                                    "keep-alive" if self.keep_alive.is_none() => {
+                                        self.keep_alive = Some(true)
+                                    }
+                                    _ => (),
+                                }
+                            }
+                            Ok(())
+                        }
                     }
                 } else {
                     Err(RequestError::HeaderError(
@@ -237,6 +259,12 @@ impl Headers {
         self.expect
     }
 
+    /// Returns `Some(false)` if the client asked for the connection to be closed,
+    /// `Some(true)` if it asked for it to be kept alive and `None` otherwise.
+    pub fn keep_alive(&self) -> Option<bool> {
+        self.keep_alive
+    }
+
     /// Returns the `Accept` header `MediaType`.
     pub fn accept(&self) -> MediaType {
         self.accept
@@ -422,6 +450,7 @@ mod tests {
                 expect,
                 chunked,
                 accept: MediaType::PlainText,
+                keep_alive: None,
             }
         }
     }
@@ -432,6 +461,7 @@ mod tests {
         assert_eq!(headers.content_length(), 0);
         assert_eq!(headers.chunked(), false);
         assert_eq!(headers.expect(), false);
+        assert_eq!(headers.keep_alive(), None);
     }
 
     #[test]
@@ -633,6 +663,16 @@ mod tests {
             )))
         );
 
+        // Test connection options.
+        assert!(header.parse_header_line(b"Connection: upgrade").is_ok());
+        assert_eq!(header.keep_alive(), None);
+        assert!(header.parse_header_line(b"Connection: Keep-Alive").is_ok());
+        assert_eq!(header.keep_alive(), Some(true));
+        assert!(header
+            .parse_header_line(b"Connection: keep-alive, close")
+            .is_ok());
+        assert_eq!(header.keep_alive(), Some(false));
+
         assert!(header
             .parse_header_line(b"Accept-Encoding: deflate")
             .is_ok());
@@ -713,5 +753,8 @@ mod tests {
 
         let header = Header::try_from(b"Accept").unwrap();
         assert_eq!(header.raw(), b"Accept");
+
+        let header = Header::try_from(b"connection").unwrap();
+        assert_eq!(header.raw(), b"Connection");
     }
 }
@@ -205,6 +205,10 @@ pub enum Method {
     Put,
     /// PATCH Method.
     Patch,
+    /// POST Method.
+    Post,
+    /// DELETE Method.
+    Delete,
 }
 
 impl Method {
@@ -220,6 +224,8 @@ impl Method {
             b"GET" => Ok(Self::Get),
             b"PUT" => Ok(Self::Put),
             b"PATCH" => Ok(Self::Patch),
+            b"POST" => Ok(Self::Post),
+            b"DELETE" => Ok(Self::Delete),
             _ => Err(RequestError::InvalidHttpMethod("Unsupported HTTP method.")),
         }
     }
@@ -230,6 +236,8 @@ impl Method {
             Self::Get => b"GET",
             Self::Put => b"PUT",
             Self::Patch => b"PATCH",
+            Self::Post => b"POST",
+            Self::Delete => b"DELETE",
         }
     }
 }
@@ -330,13 +338,17 @@ mod tests {
         assert_eq!(Method::Get.raw(), b"GET");
         assert_eq!(Method::Put.raw(), b"PUT");
         assert_eq!(Method::Patch.raw(), b"PATCH");
+        assert_eq!(Method::Post.raw(), b"POST");
+        assert_eq!(Method::Delete.raw(), b"DELETE");
 
         // Tests for try_from
         assert_eq!(Method::try_from(b"GET").unwrap(), Method::Get);
         assert_eq!(Method::try_from(b"PUT").unwrap(), Method::Put);
         assert_eq!(Method::try_from(b"PATCH").unwrap(), Method::Patch);
+        assert_eq!(Method::try_from(b"POST").unwrap(), Method::Post);
+        assert_eq!(Method::try_from(b"DELETE").unwrap(), Method::Delete);
         assert_eq!(
-            Method::try_from(b"POST").unwrap_err(),
+            Method::try_from(b"HEAD").unwrap_err(),
             RequestError::InvalidHttpMethod("Unsupported HTTP method.")
         );
     }
@@ -1,6 +1,7 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::cmp::min;
 use std::collections::VecDeque;
 use std::io::{Read, Write};
 
@@ -18,6 +19,9 @@ enum ConnectionState {
     WaitingForRequestLine,
     WaitingForHeaders,
     WaitingForBody,
+    WaitingForChunkSize,
+    WaitingForChunkData,
+    WaitingForTrailers,
     RequestReady,
 }
 
@@ -39,8 +43,8 @@ pub struct HttpConnection<T> {
     /// Contains all bytes pertaining to the body of the request that
     /// is currently being processed.
     body_vec: Vec<u8>,
-    /// Represents how many bytes from the body of the request are still
-    /// to be read.
+    /// Represents how many bytes from the body of the request, or from the
+    /// current chunk of a chunked body, are still to be read.
     body_bytes_to_be_read: u32,
     /// A queue of all requests that have been fully received and parsed.
     parsed_requests: VecDeque<Request>,
@@ -100,6 +104,21 @@ impl<T: Read + Write> HttpConnection<T> {
                         return Ok(());
                     }
                 }
+                ConnectionState::WaitingForChunkSize => {
+                    if !self.parse_chunk_size(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
+                ConnectionState::WaitingForChunkData => {
+                    if !self.parse_chunk_data(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
+                ConnectionState::WaitingForTrailers => {
+                    if !self.parse_trailers(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
                 ConnectionState::RequestReady => {
                     // This request is ready to be passed for handling.
                     // Update the state machine to expect a new request and push this request into
@@ -229,16 +248,24 @@ impl<T: Read + Write> HttpConnection<T> {
                     .ok_or(ConnectionError::ParseError(
                         RequestError::HeadersWithoutPendingRequest,
                     ))?;
-                if request.headers.content_length() == 0 {
+                let has_body = request.headers.chunked() || request.headers.content_length() != 0;
+                if has_body && request.headers.expect() {
+                    // Send expect.
+                    let expect_response =
+                        Response::new(request.http_version(), StatusCode::Continue);
+                    self.response_queue.push_back(expect_response);
+                }
+
+                if request.headers.chunked() {
+                    // A request carrying both a `Content-Length` and a chunked body is
+                    // ambiguous and might be used to smuggle requests, so we reject it.
+                    if request.headers.content_length() != 0 {
+                        return Err(ConnectionError::ParseError(RequestError::InvalidRequest));
+                    }
+                    self.state = ConnectionState::WaitingForChunkSize;
+                } else if request.headers.content_length() == 0 {
                     self.state = ConnectionState::RequestReady;
                 } else {
-                    if request.headers.expect() {
-                        // Send expect.
-                        let expect_response =
-                            Response::new(request.http_version(), StatusCode::Continue);
-                        self.response_queue.push_back(expect_response);
-                    }
-
                     self.body_bytes_to_be_read = request.headers.content_length();
                     request.body = Some(Body::new(vec![]));
                     self.state = ConnectionState::WaitingForBody;
@@ -374,6 +401,175 @@ impl<T: Read + Write> HttpConnection<T> {
         Ok(true)
     }
 
+    /// Parses bytes in `buffer` for the size line of the next chunk of a chunked body.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if the chunk size is invalid or the line is longer than
+    /// BUFFER_SIZE.
+    fn parse_chunk_size(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        let line_end_index = match self.find_line_end(*line_start_index, end_cursor)? {
+            Some(line_end_index) => line_end_index,
+            None => return Ok(false),
+        };
+        // The slice access is safe because `find_line_end` returns an index between
+        // `line_start_index` and `end_cursor`.
+        let line = &self.buffer[*line_start_index..line_end_index];
+        // The chunk size is a hexadecimal number, optionally followed by chunk extensions
+        // starting with `;`, which we ignore.
+        let size_end = line
+            .iter()
+            .position(|&byte| byte == b';')
+            .unwrap_or_else(|| line.len());
+        let chunk_size = std::str::from_utf8(&line[..size_end])
+            .ok()
+            .map(str::trim)
+            .filter(|size| !size.is_empty() && size.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|size| u32::from_str_radix(size, 16).ok())
+            .ok_or(ConnectionError::ParseError(RequestError::InvalidRequest))?;
+
+        // The unchecked addition is safe because of the previous `find_line_end()`.
+        *line_start_index = line_end_index + CRLF_LEN;
+
+        if chunk_size == 0 {
+            // The last chunk is followed by optional trailer fields.
+            self.state = ConnectionState::WaitingForTrailers;
+            return Ok(true);
+        }
+
+        // The whole body has to fit in the same range as a `Content-Length` value.
+        let body_len = (self.body_vec.len() as u64) + u64::from(chunk_size);
+        if body_len > u64::from(u32::max_value()) {
+            return Err(ConnectionError::ParseError(RequestError::Overflow));
+        }
+        // The chunk data is followed by a CR LF sequence, which we read along with it.
+        self.body_bytes_to_be_read = chunk_size
+            .checked_add(CRLF_LEN as u32)
+            .ok_or(ConnectionError::ParseError(RequestError::Overflow))?;
+        self.state = ConnectionState::WaitingForChunkData;
+        Ok(true)
+    }
+
+    /// Parses bytes in `buffer` to be put into the request body as part of the current chunk.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned when the chunk data is not followed by CR LF.
+    fn parse_chunk_data(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        if end_cursor > self.buffer.len() {
+            return Err(ConnectionError::ParseError(RequestError::Overflow));
+        }
+        let start_to_end = end_cursor
+            .checked_sub(*line_start_index)
+            .ok_or(ConnectionError::ParseError(RequestError::Underflow))?;
+        let bytes_to_read = min(start_to_end, self.body_bytes_to_be_read as usize);
+        // The unchecked addition is safe and the slice access is in bounds because
+        // `bytes_to_read` is at most `end_cursor - line_start_index`.
+        let data_end = *line_start_index + bytes_to_read;
+        self.body_vec
+            .extend_from_slice(&self.buffer[*line_start_index..data_end]);
+        *line_start_index = data_end;
+        // Safe to subtract directly as `bytes_to_read` is at most `body_bytes_to_be_read`.
+        self.body_bytes_to_be_read -= bytes_to_read as u32;
+
+        if self.body_bytes_to_be_read != 0 {
+            // We have consumed the whole buffer, so clear it and wait for the rest
+            // of the chunk.
+            self.shift_buffer_left(end_cursor, end_cursor)
+                .map_err(ConnectionError::ParseError)?;
+            return Ok(false);
+        }
+
+        // The chunk data must be followed by a CR LF sequence, which is not part of the body.
+        if !self.body_vec.ends_with(&[CR, LF]) {
+            return Err(ConnectionError::ParseError(RequestError::InvalidRequest));
+        }
+        // The subtraction is safe because `body_vec` ends with CR LF.
+        let body_len = self.body_vec.len() - CRLF_LEN;
+        self.body_vec.truncate(body_len);
+
+        self.state = ConnectionState::WaitingForChunkSize;
+        Ok(true)
+    }
+
+    /// Parses bytes in `buffer` for the trailer fields that end a chunked body.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if a trailer line is longer than BUFFER_SIZE.
+    fn parse_trailers(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        let line_end_index = match self.find_line_end(*line_start_index, end_cursor)? {
+            Some(line_end_index) => line_end_index,
+            None => return Ok(false),
+        };
+        let is_last_line = line_end_index == *line_start_index;
+        // The unchecked addition is safe because of the previous `find_line_end()`.
+        *line_start_index = line_end_index + CRLF_LEN;
+
+        // Trailer fields are not of interest to us, so we skip them until we find
+        // the empty line which ends the request.
+        if is_last_line {
+            let request = self
+                .pending_request
+                .as_mut()
+                .ok_or(ConnectionError::ParseError(
+                    RequestError::BodyWithoutPendingRequest,
+                ))?;
+            if !self.body_vec.is_empty() {
+                let body: Vec<_> = self.body_vec.drain(..).collect();
+                request.body = Some(Body::new(body));
+            }
+            self.state = ConnectionState::RequestReady;
+        }
+        Ok(true)
+    }
+
+    /// Looks for the CR LF sequence ending the line that starts at `line_start_index`
+    /// and returns its index in `buffer`.
+    /// Returns `None` if the line is incomplete, in which case the line is moved to the
+    /// beginning of the buffer so that it is completed by the next `try_read` call.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if the line is longer than BUFFER_SIZE.
+    fn find_line_end(
+        &mut self,
+        line_start_index: usize,
+        end_cursor: usize,
+    ) -> Result<Option<usize>, ConnectionError> {
+        if end_cursor > self.buffer.len() {
+            return Err(ConnectionError::ParseError(RequestError::Overflow));
+        }
+        if end_cursor < line_start_index {
+            return Err(ConnectionError::ParseError(RequestError::Underflow));
+        }
+        // Safe to access the slice as the bounds are checked above.
+        match find(&self.buffer[line_start_index..end_cursor], &[CR, LF]) {
+            // The unchecked addition is safe because `find` returns an index within
+            // the slice.
+            Some(relative_line_end_index) => Ok(Some(line_start_index + relative_line_end_index)),
+            None => {
+                if line_start_index == 0 && end_cursor == BUFFER_SIZE {
+                    return Err(ConnectionError::ParseError(RequestError::InvalidRequest));
+                }
+                self.shift_buffer_left(line_start_index, end_cursor)
+                    .map_err(ConnectionError::ParseError)?;
+                Ok(None)
+            }
+        }
+    }
+
     /// Tries to write the first available response to the provided stream.
     /// Meant to be used only with non-blocking streams and an `EPOLL` structure.
     /// Should be called whenever an `EPOLLOUT` event is signaled. If no bytes
@@ -501,8 +697,7 @@ mod tests {
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
                                  Expect: 100-continue\r\n\
-                                 Content-Length: 26\r\n\
-                                 Transfer-Encoding: chunked\r\n\r\n",
+                                 Content-Length: 26\r\n\r\n",
             )
             .unwrap();
         assert!(conn.try_read().is_ok());
@@ -513,7 +708,7 @@ mod tests {
 
         let expected_request = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(26, true, true),
+            headers: Headers::new(26, true, false),
             body: Some(Body::new(b"this is not\n\r\na json \nbody".to_vec())),
         };
 
@@ -529,8 +724,7 @@ mod tests {
         sender
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
-                                 Expect: 100-continue\r\n\
-                                 Transfer-Encoding: chunked\r\n",
+                                 Expect: 100-continue\r\n",
             )
             .unwrap();
 
@@ -549,7 +743,7 @@ mod tests {
 
         let expected_request = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(26, true, true),
+            headers: Headers::new(26, true, false),
             body: Some(Body::new(b"this is not\n\r\na json \nbody".to_vec())),
         };
         assert_eq!(request, expected_request);
@@ -565,7 +759,7 @@ mod tests {
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
                                  Expect: 100-continue\r\n\
-                                 Transfer-Encoding: chunked\r\n",
+                                 Transfer-Encoding:identity\r\n",
             )
             .unwrap();
 
@@ -582,7 +776,7 @@ mod tests {
         let request = conn.pop_parsed_request().unwrap();
         let expected_request = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(26, true, true),
+            headers: Headers::new(26, true, false),
             body: Some(Body::new(b"this is not\n\r\na json \nbody".to_vec())),
         };
         assert_eq!(request, expected_request);
@@ -597,8 +791,7 @@ mod tests {
         sender
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
-                                 Expect: 100-continue\r\n\
-                                 Transfer-Encoding: chunked\r\n",
+                                 Expect: 100-continue\r\n",
             )
             .unwrap();
 
@@ -631,7 +824,6 @@ mod tests {
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
                                  Expect: 100-continue\r\n\
-                                 Transfer-Encoding: chunked\r\n\
                                  Content-Length: 1400\r\n\r\n",
             )
             .unwrap();
@@ -646,7 +838,7 @@ mod tests {
         let request = conn.pop_parsed_request().unwrap();
         let expected_request = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(1400, true, true),
+            headers: Headers::new(1400, true, false),
             body: Some(Body::new(request_body)),
         };
 
@@ -708,15 +900,14 @@ mod tests {
         sender
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
-                                 Expect: 100-continue\r\n\
-                                 Transfer-Encoding: chunked\r\n\r\n",
+                                 Expect: 100-continue\r\n\r\n",
             )
             .unwrap();
         conn.try_read().unwrap();
         let request = conn.pop_parsed_request().unwrap();
         let expected_request = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(0, true, true),
+            headers: Headers::new(0, true, false),
             body: None,
         };
         assert_eq!(request, expected_request);
@@ -798,7 +989,6 @@ mod tests {
         sender
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
-                                 Transfer-Encoding: chunked\r\n\
                                  Content-Length: 26\r\n\r\nthis is not\n\r\na json \nbody",
             )
             .unwrap();
@@ -810,7 +1000,7 @@ mod tests {
 
         let expected_request_first = Request {
             request_line: RequestLine::new(Method::Patch, "http://localhost/home", Version::Http11),
-            headers: Headers::new(26, false, true),
+            headers: Headers::new(26, false, false),
             body: Some(Body::new(b"this is not\n\r\na json \nbody".to_vec())),
         };
 
@@ -827,6 +1017,136 @@ mod tests {
         assert_eq!(request_second, expected_request_second);
     }
 
+    #[test]
+    fn test_try_read_chunked_body() {
+        // Chunked body with extensions and trailers, followed by another request.
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        receiver.set_nonblocking(true).expect("Can't modify socket");
+        let mut conn = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT http://localhost/home HTTP/1.1\r\n\
+                                 Expect: 100-continue\r\n\
+                                 Transfer-Encoding: chunked\r\n\r\n\
+                                 5\r\nhello\r\n\
+                                 6;name=value\r\n world\r\n\
+                                 0\r\n\
+                                 Trailer-Field: ignored\r\n\r\n\
+                                 GET http://localhost/home HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+
+        conn.try_read().unwrap();
+        // The client expects a `100 Continue` response before sending the body.
+        assert!(conn.pending_write());
+        let request = conn.pop_parsed_request().unwrap();
+        let expected_request = Request {
+            request_line: RequestLine::new(Method::Put, "http://localhost/home", Version::Http11),
+            headers: Headers::new(0, true, true),
+            body: Some(Body::new(b"hello world".to_vec())),
+        };
+        assert_eq!(request, expected_request);
+        assert_eq!(request.body.unwrap(), Body::new(b"hello world".to_vec()));
+
+        let request = conn.pop_parsed_request().unwrap();
+        assert_eq!(request.method(), Method::Get);
+        assert!(conn.pop_parsed_request().is_none());
+
+        // Chunked body split across multiple reads and larger than the buffer.
+        let mut request_body: Vec<u8> = Vec::with_capacity(1400);
+        for _ in 0..100 {
+            request_body.write_all(b"This is a test").unwrap();
+        }
+        sender
+            .write_all(
+                b"PATCH http://localhost/home HTTP/1.1\r\n\
+                                 Transfer-Encoding: chunked\r\n\r\n\
+                                 5",
+            )
+            .unwrap();
+        conn.try_read().unwrap();
+        sender.write_all(b"78\r\n").unwrap();
+        conn.try_read().unwrap();
+        sender.write_all(&request_body[..1000]).unwrap();
+        conn.try_read().unwrap();
+        sender.write_all(&request_body[1000..]).unwrap();
+        sender.write_all(b"\r").unwrap();
+        conn.try_read().unwrap();
+        sender.write_all(b"\n0\r\n").unwrap();
+        conn.try_read().unwrap();
+        assert!(conn.pop_parsed_request().is_none());
+        sender.write_all(b"\r\n").unwrap();
+        conn.try_read().unwrap();
+
+        let request = conn.pop_parsed_request().unwrap();
+        assert_eq!(request.method(), Method::Patch);
+        assert_eq!(request.body.unwrap(), Body::new(request_body));
+
+        // Empty chunked body.
+        sender
+            .write_all(
+                b"PUT http://localhost/home HTTP/1.1\r\n\
+                                 Transfer-Encoding: chunked\r\n\r\n\
+                                 0\r\n\r\n",
+            )
+            .unwrap();
+        conn.try_read().unwrap();
+        assert!(conn.pop_parsed_request().unwrap().body.is_none());
+    }
+
+    #[test]
+    fn test_try_read_invalid_chunked_body() {
+        let invalid_requests: [&[u8]; 5] = [
+            // Both `Content-Length` and chunked `Transfer-Encoding` are set.
+            b"PUT http://localhost/home HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\
+                Content-Length: 5\r\n\r\n\
+                5\r\nhello\r\n0\r\n\r\n",
+            // Chunk size is not a hexadecimal number.
+            b"PUT http://localhost/home HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\r\n\
+                xyz\r\nhello\r\n0\r\n\r\n",
+            // Chunk size is signed.
+            b"PUT http://localhost/home HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\r\n\
+                +5\r\nhello\r\n0\r\n\r\n",
+            // Chunk size is larger than `u32::max_value()`.
+            b"PUT http://localhost/home HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\r\n\
+                100000000\r\nhello\r\n0\r\n\r\n",
+            // Chunk data is longer than the chunk size.
+            b"PUT http://localhost/home HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\r\n\
+                4\r\nhello\r\n0\r\n\r\n",
+        ];
+        for request in invalid_requests.iter() {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            receiver.set_nonblocking(true).expect("Can't modify socket");
+            let mut conn = HttpConnection::new(receiver);
+            sender.write_all(request).unwrap();
+            assert_eq!(
+                conn.try_read().unwrap_err(),
+                ConnectionError::ParseError(RequestError::InvalidRequest)
+            );
+        }
+
+        // Chunk size overflows when adding the trailing CR LF.
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        receiver.set_nonblocking(true).expect("Can't modify socket");
+        let mut conn = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT http://localhost/home HTTP/1.1\r\n\
+                    Transfer-Encoding: chunked\r\n\r\n\
+                    ffffffff\r\n",
+            )
+            .unwrap();
+        assert_eq!(
+            conn.try_read().unwrap_err(),
+            ConnectionError::ParseError(RequestError::Overflow)
+        );
+    }
+
     #[test]
     fn test_try_read_connection_closed() {
         // Connection abruptly closed.
@@ -836,7 +1156,6 @@ mod tests {
         sender
             .write_all(
                 b"PATCH http://localhost/home HTTP/1.1\r\n\
-                                 Transfer-Encoding: chunked\r\n\
                                  Content-Len",
             )
             .unwrap();
@@ -7,12 +7,13 @@
 //! HTTP/1.1 has a mandatory header **Host**, but as this crate is only used
 //! for parsing API requests, this header (if present) is ignored.
 //!
-//! This HTTP implementation is stateless thus it does not support chunking or
-//! compression.
+//! Request bodies can be sent either with a `Content-Length` or with the chunked
+//! transfer coding. Compression is not supported.
 //!
 //! ## Supported Headers
 //! The **micro_http** crate has support for parsing the following **Request**
 //! headers:
+//! - Connection
 //! - Content-Length
 //! - Expect
 //! - Transfer-Encoding
@@ -31,6 +32,8 @@
 //! - GET
 //! - PUT
 //! - PATCH
+//! - POST
+//! - DELETE
 //!
 //! ## Supported Status Codes
 //! The supported status codes are:
@@ -77,6 +80,13 @@
 //! non-blocking mode. Non-blocking is achieved by using `epoll` to make sure
 //! `requests` will never block when called.
 //!
+//! Connections are persistent, as defined by HTTP/1.1. Clients can pipeline
+//! requests on a connection and the server sends the responses in the order of
+//! the requests, regardless of the order in which they are answered. A connection
+//! is closed after answering a request which carries `Connection: close`, or an
+//! HTTP/1.0 request without `Connection: keep-alive`, and connections waiting for
+//! new requests can be closed after a timeout set through `set_idle_timeout`.
+//!
 //! ## Example for using the server
 //!
 //! ```
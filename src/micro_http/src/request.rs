@@ -289,6 +289,17 @@ impl Request {
     pub fn method(&self) -> Method {
         self.request_line.method
     }
+
+    /// Returns `true` if the connection should be kept open after responding to the `Request`.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`,
+    /// while HTTP/1.0 connections are closed unless the client sends `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        match self.http_version() {
+            Version::Http10 => self.headers.keep_alive() == Some(true),
+            Version::Http11 => self.headers.keep_alive() != Some(false),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -389,7 +400,7 @@ mod tests {
         );
 
         // Test for invalid method.
-        let request_line = b"POST http://localhost/home HTTP/1.0";
+        let request_line = b"HEAD http://localhost/home HTTP/1.0";
         assert_eq!(
             RequestLine::try_from(request_line).unwrap_err(),
             RequestError::InvalidHttpMethod("Unsupported HTTP method.")
@@ -522,4 +533,18 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_keep_alive() {
+        let request = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+        let request = Request::try_from(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(!request.keep_alive());
+
+        let request = Request::try_from(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!request.keep_alive());
+        let request =
+            Request::try_from(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+    }
 }
@@ -83,6 +83,7 @@ pub struct ResponseHeaders {
     server: String,
     allow: Vec<Method>,
     accept_encoding: bool,
+    keep_alive: bool,
 }
 
 impl Default for ResponseHeaders {
@@ -93,6 +94,7 @@ impl Default for ResponseHeaders {
             server: String::from("Firecracker API"),
             allow: Vec::new(),
             accept_encoding: false,
+            keep_alive: true,
         }
     }
 }
@@ -125,7 +127,13 @@ impl ResponseHeaders {
         buf.write_all(self.server.as_bytes())?;
 
         buf.write_all(&[CR, LF])?;
-        buf.write_all(b"Connection: keep-alive")?;
+        buf.write_all(Header::Connection.raw())?;
+        buf.write_all(&[COLON, SP])?;
+        if self.keep_alive {
+            buf.write_all(b"keep-alive")?;
+        } else {
+            buf.write_all(b"close")?;
+        }
         buf.write_all(&[CR, LF])?;
 
         self.write_allow_header(buf)?;
@@ -173,6 +181,11 @@ impl ResponseHeaders {
     pub fn set_encoding(&mut self) {
         self.accept_encoding = true;
     }
+
+    /// Sets whether the connection is kept open after the response is sent.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
 }
 
 /// Wrapper over an HTTP Response.
@@ -227,6 +240,14 @@ impl Response {
         self.headers.allow = methods;
     }
 
+    /// Sets whether the connection is kept open after the `Response` is sent.
+    ///
+    /// When `keep_alive` is `false`, the `Response` carries a `Connection: close` header
+    /// and the server closes the connection once the `Response` has been written.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.headers.set_keep_alive(keep_alive);
+    }
+
     /// Allows a specific HTTP method.
     pub fn allow_method(&mut self, method: Method) {
         self.headers.allow.push(method);
@@ -281,6 +302,11 @@ impl Response {
     pub fn allow(&self) -> Vec<Method> {
         self.headers.allow.clone()
     }
+
+    /// Returns `true` if the connection is kept open after the response is sent.
+    pub fn keep_alive(&self) -> bool {
+        self.headers.keep_alive
+    }
 }
 
 #[cfg(test)]
@@ -327,6 +353,19 @@ mod tests {
         assert!(response.write_all(&mut response_buf.as_mut()).is_ok());
         assert_eq!(response_buf.as_ref(), expected_response);
 
+        // Test response `Connection` header.
+        let mut response = Response::new(Version::Http11, StatusCode::NoContent);
+        assert!(response.keep_alive());
+        response.set_keep_alive(false);
+        assert!(!response.keep_alive());
+
+        let expected_response: &'static [u8] = b"HTTP/1.1 204 \r\n\
+            Server: Firecracker API\r\n\
+            Connection: close\r\n\r\n";
+        let mut response_buf: [u8; 61] = [0; 61];
+        assert!(response.write_all(&mut response_buf.as_mut()).is_ok());
+        assert_eq!(response_buf.as_ref(), expected_response);
+
         // Test write failed.
         let mut response_buf: [u8; 1] = [0; 1];
         assert!(response.write_all(&mut response_buf.as_mut()).is_err());
@@ -1,19 +1,22 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::cmp::{max, min};
 use std::io::{Read, Write};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 use crate::common::{Body, Version};
 pub use crate::common::{ConnectionError, RequestError, ServerError};
 use crate::connection::HttpConnection;
 use crate::request::Request;
 use crate::response::{Response, StatusCode};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll;
 
 static SERVER_FULL_ERROR_MESSAGE: &[u8] = b"HTTP/1.1 503\r\n\
@@ -30,13 +33,16 @@ pub struct ServerRequest {
     pub request: Request,
     /// Identification token.
     id: u64,
+    /// Position of the request among the requests received on its connection.
+    seq: u64,
 }
 
 impl ServerRequest {
     /// Creates a new `ServerRequest` object from an existing `Request`,
-    /// adding an identification token.
-    pub fn new(request: Request, id: u64) -> Self {
-        Self { request, id }
+    /// adding an identification token and the position of the request
+    /// on its connection.
+    pub fn new(request: Request, id: u64, seq: u64) -> Self {
+        Self { request, id, seq }
     }
 
     /// Returns a reference to the inner request.
@@ -53,7 +59,7 @@ impl ServerRequest {
         F: Fn(&Request) -> Response,
     {
         let http_response = callable(self.inner());
-        ServerResponse::new(http_response, self.id)
+        ServerResponse::new(http_response, self.id, self.seq)
     }
 }
 
@@ -63,11 +69,13 @@ pub struct ServerResponse {
     response: Response,
     /// Identification token.
     id: u64,
+    /// Position of the answered request on its connection.
+    seq: u64,
 }
 
 impl ServerResponse {
-    fn new(response: Response, id: u64) -> Self {
-        Self { response, id }
+    fn new(response: Response, id: u64, seq: u64) -> Self {
+        Self { response, id, seq }
     }
 }
 
@@ -91,6 +99,18 @@ struct ClientConnection<T> {
     /// absorbed responses.
     /// This has to be `0` if we want to drop the connection.
     in_flight_response_count: u32,
+    /// Sequence number given to the next request read from the connection.
+    next_request_seq: u64,
+    /// Sequence number of the request whose response has to be sent next.
+    next_response_seq: u64,
+    /// Responses which cannot be sent yet because the responses to earlier
+    /// requests are still in flight, keyed by request sequence number.
+    pending_responses: BTreeMap<u64, Response>,
+    /// Sequence number of the last request answered on the connection, if it has
+    /// to be closed. Requests following it are dropped.
+    close_after: Option<u64>,
+    /// The last time data was exchanged on the connection.
+    last_activity: Instant,
 }
 
 impl<T: Read + Write> ClientConnection<T> {
@@ -99,11 +119,17 @@ impl<T: Read + Write> ClientConnection<T> {
             connection,
             state: ClientConnectionState::AwaitingIncoming,
             in_flight_response_count: 0,
+            next_request_seq: 0,
+            next_response_seq: 0,
+            pending_responses: BTreeMap::new(),
+            close_after: None,
+            last_activity: Instant::now(),
         }
     }
 
-    fn read(&mut self) -> Result<Vec<Request>> {
+    fn read(&mut self, id: u64) -> Result<Vec<ServerRequest>> {
         // Data came into the connection.
+        self.last_activity = Instant::now();
         let mut parsed_requests = vec![];
         match self.connection.try_read() {
             Err(ConnectionError::ConnectionClosed) => {
@@ -120,7 +146,7 @@ impl<T: Read + Write> ClientConnection<T> {
                 let mut internal_error_response =
                     Response::new(Version::Http11, StatusCode::InternalServerError);
                 internal_error_response.set_body(Body::new(inner.to_string()));
-                self.connection.enqueue_response(internal_error_response);
+                self.enqueue_error_response(internal_error_response)?;
             }
             Err(ConnectionError::ParseError(inner)) => {
                 // An error occurred while parsing the read bytes.
@@ -133,7 +159,7 @@ impl<T: Read + Write> ClientConnection<T> {
                     "{{ \"error\": \"{}\nAll previous unanswered requests will be dropped.\" }}",
                     inner.to_string()
                 )));
-                self.connection.enqueue_response(error_response);
+                self.enqueue_error_response(error_response)?;
             }
             Err(ConnectionError::InvalidWrite) => {
                 // This is unreachable because `HttpConnection::try_read()` cannot return this error variant.
@@ -141,8 +167,18 @@ impl<T: Read + Write> ClientConnection<T> {
             }
             Ok(()) => {
                 while let Some(request) = self.connection.pop_parsed_request() {
+                    // Requests following the one after which the connection is
+                    // closed will never be answered.
+                    if self.close_after.is_some() {
+                        continue;
+                    }
+                    let seq = self.next_request_seq;
+                    self.next_request_seq = seq.checked_add(1).ok_or(ServerError::Overflow)?;
+                    if !request.keep_alive() {
+                        self.close_after = Some(seq);
+                    }
                     // Add all valid requests to `parsed_requests`.
-                    parsed_requests.push(request);
+                    parsed_requests.push(ServerRequest::new(request, id, seq));
                 }
             }
         }
@@ -161,6 +197,7 @@ impl<T: Read + Write> ClientConnection<T> {
 
     fn write(&mut self) -> Result<()> {
         // The stream is available for writing.
+        self.last_activity = Instant::now();
         match self.connection.try_write() {
             Err(ConnectionError::ConnectionClosed) | Err(ConnectionError::StreamError(_)) => {
                 // Writing to the stream failed so it will be removed.
@@ -174,24 +211,84 @@ impl<T: Read + Write> ClientConnection<T> {
             _ => {
                 // Check if we still have bytes to write for this connection.
                 if !self.connection.pending_write() {
-                    self.state = ClientConnectionState::AwaitingIncoming;
+                    // The connection is closed once the response to the last request
+                    // we answer on it has been written.
+                    if self
+                        .close_after
+                        .map_or(false, |close_after| self.next_response_seq > close_after)
+                    {
+                        self.state = ClientConnectionState::Closed;
+                    } else {
+                        self.state = ClientConnectionState::AwaitingIncoming;
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn enqueue_response(&mut self, response: Response) -> Result<()> {
-        if self.state != ClientConnectionState::Closed {
-            self.connection.enqueue_response(response);
-        }
+    fn enqueue_response(&mut self, seq: u64, mut response: Response) -> Result<()> {
         self.in_flight_response_count = self
             .in_flight_response_count
             .checked_sub(1)
             .ok_or(ServerError::Underflow)?;
+        if self.state == ClientConnectionState::Closed {
+            return Ok(());
+        }
+
+        if !response.keep_alive() {
+            // The responses to the requests following this one will never be sent.
+            let close_after = self
+                .close_after
+                .map_or(seq, |close_after| min(close_after, seq));
+            self.close_after = Some(close_after);
+            // The addition is safe because `close_after` is smaller than `next_request_seq`.
+            self.pending_responses.split_off(&(close_after + 1));
+        }
+        match self.close_after {
+            Some(close_after) if seq > close_after => return Ok(()),
+            // Let the client know that we close the connection after this response.
+            Some(close_after) if seq == close_after => response.set_keep_alive(false),
+            _ => (),
+        }
+
+        // Responses are sent in the order in which their requests were received.
+        self.pending_responses.insert(seq, response);
+        while let Some(response) = self.pending_responses.remove(&self.next_response_seq) {
+            self.connection.enqueue_response(response);
+            self.next_response_seq = self
+                .next_response_seq
+                .checked_add(1)
+                .ok_or(ServerError::Overflow)?;
+        }
         Ok(())
     }
 
+    // Answers a request which could not be read. The connection is closed after this
+    // response because the rest of the stream cannot be parsed reliably.
+    fn enqueue_error_response(&mut self, response: Response) -> Result<()> {
+        // The connection is already closed after an earlier request, which means
+        // the error pertains to a request that will never be answered.
+        if self.close_after.is_some() {
+            return Ok(());
+        }
+        let seq = self.next_request_seq;
+        self.next_request_seq = seq.checked_add(1).ok_or(ServerError::Overflow)?;
+        self.close_after = Some(seq);
+        self.in_flight_response_count = self
+            .in_flight_response_count
+            .checked_add(1)
+            .ok_or(ServerError::Overflow)?;
+        self.enqueue_response(seq, response)
+    }
+
+    // Returns `true` if the connection is waiting for the client to send a new request.
+    fn is_idle(&self) -> bool {
+        self.state == ClientConnectionState::AwaitingIncoming
+            && !self.connection.pending_write()
+            && self.in_flight_response_count == 0
+    }
+
     // Returns `true` if the connection is closed and safe to drop.
     fn is_done(&self) -> bool {
         self.state == ClientConnectionState::Closed
@@ -254,6 +351,11 @@ pub struct HttpServer {
     /// We use the file descriptor of the stream as the key for mapping
     /// connections because the 1-to-1 relation is guaranteed by the OS.
     connections: HashMap<RawFd, ClientConnection<UnixStream>>,
+    /// Timer which wakes the server up when a connection has been idle for too long.
+    idle_timer: TimerFd,
+    /// How long a connection can wait for a new request before the server closes it.
+    /// Idle connections are kept open indefinitely when this is `None`.
+    idle_timeout: Option<Duration>,
 }
 
 impl HttpServer {
@@ -262,22 +364,34 @@ impl HttpServer {
     /// Returns the newly formed `HttpServer`.
     ///
     /// # Errors
-    /// Returns an `IOError` when binding or `epoll::create` fails.
+    /// Returns an `IOError` when binding, `epoll::create` or `timerfd_create` fails.
     pub fn new<P: AsRef<Path>>(path_to_socket: P) -> Result<Self> {
         let socket = UnixListener::bind(path_to_socket).map_err(ServerError::IOError)?;
         let epoll = epoll::Epoll::new().map_err(ServerError::IOError)?;
+        let idle_timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(ServerError::IOError)?;
         Ok(Self {
             socket,
             epoll,
             connections: HashMap::new(),
+            idle_timer,
+            idle_timeout: None,
         })
     }
 
     /// Starts the HTTP Server.
     pub fn start_server(&mut self) -> Result<()> {
-        // Add the socket on which we listen for new connections to the
-        // `epoll` structure.
-        Self::epoll_add(&self.epoll, self.socket.as_raw_fd())
+        // Add the socket on which we listen for new connections and the
+        // idle connections timer to the `epoll` structure.
+        Self::epoll_add(&self.epoll, self.socket.as_raw_fd())?;
+        Self::epoll_add(&self.epoll, self.idle_timer.as_raw_fd())
+    }
+
+    /// Sets how long a connection can wait for a new request before the server
+    /// closes it. By default, idle connections are never closed.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+        self.arm_idle_timer();
     }
 
     /// This function is responsible for the data exchange with the clients and should
@@ -338,6 +452,10 @@ impl HttpServer {
                     Err(error) => return Err(error),
                     Ok(()) => {}
                 };
+            } else if e.fd() == self.idle_timer.as_raw_fd() {
+                // Clear the timer event. The connections which have been idle for
+                // too long are closed below.
+                self.idle_timer.read();
             } else {
                 // We have a notification on one of our open connections.
                 let fd = e.fd();
@@ -346,13 +464,7 @@ impl HttpServer {
                     // We have bytes to read from this connection.
                     // If our `read` yields `Request` objects, we wrap them with an ID before
                     // handing them to the user.
-                    parsed_requests.append(
-                        &mut client_connection
-                            .read()?
-                            .into_iter()
-                            .map(|request| ServerRequest::new(request, e.data()))
-                            .collect(),
-                    );
+                    parsed_requests.append(&mut client_connection.read(e.data())?);
                     // If the connection was incoming before we read and we now have to write
                     // either an error message or an `expect` response, we change its `epoll`
                     // event set to notify us when the stream is ready for writing.
@@ -365,20 +477,59 @@ impl HttpServer {
                     // If the connection was outgoing before we tried to write the responses
                     // and we don't have any more responses to write, we change the `epoll`
                     // event set to notify us when we have bytes to read from the stream.
-                    if client_connection.state == ClientConnectionState::AwaitingIncoming {
+                    if client_connection.state != ClientConnectionState::AwaitingOutgoing {
                         Self::epoll_mod(&self.epoll, fd, epoll::EventSet::IN)?;
                     }
                 }
             }
         }
 
+        // Close the connections which have been idle for too long.
+        if let Some(idle_timeout) = self.idle_timeout {
+            let now = Instant::now();
+            for client_connection in self.connections.values_mut() {
+                if client_connection.is_idle()
+                    && now.duration_since(client_connection.last_activity) >= idle_timeout
+                {
+                    client_connection.state = ClientConnectionState::Closed;
+                }
+            }
+        }
+
         // Remove dead connections.
         self.connections
             .retain(|_, client_connection| !client_connection.is_done());
+        self.arm_idle_timer();
 
         Ok(parsed_requests)
     }
 
+    /// Arms the idle connections timer to expire when the first idle connection times out,
+    /// or disarms it if there is no idle connection.
+    fn arm_idle_timer(&mut self) {
+        let timer_state = match self.idle_timeout {
+            Some(idle_timeout) => {
+                let now = Instant::now();
+                self.connections
+                    .values()
+                    .filter(|client_connection| client_connection.is_idle())
+                    .map(|client_connection| client_connection.last_activity + idle_timeout)
+                    .min()
+                    // A zero duration would disarm the timer, so wait for at least 1 ms.
+                    .map(|deadline| {
+                        TimerState::Oneshot(max(
+                            deadline.saturating_duration_since(now),
+                            Duration::from_millis(1),
+                        ))
+                    })
+                    .unwrap_or(TimerState::Disarmed)
+            }
+            None => TimerState::Disarmed,
+        };
+        self.idle_timer
+            .set_state(timer_state, SetTimeFlags::Default);
+    }
+
     /// The file descriptor of the `epoll` structure can enable the server to become
     /// a non-blocking structure in an application.
     ///
@@ -458,13 +609,17 @@ impl HttpServer {
     /// `Underflow` is returned when `enqueue_response` fails.
     pub fn respond(&mut self, response: ServerResponse) -> Result<()> {
         if let Some(client_connection) = self.connections.get_mut(&(response.id as i32)) {
-            // If the connection was incoming before we enqueue the response, we change its
-            // `epoll` event set to notify us when the stream is ready for writing.
-            if let ClientConnectionState::AwaitingIncoming = client_connection.state {
+            client_connection.enqueue_response(response.seq, response.response)?;
+            // If the connection was incoming before we enqueue the response and now has
+            // bytes to write, we change its `epoll` event set to notify us when the stream
+            // is ready for writing. The response might also wait for the responses to
+            // earlier requests.
+            if client_connection.state == ClientConnectionState::AwaitingIncoming
+                && client_connection.connection.pending_write()
+            {
                 client_connection.state = ClientConnectionState::AwaitingOutgoing;
                 Self::epoll_mod(&self.epoll, response.id as RawFd, epoll::EventSet::OUT)?;
             }
-            client_connection.enqueue_response(response.response)?;
         }
         Ok(())
     }
@@ -744,23 +899,175 @@ mod tests {
 
         assert!(server.requests().unwrap().is_empty());
         assert!(server.requests().unwrap().is_empty());
-        let mut buf: [u8; 255] = [0; 255];
+        let mut buf: [u8; 250] = [0; 250];
         assert!(socket.read(&mut buf[..]).unwrap() > 0);
         let error_message = b"HTTP/1.1 400 \r\n\
                               Server: Firecracker API\r\n\
-                              Connection: keep-alive\r\n\
+                              Connection: close\r\n\
                               Content-Type: application/json\r\n\
                               Content-Length: 136\r\n\r\n{ \"error\": \"Invalid header. \
                               Reason: Invalid value. Key:Content-Length; Value: alpha\nAll previous unanswered requests will be dropped.\" }";
         assert_eq!(&buf[..], &error_message[..]);
 
+        // The rest of the stream cannot be parsed, so the server closes the connection.
+        assert!(server.connections.is_empty());
+        assert_eq!(socket.read(&mut buf[..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_wait_pipelined_requests() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        // The requests following the one asking to close the connection are dropped.
         socket
             .write_all(
-                b"PATCH /machine-config HTTP/1.1\r\n\
-                         Content-Length: alpha\r\n\
-                         Content-Type: application/json\r\n\r\nwhatever body",
+                b"GET /first HTTP/1.1\r\n\r\n\
+                  GET /second HTTP/1.1\r\n\r\n\
+                  GET /third HTTP/1.1\r\nConnection: close\r\n\r\n\
+                  GET /dropped HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 3);
+
+        let handler = |request: &Request| {
+            let mut response = Response::new(Version::Http11, StatusCode::OK);
+            response.set_body(Body::new(request.uri().get_abs_path().to_string()));
+            response
+        };
+        // Answer the requests in the reverse order.
+        while let Some(server_request) = req_vec.pop() {
+            server.respond(server_request.process(handler)).unwrap();
+        }
+        // Each response is written on a separate `EPOLLOUT` notification.
+        for _ in 0..3 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert!(server.connections.is_empty());
+
+        // The responses are sent in the order of the requests and the last one
+        // announces that the connection is closed.
+        let mut expected_responses = vec![];
+        for path in &["/first", "/second", "/third"] {
+            let mut response = Response::new(Version::Http11, StatusCode::OK);
+            response.set_body(Body::new(path.to_string()));
+            response.set_keep_alive(*path != "/third");
+            response.write_all(&mut expected_responses).unwrap();
+        }
+        let mut responses = vec![];
+        socket.read_to_end(&mut responses).unwrap();
+        assert_eq!(responses, expected_responses);
+    }
+
+    #[test]
+    fn test_wait_connection_close() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        // HTTP/1.0 connections are closed by default.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http10, StatusCode::NoContent)),
             )
             .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.connections.is_empty());
+
+        let mut expected_response = vec![];
+        let mut response = Response::new(Version::Http10, StatusCode::NoContent);
+        response.set_keep_alive(false);
+        response.write_all(&mut expected_response).unwrap();
+        let mut buf = vec![];
+        socket.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, expected_response);
+
+        // A response can also close the connection, dropping the responses to the
+        // requests that follow it.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 2);
+        server
+            .respond(
+                req_vec
+                    .pop()
+                    .unwrap()
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        server
+            .respond(req_vec.pop().unwrap().process(|_request| {
+                let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
+                response.set_keep_alive(false);
+                response
+            }))
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.connections.is_empty());
+
+        let mut expected_response = vec![];
+        let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
+        response.set_keep_alive(false);
+        response.write_all(&mut expected_response).unwrap();
+        let mut buf = vec![];
+        socket.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, expected_response);
+    }
+
+    #[test]
+    fn test_wait_idle_timeout() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+        server.set_idle_timeout(Some(Duration::from_millis(50)));
+
+        let mut idle_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut busy_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        busy_socket.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+
+        // Wait for the idle connection to time out. The connection with a request
+        // in flight is kept open.
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.connections.len(), 1);
+        let mut buf = [0u8; 1024];
+        assert_eq!(idle_socket.read(&mut buf[..]).unwrap(), 0);
+
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(busy_socket.read(&mut buf[..]).unwrap() > 0);
+
+        // The connection times out once its response has been sent.
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.connections.is_empty());
+        assert_eq!(busy_socket.read(&mut buf[..]).unwrap(), 0);
     }
 
     #[test]
@@ -0,0 +1,549 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal DHCPv4 server, which hands a single lease to the guest behind a network
+//! interface.
+//!
+//! Like the MMDS network stack, the server intercepts the frames the guest sends to it, which
+//! never reach the host, and produces the replies the device model injects back into the guest.
+
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+use std::result::Result;
+
+use dumbo::pdu::dhcp::{
+    DhcpMessage, Error as DhcpMessageError, CLIENT_PORT, DHCPACK, DHCPDECLINE, DHCPDISCOVER,
+    DHCPINFORM, DHCPNAK, DHCPOFFER, DHCPRELEASE, DHCPREQUEST, FLAG_BROADCAST, OPTION_DNS_SERVERS,
+    OPTION_LEASE_TIME, OPTION_MESSAGE_TYPE, OPTION_REQUESTED_IP, OPTION_ROUTER, OPTION_SERVER_ID,
+    OPTION_SUBNET_MASK, OP_BOOTREQUEST, SERVER_PORT,
+};
+use dumbo::pdu::ethernet::{Error as EthernetFrameError, EthernetFrame, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_UDP};
+use dumbo::pdu::udp::{test_speculative_dst_port, UdpDatagram, UDP_HEADER_SIZE};
+use logger::{IncMetric, METRICS};
+use utils::net::mac::MacAddr;
+
+use crate::ns::MmdsNetworkStack;
+
+const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:02";
+const BROADCAST_MAC_ADDR: &str = "ff:ff:ff:ff:ff:ff";
+
+/// The configuration the DHCP server hands to the guest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DhcpLease {
+    /// The address of the guest.
+    pub ipv4_addr: Ipv4Addr,
+    /// The length of the prefix of the guest subnet.
+    pub prefix_len: u8,
+    /// The default gateway of the guest, if any.
+    pub gateway: Option<Ipv4Addr>,
+    /// The DNS servers of the guest.
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The time the guest is allowed to use the configuration for before renewing it, in
+    /// seconds.
+    pub lease_time_s: u32,
+}
+
+impl DhcpLease {
+    /// Returns the subnet mask of the guest.
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(
+            u32::max_value()
+                .checked_shl(32 - u32::from(self.prefix_len))
+                .unwrap_or(0),
+        )
+    }
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteReplyError {
+    Dhcp(DhcpMessageError),
+    Ethernet(EthernetFrameError),
+    IPv4Packet(IPv4PacketError),
+}
+
+// A reply waiting to be sent to the guest.
+struct PendingReply {
+    message_type: u8,
+    // The message the guest sent, which the reply copies some fields from.
+    request: Vec<u8>,
+}
+
+/// Answers the DHCP messages of the guest with the configured lease.
+pub struct DhcpServer {
+    // The Ethernet MAC address the replies are sent from.
+    mac_addr: MacAddr,
+    pub(crate) lease: DhcpLease,
+    pending_reply: Option<PendingReply>,
+}
+
+impl DhcpServer {
+    /// Creates a server handing out `lease`.
+    pub fn new(lease: DhcpLease) -> Self {
+        DhcpServer {
+            // The unwrap is safe if parse_str() is implemented properly.
+            mac_addr: MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+            lease,
+            pending_reply: None,
+        }
+    }
+
+    /// Provides the configuration handed to the guest.
+    pub fn lease(&self) -> &DhcpLease {
+        &self.lease
+    }
+
+    // The address the server identifies itself with. Clients send their renewals there, so
+    // the gateway is used when there is one.
+    fn server_id(&self) -> Ipv4Addr {
+        self.lease
+            .gateway
+            .unwrap_or_else(MmdsNetworkStack::default_ipv4_addr)
+    }
+
+    /// This is the entry point into the DHCP server. The src slice should hold the contents of
+    /// an Ethernet frame (of that exact size, without the CRC). Returns whether the frame was
+    /// heading towards a DHCP server, in which case it is consumed.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        // The frame cannot possibly contain a message for a DHCP server.
+        if !test_speculative_dst_port(src, SERVER_PORT) {
+            return false;
+        }
+
+        let eth = match EthernetFrame::from_bytes(src) {
+            Ok(eth) if eth.ethertype() == ETHERTYPE_IPV4 => eth,
+            _ => return false,
+        };
+        // Short frames may be padded, so the payload is trimmed to the length of the packet.
+        let payload = eth.payload();
+        let total_len = IPv4Packet::from_bytes_unchecked(payload).total_len() as usize;
+        let ip = match IPv4Packet::from_bytes(&payload[..total_len.min(payload.len())], false) {
+            Ok(ip) if ip.protocol() == PROTOCOL_UDP => ip,
+            _ => return false,
+        };
+        let udp = match UdpDatagram::from_bytes(ip.payload(), None) {
+            Ok(udp) if udp.destination_port() == SERVER_PORT => udp,
+            _ => return false,
+        };
+
+        METRICS.dhcp.rx_count.inc();
+        let udp_len = udp.len() as usize;
+        if udp_len < UDP_HEADER_SIZE || udp_len > ip.payload().len() {
+            METRICS.dhcp.rx_invalid.inc();
+            return true;
+        }
+        self.handle_message(&udp.payload()[..udp_len - UDP_HEADER_SIZE]);
+
+        true
+    }
+
+    fn handle_message(&mut self, bytes: &[u8]) {
+        let message = match DhcpMessage::from_bytes(bytes) {
+            Ok(message) if message.op() == OP_BOOTREQUEST => message,
+            _ => {
+                METRICS.dhcp.rx_invalid.inc();
+                return;
+            }
+        };
+        let reply_type = match message.message_type() {
+            Some(DHCPDISCOVER) => DHCPOFFER,
+            Some(DHCPREQUEST) => {
+                // A request carrying a server identifier selects the offer of a server, which
+                // may not be this one.
+                if let Some(server_id) = message.ipv4_option(OPTION_SERVER_ID) {
+                    if server_id != self.server_id() {
+                        return;
+                    }
+                }
+                // Clients either request the offered address, or ask to renew the one they use.
+                let requested_ip = message
+                    .ipv4_option(OPTION_REQUESTED_IP)
+                    .unwrap_or_else(|| message.ciaddr());
+                if requested_ip == self.lease.ipv4_addr {
+                    DHCPACK
+                } else {
+                    METRICS.dhcp.nak_count.inc();
+                    DHCPNAK
+                }
+            }
+            Some(DHCPINFORM) => DHCPACK,
+            // There is no pool of addresses to return an address to.
+            Some(DHCPDECLINE) | Some(DHCPRELEASE) => return,
+            _ => {
+                METRICS.dhcp.rx_invalid.inc();
+                return;
+            }
+        };
+
+        // A single reply is pending at a time, the guest retransmits its messages anyway.
+        self.pending_reply = Some(PendingReply {
+            message_type: reply_type,
+            request: bytes.to_vec(),
+        });
+    }
+
+    // Allows the DHCP server to write a frame to the specified buffer. Will return:
+    // - None, if the server has no frame to send at this point. The buffer can be used for
+    // something else by the device model.
+    // - Some(len), if a frame of the given length has been written to the specified buffer.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let reply = self.pending_reply.take()?;
+        match self.write_reply(buf, &reply) {
+            Ok(len) => {
+                METRICS.dhcp.tx_count.inc();
+                Some(len)
+            }
+            Err(_) => {
+                METRICS.dhcp.tx_errors.inc();
+                None
+            }
+        }
+    }
+
+    fn write_reply(
+        &self,
+        buf: &mut [u8],
+        reply: &PendingReply,
+    ) -> Result<NonZeroUsize, WriteReplyError> {
+        let request = DhcpMessage::from_bytes_unchecked(reply.request.as_slice());
+        let server_id = self.server_id();
+        let lease_time = self.lease.lease_time_s.to_be_bytes();
+        let netmask = self.lease.netmask().octets();
+        let gateway = self.lease.gateway.map(|gateway| gateway.octets());
+        let dns_servers: Vec<u8> = self
+            .lease
+            .dns_servers
+            .iter()
+            .flat_map(|dns_server| dns_server.octets().to_vec())
+            .collect();
+
+        let message_type = [reply.message_type];
+        let server_id_octets = server_id.octets();
+
+        let mut options: Vec<(u8, &[u8])> = vec![
+            (OPTION_MESSAGE_TYPE, &message_type[..]),
+            (OPTION_SERVER_ID, &server_id_octets[..]),
+        ];
+        let (ciaddr, yiaddr) = if reply.message_type == DHCPNAK {
+            (Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED)
+        } else {
+            // Informed clients already have an address, they only get the other parameters.
+            let yiaddr = if request.message_type() == Some(DHCPINFORM) {
+                Ipv4Addr::UNSPECIFIED
+            } else {
+                options.push((OPTION_LEASE_TIME, &lease_time[..]));
+                self.lease.ipv4_addr
+            };
+            options.push((OPTION_SUBNET_MASK, &netmask[..]));
+            if let Some(gateway) = gateway.as_ref() {
+                options.push((OPTION_ROUTER, &gateway[..]));
+            }
+            if !dns_servers.is_empty() {
+                options.push((OPTION_DNS_SERVERS, &dns_servers[..]));
+            }
+            (request.ciaddr(), yiaddr)
+        };
+
+        // Replies are broadcast, unless the client already uses its address and didn't ask
+        // for broadcasts.
+        let (dst_mac, dst_addr) =
+            if ciaddr != Ipv4Addr::UNSPECIFIED && request.flags() & FLAG_BROADCAST == 0 {
+                (request.chaddr(), ciaddr)
+            } else {
+                // The unwrap is safe if parse_str() is implemented properly.
+                (
+                    MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+                    Ipv4Addr::BROADCAST,
+                )
+            };
+
+        let mut eth_unsized =
+            EthernetFrame::write_incomplete(buf, dst_mac, self.mac_addr, ETHERTYPE_IPV4)
+                .map_err(WriteReplyError::Ethernet)?;
+        let mut ip_unsized = IPv4Packet::write_header(
+            eth_unsized.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            server_id,
+            dst_addr,
+        )
+        .map_err(WriteReplyError::IPv4Packet)?;
+
+        let ip_payload = ip_unsized.inner_mut().payload_mut();
+        if ip_payload.len() < UDP_HEADER_SIZE {
+            return Err(WriteReplyError::Dhcp(DhcpMessageError::SliceTooShort));
+        }
+        let message_len = DhcpMessage::write_reply(
+            &mut ip_payload[UDP_HEADER_SIZE..],
+            &request,
+            ciaddr,
+            yiaddr,
+            Ipv4Addr::UNSPECIFIED,
+            &options,
+        )
+        .map_err(WriteReplyError::Dhcp)?
+        .len();
+
+        let udp_len = UDP_HEADER_SIZE + message_len;
+        let mut udp = UdpDatagram::from_bytes_unchecked(&mut ip_payload[..udp_len]);
+        udp.set_source_port(SERVER_PORT)
+            .set_destination_port(CLIENT_PORT)
+            .set_len(udp_len as u16)
+            .set_checksum(0);
+        let checksum = udp.compute_checksum(server_id, dst_addr);
+        udp.set_checksum(checksum);
+
+        let ip_len = ip_unsized.with_payload_len_unchecked(udp_len, true).len();
+        // The unwrap() is safe because ip_len > 0.
+        Ok(NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(ip_len).len()).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dumbo::pdu::dhcp::OP_BOOTREPLY;
+
+    const GUEST_MAC_STR: &str = "11:11:11:22:22:22";
+    const XID: u32 = 0x1234_5678;
+
+    fn lease() -> DhcpLease {
+        DhcpLease {
+            ipv4_addr: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 30,
+            gateway: Some(Ipv4Addr::new(172, 16, 0, 1)),
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)],
+            lease_time_s: 3600,
+        }
+    }
+
+    // Writes a frame holding a DHCP message from the guest to `buf`, and returns its length.
+    fn write_request(
+        buf: &mut [u8],
+        message_type: u8,
+        ciaddr: Ipv4Addr,
+        options: &[(u8, &[u8])],
+    ) -> usize {
+        let guest_mac = MacAddr::parse_str(GUEST_MAC_STR).unwrap();
+        let mut message = vec![0u8; 240];
+        message[0] = OP_BOOTREQUEST;
+        message[1] = 1;
+        message[2] = 6;
+        message[4..8].copy_from_slice(&XID.to_be_bytes());
+        message[12..16].copy_from_slice(&ciaddr.octets());
+        message[28..34].copy_from_slice(guest_mac.get_bytes());
+        message[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+        for (code, value) in options {
+            message.extend_from_slice(&[*code, value.len() as u8]);
+            message.extend_from_slice(value);
+        }
+        message.push(255);
+
+        let dst_addr = Ipv4Addr::BROADCAST;
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+            guest_mac,
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut ip = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            ciaddr,
+            dst_addr,
+        )
+        .unwrap();
+        let udp_len = UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), &message)
+            .unwrap()
+            .finalize(CLIENT_PORT, SERVER_PORT, Some((ciaddr, dst_addr)))
+            .len() as usize;
+        let ip_len = ip.with_payload_len_unchecked(udp_len, true).len();
+        eth.with_payload_len_unchecked(ip_len).len()
+    }
+
+    // Checks the frame in `buf` holds a valid DHCP reply with the given destination, and
+    // calls `check` on the reply.
+    fn check_reply<F>(buf: &[u8], dst_mac: MacAddr, dst_addr: Ipv4Addr, check: F)
+    where
+        F: Fn(&DhcpMessage<&[u8]>),
+    {
+        let server_id = Ipv4Addr::new(172, 16, 0, 1);
+        let eth = EthernetFrame::from_bytes(buf).unwrap();
+        assert_eq!(eth.dst_mac(), dst_mac);
+        assert_eq!(eth.ethertype(), ETHERTYPE_IPV4);
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), server_id);
+        assert_eq!(ip.destination_address(), dst_addr);
+        let udp = UdpDatagram::from_bytes(ip.payload(), Some((server_id, dst_addr))).unwrap();
+        assert_eq!(udp.source_port(), SERVER_PORT);
+        assert_eq!(udp.destination_port(), CLIENT_PORT);
+        let message = DhcpMessage::from_bytes(udp.payload()).unwrap();
+        assert_eq!(message.op(), OP_BOOTREPLY);
+        assert_eq!(message.xid(), XID);
+        assert_eq!(message.chaddr(), MacAddr::parse_str(GUEST_MAC_STR).unwrap());
+        assert_eq!(message.ipv4_option(OPTION_SERVER_ID), Some(server_id));
+        check(&message);
+    }
+
+    #[test]
+    fn test_netmask() {
+        let mut lease = lease();
+        assert_eq!(lease.netmask(), Ipv4Addr::new(255, 255, 255, 252));
+        lease.prefix_len = 0;
+        assert_eq!(lease.netmask(), Ipv4Addr::UNSPECIFIED);
+        lease.prefix_len = 32;
+        assert_eq!(lease.netmask(), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn test_detour_frame() {
+        let mut server = DhcpServer::new(lease());
+        assert_eq!(server.lease(), &lease());
+        let mut buf = [0u8; 1024];
+
+        // Frames heading elsewhere are left alone.
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(!server.detour_frame(&buf[..20]));
+        EthernetFrame::from_bytes_unchecked(&mut buf[..len]).set_ethertype(0x86dd);
+        assert!(!server.detour_frame(&buf[..len]));
+        server.pending_reply = None;
+
+        // Messages which can't be handled are consumed, without any reply.
+        let len = write_request(&mut buf, DHCPRELEASE, lease().ipv4_addr, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        let len = write_request(&mut buf, 42, Ipv4Addr::UNSPECIFIED, &[]);
+        let invalid_count = METRICS.dhcp.rx_invalid.count();
+        assert!(server.detour_frame(&buf[..len]));
+        assert_eq!(METRICS.dhcp.rx_invalid.count(), invalid_count + 1);
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Requests selecting another server are ignored.
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_SERVER_ID, &[172, 16, 0, 3])],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // The reply doesn't fit the buffer.
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let tx_errors = METRICS.dhcp.tx_errors.count();
+        assert!(server.write_next_frame(&mut buf[..100]).is_none());
+        assert_eq!(METRICS.dhcp.tx_errors.count(), tx_errors + 1);
+    }
+
+    #[test]
+    fn test_replies() {
+        let mut server = DhcpServer::new(lease());
+        let guest_mac = MacAddr::parse_str(GUEST_MAC_STR).unwrap();
+        let broadcast_mac = MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap();
+        let guest_ip = lease().ipv4_addr;
+        let mut buf = [0u8; 1024];
+
+        // Discovery.
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |reply| {
+            assert_eq!(reply.message_type(), Some(DHCPOFFER));
+            assert_eq!(reply.yiaddr(), guest_ip);
+            assert_eq!(
+                reply.option(OPTION_LEASE_TIME),
+                Some(&[0, 0, 0x0e, 0x10][..])
+            );
+            assert_eq!(
+                reply.ipv4_option(OPTION_SUBNET_MASK),
+                Some(Ipv4Addr::new(255, 255, 255, 252))
+            );
+            assert_eq!(
+                reply.ipv4_option(OPTION_ROUTER),
+                Some(Ipv4Addr::new(172, 16, 0, 1))
+            );
+            assert_eq!(
+                reply.option(OPTION_DNS_SERVERS),
+                Some(&[8, 8, 8, 8, 1, 1, 1, 1][..])
+            );
+        });
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Selection of the offered address.
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                (OPTION_SERVER_ID, &[172, 16, 0, 1]),
+                (OPTION_REQUESTED_IP, &guest_ip.octets()),
+            ],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |reply| {
+            assert_eq!(reply.message_type(), Some(DHCPACK));
+            assert_eq!(reply.yiaddr(), guest_ip);
+            assert!(reply.option(OPTION_LEASE_TIME).is_some());
+        });
+
+        // Renewals are unicast.
+        let len = write_request(&mut buf, DHCPREQUEST, guest_ip, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], guest_mac, guest_ip, |reply| {
+            assert_eq!(reply.message_type(), Some(DHCPACK));
+            assert_eq!(reply.ciaddr(), guest_ip);
+            assert_eq!(reply.yiaddr(), guest_ip);
+        });
+
+        // Requests for another address are refused.
+        let nak_count = METRICS.dhcp.nak_count.count();
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_REQUESTED_IP, &[172, 16, 0, 3])],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |reply| {
+            assert_eq!(reply.message_type(), Some(DHCPNAK));
+            assert_eq!(reply.yiaddr(), Ipv4Addr::UNSPECIFIED);
+            assert!(reply.option(OPTION_LEASE_TIME).is_none());
+            assert!(reply.option(OPTION_SUBNET_MASK).is_none());
+        });
+        assert_eq!(METRICS.dhcp.nak_count.count(), nak_count + 1);
+
+        // Informed clients only get the other parameters.
+        let len = write_request(&mut buf, DHCPINFORM, guest_ip, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], guest_mac, guest_ip, |reply| {
+            assert_eq!(reply.message_type(), Some(DHCPACK));
+            assert_eq!(reply.yiaddr(), Ipv4Addr::UNSPECIFIED);
+            assert!(reply.option(OPTION_LEASE_TIME).is_none());
+            assert!(reply.option(OPTION_DNS_SERVERS).is_some());
+        });
+
+        // Without a gateway, the server identifies itself with the default MMDS address.
+        let mut lease = lease();
+        lease.gateway = None;
+        lease.dns_servers.clear();
+        let mut server = DhcpServer::new(lease);
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), MmdsNetworkStack::default_ipv4_addr());
+        let message = DhcpMessage::from_bytes(&ip.payload()[UDP_HEADER_SIZE..]).unwrap();
+        assert!(message.option(OPTION_ROUTER).is_none());
+        assert!(message.option(OPTION_DNS_SERVERS).is_none());
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod data_store;
+pub mod dhcp;
 pub mod ns;
 pub mod persist;
 
@@ -5,7 +5,7 @@
 #![allow(missing_docs)]
 
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 use std::result::Result;
 
@@ -13,14 +13,19 @@ use dumbo::pdu::arp::{
     test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN,
 };
 use dumbo::pdu::ethernet::{
-    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
 };
 use dumbo::pdu::ipv4::{
     test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP,
 };
+use dumbo::pdu::ipv6::{self, Error as IPv6PacketError, IPv6Packet, IPV6_VERSION, PROTOCOL_ICMPV6};
+use dumbo::pdu::ndp::{
+    self, Error as NdpMessageError, NeighborMessage, FLAG_OVERRIDE, FLAG_SOLICITED,
+    OPTION_SOURCE_LL_ADDR,
+};
 use dumbo::pdu::tcp::Error as TcpSegmentError;
 use dumbo::pdu::Incomplete;
-use dumbo::tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
+use dumbo::tcp::handler::{self, RecvError, RecvEvent, TcpIPHandler, WriteEvent};
 use dumbo::tcp::NextSegmentStatus;
 use logger::{IncMetric, METRICS};
 use utils::net::mac::MacAddr;
@@ -39,9 +44,18 @@ enum WriteArpFrameError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNdpFrameError {
+    NoPendingNdpReply,
+    Ndp(NdpMessageError),
+    Ethernet(EthernetFrameError),
+    IPv6Packet(IPv6PacketError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     Ethernet(EthernetFrameError),
     TcpSegment(TcpSegmentError),
 }
@@ -50,6 +64,7 @@ impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::IPv6Packet(inner) => WritePacketError::IPv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
@@ -66,8 +81,13 @@ pub struct MmdsNetworkStack {
     // It is the Ipv4Addr of the network interface for which the MmdsNetworkStack
     // routes the packets.
     pending_arp_reply_dest: Option<Ipv4Addr>,
+    // MMDS server IPv6 address. The MMDS is only reachable over IPv6 when there is one.
+    pub(crate) ipv6_addr: Option<Ipv6Addr>,
+    // Neighbor Advertisement destination IPv6 address. This is the address of the solicitor, or
+    // the all-nodes multicast address when the solicitor has no address yet.
+    pending_ndp_reply_dest: Option<Ipv6Addr>,
     // This handles MMDS<->guest interaction at the TCP level.
-    pub(crate) tcp_handler: TcpIPv4Handler,
+    pub(crate) tcp_handler: TcpIPHandler,
 }
 
 impl MmdsNetworkStack {
@@ -83,7 +103,9 @@ impl MmdsNetworkStack {
             mac_addr,
             ipv4_addr,
             pending_arp_reply_dest: None,
-            tcp_handler: TcpIPv4Handler::new(
+            ipv6_addr: None,
+            pending_ndp_reply_dest: None,
+            tcp_handler: TcpIPHandler::new(
                 ipv4_addr,
                 tcp_port,
                 max_connections,
@@ -116,12 +138,31 @@ impl MmdsNetworkStack {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
 
+    pub fn set_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        self.ipv6_addr = ipv6_addr;
+        self.pending_ndp_reply_dest = None;
+        self.tcp_handler.set_local_ipv6_addr(ipv6_addr);
+    }
+
+    // Checks whether the frame may contain an IPv6 packet heading to the MMDS address, or to its
+    // solicited-node multicast address (used by neighbor solicitations).
+    fn test_speculative_ipv6(&self, src: &[u8]) -> bool {
+        match self.ipv6_addr {
+            Some(addr) => {
+                ipv6::test_speculative_dst_addr(src, addr)
+                    || ipv6::test_speculative_dst_addr(src, ndp::solicited_node_addr(addr))
+            }
+            None => false,
+        }
+    }
+
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
-        // The frame cannot possibly contain an ARP request or IPv4 packet for the MMDS.
+        // The frame cannot possibly contain an ARP request, or an IPv4/IPv6 packet for the MMDS.
         if !test_speculative_tpa(src, self.ipv4_addr)
             && !test_speculative_dst_addr(src, self.ipv4_addr)
+            && !self.test_speculative_ipv6(src)
         {
             return false;
         }
@@ -130,6 +171,7 @@ impl MmdsNetworkStack {
             match eth.ethertype() {
                 ETHERTYPE_ARP => return self.detour_arp(eth),
                 ETHERTYPE_IPV4 => return self.detour_ipv4(eth),
+                ETHERTYPE_IPV6 => return self.detour_ipv6(eth),
                 _ => (),
             };
         } else {
@@ -160,30 +202,61 @@ impl MmdsNetworkStack {
                 // Note-2: For every routed packet we will have a single source MAC address, because
                 // each MmdsNetworkStack routes packets for only one network device.
                 self.remote_mac_addr = eth.src_mac();
-                match self
-                    .tcp_handler
-                    .receive_packet(&ip, super::convert_to_response)
-                {
-                    Ok(event) => {
-                        METRICS.mmds.rx_count.inc();
-                        match event {
-                            RecvEvent::NewConnectionSuccessful => {
-                                METRICS.mmds.connections_created.inc()
-                            }
-                            RecvEvent::NewConnectionReplacing => {
-                                METRICS.mmds.connections_created.inc();
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            RecvEvent::EndpointDone => {
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            _ => (),
-                        }
+                Self::record_tcp_recv(
+                    self.tcp_handler
+                        .receive_packet(&ip, super::convert_to_response),
+                );
+            } else {
+                // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
+                METRICS.mmds.rx_accepted_unusual.inc();
+            }
+            return true;
+        }
+
+        false
+    }
+
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
+        let mmds_addr = match self.ipv6_addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if let Ok(ip) = IPv6Packet::from_bytes(eth.payload()) {
+            if ip.next_header() == PROTOCOL_ICMPV6 {
+                // Solicitations for other addresses may share the solicited-node multicast
+                // address of the MMDS, so they're left alone. As with TCP, we skip verifying the
+                // checksum.
+                if let Ok(ns) = NeighborMessage::solicitation_from_bytes(ip.payload(), None) {
+                    // Neighbor Discovery messages must not have crossed a router.
+                    if ns.target_address() == mmds_addr && ip.hop_limit() == ndp::HOP_LIMIT {
+                        self.remote_mac_addr = ns
+                            .ll_addr_option(OPTION_SOURCE_LL_ADDR)
+                            .unwrap_or_else(|| eth.src_mac());
+                        self.pending_ndp_reply_dest =
+                            Some(if ip.source_address().is_unspecified() {
+                                Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1)
+                            } else {
+                                ip.source_address()
+                            });
+                        return true;
                     }
-                    Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+                    return false;
                 }
+            }
+
+            if ip.destination_address() != mmds_addr {
+                return false;
+            }
+
+            if ip.next_header() == PROTOCOL_TCP {
+                self.remote_mac_addr = eth.src_mac();
+                Self::record_tcp_recv(
+                    self.tcp_handler
+                        .receive_ipv6_packet(&ip, super::convert_to_response),
+                );
             } else {
-                // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
+                // A non-TCP, non-NDP IPv6 packet heading towards the MMDS; we consider it unusual.
                 METRICS.mmds.rx_accepted_unusual.inc();
             }
             return true;
@@ -192,12 +265,32 @@ impl MmdsNetworkStack {
         false
     }
 
+    fn record_tcp_recv(result: Result<RecvEvent, RecvError>) {
+        match result {
+            Ok(event) => {
+                METRICS.mmds.rx_count.inc();
+                match event {
+                    RecvEvent::NewConnectionSuccessful => METRICS.mmds.connections_created.inc(),
+                    RecvEvent::NewConnectionReplacing => {
+                        METRICS.mmds.connections_created.inc();
+                        METRICS.mmds.connections_destroyed.inc();
+                    }
+                    RecvEvent::EndpointDone => {
+                        METRICS.mmds.connections_destroyed.inc();
+                    }
+                    _ => (),
+                }
+            }
+            Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+        }
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
     // - Some(len), if a frame of the given length has been written to the specified buffer.
     pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
-        // We try to send ARP replies first.
+        // We try to send ARP and NDP replies first.
         if self.pending_arp_reply_dest.is_some() {
             return match self.write_arp_reply(buf) {
                 Ok(something) => {
@@ -210,6 +303,18 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if self.pending_ndp_reply_dest.is_some() {
+            return match self.write_ndp_reply(buf) {
+                Ok(something) => {
+                    METRICS.mmds.tx_count.inc();
+                    self.pending_ndp_reply_dest = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
             let call_write = match self.tcp_handler.next_segment_status() {
                 NextSegmentStatus::Available => true,
@@ -270,6 +375,54 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn write_ndp_reply(&self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WriteNdpFrameError> {
+        let (ndp_reply_dest, mmds_addr) = match (self.pending_ndp_reply_dest, self.ipv6_addr) {
+            (Some(dest), Some(addr)) => (dest, addr),
+            _ => return Err(WriteNdpFrameError::NoPendingNdpReply),
+        };
+
+        // Unsolicited advertisements go to the all-nodes multicast address, and must not have
+        // the solicited flag set.
+        let (dst_mac, flags) = if ndp_reply_dest.is_multicast() {
+            (ndp::multicast_mac_addr(ndp_reply_dest), FLAG_OVERRIDE)
+        } else {
+            (self.remote_mac_addr, FLAG_SOLICITED | FLAG_OVERRIDE)
+        };
+
+        let mut eth_unsized =
+            EthernetFrame::write_incomplete(buf, dst_mac, self.mac_addr, ETHERTYPE_IPV6)
+                .map_err(WriteNdpFrameError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = IPv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                mmds_addr,
+                ndp_reply_dest,
+            )
+            .map_err(WriteNdpFrameError::IPv6Packet)?;
+            packet.inner_mut().set_hop_limit(ndp::HOP_LIMIT);
+
+            let message_len = NeighborMessage::write_advertisement(
+                packet.inner_mut().payload_mut(),
+                flags,
+                mmds_addr,
+                self.mac_addr,
+                mmds_addr,
+                ndp_reply_dest,
+            )
+            .map_err(WriteNdpFrameError::Ndp)?
+            .len();
+
+            packet.with_payload_len_unchecked(message_len).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = self
             .prepare_eth_unsized(buf, ETHERTYPE_IPV4)
@@ -284,6 +437,12 @@ impl MmdsNetworkStack {
         }
 
         if let Some(packet_len) = maybe_len {
+            // The version field sits at the same place in IPv4 and IPv6 headers.
+            let version = IPv6Packet::from_bytes_unchecked(eth_unsized.inner().payload()).version();
+            if version == IPV6_VERSION {
+                eth_unsized.inner_mut().set_ethertype(ETHERTYPE_IPV6);
+            }
+
             return Ok(Some(
                 // The unwrap() is safe because packet_len > 0.
                 NonZeroUsize::new(
@@ -309,6 +468,7 @@ mod tests {
     // We use LOCALHOST here because const new() is not stable yet, so just reuse this const, since
     // all we're interested in is having some address different from the MMDS one.
     const REMOTE_ADDR: Ipv4Addr = Ipv4Addr::LOCALHOST;
+    const REMOTE_IPV6_ADDR: Ipv6Addr = Ipv6Addr::LOCALHOST;
     const REMOTE_MAC_STR: &str = "11:11:11:22:22:22";
     const MMDS_PORT: u16 = 80;
     const REMOTE_PORT: u16 = 1235;
@@ -373,6 +533,92 @@ mod tests {
             eth_unsized.with_payload_len_unchecked(packet_len).len()
         }
 
+        fn write_neighbor_solicitation(
+            &self,
+            buf: &mut [u8],
+            src_addr: Ipv6Addr,
+            target: Ipv6Addr,
+        ) -> usize {
+            let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+            let dst_addr = ndp::solicited_node_addr(target);
+            let mut eth_unsized = EthernetFrame::write_incomplete(
+                buf,
+                ndp::multicast_mac_addr(dst_addr),
+                remote_mac,
+                ETHERTYPE_IPV6,
+            )
+            .unwrap();
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_ICMPV6,
+                    src_addr,
+                    dst_addr,
+                )
+                .unwrap();
+                packet.inner_mut().set_hop_limit(ndp::HOP_LIMIT);
+
+                let sha = if src_addr.is_unspecified() {
+                    None
+                } else {
+                    Some(remote_mac)
+                };
+                let message_len = NeighborMessage::write_solicitation(
+                    packet.inner_mut().payload_mut(),
+                    target,
+                    sha,
+                    src_addr,
+                    dst_addr,
+                )
+                .unwrap()
+                .len();
+
+                packet.with_payload_len_unchecked(message_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn write_incoming_ipv6_tcp_segment(
+            &self,
+            buf: &mut [u8],
+            addr: Ipv6Addr,
+            flags: TcpFlags,
+        ) -> usize {
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_TCP,
+                    REMOTE_IPV6_ADDR,
+                    addr,
+                )
+                .unwrap();
+
+                let segment_len = {
+                    let mut segment = TcpSegment::write_incomplete_segment::<[u8]>(
+                        packet.inner_mut().payload_mut(),
+                        SEQ_NUMBER,
+                        1234,
+                        flags,
+                        10000,
+                        None,
+                        0,
+                        None,
+                    )
+                    .unwrap()
+                    .finalize(REMOTE_PORT, MMDS_PORT, None);
+                    let checksum = segment.compute_checksum_ipv6(REMOTE_IPV6_ADDR, addr);
+                    segment.set_checksum(checksum);
+                    segment.len()
+                };
+
+                packet.with_payload_len_unchecked(segment_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
         fn next_frame_as_ipv4_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv4Packet<&'a [u8]> {
             let len = self.write_next_frame(buf).unwrap().get();
             let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
@@ -511,6 +757,108 @@ mod tests {
         assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
 
+    #[test]
+    fn test_ns_ipv6() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let mut buf = [0u8; 2000];
+
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let mmds_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let other_addr = Ipv6Addr::new(0xfd00, 0xec3, 0, 0, 0, 0, 0, 0x254);
+
+        // The MMDS doesn't answer over IPv6 without an IPv6 address.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), REMOTE_IPV6_ADDR, mmds_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+        let len = ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        ns.set_ipv6_addr(Some(mmds_addr));
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), Some(mmds_addr));
+
+        // A solicitation for another address which shares the solicited-node multicast address
+        // of the MMDS is left alone.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), REMOTE_IPV6_ADDR, other_addr);
+        assert_eq!(
+            ndp::solicited_node_addr(other_addr),
+            ndp::solicited_node_addr(mmds_addr)
+        );
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // A solicitation for the MMDS address gets a solicited advertisement in response.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), REMOTE_IPV6_ADDR, mmds_addr);
+        assert!(ns.detour_frame(&buf[..len]));
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            assert_eq!(eth.dst_mac(), remote_mac);
+            assert_eq!(eth.src_mac(), ns.mac_addr);
+
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.next_header(), PROTOCOL_ICMPV6);
+            assert_eq!(ip.hop_limit(), ndp::HOP_LIMIT);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let na = NeighborMessage::from_bytes(
+                ip.payload(),
+                Some((ip.source_address(), ip.destination_address())),
+            )
+            .unwrap();
+            assert_eq!(na.message_type(), ndp::TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(na.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+            assert_eq!(na.target_address(), mmds_addr);
+            assert_eq!(
+                na.ll_addr_option(ndp::OPTION_TARGET_LL_ADDR),
+                Some(ns.mac_addr)
+            );
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // A solicitation from an unspecified address (duplicate address detection) gets an
+        // unsolicited advertisement sent to all nodes.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), Ipv6Addr::UNSPECIFIED, mmds_addr);
+        assert!(ns.detour_frame(&buf[..len]));
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+            assert_eq!(eth.dst_mac(), ndp::multicast_mac_addr(all_nodes));
+
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.destination_address(), all_nodes);
+            let na = NeighborMessage::from_bytes(ip.payload(), Some((mmds_addr, all_nodes)));
+            assert_eq!(na.unwrap().flags(), FLAG_OVERRIDE);
+        }
+
+        // TCP segments heading to other addresses are not detoured.
+        let len = ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), other_addr, TcpFlags::SYN);
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        // A SYN heading to the MMDS gets a SYNACK over IPv6 in response.
+        let len = ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+        assert!(ns.detour_frame(&buf[..len]));
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.next_header(), PROTOCOL_TCP);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let s = TcpSegment::from_bytes(ip.payload(), None).unwrap();
+            assert_eq!(s.compute_checksum_ipv6(mmds_addr, REMOTE_IPV6_ADDR), 0);
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.source_port(), MMDS_PORT);
+            assert_eq!(s.destination_port(), REMOTE_PORT);
+            assert_eq!(s.ack_number(), SEQ_NUMBER.wrapping_add(1));
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+    }
+
     #[test]
     fn test_set_ipv4_addr() {
         let mut ns = MmdsNetworkStack::new_with_defaults(None);
@@ -1,15 +1,16 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Defines the structures needed for saving/restoring MmdsNetworkStack.
+//! Defines the structures needed for saving/restoring MmdsNetworkStack and DhcpServer.
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
+use super::dhcp::{DhcpLease, DhcpServer};
 use super::ns::MmdsNetworkStack;
 
 /// State of a MmdsNetworkStack.
@@ -20,6 +21,20 @@ pub struct MmdsNetworkStackState {
     tcp_port: u16,
     max_connections: usize,
     max_pending_resets: usize,
+    #[version(start = 2, ser_fn = "ipv6_addr_serialize")]
+    ipv6_addr: Option<[u8; 16]>,
+}
+
+impl MmdsNetworkStackState {
+    fn ipv6_addr_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.ipv6_addr.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement MMDS IPv6 addresses.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl Persist<'_> for MmdsNetworkStack {
@@ -37,6 +52,7 @@ impl Persist<'_> for MmdsNetworkStack {
             tcp_port: self.tcp_handler.local_port(),
             max_connections: self.tcp_handler.max_connections(),
             max_pending_resets: self.tcp_handler.max_pending_resets(),
+            ipv6_addr: self.ipv6_addr.map(|addr| addr.octets()),
         }
     }
 
@@ -44,13 +60,65 @@ impl Persist<'_> for MmdsNetworkStack {
         _: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        Ok(MmdsNetworkStack::new(
+        let mut ns = MmdsNetworkStack::new(
             MacAddr::from_bytes_unchecked(&state.mac_addr),
             Ipv4Addr::from(state.ipv4_addr),
             state.tcp_port,
             std::num::NonZeroUsize::new(state.max_connections).unwrap(),
             std::num::NonZeroUsize::new(state.max_pending_resets).unwrap(),
-        ))
+        );
+        ns.set_ipv6_addr(state.ipv6_addr.map(Ipv6Addr::from));
+        Ok(ns)
+    }
+}
+
+/// State of a DhcpServer.
+#[derive(Clone, Versionize)]
+pub struct DhcpServerState {
+    ipv4_addr: u32,
+    prefix_len: u8,
+    gateway: Option<u32>,
+    dns_servers: Vec<u32>,
+    lease_time_s: u32,
+}
+
+impl Persist<'_> for DhcpServer {
+    type State = DhcpServerState;
+    type ConstructorArgs = ();
+    type Error = ();
+
+    fn save(&self) -> Self::State {
+        let lease = self.lease();
+        DhcpServerState {
+            ipv4_addr: lease.ipv4_addr.into(),
+            prefix_len: lease.prefix_len,
+            gateway: lease.gateway.map(u32::from),
+            dns_servers: lease
+                .dns_servers
+                .iter()
+                .map(|addr| u32::from(*addr))
+                .collect(),
+            lease_time_s: lease.lease_time_s,
+        }
+    }
+
+    fn restore(
+        _: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        // A reply still pending when the snapshot was taken is lost, the guest retransmits
+        // its message.
+        Ok(DhcpServer::new(DhcpLease {
+            ipv4_addr: Ipv4Addr::from(state.ipv4_addr),
+            prefix_len: state.prefix_len,
+            gateway: state.gateway.map(Ipv4Addr::from),
+            dns_servers: state
+                .dns_servers
+                .iter()
+                .map(|addr| Ipv4Addr::from(*addr))
+                .collect(),
+            lease_time_s: state.lease_time_s,
+        }))
     }
 }
 
@@ -90,4 +158,68 @@ mod tests {
             ns.tcp_handler.max_pending_resets()
         );
     }
+
+    #[test]
+    fn test_ipv6_addr_persistence() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        ns.set_ipv6_addr(Some(ipv6_addr));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+
+        // IPv6 addresses can't be saved in the first snapshot version.
+        assert_eq!(
+            format!(
+                "{:?}",
+                ns.save()
+                    .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                    .err()
+                    .unwrap()
+            ),
+            "Semantic(\"Target version does not implement MMDS IPv6 addresses.\")"
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_ns.ipv6_addr, Some(ipv6_addr));
+        assert_eq!(restored_ns.tcp_handler.local_ipv6_addr(), Some(ipv6_addr));
+    }
+
+    #[test]
+    fn test_dhcp_server_persistence() {
+        let server = DhcpServer::new(DhcpLease {
+            ipv4_addr: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 24,
+            gateway: Some(Ipv4Addr::new(172, 16, 0, 1)),
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)],
+            lease_time_s: 3600,
+        });
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        server
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        let restored_server = DhcpServer::restore(
+            (),
+            &DhcpServerState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_server.lease(), server.lease());
+    }
 }
@@ -245,6 +245,7 @@ pub enum TokenType {
 }
 
 /// Enum that describes the type of token bucket update.
+#[derive(Clone)]
 pub enum BucketUpdate {
     /// No Update - same as before.
     None,
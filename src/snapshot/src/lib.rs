@@ -107,6 +107,16 @@ impl Snapshot {
     where
         T: Read,
         O: Versionize,
+    {
+        let data_version = Self::get_data_version(&mut reader, &version_map)?;
+
+        Ok(O::deserialize(&mut reader, &version_map, data_version).map_err(Error::Versionize)?)
+    }
+
+    /// Reads the header of an existing snapshot and returns its data version.
+    pub fn get_data_version<T>(mut reader: &mut T, version_map: &VersionMap) -> Result<u16, Error>
+    where
+        T: Read,
     {
         let format_version_map = Self::format_version_map();
         let magic_id =
@@ -125,8 +135,7 @@ impl Snapshot {
             return Err(Error::InvalidDataVersion(hdr.data_version));
         }
 
-        Ok(O::deserialize(&mut reader, &version_map, hdr.data_version)
-            .map_err(Error::Versionize)?)
+        Ok(hdr.data_version)
     }
 
     /// Attempts to load an existing snapshot and validate CRC.
@@ -512,6 +521,33 @@ mod tests {
         let _: Test1 = Snapshot::load(&mut snapshot_mem.as_slice(), 38, vm).unwrap();
     }
 
+    #[test]
+    fn test_get_data_version() {
+        let mut vm = VersionMap::new();
+        vm.new_version();
+        let state = Test1 {
+            field_x: 0,
+            field0: 0,
+            field1: 1,
+        };
+
+        let mut snapshot_mem = vec![0u8; 1024];
+        Snapshot::new(vm.clone(), 2)
+            .save(&mut snapshot_mem.as_mut_slice(), &state)
+            .unwrap();
+        assert_eq!(
+            Snapshot::get_data_version(&mut snapshot_mem.as_slice(), &vm).unwrap(),
+            2
+        );
+
+        // The data version must be known to the version map.
+        assert_eq!(
+            Snapshot::get_data_version(&mut snapshot_mem.as_slice(), &VersionMap::new())
+                .unwrap_err(),
+            Error::InvalidDataVersion(2)
+        );
+    }
+
     #[test]
     fn test_invalid_snapshot_size() {
         let vm = VersionMap::new();
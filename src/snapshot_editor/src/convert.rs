@@ -0,0 +1,122 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Converts a microVM state to another snapshot data version.
+
+use std::io;
+
+use versionize::{Versionize, VersionizeError};
+use vmm::persist::MicrovmState;
+use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
+
+use super::{Error, Result};
+
+/// Translates a Firecracker release version to its snapshot data version.
+pub fn target_data_version(fc_version: &str) -> Result<u16> {
+    FC_VERSION_TO_SNAP_VERSION
+        .get(fc_version)
+        .copied()
+        .ok_or_else(|| Error::InvalidTargetVersion(fc_version.to_string()))
+}
+
+// Serializes `component` in the `target_version` data version, only to find out whether it
+// can be represented in it.
+fn check<T: Versionize>(component: &T, target_version: u16) -> Option<String> {
+    match component.serialize(&mut io::sink(), &VERSION_MAP, target_version) {
+        Ok(()) => None,
+        Err(VersionizeError::Semantic(reason)) => Some(reason),
+        Err(err) => Some(format!("{:?}", err)),
+    }
+}
+
+/// Lists the parts of `state` which cannot be represented in the `target_version` snapshot
+/// data version. Each part only reports the first field it cannot represent.
+pub fn unrepresentable_fields(state: &MicrovmState, target_version: u16) -> Vec<String> {
+    let mut report = Vec::new();
+    let mut add = |part: String, result: Option<String>| {
+        if let Some(reason) = result {
+            report.push(format!("{}: {}", part, reason));
+        }
+    };
+
+    add("vm info".to_string(), check(&state.vm_info, target_version));
+    add(
+        "memory state".to_string(),
+        check(&state.memory_state, target_version),
+    );
+    add(
+        "vm state".to_string(),
+        check(&state.vm_state, target_version),
+    );
+    for (index, vcpu_state) in state.vcpu_states.iter().enumerate() {
+        add(format!("vcpu {}", index), check(vcpu_state, target_version));
+    }
+
+    // Some fields of the device states are only there for a given device, so each device is
+    // checked alone within otherwise empty device states.
+    let devices = &state.device_states;
+    let mut no_devices = devices.clone();
+    no_devices.block_devices.clear();
+    no_devices.net_devices.clear();
+    no_devices.vsock_device = None;
+    no_devices.balloon_device = None;
+    no_devices.mem_device = None;
+
+    for block in devices.block_devices.iter() {
+        let mut single_device = no_devices.clone();
+        single_device.block_devices.push(block.clone());
+        add(
+            format!("block device {}", block.device_id),
+            check(&single_device, target_version),
+        );
+    }
+    for net in devices.net_devices.iter() {
+        let mut single_device = no_devices.clone();
+        single_device.net_devices.push(net.clone());
+        add(
+            format!("net device {}", net.device_id),
+            check(&single_device, target_version),
+        );
+    }
+    if let Some(ref vsock) = devices.vsock_device {
+        let mut single_device = no_devices.clone();
+        single_device.vsock_device = Some(vsock.clone());
+        add(
+            format!("vsock device {}", vsock.device_id),
+            check(&single_device, target_version),
+        );
+    }
+    if let Some(ref balloon) = devices.balloon_device {
+        let mut single_device = no_devices.clone();
+        single_device.balloon_device = Some(balloon.clone());
+        add(
+            format!("balloon device {}", balloon.device_id),
+            check(&single_device, target_version),
+        );
+    }
+    if let Some(ref mem) = devices.mem_device {
+        let mut single_device = no_devices.clone();
+        single_device.mem_device = Some(mem.clone());
+        add(
+            format!("memory device {}", mem.device_id),
+            check(&single_device, target_version),
+        );
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_data_version() {
+        assert_eq!(target_data_version("0.23.0").unwrap(), 1);
+        assert_eq!(target_data_version("0.24.0").unwrap(), 2);
+        match target_data_version("0.22.0") {
+            Err(Error::InvalidTargetVersion(ref version)) if version == "0.22.0" => (),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Describes the content of a microVM state file as JSON.
+
+use devices::virtio::vsock::persist::VsockBackendState;
+use serde_json::{json, Value};
+use vmm::persist::MicrovmState;
+
+// Registers and guest addresses read better in hexadecimal.
+fn hex(value: u64) -> Value {
+    Value::String(format!("{:#x}", value))
+}
+
+// The MMIO slot of a saved device.
+macro_rules! mmio_slot {
+    ($device:expr) => {
+        json!({
+            "addr": hex($device.mmio_slot.addr),
+            "len": hex($device.mmio_slot.len),
+            "irqs": $device.mmio_slot.irqs,
+        })
+    };
+}
+
+/// Describes `state`, loaded from a state file with the `data_version` data format and
+/// whose CRC is `crc_valid`.
+pub fn describe(state: &MicrovmState, data_version: u16, crc_valid: bool) -> Value {
+    json!({
+        "data_version": data_version,
+        "crc_valid": crc_valid,
+        "mem_size_mib": state.vm_info.mem_size_mib,
+        "memory_regions": describe_memory(state),
+        "vcpus": describe_vcpus(state),
+        "devices": describe_devices(state),
+    })
+}
+
+fn describe_memory(state: &MicrovmState) -> Value {
+    state
+        .memory_state
+        .regions
+        .iter()
+        .map(|region| {
+            json!({
+                "base_address": hex(region.base_address),
+                "size": region.size,
+                "offset": region.offset,
+            })
+        })
+        .collect()
+}
+
+fn describe_vcpus(state: &MicrovmState) -> Value {
+    state
+        .vcpu_states
+        .iter()
+        .map(|vcpu| {
+            let regs = vcpu.regs();
+            let sregs = vcpu.sregs();
+            json!({
+                "mp_state": vcpu.mp_state().mp_state,
+                "regs": {
+                    "rax": hex(regs.rax),
+                    "rbx": hex(regs.rbx),
+                    "rcx": hex(regs.rcx),
+                    "rdx": hex(regs.rdx),
+                    "rsi": hex(regs.rsi),
+                    "rdi": hex(regs.rdi),
+                    "rsp": hex(regs.rsp),
+                    "rbp": hex(regs.rbp),
+                    "r8": hex(regs.r8),
+                    "r9": hex(regs.r9),
+                    "r10": hex(regs.r10),
+                    "r11": hex(regs.r11),
+                    "r12": hex(regs.r12),
+                    "r13": hex(regs.r13),
+                    "r14": hex(regs.r14),
+                    "r15": hex(regs.r15),
+                    "rip": hex(regs.rip),
+                    "rflags": hex(regs.rflags),
+                },
+                "sregs": {
+                    "cr0": hex(sregs.cr0),
+                    "cr2": hex(sregs.cr2),
+                    "cr3": hex(sregs.cr3),
+                    "cr4": hex(sregs.cr4),
+                    "cr8": hex(sregs.cr8),
+                    "efer": hex(sregs.efer),
+                    "apic_base": hex(sregs.apic_base),
+                },
+            })
+        })
+        .collect()
+}
+
+fn describe_devices(state: &MicrovmState) -> Value {
+    let devices = &state.device_states;
+
+    let block: Vec<Value> = devices
+        .block_devices
+        .iter()
+        .map(|block| {
+            json!({
+                "drive_id": block.device_id,
+                "path_on_host": block.device_state.disk_path(),
+                "format": block.device_state.format(),
+                "cache_type": block.device_state.cache_type(),
+                "overlay_path": block.device_state.overlay_path(),
+                "is_root_device": block.device_state.is_root_device(),
+                "partuuid": block.device_state.partuuid(),
+                "num_queues": block.device_state.num_queues(),
+                "io_threads": block.device_state.io_threads(),
+                "nsectors": block.device_state.nsectors(),
+                "mmio_slot": mmio_slot!(block),
+            })
+        })
+        .collect();
+
+    let net: Vec<Value> = devices
+        .net_devices
+        .iter()
+        .map(|net| {
+            json!({
+                "iface_id": net.device_id,
+                "host_dev_name": net.device_state.tap_if_name(),
+                "guest_mac": net.device_state.guest_mac().to_string(),
+                "mmio_slot": mmio_slot!(net),
+            })
+        })
+        .collect();
+
+    let vsock = devices.vsock_device.as_ref().map(|vsock| {
+        let uds_path = match vsock.device_state.backend {
+            VsockBackendState::Uds(ref uds_state) => uds_state.path(),
+        };
+        json!({
+            "vsock_id": vsock.device_id,
+            "guest_cid": vsock.device_state.frontend.cid,
+            "uds_path": uds_path,
+            "mmio_slot": mmio_slot!(vsock),
+        })
+    });
+
+    let balloon = devices.balloon_device.as_ref().map(|balloon| {
+        json!({
+            "device_id": balloon.device_id,
+            "mmio_slot": mmio_slot!(balloon),
+        })
+    });
+
+    let mem = devices.mem_device.as_ref().map(|mem| {
+        json!({
+            "device_id": mem.device_id,
+            "mmio_slot": mmio_slot!(mem),
+        })
+    });
+
+    json!({
+        "block": block,
+        "net": net,
+        "vsock": vsock,
+        "balloon": balloon,
+        "mem": mem,
+    })
+}
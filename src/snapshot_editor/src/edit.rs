@@ -0,0 +1,84 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rewrites the host resources referenced by a microVM state.
+
+use devices::virtio::vsock::persist::VsockBackendState;
+use vmm::persist::MicrovmState;
+
+use super::{Error, Result};
+
+/// A change to the host resources referenced by a microVM state.
+#[derive(Debug, PartialEq)]
+pub enum Edit {
+    /// Backs the net device with `iface_id` by the `tap_name` tap interface.
+    TapName { iface_id: String, tap_name: String },
+    /// Backs the block device with `drive_id` by the file at `path`.
+    DrivePath { drive_id: String, path: String },
+    /// Makes the vsock device listen on the Unix socket at `path`.
+    VsockUdsPath(String),
+}
+
+/// Splits an `<id>=<value>` command line argument.
+pub fn split_id_value(arg: &str) -> Result<(String, String)> {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(value)) if !id.is_empty() && !value.is_empty() => {
+            Ok((id.to_string(), value.to_string()))
+        }
+        _ => Err(Error::InvalidIdValue(arg.to_string())),
+    }
+}
+
+/// Applies `edit` to `state`.
+pub fn apply(state: &mut MicrovmState, edit: &Edit) -> Result<()> {
+    let devices = &mut state.device_states;
+    match edit {
+        Edit::TapName { iface_id, tap_name } => devices
+            .net_devices
+            .iter_mut()
+            .find(|net| &net.device_id == iface_id)
+            .ok_or_else(|| Error::UnknownDevice(iface_id.clone()))?
+            .device_state
+            .set_tap_if_name(tap_name.clone()),
+        Edit::DrivePath { drive_id, path } => devices
+            .block_devices
+            .iter_mut()
+            .find(|block| &block.device_id == drive_id)
+            .ok_or_else(|| Error::UnknownDevice(drive_id.clone()))?
+            .device_state
+            .set_disk_path(path.clone()),
+        Edit::VsockUdsPath(path) => {
+            let vsock = devices.vsock_device.as_mut().ok_or(Error::NoVsockDevice)?;
+            match vsock.device_state.backend {
+                VsockBackendState::Uds(ref mut uds_state) => uds_state.set_path(path.clone()),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_id_value() {
+        assert_eq!(
+            split_id_value("eth0=tap0").unwrap(),
+            ("eth0".to_string(), "tap0".to_string())
+        );
+        // Only the first `=` separates the ID from the value.
+        assert_eq!(
+            split_id_value("rootfs=/srv/a=b.ext4").unwrap(),
+            ("rootfs".to_string(), "/srv/a=b.ext4".to_string())
+        );
+
+        for arg in &["tap0", "=tap0", "eth0=", ""] {
+            match split_id_value(arg) {
+                Err(Error::InvalidIdValue(ref value)) if value == arg => (),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
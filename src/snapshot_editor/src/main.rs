@@ -0,0 +1,385 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+// Snapshots are currently only supported on x86_64.
+#[cfg(target_arch = "x86_64")]
+mod convert;
+#[cfg(target_arch = "x86_64")]
+mod dump;
+#[cfg(target_arch = "x86_64")]
+mod edit;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::result;
+
+use utils::arg_parser::{ArgParser, Argument, Arguments, Error as ParsingError};
+
+const SNAPSHOT_EDITOR_VERSION: &str = env!("FIRECRACKER_VERSION");
+
+#[derive(Debug)]
+#[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+pub enum Error {
+    ArgumentParsing(ParsingError),
+    CreateOutputFile(PathBuf, io::Error),
+    InvalidCrc,
+    InvalidIdValue(String),
+    InvalidTargetVersion(String),
+    LoadSnapshot(snapshot::Error),
+    MissingOutputPath,
+    NoVsockDevice,
+    ReadSnapshotFile(PathBuf, io::Error),
+    SaveSnapshot(snapshot::Error),
+    Serialize(serde_json::Error),
+    UnknownDevice(String),
+    UnrepresentableState(u16, Vec<String>),
+    UnsupportedArch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match *self {
+            ArgumentParsing(ref err) => write!(f, "Failed to parse arguments: {}", err),
+            CreateOutputFile(ref path, ref err) => {
+                write!(f, "Failed to create output file {:?}: {}", path, err)
+            }
+            InvalidCrc => write!(
+                f,
+                "The snapshot CRC is invalid, refusing to rewrite a corrupted state."
+            ),
+            InvalidIdValue(ref arg) => write!(
+                f,
+                "Invalid argument value {}, expected <device id>=<value>.",
+                arg
+            ),
+            InvalidTargetVersion(ref version) => write!(
+                f,
+                "Cannot translate Firecracker version {} to a snapshot data version.",
+                version
+            ),
+            LoadSnapshot(ref err) => write!(f, "Failed to load the microVM state: {:?}", err),
+            MissingOutputPath => write!(
+                f,
+                "Editing or converting a snapshot requires an --output-path."
+            ),
+            NoVsockDevice => write!(f, "The snapshot has no vsock device."),
+            ReadSnapshotFile(ref path, ref err) => {
+                write!(f, "Failed to read snapshot file {:?}: {}", path, err)
+            }
+            SaveSnapshot(ref err) => write!(f, "Failed to save the microVM state: {:?}", err),
+            Serialize(ref err) => write!(f, "Failed to describe the microVM state: {}", err),
+            UnknownDevice(ref id) => write!(f, "The snapshot has no device with ID {}.", id),
+            UnrepresentableState(ref version, ref fields) => write!(
+                f,
+                "The microVM state cannot be represented in snapshot data version {}:\n{}",
+                version,
+                fields.join("\n")
+            ),
+            UnsupportedArch => write!(f, "Snapshots are only supported on x86_64."),
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn build_arg_parser() -> ArgParser<'static> {
+    ArgParser::new()
+        .arg(
+            Argument::new("snapshot-path")
+                .required(true)
+                .takes_value(true)
+                .help("Path to the microVM state file to inspect or edit."),
+        )
+        .arg(Argument::new("output-path").takes_value(true).help(
+            "Path to the microVM state file written with the edits applied. Without it, \
+             the state is dumped as JSON to the standard output.",
+        ))
+        .arg(Argument::new("target-version").takes_value(true).help(
+            "Firecracker version whose snapshot data format the output state file is \
+             written in. Defaults to the data format of the original state file.",
+        ))
+        .arg(
+            Argument::new("set-tap-name")
+                .allow_multiple(true)
+                .help("Backs a net device by another tap interface: <iface id>=<tap name>."),
+        )
+        .arg(
+            Argument::new("set-drive-path")
+                .allow_multiple(true)
+                .help("Backs a block device by another file: <drive id>=<path on host>."),
+        )
+        .arg(
+            Argument::new("set-vsock-uds-path")
+                .takes_value(true)
+                .help("Makes the vsock device listen on another Unix socket."),
+        )
+}
+
+#[cfg(target_arch = "x86_64")]
+fn parse_edits(arguments: &Arguments) -> Result<Vec<edit::Edit>> {
+    let mut edits = Vec::new();
+    for arg in arguments.multiple_values("set-tap-name").unwrap_or(&[]) {
+        let (iface_id, tap_name) = edit::split_id_value(arg)?;
+        edits.push(edit::Edit::TapName { iface_id, tap_name });
+    }
+    for arg in arguments.multiple_values("set-drive-path").unwrap_or(&[]) {
+        let (drive_id, path) = edit::split_id_value(arg)?;
+        edits.push(edit::Edit::DrivePath { drive_id, path });
+    }
+    if let Some(path) = arguments.single_value("set-vsock-uds-path") {
+        edits.push(edit::Edit::VsockUdsPath(path.clone()));
+    }
+    Ok(edits)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run(arguments: &Arguments) -> Result<()> {
+    use std::fs::{self, OpenOptions};
+
+    use snapshot::Snapshot;
+    use vmm::persist::MicrovmState;
+    use vmm::version_map::VERSION_MAP;
+
+    // Safe to unwrap since the argument is required.
+    let snapshot_path = PathBuf::from(arguments.single_value("snapshot-path").unwrap());
+    let output_path = arguments.single_value("output-path").map(PathBuf::from);
+    let edits = parse_edits(arguments)?;
+    let target_version = arguments.single_value("target-version");
+    if output_path.is_none() && (!edits.is_empty() || target_version.is_some()) {
+        return Err(Error::MissingOutputPath);
+    }
+    let target_data_version = match target_version {
+        Some(version) => Some(convert::target_data_version(version)?),
+        None => None,
+    };
+
+    let snapshot_bytes = fs::read(&snapshot_path)
+        .map_err(|err| Error::ReadSnapshotFile(snapshot_path.clone(), err))?;
+    let data_version = Snapshot::get_data_version(&mut snapshot_bytes.as_slice(), &VERSION_MAP)
+        .map_err(Error::LoadSnapshot)?;
+
+    // A state failing the CRC check can still be inspected, which helps finding out what
+    // went wrong with it.
+    let checked_state: result::Result<MicrovmState, snapshot::Error> = Snapshot::load(
+        &mut snapshot_bytes.as_slice(),
+        snapshot_bytes.len(),
+        VERSION_MAP.clone(),
+    );
+    let (mut state, crc_valid) = match checked_state {
+        Ok(state) => (state, true),
+        Err(snapshot::Error::Crc64(_)) => (
+            Snapshot::unchecked_load(&mut snapshot_bytes.as_slice(), VERSION_MAP.clone())
+                .map_err(Error::LoadSnapshot)?,
+            false,
+        ),
+        Err(err) => return Err(Error::LoadSnapshot(err)),
+    };
+
+    match output_path {
+        None => {
+            let description = dump::describe(&state, data_version, crc_valid);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&description).map_err(Error::Serialize)?
+            );
+        }
+        Some(output_path) => {
+            if !crc_valid {
+                return Err(Error::InvalidCrc);
+            }
+            for edit in edits.iter() {
+                edit::apply(&mut state, edit)?;
+            }
+
+            // Without a target version, the edited state keeps the data version of the
+            // original one.
+            let output_data_version = target_data_version.unwrap_or(data_version);
+            let unrepresentable = convert::unrepresentable_fields(&state, output_data_version);
+            if !unrepresentable.is_empty() {
+                return Err(Error::UnrepresentableState(
+                    output_data_version,
+                    unrepresentable,
+                ));
+            }
+
+            let mut output_file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&output_path)
+                .map_err(|err| Error::CreateOutputFile(output_path.clone(), err))?;
+            Snapshot::new(VERSION_MAP.clone(), output_data_version)
+                .save(&mut output_file, &state)
+                .map_err(Error::SaveSnapshot)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run(_: &Arguments) -> Result<()> {
+    Err(Error::UnsupportedArch)
+}
+
+fn main() {
+    let mut arg_parser = build_arg_parser();
+
+    match arg_parser.parse_from_cmdline() {
+        Err(err) => {
+            println!(
+                "Arguments parsing error: {} \n\n\
+                 For more information try --help.",
+                err
+            );
+            process::exit(1);
+        }
+        _ => {
+            if arg_parser.arguments().flag_present("help") {
+                println!("Snapshot editor v{}\n", SNAPSHOT_EDITOR_VERSION);
+                println!("{}\n", arg_parser.formatted_help());
+                process::exit(0);
+            }
+
+            if arg_parser.arguments().flag_present("version") {
+                println!("Snapshot editor v{}\n", SNAPSHOT_EDITOR_VERSION);
+                process::exit(0);
+            }
+        }
+    }
+
+    if let Err(err) = run(arg_parser.arguments()) {
+        eprintln!("Snapshot editor error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_args(args: &[&str]) -> Vec<String> {
+        std::iter::once("snapshot_editor")
+            .chain(args.iter().cloned())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_parse_edits() {
+        let mut arg_parser = build_arg_parser();
+        arg_parser
+            .parse(&make_args(&[
+                "--snapshot-path",
+                "vm.snap",
+                "--set-tap-name",
+                "eth0=tap1",
+                "--set-drive-path",
+                "rootfs=/srv/rootfs.ext4",
+                "--set-tap-name",
+                "eth1=tap2",
+                "--set-vsock-uds-path",
+                "/srv/v.sock",
+            ]))
+            .unwrap();
+        assert_eq!(
+            parse_edits(arg_parser.arguments()).unwrap(),
+            vec![
+                edit::Edit::TapName {
+                    iface_id: "eth0".to_string(),
+                    tap_name: "tap1".to_string()
+                },
+                edit::Edit::TapName {
+                    iface_id: "eth1".to_string(),
+                    tap_name: "tap2".to_string()
+                },
+                edit::Edit::DrivePath {
+                    drive_id: "rootfs".to_string(),
+                    path: "/srv/rootfs.ext4".to_string()
+                },
+                edit::Edit::VsockUdsPath("/srv/v.sock".to_string()),
+            ]
+        );
+
+        let mut arg_parser = build_arg_parser();
+        arg_parser
+            .parse(&make_args(&[
+                "--snapshot-path",
+                "vm.snap",
+                "--set-tap-name",
+                "tap1",
+            ]))
+            .unwrap();
+        assert_eq!(
+            parse_edits(arg_parser.arguments()).unwrap_err().to_string(),
+            "Invalid argument value tap1, expected <device id>=<value>."
+        );
+
+        // Editing a snapshot requires a destination file.
+        let mut arg_parser = build_arg_parser();
+        arg_parser
+            .parse(&make_args(&[
+                "--snapshot-path",
+                "vm.snap",
+                "--set-tap-name",
+                "eth0=tap1",
+            ]))
+            .unwrap();
+        match run(arg_parser.arguments()) {
+            Err(Error::MissingOutputPath) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_display() {
+        use self::Error::*;
+
+        let err = ArgumentParsing(ParsingError::MissingArgument("snapshot-path".to_string()));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CreateOutputFile(PathBuf::from("/foo"), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidCrc;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidIdValue("foo".to_string());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidTargetVersion("0.1.0".to_string());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = LoadSnapshot(snapshot::Error::InvalidSnapshotSize);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MissingOutputPath;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = NoVsockDevice;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = ReadSnapshotFile(PathBuf::from("/foo"), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SaveSnapshot(snapshot::Error::Io(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = Serialize(serde_json::from_str::<u32>("foo").unwrap_err());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnknownDevice("foo".to_string());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnrepresentableState(1, vec!["balloon device foo: bar".to_string()]);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnsupportedArch;
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::Ipv6Addr;
+
+/// Checks if an IPv6 address is either link-local (fe80::/10, RFC 4291) or unique local
+/// (fc00::/7, RFC 4193), i.e. not routable over the internet.
+/// # Examples
+///
+/// ```
+/// use std::net::Ipv6Addr;
+/// use utils::net::ipv6addr::is_local_valid;
+///
+/// is_local_valid(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254));
+///
+pub fn is_local_valid(ipv6_addr: Ipv6Addr) -> bool {
+    let first_segment = ipv6_addr.segments()[0];
+    first_segment & 0xffc0 == 0xfe80 || first_segment & 0xfe00 == 0xfc00
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::ipv6addr::is_local_valid;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_is_local_valid() {
+        // Global, loopback, unspecified and multicast addresses.
+        let mut ipv6_addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        assert!(!is_local_valid(ipv6_addr));
+        ipv6_addr = Ipv6Addr::LOCALHOST;
+        assert!(!is_local_valid(ipv6_addr));
+        ipv6_addr = Ipv6Addr::UNSPECIFIED;
+        assert!(!is_local_valid(ipv6_addr));
+        ipv6_addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        assert!(!is_local_valid(ipv6_addr));
+
+        // Just outside the link-local range.
+        ipv6_addr = Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 1);
+        assert!(!is_local_valid(ipv6_addr));
+
+        // Link-local addresses.
+        ipv6_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        assert!(is_local_valid(ipv6_addr));
+        ipv6_addr = Ipv6Addr::new(0xfebf, 0xffff, 0, 0, 0, 0, 0, 1);
+        assert!(is_local_valid(ipv6_addr));
+
+        // Unique local addresses.
+        ipv6_addr = Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 1);
+        assert!(is_local_valid(ipv6_addr));
+        ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        assert!(is_local_valid(ipv6_addr));
+    }
+}
@@ -13,4 +13,6 @@
 
 /// Provides IPv4 address utility methods.
 pub mod ipv4addr;
+/// Provides IPv6 address utility methods.
+pub mod ipv6addr;
 pub mod mac;
@@ -63,6 +63,19 @@ impl Bitmap {
         }
     }
 
+    /// Reset a range of bits starting at `start_addr` and continuing for the next `len` bytes.
+    pub fn reset_addr_range(&self, start_addr: usize, len: usize) {
+        let first_bit = start_addr / self.page_size;
+        let page_count = (len + self.page_size - 1) / self.page_size;
+        for n in first_bit..(first_bit + page_count) {
+            if n >= self.size {
+                // Attempts to reset bits beyond the end of the bitmap are simply ignored.
+                break;
+            }
+            self.map[n >> 6].fetch_and(!(1 << (n & 63)), Ordering::SeqCst);
+        }
+    }
+
     /// Get the length of the bitmap in bits (i.e. in how many pages it can represent).
     pub fn len(&self) -> usize {
         self.size
@@ -121,6 +134,10 @@ mod tests {
         assert!(copy_b.is_addr_set(256));
         assert!(!copy_b.is_addr_set(384));
 
+        b.reset_addr_range(256, 128);
+        assert!(b.is_addr_set(128));
+        assert!(!b.is_addr_set(256));
+
         b.reset();
         assert!(!b.is_addr_set(128));
         assert!(!b.is_addr_set(256));
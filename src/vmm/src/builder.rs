@@ -16,6 +16,7 @@ use crate::device_manager::{legacy::PortIODeviceManager, persist::MMIODevManager
 #[cfg(target_arch = "x86_64")]
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, GUEST_PHYS_ADDR_SPACE_SIZE};
 use crate::vstate::{
     system::KvmContext,
     vcpu::{Vcpu, VcpuConfig},
@@ -25,7 +26,10 @@ use crate::{device_manager, Error, Vmm, VmmEventsObserver};
 
 use arch::InitrdConfig;
 use devices::legacy::Serial;
-use devices::virtio::{Balloon, Block, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend};
+use devices::virtio::{
+    spawn_io_threads, Balloon, Block, Mem, MmioTransport, Net, VirtioDevice, Vsock,
+    VsockUnixBackend,
+};
 use kernel::cmdline::Cmdline as KernelCmdline;
 use logger::warn;
 use polly::event_manager::{Error as EventManagerError, EventManager, Subscriber};
@@ -35,7 +39,7 @@ use snapshot::Persist;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
 use utils::time::TimestampUs;
-use vm_memory::{GuestAddress, GuestMemoryMmap};
+use vm_memory::{GuestAddress, GuestMemoryMmap, GuestRegionMmap, MmapRegion};
 
 /// Errors associated with starting the instance.
 #[derive(Debug)]
@@ -48,6 +52,8 @@ pub enum StartMicrovmError {
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Failed to create the memory hotplug device.
+    CreateMemDevice(devices::virtio::mem::Error),
     /// Memory regions are overlapping or mmap fails.
     GuestMemoryMmap(vm_memory::Error),
     /// Cannot load initrd due to an invalid memory configuration.
@@ -96,6 +102,7 @@ impl Display for StartMicrovmError {
             }
             ConfigureSystem(e) => write!(f, "System configuration error: {:?}", e),
             CreateRateLimiter(err) => write!(f, "Cannot create RateLimiter: {}", err),
+            CreateMemDevice(err) => write!(f, "Cannot create memory hotplug device: {:?}", err),
             CreateNetDevice(err) => {
                 let mut err_msg = format!("{:?}", err);
                 err_msg = err_msg.replace("\"", "");
@@ -209,10 +216,16 @@ fn create_vmm_and_vcpus(
     event_manager: &mut EventManager,
     guest_memory: GuestMemoryMmap,
     track_dirty_pages: bool,
-    vcpu_count: u8,
+    vcpu_config: &VcpuConfig,
 ) -> std::result::Result<(Vmm, Vec<Vcpu>), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
+    let vcpu_count = vcpu_config.vcpu_count;
+    // KVM cannot create vcpus once the VMM seccomp filter is in place, so the ones which can be
+    // hot-plugged later on are created upfront.
+    #[cfg(target_arch = "x86_64")]
+    let max_vcpu_count = vcpu_config.max_vcpu_count;
+
     // Set up Kvm Vm and register memory regions.
     let mut vm = setup_kvm_vm(&guest_memory, track_dirty_pages)?;
 
@@ -233,7 +246,7 @@ fn create_vmm_and_vcpus(
     #[cfg(target_arch = "x86_64")]
     let pio_device_manager = {
         setup_interrupt_controller(&mut vm)?;
-        vcpus = create_vcpus(&vm, vcpu_count, &exit_evt).map_err(Internal)?;
+        vcpus = create_vcpus(&vm, max_vcpu_count, &exit_evt).map_err(Internal)?;
 
         // Serial device setup.
         let serial_device = setup_serial_device(
@@ -265,6 +278,12 @@ fn create_vmm_and_vcpus(
         events_observer: Some(Box::new(SerialStdin::get())),
         guest_memory,
         vcpus_handles: Vec::new(),
+        #[cfg(target_arch = "x86_64")]
+        parked_vcpus_handles: Vec::new(),
+        #[cfg(target_arch = "x86_64")]
+        vcpu_config: vcpu_config.clone(),
+        #[cfg(target_arch = "x86_64")]
+        vcpus_running: false,
         exit_evt,
         vm,
         mmio_device_manager,
@@ -298,9 +317,19 @@ pub fn build_microvm_for_boot(
             .ok_or(MissingMemSizeConfig)?,
         track_dirty_pages,
     )?;
+    // The kernel only gets to know about the boot memory. The memory hotplug region is
+    // exposed to the guest exclusively through the virtio-mem device.
+    let boot_memory = guest_memory.clone();
+    let (guest_memory, mem_device) = match vm_resources.memory_hotplug.as_ref() {
+        Some(config) => {
+            let (guest_memory, mem_device) = create_mem_device(&boot_memory, config)?;
+            (guest_memory, Some(mem_device))
+        }
+        None => (guest_memory, None),
+    };
     let vcpu_config = vm_resources.vcpu_config();
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
-    let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
+    let entry_addr = load_kernel(boot_config, &boot_memory)?;
+    let initrd = load_initrd_from_config(boot_config, &boot_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
     let mut boot_cmdline = boot_config.cmdline.clone();
@@ -308,12 +337,8 @@ pub fn build_microvm_for_boot(
     // Timestamp for measuring microVM boot duration.
     let request_ts = TimestampUs::default();
 
-    let (mut vmm, mut vcpus) = create_vmm_and_vcpus(
-        event_manager,
-        guest_memory,
-        track_dirty_pages,
-        vcpu_config.vcpu_count,
-    )?;
+    let (mut vmm, mut vcpus) =
+        create_vmm_and_vcpus(event_manager, guest_memory, track_dirty_pages, &vcpu_config)?;
 
     // The boot timer device needs to be the first device attached in order
     // to maintain the same MMIO address referenced in the documentation
@@ -326,11 +351,16 @@ pub fn build_microvm_for_boot(
         attach_balloon_device(&mut vmm, &mut boot_cmdline, balloon, event_manager)?;
     }
 
+    if let Some(mem_device) = mem_device {
+        attach_mem_device(&mut vmm, &mut boot_cmdline, &mem_device, event_manager)?;
+    }
+
     attach_block_devices(
         &mut vmm,
         &mut boot_cmdline,
         vm_resources.block.list.iter(),
         event_manager,
+        seccomp_filter,
     )?;
     attach_net_devices(
         &mut vmm,
@@ -345,8 +375,19 @@ pub fn build_microvm_for_boot(
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
+    // Only the boot vCPUs are brought up by the guest kernel. The remaining ones are described
+    // to the guest, so that they can be onlined once hot-plugged.
+    #[cfg(target_arch = "x86_64")]
+    {
+        if vcpu_config.max_vcpu_count > vcpu_config.vcpu_count {
+            boot_cmdline.insert("maxcpus", vcpu_config.vcpu_count.to_string().as_str())?;
+            attach_cpu_hotplug_device(&mut vmm, &mut boot_cmdline, vcpu_config.vcpu_count)?;
+        }
+    }
+
     configure_system_for_boot(
         &vmm,
+        &boot_memory,
         vcpus.as_mut(),
         vcpu_config,
         entry_addr,
@@ -392,13 +433,25 @@ pub fn build_microvm_from_snapshot(
     let vcpu_count = u8::try_from(microvm_state.vcpu_states.len())
         .map_err(|_| MicrovmStateError::InvalidInput)
         .map_err(RestoreMicrovmState)?;
+    // The parked vCPUs are saved along with the running ones, so the restored microVM can
+    // still hot-plug them.
+    let max_vcpu_count =
+        u8::try_from(microvm_state.vcpu_states.len() + microvm_state.parked_vcpu_states.len())
+            .map_err(|_| MicrovmStateError::InvalidInput)
+            .map_err(RestoreMicrovmState)?;
+    let vcpu_config = VcpuConfig {
+        vcpu_count,
+        max_vcpu_count,
+        ht_enabled: false,
+        cpu_template: None,
+    };
 
     // Build Vmm.
     let (mut vmm, vcpus) = create_vmm_and_vcpus(
         event_manager,
         guest_memory.clone(),
         track_dirty_pages,
-        vcpu_count,
+        &vcpu_config,
     )?;
 
     // Restore kvm vm state.
@@ -412,6 +465,7 @@ pub fn build_microvm_from_snapshot(
         mem: guest_memory,
         vm: vmm.vm.fd(),
         event_manager,
+        seccomp_filter,
     };
     vmm.mmio_device_manager =
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
@@ -423,7 +477,7 @@ pub fn build_microvm_from_snapshot(
         .map_err(StartMicrovmError::Internal)?;
 
     // Restore vcpus kvm state.
-    vmm.restore_vcpu_states(microvm_state.vcpu_states)
+    vmm.restore_vcpu_states(microvm_state.vcpu_states, microvm_state.parked_vcpu_states)
         .map_err(RestoreMicrovmState)?;
 
     let vmm = Arc::new(Mutex::new(vmm));
@@ -440,6 +494,50 @@ pub fn build_microvm_from_snapshot(
     Ok(vmm)
 }
 
+/// Maps the memory hotplug region right after `boot_memory` and creates the virtio-mem device
+/// managing it. Returns the guest memory extended with the hotplug region, along with the device.
+fn create_mem_device(
+    boot_memory: &GuestMemoryMmap,
+    config: &MemoryHotplugConfig,
+) -> std::result::Result<(GuestMemoryMmap, Arc<Mutex<Mem>>), StartMicrovmError> {
+    use self::StartMicrovmError::CreateMemDevice;
+    use devices::virtio::mem::{Error as MemError, MIB_TO_BYTES};
+
+    let to_bytes = |size_mib: u64| {
+        size_mib
+            .checked_mul(MIB_TO_BYTES)
+            .ok_or(CreateMemDevice(MemError::InvalidSize(size_mib)))
+    };
+    let region_size = to_bytes(config.total_size_mib)?;
+    let start_addr = GuestAddress(arch::hotplug_memory_start(boot_memory));
+    // The boot memory may have grown since the configuration was validated.
+    match start_addr.0.checked_add(region_size) {
+        Some(end_addr) if end_addr <= GUEST_PHYS_ADDR_SPACE_SIZE => (),
+        _ => return Err(CreateMemDevice(MemError::InvalidSize(region_size))),
+    }
+
+    let region = MmapRegion::new(region_size as usize)
+        .map_err(vm_memory::Error::MmapRegion)
+        .and_then(|mapping| GuestRegionMmap::new(mapping, start_addr))
+        .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let guest_memory = boot_memory
+        .insert_region(region)
+        .map_err(StartMicrovmError::GuestMemoryMmap)?;
+
+    let mem_device = Mem::new(
+        start_addr,
+        region_size,
+        to_bytes(config.block_size_mib)?,
+        to_bytes(config.requested_size_mib)?,
+        // `restored` flag is false because this code path
+        // is never called by snapshot restore functionality.
+        false,
+    )
+    .map_err(CreateMemDevice)?;
+
+    Ok((guest_memory, Arc::new(Mutex::new(mem_device))))
+}
+
 /// Creates GuestMemory of `mem_size_mib` MiB in size.
 pub fn create_guest_memory(
     mem_size_mib: usize,
@@ -646,9 +744,11 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> super::Result<Ve
 }
 
 /// Configures the system for booting Linux.
+/// `boot_memory` is the part of the guest memory the kernel is told about at boot time.
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
 pub fn configure_system_for_boot(
     vmm: &Vmm,
+    boot_memory: &GuestMemoryMmap,
     vcpus: &mut [Vcpu],
     vcpu_config: VcpuConfig,
     entry_addr: GuestAddress,
@@ -658,7 +758,8 @@ pub fn configure_system_for_boot(
     use self::StartMicrovmError::*;
     #[cfg(target_arch = "x86_64")]
     {
-        for vcpu in vcpus.iter_mut() {
+        let (boot_vcpus, parked_vcpus) = vcpus.split_at_mut(vcpu_config.vcpu_count as usize);
+        for vcpu in boot_vcpus.iter_mut() {
             vcpu.kvm_vcpu
                 .configure(
                     vmm.guest_memory(),
@@ -669,6 +770,12 @@ pub fn configure_system_for_boot(
                 .map_err(Error::VcpuConfigure)
                 .map_err(Internal)?;
         }
+        for vcpu in parked_vcpus.iter_mut() {
+            vcpu.kvm_vcpu
+                .configure_hotplugged(&vcpu_config, vmm.vm.supported_cpuid().clone())
+                .map_err(Error::VcpuConfigure)
+                .map_err(Internal)?;
+        }
 
         // Write the kernel command line to guest memory. This is x86_64 specific, since on
         // aarch64 the command line will be specified through the FDT.
@@ -679,11 +786,11 @@ pub fn configure_system_for_boot(
         )
         .map_err(LoadCommandline)?;
         arch::x86_64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             vm_memory::GuestAddress(arch::x86_64::layout::CMDLINE_START),
             boot_cmdline.len() + 1,
             initrd,
-            vcpus.len() as u8,
+            vcpu_config.max_vcpu_count,
         )
         .map_err(ConfigureSystem)?;
     }
@@ -701,7 +808,7 @@ pub fn configure_system_for_boot(
             .map(|cpu| cpu.kvm_vcpu.get_mpidr())
             .collect();
         arch::aarch64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             &boot_cmdline.as_cstring().map_err(LoadCommandline)?,
             vcpu_mpidr,
             vmm.mmio_device_manager.get_device_info(),
@@ -750,11 +857,25 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+#[cfg(target_arch = "x86_64")]
+fn attach_cpu_hotplug_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    vcpu_count: u8,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    vmm.mmio_device_manager
+        .register_new_mmio_cpu_hotplug(vmm.vm.fd(), vcpu_count, cmdline)
+        .map_err(RegisterMmioDevice)
+}
+
 fn attach_block_devices<'a>(
     vmm: &mut Vmm,
     cmdline: &mut KernelCmdline,
     blocks: impl Iterator<Item = &'a Arc<Mutex<Block>>>,
     event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
 ) -> std::result::Result<(), StartMicrovmError> {
     for block in blocks {
         let id = {
@@ -774,6 +895,10 @@ fn attach_block_devices<'a>(
         };
         // The device mutex mustn't be locked here otherwise it will deadlock.
         attach_virtio_device(event_manager, vmm, id, block.clone(), cmdline)?;
+        if block.lock().expect("Poisoned lock").has_io_threads() {
+            spawn_io_threads(block, seccomp_filter)
+                .map_err(StartMicrovmError::AttachBlockDevice)?;
+        }
     }
     Ok(())
 }
@@ -814,6 +939,17 @@ fn attach_balloon_device(
     attach_virtio_device(event_manager, vmm, id, balloon.clone(), cmdline)
 }
 
+fn attach_mem_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    mem_device: &Arc<Mutex<Mem>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let id = String::from(mem_device.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_virtio_device(event_manager, vmm, id, mem_device.clone(), cmdline)
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::io::Cursor;
@@ -821,12 +957,13 @@ pub mod tests {
     use super::*;
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType, ImageFormat};
+    use crate::vmm_config::memory_hotplug::MEM_DEV_ID;
+    use crate::vmm_config::net::{NetBuilder, NetworkBackendConfig, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
     use arch::DeviceType;
-    use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_VSOCK};
+    use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_VSOCK};
     use kernel::cmdline::Cmdline;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
@@ -892,6 +1029,17 @@ pub mod tests {
             events_observer: Some(Box::new(SerialStdin::get())),
             guest_memory,
             vcpus_handles: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            parked_vcpus_handles: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            vcpu_config: VcpuConfig {
+                vcpu_count: 1,
+                max_vcpu_count: 1,
+                ht_enabled: false,
+                cpu_template: None,
+            },
+            #[cfg(target_arch = "x86_64")]
+            vcpus_running: false,
             exit_evt,
             vm,
             mmio_device_manager,
@@ -927,15 +1075,27 @@ pub mod tests {
                     .to_str()
                     .unwrap()
                     .to_string(),
+                format: ImageFormat::Raw,
+                cache_type: CacheType::Unsafe,
+                overlay_path: None,
                 is_root_device: custom_block_cfg.is_root_device,
                 partuuid: custom_block_cfg.partuuid.clone(),
                 is_read_only: custom_block_cfg.is_read_only,
+                num_queues: 1,
+                io_threads: false,
                 rate_limiter: None,
             };
             block_dev_configs.insert(block_device_config).unwrap();
         }
 
-        attach_block_devices(vmm, cmdline, block_dev_configs.list.iter(), event_manager).unwrap();
+        attach_block_devices(
+            vmm,
+            cmdline,
+            block_dev_configs.list.iter(),
+            event_manager,
+            &[],
+        )
+        .unwrap();
         block_files
     }
 
@@ -988,6 +1148,24 @@ pub mod tests {
             .is_some());
     }
 
+    pub(crate) fn insert_mem_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        mem_config: MemoryHotplugConfig,
+    ) -> GuestMemoryMmap {
+        let (guest_memory, mem_device) =
+            create_mem_device(vmm.guest_memory(), &mem_config).unwrap();
+
+        assert!(attach_mem_device(vmm, cmdline, &mem_device, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .is_some());
+        guest_memory
+    }
+
     fn make_test_bin() -> Vec<u8> {
         let mut fake_bin = Vec::new();
         fake_bin.resize(1_000_000, 0xAA);
@@ -1071,6 +1249,32 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_create_mem_device() {
+        use vm_memory::GuestMemory;
+
+        let boot_memory = create_guest_memory(128, true).unwrap();
+        let config = MemoryHotplugConfig {
+            total_size_mib: 256,
+            block_size_mib: 2,
+            requested_size_mib: 128,
+        };
+
+        let (guest_memory, mem_device) = create_mem_device(&boot_memory, &config).unwrap();
+        let start_addr = arch::hotplug_memory_start(&boot_memory);
+        assert_eq!(guest_memory.num_regions(), boot_memory.num_regions() + 1);
+        assert!(guest_memory.is_dirty_tracking_enabled());
+        assert_eq!(
+            guest_memory.last_addr(),
+            GuestAddress(start_addr + (256 << 20) - 1)
+        );
+
+        let mem_device = mem_device.lock().unwrap();
+        assert_eq!(mem_device.start_addr(), GuestAddress(start_addr));
+        assert_eq!(mem_device.region_size(), 256 << 20);
+        assert_eq!(mem_device.requested_size(), 128 << 20);
+    }
+
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
@@ -1099,6 +1303,11 @@ pub mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1265,6 +1474,9 @@ pub mod tests {
             must_tell_host: false,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1276,6 +1488,30 @@ pub mod tests {
             .contains("virtio_mmio.device=4K@0xd0000000:5"));
     }
 
+    #[test]
+    fn test_attach_mem_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let config = MemoryHotplugConfig {
+            total_size_mib: 128,
+            block_size_mib: 2,
+            requested_size_mib: 0,
+        };
+        let (_, mem_device) = create_mem_device(vmm.guest_memory(), &config).unwrap();
+
+        let mut cmdline = default_kernel_cmdline();
+        attach_mem_device(&mut vmm, &mut cmdline, &mem_device, &mut event_manager).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .is_some());
+        // Check if the memory hotplug device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
     #[test]
     fn test_attach_vsock_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1308,6 +1544,9 @@ pub mod tests {
         let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = CreateMemDevice(devices::virtio::mem::Error::InvalidBlockSize(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = Internal(Error::Serial(io::Error::from_raw_os_error(0)));
         let _ = format!("{}{:?}", err, err);
 
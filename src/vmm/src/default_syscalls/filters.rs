@@ -39,7 +39,7 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                 )?],],
             ),
             allow_syscall(libc::SYS_close),
-            // Needed for vsock
+            // Needed for vsock and the Unix socket net backend
             allow_syscall(libc::SYS_connect),
             allow_syscall(libc::SYS_epoll_ctl),
             allow_syscall(libc::SYS_epoll_pwait),
@@ -47,6 +47,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_epoll_wait),
             allow_syscall(libc::SYS_exit),
             allow_syscall(libc::SYS_exit_group),
+            // Used by the discard and write zeroes requests of block devices
+            allow_syscall(libc::SYS_fallocate),
             // Used by snapshotting, drive patching and rescanning
             allow_syscall_if(
                 libc::SYS_fcntl,
@@ -55,8 +57,13 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     Cond::new(2, ArgLen::DWORD, Eq, super::FCNTL_FD_CLOEXEC)?,
                 ],],
             ),
+            // Used by qcow2 images and the writeback, direct and writethrough caching of block
+            // devices, for writing back to disk
+            allow_syscall(libc::SYS_fdatasync),
             // Used for drive patching & rescanning, for reading the local timezone
             allow_syscall(libc::SYS_fstat),
+            // Used by the copy-on-write overlays of block devices, for writing back to disk
+            allow_syscall(libc::SYS_fsync),
             // Used for snapshotting
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_ftruncate),
@@ -113,25 +120,73 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_open),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(libc::SYS_openat),
+            // Used by the copy-on-write overlays and qcow2 images of block devices
+            allow_syscall(libc::SYS_pread64),
+            allow_syscall(libc::SYS_pwrite64),
             allow_syscall(libc::SYS_read),
             // Used by the API thread and vsock
             allow_syscall(libc::SYS_recvfrom),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
-            // Used by the API thread and vsock
+            // Used to wake up the kernel when sending frames through AF_XDP sockets
+            allow_syscall_if(
+                libc::SYS_sendto,
+                or![and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    libc::MSG_DONTWAIT as u64
+                )?],],
+            ),
+            // Used by the API thread, vsock and the Unix socket net backend. vsock also
+            // connects to TCP ports on localhost.
             allow_syscall_if(
                 libc::SYS_socket,
-                or![and![
-                    Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
-                    Cond::new(
-                        1,
-                        ArgLen::DWORD,
-                        Eq,
-                        (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
-                    )?,
-                    Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
-                ],],
+                or![
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET6 as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_SEQPACKET as u64)
+                                | (libc::SOCK_NONBLOCK as u64)
+                                | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                ],
             ),
             // Used to kick vcpus
             allow_syscall_if(
@@ -13,6 +13,8 @@ use std::{fmt, io};
 use arch::aarch64::DeviceInfoForFDT;
 use arch::DeviceType;
 use devices::pseudo::BootTimer;
+#[cfg(target_arch = "x86_64")]
+use devices::pseudo::CpuHotplug;
 use devices::virtio::{
     Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
     TYPE_VSOCK,
@@ -21,7 +23,6 @@ use devices::BusDevice;
 use kernel::cmdline as kernel_cmdline;
 use kvm_ioctls::{IoEventAddress, VmFd};
 use logger::info;
-#[cfg(target_arch = "aarch64")]
 use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -307,6 +308,46 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, slot, Arc::new(Mutex::new(device)))
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Create and register the vCPU hotplug notification device, with the first `vcpu_count`
+    /// vCPUs present. The device is described to the guest through the kernel cmdline.
+    pub fn register_new_mmio_cpu_hotplug(
+        &mut self,
+        vm: &VmFd,
+        vcpu_count: u8,
+        cmdline: &mut kernel_cmdline::Cmdline,
+    ) -> Result<()> {
+        let slot = self.allocate_new_slot(1)?;
+        let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        cmdline
+            .insert(
+                "firecracker.cpu_hotplug",
+                &format!("{}K@0x{:08x}:{}", slot.len / 1024, slot.addr, slot.irqs[0]),
+            )
+            .map_err(Error::Cmdline)?;
+
+        let device = CpuHotplug::new(vcpu_count, interrupt_evt);
+        self.register_mmio_cpu_hotplug(vm, device, &slot)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Register an existing vCPU hotplug notification device at a specific slot.
+    pub fn register_mmio_cpu_hotplug(
+        &mut self,
+        vm: &VmFd,
+        device: CpuHotplug,
+        slot: &MMIODeviceInfo,
+    ) -> Result<()> {
+        if slot.irqs.len() != 1 {
+            return Err(Error::InvalidInput);
+        }
+        vm.register_irqfd(device.interrupt_evt(), slot.irqs[0])
+            .map_err(Error::RegisterIrqFd)?;
+
+        let identifier = (DeviceType::CpuHotplug, DeviceType::CpuHotplug.to_string());
+        self.register_mmio_device(identifier, slot.clone(), Arc::new(Mutex::new(device)))
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -574,6 +615,28 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_register_cpu_hotplug() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+
+        device_manager
+            .register_new_mmio_cpu_hotplug(vm.fd(), 2, &mut cmdline)
+            .unwrap();
+        assert!(device_manager
+            .get_device(DeviceType::CpuHotplug, &DeviceType::CpuHotplug.to_string())
+            .is_some());
+        assert!(cmdline.as_str().contains(&format!(
+            "firecracker.cpu_hotplug=4K@0xd0000000:{}",
+            arch::IRQ_BASE
+        )));
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
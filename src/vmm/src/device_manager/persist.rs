@@ -12,21 +12,26 @@ use std::sync::{Arc, Mutex};
 
 use super::mmio::*;
 
+use devices::pseudo::{CpuHotplug, CpuHotplugState};
 use devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use devices::virtio::balloon::{Balloon, Error as BalloonError};
 use devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
-use devices::virtio::block::Block;
+use devices::virtio::block::{spawn_io_threads, Block};
+use devices::virtio::mem::persist::{MemConstructorArgs, MemState};
+use devices::virtio::mem::{Error as MemError, Mem};
 use devices::virtio::net::persist::{Error as NetError, NetConstructorArgs, NetState};
 use devices::virtio::net::Net;
 use devices::virtio::persist::{MmioTransportConstructorArgs, MmioTransportState};
 use devices::virtio::vsock::persist::{VsockConstructorArgs, VsockState, VsockUdsConstructorArgs};
 use devices::virtio::vsock::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
 use devices::virtio::{
-    MmioTransport, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_VSOCK,
+    MmioTransport, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_VSOCK,
 };
 use kvm_ioctls::VmFd;
 use polly::event_manager::{Error as EventMgrError, EventManager, Subscriber};
+use seccomp::BpfProgramRef;
 use snapshot::Persist;
+use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
@@ -36,7 +41,9 @@ use vm_memory::GuestMemoryMmap;
 pub enum Error {
     Balloon(BalloonError),
     Block(io::Error),
+    CpuHotplug(io::Error),
     EventManager(EventMgrError),
+    Mem(MemError),
     DeviceManager(super::mmio::Error),
     MmioTransport,
     Net(NetError),
@@ -70,6 +77,28 @@ pub struct ConnectedBlockState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[derive(Clone, Versionize)]
+/// Holds the state of the vCPU hotplug device connected to the MMIO space.
+pub struct ConnectedCpuHotplugState {
+    /// Device state.
+    pub device_state: CpuHotplugState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[derive(Clone, Versionize)]
+/// Holds the state of a memory hotplug device connected to the MMIO space.
+pub struct ConnectedMemState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: MemState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
 #[derive(Clone, Versionize)]
 /// Holds the state of a net device connected to the MMIO space.
 pub struct ConnectedNetState {
@@ -108,6 +137,12 @@ pub struct DeviceStates {
     /// Balloon device state.
     #[version(start = 2, ser_fn = "balloon_serialize")]
     pub balloon_device: Option<ConnectedBalloonState>,
+    /// Memory hotplug device state.
+    #[version(start = 2, ser_fn = "mem_serialize")]
+    pub mem_device: Option<ConnectedMemState>,
+    /// vCPU hotplug device state.
+    #[version(start = 2, ser_fn = "cpu_hotplug_serialize")]
+    pub cpu_hotplug_device: Option<ConnectedCpuHotplugState>,
 }
 
 impl DeviceStates {
@@ -120,12 +155,33 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    fn mem_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mem_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-mem device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn cpu_hotplug_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.cpu_hotplug_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the vCPU hotplug device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
     pub mem: GuestMemoryMmap,
     pub vm: &'a VmFd,
     pub event_manager: &'a mut EventManager,
+    pub seccomp_filter: BpfProgramRef<'a>,
 }
 
 impl<'a> Persist<'a> for MMIODeviceManager {
@@ -137,6 +193,8 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         let mut states = DeviceStates {
             balloon_device: None,
             block_devices: Vec::new(),
+            cpu_hotplug_device: None,
+            mem_device: None,
             net_devices: Vec::new(),
             vsock_device: None,
         };
@@ -145,6 +203,22 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 // No need to save BootTimer state.
                 return Ok(());
             }
+            #[cfg(target_arch = "x86_64")]
+            {
+                if *devtype == arch::DeviceType::CpuHotplug {
+                    let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
+                    let cpu_hotplug_state = locked_bus_dev
+                        .as_any()
+                        .downcast_ref::<CpuHotplug>()
+                        .expect("Unexpected BusDevice type")
+                        .save();
+                    states.cpu_hotplug_device = Some(ConnectedCpuHotplugState {
+                        device_state: cpu_hotplug_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                    return Ok(());
+                }
+            }
 
             let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
             let mmio_transport = locked_bus_dev
@@ -183,6 +257,15 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         mmio_slot: devinfo.clone(),
                     });
                 }
+                TYPE_MEM => {
+                    let mem_state = locked_device.as_any().downcast_ref::<Mem>().unwrap().save();
+                    states.mem_device = Some(ConnectedMemState {
+                        device_id: devid.clone(),
+                        device_state: mem_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
                 TYPE_NET => {
                     let net_state = locked_device.as_any().downcast_ref::<Net>().unwrap().save();
                     states.net_devices.push(ConnectedNetState {
@@ -226,6 +309,19 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         let mem = &constructor_args.mem;
         let vm = constructor_args.vm;
 
+        if let Some(cpu_hotplug_state) = &state.cpu_hotplug_device {
+            dev_manager
+                .slot_sanity_check(&cpu_hotplug_state.mmio_slot)
+                .map_err(Error::DeviceManager)?;
+            let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::CpuHotplug)?;
+            // Restoring the vCPU hotplug device state can't fail.
+            let device = CpuHotplug::restore(interrupt_evt, &cpu_hotplug_state.device_state)
+                .expect("Cannot restore vCPU hotplug device");
+            dev_manager
+                .register_mmio_cpu_hotplug(vm, device, &cpu_hotplug_state.mmio_slot)
+                .map_err(Error::DeviceManager)?;
+        }
+
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
                                   as_subscriber: Arc<Mutex<dyn Subscriber>>,
                                   id: &String,
@@ -271,6 +367,25 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        if let Some(mem_state) = &state.mem_device {
+            let device = Arc::new(Mutex::new(
+                Mem::restore(
+                    MemConstructorArgs { mem: mem.clone() },
+                    &mem_state.device_state,
+                )
+                .map_err(Error::Mem)?,
+            ));
+
+            restore_helper(
+                device.clone(),
+                device,
+                &mem_state.device_id,
+                &mem_state.transport_state,
+                &mem_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+
         for block_state in &state.block_devices {
             let device = Arc::new(Mutex::new(
                 Block::restore(
@@ -282,12 +397,15 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                device.clone(),
                 &block_state.device_id,
                 &block_state.transport_state,
                 &block_state.mmio_slot,
                 constructor_args.event_manager,
             )?;
+            if block_state.device_state.io_threads() {
+                spawn_io_threads(&device, constructor_args.seccomp_filter).map_err(Error::Block)?;
+            }
         }
         for net_state in &state.net_devices {
             let device = Arc::new(Mutex::new(
@@ -343,7 +461,8 @@ mod tests {
     use super::*;
     use crate::builder::tests::*;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
-    use crate::vmm_config::net::NetworkInterfaceConfig;
+    use crate::vmm_config::memory_hotplug::MemoryHotplugConfig;
+    use crate::vmm_config::net::{NetworkBackendConfig, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::VsockDeviceConfig;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
@@ -382,6 +501,40 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedCpuHotplugState {
+        fn eq(&self, other: &ConnectedCpuHotplugState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedCpuHotplugState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedCpuHotplugDevice {{ mmio_slot: {:?} }}",
+                self.mmio_slot
+            )
+        }
+    }
+
+    impl PartialEq for ConnectedMemState {
+        fn eq(&self, other: &ConnectedMemState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedMemState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedMemDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
     impl PartialEq for ConnectedNetState {
         fn eq(&self, other: &ConnectedNetState) -> bool {
             // Actual device state equality is checked by the device's tests.
@@ -420,6 +573,8 @@ mod tests {
         fn eq(&self, other: &DeviceStates) -> bool {
             self.balloon_device == other.balloon_device
                 && self.block_devices == other.block_devices
+                && self.cpu_hotplug_device == other.cpu_hotplug_device
+                && self.mem_device == other.mem_device
                 && self.net_devices == other.net_devices
                 && self.vsock_device == other.vsock_device
         }
@@ -488,6 +643,9 @@ mod tests {
                 must_tell_host: true,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 1,
+                free_page_hinting: false,
+                free_page_reporting: false,
+                auto_target: None,
             };
             insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
             // Add a block device.
@@ -503,6 +661,11 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: true,
+                num_queues: 1,
+                guest_announce: false,
+                backend: NetworkBackendConfig::Tap,
+                anti_spoofing: None,
+                dhcp: None,
             };
             insert_net_device(
                 &mut vmm,
@@ -516,6 +679,7 @@ mod tests {
                 vsock_id: vsock_dev_id.to_string(),
                 guest_cid: 3,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                port_map: Vec::new(),
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
 
@@ -549,10 +713,103 @@ mod tests {
             mem: vmm.guest_memory().clone(),
             vm: vmm.vm.fd(),
             event_manager: &mut event_manager,
+            seccomp_filter: &[],
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
 
         assert_eq!(restored_dev_manager, original_mmio_device_manager);
     }
+
+    #[test]
+    fn test_mem_device_persistence() {
+        let mut buf = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let config = MemoryHotplugConfig {
+            total_size_mib: 128,
+            block_size_mib: 2,
+            requested_size_mib: 64,
+        };
+        let guest_memory = insert_mem_device(&mut vmm, &mut cmdline, &mut event_manager, config);
+
+        assert_eq!(
+            vmm.mmio_device_manager
+                .save()
+                .serialize(&mut buf.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-mem device.".to_string()
+            ))
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(DeviceStates::type_id(), 2);
+        vmm.mmio_device_manager
+            .save()
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .unwrap();
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let restored_vmm = default_vmm();
+        let device_states: DeviceStates =
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert!(device_states.mem_device.is_some());
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: guest_memory,
+            vm: restored_vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            seccomp_filter: &[],
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+
+        assert_eq!(restored_dev_manager, vmm.mmio_device_manager.soft_clone());
+    }
+
+    #[test]
+    fn test_cpu_hotplug_device_persistence() {
+        let mut buf = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        vmm.mmio_device_manager
+            .register_new_mmio_cpu_hotplug(vmm.vm.fd(), 1, &mut cmdline)
+            .unwrap();
+
+        assert_eq!(
+            vmm.mmio_device_manager
+                .save()
+                .serialize(&mut buf.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(
+                "Target version does not implement the vCPU hotplug device.".to_string()
+            ))
+        );
+
+        version_map
+            .new_version()
+            .set_type_version(DeviceStates::type_id(), 2);
+        vmm.mmio_device_manager
+            .save()
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .unwrap();
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let restored_vmm = default_vmm();
+        let device_states: DeviceStates =
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert!(device_states.cpu_hotplug_device.is_some());
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: restored_vmm.guest_memory().clone(),
+            vm: restored_vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            seccomp_filter: &[],
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+
+        assert_eq!(restored_dev_manager, vmm.mmio_device_manager.soft_clone());
+    }
 }
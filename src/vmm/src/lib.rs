@@ -47,16 +47,23 @@ use crate::memory_snapshot::SnapshotMemory;
 #[cfg(target_arch = "x86_64")]
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
 #[cfg(target_arch = "x86_64")]
-use crate::vstate::vcpu::VcpuState;
+use crate::vmm_config::machine_config::VmConfigError;
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vcpu::{VcpuConfig, VcpuState};
 use crate::vstate::{
     vcpu::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse},
     vm::Vm,
 };
 use arch::DeviceType;
+#[cfg(target_arch = "x86_64")]
+use devices::pseudo::CpuHotplug;
 use devices::virtio::balloon::Error as BalloonError;
+use devices::virtio::mem::Error as MemError;
+use devices::virtio::net::PacketCapture;
 use devices::virtio::{
-    Balloon, BalloonConfig, BalloonStats, Block, MmioTransport, Net, BALLOON_DEV_ID, TYPE_BALLOON,
-    TYPE_BLOCK, TYPE_NET,
+    Balloon, BalloonConfig, BalloonStats, Block, BlockDeviceLatencies, DiscardedPages,
+    HintingStatus, Mem, MemStatus, MmioTransport, Net, Vsock, VsockUnixBackend, BALLOON_DEV_ID,
+    MEM_DEV_ID, TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_VSOCK,
 };
 use devices::BusDevice;
 use logger::{error, info, warn, LoggerError, MetricsError, METRICS};
@@ -141,16 +148,28 @@ pub enum Error {
     VcpuEvent(vstate::vcpu::Error),
     /// Cannot create a vCPU handle.
     VcpuHandle(vstate::vcpu::Error),
+    /// Cannot notify the guest about vCPU hotplug.
+    #[cfg(target_arch = "x86_64")]
+    VcpuHotplugNotify(io::Error),
     /// vCPU pause failed.
     VcpuPause,
     /// vCPU exit failed.
     VcpuExit,
+    /// No parked vCPU is left to plug.
+    #[cfg(target_arch = "x86_64")]
+    VcpuPlug,
     /// vCPU resume failed.
     VcpuResume,
     /// Vcpu send message failed.
     VcpuMessage,
     /// Cannot spawn a new Vcpu thread.
     VcpuSpawn(io::Error),
+    /// No vCPU is left to unplug.
+    #[cfg(target_arch = "x86_64")]
+    VcpuUnplug,
+    /// The guest did not report the vCPUs to unplug as offline.
+    #[cfg(target_arch = "x86_64")]
+    VcpuUnplugOnline(u32),
     /// Vm error.
     Vm(vstate::vm::Error),
     /// Error thrown by observer object on Vmm initialization.
@@ -184,11 +203,23 @@ impl Display for Error {
             VcpuCreate(e) => write!(f, "Error creating the vcpu: {}", e),
             VcpuEvent(e) => write!(f, "Cannot send event to vCPU. {}", e),
             VcpuHandle(e) => write!(f, "Cannot create a vCPU handle. {}", e),
+            #[cfg(target_arch = "x86_64")]
+            VcpuHotplugNotify(e) => write!(f, "Cannot notify the guest about vCPU hotplug: {}", e),
             VcpuPause => write!(f, "Failed to pause the vCPUs."),
             VcpuExit => write!(f, "Failed to exit the vCPUs."),
+            #[cfg(target_arch = "x86_64")]
+            VcpuPlug => write!(f, "No parked vCPU left to plug."),
             VcpuResume => write!(f, "Failed to resume the vCPUs."),
             VcpuMessage => write!(f, "Failed to message the vCPUs."),
             VcpuSpawn(e) => write!(f, "Cannot spawn Vcpu thread: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            VcpuUnplug => write!(f, "No vCPU left to unplug."),
+            #[cfg(target_arch = "x86_64")]
+            VcpuUnplugOnline(cpus) => write!(
+                f,
+                "The guest did not offline the vCPUs to unplug (bitmap {:#x}).",
+                cpus
+            ),
             Vm(e) => write!(f, "Vm error: {}", e),
             VmmObserverInit(e) => write!(
                 f,
@@ -233,6 +264,14 @@ pub struct Vmm {
     guest_memory: GuestMemoryMmap,
 
     vcpus_handles: Vec<VcpuHandle>,
+    // The vcpus which can be hot-plugged are created at boot and kept here, paused, along with
+    // the ones removed from the guest. The last one is the next to be plugged.
+    #[cfg(target_arch = "x86_64")]
+    parked_vcpus_handles: Vec<VcpuHandle>,
+    #[cfg(target_arch = "x86_64")]
+    vcpu_config: VcpuConfig,
+    #[cfg(target_arch = "x86_64")]
+    vcpus_running: bool,
     exit_evt: EventFd,
     vm: Vm,
 
@@ -280,6 +319,15 @@ impl Vmm {
             );
         }
 
+        // The vcpus past the boot ones stay parked until they are hot-plugged.
+        #[cfg(target_arch = "x86_64")]
+        {
+            while self.vcpus_handles.len() > self.vcpu_config.vcpu_count as usize {
+                let handle = self.vcpus_handles.pop().expect("Missing vcpu handle");
+                self.parked_vcpus_handles.push(handle);
+            }
+        }
+
         Ok(())
     }
 
@@ -304,17 +352,147 @@ impl Vmm {
     pub fn resume_vm(&mut self) -> Result<()> {
         self.mmio_device_manager.kick_devices();
         self.broadcast_vcpu_event(VcpuEvent::Resume, VcpuResponse::Resumed)
-            .map_err(|_| Error::VcpuResume)
+            .map_err(|_| Error::VcpuResume)?;
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.vcpus_running = true;
+        }
+        Ok(())
     }
 
     /// Sends a pause command to the vCPUs.
     pub fn pause_vm(&mut self) -> Result<()> {
         self.broadcast_vcpu_event(VcpuEvent::Pause, VcpuResponse::Paused)
-            .map_err(|_| Error::VcpuPause)
+            .map_err(|_| Error::VcpuPause)?;
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.vcpus_running = false;
+        }
+        Ok(())
+    }
+
+    /// Grows or shrinks the set of vCPUs of the running microVM to `vcpu_count`.
+    ///
+    /// The vCPUs are taken from the ones parked at boot, which have the CPUID topology of the
+    /// boot-time configuration, and are started in the same state as the existing ones. The
+    /// guest is notified through the vCPU hotplug device and brings the new vCPUs online. vCPUs
+    /// are only removed once the guest reported them offline through the device.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_vcpu_count(&mut self, vcpu_count: u8) -> std::result::Result<(), VmConfigError> {
+        if vcpu_count == 0 || (self.vcpu_config.ht_enabled && vcpu_count > 1 && vcpu_count % 2 == 1)
+        {
+            return Err(VmConfigError::InvalidVcpuCount);
+        }
+        if vcpu_count > self.vcpu_config.max_vcpu_count {
+            return Err(VmConfigError::VcpuCountExceedsMax);
+        }
+        // The hotplug device is only attached to microVMs booted with spare vCPUs.
+        if self
+            .get_bus_device(DeviceType::CpuHotplug, &DeviceType::CpuHotplug.to_string())
+            .is_none()
+        {
+            return Err(VmConfigError::VcpuHotplug(
+                "the microVM was not booted with spare vCPUs".to_string(),
+            ));
+        }
+
+        let current_vcpu_count = self.vcpus_handles.len() as u8;
+        if vcpu_count > current_vcpu_count {
+            self.plug_vcpus(vcpu_count - current_vcpu_count)
+        } else {
+            self.unplug_vcpus(current_vcpu_count - vcpu_count)
+        }
+        .and_then(|()| self.notify_vcpu_hotplug())
+        .map_err(|e| VmConfigError::VcpuHotplug(e.to_string()))?;
+
+        info!("The microVM now has {} vCPUs.", vcpu_count);
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn plug_vcpus(&mut self, count: u8) -> Result<()> {
+        for _ in 0..count {
+            let handle = self.parked_vcpus_handles.pop().ok_or(Error::VcpuPlug)?;
+
+            // Parked vcpus are paused.
+            if self.vcpus_running {
+                handle
+                    .send_event(VcpuEvent::Resume)
+                    .map_err(Error::VcpuEvent)?;
+                match handle
+                    .response_receiver()
+                    .recv_timeout(Duration::from_millis(1000))
+                {
+                    Ok(VcpuResponse::Resumed) => (),
+                    _ => return Err(Error::VcpuResume),
+                }
+            }
+            self.vcpus_handles.push(handle);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn unplug_vcpus(&mut self, count: u8) -> Result<()> {
+        let vcpu_count = self.vcpus_handles.len() as u8;
+        let new_vcpu_count = vcpu_count.checked_sub(count).ok_or(Error::VcpuUnplug)?;
+        // A vCPU still online in the guest may have work scheduled on it, so refuse to take
+        // it away.
+        let online_cpus = self.with_cpu_hotplug(|cpu_hotplug| cpu_hotplug.online_cpus())
+            & CpuHotplug::cpus_mask(vcpu_count)
+            & !CpuHotplug::cpus_mask(new_vcpu_count);
+        if online_cpus != 0 {
+            return Err(Error::VcpuUnplugOnline(online_cpus));
+        }
+
+        for _ in 0..count {
+            let handle = self.vcpus_handles.pop().ok_or(Error::VcpuUnplug)?;
+            handle
+                .send_event(VcpuEvent::Pause)
+                .map_err(Error::VcpuEvent)?;
+            match handle
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Paused) => (),
+                _ => return Err(Error::VcpuPause),
+            }
+            self.parked_vcpus_handles.push(handle);
+        }
+        Ok(())
+    }
+
+    // Lets the guest know that the set of present vcpus changed.
+    #[cfg(target_arch = "x86_64")]
+    fn notify_vcpu_hotplug(&self) -> Result<()> {
+        let vcpu_count = self.vcpus_handles.len() as u8;
+        self.with_cpu_hotplug(|cpu_hotplug| cpu_hotplug.set_vcpu_count(vcpu_count))
+            .map_err(Error::VcpuHotplugNotify)
+    }
+
+    // Runs `f` on the vCPU hotplug device.
+    #[cfg(target_arch = "x86_64")]
+    fn with_cpu_hotplug<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut CpuHotplug) -> T,
+    {
+        let busdev = self
+            .get_bus_device(DeviceType::CpuHotplug, &DeviceType::CpuHotplug.to_string())
+            .expect("Missing vCPU hotplug device");
+        let mut locked_busdev = busdev.lock().expect("Poisoned lock");
+        f(locked_busdev
+            .as_mut_any()
+            .downcast_mut::<CpuHotplug>()
+            .expect("Unexpected BusDevice type"))
     }
 
     /// Sends an exit command to the vCPUs.
     pub fn exit_vcpus(&mut self) -> Result<()> {
+        // The parked vcpus run threads of their own, which have to exit as well.
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.vcpus_handles.append(&mut self.parked_vcpus_handles);
+        }
         self.broadcast_vcpu_event(
             VcpuEvent::Exit,
             VcpuResponse::Exited(FC_EXIT_CODE_GENERIC_ERROR),
@@ -347,6 +525,8 @@ impl Vmm {
             }
         }
 
+        self.stop_block_io_threads();
+
         // Write the metrics before exiting.
         if let Err(e) = METRICS.write() {
             error!("Failed to write metrics while stopping: {}", e);
@@ -359,11 +539,50 @@ impl Vmm {
         }
     }
 
+    // Makes the I/O threads of the block devices exit, and waits for them.
+    fn stop_block_io_threads(&self) {
+        let mut handles = Vec::new();
+        let _: std::result::Result<(), ()> =
+            self.mmio_device_manager
+                .for_each_device(|device_type, _, _, bus_device| {
+                    if *device_type != DeviceType::Virtio(TYPE_BLOCK) {
+                        return Ok(());
+                    }
+
+                    let virtio_device = bus_device
+                        .lock()
+                        .expect("Poisoned lock")
+                        .as_any()
+                        .downcast_ref::<MmioTransport>()
+                        // Only MmioTransport implements BusDevice at this point.
+                        .expect("Unexpected BusDevice type")
+                        .device();
+
+                    // The device lock is released before joining the threads, which may be
+                    // waiting for it.
+                    let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+                    handles.extend(
+                        locked_device
+                            .as_mut_any()
+                            .downcast_mut::<Block>()
+                            .unwrap()
+                            .stop_io_threads(),
+                    );
+                    Ok(())
+                });
+
+        for handle in handles {
+            if handle.join().is_err() {
+                error!("A block I/O thread panicked.");
+            }
+        }
+    }
+
     /// Saves the state of a paused Microvm.
     #[cfg(target_arch = "x86_64")]
     pub fn save_state(&mut self) -> std::result::Result<MicrovmState, MicrovmStateError> {
         use self::MicrovmStateError::SaveVmState;
-        let vcpu_states = self.save_vcpu_states()?;
+        let (vcpu_states, parked_vcpu_states) = self.save_vcpu_states()?;
 
         let vm_state = self.vm.save_state().map_err(SaveVmState)?;
 
@@ -378,21 +597,27 @@ impl Vmm {
             vm_state,
             vcpu_states,
             device_states,
+            parked_vcpu_states,
         })
     }
 
+    // Saves the states of the running vcpus, followed by the ones of the parked vcpus.
     #[cfg(target_arch = "x86_64")]
-    fn save_vcpu_states(&mut self) -> std::result::Result<Vec<VcpuState>, MicrovmStateError> {
+    fn save_vcpu_states(
+        &mut self,
+    ) -> std::result::Result<(Vec<VcpuState>, Vec<VcpuState>), MicrovmStateError> {
         use self::MicrovmStateError::*;
-        for handle in self.vcpus_handles.iter() {
+        let handles = self
+            .vcpus_handles
+            .iter()
+            .chain(self.parked_vcpus_handles.iter());
+        for handle in handles.clone() {
             handle
                 .send_event(VcpuEvent::SaveState)
                 .map_err(SignalVcpu)?;
         }
 
-        let vcpu_responses = self
-            .vcpus_handles
-            .iter()
+        let vcpu_responses = handles
             // `Iterator::collect` can transform a `Vec<Result>` into a `Result<Vec>`.
             .map(|handle| {
                 handle
@@ -402,7 +627,7 @@ impl Vmm {
             .collect::<std::result::Result<Vec<VcpuResponse>, RecvTimeoutError>>()
             .map_err(|_| UnexpectedVcpuResponse)?;
 
-        let vcpu_states = vcpu_responses
+        let mut vcpu_states = vcpu_responses
             .into_iter()
             .map(|response| match response {
                 VcpuResponse::SavedState(state) => Ok(*state),
@@ -411,8 +636,9 @@ impl Vmm {
                 _ => Err(UnexpectedVcpuResponse),
             })
             .collect::<std::result::Result<Vec<VcpuState>, MicrovmStateError>>()?;
+        let parked_vcpu_states = vcpu_states.split_off(self.vcpus_handles.len());
 
-        Ok(vcpu_states)
+        Ok((vcpu_states, parked_vcpu_states))
     }
 
     // Sends an event to all vCPUs and waits for a response.
@@ -432,25 +658,33 @@ impl Vmm {
     }
 
     #[cfg(target_arch = "x86_64")]
-    /// Restores vcpus kvm states.
+    /// Restores the kvm states of the running vcpus and of the parked ones.
     pub fn restore_vcpu_states(
         &mut self,
-        mut vcpu_states: Vec<VcpuState>,
+        vcpu_states: Vec<VcpuState>,
+        parked_vcpu_states: Vec<VcpuState>,
     ) -> std::result::Result<(), MicrovmStateError> {
         use self::MicrovmStateError::*;
 
-        if vcpu_states.len() != self.vcpus_handles.len() {
+        if vcpu_states.len() != self.vcpus_handles.len()
+            || parked_vcpu_states.len() != self.parked_vcpus_handles.len()
+        {
             return Err(InvalidInput);
         }
-        for (handle, state) in self.vcpus_handles.iter().zip(vcpu_states.drain(..)) {
+        let handles = self
+            .vcpus_handles
+            .iter()
+            .chain(self.parked_vcpus_handles.iter());
+        for (handle, state) in handles
+            .clone()
+            .zip(vcpu_states.into_iter().chain(parked_vcpu_states))
+        {
             handle
                 .send_event(VcpuEvent::RestoreState(Box::new(state)))
                 .map_err(MicrovmStateError::SignalVcpu)?;
         }
 
-        let vcpu_responses = self
-            .vcpus_handles
-            .iter()
+        let vcpu_responses = handles
             // `Iterator::collect` can transform a `Vec<Result>` into a `Result<Vec>`.
             .map(|handle| {
                 handle
@@ -497,6 +731,30 @@ impl Vmm {
         Ok(bitmap)
     }
 
+    /// Returns the pages discarded through the balloon device since the previous call.
+    pub fn take_discarded_pages(&self) -> DiscardedPages {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+            locked_device
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .take_discarded_pages()
+        } else {
+            DiscardedPages::new()
+        }
+    }
+
     /// Enables or disables KVM dirty page tracking.
     pub fn set_dirty_page_tracking(&mut self, enable: bool) -> Result<()> {
         // This function _always_ results in an ioctl update. The VMM is stateless in the sense
@@ -521,6 +779,39 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Updates the size of the emulated block device with id `drive_id` after its backing file
+    /// was resized, and notifies the guest.
+    pub fn update_block_device_size(&mut self, drive_id: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block.refresh_size().map_err(|e| e.to_string())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Returns the request latencies of the block device with id `drive_id`.
+    pub fn block_device_latencies(&self, drive_id: &str) -> Result<BlockDeviceLatencies> {
+        let mut latencies = None;
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                latencies = Some(block.latencies());
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        // The closure ran if the device was found.
+        Ok(latencies.unwrap_or_default())
+    }
+
+    /// Writes the current content of the copy-on-write overlay of the block device with
+    /// `drive_id` id to a new overlay file at `overlay_path`.
+    pub fn save_block_overlay(&self, drive_id: &str, overlay_path: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block.save_overlay(overlay_path).map_err(|e| e.to_string())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for net device with `net_id` id.
     pub fn update_net_rate_limiters(
         &mut self,
@@ -538,6 +829,61 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Checks that the link of the net device with `net_id` id can be updated, asking the guest
+    /// to announce itself if `announce` is set.
+    pub fn check_net_link_update(&mut self, net_id: &str, announce: bool) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.check_link_update(announce)
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Updates the link state reported to the guest by the net device with `net_id` id, and
+    /// asks the guest to announce itself if `announce` is set.
+    pub fn update_net_link(
+        &mut self,
+        net_id: &str,
+        link_up: Option<bool>,
+        announce: bool,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.update_link(link_up, announce)
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Replaces the host backend of the net device with `net_id` id by `host_dev_name`.
+    pub fn update_net_backend(&mut self, net_id: &str, host_dev_name: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.swap_backend(host_dev_name)
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Starts capturing the frames of the net device with `net_id` id into `capture`.
+    pub fn start_net_capture(&mut self, net_id: &str, capture: PacketCapture) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.start_capture(capture).map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Stops the packet capture running on the net device with `net_id` id.
+    pub fn stop_net_capture(&mut self, net_id: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.stop_capture().map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> std::result::Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -565,6 +911,35 @@ impl Vmm {
         }
     }
 
+    /// Resets the connections of the vsock device, if any: the host-side connections are
+    /// closed and the guest driver is sent a transport reset event. The host-side listening
+    /// sockets are kept.
+    pub fn reset_vsock_connections(&self) -> std::result::Result<(), devices::Error> {
+        self.mmio_device_manager
+            .for_each_device(|device_type, _, _, bus_device| {
+                if *device_type != DeviceType::Virtio(TYPE_VSOCK) {
+                    return Ok(());
+                }
+
+                let virtio_device = bus_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_any()
+                    .downcast_ref::<MmioTransport>()
+                    // Only MmioTransport implements BusDevice at this point.
+                    .expect("Unexpected BusDevice type")
+                    .device();
+
+                let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+                locked_device
+                    .as_mut_any()
+                    // Currently, VsockUnixBackend is the only implementation of VsockBackend.
+                    .downcast_mut::<Vsock<VsockUnixBackend>>()
+                    .unwrap()
+                    .reset_transport()
+            })
+    }
+
     /// Returns the latest balloon statistics if they are enabled.
     pub fn latest_balloon_stats(&self) -> std::result::Result<BalloonStats, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -665,6 +1040,112 @@ impl Vmm {
             Err(BalloonError::DeviceNotFound)
         }
     }
+
+    /// Asks the guest to start hinting its free pages to the balloon device.
+    pub fn start_balloon_hinting(
+        &mut self,
+        acknowledge_on_stop: bool,
+    ) -> std::result::Result<(), BalloonError> {
+        self.with_balloon(|balloon| balloon.start_hinting(acknowledge_on_stop))
+    }
+
+    /// Completes the free page hinting run of the balloon device.
+    pub fn stop_balloon_hinting(&mut self) -> std::result::Result<(), BalloonError> {
+        self.with_balloon(|balloon| balloon.stop_hinting())
+    }
+
+    /// Returns the progress of the free page hinting run of the balloon device.
+    pub fn balloon_hinting_status(&self) -> std::result::Result<HintingStatus, BalloonError> {
+        self.with_balloon(|balloon| balloon.hinting_status())
+    }
+
+    fn with_balloon<T, F>(&self, f: F) -> std::result::Result<T, BalloonError>
+    where
+        F: FnOnce(&mut Balloon) -> std::result::Result<T, BalloonError>,
+    {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+            f(locked_device
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Returns the current sizes of the memory hotplug device.
+    pub fn mem_device_status(&self) -> std::result::Result<MemStatus, MemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let status = virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Mem>()
+                .unwrap()
+                .status();
+
+            Ok(status)
+        } else {
+            Err(MemError::DeviceNotFound)
+        }
+    }
+
+    /// Updates the amount of memory the guest is asked to plug through the memory
+    /// hotplug device.
+    pub fn update_mem_device_requested_size(
+        &mut self,
+        requested_size_mib: u64,
+    ) -> std::result::Result<(), MemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            {
+                let virtio_device = busdev
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_any()
+                    .downcast_ref::<MmioTransport>()
+                    // Only MmioTransport implements BusDevice at this point.
+                    .expect("Unexpected BusDevice type")
+                    .device();
+
+                virtio_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_mut_any()
+                    .downcast_mut::<Mem>()
+                    .unwrap()
+                    .update_requested_size(requested_size_mib)?;
+            }
+
+            // The driver re-reads the config space and plugs or unplugs memory
+            // to reach the new requested size.
+            let locked_dev = busdev.lock().expect("Poisoned lock");
+            locked_dev
+                .interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG)
+                .map_err(MemError::InterruptError)
+        } else {
+            Err(MemError::DeviceNotFound)
+        }
+    }
 }
 
 impl Subscriber for Vmm {
@@ -6,19 +6,129 @@
 // Currently only used on x86_64.
 #![cfg(target_arch = "x86_64")]
 
+use std::cmp;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::SeekFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
 
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use devices::virtio::DiscardedPages;
+use versionize::crc::CRC64Writer;
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{
     Bytes, FileOffset, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
     GuestMemoryRegion, GuestRegionMmap, MemoryRegionAddress, MmapRegion,
 };
 
+use crate::vmm_config::snapshot::MemFileFormat;
 use crate::DirtyBitmap;
 
+/// Magic number identifying a chunked memory file.
+pub const CHUNKED_MAGIC: &[u8; 8] = b"FCMEMCHK";
+/// Version of the chunked memory file format.
+const CHUNKED_VERSION: u32 = 1;
+/// Amount of guest memory described by a chunk, the last chunk of a region
+/// can be smaller.
+pub const CHUNK_SIZE: usize = 64 << 10;
+// Size of the header: magic, version, chunk size and number of chunks.
+const CHUNKED_HEADER_SIZE: usize = 24;
+// Size of a chunk descriptor: kind, stored length, offset and checksum.
+const CHUNK_DESCRIPTOR_SIZE: usize = 24;
+// The zstd compression level used for the chunks.
+const COMPRESSION_LEVEL: i32 = 3;
+// Flags of the /proc/self/pagemap entries, see the kernel's
+// Documentation/admin-guide/mm/pagemap.rst.
+const PAGEMAP_ENTRY_SIZE: usize = 8;
+const PAGEMAP_PRESENT: u64 = 1 << 63;
+const PAGEMAP_SWAPPED: u64 = 1 << 62;
+
+/// The ways a chunk of guest memory can be stored in a chunked memory file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkKind {
+    /// The chunk only contains zeroes and is not stored.
+    Zero = 0,
+    /// The chunk is stored as is.
+    Raw = 1,
+    /// The chunk is compressed with zstd.
+    Zstd = 2,
+}
+
+/// Describes where a chunk of guest memory is stored in a chunked memory file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkDescriptor {
+    /// How the chunk is stored.
+    pub kind: ChunkKind,
+    /// Number of bytes the chunk takes in the file.
+    pub len: u32,
+    /// Offset of the chunk in the file.
+    pub offset: u64,
+    /// CRC64 of the uncompressed chunk.
+    pub checksum: u64,
+}
+
+impl ChunkDescriptor {
+    fn to_bytes(self) -> [u8; CHUNK_DESCRIPTOR_SIZE] {
+        let mut bytes = [0u8; CHUNK_DESCRIPTOR_SIZE];
+        bytes[0..4].copy_from_slice(&(self.kind as u32).to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, Error> {
+        let kind = match read_u32(&bytes[0..4]) {
+            0 => ChunkKind::Zero,
+            1 => ChunkKind::Raw,
+            2 => ChunkKind::Zstd,
+            _ => return Err(Error::InvalidChunkedFile("unknown chunk kind")),
+        };
+        Ok(ChunkDescriptor {
+            kind,
+            len: read_u32(&bytes[4..8]),
+            offset: read_u64(&bytes[8..16]),
+            checksum: read_u64(&bytes[16..24]),
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut crc_writer = CRC64Writer::new(std::io::sink());
+    // Writing to a sink cannot fail.
+    crc_writer.write_all(data).unwrap();
+    crc_writer.checksum()
+}
+
+// Returns the guest address and size of the chunks the regions described by
+// `state` are split in.
+fn chunks(state: &GuestMemoryState) -> Vec<(GuestAddress, usize)> {
+    state
+        .regions
+        .iter()
+        .flat_map(|region| {
+            (0..region.size).step_by(CHUNK_SIZE).map(move |pos| {
+                (
+                    GuestAddress(region.base_address + pos as u64),
+                    std::cmp::min(CHUNK_SIZE, region.size - pos),
+                )
+            })
+        })
+        .collect()
+}
+
 /// State of a guest memory region saved to file/buffer.
 #[derive(Debug, PartialEq, Versionize)]
 pub struct GuestMemoryRegionState {
@@ -35,6 +145,29 @@ pub struct GuestMemoryRegionState {
 pub struct GuestMemoryState {
     /// List of regions.
     pub regions: Vec<GuestMemoryRegionState>,
+    /// Format of the file the guest memory is saved to.
+    #[version(
+        start = 2,
+        default_fn = "default_file_format",
+        ser_fn = "file_format_serialize"
+    )]
+    pub file_format: MemFileFormat,
+}
+
+impl GuestMemoryState {
+    fn file_format_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.file_format != MemFileFormat::Raw {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement memory file formats other than raw.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_file_format(_: u16) -> MemFileFormat {
+        MemFileFormat::Raw
+    }
 }
 
 /// Defines the interface for snapshotting memory.
@@ -52,6 +185,14 @@ where
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> std::result::Result<(), Error>;
+    /// Dumps all contents of GuestMemoryMmap to a writer as checksummed
+    /// chunks, leaving out the chunks that only contain zeroes and
+    /// optionally compressing the others.
+    fn dump_chunked<T: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut T,
+        compress: bool,
+    ) -> std::result::Result<(), Error>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -59,6 +200,83 @@ where
         state: &GuestMemoryState,
         track_dirty_pages: bool,
     ) -> std::result::Result<Self, Error>;
+    /// Creates a GuestMemoryMmap given a chunked `file` containing the data
+    /// and a `state` containing mapping information, validating the
+    /// checksum of every chunk.
+    fn restore_chunked(
+        file: &mut File,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+    ) -> std::result::Result<Self, Error>;
+}
+
+/// Clears from `dirty_bitmap`, and from the dirty bitmaps of the guest memory
+/// regions, the `discarded_pages` which were not touched since they were
+/// discarded. Those pages are not backed by host memory anymore and the guest
+/// does not rely on their content, so diff snapshots can skip them.
+pub fn clear_discarded_pages(
+    guest_memory: &GuestMemoryMmap,
+    dirty_bitmap: &mut DirtyBitmap,
+    discarded_pages: &DiscardedPages,
+) -> std::result::Result<(), Error> {
+    if discarded_pages.is_empty() {
+        return Ok(());
+    }
+
+    let page_size = sysconf::page::pagesize();
+    let mut pagemap = File::open("/proc/self/pagemap").map_err(Error::FileHandle)?;
+    let mut entries = [0u8; 64 * PAGEMAP_ENTRY_SIZE];
+
+    guest_memory.with_regions_mut(|slot, region| {
+        let (discarded_bitmap, kvm_bitmap) =
+            match (discarded_pages.get(&slot), dirty_bitmap.get_mut(&slot)) {
+                (Some(discarded_bitmap), Some(kvm_bitmap)) => (discarded_bitmap, kvm_bitmap),
+                _ => return Ok(()),
+            };
+        let region_pages = region.len() as usize / page_size;
+        let first_entry = region.as_ptr() as usize / page_size;
+
+        for (i, (discarded, dirty)) in discarded_bitmap
+            .iter()
+            .zip(kvm_bitmap.iter_mut())
+            .enumerate()
+        {
+            if *discarded == 0 || i * 64 >= region_pages {
+                continue;
+            }
+
+            // The pagemap entries of the 64 pages covered by the bitmap word.
+            let word_pages = cmp::min(64, region_pages - i * 64);
+            let word_entries = &mut entries[..word_pages * PAGEMAP_ENTRY_SIZE];
+            pagemap
+                .seek(SeekFrom::Start(
+                    ((first_entry + i * 64) * PAGEMAP_ENTRY_SIZE) as u64,
+                ))
+                .map_err(Error::FileHandle)?;
+            pagemap
+                .read_exact(word_entries)
+                .map_err(Error::FileHandle)?;
+
+            for j in 0..word_pages {
+                if (discarded >> j) & 1 == 0 {
+                    continue;
+                }
+                let mut entry = [0u8; PAGEMAP_ENTRY_SIZE];
+                entry.copy_from_slice(
+                    &word_entries[j * PAGEMAP_ENTRY_SIZE..(j + 1) * PAGEMAP_ENTRY_SIZE],
+                );
+                // A page which is neither present nor swapped was not touched
+                // by the guest or by the devices since it was discarded.
+                if u64::from_ne_bytes(entry) & (PAGEMAP_PRESENT | PAGEMAP_SWAPPED) == 0 {
+                    *dirty &= !(1u64 << j);
+                    if let Some(bitmap) = region.dirty_bitmap() {
+                        bitmap.reset_addr_range((i * 64 + j) * page_size, page_size);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
 }
 
 /// Errors associated with dumping guest memory to file.
@@ -70,6 +288,14 @@ pub enum Error {
     CreateMemory(vm_memory::Error),
     /// Cannot create region.
     CreateRegion(vm_memory::mmap::MmapRegionError),
+    /// The checksum of a memory chunk does not match its content.
+    ChunkChecksum(usize),
+    /// Cannot compress or decompress a memory chunk.
+    Compression(std::io::Error),
+    /// The chunked memory file is malformed.
+    InvalidChunkedFile(&'static str),
+    /// Cannot read memory.
+    ReadMemory(GuestMemoryError),
     /// Cannot dump memory.
     WriteMemory(GuestMemoryError),
 }
@@ -81,6 +307,10 @@ impl Display for Error {
             FileHandle(err) => write!(f, "Cannot access file: {:?}", err),
             CreateMemory(err) => write!(f, "Cannot create memory: {:?}", err),
             CreateRegion(err) => write!(f, "Cannot create memory region: {:?}", err),
+            ChunkChecksum(index) => write!(f, "Checksum mismatch for memory chunk {}", index),
+            Compression(err) => write!(f, "Cannot (de)compress memory chunk: {:?}", err),
+            InvalidChunkedFile(msg) => write!(f, "Invalid chunked memory file: {}", msg),
+            ReadMemory(err) => write!(f, "Cannot read memory: {:?}", err),
             WriteMemory(err) => write!(f, "Cannot dump memory: {:?}", err),
         }
     }
@@ -166,6 +396,73 @@ impl SnapshotMemory for GuestMemoryMmap {
         .map_err(Error::WriteMemory)
     }
 
+    /// Dumps all contents of GuestMemoryMmap to a writer as checksummed
+    /// chunks, leaving out the chunks that only contain zeroes and
+    /// optionally compressing the others. Uncompressed chunks are page
+    /// aligned in the file, so they can be mapped when restoring.
+    fn dump_chunked<T: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut T,
+        compress: bool,
+    ) -> std::result::Result<(), Error> {
+        let page_size = sysconf::page::pagesize() as u64;
+        let num_chunks = chunks(&self.describe()).len();
+        let mut descriptors = Vec::with_capacity(num_chunks);
+        let mut buf = vec![0u8; CHUNK_SIZE];
+
+        // The chunks follow the header and the index, which are written last
+        // since the stored length of the chunks is not known in advance.
+        let mut offset = (CHUNKED_HEADER_SIZE + num_chunks * CHUNK_DESCRIPTOR_SIZE) as u64;
+        writer
+            .seek(SeekFrom::Start(offset))
+            .map_err(Error::FileHandle)?;
+        self.with_regions_mut(|_, region| -> std::result::Result<(), Error> {
+            let region_len = region.len() as usize;
+            for pos in (0..region_len).step_by(CHUNK_SIZE) {
+                let chunk = &mut buf[..std::cmp::min(CHUNK_SIZE, region_len - pos)];
+                region
+                    .read_slice(chunk, MemoryRegionAddress(pos as u64))
+                    .map_err(Error::ReadMemory)?;
+
+                let (kind, data) = if chunk.iter().all(|&byte| byte == 0) {
+                    (ChunkKind::Zero, Vec::new())
+                } else if compress {
+                    let data = zstd::encode_all(&chunk[..], COMPRESSION_LEVEL)
+                        .map_err(Error::Compression)?;
+                    (ChunkKind::Zstd, data)
+                } else {
+                    offset = (offset + page_size - 1) & !(page_size - 1);
+                    writer
+                        .seek(SeekFrom::Start(offset))
+                        .map_err(Error::FileHandle)?;
+                    (ChunkKind::Raw, chunk.to_vec())
+                };
+                writer.write_all(&data).map_err(Error::FileHandle)?;
+
+                descriptors.push(ChunkDescriptor {
+                    kind,
+                    len: data.len() as u32,
+                    offset,
+                    checksum: checksum(chunk),
+                });
+                offset += data.len() as u64;
+            }
+            Ok(())
+        })?;
+
+        let mut header = Vec::with_capacity(CHUNKED_HEADER_SIZE);
+        header.extend_from_slice(CHUNKED_MAGIC);
+        header.extend_from_slice(&CHUNKED_VERSION.to_le_bytes());
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&(num_chunks as u64).to_le_bytes());
+        for descriptor in descriptors.iter() {
+            header.extend_from_slice(&descriptor.to_bytes());
+        }
+        writer.seek(SeekFrom::Start(0)).map_err(Error::FileHandle)?;
+        writer.write_all(&header).map_err(Error::FileHandle)?;
+        writer.flush().map_err(Error::FileHandle)
+    }
+
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -199,6 +496,162 @@ impl SnapshotMemory for GuestMemoryMmap {
 
         Ok(Self::from_regions(mmap_regions).map_err(Error::CreateMemory)?)
     }
+
+    /// Creates a GuestMemoryMmap given a chunked `file` containing the data
+    /// and a `state` containing mapping information, validating the
+    /// checksum of every chunk. Page aligned uncompressed chunks are mapped
+    /// privately from the file and zero chunks are left as untouched
+    /// anonymous memory, so only the compressed chunks are copied.
+    fn restore_chunked(
+        file: &mut File,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+    ) -> std::result::Result<Self, Error> {
+        let chunks = chunks(state);
+        let descriptors = read_chunk_descriptors(file, chunks.len())?;
+
+        let ranges: Vec<(GuestAddress, usize)> = state
+            .regions
+            .iter()
+            .map(|region| (GuestAddress(region.base_address), region.size))
+            .collect();
+        let guest_memory = if track_dirty_pages {
+            Self::from_ranges_with_tracking(&ranges)
+        } else {
+            Self::from_ranges(&ranges)
+        }
+        .map_err(Error::CreateMemory)?;
+
+        let page_size = sysconf::page::pagesize() as u64;
+        let mut data = Vec::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        for (index, ((addr, size), descriptor)) in chunks.into_iter().zip(descriptors).enumerate() {
+            let chunk = &mut buf[..size];
+            match descriptor.kind {
+                // Anonymous memory is already zeroed.
+                ChunkKind::Zero if descriptor.len == 0 => {
+                    for byte in chunk.iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                ChunkKind::Zero => return Err(Error::InvalidChunkedFile("zero chunk with data")),
+                ChunkKind::Raw if descriptor.len as usize != size => {
+                    return Err(Error::InvalidChunkedFile("chunk size mismatch"))
+                }
+                ChunkKind::Raw if descriptor.offset % page_size == 0 => {
+                    map_chunk(&guest_memory, addr, size, file, descriptor.offset)?;
+                    // Reading the mapping goes through the page cache, without
+                    // copying the chunk to anonymous memory.
+                    guest_memory
+                        .read_slice(chunk, addr)
+                        .map_err(Error::ReadMemory)?;
+                }
+                ChunkKind::Raw | ChunkKind::Zstd => {
+                    data.resize(descriptor.len as usize, 0);
+                    file.seek(SeekFrom::Start(descriptor.offset))
+                        .map_err(Error::FileHandle)?;
+                    file.read_exact(&mut data).map_err(Error::FileHandle)?;
+                    if descriptor.kind == ChunkKind::Zstd {
+                        data = zstd::decode_all(&data[..]).map_err(Error::Compression)?;
+                        if data.len() != size {
+                            return Err(Error::InvalidChunkedFile("chunk size mismatch"));
+                        }
+                    }
+                    chunk.copy_from_slice(&data);
+                    guest_memory
+                        .write_slice(chunk, addr)
+                        .map_err(Error::WriteMemory)?;
+                }
+            }
+            if checksum(chunk) != descriptor.checksum {
+                return Err(Error::ChunkChecksum(index));
+            }
+        }
+
+        // Filling in the memory does not count as a guest write.
+        if track_dirty_pages {
+            let _: std::result::Result<(), ()> = guest_memory.with_regions_mut(|_, region| {
+                region.dirty_bitmap().unwrap().reset();
+                Ok(())
+            });
+        }
+
+        Ok(guest_memory)
+    }
+}
+
+// Reads the header of a chunked memory `file` holding `num_chunks` chunks, and
+// returns the descriptors of the chunks.
+fn read_chunk_descriptors(
+    file: &mut File,
+    num_chunks: usize,
+) -> std::result::Result<Vec<ChunkDescriptor>, Error> {
+    let file_len = file.metadata().map_err(Error::FileHandle)?.len();
+    let mut header = vec![0u8; CHUNKED_HEADER_SIZE + num_chunks * CHUNK_DESCRIPTOR_SIZE];
+    file.seek(SeekFrom::Start(0)).map_err(Error::FileHandle)?;
+    file.read_exact(&mut header[..CHUNKED_HEADER_SIZE])
+        .map_err(Error::FileHandle)?;
+    if &header[0..8] != CHUNKED_MAGIC {
+        return Err(Error::InvalidChunkedFile("bad magic"));
+    }
+    if read_u32(&header[8..12]) != CHUNKED_VERSION {
+        return Err(Error::InvalidChunkedFile("unsupported version"));
+    }
+    if read_u32(&header[12..16]) as usize != CHUNK_SIZE {
+        return Err(Error::InvalidChunkedFile("unsupported chunk size"));
+    }
+    if read_u64(&header[16..24]) != num_chunks as u64 {
+        return Err(Error::InvalidChunkedFile(
+            "number of chunks does not match the memory state",
+        ));
+    }
+    file.read_exact(&mut header[CHUNKED_HEADER_SIZE..])
+        .map_err(Error::FileHandle)?;
+
+    header[CHUNKED_HEADER_SIZE..]
+        .chunks(CHUNK_DESCRIPTOR_SIZE)
+        .map(|bytes| {
+            let descriptor = ChunkDescriptor::from_bytes(bytes)?;
+            if descriptor
+                .offset
+                .checked_add(u64::from(descriptor.len))
+                .map_or(true, |end| end > file_len)
+            {
+                return Err(Error::InvalidChunkedFile("chunk out of bounds"));
+            }
+            Ok(descriptor)
+        })
+        .collect()
+}
+
+// Replaces the `size` bytes of guest memory at `addr` with a private mapping of
+// `file` at `offset`.
+fn map_chunk(
+    guest_memory: &GuestMemoryMmap,
+    addr: GuestAddress,
+    size: usize,
+    file: &File,
+    offset: u64,
+) -> std::result::Result<(), Error> {
+    let host_addr = guest_memory
+        .get_host_address(addr)
+        .map_err(Error::WriteMemory)?;
+    // Safe because the chunk lies within a guest memory region, whose mapping
+    // is replaced by one of the same size and protection.
+    let ret = unsafe {
+        libc::mmap(
+            host_addr as *mut libc::c_void,
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_FIXED | libc::MAP_NORESERVE,
+            file.as_raw_fd(),
+            offset as libc::off_t,
+        )
+    };
+    if ret == libc::MAP_FAILED {
+        return Err(Error::FileHandle(std::io::Error::last_os_error()));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -234,6 +687,7 @@ mod tests {
                     offset: page_size as u64,
                 },
             ],
+            file_format: MemFileFormat::Raw,
         };
 
         let actual_memory_state = guest_memory.describe();
@@ -259,12 +713,50 @@ mod tests {
                     offset: page_size as u64 * 3,
                 },
             ],
+            file_format: MemFileFormat::Raw,
         };
 
         let actual_memory_state = guest_memory.describe();
         assert_eq!(expected_memory_state, actual_memory_state);
     }
 
+    #[test]
+    fn test_clear_discarded_pages() {
+        let page_size: usize = sysconf::page::pagesize();
+        let guest_memory =
+            GuestMemoryMmap::from_ranges_with_tracking(&[(GuestAddress(0), page_size * 4)])
+                .unwrap();
+
+        // The first two pages are written, then the second one is discarded.
+        guest_memory
+            .write(&vec![1u8; page_size * 2], GuestAddress(0))
+            .unwrap();
+        let host_addr = guest_memory
+            .get_host_address(GuestAddress(page_size as u64))
+            .unwrap();
+        assert_eq!(
+            unsafe { libc::madvise(host_addr as *mut _, page_size, libc::MADV_DONTNEED) },
+            0
+        );
+
+        // The first three pages were discarded, but the guest wrote the first
+        // one again. All the pages are dirty.
+        let mut discarded_pages = DiscardedPages::new();
+        discarded_pages.insert(0, vec![0b0111]);
+        let mut dirty_bitmap: DirtyBitmap = HashMap::new();
+        dirty_bitmap.insert(0, vec![0b1111]);
+
+        clear_discarded_pages(&guest_memory, &mut dirty_bitmap, &discarded_pages).unwrap();
+        assert_eq!(dirty_bitmap[&0], vec![0b1001]);
+        let region = guest_memory.find_region(GuestAddress(0)).unwrap();
+        assert!(region.dirty_bitmap().unwrap().is_addr_set(0));
+        assert!(!region.dirty_bitmap().unwrap().is_addr_set(page_size));
+
+        // Nothing changes without discarded pages.
+        clear_discarded_pages(&guest_memory, &mut dirty_bitmap, &DiscardedPages::new()).unwrap();
+        assert_eq!(dirty_bitmap[&0], vec![0b1001]);
+    }
+
     #[test]
     fn test_restore_memory() {
         let page_size: usize = sysconf::page::pagesize();
@@ -382,4 +874,128 @@ mod tests {
             assert_eq!(expected_first_region, diff_file_content);
         }
     }
+
+    #[test]
+    fn test_chunked_memory() {
+        // Two regions with a gap between them, the second one ending with a
+        // partial chunk.
+        let mem_regions = [
+            (GuestAddress(0), CHUNK_SIZE * 2),
+            (GuestAddress(CHUNK_SIZE as u64 * 3), CHUNK_SIZE + 0x1000),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        // Only the first chunk of each region holds data, the others are zero.
+        let first_chunk = vec![1u8; CHUNK_SIZE];
+        guest_memory
+            .write(&first_chunk[..], GuestAddress(0))
+            .unwrap();
+        let last_chunk = vec![2u8; 0x1000];
+        guest_memory
+            .write(&last_chunk[..], GuestAddress(CHUNK_SIZE as u64 * 4))
+            .unwrap();
+        let memory_state = guest_memory.describe();
+
+        for &compress in [false, true].iter() {
+            let mut file = TempFile::new().unwrap().into_file();
+            guest_memory.dump_chunked(&mut file, compress).unwrap();
+
+            let page_size = sysconf::page::pagesize();
+            let index_end = CHUNKED_HEADER_SIZE + 4 * CHUNK_DESCRIPTOR_SIZE;
+            let file_len = file.metadata().unwrap().len() as usize;
+            let first_chunk_offset = read_chunk_descriptors(&mut file, 4).unwrap()[0].offset;
+            if compress {
+                assert!(file_len < index_end + CHUNK_SIZE);
+            } else {
+                // The zero chunks are left out, and the others are page aligned.
+                assert_eq!(first_chunk_offset, page_size as u64);
+                assert_eq!(file_len, page_size + CHUNK_SIZE + 0x1000);
+            }
+
+            let restored_guest_memory =
+                GuestMemoryMmap::restore_chunked(&mut file, &memory_state, true).unwrap();
+            let mut actual = vec![0u8; CHUNK_SIZE * 2];
+            restored_guest_memory
+                .read_slice(&mut actual, GuestAddress(0))
+                .unwrap();
+            assert_eq!(&actual[..CHUNK_SIZE], &first_chunk[..]);
+            assert!(actual[CHUNK_SIZE..].iter().all(|&byte| byte == 0));
+            let mut actual = vec![0u8; CHUNK_SIZE + 0x1000];
+            restored_guest_memory
+                .read_slice(&mut actual, GuestAddress(CHUNK_SIZE as u64 * 3))
+                .unwrap();
+            assert!(actual[..CHUNK_SIZE].iter().all(|&byte| byte == 0));
+            assert_eq!(&actual[CHUNK_SIZE..], &last_chunk[..]);
+            // Loading the memory does not dirty it.
+            let _: std::result::Result<(), Error> = restored_guest_memory.with_regions(|_, r| {
+                assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+                Ok(())
+            });
+            // Guest writes don't reach the memory file.
+            restored_guest_memory
+                .write(&[3u8], GuestAddress(0))
+                .unwrap();
+            let mut byte = [0u8; 1];
+            file.seek(SeekFrom::Start(first_chunk_offset + 8)).unwrap();
+            file.read_exact(&mut byte).unwrap();
+            if !compress {
+                assert_eq!(byte[0], 1);
+            }
+
+            // Corrupt the data of the first chunk.
+            file.seek(SeekFrom::Start(first_chunk_offset + 8)).unwrap();
+            file.write_all(&[byte[0] ^ 0xff]).unwrap();
+            match GuestMemoryMmap::restore_chunked(&mut file, &memory_state, false) {
+                Err(Error::ChunkChecksum(0))
+                | Err(Error::Compression(_))
+                | Err(Error::InvalidChunkedFile(_)) => (),
+                _ => panic!("Corrupted chunk was not detected."),
+            }
+
+            // The memory state has to match the file.
+            let other_state = GuestMemoryState {
+                regions: vec![GuestMemoryRegionState {
+                    base_address: 0,
+                    size: CHUNK_SIZE,
+                    offset: 0,
+                }],
+                file_format: MemFileFormat::Chunked,
+            };
+            match GuestMemoryMmap::restore_chunked(&mut file, &other_state, false) {
+                Err(Error::InvalidChunkedFile(_)) => (),
+                _ => panic!("Mismatching memory state was not detected."),
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_format_versionize() {
+        let mut memory_state = GuestMemoryState {
+            regions: vec![],
+            file_format: MemFileFormat::Compressed,
+        };
+        let mut buf = vec![0; 100];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(GuestMemoryState::type_id(), 2);
+
+        // Version 1 only knows about raw memory files.
+        assert!(memory_state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .is_err());
+        memory_state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            GuestMemoryState::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state, memory_state);
+
+        memory_state.file_format = MemFileFormat::Raw;
+        memory_state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state =
+            GuestMemoryState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(restored_state.file_format, MemFileFormat::Raw);
+    }
 }
@@ -15,7 +15,10 @@ use std::sync::{Arc, Mutex};
 use crate::builder::{self, StartMicrovmError};
 use crate::device_manager::persist::Error as DevicePersistError;
 use crate::mem_size_mib;
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, DriveOverlay, LoadSnapshotParams, MemFileFormat, NetworkOverride,
+    SnapshotType,
+};
 use crate::vstate::{self, vcpu::VcpuState, vm::VmState};
 
 use crate::device_manager::persist::DeviceStates;
@@ -25,7 +28,7 @@ use crate::version_map::FC_VERSION_TO_SNAP_VERSION;
 use polly::event_manager::EventManager;
 use seccomp::BpfProgramRef;
 use snapshot::Snapshot;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
 
@@ -51,6 +54,29 @@ pub struct MicrovmState {
     pub vcpu_states: Vec<VcpuState>,
     /// Device states.
     pub device_states: DeviceStates,
+    /// States of the vcpus that can be hot-plugged, in the order they get plugged.
+    #[version(
+        start = 2,
+        default_fn = "default_parked_vcpu_states",
+        ser_fn = "parked_vcpu_states_serialize"
+    )]
+    pub parked_vcpu_states: Vec<VcpuState>,
+}
+
+impl MicrovmState {
+    fn parked_vcpu_states_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.parked_vcpu_states.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vCPU hotplug.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_parked_vcpu_states(_: u16) -> Vec<VcpuState> {
+        Vec::new()
+    }
 }
 
 /// Errors related to saving and restoring Microvm state.
@@ -98,6 +124,10 @@ impl Display for MicrovmStateError {
 pub enum CreateSnapshotError {
     /// Failed to get dirty bitmap.
     DirtyBitmap,
+    /// A drive overlay refers to a drive without overlay.
+    InvalidDriveOverlay(String),
+    /// Diff snapshots can only be written to raw memory files.
+    InvalidMemFileFormat,
     /// Failed to translate microVM version to snapshot data version.
     InvalidVersion,
     /// Failed to save VM state.
@@ -108,10 +138,16 @@ pub enum CreateSnapshotError {
     MemoryBackingFile(io::Error),
     /// Failed to save MicrovmState.
     MicrovmState(MicrovmStateError),
+    /// No path was provided to save the overlay of a drive.
+    MissingDriveOverlay(String),
+    /// Failed to save the overlay of a drive.
+    SaveDriveOverlay(crate::Error),
     /// Failed to serialize microVM state.
     SerializeMicrovmState(snapshot::Error),
     /// Failed to open the snapshot backing file.
     SnapshotBackingFile(io::Error),
+    /// Failed to reset the vsock connections.
+    VsockReset(devices::Error),
 }
 
 impl Display for CreateSnapshotError {
@@ -119,6 +155,16 @@ impl Display for CreateSnapshotError {
         use self::CreateSnapshotError::*;
         match self {
             DirtyBitmap => write!(f, "Cannot get dirty bitmap"),
+            InvalidDriveOverlay(drive_id) => {
+                write!(
+                    f,
+                    "Cannot save the overlay of drive {}: no overlay",
+                    drive_id
+                )
+            }
+            InvalidMemFileFormat => {
+                write!(f, "Diff snapshots can only be written to raw memory files")
+            }
             InvalidVersion => write!(
                 f,
                 "Cannot translate microVM version to snapshot data version"
@@ -127,8 +173,15 @@ impl Display for CreateSnapshotError {
             Memory(err) => write!(f, "Cannot write memory file: {:?}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {:?}", err),
             MicrovmState(err) => write!(f, "Cannot save microvm state: {}", err),
+            MissingDriveOverlay(drive_id) => write!(
+                f,
+                "Cannot save the overlay of drive {}: no overlay path provided",
+                drive_id
+            ),
+            SaveDriveOverlay(err) => write!(f, "Cannot save drive overlay: {}", err),
             SerializeMicrovmState(err) => write!(f, "Cannot serialize MicrovmState: {:?}", err),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {:?}", err),
+            VsockReset(err) => write!(f, "Cannot reset the vsock connections: {:?}", err),
         }
     }
 }
@@ -142,6 +195,12 @@ pub enum LoadSnapshotError {
     DeserializeMemory(memory_snapshot::Error),
     /// Failed to deserialize microVM state.
     DeserializeMicrovmState(snapshot::Error),
+    /// Failed to copy the saved overlay of a drive.
+    CloneDriveOverlay(io::Error),
+    /// A drive overlay refers to a drive without overlay in the snapshot.
+    InvalidDriveOverlay(String),
+    /// A network override refers to an interface missing from the snapshot.
+    InvalidNetworkOverride(String),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
     /// Failed to open the snapshot backing file.
@@ -157,6 +216,17 @@ impl Display for LoadSnapshotError {
             BuildMicroVm(err) => write!(f, "Cannot build a microVM from snapshot: {}", err),
             DeserializeMemory(err) => write!(f, "Cannot deserialize memory: {}", err),
             DeserializeMicrovmState(err) => write!(f, "Cannot deserialize MicrovmState: {:?}", err),
+            CloneDriveOverlay(err) => write!(f, "Cannot copy drive overlay: {}", err),
+            InvalidDriveOverlay(drive_id) => write!(
+                f,
+                "Cannot override the overlay of drive {}: no overlay in the snapshot",
+                drive_id
+            ),
+            InvalidNetworkOverride(iface_id) => write!(
+                f,
+                "Cannot override network interface {}: not found in the snapshot",
+                iface_id
+            ),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {}", err),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {}", err),
             SnapshotBackingFileMetadata(err) => write!(f, "Cannot retrieve file metadata: {}", err),
@@ -170,11 +240,19 @@ pub fn create_snapshot(
     params: &CreateSnapshotParams,
     version_map: VersionMap,
 ) -> std::result::Result<(), CreateSnapshotError> {
-    let microvm_state = vmm
+    let mut microvm_state = vmm
         .save_state()
         .map_err(CreateSnapshotError::MicrovmState)?;
+    microvm_state.memory_state.file_format = params.mem_file_format;
+
+    save_drive_overlays(vmm, &mut microvm_state, &params.drive_overlays)?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
+    snapshot_memory_to_file(
+        vmm,
+        &params.mem_file_path,
+        &params.snapshot_type,
+        params.mem_file_format,
+    )?;
 
     snapshot_state_to_file(
         &microvm_state,
@@ -183,6 +261,50 @@ pub fn create_snapshot(
         version_map,
     )?;
 
+    if params.reset_vsock {
+        vmm.reset_vsock_connections()
+            .map_err(CreateSnapshotError::VsockReset)?;
+    }
+
+    Ok(())
+}
+
+// Saves the overlays of the drives to the paths in `drive_overlays`, and points the state of
+// each drive to its saved overlay.
+fn save_drive_overlays(
+    vmm: &Vmm,
+    microvm_state: &mut MicrovmState,
+    drive_overlays: &[DriveOverlay],
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    // The overlays written by the microVM keep changing once it resumes, so the snapshot
+    // can't refer to them.
+    for block_state in microvm_state.device_states.block_devices.iter() {
+        if block_state.device_state.overlay_path().is_some()
+            && !drive_overlays
+                .iter()
+                .any(|drive_overlay| drive_overlay.drive_id == block_state.device_id)
+        {
+            return Err(MissingDriveOverlay(block_state.device_id.clone()));
+        }
+    }
+
+    for drive_overlay in drive_overlays {
+        let block_state = microvm_state
+            .device_states
+            .block_devices
+            .iter_mut()
+            .find(|block_state| {
+                block_state.device_id == drive_overlay.drive_id
+                    && block_state.device_state.overlay_path().is_some()
+            })
+            .ok_or_else(|| InvalidDriveOverlay(drive_overlay.drive_id.clone()))?;
+        vmm.save_block_overlay(&drive_overlay.drive_id, &drive_overlay.overlay_path)
+            .map_err(SaveDriveOverlay)?;
+        block_state
+            .device_state
+            .set_overlay_path(drive_overlay.overlay_path.clone());
+    }
     Ok(())
 }
 
@@ -220,8 +342,14 @@ fn snapshot_memory_to_file(
     vmm: &Vmm,
     mem_file_path: &PathBuf,
     snapshot_type: &SnapshotType,
+    mem_file_format: MemFileFormat,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
+    // Diff snapshots are layered over previous raw memory files.
+    if *snapshot_type == SnapshotType::Diff && mem_file_format != MemFileFormat::Raw {
+        return Err(InvalidMemFileFormat);
+    }
+
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
@@ -229,6 +357,17 @@ fn snapshot_memory_to_file(
         .open(mem_file_path)
         .map_err(MemoryBackingFile)?;
 
+    // The pages discarded through the balloon are tracked between consecutive snapshots.
+    let discarded_pages = vmm.take_discarded_pages();
+
+    if mem_file_format != MemFileFormat::Raw {
+        let compress = mem_file_format == MemFileFormat::Compressed;
+        return vmm
+            .guest_memory()
+            .dump_chunked(&mut file, compress)
+            .map_err(Memory);
+    }
+
     // Set the length of the file to the full size of the memory area.
     let mem_size_mib = mem_size_mib(vmm.guest_memory());
     file.set_len((mem_size_mib * 1024 * 1024) as u64)
@@ -236,7 +375,13 @@ fn snapshot_memory_to_file(
 
     match snapshot_type {
         SnapshotType::Diff => {
-            let dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
+            let mut dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
+            memory_snapshot::clear_discarded_pages(
+                vmm.guest_memory(),
+                &mut dirty_bitmap,
+                &discarded_pages,
+            )
+            .map_err(Memory)?;
             vmm.guest_memory()
                 .dump_dirty(&mut file, &dirty_bitmap)
                 .map_err(Memory)
@@ -254,7 +399,9 @@ pub fn load_snapshot(
 ) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
     use self::LoadSnapshotError::*;
     let track_dirty_pages = params.enable_diff_snapshots;
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    let mut microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    apply_network_overrides(&mut microvm_state, &params.network_overrides)?;
+    apply_drive_overlays(&mut microvm_state, &params.drive_overlays)?;
     let guest_memory = guest_memory_from_file(
         &params.mem_file_path,
         &microvm_state.memory_state,
@@ -270,6 +417,55 @@ pub fn load_snapshot(
     .map_err(BuildMicroVm)
 }
 
+fn apply_network_overrides(
+    microvm_state: &mut MicrovmState,
+    network_overrides: &[NetworkOverride],
+) -> std::result::Result<(), LoadSnapshotError> {
+    for net_override in network_overrides {
+        let net_state = microvm_state
+            .device_states
+            .net_devices
+            .iter_mut()
+            .find(|net_state| net_state.device_id == net_override.iface_id)
+            .ok_or_else(|| {
+                LoadSnapshotError::InvalidNetworkOverride(net_override.iface_id.clone())
+            })?;
+        net_state
+            .device_state
+            .set_tap_if_name(net_override.host_dev_name.clone());
+        if let Some(guest_mac) = net_override.guest_mac.as_ref() {
+            net_state.device_state.set_guest_mac(guest_mac);
+        }
+    }
+    Ok(())
+}
+
+// Copies the saved overlays of the drives to the paths in `drive_overlays`, which the
+// restored drives write to.
+fn apply_drive_overlays(
+    microvm_state: &mut MicrovmState,
+    drive_overlays: &[DriveOverlay],
+) -> std::result::Result<(), LoadSnapshotError> {
+    for drive_overlay in drive_overlays {
+        let block_state = microvm_state
+            .device_states
+            .block_devices
+            .iter_mut()
+            .find(|block_state| {
+                block_state.device_id == drive_overlay.drive_id
+                    && block_state.device_state.overlay_path().is_some()
+            })
+            .ok_or_else(|| {
+                LoadSnapshotError::InvalidDriveOverlay(drive_overlay.drive_id.clone())
+            })?;
+        block_state
+            .device_state
+            .clone_overlay(drive_overlay.overlay_path.clone())
+            .map_err(LoadSnapshotError::CloneDriveOverlay)?;
+    }
+    Ok(())
+}
+
 fn snapshot_state_from_file(
     snapshot_path: &PathBuf,
     version_map: VersionMap,
@@ -289,8 +485,15 @@ fn guest_memory_from_file(
     track_dirty_pages: bool,
 ) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
     use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
-    let mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
-    GuestMemoryMmap::restore(&mem_file, mem_state, track_dirty_pages).map_err(DeserializeMemory)
+    let mut mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
+    match mem_state.file_format {
+        MemFileFormat::Raw => GuestMemoryMmap::restore(&mem_file, mem_state, track_dirty_pages)
+            .map_err(DeserializeMemory),
+        MemFileFormat::Chunked | MemFileFormat::Compressed => {
+            GuestMemoryMmap::restore_chunked(&mut mem_file, mem_state, track_dirty_pages)
+                .map_err(DeserializeMemory)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -302,13 +505,14 @@ mod tests {
     };
     use crate::memory_snapshot::SnapshotMemory;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
-    use crate::vmm_config::net::NetworkInterfaceConfig;
+    use crate::vmm_config::net::{NetworkBackendConfig, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::Vmm;
 
     use polly::event_manager::EventManager;
     use snapshot::Persist;
     use utils::{errno, tempfile::TempFile};
+    use vm_memory::{Bytes, GuestAddress};
 
     fn default_vmm_with_devices(event_manager: &mut EventManager) -> Vmm {
         let mut vmm = default_vmm();
@@ -320,6 +524,9 @@ mod tests {
             must_tell_host: false,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         };
         insert_balloon_device(&mut vmm, &mut cmdline, event_manager, balloon_config);
 
@@ -336,6 +543,11 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         };
         insert_net_device(&mut vmm, &mut cmdline, event_manager, network_interface);
 
@@ -368,6 +580,7 @@ mod tests {
             device_states: states,
             memory_state,
             vcpu_states: vec![VcpuState::default()],
+            parked_vcpu_states: vec![VcpuState::default()],
             vm_info: VmInfo { mem_size_mib: 1u64 },
             vm_state: vmm.vm.save_state().unwrap(),
         };
@@ -381,7 +594,8 @@ mod tests {
 
         version_map
             .new_version()
-            .set_type_version(DeviceStates::type_id(), 2);
+            .set_type_version(DeviceStates::type_id(), 2)
+            .set_type_version(MicrovmState::type_id(), 2);
         microvm_state
             .serialize(&mut buf.as_mut_slice(), &version_map, 2)
             .unwrap();
@@ -390,12 +604,103 @@ mod tests {
             MicrovmState::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
 
         assert_eq!(restored_microvm_state.vm_info, microvm_state.vm_info);
+        assert_eq!(restored_microvm_state.parked_vcpu_states.len(), 1);
         assert_eq!(
             restored_microvm_state.device_states,
             microvm_state.device_states
         )
     }
 
+    #[test]
+    fn test_guest_memory_from_file() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        // Guest controlled memory which looks like a chunked memory file.
+        guest_memory
+            .write_slice(memory_snapshot::CHUNKED_MAGIC, GuestAddress(0))
+            .unwrap();
+        let mut memory_state = guest_memory.describe();
+        let memory_file = TempFile::new().unwrap();
+        guest_memory.dump(&mut memory_file.as_file()).unwrap();
+
+        // The format recorded in the state is used, not the content of the file.
+        let restored_memory =
+            guest_memory_from_file(&memory_file.as_path().to_path_buf(), &memory_state, false)
+                .unwrap();
+        let mut magic = [0u8; 8];
+        restored_memory
+            .read_slice(&mut magic, GuestAddress(0))
+            .unwrap();
+        assert_eq!(&magic, memory_snapshot::CHUNKED_MAGIC);
+
+        memory_state.file_format = MemFileFormat::Chunked;
+        assert!(
+            guest_memory_from_file(&memory_file.as_path().to_path_buf(), &memory_state, false)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_apply_network_overrides() {
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let vmm = default_vmm_with_devices(&mut event_manager);
+        let mut microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states: vec![VcpuState::default()],
+            parked_vcpu_states: Vec::new(),
+            vm_info: VmInfo { mem_size_mib: 1u64 },
+            vm_state: vmm.vm.save_state().unwrap(),
+        };
+
+        let mut net_override = NetworkOverride {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname2"),
+            guest_mac: None,
+        };
+        apply_network_overrides(&mut microvm_state, &[net_override.clone()]).unwrap();
+
+        net_override.iface_id = String::from("unknown");
+        match apply_network_overrides(&mut microvm_state, &[net_override]) {
+            Err(LoadSnapshotError::InvalidNetworkOverride(iface_id)) => {
+                assert_eq!(iface_id, "unknown")
+            }
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_drive_overlays() {
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let vmm = default_vmm_with_devices(&mut event_manager);
+        let mut microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states: vec![VcpuState::default()],
+            parked_vcpu_states: Vec::new(),
+            vm_info: VmInfo { mem_size_mib: 1u64 },
+            vm_state: vmm.vm.save_state().unwrap(),
+        };
+
+        // The drives have no overlay, so there's nothing to save.
+        save_drive_overlays(&vmm, &mut microvm_state, &[]).unwrap();
+        apply_drive_overlays(&mut microvm_state, &[]).unwrap();
+
+        let drive_overlay = DriveOverlay {
+            drive_id: String::from("root"),
+            overlay_path: String::from("/tmp/overlay"),
+        };
+        match save_drive_overlays(&vmm, &mut microvm_state, &[drive_overlay.clone()]) {
+            Err(CreateSnapshotError::InvalidDriveOverlay(drive_id)) => {
+                assert_eq!(drive_id, "root")
+            }
+            _ => panic!("Unexpected result."),
+        }
+        match apply_drive_overlays(&mut microvm_state, &[drive_overlay]) {
+            Err(LoadSnapshotError::InvalidDriveOverlay(drive_id)) => assert_eq!(drive_id, "root"),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use crate::persist::CreateSnapshotError::*;
@@ -404,6 +709,12 @@ mod tests {
         let err = DirtyBitmap;
         let _ = format!("{}{:?}", err, err);
 
+        let err = InvalidDriveOverlay(String::from("rootfs"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidMemFileFormat;
+        let _ = format!("{}{:?}", err, err);
+
         let err = InvalidVersion;
         let _ = format!("{}{:?}", err, err);
 
@@ -421,11 +732,22 @@ mod tests {
         let err = MicrovmState(MicrovmStateError::UnexpectedVcpuResponse);
         let _ = format!("{}{:?}", err, err);
 
+        let err = MissingDriveOverlay(String::from("rootfs"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SaveDriveOverlay(crate::Error::DeviceManager(
+            crate::device_manager::mmio::Error::DeviceNotFound,
+        ));
+        let _ = format!("{}{:?}", err, err);
+
         let err = SerializeMicrovmState(snapshot::Error::InvalidMagic(0));
         let _ = format!("{}{:?}", err, err);
 
         let err = SnapshotBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
+
+        let err = VsockReset(devices::Error::MalformedDescriptor);
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -443,6 +765,15 @@ mod tests {
         let err = DeserializeMicrovmState(snapshot::Error::Io(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = CloneDriveOverlay(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidDriveOverlay(String::from("rootfs"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidNetworkOverride(String::from("netif"));
+        let _ = format!("{}{:?}", err, err);
+
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
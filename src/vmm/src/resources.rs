@@ -13,6 +13,7 @@ use crate::vmm_config::drive::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError, DEFAULT_MEM_SIZE_MIB};
+use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugConfigError};
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
@@ -20,6 +21,7 @@ use crate::vmm_config::vsock::*;
 use crate::vstate::vcpu::VcpuConfig;
 use mmds::ns::MmdsNetworkStack;
 use utils::net::ipv4addr::is_link_local_valid;
+use utils::net::ipv6addr::is_local_valid;
 
 use serde::Deserialize;
 
@@ -38,6 +40,8 @@ pub enum Error {
     InvalidJson,
     /// Logger configuration error.
     Logger(LoggerConfigError),
+    /// Memory hotplug device configuration error.
+    MemoryHotplug(MemoryHotplugConfigError),
     /// Metrics system configuration error.
     Metrics(MetricsConfigError),
     /// MMDS configuration error.
@@ -63,6 +67,8 @@ pub struct VmmConfig {
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
     machine_config: Option<VmConfig>,
+    #[serde(rename = "memory-hotplug")]
+    memory_hotplug: Option<MemoryHotplugConfig>,
     #[serde(rename = "metrics")]
     metrics: Option<MetricsConfig>,
     #[serde(rename = "mmds-config")]
@@ -87,6 +93,8 @@ pub struct VmResources {
     pub vsock: VsockBuilder,
     /// The balloon device.
     pub balloon: BalloonBuilder,
+    /// The memory hotplug device configuration.
+    pub memory_hotplug: Option<MemoryHotplugConfig>,
     /// The network devices builder.
     pub net_builder: NetBuilder,
     /// The configuration for `MmdsNetworkStack`.
@@ -147,6 +155,12 @@ impl VmResources {
                 .map_err(Error::BalloonDevice)?;
         }
 
+        if let Some(memory_hotplug_config) = vmm_config.memory_hotplug {
+            resources
+                .set_memory_hotplug_config(memory_hotplug_config)
+                .map_err(Error::MemoryHotplug)?;
+        }
+
         if let Some(mmds_config) = vmm_config.mmds_config {
             resources
                 .set_mmds_config(mmds_config)
@@ -160,8 +174,10 @@ impl VmResources {
     pub fn vcpu_config(&self) -> VcpuConfig {
         // The unwraps are ok to use because the values are initialized using defaults if not
         // supplied by the user.
+        let vcpu_count = self.vm_config().vcpu_count.unwrap();
         VcpuConfig {
-            vcpu_count: self.vm_config().vcpu_count.unwrap(),
+            vcpu_count,
+            max_vcpu_count: self.vm_config().max_vcpu_count.unwrap_or(vcpu_count),
             ht_enabled: self.vm_config().ht_enabled.unwrap(),
             cpu_template: self.vm_config().cpu_template,
         }
@@ -217,8 +233,22 @@ impl VmResources {
             return Err(VmConfigError::InvalidVcpuCount);
         }
 
+        let max_vcpu_count_value = machine_config
+            .max_vcpu_count
+            .or(self.vm_config.max_vcpu_count);
+
+        // The vCPUs that can be hot-plugged later on are subject to the same constraints.
+        if let Some(max_vcpu_count) = max_vcpu_count_value {
+            if max_vcpu_count < vcpu_count_value
+                || (ht_enabled && max_vcpu_count > 1 && max_vcpu_count % 2 == 1)
+            {
+                return Err(VmConfigError::InvalidMaxVcpuCount);
+            }
+        }
+
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
+        self.vm_config.max_vcpu_count = max_vcpu_count_value;
         self.vm_config.ht_enabled = Some(ht_enabled);
         self.vm_config.track_dirty_pages = machine_config.track_dirty_pages;
 
@@ -258,6 +288,16 @@ impl VmResources {
         self.balloon.set(config)
     }
 
+    /// Sets a memory hotplug device to be attached when the VM starts.
+    pub fn set_memory_hotplug_config(
+        &mut self,
+        config: MemoryHotplugConfig,
+    ) -> Result<MemoryHotplugConfigError> {
+        config.validate(self.vm_config.mem_size_mib.unwrap_or(DEFAULT_MEM_SIZE_MIB))?;
+        self.memory_hotplug = Some(config);
+        Ok(())
+    }
+
     /// Set the guest boot source configuration.
     pub fn set_boot_source(
         &mut self,
@@ -307,18 +347,35 @@ impl VmResources {
         body: NetworkInterfaceConfig,
     ) -> Result<NetworkInterfaceError> {
         self.net_builder.build(body).map(|net_device| {
-            // Update `Net` device `MmdsNetworkStack` IPv4 address.
+            // Update `Net` device `MmdsNetworkStack` IPv4 and IPv6 addresses.
             match &self.mmds_config {
-                Some(cfg) => cfg.ipv4_addr().map_or((), |ipv4_addr| {
+                Some(cfg) => {
                     if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                        mmds_ns.set_ipv4_addr(ipv4_addr);
+                        if let Some(ipv4_addr) = cfg.ipv4_addr() {
+                            mmds_ns.set_ipv4_addr(ipv4_addr);
+                        }
+                        mmds_ns.set_ipv6_addr(cfg.ipv6_addr());
                     };
-                }),
+                }
                 None => (),
             };
         })
     }
 
+    /// Starts a packet capture on a network device to be attached when the VM starts.
+    pub fn start_net_capture(
+        &mut self,
+        config: NetworkCaptureConfig,
+    ) -> Result<NetworkInterfaceError> {
+        self.net_builder
+            .start_capture(&config.iface_id, config.open()?)
+    }
+
+    /// Stops the packet capture running on a network device to be attached when the VM starts.
+    pub fn stop_net_capture(&mut self, iface_id: &str) -> Result<NetworkInterfaceError> {
+        self.net_builder.stop_capture(iface_id)
+    }
+
     /// Sets a vsock device to be attached when the VM starts.
     pub fn set_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<VsockConfigError> {
         self.vsock.insert(config)
@@ -333,10 +390,17 @@ impl VmResources {
             _ => Err(MmdsConfigError::InvalidIpv4Addr),
         }?;
 
-        // Update existing built network device `MmdsNetworkStack` IPv4 address.
+        // Check IPv6 address validity. IPv6 is opt-in, so there is no default address.
+        let ipv6_addr = config.ipv6_addr();
+        if ipv6_addr.map_or(false, |ipv6_addr| !is_local_valid(ipv6_addr)) {
+            return Err(MmdsConfigError::InvalidIpv6Addr);
+        }
+
+        // Update existing built network device `MmdsNetworkStack` IPv4 and IPv6 addresses.
         for net_device in self.net_builder.iter_mut() {
             if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                mmds_ns.set_ipv4_addr(ipv4_addr)
+                mmds_ns.set_ipv4_addr(ipv4_addr);
+                mmds_ns.set_ipv6_addr(ipv6_addr);
             }
         }
 
@@ -353,9 +417,9 @@ mod tests {
     use super::*;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
+    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType, ImageFormat};
     use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
-    use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::net::{NetBuilder, NetworkBackendConfig, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
     use crate::vstate::vcpu::VcpuConfig;
@@ -377,6 +441,11 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         }
     }
 
@@ -393,9 +462,14 @@ mod tests {
             BlockDeviceConfig {
                 drive_id: "block1".to_string(),
                 path_on_host: tmp_file.as_path().to_str().unwrap().to_string(),
+                format: ImageFormat::Raw,
+                cache_type: CacheType::Unsafe,
+                overlay_path: None,
                 is_root_device: false,
                 partuuid: Some("0eaa91a0-01".to_string()),
                 is_read_only: false,
+                num_queues: 1,
+                io_threads: false,
                 rate_limiter: Some(RateLimiterConfig::default()),
             },
             tmp_file,
@@ -427,6 +501,7 @@ mod tests {
             block: default_blocks(),
             vsock: Default::default(),
             balloon: Default::default(),
+            memory_hotplug: None,
             net_builder: default_net_builder(),
             mmds_config: None,
             boot_timer: false,
@@ -706,7 +781,8 @@ mod tests {
                         "ht_enabled": false
                     }},
                     "mmds-config": {{
-                        "ipv4_address": "169.254.170.2"
+                        "ipv4_address": "169.254.170.2",
+                        "ipv6_address": "fd00:ec2::254"
                     }}
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
@@ -750,6 +826,9 @@ mod tests {
                         "mem_size_mib": 1024,
                         "ht_enabled": false
                     }},
+                    "memory-hotplug": {{
+                        "total_size_mib": 1024
+                    }},
                     "mmds-config": {{}}
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
@@ -763,6 +842,7 @@ mod tests {
         let vm_resources = default_vm_resources();
         let expected_vcpu_config = VcpuConfig {
             vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
+            max_vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
             ht_enabled: vm_resources.vm_config().ht_enabled.unwrap(),
             cpu_template: vm_resources.vm_config().cpu_template,
         };
@@ -784,6 +864,7 @@ mod tests {
         let mut vm_resources = default_vm_resources();
         let mut aux_vm_config = VmConfig {
             vcpu_count: Some(32),
+            max_vcpu_count: None,
             mem_size_mib: Some(512),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
@@ -807,6 +888,29 @@ mod tests {
         );
         aux_vm_config.vcpu_count = Some(32);
 
+        // Invalid max vcpu count.
+        aux_vm_config.vcpu_count = Some(4);
+        aux_vm_config.max_vcpu_count = Some(2);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        aux_vm_config.max_vcpu_count = Some(7);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        aux_vm_config.max_vcpu_count = Some(8);
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vcpu_config().max_vcpu_count, 8);
+        aux_vm_config.max_vcpu_count = None;
+        aux_vm_config.vcpu_count = Some(32);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        aux_vm_config.max_vcpu_count = Some(32);
+
         // Invalid mem_size_mib.
         aux_vm_config.mem_size_mib = Some(0);
         assert_eq!(
@@ -822,6 +926,9 @@ mod tests {
                 must_tell_host: false,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 0,
+                free_page_hinting: false,
+                free_page_reporting: false,
+                auto_target: None,
             })
             .unwrap();
         aux_vm_config.mem_size_mib = Some(90);
@@ -843,6 +950,7 @@ mod tests {
             block: default_blocks(),
             vsock: Default::default(),
             balloon: BalloonBuilder::new(),
+            memory_hotplug: None,
             net_builder: default_net_builder(),
             mmds_config: None,
             boot_timer: false,
@@ -852,6 +960,9 @@ mod tests {
             must_tell_host: false,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         };
         assert!(vm_resources.balloon.get().is_none());
         vm_resources
@@ -879,6 +990,7 @@ mod tests {
             block: default_blocks(),
             vsock: Default::default(),
             balloon: BalloonBuilder::new(),
+            memory_hotplug: None,
             net_builder: default_net_builder(),
             mmds_config: None,
             boot_timer: false,
@@ -887,6 +999,36 @@ mod tests {
         assert!(vm_resources.set_balloon_device(new_balloon_cfg).is_err());
     }
 
+    #[test]
+    fn test_set_memory_hotplug_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+            requested_size_mib: 256,
+        };
+        assert!(vm_resources.memory_hotplug.is_none());
+        vm_resources
+            .set_memory_hotplug_config(config.clone())
+            .unwrap();
+        assert_eq!(vm_resources.memory_hotplug.as_ref().unwrap(), &config);
+
+        // Invalid configurations leave the previous one in place.
+        config.requested_size_mib = 2048;
+        match vm_resources.set_memory_hotplug_config(config) {
+            Err(MemoryHotplugConfigError::InvalidRequestedSize) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            vm_resources
+                .memory_hotplug
+                .as_ref()
+                .unwrap()
+                .requested_size_mib,
+            256
+        );
+    }
+
     #[test]
     fn test_boot_config() {
         let vm_resources = default_vm_resources();
@@ -982,4 +1124,33 @@ mod tests {
         vm_resources.build_net_device(new_net_device_cfg).unwrap();
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
+
+    #[test]
+    fn test_set_mmds_config() {
+        let mut vm_resources = default_vm_resources();
+
+        // The IPv6 address must be link-local or unique local.
+        let config = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: Some("2001:db8::1".parse().unwrap()),
+        };
+        assert_eq!(
+            vm_resources
+                .set_mmds_config(config)
+                .unwrap_err()
+                .to_string(),
+            "The MMDS IPv6 address is neither link local nor unique local."
+        );
+        assert!(vm_resources.mmds_config.is_none());
+
+        let config = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: Some("fd00:ec2::254".parse().unwrap()),
+        };
+        vm_resources.set_mmds_config(config).unwrap();
+        assert_eq!(
+            vm_resources.mmds_config.as_ref().unwrap().ipv6_addr(),
+            Some("fd00:ec2::254".parse().unwrap())
+        );
+    }
 }
@@ -24,17 +24,21 @@ use crate::version_map::VERSION_MAP;
 use crate::vmm_config;
 use crate::vmm_config::balloon::{
     BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
-    BalloonUpdateStatsConfig,
+    BalloonUpdateStatsConfig, HintingStatus, StartHintingCmd,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, DriveError};
+use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceLatencies, DriveError};
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError};
+use crate::vmm_config::memory_hotplug::{
+    MemStatus, MemoryHotplugConfig, MemoryHotplugConfigError, MemoryHotplugSizeUpdate,
+};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceError,
+    NetworkInterfaceUpdateConfig,
 };
 #[cfg(target_arch = "x86_64")]
 use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
@@ -64,6 +68,13 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the progress of the balloon device free page hinting run.
+    GetBalloonHintingStatus,
+    /// Get the request latencies of a block device, after microVM start. The data associated
+    /// with this variant represents the `drive_id`.
+    GetBlockDeviceLatencies(String),
+    /// Get the sizes of the memory hotplug device.
+    GetMemoryHotplugStatus,
     /// Get the configuration of the microVM.
     GetVmConfiguration,
     /// Flush the metrics. This action can only be called after the logger has been configured.
@@ -88,6 +99,10 @@ pub enum VmmAction {
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the memory hotplug device or update the one that already exists using the
+    /// `MemoryHotplugConfig` as input. This action can only be called before the microVM
+    /// has booted.
+    SetMemoryHotplugDevice(MemoryHotplugConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
     /// Set the vsock device or update the one that already exists using the
@@ -97,8 +112,18 @@ pub enum VmmAction {
     /// Set the microVM configuration (memory & vcpu) using `VmConfig` as input. This
     /// action can only be called before the microVM has booted.
     SetVmConfiguration(VmConfig),
+    /// Start a free page hinting run of the balloon device, after microVM start.
+    StartBalloonHinting(StartHintingCmd),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
+    /// Start capturing the frames of a network interface using the `NetworkCaptureConfig` as
+    /// input.
+    StartNetworkCapture(NetworkCaptureConfig),
+    /// Complete the free page hinting run of the balloon device, after microVM start.
+    StopBalloonHinting,
+    /// Stop the packet capture running on a network interface. The data associated with this
+    /// variant represents the `iface_id`.
+    StopNetworkCapture(String),
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
@@ -110,6 +135,11 @@ pub enum VmmAction {
     /// Update the path of an existing block device. The data associated with this variant
     /// represents the `drive_id` and the `path_on_host`.
     UpdateBlockDevicePath(String, String),
+    /// Update the size of an existing block device after its backing file was resized. The
+    /// data associated with this variant represents the `drive_id`.
+    UpdateBlockDeviceSize(String),
+    /// Update the amount of memory the guest is asked to plug, after microVM start.
+    UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
@@ -125,8 +155,8 @@ pub enum VmmActionError {
     /// The action `CreateSnapshot` failed.
     #[cfg(target_arch = "x86_64")]
     CreateSnapshot(CreateSnapshotError),
-    /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
-    /// failed because of bad user input.
+    /// One of the actions `InsertBlockDevice`, `UpdateBlockDevicePath` or
+    /// `UpdateBlockDeviceSize` failed because of bad user input.
     DriveConfig(DriveError),
     /// Internal Vmm error.
     InternalVmm(VmmError),
@@ -140,6 +170,8 @@ pub enum VmmActionError {
     Logger(LoggerConfigError),
     /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed because of bad input.
     MachineConfig(VmConfigError),
+    /// One of the memory hotplug actions failed because of bad user input.
+    MemoryHotplugConfig(MemoryHotplugConfigError),
     /// The action `ConfigureMetrics` failed because of bad user input.
     Metrics(MetricsConfigError),
     /// The action `SetMmdsConfiguration` failed because of bad user input.
@@ -179,6 +211,7 @@ impl Display for VmmActionError {
                 }
                 Logger(err) => err.to_string(),
                 MachineConfig(err) => err.to_string(),
+                MemoryHotplugConfig(err) => err.to_string(),
                 Metrics(err) => err.to_string(),
                 MmdsConfig(err) => err.to_string(),
                 NetworkConfig(err) => err.to_string(),
@@ -204,12 +237,18 @@ impl Display for VmmActionError {
 pub enum VmmData {
     /// The balloon device configuration.
     BalloonConfig(BalloonDeviceConfig),
+    /// The progress of the balloon device free page hinting run.
+    BalloonHintingStatus(HintingStatus),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The request latencies of a block device.
+    BlockDeviceLatencies(BlockDeviceLatencies),
     /// No data is sent on the channel.
     Empty,
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The sizes of the memory hotplug device.
+    MemoryHotplugStatus(MemStatus),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -300,6 +339,7 @@ impl<'a> PrebootApiController<'a> {
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
             GetBalloonConfig => self.balloon_config(),
+            GetMemoryHotplugStatus => self.memory_hotplug_status(),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -308,18 +348,27 @@ impl<'a> PrebootApiController<'a> {
             #[cfg(target_arch = "x86_64")]
             LoadSnapshot(config) => self.load_snapshot(&config),
             SetBalloonDevice(config) => self.set_balloon_device(config),
+            SetMemoryHotplugDevice(config) => self.set_memory_hotplug_device(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetVmConfiguration(config) => self.set_vm_config(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
             StartMicroVm => self.start_microvm(),
+            StartNetworkCapture(config) => self.start_net_capture(config),
+            StopNetworkCapture(iface_id) => self.stop_net_capture(&iface_id),
             // Operations not allowed pre-boot.
             FlushMetrics
             | Pause
             | Resume
+            | GetBalloonHintingStatus
             | GetBalloonStats
+            | GetBlockDeviceLatencies(_)
+            | StartBalloonHinting(_)
+            | StopBalloonHinting
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevicePath(_, _)
+            | UpdateBlockDeviceSize(_)
+            | UpdateMemoryHotplugSize(_)
             | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
             CreateSnapshot(_) | SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
@@ -334,6 +383,16 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn memory_hotplug_status(&mut self) -> ActionResult {
+        self.vm_resources
+            .memory_hotplug
+            .clone()
+            .map(|config| VmmData::MemoryHotplugStatus(MemStatus::from(config)))
+            .ok_or(VmmActionError::MemoryHotplugConfig(
+                MemoryHotplugConfigError::DeviceNotFound,
+            ))
+    }
+
     fn insert_block_device(&mut self, cfg: BlockDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -350,6 +409,20 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::NetworkConfig)
     }
 
+    fn start_net_capture(&mut self, cfg: NetworkCaptureConfig) -> ActionResult {
+        self.vm_resources
+            .start_net_capture(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
+    fn stop_net_capture(&mut self, iface_id: &str) -> ActionResult {
+        self.vm_resources
+            .stop_net_capture(iface_id)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
     fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -358,6 +431,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn set_memory_hotplug_device(&mut self, cfg: MemoryHotplugConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_memory_hotplug_config(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::MemoryHotplugConfig)
+    }
+
     fn set_boot_source(&mut self, cfg: BootSourceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -466,11 +547,60 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            GetBlockDeviceLatencies(drive_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .block_device_latencies(&drive_id)
+                .map(VmmData::BlockDeviceLatencies)
+                .map_err(DriveError::GetLatencies)
+                .map_err(VmmActionError::DriveConfig),
+            GetBalloonHintingStatus => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .balloon_hinting_status()
+                .map(VmmData::BalloonHintingStatus)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            GetMemoryHotplugStatus => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .mem_device_status()
+                .map(VmmData::MemoryHotplugStatus)
+                .map_err(|e| {
+                    VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::from(e))
+                }),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
             Pause => self.pause(),
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            #[cfg(target_arch = "x86_64")]
+            SetVmConfiguration(vm_config) => self.update_vm_config(vm_config),
+            StartBalloonHinting(cmd) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .start_balloon_hinting(cmd.acknowledge_on_stop)
+                .map(|_| VmmData::Empty)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            StartNetworkCapture(config) => self.start_net_capture(config),
+            StopBalloonHinting => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .stop_balloon_hinting()
+                .map(|_| VmmData::Empty)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            StopNetworkCapture(iface_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .stop_net_capture(&iface_id)
+                .map(|_| VmmData::Empty)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig),
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -488,7 +618,17 @@ impl RuntimeApiController {
             UpdateBlockDevicePath(drive_id, new_path) => {
                 self.update_block_device_path(&drive_id, new_path)
             }
-            UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateBlockDeviceSize(drive_id) => self.update_block_device_size(&drive_id),
+            UpdateMemoryHotplugSize(size_update) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .update_mem_device_requested_size(size_update.requested_size_mib)
+                .map(|_| VmmData::Empty)
+                .map_err(|e| {
+                    VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::from(e))
+                }),
+            UpdateNetworkInterface(netif_update) => self.update_network_interface(netif_update),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
@@ -497,12 +637,14 @@ impl RuntimeApiController {
             | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
             | SetBalloonDevice(_)
+            | SetMemoryHotplugDevice(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
-            | SetVmConfiguration(_)
             | StartMicroVm => Err(VmmActionError::OperationNotSupportedPostBoot),
             #[cfg(target_arch = "x86_64")]
             LoadSnapshot(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(target_arch = "aarch64")]
+            SetVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
     }
 
@@ -545,6 +687,37 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
+    /// Updates the machine configuration of a running microVM.
+    /// Only the vCPU count can be changed after boot.
+    #[cfg(target_arch = "x86_64")]
+    fn update_vm_config(&mut self, cfg: VmConfig) -> ActionResult {
+        fn changed<T: PartialEq>(new: &Option<T>, current: &Option<T>) -> bool {
+            new.is_some() && new != current
+        }
+
+        let vcpu_count = match cfg.vcpu_count {
+            Some(vcpu_count)
+                if !changed(&cfg.mem_size_mib, &self.vm_config.mem_size_mib)
+                    && !changed(&cfg.ht_enabled, &self.vm_config.ht_enabled)
+                    && !changed(&cfg.cpu_template, &self.vm_config.cpu_template)
+                    && !changed(&cfg.max_vcpu_count, &self.vm_config.max_vcpu_count)
+                    && (!cfg.track_dirty_pages || self.vm_config.track_dirty_pages) =>
+            {
+                vcpu_count
+            }
+            _ => return Err(VmmActionError::OperationNotSupportedPostBoot),
+        };
+
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .set_vcpu_count(vcpu_count)
+            .map_err(VmmActionError::MachineConfig)?;
+        self.vm_config.vcpu_count = Some(vcpu_count);
+
+        Ok(VmmData::Empty)
+    }
+
     /// Write the metrics on user demand (flush). We use the word `flush` here to highlight the fact
     /// that the metrics will be written immediately.
     /// Defer to inner Vmm. We'll move to a variant where the Vmm simply exposes functionality like
@@ -614,18 +787,57 @@ impl RuntimeApiController {
             .map_err(VmmActionError::DriveConfig)
     }
 
+    /// Updates the size of the emulated block device with id `drive_id` after its backing file
+    /// was resized.
+    fn update_block_device_size(&mut self, drive_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .update_block_device_size(drive_id)
+            .map(|()| VmmData::Empty)
+            .map_err(DriveError::DeviceUpdate)
+            .map_err(VmmActionError::DriveConfig)
+    }
+
     /// Updates configuration for an emulated net device as described in `new_cfg`.
-    fn update_net_rate_limiters(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
+    fn update_network_interface(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        let update_link = new_cfg.link_up.is_some() || new_cfg.announce;
+        // Validate the link update before applying anything, so that a rejected update
+        // leaves the device untouched. The rate limiters are patched last as that can't fail.
+        vmm.check_net_link_update(&new_cfg.iface_id, new_cfg.announce)
+            .and_then(|()| match new_cfg.host_dev_name.as_ref() {
+                Some(host_dev_name) => vmm.update_net_backend(&new_cfg.iface_id, host_dev_name),
+                None => Ok(()),
+            })
+            .and_then(|()| {
+                if update_link {
+                    vmm.update_net_link(&new_cfg.iface_id, new_cfg.link_up, new_cfg.announce)
+                } else {
+                    Ok(())
+                }
+            })
+            .and_then(|()| {
+                vmm.update_net_rate_limiters(
+                    &new_cfg.iface_id,
+                    new_cfg.rx_bytes(),
+                    new_cfg.rx_ops(),
+                    new_cfg.tx_bytes(),
+                    new_cfg.tx_ops(),
+                )
+            })
+            .map(|()| VmmData::Empty)
+            .map_err(NetworkInterfaceError::DeviceUpdate)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
+    /// Starts capturing the frames of an emulated net device as described in `cfg`.
+    fn start_net_capture(&mut self, cfg: NetworkCaptureConfig) -> ActionResult {
+        let capture = cfg.open().map_err(VmmActionError::NetworkConfig)?;
         self.vmm
             .lock()
             .expect("Poisoned lock")
-            .update_net_rate_limiters(
-                &new_cfg.iface_id,
-                new_cfg.rx_bytes(),
-                new_cfg.rx_ops(),
-                new_cfg.tx_bytes(),
-                new_cfg.tx_ops(),
-            )
+            .start_net_capture(&cfg.iface_id, capture)
             .map(|()| VmmData::Empty)
             .map_err(NetworkInterfaceError::DeviceUpdate)
             .map_err(VmmActionError::NetworkConfig)
@@ -636,10 +848,17 @@ impl RuntimeApiController {
 mod tests {
     use super::*;
     use crate::vmm_config::balloon::BalloonBuilder;
+    use crate::vmm_config::drive::{CacheType, ImageFormat};
     use crate::vmm_config::logger::LoggerLevel;
+    use crate::vmm_config::net::NetworkBackendConfig;
+    #[cfg(target_arch = "x86_64")]
+    use crate::vmm_config::snapshot::MemFileFormat;
     use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
+    use devices::virtio::mem::Error as MemError;
+    use devices::virtio::net::{CaptureFormat, PacketCapture, DEFAULT_SNAPLEN};
     use devices::virtio::VsockError;
     use seccomp::BpfProgramRef;
+    use utils::tempfile::TempFile;
 
     use std::path::PathBuf;
 
@@ -659,6 +878,7 @@ mod tests {
                 (LoadSnapshotNotAllowed, LoadSnapshotNotAllowed) => true,
                 (Logger(_), Logger(_)) => true,
                 (MachineConfig(_), MachineConfig(_)) => true,
+                (MemoryHotplugConfig(_), MemoryHotplugConfig(_)) => true,
                 (Metrics(_), Metrics(_)) => true,
                 (MmdsConfig(_), MmdsConfig(_)) => true,
                 (NetworkConfig(_), NetworkConfig(_)) => true,
@@ -678,11 +898,15 @@ mod tests {
         pub balloon: BalloonBuilder,
         balloon_config_called: bool,
         balloon_set: bool,
+        pub memory_hotplug: Option<MemoryHotplugConfig>,
+        memory_hotplug_set: bool,
         boot_cfg_set: bool,
         block_set: bool,
         vsock_set: bool,
         net_set: bool,
         mmds_set: bool,
+        net_capture_started: bool,
+        net_capture_stopped: bool,
         pub boot_timer: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
@@ -720,6 +944,17 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_memory_hotplug_config(
+            &mut self,
+            _: MemoryHotplugConfig,
+        ) -> Result<(), MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::InvalidTotalSize);
+            }
+            self.memory_hotplug_set = true;
+            Ok(())
+        }
+
         pub fn set_boot_source(
             &mut self,
             _: BootSourceConfig,
@@ -752,6 +987,25 @@ mod tests {
             Ok(())
         }
 
+        pub fn start_net_capture(
+            &mut self,
+            _: NetworkCaptureConfig,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::DeviceNotFound(String::new()));
+            }
+            self.net_capture_started = true;
+            Ok(())
+        }
+
+        pub fn stop_net_capture(&mut self, _: &str) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::DeviceNotFound(String::new()));
+            }
+            self.net_capture_stopped = true;
+            Ok(())
+        }
+
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -775,17 +1029,32 @@ mod tests {
     #[derive(Debug, Default)]
     pub struct MockVmm {
         pub balloon_config_called: bool,
+        pub balloon_hinting_status_called: bool,
+        pub block_device_latencies_called: bool,
         pub latest_balloon_stats_called: bool,
+        pub mem_device_status_called: bool,
         pub pause_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub set_vcpu_count_called: bool,
+        pub start_balloon_hinting_called: bool,
+        pub start_net_capture_called: bool,
+        pub stop_balloon_hinting_called: bool,
+        pub stop_net_capture_called: bool,
         pub update_balloon_config_called: bool,
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
+        pub update_block_device_size_called: bool,
+        pub update_mem_device_requested_size_called: bool,
+        pub update_net_link_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub update_net_backend_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
+        // when `true`, net link updates are rejected
+        pub force_net_link_errors: bool,
     }
 
     impl MockVmm {
@@ -816,6 +1085,15 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_vcpu_count(&mut self, _: u8) -> Result<(), VmConfigError> {
+            if self.force_errors {
+                return Err(VmConfigError::VcpuCountExceedsMax);
+            }
+            self.set_vcpu_count_called = true;
+            Ok(())
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -848,6 +1126,46 @@ mod tests {
             Ok(())
         }
 
+        pub fn start_balloon_hinting(&mut self, _: bool) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::HintingDisabled);
+            }
+            self.start_balloon_hinting_called = true;
+            Ok(())
+        }
+
+        pub fn stop_balloon_hinting(&mut self) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::HintingDisabled);
+            }
+            self.stop_balloon_hinting_called = true;
+            Ok(())
+        }
+
+        pub fn balloon_hinting_status(&mut self) -> Result<HintingStatus, BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::HintingDisabled);
+            }
+            self.balloon_hinting_status_called = true;
+            Ok(HintingStatus::default())
+        }
+
+        pub fn mem_device_status(&mut self) -> Result<MemStatus, MemError> {
+            if self.force_errors {
+                return Err(MemError::DeviceNotFound);
+            }
+            self.mem_device_status_called = true;
+            Ok(MemStatus::default())
+        }
+
+        pub fn update_mem_device_requested_size(&mut self, _: u64) -> Result<(), MemError> {
+            if self.force_errors {
+                return Err(MemError::DeviceNotFound);
+            }
+            self.update_mem_device_requested_size_called = true;
+            Ok(())
+        }
+
         pub fn update_block_device_path(&mut self, _: &str, _: String) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -858,6 +1176,29 @@ mod tests {
             Ok(())
         }
 
+        pub fn block_device_latencies(
+            &mut self,
+            _: &str,
+        ) -> Result<BlockDeviceLatencies, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.block_device_latencies_called = true;
+            Ok(BlockDeviceLatencies::default())
+        }
+
+        pub fn update_block_device_size(&mut self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_block_device_size_called = true;
+            Ok(())
+        }
+
         pub fn update_net_rate_limiters(
             &mut self,
             _: &str,
@@ -874,6 +1215,60 @@ mod tests {
             self.update_net_rate_limiters_called = true;
             Ok(())
         }
+
+        pub fn check_net_link_update(&mut self, _: &str, _: bool) -> Result<(), VmmError> {
+            if self.force_errors || self.force_net_link_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn update_net_link(
+            &mut self,
+            _: &str,
+            _: Option<bool>,
+            _: bool,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_net_link_called = true;
+            Ok(())
+        }
+
+        pub fn update_net_backend(&mut self, _: &str, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_net_backend_called = true;
+            Ok(())
+        }
+
+        pub fn start_net_capture(&mut self, _: &str, _: PacketCapture) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.start_net_capture_called = true;
+            Ok(())
+        }
+
+        pub fn stop_net_capture(&mut self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.stop_net_capture_called = true;
+            Ok(())
+        }
     }
 
     // Need to redefine this since the non-test one uses real VmResources
@@ -1022,13 +1417,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_set_memory_hotplug_dev() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+            requested_size_mib: 0,
+        };
+        let req = VmmAction::SetMemoryHotplugDevice(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.memory_hotplug_set)
+        });
+
+        let req = VmmAction::SetMemoryHotplugDevice(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::InvalidTotalSize),
+        );
+    }
+
+    #[test]
+    fn test_preboot_memory_hotplug_status() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+            requested_size_mib: 512,
+        };
+        let mut vm_resources = MockVmRes::default();
+        vm_resources.memory_hotplug = Some(config.clone());
+        let mut evmgr = EventManager::new().unwrap();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+        assert_eq!(
+            preboot.handle_preboot_request(VmmAction::GetMemoryHotplugStatus),
+            Ok(VmmData::MemoryHotplugStatus(MemStatus::from(config)))
+        );
+
+        check_preboot_request_err(
+            VmmAction::GetMemoryHotplugStatus,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_preboot_insert_block_dev() {
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::new(),
             rate_limiter: None,
         });
@@ -1039,9 +1481,14 @@ mod tests {
 
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::new(),
             rate_limiter: None,
         });
@@ -1060,6 +1507,11 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1073,6 +1525,11 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         });
         check_preboot_request_err(
             req,
@@ -1082,12 +1539,50 @@ mod tests {
         );
     }
 
+    fn capture_config(path: PathBuf) -> NetworkCaptureConfig {
+        NetworkCaptureConfig {
+            iface_id: String::new(),
+            path,
+            format: CaptureFormat::Pcap,
+            snaplen: DEFAULT_SNAPLEN,
+            ring_size: None,
+        }
+    }
+
+    #[test]
+    fn test_preboot_net_capture() {
+        let req = VmmAction::StartNetworkCapture(capture_config(PathBuf::new()));
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.net_capture_started)
+        });
+
+        let req = VmmAction::StopNetworkCapture(String::new());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.net_capture_stopped)
+        });
+
+        let req = VmmAction::StartNetworkCapture(capture_config(PathBuf::new()));
+        check_preboot_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceNotFound(String::new())),
+        );
+
+        let req = VmmAction::StopNetworkCapture(String::new());
+        check_preboot_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceNotFound(String::new())),
+        );
+    }
+
     #[test]
     fn test_preboot_set_vsock_dev() {
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            port_map: Vec::new(),
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1098,6 +1593,7 @@ mod tests {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            port_map: Vec::new(),
         });
         check_preboot_request_err(
             req,
@@ -1109,13 +1605,19 @@ mod tests {
 
     #[test]
     fn test_preboot_set_mmds_config() {
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+        });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
             assert!(vm_res.mmds_set)
         });
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+        });
         check_preboot_request_err(
             req,
             VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
@@ -1150,15 +1652,44 @@ mod tests {
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::StartBalloonHinting(StartHintingCmd::default()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::StopBalloonHinting,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetBalloonHintingStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBlockDevicePath(String::new(), String::new()),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::UpdateBlockDeviceSize(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetBlockDeviceLatencies(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+                requested_size_mib: 0,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
                 iface_id: String::new(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                link_up: None,
+                announce: false,
+                host_dev_name: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1168,7 +1699,10 @@ mod tests {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                mem_file_format: MemFileFormat::Raw,
                 version: None,
+                reset_vsock: false,
+                drive_overlays: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1346,6 +1880,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_memory_hotplug_status() {
+        let req = VmmAction::GetMemoryHotplugStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MemoryHotplugStatus(MemStatus::default()))
+            );
+            assert!(vmm.mem_device_status_called)
+        });
+
+        let req = VmmAction::GetMemoryHotplugStatus;
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_memory_hotplug_size() {
+        let req = VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+            requested_size_mib: 0,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_mem_device_requested_size_called)
+        });
+
+        let req = VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+            requested_size_mib: 0,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_runtime_update_balloon_stats_config() {
         let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
@@ -1365,6 +1936,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_balloon_hinting() {
+        let req = VmmAction::StartBalloonHinting(StartHintingCmd::default());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.start_balloon_hinting_called)
+        });
+
+        let req = VmmAction::StopBalloonHinting;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.stop_balloon_hinting_called)
+        });
+
+        let req = VmmAction::GetBalloonHintingStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BalloonHintingStatus(HintingStatus::default()))
+            );
+            assert!(vmm.balloon_hinting_status_called)
+        });
+
+        let req = VmmAction::StartBalloonHinting(StartHintingCmd::default());
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::HintingNotEnabled),
+        );
+    }
+
     #[test]
     fn test_runtime_update_block_device_path() {
         let req = VmmAction::UpdateBlockDevicePath(String::new(), String::new());
@@ -1382,23 +1983,160 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_update_block_device_size() {
+        let req = VmmAction::UpdateBlockDeviceSize(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_device_size_called)
+        });
+
+        let req = VmmAction::UpdateBlockDeviceSize(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_block_device_latencies() {
+        let req = VmmAction::GetBlockDeviceLatencies(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BlockDeviceLatencies(
+                    BlockDeviceLatencies::default()
+                ))
+            );
+            assert!(vmm.block_device_latencies_called)
+        });
+
+        let req = VmmAction::GetBlockDeviceLatencies(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::GetLatencies(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
+    }
+
     #[test]
     fn test_runtime_update_net_rate_limiters() {
         let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            link_up: None,
+            announce: false,
+            host_dev_name: None,
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_net_rate_limiters_called)
+            assert!(vmm.update_net_rate_limiters_called);
+            assert!(!vmm.update_net_link_called);
+            assert!(!vmm.update_net_backend_called);
         });
 
+        // A rejected link update doesn't leave the interface partially updated.
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            force_net_link_errors: true,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(VmConfig::default(), vmm.clone());
         let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            link_up: None,
+            announce: true,
+            host_dev_name: Some(String::from("tap1")),
+        });
+        assert!(runtime.handle_request(req).is_err());
+        let vmm = vmm.lock().unwrap();
+        assert!(!vmm.update_net_backend_called);
+        assert!(!vmm.update_net_link_called);
+        assert!(!vmm.update_net_rate_limiters_called);
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            link_up: Some(false),
+            announce: true,
+            host_dev_name: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_link_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            link_up: None,
+            announce: false,
+            host_dev_name: Some(String::from("tap1")),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_backend_called);
+            assert!(!vmm.update_net_link_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            link_up: None,
+            announce: false,
+            host_dev_name: None,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_net_capture() {
+        let capture_file = TempFile::new().unwrap();
+        let req =
+            VmmAction::StartNetworkCapture(capture_config(capture_file.as_path().to_path_buf()));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.start_net_capture_called)
+        });
+
+        let req = VmmAction::StopNetworkCapture(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.stop_net_capture_called)
         });
+
+        // The capture file cannot be created.
+        let req = VmmAction::StartNetworkCapture(capture_config(PathBuf::new()));
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::CreateCapture(
+                std::io::Error::from_raw_os_error(0),
+            )),
+        );
+
+        let req =
+            VmmAction::StartNetworkCapture(capture_config(capture_file.as_path().to_path_buf()));
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
+
+        let req = VmmAction::StopNetworkCapture(String::new());
         check_runtime_request_err(
             req,
             VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
@@ -1407,12 +2145,63 @@ mod tests {
         );
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_set_vcpu_count() {
+        let req = VmmAction::SetVmConfiguration(VmConfig {
+            vcpu_count: Some(2),
+            mem_size_mib: None,
+            ht_enabled: None,
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_vcpu_count_called)
+        });
+
+        let req = VmmAction::SetVmConfiguration(VmConfig {
+            vcpu_count: Some(2),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::MachineConfig(VmConfigError::VcpuCountExceedsMax),
+        );
+
+        // Only the vCPU count can change.
+        let req = VmmAction::SetVmConfiguration(VmConfig {
+            vcpu_count: None,
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Err(VmmActionError::OperationNotSupportedPostBoot));
+            assert!(!vmm.set_vcpu_count_called)
+        });
+        let req = VmmAction::SetVmConfiguration(VmConfig {
+            vcpu_count: Some(2),
+            ht_enabled: Some(true),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Err(VmmActionError::OperationNotSupportedPostBoot));
+            assert!(!vmm.set_vcpu_count_called)
+        });
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
             VmmAction::ConfigureBootSource(BootSourceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetMemoryHotplugDevice(MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 2,
+                requested_size_mib: 0,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
         check_runtime_request_err(
             VmmAction::ConfigureLogger(LoggerConfig {
                 log_path: PathBuf::new(),
@@ -1431,9 +2220,14 @@ mod tests {
         check_runtime_request_err(
             VmmAction::InsertBlockDevice(BlockDeviceConfig {
                 path_on_host: String::new(),
+                format: ImageFormat::Raw,
+                cache_type: CacheType::Unsafe,
+                overlay_path: None,
                 is_root_device: false,
                 partuuid: None,
                 is_read_only: false,
+                num_queues: 1,
+                io_threads: false,
                 drive_id: String::new(),
                 rate_limiter: None,
             }),
@@ -1447,6 +2241,11 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: false,
+                num_queues: 1,
+                guest_announce: false,
+                backend: NetworkBackendConfig::Tap,
+                anti_spoofing: None,
+                dhcp: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1455,6 +2254,7 @@ mod tests {
                 vsock_id: String::new(),
                 guest_cid: 0,
                 uds_path: String::new(),
+                port_map: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1467,15 +2267,22 @@ mod tests {
                 vsock_id: String::new(),
                 guest_cid: 0,
                 uds_path: String::new(),
+                port_map: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
-            VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None }),
+            VmmAction::SetMmdsConfiguration(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: None,
+            }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
-            VmmAction::SetVmConfiguration(VmConfig::default()),
+            VmmAction::SetVmConfiguration(VmConfig {
+                mem_size_mib: Some(256),
+                ..Default::default()
+            }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         #[cfg(target_arch = "x86_64")]
@@ -1484,6 +2291,8 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 enable_diff_snapshots: false,
+                network_overrides: Vec::new(),
+                drive_overlays: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1502,6 +2311,8 @@ mod tests {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
             enable_diff_snapshots: false,
+            network_overrides: Vec::new(),
+            drive_overlays: Vec::new(),
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -1521,9 +2332,14 @@ mod tests {
 
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::new(),
             rate_limiter: None,
         });
@@ -1536,6 +2352,11 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
@@ -1546,13 +2367,17 @@ mod tests {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            port_map: Vec::new(),
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
         let req = VmmAction::SetVmConfiguration(VmConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetVmConfiguration");
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+        });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
     }
 }
@@ -8,6 +8,18 @@ use std::collections::HashMap;
 // Currently only supports x86_64.
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::DeviceStates;
+#[cfg(target_arch = "x86_64")]
+use crate::memory_snapshot::GuestMemoryState;
+#[cfg(target_arch = "x86_64")]
+use crate::persist::MicrovmState;
+#[cfg(target_arch = "x86_64")]
+use devices::virtio::block::persist::BlockState;
+#[cfg(target_arch = "x86_64")]
+use devices::virtio::net::persist::NetState;
+#[cfg(target_arch = "x86_64")]
+use devices::virtio::vsock::persist::VsockUdsState;
+#[cfg(target_arch = "x86_64")]
+use mmds::persist::MmdsNetworkStackState;
 
 use lazy_static::lazy_static;
 use versionize::VersionMap;
@@ -21,7 +33,15 @@ lazy_static! {
         #[cfg(target_arch = "x86_64")]
         {
             let mut version_map = VersionMap::new();
-            version_map.new_version().set_type_version(DeviceStates::type_id(), 2);
+            version_map
+                .new_version()
+                .set_type_version(BlockState::type_id(), 2)
+                .set_type_version(DeviceStates::type_id(), 2)
+                .set_type_version(GuestMemoryState::type_id(), 2)
+                .set_type_version(MicrovmState::type_id(), 2)
+                .set_type_version(NetState::type_id(), 2)
+                .set_type_version(MmdsNetworkStackState::type_id(), 2)
+                .set_type_version(VsockUdsState::type_id(), 2);
             version_map
         }
 
@@ -4,7 +4,7 @@
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
-pub use devices::virtio::balloon::device::BalloonStats;
+pub use devices::virtio::balloon::device::{AutoTarget, BalloonStats, HintingStatus};
 use devices::virtio::balloon::Error as BalloonError;
 pub use devices::virtio::BALLOON_DEV_ID;
 use devices::virtio::{Balloon, BalloonConfig};
@@ -27,6 +27,17 @@ pub enum BalloonConfigError {
     /// The user polled the statistics of a balloon device that
     /// does not have the statistics enabled.
     StatsNotFound,
+    /// The user made a free page hinting request on a balloon device
+    /// that does not have free page hinting enabled.
+    HintingNotEnabled,
+    /// The user updated the target size of a balloon device managed by an
+    /// automatic target policy.
+    AutoTargetEnabled,
+    /// The user set an automatic target policy on a balloon device that
+    /// does not have the statistics enabled.
+    AutoTargetWithoutStats,
+    /// The automatic target policy has a zero inflate or deflate rate.
+    InvalidAutoTarget,
     /// Failed to create a balloon device.
     CreateFailure(devices::virtio::balloon::Error),
     /// Failed to update the configuration of the ballon device.
@@ -42,6 +53,21 @@ impl fmt::Display for BalloonConfigError {
             InvalidStatsUpdate => write!(f, "Cannot enable/disable the statistics after boot."),
             TooManyPagesRequested => write!(f, "Amount of pages requested is too large."),
             StatsNotFound => write!(f, "Statistics for the balloon device are not enabled"),
+            HintingNotEnabled => {
+                write!(f, "Free page hinting for the balloon device is not enabled")
+            }
+            AutoTargetEnabled => write!(
+                f,
+                "The target size is managed by the automatic target policy."
+            ),
+            AutoTargetWithoutStats => write!(
+                f,
+                "The automatic target policy needs the statistics to be enabled."
+            ),
+            InvalidAutoTarget => write!(
+                f,
+                "The automatic target policy rates must be greater than 0."
+            ),
             CreateFailure(e) => write!(f, "Error creating the balloon device: {:?}", e),
             UpdateFailure(e) => write!(
                 f,
@@ -61,6 +87,10 @@ impl From<BalloonError> for BalloonConfigError {
             BalloonError::StatisticsStateChange => Self::InvalidStatsUpdate,
             BalloonError::StatisticsDisabled => Self::StatsNotFound,
             BalloonError::TooManyPagesRequested => Self::TooManyPagesRequested,
+            BalloonError::HintingDisabled => Self::HintingNotEnabled,
+            BalloonError::AutoTargetEnabled => Self::AutoTargetEnabled,
+            BalloonError::AutoTargetWithoutStats => Self::AutoTargetWithoutStats,
+            BalloonError::InvalidAutoTarget => Self::InvalidAutoTarget,
             e => Self::CreateFailure(e),
         }
     }
@@ -83,6 +113,15 @@ pub struct BalloonDeviceConfig {
     /// Interval in seconds between refreshing statistics.
     #[serde(default)]
     pub stats_polling_interval_s: u16,
+    /// Option to let the host ask the guest for its free pages.
+    #[serde(default)]
+    pub free_page_hinting: bool,
+    /// Option to let the guest report its free pages continuously.
+    #[serde(default)]
+    pub free_page_reporting: bool,
+    /// Policy used to adjust the target size from the statistics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_target: Option<AutoTarget>,
 }
 
 impl From<BalloonConfig> for BalloonDeviceConfig {
@@ -92,6 +131,9 @@ impl From<BalloonConfig> for BalloonDeviceConfig {
             deflate_on_oom: state.deflate_on_oom,
             must_tell_host: state.must_tell_host,
             stats_polling_interval_s: state.stats_polling_interval_s,
+            free_page_hinting: state.free_page_hinting,
+            free_page_reporting: state.free_page_reporting,
+            auto_target: state.auto_target,
         }
     }
 }
@@ -116,6 +158,28 @@ pub struct BalloonUpdateStatsConfig {
     pub stats_polling_interval_s: u16,
 }
 
+fn default_acknowledge_on_stop() -> bool {
+    true
+}
+
+/// The data fed into a free page hinting start request.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StartHintingCmd {
+    /// Whether the hinting run is completed as soon as the guest stops
+    /// hinting pages. Otherwise, the run has to be stopped explicitly.
+    #[serde(default = "default_acknowledge_on_stop")]
+    pub acknowledge_on_stop: bool,
+}
+
+impl Default for StartHintingCmd {
+    fn default() -> Self {
+        StartHintingCmd {
+            acknowledge_on_stop: default_acknowledge_on_stop(),
+        }
+    }
+}
+
 /// A builder for `Balloon` devices from 'BalloonDeviceConfig'.
 pub struct BalloonBuilder {
     inner: Option<MutexBalloon>,
@@ -137,18 +201,20 @@ impl BalloonBuilder {
     /// Inserts a Balloon device in the store.
     /// If an entry already exists, it will overwrite it.
     pub fn set(&mut self, cfg: BalloonDeviceConfig) -> Result<()> {
-        self.inner = Some(Arc::new(Mutex::new(
-            Balloon::new(
-                cfg.amount_mb,
-                cfg.must_tell_host,
-                cfg.deflate_on_oom,
-                cfg.stats_polling_interval_s,
-                // `restored` flag is false because this code path
-                // is never called by snapshot restore functionality.
-                false,
-            )
-            .map_err(BalloonConfigError::CreateFailure)?,
-        )));
+        let mut balloon = Balloon::new(
+            cfg.amount_mb,
+            cfg.must_tell_host,
+            cfg.deflate_on_oom,
+            cfg.stats_polling_interval_s,
+            cfg.free_page_hinting,
+            cfg.free_page_reporting,
+            // `restored` flag is false because this code path
+            // is never called by snapshot restore functionality.
+            false,
+        )
+        .map_err(BalloonConfigError::CreateFailure)?;
+        balloon.set_auto_target(cfg.auto_target)?;
+        self.inner = Some(Arc::new(Mutex::new(balloon)));
 
         Ok(())
     }
@@ -177,6 +243,9 @@ pub(crate) mod tests {
             must_tell_host: false,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         }
     }
 
@@ -196,6 +265,9 @@ pub(crate) mod tests {
             must_tell_host: false,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
+            free_page_reporting: false,
+            auto_target: None,
         };
         assert_eq!(default_balloon_config, balloon_config);
         let mut builder = BalloonBuilder::new();
@@ -209,6 +281,42 @@ pub(crate) mod tests {
         let _stats_update_config = BalloonUpdateStatsConfig {
             stats_polling_interval_s: 5,
         };
+        assert!(StartHintingCmd::default().acknowledge_on_stop);
+    }
+
+    #[test]
+    fn test_balloon_auto_target() {
+        let auto_target = AutoTarget {
+            min_free_mib: 64,
+            max_balloon_mib: 128,
+            inflate_rate_mib: 16,
+            deflate_rate_mib: 32,
+        };
+        let mut builder = BalloonBuilder::new();
+
+        // The statistics must be enabled.
+        let mut balloon_config = default_config();
+        balloon_config.auto_target = Some(auto_target);
+        match builder.set(balloon_config.clone()) {
+            Err(BalloonConfigError::AutoTargetWithoutStats) => (),
+            _ => unreachable!(),
+        }
+        assert!(builder.get().is_none());
+
+        // The rates must be non-zero.
+        balloon_config.stats_polling_interval_s = 1;
+        balloon_config.auto_target = Some(AutoTarget {
+            inflate_rate_mib: 0,
+            ..auto_target
+        });
+        match builder.set(balloon_config.clone()) {
+            Err(BalloonConfigError::InvalidAutoTarget) => (),
+            _ => unreachable!(),
+        }
+
+        balloon_config.auto_target = Some(auto_target);
+        builder.set(balloon_config.clone()).unwrap();
+        assert_eq!(builder.get_config().unwrap(), balloon_config);
     }
 
     #[test]
@@ -218,6 +326,9 @@ pub(crate) mod tests {
             deflate_on_oom: false,
             must_tell_host: true,
             stats_polling_interval_s: 3,
+            free_page_hinting: true,
+            free_page_reporting: false,
+            auto_target: None,
         };
 
         let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
@@ -225,6 +336,9 @@ pub(crate) mod tests {
             deflate_on_oom: false,
             must_tell_host: true,
             stats_polling_interval_s: 3,
+            free_page_hinting: true,
+            free_page_reporting: false,
+            auto_target: None,
         });
 
         assert_eq!(expected_balloon_config, actual_balloon_config);
@@ -253,5 +367,17 @@ pub(crate) mod tests {
 
         let err = StatsNotFound;
         let _ = format!("{}{:?}", err, err);
+
+        let err = HintingNotEnabled;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = AutoTargetEnabled;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = AutoTargetWithoutStats;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidAutoTarget;
+        let _ = format!("{}{:?}", err, err);
     }
 }
@@ -11,10 +11,13 @@ use std::sync::{Arc, Mutex};
 
 use super::RateLimiterConfig;
 use crate::Error as VmmError;
+use devices::virtio::block::MAX_QUEUES;
 use devices::virtio::Block;
 
 use serde::Deserialize;
 
+pub use devices::virtio::{BlockDeviceLatencies, CacheType, ImageFormat, LatencyStats};
+
 type Result<T> = result::Result<T, DriveError>;
 
 /// Errors associated with the operations allowed on a drive.
@@ -29,8 +32,12 @@ pub enum DriveError {
     CreateRateLimiter(io::Error),
     /// Error during drive update (patch).
     DeviceUpdate(VmmError),
+    /// Cannot get the request latencies of the drive.
+    GetLatencies(VmmError),
     /// The block device path is invalid.
     InvalidBlockDevicePath,
+    /// The number of queues is zero or larger than `MAX_QUEUES`.
+    InvalidNumQueues(usize),
     /// Cannot open block device due to invalid permissions or path.
     OpenBlockDevice(io::Error),
     /// A root block device was already added.
@@ -50,7 +57,13 @@ impl Display for DriveError {
             BlockDeviceUpdateFailed(e) => write!(f, "The update operation failed: {}", e),
             CreateRateLimiter(e) => write!(f, "Cannot create RateLimiter: {}", e),
             DeviceUpdate(e) => write!(f, "Error during drive update (patch): {}", e),
+            GetLatencies(e) => write!(f, "Cannot get the drive latencies: {}", e),
             InvalidBlockDevicePath => write!(f, "Invalid block device path!"),
+            InvalidNumQueues(num_queues) => write!(
+                f,
+                "Invalid number of queues: {}. It must be between 1 and {}.",
+                num_queues, MAX_QUEUES
+            ),
             OpenBlockDevice(e) => write!(
                 f,
                 "Cannot open block device. Invalid permission/path: {}",
@@ -69,6 +82,15 @@ pub struct BlockDeviceConfig {
     pub drive_id: String,
     /// Path of the drive.
     pub path_on_host: String,
+    /// Format of the disk image at `path_on_host`. The default value is `Raw`.
+    #[serde(default)]
+    pub format: ImageFormat,
+    /// How the host caches the disk image. The default value is `Unsafe`.
+    #[serde(default)]
+    pub cache_type: CacheType,
+    /// Path of a copy-on-write overlay file. When set, the drive is only read from
+    /// `path_on_host`, and the guest writes go to the overlay file, created if missing.
+    pub overlay_path: Option<String>,
     /// If set to true, it makes the current device the root block device.
     /// Setting this flag to true will mount the block device in the
     /// guest under /dev/vda unless the partuuid is present.
@@ -79,10 +101,22 @@ pub struct BlockDeviceConfig {
     /// If set to true, the drive is opened in read-only mode. Otherwise, the
     /// drive is opened as read-write.
     pub is_read_only: bool,
+    /// Number of request queues of the device. When larger than one, the guest can submit
+    /// requests from several vCPUs in parallel.
+    #[serde(default = "default_num_queues")]
+    pub num_queues: usize,
+    /// If this field is set, each queue is serviced by a dedicated I/O thread instead of the
+    /// VMM event loop.
+    #[serde(default)]
+    pub io_threads: bool,
     /// Rate Limiter for I/O operations.
     pub rate_limiter: Option<RateLimiterConfig>,
 }
 
+fn default_num_queues() -> usize {
+    1
+}
+
 /// Wrapper for the collection that holds all the Block Devices
 #[derive(Default)]
 pub struct BlockBuilder {
@@ -166,6 +200,10 @@ impl BlockBuilder {
             return Err(DriveError::InvalidBlockDevicePath);
         }
 
+        if block_device_config.num_queues == 0 || block_device_config.num_queues > MAX_QUEUES {
+            return Err(DriveError::InvalidNumQueues(block_device_config.num_queues));
+        }
+
         let rate_limiter = block_device_config
             .rate_limiter
             .map(super::RateLimiterConfig::try_into)
@@ -177,8 +215,13 @@ impl BlockBuilder {
             block_device_config.drive_id,
             block_device_config.partuuid,
             block_device_config.path_on_host,
+            block_device_config.format,
+            block_device_config.cache_type,
+            block_device_config.overlay_path,
             block_device_config.is_read_only,
             block_device_config.is_root_device,
+            block_device_config.num_queues,
+            block_device_config.io_threads,
             rate_limiter.unwrap_or_default(),
         )
         .map_err(DriveError::CreateBlockDevice)
@@ -203,9 +246,14 @@ mod tests {
         fn clone(&self) -> Self {
             BlockDeviceConfig {
                 path_on_host: self.path_on_host.clone(),
+                format: self.format,
+                cache_type: self.cache_type,
+                overlay_path: self.overlay_path.clone(),
                 is_root_device: self.is_root_device,
                 partuuid: self.partuuid.clone(),
                 is_read_only: self.is_read_only,
+                num_queues: self.num_queues,
+                io_threads: self.io_threads,
                 drive_id: self.drive_id.clone(),
                 rate_limiter: None,
             }
@@ -225,9 +273,14 @@ mod tests {
         let dummy_id = String::from("1");
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_path,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: dummy_id.clone(),
             rate_limiter: None,
         };
@@ -254,9 +307,14 @@ mod tests {
 
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_path,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: true,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -280,9 +338,14 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device_1 = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -291,9 +354,14 @@ mod tests {
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let root_block_device_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("2"),
             rate_limiter: None,
         };
@@ -313,9 +381,14 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -324,9 +397,14 @@ mod tests {
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("2"),
             rate_limiter: None,
         };
@@ -335,9 +413,14 @@ mod tests {
         let dummy_path_3 = dummy_file_3.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_3 = BlockDeviceConfig {
             path_on_host: dummy_path_3,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("3"),
             rate_limiter: None,
         };
@@ -371,9 +454,14 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -382,9 +470,14 @@ mod tests {
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("2"),
             rate_limiter: None,
         };
@@ -393,9 +486,14 @@ mod tests {
         let dummy_path_3 = dummy_file_3.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_3 = BlockDeviceConfig {
             path_on_host: dummy_path_3,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("3"),
             rate_limiter: None,
         };
@@ -430,9 +528,14 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1.clone(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -441,9 +544,14 @@ mod tests {
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let mut dummy_block_device_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2.clone(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("2"),
             rate_limiter: None,
         };
@@ -499,9 +607,14 @@ mod tests {
 
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("1"),
             rate_limiter: None,
         };
@@ -510,9 +623,14 @@ mod tests {
         root_block_device_old.is_root_device = false;
         let root_block_device_new = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: true,
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
             drive_id: String::from("2"),
             rate_limiter: None,
         };
@@ -533,9 +651,14 @@ mod tests {
         let block_config = BlockDeviceConfig {
             drive_id: "dummy_drive".to_string(),
             path_on_host: dummy_block_file.as_path().to_str().unwrap().to_string(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
             is_root_device: false,
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: true,
+            num_queues: 1,
+            io_threads: false,
             rate_limiter: None,
         };
 
@@ -549,4 +672,98 @@ mod tests {
         );
         assert_eq!(block_config.is_read_only, expected_is_read_only);
     }
+
+    #[test]
+    fn test_add_block_device_with_overlay() {
+        let base_file = TempFile::new().unwrap();
+        base_file.as_file().set_len(0x1000).unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        let overlay_path = overlay_file.as_path().to_str().unwrap().to_string();
+        let block_device = BlockDeviceConfig {
+            path_on_host: base_file.as_path().to_str().unwrap().to_string(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: Some(overlay_path.clone()),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+        };
+
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(block_device).unwrap();
+
+        let block = block_devs.list[0].lock().unwrap();
+        assert_eq!(block.overlay_path(), Some(overlay_path.as_str()));
+        assert!(!block.is_read_only());
+        // The overlay is as large as the base image.
+        assert_eq!(overlay_file.as_file().metadata().unwrap().len(), 0x1000);
+    }
+
+    #[test]
+    fn test_add_block_device_with_wrong_format() {
+        // The file holds no qcow2 header.
+        let raw_file = TempFile::new().unwrap();
+        raw_file.as_file().set_len(0x1000).unwrap();
+        let block_device = BlockDeviceConfig {
+            path_on_host: raw_file.as_path().to_str().unwrap().to_string(),
+            format: ImageFormat::Qcow2,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            num_queues: 1,
+            io_threads: false,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+        };
+
+        let mut block_devs = BlockBuilder::new();
+        match block_devs.insert(block_device) {
+            Err(DriveError::CreateBlockDevice(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+        assert!(block_devs.list.is_empty());
+    }
+
+    #[test]
+    fn test_add_multi_queue_block_device() {
+        let dummy_file = TempFile::new().unwrap();
+        dummy_file.as_file().set_len(0x1000).unwrap();
+        let mut block_device = BlockDeviceConfig {
+            path_on_host: dummy_file.as_path().to_str().unwrap().to_string(),
+            format: ImageFormat::Raw,
+            cache_type: CacheType::Unsafe,
+            overlay_path: None,
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            num_queues: MAX_QUEUES + 1,
+            io_threads: true,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+        };
+
+        let mut block_devs = BlockBuilder::new();
+        assert_eq!(
+            block_devs.insert(block_device.clone()),
+            Err(DriveError::InvalidNumQueues(MAX_QUEUES + 1))
+        );
+        block_device.num_queues = 0;
+        assert_eq!(
+            block_devs.insert(block_device.clone()),
+            Err(DriveError::InvalidNumQueues(0))
+        );
+        assert!(block_devs.list.is_empty());
+
+        block_device.num_queues = 4;
+        block_devs.insert(block_device).unwrap();
+        let block = block_devs.list[0].lock().unwrap();
+        assert_eq!(block.num_queues(), 4);
+        assert!(block.has_io_threads());
+    }
 }
@@ -20,9 +20,16 @@ pub enum VmConfigError {
     /// The vcpu count is invalid. When hyperthreading is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     InvalidVcpuCount,
+    /// The maximum vcpu count is invalid. It cannot be smaller than the boot vcpu count and,
+    /// when hyperthreading is enabled, it must be either 1 or an even number.
+    InvalidMaxVcpuCount,
     /// Could not get the config of the balloon device from the VM resources, even though a
     /// balloon device was previously installed.
     InvalidVmState,
+    /// The requested vcpu count exceeds the maximum vcpu count configured before boot.
+    VcpuCountExceedsMax,
+    /// Adding or removing vcpus on the running microVM failed.
+    VcpuHotplug(String),
 }
 
 impl fmt::Display for VmConfigError {
@@ -40,11 +47,23 @@ impl fmt::Display for VmConfigError {
                 "The vCPU number is invalid! The vCPU number can only \
                  be 1 or an even number when hyperthreading is enabled.",
             ),
+            InvalidMaxVcpuCount => write!(
+                f,
+                "The maximum vCPU number is invalid! It cannot be smaller \
+                 than the vCPU number and can only be 1 or an even number \
+                 when hyperthreading is enabled.",
+            ),
             InvalidVmState => write!(
                 f,
                 "Could not get the configuration of the previously \
                  installed balloon device to validate the memory size.",
             ),
+            VcpuCountExceedsMax => write!(
+                f,
+                "The vCPU number exceeds the maximum vCPU number \
+                 configured before boot.",
+            ),
+            VcpuHotplug(ref err) => write!(f, "Cannot resize the vCPU set: {}", err),
         }
     }
 }
@@ -61,6 +80,13 @@ pub struct VmConfig {
         deserialize_with = "validate_vcpu_num"
     )]
     pub vcpu_count: Option<u8>,
+    /// Maximum number of vcpus the microVM can be scaled up to after boot.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "validate_vcpu_num"
+    )]
+    pub max_vcpu_count: Option<u8>,
     /// The memory size in MiB.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_size_mib: Option<usize>,
@@ -79,6 +105,7 @@ impl Default for VmConfig {
     fn default() -> Self {
         VmConfig {
             vcpu_count: Some(1),
+            max_vcpu_count: None,
             mem_size_mib: Some(DEFAULT_MEM_SIZE_MIB),
             ht_enabled: Some(false),
             cpu_template: None,
@@ -90,6 +117,7 @@ impl Default for VmConfig {
 impl fmt::Display for VmConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let vcpu_count = self.vcpu_count.unwrap_or(1);
+        let max_vcpu_count = self.max_vcpu_count.unwrap_or(vcpu_count);
         let mem_size = self.mem_size_mib.unwrap_or(DEFAULT_MEM_SIZE_MIB);
         let ht_enabled = self.ht_enabled.unwrap_or(false);
         let cpu_template = self
@@ -97,9 +125,9 @@ impl fmt::Display for VmConfig {
             .map_or("Uninitialized".to_string(), |c| c.to_string());
         write!(
             f,
-            "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"ht_enabled\": {:?}, \
-             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
-            vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
+            "{{ \"vcpu_count\": {:?}, \"max_vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \
+             \"ht_enabled\": {:?}, \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
+            vcpu_count, max_vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
         )
     }
 }
@@ -157,5 +185,9 @@ mod tests {
 
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
+
+        let expected_str = "The vCPU number exceeds the maximum vCPU number \
+                            configured before boot.";
+        assert_eq!(VmConfigError::VcpuCountExceedsMax.to_string(), expected_str);
     }
 }
@@ -0,0 +1,237 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use devices::virtio::mem::Error as MemError;
+pub use devices::virtio::{MemStatus, MEM_DEV_ID};
+
+use serde::{Deserialize, Serialize};
+
+/// Default size of a memory hotplug block, in MiB.
+pub const DEFAULT_BLOCK_SIZE_MIB: u64 = 2;
+/// Size of the guest physical address space KVM provides by default, which the memory hotplug
+/// region has to fit in.
+pub const GUEST_PHYS_ADDR_SPACE_SIZE: u64 = 1 << 40;
+
+/// Errors associated with the operations allowed on the memory hotplug device.
+#[derive(Debug)]
+pub enum MemoryHotplugConfigError {
+    /// The user made a request on an inexistent memory hotplug device.
+    DeviceNotFound,
+    /// Device not activated yet.
+    DeviceNotActive,
+    /// The block size is not a power of two of at least 2 MiB.
+    InvalidBlockSize,
+    /// The total size is zero, not a multiple of the block size, or the region doesn't fit in
+    /// the guest address space.
+    InvalidTotalSize,
+    /// The requested size exceeds the total size or is not a multiple of the block size.
+    InvalidRequestedSize,
+    /// Failed to create the memory hotplug device.
+    CreateFailure(MemError),
+    /// Failed to update the configuration of the memory hotplug device.
+    UpdateFailure(std::io::Error),
+}
+
+impl fmt::Display for MemoryHotplugConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
+        use self::MemoryHotplugConfigError::*;
+        match self {
+            DeviceNotFound => write!(f, "No memory hotplug device found."),
+            DeviceNotActive => write!(f, "Device is inactive, check virtio-mem driver is enabled."),
+            InvalidBlockSize => write!(
+                f,
+                "The block size must be a power of two of at least {} MiB.",
+                DEFAULT_BLOCK_SIZE_MIB
+            ),
+            InvalidTotalSize => write!(
+                f,
+                "The total size must be a non-zero multiple of the block size, and fit in the \
+                 guest address space after the boot memory."
+            ),
+            InvalidRequestedSize => write!(
+                f,
+                "The requested size must be a multiple of the block size, no larger than the \
+                 total size."
+            ),
+            CreateFailure(e) => write!(f, "Error creating the memory hotplug device: {:?}", e),
+            UpdateFailure(e) => write!(
+                f,
+                "Error updating the memory hotplug device configuration: {:?}",
+                e
+            ),
+        }
+    }
+}
+
+impl From<MemError> for MemoryHotplugConfigError {
+    fn from(error: MemError) -> Self {
+        match error {
+            MemError::DeviceNotFound => Self::DeviceNotFound,
+            MemError::DeviceNotActive => Self::DeviceNotActive,
+            MemError::InterruptError(io_error) => Self::UpdateFailure(io_error),
+            MemError::InvalidSize(_) => Self::InvalidRequestedSize,
+            e => Self::CreateFailure(e),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, MemoryHotplugConfigError>;
+
+fn default_block_size_mib() -> u64 {
+    DEFAULT_BLOCK_SIZE_MIB
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from memory hotplug related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugConfig {
+    /// Size of the hotpluggable memory region in MiB.
+    pub total_size_mib: u64,
+    /// Granularity in MiB at which memory is plugged and unplugged.
+    #[serde(default = "default_block_size_mib")]
+    pub block_size_mib: u64,
+    /// Amount of memory in MiB the guest is asked to plug.
+    #[serde(default)]
+    pub requested_size_mib: u64,
+}
+
+impl MemoryHotplugConfig {
+    /// Checks that the sizes can be handled by the device, and that the region fits after
+    /// `boot_mem_size_mib` MiB of boot memory.
+    pub fn validate(&self, boot_mem_size_mib: usize) -> Result<()> {
+        if !self.block_size_mib.is_power_of_two() || self.block_size_mib < DEFAULT_BLOCK_SIZE_MIB {
+            return Err(MemoryHotplugConfigError::InvalidBlockSize);
+        }
+        if self.total_size_mib == 0
+            || self.total_size_mib % self.block_size_mib != 0
+            || self.total_size_mib > Self::max_total_size_mib(boot_mem_size_mib)
+        {
+            return Err(MemoryHotplugConfigError::InvalidTotalSize);
+        }
+        if self.requested_size_mib > self.total_size_mib
+            || self.requested_size_mib % self.block_size_mib != 0
+        {
+            return Err(MemoryHotplugConfigError::InvalidRequestedSize);
+        }
+        Ok(())
+    }
+
+    // Returns the size of the address space left after `boot_mem_size_mib` MiB of boot memory.
+    fn max_total_size_mib(boot_mem_size_mib: usize) -> u64 {
+        let boot_mem_end = arch::arch_memory_regions(boot_mem_size_mib << 20)
+            .iter()
+            .map(|(addr, size)| addr.0 + *size as u64)
+            .max()
+            .unwrap_or(0);
+        GUEST_PHYS_ADDR_SPACE_SIZE.saturating_sub(arch::hotplug_memory_start_after(boot_mem_end))
+            >> 20
+    }
+}
+
+impl From<MemoryHotplugConfig> for MemStatus {
+    fn from(config: MemoryHotplugConfig) -> Self {
+        MemStatus {
+            total_size_mib: config.total_size_mib,
+            block_size_mib: config.block_size_mib,
+            plugged_size_mib: 0,
+            requested_size_mib: config.requested_size_mib,
+        }
+    }
+}
+
+/// The data fed into a memory hotplug update request. Only the amount of
+/// memory the guest is asked to plug can be changed after boot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugSizeUpdate {
+    /// Amount of memory in MiB the guest is asked to plug.
+    pub requested_size_mib: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+            requested_size_mib: 512,
+        };
+        assert!(config.validate(128).is_ok());
+
+        config.block_size_mib = 3;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidBlockSize) => (),
+            _ => unreachable!(),
+        }
+        config.block_size_mib = 1;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidBlockSize) => (),
+            _ => unreachable!(),
+        }
+
+        config.block_size_mib = 128;
+        config.total_size_mib = 1000;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => unreachable!(),
+        }
+        config.total_size_mib = 0;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => unreachable!(),
+        }
+
+        config.total_size_mib = GUEST_PHYS_ADDR_SPACE_SIZE >> 20;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => unreachable!(),
+        }
+        // The region is placed after the boot memory.
+        config.total_size_mib = (GUEST_PHYS_ADDR_SPACE_SIZE >> 20) - 8192;
+        assert!(config.validate(128).is_ok());
+        match config.validate(8192) {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => unreachable!(),
+        }
+
+        config.total_size_mib = 1024;
+        config.requested_size_mib = 100;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidRequestedSize) => (),
+            _ => unreachable!(),
+        }
+        config.requested_size_mib = 2048;
+        match config.validate(128) {
+            Err(MemoryHotplugConfigError::InvalidRequestedSize) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: MemoryHotplugConfig =
+            serde_json::from_str(r#"{"total_size_mib": 1024}"#).unwrap();
+        assert_eq!(config.block_size_mib, DEFAULT_BLOCK_SIZE_MIB);
+        assert_eq!(config.requested_size_mib, 0);
+        assert_eq!(
+            MemStatus::from(config),
+            MemStatus {
+                total_size_mib: 1024,
+                block_size_mib: DEFAULT_BLOCK_SIZE_MIB,
+                plugged_size_mib: 0,
+                requested_size_mib: 0,
+            }
+        );
+
+        assert!(serde_json::from_str::<MemoryHotplugConfig>(
+            r#"{"total_size_mib": 1024, "foo": 1}"#
+        )
+        .is_err());
+    }
+}
@@ -3,7 +3,7 @@
 
 use serde::{export::Formatter, Deserialize};
 use std::fmt::{Display, Result};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 /// Keeps the MMDS configuration.
 #[derive(Debug, Deserialize, PartialEq)]
@@ -11,6 +11,8 @@ use std::net::Ipv4Addr;
 pub struct MmdsConfig {
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// MMDS IPv6 configured address. The MMDS is only reachable over IPv6 when one is set.
+    pub ipv6_address: Option<Ipv6Addr>,
 }
 
 impl MmdsConfig {
@@ -19,6 +21,12 @@ impl MmdsConfig {
     pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
         self.ipv4_address
     }
+
+    /// Returns the MMDS IPv6 address if one was configured.
+    /// Otherwise returns None.
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_address
+    }
 }
 
 /// MMDS configuration related errors.
@@ -26,6 +34,8 @@ impl MmdsConfig {
 pub enum MmdsConfigError {
     /// The provided IPv4 address is not link-local valid.
     InvalidIpv4Addr,
+    /// The provided IPv6 address is neither link-local nor unique local.
+    InvalidIpv6Addr,
 }
 
 impl Display for MmdsConfigError {
@@ -34,6 +44,10 @@ impl Display for MmdsConfigError {
             MmdsConfigError::InvalidIpv4Addr => {
                 write!(f, "The MMDS IPv4 address is not link local.")
             }
+            MmdsConfigError::InvalidIpv6Addr => write!(
+                f,
+                "The MMDS IPv6 address is neither link local nor unique local."
+            ),
         }
     }
 }
@@ -24,6 +24,8 @@ pub mod instance_info;
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for configuring the memory hotplug device.
+pub mod memory_hotplug;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
@@ -3,13 +3,20 @@
 
 use std::convert::TryInto;
 use std::fmt;
+use std::fs::OpenOptions;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
 
 use super::RateLimiterConfig;
 use crate::Error as VmmError;
-use devices::virtio::net::TapError;
+use devices::virtio::net::{
+    AntiSpoofingFilter, BackendConfig, CaptureFormat, PacketCapture, TapError, DEFAULT_SNAPLEN,
+    MAX_QUEUE_PAIRS,
+};
 use devices::virtio::Net;
+use mmds::dhcp::{DhcpLease, DhcpServer};
 use rate_limiter::{BucketUpdate, TokenBucket};
 use utils::net::mac::MacAddr;
 
@@ -37,6 +44,88 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
+    /// Number of RX/TX queue pairs of the device. When larger than one, the TAP device
+    /// is opened in multi-queue mode and each queue pair is backed by its own TAP queue.
+    #[serde(default = "default_num_queues")]
+    pub num_queues: usize,
+    /// If this field is set, the guest can be asked to announce itself on the network
+    /// (e.g. by sending gratuitous ARPs) through an interface update. This adds a
+    /// control queue to the device.
+    #[serde(default)]
+    pub guest_announce: bool,
+    /// The kind of host backend `host_dev_name` refers to.
+    #[serde(default)]
+    pub backend: NetworkBackendConfig,
+    /// If this field is set, the frames sent by the guest are dropped when their source
+    /// MAC address is not `guest_mac`, or when their IPv4 sender is not an allowed one.
+    pub anti_spoofing: Option<AntiSpoofingConfig>,
+    /// If this field is set, the device model answers the DHCP messages of the guest with
+    /// the given configuration. These messages do not reach the associated TAP device.
+    pub dhcp: Option<DhcpConfig>,
+}
+
+/// The addresses a guest network interface is allowed to send frames from.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AntiSpoofingConfig {
+    /// The IPv4 addresses the guest can send IPv4 packets and ARP frames from. Only the
+    /// source MAC address of the frames is checked when missing.
+    pub allowed_ipv4: Option<Vec<Ipv4Addr>>,
+}
+
+/// The configuration a guest network interface is handed over DHCP.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// The IPv4 address of the guest.
+    pub ipv4_address: Ipv4Addr,
+    /// The length of the prefix of the guest subnet.
+    pub prefix_len: u8,
+    /// The default gateway of the guest.
+    pub gateway: Option<Ipv4Addr>,
+    /// The DNS servers of the guest.
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The lease time, in seconds.
+    #[serde(default = "default_lease_time_s")]
+    pub lease_time_s: u32,
+}
+
+fn default_lease_time_s() -> u32 {
+    86400
+}
+
+/// The host backend of a guest network interface.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum NetworkBackendConfig {
+    /// The TAP device named `host_dev_name`.
+    Tap,
+    /// The Unix `SOCK_SEQPACKET` socket listening at `host_dev_name`, exchanging
+    /// raw Ethernet frames with a userspace process.
+    UnixSocket,
+    /// AF_XDP sockets bound to the queues of the host interface `host_dev_name`.
+    Xdp {
+        /// Path of the pinned XSKMAP the XDP program attached to the host interface
+        /// redirects the guest traffic to.
+        xsk_map_path: String,
+    },
+}
+
+impl Default for NetworkBackendConfig {
+    fn default() -> Self {
+        NetworkBackendConfig::Tap
+    }
+}
+
+impl From<NetworkBackendConfig> for BackendConfig {
+    fn from(config: NetworkBackendConfig) -> Self {
+        match config {
+            NetworkBackendConfig::Tap => BackendConfig::Tap,
+            NetworkBackendConfig::UnixSocket => BackendConfig::UnixSocket,
+            NetworkBackendConfig::Xdp { xsk_map_path } => BackendConfig::Xdp(xsk_map_path),
+        }
+    }
 }
 
 // Serde does not allow specifying a default value for a field
@@ -46,8 +135,12 @@ fn default_allow_mmds_requests() -> bool {
     false
 }
 
+fn default_num_queues() -> usize {
+    1
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// and the link state reported to the guest can be updated.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -59,6 +152,51 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// New link state reported to the guest. The link state is left unchanged if missing.
+    pub link_up: Option<bool>,
+    /// If this field is set, the guest is asked to announce itself on the network.
+    #[serde(default)]
+    pub announce: bool,
+    /// Host level path of a TAP device replacing the one backing the guest interface.
+    pub host_dev_name: Option<String>,
+}
+
+/// The data fed into a packet capture start request.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkCaptureConfig {
+    /// The net iface ID, as provided by the user at iface creation time.
+    pub iface_id: String,
+    /// Path of the capture file, which is created or truncated when the capture starts.
+    pub path: PathBuf,
+    /// The format of the capture file.
+    #[serde(default)]
+    pub format: CaptureFormat,
+    /// Maximum number of bytes captured from each frame.
+    #[serde(default = "default_snaplen")]
+    pub snaplen: u32,
+    /// If this field is set, only the `ring_size` most recent frames are kept in memory and
+    /// written to the capture file when the capture stops. Otherwise, the frames are written
+    /// as they are captured.
+    pub ring_size: Option<usize>,
+}
+
+fn default_snaplen() -> u32 {
+    DEFAULT_SNAPLEN
+}
+
+impl NetworkCaptureConfig {
+    /// Creates the capture file and starts a packet capture into it.
+    pub fn open(&self) -> Result<PacketCapture> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)
+            .map_err(NetworkInterfaceError::CreateCapture)?;
+        PacketCapture::new(file, self.format, self.snaplen, self.ring_size)
+            .map_err(NetworkInterfaceError::CreateCapture)
+    }
 }
 
 macro_rules! get_bucket_update {
@@ -118,6 +256,18 @@ pub enum NetworkInterfaceError {
     DeviceUpdate(VmmError),
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// The number of queue pairs is invalid.
+    InvalidNumQueues(usize),
+    /// The anti-spoofing filter needs a guest MAC address.
+    AntiSpoofingWithoutGuestMac,
+    /// The prefix length of the DHCP configuration is invalid.
+    InvalidDhcpPrefixLen(u8),
+    /// There is no network interface with the given ID.
+    DeviceNotFound(String),
+    /// Cannot create the packet capture file.
+    CreateCapture(std::io::Error),
+    /// Starting or stopping a packet capture failed.
+    Capture(devices::virtio::net::Error),
 }
 
 impl fmt::Display for NetworkInterfaceError {
@@ -145,6 +295,29 @@ impl fmt::Display for NetworkInterfaceError {
                     tap_err
                 )
             }
+            InvalidNumQueues(num_queues) => write!(
+                f,
+                "Invalid number of queue pairs: {}. It must be between 1 and {}.",
+                num_queues, MAX_QUEUE_PAIRS
+            ),
+            AntiSpoofingWithoutGuestMac => write!(
+                f,
+                "The anti-spoofing filter requires the guest MAC address to be set."
+            ),
+            InvalidDhcpPrefixLen(prefix_len) => write!(
+                f,
+                "Invalid DHCP prefix length: {}. It must be at most 32.",
+                prefix_len
+            ),
+            DeviceNotFound(iface_id) => {
+                write!(
+                    f,
+                    "No network interface with the ID {} was found.",
+                    iface_id
+                )
+            }
+            CreateCapture(e) => write!(f, "Cannot create the packet capture file: {}", e),
+            Capture(e) => write!(f, "Packet capture error: {:?}", e),
         }
     }
 }
@@ -211,29 +384,95 @@ impl NetBuilder {
         Ok(net)
     }
 
+    /// Starts a packet capture on the network device with `iface_id` ID.
+    pub fn start_capture(&self, iface_id: &str, capture: PacketCapture) -> Result<()> {
+        self.with_net(iface_id, |net| net.start_capture(capture))
+    }
+
+    /// Stops the packet capture running on the network device with `iface_id` ID.
+    pub fn stop_capture(&self, iface_id: &str) -> Result<()> {
+        self.with_net(iface_id, Net::stop_capture)
+    }
+
+    fn with_net<F>(&self, iface_id: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Net) -> devices::virtio::net::Result<()>,
+    {
+        let net = self
+            .net_devices
+            .iter()
+            .find(|net| net.lock().expect("Poisoned lock").id() == iface_id)
+            .ok_or_else(|| NetworkInterfaceError::DeviceNotFound(iface_id.to_string()))?;
+        f(&mut net.lock().expect("Poisoned lock")).map_err(NetworkInterfaceError::Capture)
+    }
+
     /// Creates a Net device from a NetworkInterfaceConfig.
     pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net> {
-        let rx_rate_limiter = cfg
-            .rx_rate_limiter
-            .map(super::RateLimiterConfig::try_into)
-            .transpose()
-            .map_err(NetworkInterfaceError::CreateRateLimiter)?;
-        let tx_rate_limiter = cfg
-            .tx_rate_limiter
-            .map(super::RateLimiterConfig::try_into)
-            .transpose()
-            .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+        if cfg.num_queues == 0 || cfg.num_queues > MAX_QUEUE_PAIRS {
+            return Err(NetworkInterfaceError::InvalidNumQueues(cfg.num_queues));
+        }
+
+        let anti_spoofing = match cfg.anti_spoofing {
+            Some(anti_spoofing_cfg) => Some(AntiSpoofingFilter::new(
+                cfg.guest_mac
+                    .ok_or(NetworkInterfaceError::AntiSpoofingWithoutGuestMac)?,
+                anti_spoofing_cfg.allowed_ipv4,
+            )),
+            None => None,
+        };
+
+        let dhcp_server = match cfg.dhcp {
+            Some(dhcp_cfg) => {
+                if dhcp_cfg.prefix_len > 32 {
+                    return Err(NetworkInterfaceError::InvalidDhcpPrefixLen(
+                        dhcp_cfg.prefix_len,
+                    ));
+                }
+                Some(DhcpServer::new(DhcpLease {
+                    ipv4_addr: dhcp_cfg.ipv4_address,
+                    prefix_len: dhcp_cfg.prefix_len,
+                    gateway: dhcp_cfg.gateway,
+                    dns_servers: dhcp_cfg.dns_servers,
+                    lease_time_s: dhcp_cfg.lease_time_s,
+                }))
+            }
+            None => None,
+        };
+
+        // Every queue pair gets its own rate limiters, built from the same configuration.
+        let mut rate_limiters = Vec::with_capacity(cfg.num_queues);
+        for _ in 0..cfg.num_queues {
+            let rx_rate_limiter = cfg
+                .rx_rate_limiter
+                .map(super::RateLimiterConfig::try_into)
+                .transpose()
+                .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+            let tx_rate_limiter = cfg
+                .tx_rate_limiter
+                .map(super::RateLimiterConfig::try_into)
+                .transpose()
+                .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+            rate_limiters.push((
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+            ));
+        }
 
         // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
+        let mut net = devices::virtio::net::Net::new(
             cfg.iface_id,
             cfg.host_dev_name.clone(),
+            cfg.backend.into(),
             cfg.guest_mac.as_ref(),
-            rx_rate_limiter.unwrap_or_default(),
-            tx_rate_limiter.unwrap_or_default(),
+            rate_limiters,
             cfg.allow_mmds_requests,
+            cfg.guest_announce,
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_anti_spoofing_filter(anti_spoofing);
+        net.set_dhcp_server(dhcp_server);
+
+        Ok(net)
     }
 }
 
@@ -242,6 +481,7 @@ mod tests {
     use std::str;
 
     use super::*;
+    use utils::tempfile::TempFile;
 
     impl NetBuilder {
         pub fn len(&self) -> usize {
@@ -261,6 +501,11 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            num_queues: 1,
+            guest_announce: false,
+            backend: NetworkBackendConfig::Tap,
+            anti_spoofing: None,
+            dhcp: None,
         }
     }
 
@@ -273,6 +518,11 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: self.allow_mmds_requests,
+                num_queues: self.num_queues,
+                guest_announce: self.guest_announce,
+                backend: self.backend.clone(),
+                anti_spoofing: self.anti_spoofing.clone(),
+                dhcp: self.dhcp.clone(),
             }
         }
     }
@@ -344,9 +594,63 @@ mod tests {
         );
         assert_eq!(net_builder.net_devices.len(), 1);
 
+        // Error Case: Add new network config with an invalid number of queue pairs.
+        let mut netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        netif_2.num_queues = 0;
+        assert_eq!(
+            net_builder.build(netif_2).err().unwrap().to_string(),
+            NetworkInterfaceError::InvalidNumQueues(0).to_string()
+        );
+        assert_eq!(net_builder.net_devices.len(), 1);
+
+        // Error Case: Add new network config with an anti-spoofing filter but no guest MAC.
+        let mut netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        netif_2.guest_mac = None;
+        netif_2.anti_spoofing = Some(AntiSpoofingConfig { allowed_ipv4: None });
+        assert_eq!(
+            net_builder.build(netif_2).err().unwrap().to_string(),
+            NetworkInterfaceError::AntiSpoofingWithoutGuestMac.to_string()
+        );
+        assert_eq!(net_builder.net_devices.len(), 1);
+
+        // Error Case: Add new network config with an invalid DHCP prefix length.
+        let mut netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        netif_2.dhcp = Some(DhcpConfig {
+            ipv4_address: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 33,
+            gateway: None,
+            dns_servers: vec![],
+            lease_time_s: default_lease_time_s(),
+        });
+        assert_eq!(
+            net_builder.build(netif_2).err().unwrap().to_string(),
+            NetworkInterfaceError::InvalidDhcpPrefixLen(33).to_string()
+        );
+        assert_eq!(net_builder.net_devices.len(), 1);
+
         // Adding the second valid network config.
-        let netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
-        assert!(net_builder.build(netif_2).is_ok());
+        let mut netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        netif_2.dhcp = Some(DhcpConfig {
+            ipv4_address: Ipv4Addr::new(172, 16, 0, 2),
+            prefix_len: 24,
+            gateway: Some(Ipv4Addr::new(172, 16, 0, 1)),
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8)],
+            lease_time_s: 3600,
+        });
+        let allowed_ipv4 = vec![Ipv4Addr::new(10, 1, 2, 3)];
+        netif_2.anti_spoofing = Some(AntiSpoofingConfig {
+            allowed_ipv4: Some(allowed_ipv4.clone()),
+        });
+        let net_2 = net_builder.build(netif_2).unwrap();
+        let net_2 = net_2.lock().unwrap();
+        let filter = net_2.anti_spoofing_filter().unwrap();
+        assert_eq!(filter.mac(), &MacAddr::parse_str(guest_mac_2).unwrap());
+        assert_eq!(filter.allowed_ipv4(), Some(&allowed_ipv4[..]));
+        let lease = net_2.dhcp_server().unwrap().lease();
+        assert_eq!(lease.ipv4_addr, Ipv4Addr::new(172, 16, 0, 2));
+        assert_eq!(lease.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(lease.lease_time_s, 3600);
+        drop(net_2);
 
         // Error Cases for UPDATE
         // Error Case: Update netif_2 mac using the same mac as netif_1.
@@ -390,6 +694,80 @@ mod tests {
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname),
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname)
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidNumQueues(0),
+            NetworkInterfaceError::InvalidNumQueues(0)
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::AntiSpoofingWithoutGuestMac,
+            NetworkInterfaceError::AntiSpoofingWithoutGuestMac
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidDhcpPrefixLen(33),
+            NetworkInterfaceError::InvalidDhcpPrefixLen(33)
+        );
+        let err = NetworkInterfaceError::DeviceNotFound("id".to_string());
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::CreateCapture(std::io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::Capture(devices::virtio::net::Error::NoCapture);
+        let _ = format!("{}{:?}", err, err);
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut net_builder = NetBuilder::new();
+        let netif = create_netif("capture_id", "capture_dev", "01:23:45:67:89:0c");
+        assert!(net_builder.build(netif).is_ok());
+
+        let file = TempFile::new().unwrap();
+        let capture_cfg: NetworkCaptureConfig = serde_json::from_str(&format!(
+            r#"{{"iface_id": "capture_id", "path": "{}"}}"#,
+            file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        assert_eq!(capture_cfg.format, CaptureFormat::Pcap);
+        assert_eq!(capture_cfg.snaplen, DEFAULT_SNAPLEN);
+        assert_eq!(capture_cfg.ring_size, None);
+
+        assert_eq!(
+            net_builder
+                .start_capture("unknown_id", capture_cfg.open().unwrap())
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::DeviceNotFound("unknown_id".to_string()).to_string()
+        );
+        net_builder
+            .start_capture("capture_id", capture_cfg.open().unwrap())
+            .unwrap();
+        assert_eq!(
+            net_builder
+                .start_capture("capture_id", capture_cfg.open().unwrap())
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::Capture(devices::virtio::net::Error::CaptureInProgress)
+                .to_string()
+        );
+        net_builder.stop_capture("capture_id").unwrap();
+        assert_eq!(
+            net_builder
+                .stop_capture("capture_id")
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::Capture(devices::virtio::net::Error::NoCapture).to_string()
+        );
+        // The pcap file header was written.
+        assert_eq!(std::fs::metadata(file.as_path()).unwrap().len(), 24);
+
+        let mut capture_cfg = capture_cfg;
+        capture_cfg.path = PathBuf::from("/nonexistent/capture.pcap");
+        assert!(capture_cfg.open().is_err());
     }
 
     #[test]
@@ -6,6 +6,9 @@
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
+use utils::net::mac::MacAddr;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
@@ -23,6 +26,23 @@ impl Default for SnapshotType {
     }
 }
 
+/// The formats the guest memory file of a snapshot can be written in.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+pub enum MemFileFormat {
+    /// Raw guest memory, can be mapped directly when loading the snapshot.
+    Raw,
+    /// Guest memory split in checksummed chunks, all-zero chunks are left out.
+    Chunked,
+    /// Like `Chunked`, with each chunk compressed with zstd.
+    Compressed,
+}
+
+impl Default for MemFileFormat {
+    fn default() -> MemFileFormat {
+        MemFileFormat::Raw
+    }
+}
+
 /// Stores the configuration that will be used for creating a snapshot.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -35,9 +55,22 @@ pub struct CreateSnapshotParams {
     pub snapshot_path: PathBuf,
     /// Path to the file that will contain the guest memory.
     pub mem_file_path: PathBuf,
+    /// The format of the guest memory file. The default value is `Raw`.
+    /// Diff snapshots can only be written in the `Raw` format.
+    #[serde(default)]
+    pub mem_file_format: MemFileFormat,
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
+    /// Setting this flag resets the vsock connections of the microVM once the snapshot
+    /// is created, like they are reset in the microVMs restored from it. The host-side
+    /// listening sockets stay open, so agents can reconnect after the microVM resumes.
+    #[serde(default)]
+    pub reset_vsock: bool,
+    /// Paths where the copy-on-write overlays of the drives are saved. Every drive with an
+    /// overlay needs one.
+    #[serde(default)]
+    pub drive_overlays: Vec<DriveOverlay>,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -52,6 +85,35 @@ pub struct LoadSnapshotParams {
     /// allow taking subsequent incremental snapshots.
     #[serde(default)]
     pub enable_diff_snapshots: bool,
+    /// Host backends replacing the ones recorded in the snapshot for some network interfaces.
+    #[serde(default)]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Paths of the copy-on-write overlays written by the restored drives, seeded from the
+    /// overlays saved in the snapshot.
+    #[serde(default)]
+    pub drive_overlays: Vec<DriveOverlay>,
+}
+
+/// Replaces the host backend of a network interface when loading a snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkOverride {
+    /// ID of the network interface, as provided at its creation.
+    pub iface_id: String,
+    /// Host level path of the TAP device backing the restored interface.
+    pub host_dev_name: String,
+    /// New guest MAC address of the restored interface.
+    pub guest_mac: Option<MacAddr>,
+}
+
+/// Locates the copy-on-write overlay of a drive, when creating or loading a snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveOverlay {
+    /// ID of the drive, as provided at its creation.
+    pub drive_id: String,
+    /// Host level path of the overlay file.
+    pub overlay_path: String,
 }
 
 /// The microVM state options.
@@ -1,10 +1,13 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashSet;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
-use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use devices::virtio::{
+    Vsock, VsockError, VsockHostEndpoint, VsockPortMapping, VsockUnixBackend, VsockUnixBackendError,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +20,11 @@ pub enum VsockConfigError {
     CreateVsockBackend(VsockUnixBackendError),
     /// Failed to create the vsock device.
     CreateVsockDevice(VsockError),
+    /// The port map has several entries for the same guest port.
+    DuplicatePortMapping(u32),
+    /// The port map entry of a guest port sets both a Unix socket and a TCP port, or none of
+    /// the port overrides.
+    InvalidPortMapping(u32),
 }
 
 impl fmt::Display for VsockConfigError {
@@ -27,6 +35,17 @@ impl fmt::Display for VsockConfigError {
                 write!(f, "Cannot create backend for vsock device: {:?}", e)
             }
             CreateVsockDevice(ref e) => write!(f, "Cannot create vsock device: {:?}", e),
+            DuplicatePortMapping(port) => write!(
+                f,
+                "The vsock port map has several entries for port {}.",
+                port
+            ),
+            InvalidPortMapping(port) => write!(
+                f,
+                "The vsock port map entry of port {} must set at most one of uds_path and \
+                 tcp_port, and at least one of uds_path, tcp_port and listen_uds_path.",
+                port
+            ),
         }
     }
 }
@@ -44,11 +63,54 @@ pub struct VsockDeviceConfig {
     pub guest_cid: u32,
     /// Path to local unix socket.
     pub uds_path: String,
+    /// Routing overrides of individual guest ports.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub port_map: Vec<VsockPortMappingConfig>,
+}
+
+/// Routes the connections to a guest vsock port through host sockets other than the default
+/// `<uds_path>_<port>` one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockPortMappingConfig {
+    /// The guest vsock port.
+    pub port: u32,
+    /// Path of the host Unix socket to which guest connections on `port` are forwarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_path: Option<String>,
+    /// Localhost TCP port to which guest connections on `port` are forwarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_port: Option<u16>,
+    /// Path of a host Unix socket on which host connections to the guest `port` are accepted,
+    /// without the `CONNECT <port>` handshake.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen_uds_path: Option<String>,
+}
+
+impl VsockPortMappingConfig {
+    fn to_port_mapping(&self) -> Result<VsockPortMapping> {
+        let target = match (self.uds_path.as_ref(), self.tcp_port) {
+            (Some(_), Some(_)) => return Err(VsockConfigError::InvalidPortMapping(self.port)),
+            (Some(path), None) => Some(VsockHostEndpoint::Unix(path.clone())),
+            (None, Some(tcp_port)) => Some(VsockHostEndpoint::TcpLocalhost(tcp_port)),
+            (None, None) => None,
+        };
+        if target.is_none() && self.listen_uds_path.is_none() {
+            return Err(VsockConfigError::InvalidPortMapping(self.port));
+        }
+
+        Ok(VsockPortMapping {
+            port: self.port,
+            target,
+            listen_path: self.listen_uds_path.clone(),
+        })
+    }
 }
 
 struct VsockAndUnixPath {
     vsock: MutexVsockUnix,
     uds_path: String,
+    listen_uds_paths: Vec<String>,
 }
 
 /// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
@@ -68,12 +130,19 @@ impl VsockBuilder {
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<()> {
         // Make sure to drop the old one and remove the socket before creating a new one.
         if let Some(existing) = self.inner.take() {
-            std::fs::remove_file(existing.uds_path)
-                .map_err(VsockUnixBackendError::UnixBind)
-                .map_err(VsockConfigError::CreateVsockBackend)?;
+            for path in std::iter::once(existing.uds_path).chain(existing.listen_uds_paths) {
+                std::fs::remove_file(path)
+                    .map_err(VsockUnixBackendError::UnixBind)
+                    .map_err(VsockConfigError::CreateVsockBackend)?;
+            }
         }
         self.inner = Some(VsockAndUnixPath {
             uds_path: cfg.uds_path.clone(),
+            listen_uds_paths: cfg
+                .port_map
+                .iter()
+                .filter_map(|mapping| mapping.listen_uds_path.clone())
+                .collect(),
             vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
         });
         Ok(())
@@ -86,7 +155,16 @@ impl VsockBuilder {
 
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(cfg: VsockDeviceConfig) -> Result<Vsock<VsockUnixBackend>> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)
+        let mut ports = HashSet::new();
+        let mut port_map = Vec::with_capacity(cfg.port_map.len());
+        for mapping in cfg.port_map.iter() {
+            if !ports.insert(mapping.port) {
+                return Err(VsockConfigError::DuplicatePortMapping(mapping.port));
+            }
+            port_map.push(mapping.to_port_mapping()?);
+        }
+
+        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path, port_map)
             .map_err(VsockConfigError::CreateVsockBackend)?;
 
         Ok(Vsock::new(u64::from(cfg.guest_cid), backend)
@@ -104,6 +182,7 @@ pub(crate) mod tests {
             vsock_id: "vsock".to_string(),
             guest_cid: 3,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            port_map: Vec::new(),
         }
     }
 
@@ -133,6 +212,55 @@ pub(crate) mod tests {
         assert_eq!(vsock.lock().unwrap().cid(), new_cid as u64);
     }
 
+    #[test]
+    fn test_vsock_port_map() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        let listen_uds_path = format!("{}_listen", vsock_config.uds_path);
+        let mapping = VsockPortMappingConfig {
+            port: 52,
+            uds_path: None,
+            tcp_port: Some(8080),
+            listen_uds_path: Some(listen_uds_path.clone()),
+        };
+
+        // Guest ports can only be mapped once.
+        vsock_config.port_map = vec![mapping.clone(), mapping.clone()];
+        assert_eq!(
+            VsockBuilder::create_unixsock_vsock(vsock_config.clone())
+                .err()
+                .unwrap()
+                .to_string(),
+            "The vsock port map has several entries for port 52."
+        );
+
+        // Guest connections can't go to both a Unix socket and a TCP port.
+        let mut invalid_mapping = mapping.clone();
+        invalid_mapping.uds_path = Some("/tmp/52.sock".to_string());
+        vsock_config.port_map = vec![invalid_mapping];
+        assert!(VsockBuilder::create_unixsock_vsock(vsock_config.clone()).is_err());
+
+        // The entry must override something.
+        let mut invalid_mapping = mapping.clone();
+        invalid_mapping.tcp_port = None;
+        invalid_mapping.listen_uds_path = None;
+        vsock_config.port_map = vec![invalid_mapping];
+        assert!(VsockBuilder::create_unixsock_vsock(vsock_config.clone()).is_err());
+
+        // Re-inserting the device releases the listening sockets of the port map as well.
+        let mut store = VsockBuilder::new();
+        vsock_config.port_map = vec![mapping];
+        store.insert(vsock_config.clone()).unwrap();
+        assert!(std::path::Path::new(&listen_uds_path).exists());
+        store.insert(vsock_config.clone()).unwrap();
+        assert!(std::path::Path::new(&listen_uds_path).exists());
+
+        vsock_config.port_map = Vec::new();
+        store.insert(vsock_config).unwrap();
+        assert!(!std::path::Path::new(&listen_uds_path).exists());
+    }
+
     #[test]
     fn test_error_messages() {
         use super::VsockConfigError::*;
@@ -146,5 +274,11 @@ pub(crate) mod tests {
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
+
+        let err = DuplicatePortMapping(52);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPortMapping(52);
+        let _ = format!("{}{:?}", err, err);
     }
 }
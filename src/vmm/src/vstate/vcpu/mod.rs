@@ -81,10 +81,12 @@ impl Display for Error {
 pub type Result<T> = result::Result<T, Error>;
 
 /// Encapsulates configuration parameters for the guest vCPUS.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct VcpuConfig {
     /// Number of guest VCPUs.
     pub vcpu_count: u8,
+    /// Maximum number of guest VCPUs, including the ones that can be hot-plugged after boot.
+    pub max_vcpu_count: u8,
     /// Enable hyperthreading in the CPUID configuration.
     pub ht_enabled: bool,
     /// CPUID template to use.
@@ -709,6 +711,7 @@ mod tests {
         {
             let vcpu_config = VcpuConfig {
                 vcpu_count: 1,
+                max_vcpu_count: 1,
                 ht_enabled: false,
                 cpu_template: None,
             };
@@ -181,10 +181,47 @@ impl KvmVcpu {
         guest_mem: &GuestMemoryMmap,
         kernel_start_addr: GuestAddress,
         vcpu_config: &VcpuConfig,
-        mut cpuid: CpuId,
+        cpuid: CpuId,
     ) -> Result<()> {
-        let cpuid_vm_spec = VmSpec::new(self.index, vcpu_config.vcpu_count, vcpu_config.ht_enabled)
-            .map_err(Error::CpuId)?;
+        self.configure_cpuid(vcpu_config, cpuid)?;
+
+        arch::x86_64::msr::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value() as u64)
+            .map_err(Error::REGSConfiguration)?;
+        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
+        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd).map_err(Error::SREGSConfiguration)?;
+        arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
+        Ok(())
+    }
+
+    /// Configures a x86_64 specific vcpu which is parked at boot, to be hot-plugged later on.
+    ///
+    /// Unlike the boot vcpus, a hot-plugged vcpu is brought up by the guest itself through the
+    /// INIT/SIPI sequence, so there is no need to set up its registers.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_config` - The vCPU configuration.
+    /// * `cpuid` - The capabilities exposed by this vCPU.
+    pub fn configure_hotplugged(&mut self, vcpu_config: &VcpuConfig, cpuid: CpuId) -> Result<()> {
+        self.configure_cpuid(vcpu_config, cpuid)?;
+
+        arch::x86_64::msr::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
+        arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
+        Ok(())
+    }
+
+    // Filters `cpuid` for this vcpu and applies the configured CPU template.
+    // The topology is computed over the maximum number of vcpus, so that it stays consistent
+    // when vcpus are hot-plugged after boot.
+    fn configure_cpuid(&mut self, vcpu_config: &VcpuConfig, mut cpuid: CpuId) -> Result<()> {
+        let cpuid_vm_spec = VmSpec::new(
+            self.index,
+            vcpu_config.max_vcpu_count,
+            vcpu_config.ht_enabled,
+        )
+        .map_err(Error::CpuId)?;
 
         filter_cpuid(&mut cpuid, &cpuid_vm_spec).map_err(|e| {
             METRICS.vcpu.filter_cpuid.inc();
@@ -206,15 +243,7 @@ impl KvmVcpu {
             }
         }
 
-        self.fd.set_cpuid2(&cpuid).map_err(Error::VcpuSetCpuid)?;
-
-        arch::x86_64::msr::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
-        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value() as u64)
-            .map_err(Error::REGSConfiguration)?;
-        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
-        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd).map_err(Error::SREGSConfiguration)?;
-        arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
-        Ok(())
+        self.fd.set_cpuid2(&cpuid).map_err(Error::VcpuSetCpuid)
     }
 
     /// Sets a Port Mapped IO bus for this vcpu.
@@ -390,6 +419,23 @@ pub struct VcpuState {
     xsave: kvm_xsave,
 }
 
+impl VcpuState {
+    /// Returns the saved general purpose registers.
+    pub fn regs(&self) -> &kvm_regs {
+        &self.regs
+    }
+
+    /// Returns the saved special registers.
+    pub fn sregs(&self) -> &kvm_sregs {
+        &self.sregs
+    }
+
+    /// Returns the saved multiprocessing state.
+    pub fn mp_state(&self) -> &kvm_mp_state {
+        &self.mp_state
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate cpuid;
@@ -430,6 +476,7 @@ mod tests {
 
         let mut vcpu_config = VcpuConfig {
             vcpu_count: 1,
+            max_vcpu_count: 1,
             ht_enabled: false,
             cpu_template: None,
         };
@@ -473,6 +520,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_configure_hotplugged_vcpu() {
+        let (vm, _vm_mem) = setup_vm(0x10000);
+        vm.setup_irqchip().unwrap();
+        let mut vcpu = KvmVcpu::new(1, &vm).unwrap();
+
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            max_vcpu_count: 2,
+            ht_enabled: false,
+            cpu_template: None,
+        };
+        assert!(vcpu
+            .configure_hotplugged(&vcpu_config, vm.supported_cpuid().clone())
+            .is_ok());
+    }
+
     #[test]
     fn test_vcpu_cpuid_restore() {
         let (_vm, vcpu, _) = setup_vcpu(0x1000);
@@ -30,7 +30,7 @@ use vmm::resources::VmResources;
 use vmm::version_map::VERSION_MAP;
 use vmm::vmm_config::boot_source::BootSourceConfig;
 #[cfg(target_arch = "x86_64")]
-use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
+use vmm::vmm_config::snapshot::{CreateSnapshotParams, MemFileFormat, SnapshotType};
 use vmm::Vmm;
 
 use crate::mock_devices::MockSerialInput;
@@ -354,7 +354,10 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
                 snapshot_type,
                 snapshot_path: snapshot_file.as_path().to_path_buf(),
                 mem_file_path: memory_file.as_path().to_path_buf(),
+                mem_file_format: MemFileFormat::Raw,
                 version: Some(String::from("0.24.0")),
+                reset_vsock: false,
+                drive_overlays: Vec::new(),
             };
 
             {
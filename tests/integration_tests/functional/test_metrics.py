@@ -36,6 +36,7 @@ def test_flush_metrics(test_microvm_with_api):
         'i8042',
         'latencies_us',
         'logger',
+        'mem',
         'mmds',
         'net',
         'patch_api_requests',